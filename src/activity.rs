@@ -0,0 +1,18 @@
+//! What the compiler is working on, kept for each thread so the internal compiler error report of a
+//! panicking thread names its own item, while parsing and code generation run in parallel.
+
+use std::{cell::RefCell, fmt};
+
+thread_local! {
+    static CURRENT_ITEM: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Records what the compiler is working on in this thread.
+pub fn set_current_item(item: impl fmt::Display) {
+    CURRENT_ITEM.with_borrow_mut(|current| *current = Some(item.to_string()));
+}
+
+/// What the compiler was last working on in this thread, if anything.
+pub fn current_item() -> Option<String> {
+    CURRENT_ITEM.with_borrow(|current| current.clone())
+}
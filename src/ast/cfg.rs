@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+/// The configuration a compile unit is built with. Its items whose `#[cfg(...)]` predicate
+/// doesn't hold are removed before lowering.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct Cfg {
+    /// The operating system of the target, like `linux`, `macos`, `windows` or `wasi`.
+    pub target_os: String,
+    /// The architecture of the target, like `x86_64`, `aarch64` or `wasm32`.
+    pub target_arch: String,
+    /// The family of the target: `unix`, `windows` or `wasm`, empty for bare metal ones.
+    pub target_family: String,
+    /// The profile being built, like `dev` or `release`.
+    pub profile: String,
+    /// Whether the profile enables debug assertions, checked with `#[cfg(debug_assertions)]`.
+    pub debug_assertions: bool,
+    /// Whether the unit is built by `concrete test`, only the package being tested is.
+    pub test: bool,
+    /// The enabled features of its package.
+    pub features: BTreeSet<String>,
+    /// The options set by the build script of its package, with their values, `None` if it has
+    /// no build script.
+    pub custom: Option<BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl Default for Cfg {
+    fn default() -> Self {
+        Self::for_target(None)
+    }
+}
+
+impl Cfg {
+    /// The configuration of the given target triple, or the host if it's `None`, with the `dev`
+    /// profile.
+    pub fn for_target(triple: Option<&str>) -> Self {
+        let (target_os, target_arch, target_family) = match triple {
+            Some(triple) => parse_triple(triple),
+            None => (
+                std::env::consts::OS.to_string(),
+                std::env::consts::ARCH.to_string(),
+                std::env::consts::FAMILY.to_string(),
+            ),
+        };
+
+        Self {
+            target_os,
+            target_arch,
+            target_family,
+            profile: "dev".to_string(),
+            debug_assertions: true,
+            test: false,
+            features: BTreeSet::new(),
+            custom: None,
+        }
+    }
+}
+
+/// The operating system, architecture and family of a target triple, named like the ones of the
+/// host in [`std::env::consts`].
+fn parse_triple(triple: &str) -> (String, String, String) {
+    let arch = triple.split('-').next().unwrap_or_default();
+    let arch = match arch {
+        "arm64" => "aarch64",
+        x if x.starts_with("riscv64") => "riscv64",
+        x if x.starts_with("riscv32") => "riscv32",
+        x if x.starts_with("armv7") || x.starts_with("thumbv") => "arm",
+        x if x.starts_with("i686") || x.starts_with("i586") => "x86",
+        x => x,
+    };
+
+    let has = |name: &str| triple.split('-').any(|x| x.starts_with(name));
+    let os = if has("android") {
+        "android"
+    } else if has("linux") {
+        "linux"
+    } else if has("darwin") || has("macos") {
+        "macos"
+    } else if has("ios") {
+        "ios"
+    } else if has("windows") {
+        "windows"
+    } else if has("wasi") {
+        "wasi"
+    } else if has("freebsd") {
+        "freebsd"
+    } else if has("netbsd") {
+        "netbsd"
+    } else if has("openbsd") {
+        "openbsd"
+    } else if has("none") {
+        "none"
+    } else {
+        "unknown"
+    };
+
+    let family = if os == "windows" {
+        "windows"
+    } else if arch.starts_with("wasm") {
+        "wasm"
+    } else if os == "none" || os == "unknown" {
+        ""
+    } else {
+        "unix"
+    };
+
+    (os.to_string(), arch.to_string(), family.to_string())
+}
@@ -2,8 +2,9 @@ use core::fmt;
 use std::ops::Range;
 
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Span {
     pub from: usize,
     pub to: usize,
@@ -21,14 +22,14 @@ impl From<Span> for Range<usize> {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct DocString {
     pub contents: Vec<String>,
     pub span: Span,
 }
 
 /// Identifiers, without a path or generics.
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Ident {
     pub name: String,
     pub span: Span,
@@ -39,7 +40,7 @@ pub struct Ident {
 /// std::module::X<T>
 /// ```
 /// Used only when specifying types.
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct TypeName {
     pub path: Vec<Ident>,
     pub name: Ident,
@@ -61,16 +62,46 @@ impl fmt::Display for TypeName {
 /// Used as a generic parameter definition in function / adt declarations.
 ///
 /// Note: When initializing a type with generics, we use a TypeName, not a GenericParam.
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct GenericParam {
     pub name: Ident,
     pub bounds: Vec<TypeName>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Attribute {
     pub name: String,
     pub value: Option<String>,
+    /// The arguments between parentheses, like the predicate of `#[cfg(test)]`.
+    pub args: Vec<AttributeArg>,
     pub span: Span,
 }
+
+/// An argument of an attribute:
+/// ```text
+/// #[cfg(any(test, target_os = "linux"))]
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AttributeArg {
+    Word(Ident),
+    NameValue {
+        name: Ident,
+        value: String,
+        span: Span,
+    },
+    List {
+        name: Ident,
+        args: Vec<AttributeArg>,
+        span: Span,
+    },
+}
+
+impl AttributeArg {
+    pub fn span(&self) -> Span {
+        match self {
+            AttributeArg::Word(name) => name.span,
+            AttributeArg::NameValue { span, .. } | AttributeArg::List { span, .. } => *span,
+        }
+    }
+}
@@ -1,18 +1,22 @@
+use serde::{Deserialize, Serialize};
+
 use super::{
-    common::{DocString, Ident},
+    common::{Attribute, DocString, Ident, Span},
     expressions::Expression,
     types::TypeDescriptor,
 };
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConstantDecl {
     pub doc_string: Option<DocString>,
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub is_pub: bool,
+    pub vis_span: Span,
     pub r#type: TypeDescriptor,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConstantDef {
     pub decl: ConstantDecl,
     pub value: Expression,
@@ -1,27 +1,32 @@
+use serde::{Deserialize, Serialize};
+
 use super::{
-    common::{GenericParam, Ident, Span},
+    common::{Attribute, GenericParam, Ident, Span},
     expressions::Expression,
     structs::Field,
 };
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UnionDecl {
     pub name: Ident,
     pub generics: Vec<GenericParam>,
     pub variants: Vec<Field>,
+    pub attributes: Vec<Attribute>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct EnumDecl {
     pub name: Ident,
     pub generics: Vec<GenericParam>,
     pub variants: Vec<EnumVariant>,
+    pub attributes: Vec<Attribute>,
     pub is_pub: bool,
+    pub vis_span: Span,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct EnumVariant {
     pub name: Ident,
     pub fields: Vec<Field>,
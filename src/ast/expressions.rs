@@ -1,12 +1,16 @@
 use std::collections::HashMap;
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 use super::{
     common::{Ident, Span, TypeName},
+    macros::MacroCall,
+    patterns::FieldPattern,
     statements::Statement,
     types::TypeDescriptor,
 };
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Expression {
     Value(ValueExpr, Span),
     // Type#func()
@@ -19,12 +23,19 @@ pub enum Expression {
     StructInit(StructInitExpr),
     EnumInit(EnumInitExpr),
     ArrayInit(ArrayInitExpr),
+    /// `(a, b)`
+    Tuple(Vec<Self>, Span),
+    /// `value?`, unwraps an `Option` or `Result` or returns its `None` or `Err` early.
+    Try(Box<Self>, Span),
+    /// `await future`, the value of a `Future`, suspending the async function until it's ready.
+    Await(Box<Self>, Span),
     Deref(Box<Self>, Span),
     AsRef(Box<Self>, bool, Span),
     Cast(Box<Self>, TypeDescriptor, Span),
+    MacroCall(Box<MacroCall>),
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum ValueExpr {
     ConstBool(bool, Span),
     ConstChar(char, Span),
@@ -34,56 +45,60 @@ pub enum ValueExpr {
     Path(PathOp),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StructInitExpr {
     pub name: TypeName,
+    #[serde(with = "field_list")]
     pub fields: HashMap<Ident, StructInitField>,
+    /// `..base`, the value the fields that aren't given are copied from.
+    pub base: Option<Box<StructInitField>>,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StructInitField {
     pub value: Expression,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AssocMethodCall {
     pub assoc_type: TypeName,
     pub fn_call: FnCallOp,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EnumInitExpr {
     pub name: TypeName,
     pub variant: Ident,
+    #[serde(with = "field_list")]
     pub fields: HashMap<Ident, StructInitField>,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EnumMatchExpr {
     pub name: TypeName,
     pub variant: Ident,
-    pub field_values: Vec<Ident>,
+    pub field_values: Vec<FieldPattern>,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ArrayInitExpr {
     pub values: Vec<Expression>,
     pub span: Span,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum UnaryOp {
     ArithNeg,
     LogicalNot,
     BitwiseNot,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum BinaryOp {
     Arith(ArithOp),
     Logic(LogicOp),
@@ -91,7 +106,7 @@ pub enum BinaryOp {
     Bitwise(BitwiseOp),
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum ArithOp {
     Add,
     Sub,
@@ -100,13 +115,13 @@ pub enum ArithOp {
     Mod,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum LogicOp {
     And,
     Or,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum CmpOp {
     Eq,
     NotEq,
@@ -116,21 +131,21 @@ pub enum CmpOp {
     GtEq,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum BitwiseOp {
     And,
     Or,
     Xor,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MatchExpr {
     pub expr: Box<Expression>,
     pub variants: Vec<MatchVariant>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct IfExpr {
     pub cond: Box<Expression>,
     pub block_stmts: Vec<Statement>,
@@ -138,34 +153,36 @@ pub struct IfExpr {
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MatchVariant {
     pub case: MatchCaseExpr,
     pub block: Vec<Statement>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum MatchCaseExpr {
     Value(ValueExpr),
     Enum(EnumMatchExpr),
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum PathSegment {
     FieldAccess(Ident, Span),
     ArrayIndex(ValueExpr, Span),
+    /// `a[start..end]`, both bounds are optional.
+    Slice(Option<ValueExpr>, Option<ValueExpr>, Span),
     MethodCall(FnCallOp, Span),
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct PathOp {
     pub first: Ident,
     pub extra: Vec<PathSegment>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct FnCallOp {
     pub path: Vec<Ident>,
     pub target: Ident,
@@ -173,3 +190,23 @@ pub struct FnCallOp {
     pub args: Vec<Expression>,
     pub span: Span,
 }
+
+/// The fields of an initialization are serialized as a list of pairs, as a map is keyed by strings.
+mod field_list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        fields: &HashMap<Ident, StructInitField>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(fields)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<Ident, StructInitField>, D::Error> {
+        Ok(Vec::<(Ident, StructInitField)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
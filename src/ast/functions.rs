@@ -1,12 +1,15 @@
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
+
 use super::{
     common::{Attribute, DocString, GenericParam, Ident, Span, TypeName},
+    patterns::Pattern,
     statements::Statement,
     types::{TypeDecl, TypeDescriptor},
 };
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct FunctionDecl {
     pub doc_string: Option<DocString>,
     pub generic_params: Vec<GenericParam>,
@@ -14,34 +17,61 @@ pub struct FunctionDecl {
     pub params: Vec<Param>,
     pub ret_type: Option<TypeDescriptor>,
     pub is_extern: bool,
+    /// Whether it can be called in constant expressions, evaluated at compile time.
+    pub is_const: bool,
+    /// Whether it can only be called in unsafe code, its body being unsafe code too.
+    pub is_unsafe: bool,
+    /// Whether calling it gives a `Future` of its return value, its body running when polled.
+    pub is_async: bool,
     pub is_pub: bool,
+    /// The span of `pub`, or the empty span where it goes when the item is private.
+    pub vis_span: Span,
     pub attributes: Vec<Attribute>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct FunctionDef {
     pub decl: Arc<FunctionDecl>,
     pub body: Vec<Statement>,
+    /// Whether its body was left out of the metadata of its unit, the function being declared
+    /// with its code in the object the unit was built to, see [`super::CompilationUnit::is_prebuilt`].
+    pub is_prebuilt: bool,
+    pub span: Span,
+}
+
+/// `extern "C" { fn puts(value: *mut u8) -> i32; }`, functions defined in native code, called
+/// with the given ABI.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExternBlock {
+    pub attributes: Vec<Attribute>,
+    pub abi: String,
+    pub abi_span: Span,
+    pub functions: Vec<Arc<FunctionDecl>>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct Param {
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub r#type: TypeDescriptor,
+    /// The pattern the argument is destructured with, the name is then a placeholder.
+    pub pattern: Option<Pattern>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ImplBlock {
+    pub attributes: Vec<Attribute>,
     pub target: TypeDescriptor,
     pub generic_params: Vec<GenericParam>,
     pub methods: Vec<Arc<FunctionDef>>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ImplTraitBlock {
+    pub attributes: Vec<Attribute>,
     pub target_trait: TypeName,
     pub target: TypeDescriptor,
     pub generic_params: Vec<GenericParam>,
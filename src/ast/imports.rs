@@ -1,8 +1,28 @@
-use super::common::{Ident, Span};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+use super::common::{Attribute, Ident, Span};
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct ImportStmt {
+    pub attributes: Vec<Attribute>,
     pub module: Vec<Ident>,
-    pub symbols: Vec<Ident>,
+    pub symbols: Vec<ImportedSymbol>,
+    /// The span of the `*` of a glob import, `import a.{*};`, which imports all the items of the
+    /// module the importing one can use.
+    pub glob: Option<Span>,
     pub span: Span,
 }
+
+/// A symbol of an import, `name` or `name as alias`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct ImportedSymbol {
+    pub name: Ident,
+    pub alias: Option<Ident>,
+}
+
+impl ImportedSymbol {
+    /// The name the symbol is known by in the importing module.
+    pub fn local_name(&self) -> &Ident {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+}
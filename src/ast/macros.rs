@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+use crate::parser::tokens::Token;
+
+use super::common::{Attribute, DocString, Ident, Span};
+
+/// `macro name { (pattern) => { template }, ... }`, expanded before lowering by the first rule
+/// whose pattern matches the arguments of a call.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MacroDef {
+    pub doc_string: Option<DocString>,
+    pub attributes: Vec<Attribute>,
+    pub name: Ident,
+    pub is_pub: bool,
+    pub vis_span: Span,
+    pub rules: Vec<MacroRule>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MacroRule {
+    /// The tokens between the parentheses, with `$name:kind` for the fragments it binds.
+    pub pattern: Vec<TokenTree>,
+    /// The tokens between the braces, with `$name` where the fragments go.
+    pub template: Vec<TokenTree>,
+    pub span: Span,
+}
+
+/// A token, or a group of them between matching delimiters.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TokenTree {
+    Token(Token, Span),
+    Group(Delimiter, Vec<TokenTree>, Span),
+}
+
+impl TokenTree {
+    pub fn span(&self) -> Span {
+        match self {
+            TokenTree::Token(_, span) | TokenTree::Group(_, _, span) => *span,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Delimiter {
+    /// `( ... )`
+    Paren,
+    /// `{ ... }`
+    Brace,
+    /// `[ ... ]`
+    Bracket,
+}
+
+impl Delimiter {
+    /// The opening and closing tokens.
+    pub fn tokens(&self) -> (Token, Token) {
+        match self {
+            Delimiter::Paren => (Token::LeftParen, Token::RightParen),
+            Delimiter::Brace => (Token::LeftBracket, Token::RightBracket),
+            Delimiter::Bracket => (Token::LeftSquareBracket, Token::RightSquareBracket),
+        }
+    }
+}
+
+/// `name!(arguments)`, as an expression or as a statement.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MacroCall {
+    pub name: Ident,
+    pub args: Vec<TokenTree>,
+    /// The span between the parentheses.
+    pub args_span: Span,
+    pub span: Span,
+}
@@ -1,19 +1,39 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use cfg::Cfg;
 use modules::Module;
 
+pub mod cfg;
 pub mod common;
 pub mod constants;
 pub mod enums;
 pub mod expressions;
 pub mod functions;
 pub mod imports;
+pub mod macros;
 pub mod modules;
+pub mod patterns;
 pub mod statements;
 pub mod structs;
 pub mod traits;
 pub mod types;
 
 /// A compile represents a whole package, made up of various modules.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct CompilationUnit {
     pub modules: Vec<Module>,
+    /// The names the top level modules of other units are referred to by in this one, for the
+    /// packages whose modules were renamed because the build has them from several sources.
+    pub module_aliases: HashMap<String, String>,
+    /// The configuration the `#[cfg(...)]` attributes of its items are checked against.
+    pub cfg: Cfg,
+    /// Whether it's a unit of a dependency of the package being built, whose unused code isn't
+    /// warned about.
+    pub is_dependency: bool,
+    /// Whether it's loaded from the metadata of a dependency built already: its items are
+    /// configured and its macros and derives expanded, and the functions without an inlinable
+    /// body only have their signature, their code being in the object the dependency was built to.
+    pub is_prebuilt: bool,
 }
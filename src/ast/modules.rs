@@ -1,19 +1,23 @@
 use std::{path::PathBuf, sync::Arc};
 
+use serde::{Deserialize, Serialize};
+
 use super::{
-    common::{DocString, Ident, Span},
+    common::{Attribute, DocString, Ident, Span},
     constants::ConstantDef,
     enums::{EnumDecl, UnionDecl},
-    functions::{FunctionDecl, FunctionDef, ImplBlock, ImplTraitBlock},
+    functions::{ExternBlock, FunctionDecl, FunctionDef, ImplBlock, ImplTraitBlock},
     imports::ImportStmt,
+    macros::MacroDef,
     structs::StructDecl,
     traits::TraitDecl,
     types::TypeDecl,
 };
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Module {
     pub doc_string: Option<DocString>,
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub contents: Vec<ModuleDefItem>,
     pub file_path: PathBuf,
@@ -21,11 +25,12 @@ pub struct Module {
 }
 
 /// Top level module items, behind Arcs so they are cheaply clonable during lowering.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum ModuleDefItem {
     Constant(Arc<ConstantDef>),
     Function(Arc<FunctionDef>),
     FunctionDecl(Arc<FunctionDecl>),
+    ExternBlock(Arc<ExternBlock>),
     Impl(Arc<ImplBlock>),
     ImplTrait(Arc<ImplTraitBlock>),
     Struct(Arc<StructDecl>),
@@ -36,4 +41,5 @@ pub enum ModuleDefItem {
     Module(Arc<Module>),
     ExternalModule(Ident),
     Import(Arc<ImportStmt>),
+    Macro(Arc<MacroDef>),
 }
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use super::common::{Ident, Span, TypeName};
+
+/// Destructures a value into bindings, in lets, parameters and the fields of match arms.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Pattern {
+    /// Binds the whole value to the name.
+    Binding(Ident),
+    /// `Name { a, b: pattern }`, the fields left out are ignored.
+    Struct {
+        name: TypeName,
+        fields: Vec<FieldPattern>,
+        span: Span,
+    },
+    /// `Name#Variant { a, b: pattern }`, it can only be used outside of match arms if the enum
+    /// has a single variant, as otherwise the value may not match it.
+    Variant {
+        name: TypeName,
+        variant: Ident,
+        fields: Vec<FieldPattern>,
+        span: Span,
+    },
+}
+
+/// `field: pattern`, or just `field` to bind it to its own name.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct FieldPattern {
+    pub name: Ident,
+    pub pattern: Pattern,
+    pub span: Span,
+}
+
+impl Pattern {
+    pub fn get_span(&self) -> Span {
+        match self {
+            Pattern::Binding(name) => name.span,
+            Pattern::Struct { span, .. } | Pattern::Variant { span, .. } => *span,
+        }
+    }
+
+    /// The names bound by the pattern, in order.
+    pub fn bindings(&self) -> Vec<&Ident> {
+        match self {
+            Pattern::Binding(name) => vec![name],
+            Pattern::Struct { fields, .. } | Pattern::Variant { fields, .. } => {
+                fields.iter().flat_map(|x| x.pattern.bindings()).collect()
+            }
+        }
+    }
+}
@@ -1,30 +1,66 @@
+use serde::{Deserialize, Serialize};
+
 use super::{
     common::{Ident, Span},
     expressions::{Expression, FnCallOp, IfExpr, MatchExpr, PathOp},
+    macros::MacroCall,
+    patterns::Pattern,
     types::TypeDescriptor,
 };
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Statement {
     Assign(AssignStmt),
     Match(MatchExpr),
     For(ForStmt),
+    ForIn(ForInStmt),
     If(IfExpr),
     Let(LetStmt),
     Return(ReturnStmt),
+    /// `become call(args);`, returning the value of a call made in place of the current
+    /// function, guaranteed to not grow the stack.
+    Become(BecomeStmt),
     While(WhileStmt),
     FnCall(FnCallOp),
     // To allow method calls.
     PathOp(PathOp),
+    MacroCall(MacroCall),
+    Defer(DeferStmt),
+    Unsafe(UnsafeBlock),
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Assign(x) => x.span,
+            Statement::Match(x) => x.span,
+            Statement::For(x) => x.span,
+            Statement::ForIn(x) => x.span,
+            Statement::If(x) => x.span,
+            Statement::Let(x) => x.span,
+            Statement::Return(x) => x.span,
+            Statement::Become(x) => x.span,
+            Statement::While(x) => x.span,
+            Statement::FnCall(x) => x.span,
+            Statement::PathOp(x) => x.span,
+            Statement::MacroCall(x) => x.span,
+            Statement::Defer(x) => x.span,
+            Statement::Unsafe(x) => x.span,
+        }
+    }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum LetStmtTarget {
-    Simple { id: Ident, r#type: TypeDescriptor },
-    Destructure(Vec<Binding>),
+    Simple {
+        id: Ident,
+        r#type: TypeDescriptor,
+    },
+    /// `let Name { a, b } = value;`, the type comes from the pattern.
+    Destructure(Pattern),
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct LetStmt {
     pub is_mutable: bool,
     pub target: LetStmtTarget,
@@ -32,13 +68,19 @@ pub struct LetStmt {
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ReturnStmt {
     pub value: Option<Expression>,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BecomeStmt {
+    pub value: Expression,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct AssignStmt {
     pub lvalue: PathOp,
     pub derefs: usize,
@@ -46,14 +88,7 @@ pub struct AssignStmt {
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
-pub struct Binding {
-    pub id: Ident,
-    pub rename: Option<Ident>,
-    pub r#type: TypeDescriptor,
-}
-
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ForStmt {
     pub init: Option<LetStmt>,
     pub condition: Option<Expression>,
@@ -62,8 +97,45 @@ pub struct ForStmt {
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// `for x in iterable { ... }`, the block runs once per item with it bound to the name.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ForInStmt {
+    pub name: Ident,
+    pub iterable: ForIterable,
+    pub block_stmts: Vec<Statement>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ForIterable {
+    /// `start..end`, the end is excluded.
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+    },
+    /// An array, a slice or a value with a `next` method returning an option-like enum.
+    Value(Box<Expression>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct WhileStmt {
     pub condition: Expression,
     pub block_stmts: Vec<Statement>,
+    pub span: Span,
+}
+
+/// `defer { ... }` or `defer call();`, the statements run when the block it's in is left, by
+/// reaching its end or returning.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DeferStmt {
+    pub block_stmts: Vec<Statement>,
+    pub span: Span,
+}
+
+/// `unsafe { ... }`, the statements can dereference raw pointers, offset them and call `unsafe`
+/// functions.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UnsafeBlock {
+    pub block_stmts: Vec<Statement>,
+    pub span: Span,
 }
@@ -1,22 +1,30 @@
+use serde::{Deserialize, Serialize};
+
 use super::{
-    common::{Attribute, GenericParam, Ident, Span},
+    common::{Attribute, DocString, GenericParam, Ident, Span},
+    expressions::Expression,
     types::TypeDescriptor,
 };
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct StructDecl {
+    pub doc_string: Option<DocString>,
     pub name: Ident,
     pub generics: Vec<GenericParam>,
     pub attributes: Vec<Attribute>,
     pub fields: Vec<Field>,
     pub is_pub: bool,
+    pub vis_span: Span,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Field {
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub r#type: TypeDescriptor,
     pub is_pub: bool,
+    /// `= value`, the constant a struct field takes when it isn't given.
+    pub default: Option<Expression>,
     pub span: Span,
 }
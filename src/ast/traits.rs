@@ -1,19 +1,23 @@
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
+
 use crate::ir::Span;
 
 use super::{
-    common::{DocString, GenericParam, Ident},
+    common::{Attribute, DocString, GenericParam, Ident},
     functions::FunctionDecl,
     types::AssociatedType,
 };
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TraitDecl {
     pub doc_string: Option<DocString>,
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub generic_params: Vec<GenericParam>,
     pub is_pub: bool,
+    pub vis_span: Span,
     pub span: Span,
     pub associated_types: Vec<Arc<AssociatedType>>,
     pub methods: Vec<Arc<FunctionDecl>>,
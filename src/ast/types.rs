@@ -1,9 +1,13 @@
 use std::fmt;
 
-use super::common::{DocString, Ident, Span, TypeName};
+use super::{
+    common::{Attribute, DocString, Ident, Span, TypeName},
+    expressions::{Expression, ValueExpr},
+};
 use educe::Educe;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Educe)]
+#[derive(Clone, Debug, Educe, Serialize, Deserialize)]
 #[educe(PartialEq, Eq, Hash)]
 pub enum TypeDescriptor {
     Type {
@@ -33,7 +37,19 @@ pub enum TypeDescriptor {
     },
     Array {
         of: Box<Self>,
-        size: u64,
+        size: ArraySize,
+        #[educe(PartialEq(ignore), Hash(ignore))]
+        span: Span,
+    },
+    /// A view into an array, `[T]`: a pointer to its elements and their count.
+    Slice {
+        of: Box<Self>,
+        #[educe(PartialEq(ignore), Hash(ignore))]
+        span: Span,
+    },
+    /// An anonymous product type, `(A, B)`.
+    Tuple {
+        of: Vec<Self>,
         #[educe(PartialEq(ignore), Hash(ignore))]
         span: Span,
     },
@@ -45,6 +61,15 @@ pub enum TypeDescriptor {
     },
 }
 
+/// The length of an array type.
+#[derive(Clone, Debug, Educe, Serialize, Deserialize)]
+#[educe(PartialEq, Eq, Hash)]
+pub enum ArraySize {
+    Literal(u64),
+    /// An expression evaluated at compile time, like `N * 2` where `N` is a constant.
+    Constant(#[educe(Hash(ignore))] Box<Expression>),
+}
+
 impl TypeDescriptor {
     pub fn get_name(&self) -> Option<String> {
         match self {
@@ -54,6 +79,8 @@ impl TypeDescriptor {
             TypeDescriptor::ConstPtr { of, .. } => of.get_name(),
             TypeDescriptor::MutPtr { of, .. } => of.get_name(),
             TypeDescriptor::Array { .. } => None,
+            TypeDescriptor::Slice { .. } => None,
+            TypeDescriptor::Tuple { .. } => None,
             TypeDescriptor::SelfType { .. } => None,
         }
     }
@@ -73,6 +100,8 @@ impl TypeDescriptor {
             TypeDescriptor::ConstPtr { span, .. } => *span,
             TypeDescriptor::MutPtr { span, .. } => *span,
             TypeDescriptor::Array { span, .. } => *span,
+            TypeDescriptor::Slice { span, .. } => *span,
+            TypeDescriptor::Tuple { span, .. } => *span,
             TypeDescriptor::SelfType { span, .. } => *span,
         }
     }
@@ -97,7 +126,21 @@ impl fmt::Display for TypeDescriptor {
             TypeDescriptor::MutRef { of, .. } => write!(f, "&mut {of}"),
             TypeDescriptor::ConstPtr { of, .. } => write!(f, "*const {of}"),
             TypeDescriptor::MutPtr { of, .. } => write!(f, "*mut {of}"),
-            TypeDescriptor::Array { of, .. } => write!(f, "&{of}"),
+            TypeDescriptor::Array { of, size, .. } => write!(f, "[{of}; {size}]"),
+            TypeDescriptor::Slice { of, .. } => write!(f, "[{of}]"),
+            TypeDescriptor::Tuple { of, .. } => {
+                write!(f, "(")?;
+                for (i, ty) in of.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{ty}")?;
+                }
+                if of.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
             TypeDescriptor::SelfType { is_ref, is_mut, .. } => match (*is_ref, *is_mut) {
                 (true, true) => write!(f, "&mut self"),
                 (true, false) => write!(f, "&self"),
@@ -108,15 +151,32 @@ impl fmt::Display for TypeDescriptor {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+impl fmt::Display for ArraySize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArraySize::Literal(size) => write!(f, "{size}"),
+            ArraySize::Constant(expr) => match expr.as_ref() {
+                Expression::Value(ValueExpr::Path(path), _) if path.extra.is_empty() => {
+                    write!(f, "{}", path.first.name)
+                }
+                _ => write!(f, "_"),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct TypeDecl {
     pub doc_string: Option<DocString>,
+    pub attributes: Vec<Attribute>,
     pub name: Ident,
     pub value: TypeDescriptor,
+    pub is_pub: bool,
+    pub vis_span: Span,
     pub span: Span,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct AssociatedType {
     pub doc_string: Option<DocString>,
     pub name: Ident,
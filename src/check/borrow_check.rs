@@ -0,0 +1,520 @@
+//! The aliasing rules of borrows: while a shared borrow of a value is still used, the value can't
+//! be mutated or borrowed mutably, and while a mutable borrow of it is still used, the value can't
+//! be used other than through that borrow.
+//!
+//! There are no lifetimes, a borrow lives as long as a local holding it is used later in the
+//! function. The locals that can hold borrows are the ones with references in their type, and the
+//! result of a call holds the borrows of its arguments.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::{
+    ast::common::Span,
+    ir::{
+        BlockIndex, Function, IR, LocalIndex, Mutability, Operand, Place, PlaceElem, Rvalue,
+        StatementKind, TerminatorKind, Type, TypeIndex, lowering::LoweringError,
+    },
+};
+
+/// Checks the borrows of all the functions of the program.
+pub fn check_borrows(ir: &IR) -> Result<(), LoweringError> {
+    for (_, function) in ir.functions.iter() {
+        if let Some(function) = function {
+            if !function.basic_blocks.is_empty() {
+                BorrowChecker::new(ir, function).check()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A borrow taken in the function.
+struct Loan {
+    local: LocalIndex,
+    mutability: Mutability,
+    span: Option<Span>,
+    /// Whether it borrows the local itself rather than a value behind a reference it holds, the
+    /// local being gone once the function returns.
+    in_frame: bool,
+}
+
+/// What a statement does to a place.
+#[derive(Clone, Copy)]
+enum Access {
+    Read,
+    Write,
+    Borrow(Mutability),
+}
+
+/// The borrows each local may hold.
+type Held = Vec<BTreeSet<usize>>;
+
+struct BorrowChecker<'a> {
+    ir: &'a IR,
+    function: &'a Function,
+    /// Whether the type of each local has references, so it can hold borrows.
+    holds_borrows: Vec<bool>,
+    loans: Vec<Loan>,
+    /// The loan taken at each block, statement and call argument.
+    loan_ids: HashMap<(BlockIndex, usize, usize), usize>,
+    /// The locals used after each statement of each block, the terminator being the last one.
+    live_after: Vec<Vec<BTreeSet<LocalIndex>>>,
+}
+
+impl<'a> BorrowChecker<'a> {
+    fn new(ir: &'a IR, function: &'a Function) -> Self {
+        let holds_borrows = function
+            .locals
+            .iter()
+            .map(|x| has_references(ir, x.ty, &mut HashSet::new()))
+            .collect();
+
+        Self {
+            ir,
+            function,
+            holds_borrows,
+            loans: Vec::new(),
+            loan_ids: HashMap::new(),
+            live_after: liveness(function),
+        }
+    }
+
+    fn check(&mut self) -> Result<(), LoweringError> {
+        let blocks = self.function.basic_blocks.len();
+        let mut entry: Vec<Option<Held>> = vec![None; blocks];
+        entry[0] = Some(vec![BTreeSet::new(); self.function.locals.len()]);
+
+        // The borrows held at the start of each block, until they don't grow anymore.
+        let mut pending = BTreeSet::from([0]);
+        while let Some(block) = pending.pop_first() {
+            let mut held = entry[block].clone().expect("pending blocks are reached");
+            self.visit_block(block, &mut held, false)?;
+
+            for target in successors(&self.function.basic_blocks[block].terminator.kind) {
+                let changed = match &mut entry[target] {
+                    Some(target_held) => {
+                        let mut changed = false;
+                        for (target_loans, loans) in target_held.iter_mut().zip(&held) {
+                            for loan in loans {
+                                changed |= target_loans.insert(*loan);
+                            }
+                        }
+                        changed
+                    }
+                    target_held => {
+                        *target_held = Some(held.clone());
+                        true
+                    }
+                };
+
+                if changed {
+                    pending.insert(target);
+                }
+            }
+        }
+
+        for (block, held) in entry.into_iter().enumerate() {
+            if let Some(mut held) = held {
+                self.visit_block(block, &mut held, true)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the borrows held through the block, checking its accesses if `report` is set.
+    fn visit_block(
+        &mut self,
+        block: BlockIndex,
+        held: &mut Held,
+        report: bool,
+    ) -> Result<(), LoweringError> {
+        let basic_block = &self.function.basic_blocks[block];
+
+        for (idx, statement) in basic_block.statements.iter().enumerate() {
+            match &statement.kind {
+                StatementKind::Assign(place, rvalue) => {
+                    let active = self.active_loans(held, block, idx, Some(place));
+                    let loans = self.visit_rvalue(
+                        (block, idx, 0),
+                        rvalue,
+                        statement.span,
+                        held,
+                        &active,
+                        report,
+                    )?;
+
+                    if report {
+                        self.check_access(place, Access::Write, statement.span, &active)?;
+                    }
+                    self.store(place, loans, held);
+                }
+                StatementKind::StorageLive(_) => {}
+                StatementKind::StorageDead(local) => held[*local].clear(),
+            }
+        }
+
+        let idx = basic_block.statements.len();
+        let span = basic_block.terminator.span;
+        match &basic_block.terminator.kind {
+            TerminatorKind::Call {
+                args, destination, ..
+            } => {
+                let mut active = self.active_loans(held, block, idx, Some(destination));
+                if report {
+                    self.check_access(destination, Access::Write, span, &active)?;
+                }
+
+                // The borrows of the arguments live until the call returns.
+                let mut loans = BTreeSet::new();
+                for (arg_idx, arg) in args.iter().enumerate() {
+                    let arg_loans =
+                        self.visit_rvalue((block, idx, arg_idx), arg, span, held, &active, report)?;
+                    active.extend(arg_loans.iter().copied());
+                    loans.extend(arg_loans);
+                }
+
+                self.store(destination, loans, held);
+            }
+            TerminatorKind::TailCall { args, .. } => {
+                let active = self.active_loans(held, block, idx, None);
+
+                // The callee runs in place of the function, so its arguments can't borrow the
+                // locals of the function.
+                for (arg_idx, arg) in args.iter().enumerate() {
+                    let loans =
+                        self.visit_rvalue((block, idx, arg_idx), arg, span, held, &active, report)?;
+                    let Some(loan) = loans.iter().map(|x| &self.loans[*x]).find(|x| x.in_frame)
+                    else {
+                        continue;
+                    };
+                    if let Some(span) = span.filter(|_| report) {
+                        let local = &self.function.locals[loan.local];
+                        return Err(LoweringError::InvalidTailCall {
+                            span,
+                            reason: format!(
+                                "the call borrows `{}`, which doesn't outlive the caller",
+                                local.debug_name.as_deref().unwrap_or("value")
+                            ),
+                            path: self.ir.modules[self.function.module_idx].file_path.clone(),
+                        });
+                    }
+                }
+            }
+            TerminatorKind::SwitchInt {
+                discriminator: cond,
+                ..
+            }
+            | TerminatorKind::BoundsCheck { cond, .. }
+            | TerminatorKind::OverflowCheck { cond, .. } => {
+                if report {
+                    if let Operand::Place(place) = cond {
+                        let active = self.active_loans(held, block, idx, None);
+                        self.check_access(place, Access::Read, span, &active)?;
+                    }
+                }
+            }
+            TerminatorKind::UndefinedCheck { check, .. } => {
+                if report {
+                    if let Operand::Place(place) = check.operand() {
+                        let active = self.active_loans(held, block, idx, None);
+                        self.check_access(place, Access::Read, span, &active)?;
+                    }
+                }
+            }
+            TerminatorKind::Goto { .. } | TerminatorKind::Return | TerminatorKind::Unreachable => {}
+        }
+
+        Ok(())
+    }
+
+    /// Checks the accesses of the value and returns the borrows it holds.
+    fn visit_rvalue(
+        &mut self,
+        point: (BlockIndex, usize, usize),
+        rvalue: &Rvalue,
+        span: Option<Span>,
+        held: &Held,
+        active: &BTreeSet<usize>,
+        report: bool,
+    ) -> Result<BTreeSet<usize>, LoweringError> {
+        let mut loans = BTreeSet::new();
+
+        for place in rvalue_places(rvalue) {
+            loans.extend(held[place.local].iter().copied());
+            for elem in &place.projection {
+                if let PlaceElem::Index(local) = elem {
+                    loans.extend(held[*local].iter().copied());
+                }
+            }
+        }
+
+        // A slice borrows the elements it points to.
+        let borrow = match rvalue {
+            Rvalue::Ref(mutability, place) => Some((*mutability, place)),
+            Rvalue::Slice(place, ..) => Some((Mutability::Not, place)),
+            _ => None,
+        };
+
+        if report {
+            for place in rvalue_places(rvalue) {
+                let access = match borrow {
+                    Some((mutability, borrowed)) if std::ptr::eq(place, borrowed) => {
+                        Access::Borrow(mutability)
+                    }
+                    _ => Access::Read,
+                };
+                self.check_access(place, access, span, active)?;
+            }
+        }
+
+        if let Some((mutability, place)) = borrow {
+            let next_id = self.loans.len();
+            let id = *self.loan_ids.entry(point).or_insert(next_id);
+            if id == next_id {
+                self.loans.push(Loan {
+                    local: place.local,
+                    mutability,
+                    span,
+                    in_frame: !place.projection.contains(&PlaceElem::Deref),
+                });
+            }
+            loans.insert(id);
+        }
+
+        Ok(loans)
+    }
+
+    /// Records the borrows stored in the place.
+    fn store(&self, place: &Place, loans: BTreeSet<usize>, held: &mut Held) {
+        let local = place.local;
+        if !self.holds_borrows[local] {
+            if place.projection.is_empty() {
+                held[local].clear();
+            }
+            return;
+        }
+
+        if place.projection.is_empty() {
+            held[local] = loans;
+        } else {
+            held[local].extend(loans);
+        }
+    }
+
+    /// The borrows held by the locals that are used after the statement, except the one it
+    /// overwrites.
+    fn active_loans(
+        &self,
+        held: &Held,
+        block: BlockIndex,
+        statement: usize,
+        destination: Option<&Place>,
+    ) -> BTreeSet<usize> {
+        let overwritten = destination
+            .filter(|x| x.projection.is_empty())
+            .map(|x| x.local);
+
+        self.live_after[block][statement]
+            .iter()
+            .filter(|x| Some(**x) != overwritten)
+            .flat_map(|x| held[*x].iter().copied())
+            .collect()
+    }
+
+    fn check_access(
+        &self,
+        place: &Place,
+        access: Access,
+        span: Option<Span>,
+        active: &BTreeSet<usize>,
+    ) -> Result<(), LoweringError> {
+        let conflict = active.iter().map(|x| &self.loans[*x]).find(|loan| {
+            loan.local == place.local
+                && match access {
+                    Access::Write | Access::Borrow(Mutability::Mut) => true,
+                    Access::Read | Access::Borrow(Mutability::Not) => {
+                        loan.mutability == Mutability::Mut
+                    }
+                }
+        });
+
+        let Some(loan) = conflict else {
+            return Ok(());
+        };
+        let Some(span) = span.or(loan.span) else {
+            return Ok(());
+        };
+
+        let local = &self.function.locals[place.local];
+        Err(LoweringError::BorrowConflict {
+            span,
+            borrow_span: loan.span,
+            name: local
+                .debug_name
+                .clone()
+                .unwrap_or_else(|| "value".to_string()),
+            access: match access {
+                Access::Read => "use",
+                Access::Write => "assign to",
+                Access::Borrow(Mutability::Not) => "borrow",
+                Access::Borrow(Mutability::Mut) => "mutably borrow",
+            },
+            mutable_borrow: loan.mutability == Mutability::Mut,
+            path: self.ir.modules[self.function.module_idx].file_path.clone(),
+        })
+    }
+}
+
+/// The locals used after each statement of each block, the terminator being the last one.
+fn liveness(function: &Function) -> Vec<Vec<BTreeSet<LocalIndex>>> {
+    let blocks = &function.basic_blocks;
+    let mut live_in = vec![BTreeSet::new(); blocks.len()];
+    let mut live_after = vec![Vec::new(); blocks.len()];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for (idx, block) in blocks.iter().enumerate().rev() {
+            let mut live: BTreeSet<LocalIndex> = successors(&block.terminator.kind)
+                .into_iter()
+                .flat_map(|x| live_in[x].iter().copied())
+                .collect();
+            let mut points = vec![BTreeSet::new(); block.statements.len() + 1];
+
+            points[block.statements.len()] = live.clone();
+            match &block.terminator.kind {
+                TerminatorKind::Call {
+                    args, destination, ..
+                } => {
+                    define(destination, &mut live);
+                    for place in args.iter().flat_map(rvalue_places) {
+                        use_place(place, &mut live);
+                    }
+                }
+                TerminatorKind::TailCall { args, .. } => {
+                    for place in args.iter().flat_map(rvalue_places) {
+                        use_place(place, &mut live);
+                    }
+                }
+                TerminatorKind::SwitchInt {
+                    discriminator: Operand::Place(place),
+                    ..
+                }
+                | TerminatorKind::BoundsCheck {
+                    cond: Operand::Place(place),
+                    ..
+                }
+                | TerminatorKind::OverflowCheck {
+                    cond: Operand::Place(place),
+                    ..
+                } => use_place(place, &mut live),
+                TerminatorKind::UndefinedCheck { check, .. } => {
+                    if let Operand::Place(place) = check.operand() {
+                        use_place(place, &mut live);
+                    }
+                }
+                _ => {}
+            }
+
+            for (statement_idx, statement) in block.statements.iter().enumerate().rev() {
+                points[statement_idx] = live.clone();
+                match &statement.kind {
+                    StatementKind::Assign(place, rvalue) => {
+                        define(place, &mut live);
+                        for place in rvalue_places(rvalue) {
+                            use_place(place, &mut live);
+                        }
+                    }
+                    StatementKind::StorageLive(_) => {}
+                    StatementKind::StorageDead(local) => {
+                        live.remove(local);
+                    }
+                }
+            }
+
+            if live != live_in[idx] {
+                live_in[idx] = live;
+                changed = true;
+            }
+            live_after[idx] = points;
+        }
+    }
+
+    live_after
+}
+
+/// A write to the place: a whole local is overwritten, a part of it keeps the rest.
+fn define(place: &Place, live: &mut BTreeSet<LocalIndex>) {
+    if place.projection.is_empty() {
+        live.remove(&place.local);
+    } else {
+        use_place(place, live);
+    }
+}
+
+fn use_place(place: &Place, live: &mut BTreeSet<LocalIndex>) {
+    live.insert(place.local);
+    for elem in &place.projection {
+        if let PlaceElem::Index(local) = elem {
+            live.insert(*local);
+        }
+    }
+}
+
+pub(crate) fn rvalue_places(rvalue: &Rvalue) -> Vec<&Place> {
+    let (place, operands) = match rvalue {
+        Rvalue::Use(op) | Rvalue::UnaryOp(_, op) | Rvalue::Cast(op, _, _) => (None, vec![op]),
+        Rvalue::LogicOp(_, (lhs, rhs))
+        | Rvalue::BinaryOp(_, (lhs, rhs))
+        | Rvalue::Overflows(_, (lhs, rhs)) => (None, vec![lhs, rhs]),
+        Rvalue::Ref(_, place) | Rvalue::Move(place) | Rvalue::Len(place) => (Some(place), vec![]),
+        Rvalue::Slice(place, start, end, _) => (Some(place), vec![start, end]),
+    };
+
+    place
+        .into_iter()
+        .chain(operands.into_iter().filter_map(|x| match x {
+            Operand::Place(place) => Some(place),
+            Operand::Const(_) => None,
+        }))
+        .collect()
+}
+
+pub(crate) fn successors(terminator: &TerminatorKind) -> Vec<BlockIndex> {
+    match terminator {
+        TerminatorKind::Goto { target }
+        | TerminatorKind::BoundsCheck { target, .. }
+        | TerminatorKind::OverflowCheck { target, .. }
+        | TerminatorKind::UndefinedCheck { target, .. } => {
+            vec![*target]
+        }
+        TerminatorKind::Call { target, .. } => target.iter().copied().collect(),
+        TerminatorKind::SwitchInt { targets, .. } => targets.targets.clone(),
+        TerminatorKind::Return | TerminatorKind::TailCall { .. } | TerminatorKind::Unreachable => {
+            Vec::new()
+        }
+    }
+}
+
+/// Whether values of the type can have references, raw pointers aren't tracked.
+fn has_references(ir: &IR, ty: TypeIndex, visited: &mut HashSet<TypeIndex>) -> bool {
+    if !visited.insert(ty) {
+        return false;
+    }
+
+    match &ir.types[ty] {
+        Some(Type::Ref(..) | Type::Slice(_)) => true,
+        Some(Type::Array(inner, _)) => has_references(ir, *inner, visited),
+        Some(Type::Adt(adt)) => ir.aggregates[*adt].as_ref().is_some_and(|adt| {
+            adt.variants
+                .iter()
+                .flat_map(|x| &x.fields)
+                .any(|x| has_references(ir, x.ty, visited))
+        }),
+        Some(Type::Tuple(fields)) => fields.iter().any(|x| has_references(ir, *x, visited)),
+        _ => false,
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use ariadne::{ColorGenerator, Label, Report, ReportKind, Source};
+use serde_json::{Value, json};
+
+use crate::{
+    ast::common::Span,
+    parser::{error::SourceError, read_source},
+};
+
+use super::{
+    FileSpan,
+    suggestions::{Applicability, Suggestion},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A message from the compiler about the source code, emitted by every phase.
+///
+/// The parser, lowering and lints all create diagnostics, which are rendered the same way by
+/// [`Diagnostic::to_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: Option<String>,
+    /// The file of the primary span.
+    pub path: PathBuf,
+    /// Where the diagnostic is reported, also used to tell duplicates apart.
+    pub span: Span,
+    /// The labeled spans, in the order they are shown, they may point to other files.
+    pub labels: Vec<DiagnosticLabel>,
+    pub notes: Vec<String>,
+    pub helps: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticLabel {
+    pub path: PathBuf,
+    pub span: Span,
+    pub message: Option<String>,
+}
+
+impl DiagnosticLabel {
+    pub fn new(path: PathBuf, span: Span) -> Self {
+        Self {
+            path,
+            span,
+            message: None,
+        }
+    }
+
+    pub fn with_message(mut self, message: impl fmt::Display) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: impl Into<String>, path: PathBuf, span: Span) -> Self {
+        Self {
+            severity,
+            code: code.into(),
+            message: None,
+            path,
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            helps: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn error(code: impl Into<String>, path: PathBuf, span: Span) -> Self {
+        Self::new(Severity::Error, code, path, span)
+    }
+
+    pub fn warning(code: impl Into<String>, path: PathBuf, span: Span) -> Self {
+        Self::new(Severity::Warning, code, path, span)
+    }
+
+    pub fn with_message(mut self, message: impl fmt::Display) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// Adds a label on the primary file.
+    pub fn with_label(mut self, span: Span, message: impl fmt::Display) -> Self {
+        self.labels
+            .push(DiagnosticLabel::new(self.path.clone(), span).with_message(message));
+        self
+    }
+
+    /// Adds a label on the given file.
+    pub fn with_label_in(mut self, label: DiagnosticLabel) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl fmt::Display) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl fmt::Display) -> Self {
+        self.helps.push(help.to_string());
+        self
+    }
+
+    pub fn with_suggestions(mut self, suggestions: impl IntoIterator<Item = Suggestion>) -> Self {
+        self.suggestions.extend(suggestions);
+        self
+    }
+
+    /// The message of the diagnostic, or the one of the label at its primary span if it has none.
+    pub fn primary_message(&self) -> String {
+        self.message
+            .clone()
+            .or_else(|| {
+                self.labels
+                    .iter()
+                    .find(|x| x.span == self.span && x.path == self.path)
+                    .and_then(|x| x.message.clone())
+            })
+            .unwrap_or_else(|| self.code.clone())
+    }
+
+    /// The primary span, as used by the reports.
+    pub fn file_span(&self) -> FileSpan {
+        FileSpan::new(self.path.display().to_string(), self.span.into())
+    }
+
+    /// Creates the report of this diagnostic, the only place diagnostics are turned into reports.
+    pub fn to_report(&self) -> Report<'static, FileSpan> {
+        let mut colors = ColorGenerator::new();
+        colors.next();
+
+        let kind = match self.severity {
+            Severity::Error => ReportKind::Error,
+            Severity::Warning => ReportKind::Warning,
+        };
+
+        let mut report = Report::build(kind, self.file_span()).with_code(&self.code);
+
+        if let Some(message) = &self.message {
+            report = report.with_message(message);
+        }
+
+        for (i, label) in self.labels.iter().enumerate() {
+            let filespan = FileSpan::new(label.path.display().to_string(), label.span.into());
+            let mut label_report = Label::new(filespan)
+                .with_order(i as i32)
+                .with_color(colors.next());
+
+            if let Some(message) = &label.message {
+                label_report = label_report.with_message(message);
+            }
+
+            report = report.with_label(label_report);
+        }
+
+        for note in &self.notes {
+            report = report.with_note(note);
+        }
+
+        for help in &self.helps {
+            report = report.with_help(help);
+        }
+
+        for suggestion in &self.suggestions {
+            report = report.with_help(suggestion.help());
+        }
+
+        report.finish()
+    }
+
+    /// Renders the diagnostic in a single line, as `file:line:col: error[code]: message`.
+    pub fn to_short(&self, sources: &mut SourceCache) -> String {
+        let path = self.path.display().to_string();
+        let (line, column) = sources.line_col(&path, self.span.from).unwrap_or((1, 1));
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        format!(
+            "{}:{}:{}: {}[{}]: {}",
+            path,
+            line,
+            column,
+            severity,
+            self.code,
+            self.primary_message()
+        )
+    }
+
+    /// The diagnostic as a single JSON object, for `--message-format=json`. The spans are byte
+    /// offsets into their file, with the line and column of their start when the file can be read.
+    pub fn to_json(&self, sources: &mut SourceCache) -> Value {
+        let labels: Vec<Value> = self
+            .labels
+            .iter()
+            .map(|x| {
+                json!({
+                    "file": x.path.display().to_string(),
+                    "span": json_span(&x.path, x.span, sources),
+                    "message": x.message,
+                })
+            })
+            .collect();
+
+        let suggestions: Vec<Value> = self
+            .suggestions
+            .iter()
+            .map(|x| {
+                let file = x.file(&self.path);
+                json!({
+                    "message": x.message,
+                    "file": file.display().to_string(),
+                    "span": json_span(file, x.span, sources),
+                    "replacement": x.replacement,
+                    "machine_applicable": x.applicability == Applicability::MachineApplicable,
+                })
+            })
+            .collect();
+
+        json!({
+            "code": self.code,
+            "severity": match self.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            },
+            "file": self.path.display().to_string(),
+            "span": json_span(&self.path, self.span, sources),
+            "message": self.primary_message(),
+            "labels": labels,
+            "notes": self.notes,
+            "helps": self.helps,
+            "suggestions": suggestions,
+        })
+    }
+
+    /// Prints the diagnostic to stderr, reading the sources from the cache.
+    pub fn eprint(&self, cache: &mut SourceCache) -> std::io::Result<()> {
+        self.to_report().eprint(cache)
+    }
+}
+
+fn json_span(path: &Path, span: Span, sources: &mut SourceCache) -> Value {
+    let mut value = json!({ "from": span.from, "to": span.to });
+
+    if let Some((line, column)) = sources.line_col(&path.display().to_string(), span.from) {
+        value["line"] = json!(line);
+        value["column"] = json!(column);
+    }
+
+    value
+}
+
+/// The sources the diagnostics point to, read from disk once and shared by all the reports.
+///
+/// Sources that don't exist on disk can be added with [`SourceCache::insert`].
+#[derive(Default)]
+pub struct SourceCache {
+    sources: HashMap<String, Source<String>>,
+}
+
+impl SourceCache {
+    pub fn insert(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        self.sources
+            .insert(path.into(), Source::from(source.into()));
+    }
+
+    /// The 1-based line and column, counted in characters, of the byte offset in the given file.
+    pub fn line_col(&mut self, path: &str, offset: usize) -> Option<(usize, usize)> {
+        let source = ariadne::Cache::fetch(self, &path.to_string()).ok()?;
+        let (line, idx, column) = source.get_byte_line(offset)?;
+        let text = source.get_line_text(line)?;
+        let column = text.get(..column).map_or(column, |x| x.chars().count());
+
+        Some((idx + 1, column + 1))
+    }
+}
+
+impl fmt::Debug for SourceCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SourceCache")
+            .field("sources", &self.sources.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl ariadne::Cache<String> for SourceCache {
+    type Storage = String;
+
+    fn fetch(&mut self, id: &String) -> Result<&Source<Self::Storage>, impl fmt::Debug> {
+        if !self.sources.contains_key(id) {
+            let source = match read_source(Path::new(id)) {
+                Ok(x) => x,
+                Err(SourceError::InvalidUtf8 { lossy, .. }) => lossy,
+                Err(error) => return Err(error),
+            };
+            self.sources.insert(id.clone(), Source::from(source));
+        }
+
+        Ok(&self.sources[id])
+    }
+
+    fn display<'a>(&self, id: &'a String) -> Option<impl fmt::Display + 'a> {
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        check::lowering_error_to_diagnostic,
+        ir::lowering::lower_compile_units,
+        parser::{ProgramSource, parse_ast},
+    };
+
+    /// The diagnostic of the first error found parsing or lowering the source.
+    fn diagnose(source: &ProgramSource) -> Diagnostic {
+        match parse_ast(source) {
+            Ok(unit) => lowering_error_to_diagnostic(
+                lower_compile_units(&[unit]).expect_err("the source has an error"),
+            ),
+            Err(error) => error.to_diagnostic(source),
+        }
+    }
+
+    #[test]
+    fn phases_render_the_same_way() {
+        let parse = ProgramSource::new(
+            "mod A {\n    fn f( {}\n}\n".to_string(),
+            Path::new("parse.con"),
+        );
+        let lower = ProgramSource::new(
+            "mod A {\n    fn f() -> i32 { return g(); }\n}\n".to_string(),
+            Path::new("lower.con"),
+        );
+
+        let mut sources = SourceCache::default();
+        for source in [&parse, &lower] {
+            sources.insert(source.path.display().to_string(), source.input.clone());
+        }
+
+        let parse = diagnose(&parse);
+        assert_eq!(
+            parse.to_short(&mut sources),
+            "parse.con:2:11: error[P3]: Parse error."
+        );
+        let lower = diagnose(&lower);
+        assert_eq!(
+            lower.to_short(&mut sources),
+            "lower.con:2:28: error[FunctionNotFound]: Function \"g\" not found."
+        );
+        assert_eq!(
+            lower.to_json(&mut sources)["span"],
+            json!({ "from": 35, "to": 38, "line": 2, "column": 28 })
+        );
+    }
+
+    #[test]
+    fn missing_sources_have_no_location() {
+        let span = Span::new(3, 4);
+        let diagnostic = Diagnostic::error("E1", PathBuf::from("missing.con"), span)
+            .with_label(span, "Type not found.");
+
+        let mut sources = SourceCache::default();
+        assert_eq!(
+            diagnostic.to_short(&mut sources),
+            "missing.con:1:1: error[E1]: Type not found."
+        );
+        assert_eq!(
+            diagnostic.to_json(&mut sources)["span"],
+            json!({ "from": 3, "to": 4 })
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use owo_colors::OwoColorize;
+
+use crate::ast::common::Span;
+
+use super::{
+    diagnostics::{Diagnostic, Severity, SourceCache},
+    sarif::to_sarif,
+};
+
+/// How the diagnostics are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Rendered reports on stderr.
+    #[default]
+    Human,
+    /// A single line per diagnostic on stderr, as `file:line:col: error[code]: message`.
+    Short,
+    /// A JSON object per line on stdout, with the code, severity, file, byte span, message and notes.
+    Json,
+    /// A SARIF 2.1.0 log on stdout, printed once all the diagnostics are emitted.
+    Sarif,
+}
+
+/// The number of errors printed unless `--error-limit` says otherwise.
+pub const DEFAULT_ERROR_LIMIT: usize = 20;
+
+/// Prints the diagnostics of a compilation, skipping duplicates and capping the number of errors shown.
+#[derive(Debug, Default)]
+pub struct Emitter {
+    /// The maximum number of errors printed, the rest are only counted.
+    error_limit: Option<usize>,
+    /// The code, message and location of the diagnostics already printed.
+    seen: HashSet<(String, String, PathBuf, Span)>,
+    sources: SourceCache,
+    format: MessageFormat,
+    /// The diagnostics to print at the end, for the formats that print them all at once.
+    collected: Vec<Diagnostic>,
+    errors: usize,
+    warnings: usize,
+    omitted_errors: usize,
+}
+
+impl Emitter {
+    pub fn new(error_limit: Option<usize>) -> Self {
+        Self {
+            error_limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_format(mut self, format: MessageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The sources used to render the diagnostics, sources not on disk can be added to it.
+    pub fn sources_mut(&mut self) -> &mut SourceCache {
+        &mut self.sources
+    }
+
+    /// Prints the diagnostic, unless one with the same code and message at the same primary span
+    /// was already printed, or the error limit was reached.
+    pub fn emit(&mut self, diagnostic: &Diagnostic) -> std::io::Result<()> {
+        if !self.seen.insert((
+            diagnostic.code.clone(),
+            diagnostic.primary_message(),
+            diagnostic.path.clone(),
+            diagnostic.span,
+        )) {
+            return Ok(());
+        }
+
+        match diagnostic.severity {
+            Severity::Error => {
+                self.errors += 1;
+
+                if self.error_limit.is_some_and(|limit| self.errors > limit) {
+                    self.omitted_errors += 1;
+                    return Ok(());
+                }
+            }
+            Severity::Warning => self.warnings += 1,
+        }
+
+        match self.format {
+            MessageFormat::Human => diagnostic.eprint(&mut self.sources),
+            MessageFormat::Short => {
+                eprintln!("{}", diagnostic.to_short(&mut self.sources));
+                Ok(())
+            }
+            MessageFormat::Json => {
+                println!("{}", diagnostic.to_json(&mut self.sources));
+                Ok(())
+            }
+            MessageFormat::Sarif => {
+                self.collected.push(diagnostic.clone());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+
+    /// The number of errors emitted, including the ones past the error limit.
+    pub fn error_count(&self) -> usize {
+        self.errors
+    }
+
+    /// Prints the number of errors and warnings emitted, or the SARIF log with all of them.
+    pub fn print_summary(&mut self) {
+        if self.format == MessageFormat::Sarif {
+            let log = to_sarif(&self.collected, &mut self.sources);
+            println!("{log:#}");
+            return;
+        }
+
+        if self.omitted_errors > 0 {
+            eprintln!(
+                "{}: {} more error(s) omitted, use `--error-limit` to show more",
+                "note".bold(),
+                self.omitted_errors
+            );
+        }
+
+        let warnings = match self.warnings {
+            0 => None,
+            1 => Some("1 warning emitted".to_string()),
+            n => Some(format!("{n} warnings emitted")),
+        };
+
+        if self.errors > 0 {
+            let errors = match self.errors {
+                1 => "1 previous error".to_string(),
+                n => format!("{n} previous errors"),
+            };
+
+            match warnings {
+                Some(warnings) => eprintln!(
+                    "{}: aborting due to {}; {}",
+                    "error".red().bold(),
+                    errors,
+                    warnings
+                ),
+                None => eprintln!("{}: aborting due to {}", "error".red().bold(), errors),
+            }
+        } else if let Some(warnings) = warnings {
+            eprintln!("{}: {}", "warning".yellow().bold(), warnings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(code: &str, message: &str, from: usize) -> Diagnostic {
+        Diagnostic::error(code, PathBuf::from("main.con"), Span::new(from, from + 1))
+            .with_message(message)
+    }
+
+    /// An emitter collecting the diagnostics instead of printing them.
+    fn emitter(error_limit: Option<usize>) -> Emitter {
+        Emitter::new(error_limit).with_format(MessageFormat::Sarif)
+    }
+
+    #[test]
+    fn duplicates_are_emitted_once() {
+        let mut emitter = emitter(None);
+        let diagnostic = error("E1", "Unresolved type.", 4);
+        for _ in 0..3 {
+            emitter.emit(&diagnostic).unwrap();
+        }
+
+        assert_eq!(emitter.collected, vec![diagnostic]);
+        assert_eq!(emitter.error_count(), 1);
+    }
+
+    #[test]
+    fn distinct_diagnostics_are_kept() {
+        let mut emitter = emitter(None);
+        let diagnostics = [
+            error("E1", "Unresolved type.", 4),
+            error("E1", "Unresolved type.", 9),
+            error("E1", "Unresolved function.", 4),
+            error("E2", "Unresolved type.", 4),
+        ];
+        for diagnostic in &diagnostics {
+            emitter.emit(diagnostic).unwrap();
+        }
+
+        assert_eq!(emitter.collected, diagnostics);
+        assert_eq!(emitter.omitted_errors, 0);
+    }
+
+    #[test]
+    fn errors_past_the_limit_are_counted() {
+        let mut emitter = emitter(Some(2));
+        for from in 0..5 {
+            emitter
+                .emit(&error("E1", "Unresolved type.", from))
+                .unwrap();
+        }
+        emitter
+            .emit(&Diagnostic::warning(
+                "W1",
+                PathBuf::from("main.con"),
+                Span::new(0, 1),
+            ))
+            .unwrap();
+
+        assert_eq!(emitter.collected.len(), 3);
+        assert_eq!(emitter.error_count(), 5);
+        assert_eq!(emitter.omitted_errors, 3);
+        assert_eq!(emitter.warnings, 1);
+    }
+}
@@ -5,10 +5,11 @@ use crate::session::Session;
 use self::errors::LinearityError;
 pub mod errors;
 
+use crate::ast::common::Ident;
 use crate::ast::expressions::{Expression, PathOp, StructInitField, ValueExpr};
 use crate::ast::functions::{FunctionDecl, FunctionDef, Param};
 use crate::ast::modules::ModuleDefItem;
-use crate::ast::statements::{AssignStmt, Binding, LetStmt, LetStmtTarget, Statement};
+use crate::ast::statements::{AssignStmt, ForIterable, LetStmt, LetStmtTarget, Statement};
 use crate::ast::Program;
 use std::path::PathBuf;
 
@@ -42,6 +43,8 @@ struct Appearances {
     write: u32,
     read: u32,
     path: u32,
+    /// The times it's the `..base` of a struct update, which copies the fields not given.
+    base: u32,
 }
 
 #[allow(dead_code)]
@@ -57,6 +60,7 @@ impl Appearances {
             write,
             read,
             path,
+            base: 0,
         }
     }
 
@@ -89,12 +93,24 @@ impl Appearances {
         Self::new(0, 0, 0, 1)
     }
 
+    fn base_once() -> Self {
+        Appearances {
+            base: 1,
+            ..Self::zero()
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.consumed == 0 && self.write == 0 && self.read == 0 && self.path == 0 && self.base == 0
+    }
+
     fn merge(&self, other: &Appearances) -> Self {
         Appearances {
             consumed: self.consumed + other.consumed,
             write: self.write + other.write,
             read: self.read + other.read,
             path: self.path + other.path,
+            base: self.base + other.base,
         }
     }
 
@@ -335,6 +351,19 @@ impl LinearityChecker {
                 }
                 apps
             }
+            Statement::ForIn(for_in_stmt) => {
+                // Handle for-in loops
+                let block = self.count_in_statements(name, &for_in_stmt.block_stmts);
+                match &for_in_stmt.iterable {
+                    ForIterable::Range { start, end } => self
+                        .count_in_expression(name, start)
+                        .merge(&self.count_in_expression(name, end))
+                        .merge(&block),
+                    ForIterable::Value(value) => {
+                        self.count_in_expression(name, value).merge(&block)
+                    }
+                }
+            }
             Statement::Assign(assign_stmt) => {
                 // Handle assignments
                 self.count_in_assign_statement(name, assign_stmt)
@@ -347,6 +376,7 @@ impl LinearityChecker {
                     Appearances::zero()
                 }
             }
+            Statement::Become(become_stmt) => self.count_in_expression(name, &become_stmt.value),
             Statement::FnCall(fn_call_op) => {
                 // Process function call arguments
                 //fn_call_op.target.iter().map(|arg| self.count_in_path_op(name, arg)).fold(Appearances::zero(), |acc, x| acc.merge(&x));
@@ -356,6 +386,13 @@ impl LinearityChecker {
                     .map(|arg| self.count_in_expression(name, arg))
                     .fold(Appearances::zero(), |acc, x| acc.merge(&x))
             }
+            Statement::Defer(defer_stmt) => {
+                // The deferred statements run once when leaving the block, consuming what they use.
+                self.count_in_statements(name, &defer_stmt.block_stmts)
+            }
+            Statement::Unsafe(unsafe_block) => {
+                self.count_in_statements(name, &unsafe_block.block_stmts)
+            }
             Statement::Match(_) => {
                 todo!("do not support match statement")
             } //_ => Appearances::zero(),
@@ -453,12 +490,19 @@ impl LinearityChecker {
                     .merge(&self.count_in_expression(name, right))
             }
             Expression::StructInit(struct_init_expr) => {
-                // Handle struct initialization
-                struct_init_expr
+                // Handle struct initialization. The `..base` the other fields come from only
+                // copies some of its fields, so it's counted apart from consuming it.
+                let fields = struct_init_expr
                     .fields
                     .values()
                     .map(|expr| self.count_struct_init(name, expr))
-                    .fold(Appearances::zero(), |acc, x| acc.merge(&x))
+                    .fold(Appearances::zero(), |acc, x| acc.merge(&x));
+                match &struct_init_expr.base {
+                    Some(base) if !self.count_struct_init(name, base).is_zero() => {
+                        fields.merge(&Appearances::base_once())
+                    }
+                    _ => fields,
+                }
             }
             Expression::ArrayInit(array_init_expr) => {
                 // Handle array initializations
@@ -547,8 +591,8 @@ impl LinearityChecker {
                 }
                 self.check_var_in_expr(state_tbl, depth, &name.name, value, context)
             }
-            LetStmtTarget::Destructure(bindings) => {
-                for binding in bindings {
+            LetStmtTarget::Destructure(pattern) => {
+                for binding in pattern.bindings() {
                     let new_state_tbl = self.check_bindings(&state_tbl, depth, binding);
                     if let Ok(new_state_tbl) = new_state_tbl {
                         state_tbl = new_state_tbl;
@@ -563,7 +607,7 @@ impl LinearityChecker {
         &self,
         state_tbl: &StateTbl,
         depth: usize,
-        binding: &Binding,
+        binding: &Ident,
     ) -> Result<StateTbl, LinearityError> {
         // TODO Do something with the bindings
         tracing::debug!("TODO implement Checking bindings: {:?}", binding);
@@ -585,6 +629,9 @@ impl LinearityChecker {
             params,
             ret_type,
             is_extern,
+            is_const: _,
+            is_unsafe: _,
+            is_async: _,
             is_pub,
             attributes,
             span,
@@ -594,7 +641,7 @@ impl LinearityChecker {
         let mut params_vec: Vec<(String, String)> = Vec::new();
         let mut params_clean_vec: Vec<String> = Vec::new();
         for param in params {
-            let Param { name, r#type } = param;
+            let Param { name, r#type, .. } = param;
             let name = name.name.clone();
             let r#type = match r#type {
                 TypeSpec::Simple {
@@ -703,6 +750,21 @@ impl LinearityChecker {
                     self.check_stmts(state_tbl, depth + 1, &for_stmt.block_stmts, context)?;
                 Ok(state_tbl)
             }
+            Statement::ForIn(for_in_stmt) => {
+                // Handle for-in loops
+                match &for_in_stmt.iterable {
+                    ForIterable::Range { start, end } => {
+                        state_tbl = self.check_expr(state_tbl, depth, start, context)?;
+                        state_tbl = self.check_expr(state_tbl, depth, end, context)?;
+                    }
+                    ForIterable::Value(value) => {
+                        state_tbl = self.check_expr(state_tbl, depth, value, context)?;
+                    }
+                }
+                state_tbl =
+                    self.check_stmts(state_tbl, depth + 1, &for_in_stmt.block_stmts, context)?;
+                Ok(state_tbl)
+            }
             Statement::Assign(assign_stmt) => {
                 // Handle assignments
                 let AssignStmt {
@@ -749,6 +811,10 @@ impl LinearityChecker {
                     Ok(state_tbl)
                 }
             }
+            Statement::Become(become_stmt) => {
+                // The arguments of the tail call are consumed by it.
+                self.check_expr(state_tbl, depth, &become_stmt.value, context)
+            }
             Statement::FnCall(fn_call_op) => {
                 // Process function call arguments
                 for arg in &fn_call_op.args {
@@ -756,6 +822,13 @@ impl LinearityChecker {
                 }
                 Ok(state_tbl)
             }
+            Statement::Defer(defer_stmt) => {
+                // Handle defers, a linear value they consume counts as consumed.
+                self.check_stmts(state_tbl, depth + 1, &defer_stmt.block_stmts, context)
+            }
+            Statement::Unsafe(unsafe_block) => {
+                self.check_stmts(state_tbl, depth + 1, &unsafe_block.block_stmts, context)
+            }
             Statement::Match(_) => {
                 tracing::debug!("Skipping linearity check for statement type: \n{:?}", stmt);
                 todo!("Implement linearity checkt for match statement")
@@ -804,7 +877,15 @@ impl LinearityChecker {
                     write,
                     read,
                     path,
+                    base,
                 } = apps;
+                // A struct update would copy the fields of the linear value not given, leaving
+                // it half consumed.
+                if base > 0 {
+                    return Err(LinearityError::StructUpdateOfLinear {
+                        variable: name.to_string(),
+                    });
+                }
                 //tracing::debug!("Checking variable: {} with state: {:?} and appearances: {:?} in expression {:?}", name, state, apps, expr);
                 tracing::debug!(
                     "Checking state_tbl variable: {}: {:?} {:?} \n <<< context {:?} >>> \n << expression {:?} >>",
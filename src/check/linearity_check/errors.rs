@@ -28,6 +28,8 @@ pub enum LinearityError {
     Unspecified { variable: String, message: String },
     #[error("Variable {variable} not found")]
     VariableNotFound { variable: String },
+    #[error("Linear variable {variable} used as the base of a struct update")]
+    StructUpdateOfLinear { variable: String },
     #[error("Inconsistent state {message}")]
     StateInconsistency { message: String },
     #[error("Not implemented: {message}")]
@@ -0,0 +1,247 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::ast::common::Span;
+
+use super::{
+    diagnostics::{Diagnostic, Severity},
+    suggestions::Suggestion,
+};
+
+/// A check that emits a warning by default, its level can be changed with `-A`, `-W` and `-D`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Lint {
+    /// An attribute the compiler doesn't know about, usually a typo.
+    UnknownAttributes,
+    /// A key in `Concrete.toml` that isn't part of the manifest format, which would otherwise be ignored.
+    UnknownManifestKeys,
+    /// A lint named in `#[allow(...)]`, `#[warn(...)]` or `#[deny(...)]` that doesn't exist.
+    UnknownLints,
+    /// A variable or parameter whose value is never read.
+    UnusedVariables,
+    /// A private function that is never called from `main`, a public function or a test.
+    UnusedFunctions,
+    /// A private struct or enum that the used functions never use.
+    UnusedTypes,
+    /// An imported name the module never refers to.
+    UnusedImports,
+    /// A statement that can't run because the ones before it always return.
+    UnreachableCode,
+}
+
+impl Lint {
+    pub const ALL: &'static [Lint] = &[
+        Lint::UnknownAttributes,
+        Lint::UnknownManifestKeys,
+        Lint::UnknownLints,
+        Lint::UnusedVariables,
+        Lint::UnusedFunctions,
+        Lint::UnusedTypes,
+        Lint::UnusedImports,
+        Lint::UnreachableCode,
+    ];
+
+    /// The name used to refer to this lint in the command line and diagnostics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnknownAttributes => "unknown_attributes",
+            Lint::UnknownManifestKeys => "unknown_manifest_keys",
+            Lint::UnknownLints => "unknown_lints",
+            Lint::UnusedVariables => "unused_variables",
+            Lint::UnusedFunctions => "unused_functions",
+            Lint::UnusedTypes => "unused_types",
+            Lint::UnusedImports => "unused_imports",
+            Lint::UnreachableCode => "unreachable_code",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Self::ALL.iter().copied().find(|x| x.name() == name)
+    }
+
+    pub fn default_level(&self) -> LintLevel {
+        match self {
+            Lint::UnknownAttributes
+            | Lint::UnknownManifestKeys
+            | Lint::UnknownLints
+            | Lint::UnusedVariables
+            | Lint::UnusedFunctions
+            | Lint::UnusedTypes
+            | Lint::UnusedImports
+            | Lint::UnreachableCode => LintLevel::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LintLevel {
+    /// The lint is not reported.
+    Allow,
+    /// The lint is reported as a warning.
+    Warn,
+    /// The lint is reported as an error, failing the compilation.
+    Deny,
+}
+
+impl LintLevel {
+    /// The level set by a lint attribute, like `#[allow(unused_variables)]`.
+    pub fn from_attribute(name: &str) -> Option<LintLevel> {
+        match name {
+            "allow" => Some(LintLevel::Allow),
+            "warn" => Some(LintLevel::Warn),
+            "deny" => Some(LintLevel::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// The level of each lint, as configured by the user.
+#[derive(Debug, Clone, Default)]
+pub struct LintLevels {
+    levels: HashMap<Lint, LintLevel>,
+    /// The levels of the lints added by plugins, by their `<plugin>::<lint>` name.
+    plugin_levels: HashMap<String, LintLevel>,
+    /// Treat all the lints that would warn as errors.
+    pub deny_warnings: bool,
+}
+
+impl LintLevels {
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn set_plugin(&mut self, name: &str, level: LintLevel) {
+        self.plugin_levels.insert(name.to_string(), level);
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        let level = self
+            .levels
+            .get(&lint)
+            .copied()
+            .unwrap_or_else(|| lint.default_level());
+
+        self.with_deny_warnings(level)
+    }
+
+    /// The level the warning is reported at, the one set by the attributes of the items it's in
+    /// takes precedence over the one of the command line.
+    pub fn warning_level(&self, warning: &Warning) -> LintLevel {
+        match warning.level {
+            Some(level) => self.with_deny_warnings(level),
+            None => self.level(warning.lint),
+        }
+    }
+
+    /// The level of a lint added by a plugin, given the one it has by default.
+    pub fn plugin_level(&self, name: &str, default: LintLevel) -> LintLevel {
+        let level = self.plugin_levels.get(name).copied().unwrap_or(default);
+        self.with_deny_warnings(level)
+    }
+
+    fn with_deny_warnings(&self, level: LintLevel) -> LintLevel {
+        if self.deny_warnings && level == LintLevel::Warn {
+            LintLevel::Deny
+        } else {
+            level
+        }
+    }
+
+    /// Whether the lint is at its default level.
+    fn is_default(&self, lint: Lint) -> bool {
+        !self.levels.contains_key(&lint) && !self.deny_warnings
+    }
+
+    /// Whether the lint added by a plugin is at its default level.
+    pub fn is_plugin_default(&self, name: &str) -> bool {
+        !self.plugin_levels.contains_key(name) && !self.deny_warnings
+    }
+}
+
+/// A warning found while compiling, reported according to the level of its lint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub span: Span,
+    pub message: String,
+    pub suggestion: Option<Suggestion>,
+    pub path: PathBuf,
+    /// The level set by a lint attribute of the items it was found in, if any.
+    pub level: Option<LintLevel>,
+}
+
+/// An item with a lint attribute, which sets the level of the lint for the code inside it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintScope {
+    pub lint: Lint,
+    pub level: LintLevel,
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+impl LintScope {
+    /// The level set by the innermost of the scopes containing the span, if any.
+    pub fn level_at(
+        scopes: &[LintScope],
+        lint: Lint,
+        path: &Path,
+        span: Span,
+    ) -> Option<LintLevel> {
+        scopes
+            .iter()
+            // The last of the attributes of an item wins.
+            .rev()
+            .filter(|x| x.lint == lint && x.path == path)
+            .filter(|x| x.span.from <= span.from && span.to <= x.span.to)
+            .min_by_key(|x| x.span.to - x.span.from)
+            .map(|x| x.level)
+    }
+}
+
+/// Creates a diagnostic from a warning, given the level it's reported at.
+///
+/// Warnings at the allow level shouldn't be reported.
+pub fn warning_to_diagnostic(warning: &Warning, levels: &LintLevels) -> Diagnostic {
+    lint_diagnostic(
+        warning.lint.name(),
+        levels.warning_level(warning),
+        warning.level.is_none() && levels.is_default(warning.lint),
+        levels.deny_warnings,
+        &warning.path,
+        warning.span,
+        &warning.message,
+    )
+    .with_suggestions(warning.suggestion.clone())
+}
+
+/// Creates the diagnostic of a lint, builtin or added by a plugin, noting how to change its level.
+pub fn lint_diagnostic(
+    name: &str,
+    level: LintLevel,
+    is_default: bool,
+    deny_warnings: bool,
+    path: &Path,
+    span: Span,
+    message: &str,
+) -> Diagnostic {
+    let severity = match level {
+        LintLevel::Deny => Severity::Error,
+        _ => Severity::Warning,
+    };
+
+    let diagnostic = Diagnostic::new(severity, name, path.to_path_buf(), span)
+        .with_message(message)
+        .with_label(span, message);
+
+    if is_default {
+        diagnostic.with_note(format!(
+            "`{name}` is on by default, use `-A {name}` to allow it"
+        ))
+    } else if level == LintLevel::Deny && deny_warnings {
+        diagnostic.with_note("warnings are treated as errors due to `--deny-warnings`")
+    } else {
+        diagnostic
+    }
+}
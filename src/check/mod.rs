@@ -1,7 +1,18 @@
-use crate::ir::lowering::LoweringError;
-use ariadne::{ColorGenerator, Label, Report, ReportKind};
+use crate::ir::lowering::{
+    AmbiguousImport, LoweringError, MacroExpansion, PrivateItem, UnexpectedTypeError,
+};
+use diagnostics::{Diagnostic, DiagnosticLabel};
 use std::ops::Range;
 
+pub mod borrow_check;
+pub mod diagnostics;
+pub mod emitter;
+pub mod lints;
+pub mod moves;
+pub mod sarif;
+pub mod suggestions;
+pub mod tail_calls;
+pub mod unused;
 // pub mod linearity_check;
 
 #[derive(Debug, Clone)]
@@ -32,88 +43,97 @@ impl ariadne::Span for FileSpan {
     }
 }
 
-/// Creates a report from a lowering error.
-pub fn lowering_error_to_report(error: LoweringError) -> Report<'static, FileSpan> {
-    let mut colors = ColorGenerator::new();
-    colors.next();
-    match error {
-        LoweringError::TraitNotFound { span, name, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("TraitNotFound")
-                .with_label(
-                    Label::new(filespan)
-                        .with_message(format!("Trait {name:?} not found."))
-                        .with_color(colors.next()),
-                )
-                .with_message("Unresolved trait.")
-                .finish()
-        }
-        LoweringError::ModuleNotFound { span, module, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("ModuleNotFound")
-                .with_label(
-                    Label::new(filespan)
-                        .with_message(format!("Module {module:?} not found."))
-                        .with_color(colors.next()),
-                )
-                .with_message("Unresolved import.")
-                .finish()
-        }
+/// Creates the diagnostics of the errors a lowering error holds, see [`LoweringError::Multiple`].
+pub fn lowering_error_to_diagnostics(error: LoweringError) -> Vec<Diagnostic> {
+    error
+        .into_errors()
+        .into_iter()
+        .map(lowering_error_to_diagnostic)
+        .collect()
+}
+
+/// Creates a diagnostic from a lowering error, only the first one of several errors, see
+/// [`lowering_error_to_diagnostics`].
+pub fn lowering_error_to_diagnostic(error: LoweringError) -> Diagnostic {
+    let code = error.code();
+    let suggestions = error.suggestions();
+    let diagnostic = match error {
+        LoweringError::TraitNotFound { span, name, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("Trait {name:?} not found."))
+            .with_message("Unresolved trait."),
+        LoweringError::ModuleNotFound { span, module, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("Module {module:?} not found."))
+            .with_message("Unresolved import."),
         LoweringError::FunctionNotFound {
             span,
             function,
             path,
-        } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("FunctionNotFound")
-                .with_label(
-                    Label::new(filespan)
-                        .with_message(format!("Function {function:?} not found."))
-                        .with_color(colors.next()),
-                )
-                .finish()
-        }
-        LoweringError::FieldNotFound { span, name, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("FieldNotFound")
-                .with_label(
-                    Label::new(filespan)
-                        .with_message(format!("field {name:?} not found."))
-                        .with_color(colors.next()),
-                )
-                .finish()
-        }
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("Function {function:?} not found.")),
+        LoweringError::FieldNotFound { span, name, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("field {name:?} not found.")),
+        LoweringError::VariantNotFound {
+            span,
+            name,
+            type_name,
+            path,
+            ..
+        } => Diagnostic::error(code, path, span).with_label(
+            span,
+            format!("variant {name:?} not found in {type_name:?}."),
+        ),
         LoweringError::ImportNotFound {
             import_span,
             module_span,
             symbol,
             path,
-        } => {
-            let path = path.display().to_string();
-            let span = symbol.span;
-            let filespan = FileSpan::new(path.clone(), span.into());
-            let module_span = FileSpan::new(path.clone(), module_span.into());
-            let import_span = FileSpan::new(path.clone(), import_span.into());
-            let symbol_span = FileSpan::new(path, symbol.span.into());
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("ImportNotFound")
-                .with_label(Label::new(module_span.clone()).with_message("In module this module."))
-                .with_label(Label::new(import_span).with_message("In this import statement"))
-                .with_label(
-                    Label::new(symbol_span)
-                        .with_message(format!("Failed to find symbol {:?}", symbol.name))
-                        .with_color(colors.next()),
-                )
-                .with_message("Unresolved import.")
-                .finish()
+        } => Diagnostic::error(code, path, symbol.span)
+            .with_label(module_span, "In module this module.")
+            .with_label(import_span, "In this import statement")
+            .with_label(
+                symbol.span,
+                format!("Failed to find symbol {:?}", symbol.name),
+            )
+            .with_message("Unresolved import."),
+        LoweringError::AmbiguousImport(error) => {
+            let AmbiguousImport {
+                span,
+                name,
+                path,
+                def_span,
+                def_path,
+            } = *error;
+
+            Diagnostic::error(code, path, span)
+                .with_label(span, format!("the glob import brings another `{name}`"))
+                .with_label_in(
+                    DiagnosticLabel::new(def_path, def_span)
+                        .with_message(format!("`{name}` is already defined here")),
+                )
+                .with_message(format!("Ambiguous name `{name}`."))
+                .with_help(format!(
+                    "import the items needed by name, or import `{name}` with another name using `as`"
+                ))
+        }
+        LoweringError::PrivateItem(error) => {
+            let PrivateItem {
+                span,
+                kind,
+                name,
+                path,
+                def_span,
+                def_path,
+                ..
+            } = *error;
+
+            // The help is the suggestion to add `pub`.
+            Diagnostic::error(code, path, span)
+                .with_label(span, format!("{kind} `{name}` is private"))
+                .with_label_in(
+                    DiagnosticLabel::new(def_path, def_span)
+                        .with_message(format!("{kind} `{name}` is defined here without `pub`")),
+                )
+                .with_message(format!("Use of a private {kind}."))
         }
         LoweringError::BorrowNotMutable {
             span,
@@ -121,505 +141,573 @@ pub fn lowering_error_to_report(error: LoweringError) -> Report<'static, FileSpa
             type_span,
             path,
         } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.into());
-            let mut labels = vec![
-                Label::new(filespan.clone())
-                    .with_message(format!(
-                        "Can't mutate {name:?} because it's behind a immutable borrow"
-                    ))
-                    .with_color(colors.next()),
-            ];
+            let mut diagnostic = Diagnostic::error(code, path, span).with_label(
+                span,
+                format!("Can't mutate {name:?} because it's behind a immutable borrow"),
+            );
 
             if let Some(type_span) = type_span {
-                labels.push(
-                    Label::new(FileSpan::new(path.clone(), type_span.into()))
-                        .with_message(format!("Variable {name:?} has this type"))
-                        .with_color(colors.next()),
-                );
+                diagnostic =
+                    diagnostic.with_label(type_span, format!("Variable {name:?} has this type"));
             }
 
-            Report::build(ReportKind::Error, filespan)
-                .with_code("BorrowNotMutable")
-                .with_labels(labels)
-                .finish()
-        }
-        LoweringError::UnrecognizedType { span, name, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("UnrecognizedType")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!("Failed to find type {:?}", name))
-                        .with_color(colors.next()),
-                )
-                .with_message(format!("Unresolved type {:?}.", name))
-                .finish()
+            diagnostic
         }
+        LoweringError::UnrecognizedType { span, name, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("Failed to find type {:?}", name))
+            .with_message(format!("Unresolved type {:?}.", name)),
         LoweringError::NotYetImplemented {
             span,
             message,
             path,
-        } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("NotYetImplemented")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(message)
-                        .with_color(colors.next()),
-                )
-                .finish()
-        }
-        LoweringError::UnexpectedType {
-            found_span: span,
-            found,
-            expected,
-            expected_span,
-            path,
-        } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.from..span.to);
-            let mut labels = vec![
-                Label::new(filespan.clone())
-                    .with_message(format!(
-                        "Unexpected type '{}', expected '{}'",
-                        found, expected
-                    ))
-                    .with_color(colors.next()),
-            ];
+        } => Diagnostic::error(code, path, span).with_label(span, message),
+        LoweringError::UnexpectedType(error) => {
+            let UnexpectedTypeError {
+                found_span: span,
+                found,
+                expected,
+                expected_span,
+                expected_path,
+                difference,
+                path,
+            } = *error;
+
+            let mut diagnostic = Diagnostic::error(code, path.clone(), span)
+                .with_label(span, format!("expected `{}`, found `{}`", expected, found))
+                .with_message("Mismatched types.");
 
-            if let Some(span) = expected_span {
-                labels.push(
-                    Label::new(FileSpan::new(path.clone(), span.into()))
-                        .with_message(format!("expected '{}' due to this expression", expected))
-                        .with_color(colors.next()),
+            if let Some(expected_span) = expected_span {
+                diagnostic = diagnostic.with_label_in(
+                    DiagnosticLabel::new(expected_path.unwrap_or(path), expected_span)
+                        .with_message(format!("expected `{}` because of this", expected)),
                 );
             }
 
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("UnexpectedType")
-                .with_labels(labels)
-                .with_message(format!("expected type {}", expected))
-                .finish()
+            if let Some(difference) = difference {
+                diagnostic = diagnostic.with_note(format!(
+                    "the types differ inside `{}`: expected `{}` in place of `_`, found `{}`",
+                    difference.context, difference.expected, difference.found
+                ));
+            }
+
+            diagnostic
         }
         LoweringError::InvalidUnaryOp {
             found_span: span,
             found,
             path,
-        } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.from..span.to);
-            let labels = vec![
-                Label::new(filespan.clone())
-                    .with_message(format!("Invalid binary operation type '{}'", found))
-                    .with_color(colors.next()),
-            ];
-
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("InvalidUnaryOp")
-                .with_labels(labels)
-                .with_message(format!("invalid binary operation type {}", found))
-                .finish()
-        }
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("Invalid binary operation type '{}'", found))
+            .with_message(format!("invalid binary operation type {}", found)),
         LoweringError::UseOfUndeclaredVariable { span, name, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("UseOfUndeclaredVariable")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!("Use of undeclared variable {:?}", name))
-                        .with_color(colors.next()),
-                )
-                .finish()
-        }
-        LoweringError::ExternFnWithBody { span, name, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("ExternFnWithBody")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!("extern function {:?} declared with body", name))
-                        .with_color(colors.next()),
-                )
-                .finish()
+            Diagnostic::error(code, path, span)
+                .with_label(span, format!("Use of undeclared variable {:?}", name))
         }
+        LoweringError::ExternFnWithBody { span, name, path } => Diagnostic::error(code, path, span)
+            .with_label(
+                span,
+                format!("extern function {:?} declared with body", name),
+            ),
         LoweringError::CallParamCountMismatch {
             span,
             found,
             needs,
             path,
-        } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("CallParamCountMismatch")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!(
-                            "function call parameter count mismatch: found {}, needs {}.",
-                            found, needs
-                        ))
-                        .with_color(colors.next()),
-                )
-                .finish()
-        }
+            fn_span,
+            fn_path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(
+                span,
+                format!(
+                    "function call parameter count mismatch: found {}, needs {}.",
+                    found, needs
+                ),
+            )
+            .with_label_in(
+                DiagnosticLabel::new(fn_path, fn_span).with_message("function declared here"),
+            ),
         LoweringError::GenericCountMismatch {
             span,
             found,
             needs,
             path,
-        } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path, span.from..span.to);
-            Report::build(ReportKind::Error, filespan.clone())
-                .with_code("GenericCountMismatch")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!(
-                            "function call generic parameter count mismatch: found {}, needs {}.",
-                            found, needs
-                        ))
-                        .with_color(colors.next()),
-                )
-                .finish()
-        }
+            fn_span,
+            fn_path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(
+                span,
+                format!(
+                    "function call generic parameter count mismatch: found {}, needs {}.",
+                    found, needs
+                ),
+            )
+            .with_label_in(
+                DiagnosticLabel::new(fn_path, fn_span).with_message("function declared here"),
+            ),
         LoweringError::NotMutable {
             span,
             declare_span,
             path,
+            ..
         } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.from..span.to);
-            let mut report = Report::build(ReportKind::Error, filespan.clone())
-                .with_code("NotMutable")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message("can't mutate this variable because it's not mutable")
-                        .with_color(colors.next()),
-                );
+            let mut diagnostic = Diagnostic::error(code, path, span)
+                .with_label(span, "can't mutate this variable because it's not mutable");
 
             if let Some(declare_span) = declare_span {
-                let declare_span = FileSpan::new(path, declare_span.into());
-                report = report.with_label(
-                    Label::new(declare_span)
-                        .with_message("variable declared here")
-                        .with_color(colors.next()),
-                );
+                diagnostic = diagnostic.with_label(declare_span, "variable declared here");
             }
-            report.with_help("Add the 'mut' keyword to the variable declaration to make the variable mutable.").finish()
+
+            diagnostic
         }
         LoweringError::CantTakeMutableBorrow {
             span,
             declare_span,
             path,
+            ..
         } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.from..span.to);
-            let mut report = Report::build(ReportKind::Error, filespan.clone())
-                        .with_code("CantTakeMutableBorrow")
-                        .with_label(
-                            Label::new(filespan.clone())
-                                .with_message("can't take a mutate borrow to this variable because it's not declared mutable")
-                                .with_color(colors.next()),
-                        );
+            let mut diagnostic = Diagnostic::error(code, path, span).with_label(
+                span,
+                "can't take a mutate borrow to this variable because it's not declared mutable",
+            );
 
             if let Some(declare_span) = declare_span {
-                let declare_span = FileSpan::new(path, declare_span.into());
-                report = report.with_label(
-                    Label::new(declare_span)
-                        .with_message("variable declared here")
-                        .with_color(colors.next()),
-                );
+                diagnostic = diagnostic.with_label(declare_span, "variable declared here");
             }
-            report.finish()
+
+            diagnostic
         }
-        LoweringError::UnknownLangItem { span, item, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.from..span.to);
-            let report = Report::build(ReportKind::Error, filespan.clone())
-                .with_code("UnknownLangItem")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!("unknown lang item '{}'", item))
-                        .with_color(colors.next()),
-                );
-            report.finish()
+        LoweringError::UnknownLangItem { span, item, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("unknown lang item '{}'", item)),
+        LoweringError::MissingLangItem { span, item, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("needs the `{item}` lang item"))
+            .with_message(format!(
+                "std defines the `{item}` lang item, a program built without it has to define \
+                 its own, marked with `#[langitem = \"{item}\"]`."
+            )),
+        LoweringError::InvalidMatch { span, reason, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("invalid match: '{}'", reason)),
+        LoweringError::Unimplemented { span, reason, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("unimplemented: '{}'", reason)),
+        LoweringError::InvalidAttribute {
+            span,
+            name,
+            reason,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("invalid attribute '{}': {}", name, reason)),
+        LoweringError::InvalidMacro {
+            span,
+            name,
+            reason,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, reason)
+            .with_message(format!("Invalid use of macro `{name}`.")),
+        LoweringError::InvalidCast {
+            span,
+            from,
+            to,
+            help,
+            path,
+        } => {
+            let diagnostic = Diagnostic::error(code, path, span)
+                .with_label(span, format!("can't cast `{from}` to `{to}`"))
+                .with_message("`as` converts between numbers, characters, booleans and pointers.");
+            match help {
+                Some(help) => diagnostic.with_help(help),
+                None => diagnostic,
+            }
         }
-        LoweringError::InvalidMatch { span, reason, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.from..span.to);
-            let report = Report::build(ReportKind::Error, filespan.clone())
-                .with_code("InvalidMatch")
+        LoweringError::LiteralOutOfRange {
+            span,
+            ty,
+            range: (min, max),
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("doesn't fit in `{ty}`"))
+            .with_message(format!("The literal is out of the range of `{ty}`."))
+            .with_note(format!("the values of `{ty}` go from {min} to {max}"))
+            .with_help("a wider type holds it, and `as` converts it to a narrower one explicitly"),
+        LoweringError::MissingFields {
+            span,
+            name,
+            fields,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(
+                span,
+                format!(
+                    "missing {}",
+                    fields
+                        .iter()
+                        .map(|x| format!("`{x}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )
+            .with_message(format!("Missing fields in the initialization of `{name}`."))
+            .with_help(format!(
+                "give them a value, or copy them from another `{name}` with `..base`"
+            )),
+        LoweringError::MethodNotFound {
+            span,
+            name,
+            ty,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("`{ty}` has no function `{name}`"))
+            .with_message(format!(
+                "Function `{name}` not found in the impl blocks of `{ty}`."
+            )),
+        LoweringError::NotAMethod {
+            span,
+            name,
+            ty,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("`{name}` doesn't take `self`"))
+            .with_message(format!(
+                "`{name}` is an associated function of `{ty}`, not a method."
+            ))
+            .with_help(format!("call it with `{ty}#{name}(...)`")),
+        LoweringError::Multiple(errors) => {
+            let first = errors.into_iter().next().expect("there are several errors");
+            return lowering_error_to_diagnostic(first);
+        }
+        LoweringError::InMacroExpansion(expansion) => {
+            let MacroExpansion {
+                error,
+                name,
+                call_span,
+                path,
+                def_span,
+                def_path,
+            } = *expansion;
+
+            lowering_error_to_diagnostic(error)
+                .with_label_in(
+                    DiagnosticLabel::new(path, call_span)
+                        .with_message(format!("in this expansion of `{name}!`")),
+                )
+                .with_label_in(
+                    DiagnosticLabel::new(def_path, def_span)
+                        .with_message(format!("the code comes from this rule of `{name}`")),
+                )
+        }
+        LoweringError::BorrowConflict {
+            span,
+            borrow_span,
+            name,
+            access,
+            mutable_borrow,
+            path,
+        } => {
+            let borrowed = if mutable_borrow {
+                "mutably borrowed"
+            } else {
+                "borrowed"
+            };
+            let mut diagnostic = Diagnostic::error(code, path, span)
                 .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!("invalid match: '{}'", reason))
-                        .with_color(colors.next()),
+                    span,
+                    format!("Can't {access} {name:?} while it's {borrowed}"),
+                )
+                .with_message("Conflicting borrow.");
+
+            if let Some(borrow_span) = borrow_span {
+                diagnostic = diagnostic.with_label(
+                    borrow_span,
+                    format!("{name:?} is {borrowed} here, and the borrow is used later"),
                 );
-            report.finish()
+            }
+
+            diagnostic
         }
-        LoweringError::Unimplemented { span, reason, path } => {
-            let path = path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), span.from..span.to);
-            let report = Report::build(ReportKind::Error, filespan.clone())
-                .with_code("Unimplemented")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(format!("unimplemented: '{}'", reason))
-                        .with_color(colors.next()),
+        LoweringError::UseOfMovedValue {
+            span,
+            move_span,
+            name,
+            path,
+        } => {
+            let mut diagnostic = Diagnostic::error(code, path, span)
+                .with_label(span, format!("{name:?} is used after it was moved"))
+                .with_message(
+                    "A struct update moves the fields of structs and enums it takes from its \
+                     base, so they and the base can't be used until they're assigned again.",
                 );
-            report.finish()
+
+            if let Some(move_span) = move_span {
+                diagnostic = diagnostic.with_label(move_span, format!("{name:?} is moved here"));
+            }
+
+            diagnostic
+        }
+        LoweringError::NotIndexable { span, found, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("can't index into a value of type {found}"))
+            .with_message("Only arrays and slices can be indexed and sliced."),
+        LoweringError::RefutablePattern {
+            span,
+            variant,
+            type_name,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(
+                span,
+                format!("`{variant}` is only one of the variants of `{type_name}`"),
+            )
+            .with_message(
+                "Lets and parameters only take patterns matching any value of the type, use a \
+                 match for enum variants.",
+            ),
+        LoweringError::NotIterable { span, found, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("can't iterate over a value of type {found}"))
+            .with_message(
+                "A for loop takes a range, an array, a slice or a value with a `next` method \
+                 returning an enum with a `Some` variant, like `Option<T>`.",
+            ),
+        LoweringError::InvalidTry { span, reason, path } => Diagnostic::error(code, path, span)
+            .with_label(span, reason)
+            .with_message(
+                "`?` unwraps the value of an `Option` or `Result`, and otherwise returns its \
+                 `None` or `Err` from a function returning the same kind of enum.",
+            ),
+        LoweringError::InvalidAwait { span, reason, path } => Diagnostic::error(code, path, span)
+            .with_label(span, reason)
+            .with_message(
+                "`await` waits for the value of a `Future`, like the one calling an async \
+                 function returns, suspending the async function it's in until it's ready.",
+            ),
+        LoweringError::InvalidAsyncFn {
+            span,
+            name: _,
+            reason,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, reason)
+            .with_message(
+                "Calling an async function returns a `Future` of its value, its body runs when \
+                 the future is polled, e.g by `std::task::block_on`.",
+            ),
+        LoweringError::InvalidTailCall { span, reason, path } => {
+            Diagnostic::error(code, path, span)
+                .with_label(span, reason)
+                .with_message(
+                    "`become` returns the value of a call made in place of the current function, \
+                     the callee needs the same parameter and return types, and nothing of the \
+                     caller can be left to run or be borrowed once it's called.",
+                )
+        }
+        LoweringError::ReturnInDefer {
+            span,
+            defer_span,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, "returns from the function")
+            .with_label(defer_span, "while running this defer")
+            .with_message(
+                "The statements of a defer run while the block is left, including by returning, \
+                 so they can't return themselves, with `return` or `?`.",
+            ),
+        LoweringError::UnsafeOperation {
+            span,
+            operation,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("{operation} is unsafe"))
+            .with_message(
+                "Raw pointers aren't checked to point to valid values, so dereferencing them, \
+                 offsetting them and calling `unsafe fn`s is only allowed in `unsafe { ... }` \
+                 blocks and `unsafe fn`s.",
+            ),
+        LoweringError::UnknownAbi { span, abi, path } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("unknown ABI {abi:?}"))
+            .with_message("The functions of an extern block are called with the \"C\" ABI."),
+        LoweringError::NotConst { span, reason, path } => Diagnostic::error(code, path, span)
+            .with_label(span, reason)
+            .with_message(
+                "Constants, array sizes and discriminants are evaluated at compile time, from \
+                 literals, other constants, operators, casts and calls to `const fn`.",
+            ),
+        LoweringError::DuplicateDiscriminant {
+            span,
+            first_span,
+            value,
+            first,
+            second,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(span, format!("`{second}` has the discriminant {value}"))
+            .with_label(first_span, format!("but `{first}` has it already"))
+            .with_message("Each variant of an enum needs its own discriminant."),
+        LoweringError::DiscriminantOverflow {
+            span,
+            name,
+            previous,
+            path,
+        } => Diagnostic::error(code, path, span)
+            .with_label(
+                span,
+                format!("the discriminant of `{name}` is past the largest `u32`"),
+            )
+            .with_message("Enum discriminant overflowed.")
+            .with_note(format!(
+                "it's the one after the discriminant of `{previous}`, give it an explicit one"
+            )),
+        LoweringError::RecursiveTypeAlias { span, name, path } => {
+            Diagnostic::error(code, path, span)
+                .with_label(span, format!("`{name}` refers to itself"))
+                .with_message(
+                    "A type alias is another name for its type, so it can't be given by itself, \
+                     directly or through other aliases.",
+                )
         }
         LoweringError::MissingVariant(error) => {
-            let path = error.path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), error.match_span.from..error.match_span.to);
-            let variant_filespan = FileSpan::new(
-                error.type_path.display().to_string(),
-                error.variant_span.from..error.variant_span.to,
-            );
-            let ty_filespan = FileSpan::new(
-                error.type_path.display().to_string(),
-                error.type_span.from..error.type_span.to,
-            );
-            let report = Report::build(ReportKind::Error, filespan.clone())
-                .with_code("MissingVariant")
+            Diagnostic::error(code, error.path.clone(), error.match_span)
                 .with_label(
-                    Label::new(filespan.clone())
-                        .with_order(0)
-                        .with_message(format!(
-                            "missing variant: '{}' for match with type '{}'",
-                            error.variant_name, error.type_name
-                        ))
-                        .with_color(colors.next()),
+                    error.match_span,
+                    format!(
+                        "missing variant: '{}' for match with type '{}'",
+                        error.variant_name, error.type_name
+                    ),
                 )
-                .with_label(
-                    Label::new(variant_filespan)
-                        .with_order(1)
-                        .with_message("With the missing variant defined here")
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.type_path.clone(), error.variant_span)
+                        .with_message("With the missing variant defined here"),
                 )
-                .with_label(
-                    Label::new(ty_filespan)
-                        .with_order(2)
-                        .with_message("For the type defined here")
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.type_path, error.type_span)
+                        .with_message("For the type defined here"),
                 )
-                .with_help("Add the missing variant to fix the issue.");
-            report.finish()
+                .with_help("Add the missing variant to fix the issue.")
         }
         LoweringError::MissingTraitType(error) => {
-            let path = error.path.display().to_string();
-            let trait_path = error.trait_path.display().to_string();
-            let type_name_span = FileSpan::new(path.clone(), error.type_name_span.into());
-            let assoc_type_name_span =
-                FileSpan::new(trait_path.clone(), error.assoc_type_name_span_def.into());
-            let assoc_trait_span = FileSpan::new(trait_path.clone(), error.trait_span.into());
-            let impl_trait_span = FileSpan::new(path.clone(), error.impl_trait_span.into());
-            let report = Report::build(ReportKind::Error, impl_trait_span.clone())
-                .with_code("MissingTraitType")
+            Diagnostic::error(code, error.path.clone(), error.impl_trait_span)
                 .with_label(
-                    Label::new(impl_trait_span.clone())
-                        .with_message(format!(
-                            "in the trait implementation {:?}",
-                            error.trait_name
-                        ))
-                        .with_color(colors.next()),
+                    error.impl_trait_span,
+                    format!("in the trait implementation {:?}", error.trait_name),
                 )
                 .with_label(
-                    Label::new(type_name_span.clone())
-                        .with_message(format!("for type {}", error.type_name))
-                        .with_color(colors.next()),
+                    error.type_name_span,
+                    format!("for type {}", error.type_name),
                 )
-                .with_label(
-                    Label::new(assoc_trait_span.clone())
-                        .with_message(format!("for the trait {} defined here", error.trait_name))
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path.clone(), error.trait_span)
+                        .with_message(format!("for the trait {} defined here", error.trait_name)),
                 )
-                .with_label(
-                    Label::new(assoc_type_name_span.clone())
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path, error.assoc_type_name_span_def)
                         .with_message(format!(
                             "missing the following associated type: {}",
                             error.assoc_type_name
-                        ))
-                        .with_color(colors.next()),
+                        )),
                 )
                 .with_message(format!(
                     "missing trait type {:?} in implementation for type {:?}",
                     error.assoc_type_name, error.type_name
                 ))
-                .with_help("Add the associated type to the trait implementation");
-            report.finish()
+                .with_help("Add the associated type to the trait implementation")
         }
         LoweringError::UnexpectedTraitType(error) => {
-            let path = error.path.display().to_string();
-            let trait_path = error.trait_path.display().to_string();
-            let type_name_span = FileSpan::new(path.clone(), error.type_name_span.into());
-            let assoc_type_name_span =
-                FileSpan::new(trait_path.clone(), error.assoc_type_name_span_def.into());
-            let assoc_trait_span = FileSpan::new(trait_path.clone(), error.trait_span.into());
-            let impl_trait_span = FileSpan::new(path.clone(), error.impl_trait_span.into());
-            let report = Report::build(ReportKind::Error, impl_trait_span.clone())
-                .with_code("UnexpectedTraitType")
+            Diagnostic::error(code, error.path.clone(), error.impl_trait_span)
                 .with_label(
-                    Label::new(impl_trait_span.clone())
-                        .with_message(format!(
-                            "in the trait implementation {:?}",
-                            error.trait_name
-                        ))
-                        .with_color(colors.next()),
+                    error.impl_trait_span,
+                    format!("in the trait implementation {:?}", error.trait_name),
                 )
                 .with_label(
-                    Label::new(type_name_span.clone())
-                        .with_message(format!("for type {}", error.type_name))
-                        .with_color(colors.next()),
+                    error.type_name_span,
+                    format!("for type {}", error.type_name),
                 )
-                .with_label(
-                    Label::new(assoc_trait_span.clone())
-                        .with_message(format!("for the trait {} defined here", error.trait_name))
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path.clone(), error.trait_span)
+                        .with_message(format!("for the trait {} defined here", error.trait_name)),
                 )
-                .with_label(
-                    Label::new(assoc_type_name_span.clone())
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path, error.assoc_type_name_span_def)
                         .with_message(format!(
                             "unexpected associated type: {}",
                             error.assoc_type_name
-                        ))
-                        .with_color(colors.next()),
+                        )),
                 )
                 .with_message(format!(
                     "unexpected trait type {:?} in implementation for type {:?}",
                     error.assoc_type_name, error.type_name
                 ))
-                .with_help("Remove the unexpected associated type from the trait implementation");
-            report.finish()
+                .with_help("Remove the unexpected associated type from the trait implementation")
         }
         LoweringError::MissingTraitFunction(error) => {
-            let path = error.path.display().to_string();
-            let trait_path = error.trait_path.display().to_string();
-            let type_name_span = FileSpan::new(path.clone(), error.type_name_span.into());
-            let assoc_type_name_span =
-                FileSpan::new(trait_path.clone(), error.func_name_span_def.into());
-            let assoc_trait_span = FileSpan::new(trait_path.clone(), error.trait_span.into());
-            let impl_trait_span = FileSpan::new(path.clone(), error.impl_trait_span.into());
-            let report = Report::build(ReportKind::Error, impl_trait_span.clone())
-                .with_code("MissingTraitFunction")
+            Diagnostic::error(code, error.path.clone(), error.impl_trait_span)
                 .with_label(
-                    Label::new(impl_trait_span.clone())
-                        .with_message(format!(
-                            "in the trait implementation {:?}",
-                            error.trait_name
-                        ))
-                        .with_color(colors.next()),
+                    error.impl_trait_span,
+                    format!("in the trait implementation {:?}", error.trait_name),
                 )
                 .with_label(
-                    Label::new(type_name_span.clone())
-                        .with_message(format!("for type {}", error.type_name))
-                        .with_color(colors.next()),
+                    error.type_name_span,
+                    format!("for type {}", error.type_name),
                 )
-                .with_label(
-                    Label::new(assoc_trait_span.clone())
-                        .with_message(format!("for the trait {} defined here", error.trait_name))
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path.clone(), error.trait_span)
+                        .with_message(format!("for the trait {} defined here", error.trait_name)),
                 )
-                .with_label(
-                    Label::new(assoc_type_name_span.clone())
-                        .with_message(format!(
-                            "missing the following function: {}",
-                            error.func_name
-                        ))
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path, error.func_name_span_def).with_message(
+                        format!("missing the following function: {}", error.func_name),
+                    ),
                 )
                 .with_message(format!(
                     "missing trait function {:?} in implementation for type {:?}",
                     error.func_name, error.type_name
                 ))
-                .with_help("Implement the missing function in the trait implementation");
-            report.finish()
+                .with_help("Implement the missing function in the trait implementation")
         }
         LoweringError::UnexpectedTraitFunction(error) => {
-            let path = error.path.display().to_string();
-            let trait_path = error.trait_path.display().to_string();
-            let type_name_span = FileSpan::new(path.clone(), error.type_name_span.into());
-            let assoc_type_name_span =
-                FileSpan::new(trait_path.clone(), error.func_name_span_def.into());
-            let assoc_trait_span = FileSpan::new(trait_path.clone(), error.trait_span.into());
-            let impl_trait_span = FileSpan::new(path.clone(), error.impl_trait_span.into());
-            let report = Report::build(ReportKind::Error, impl_trait_span.clone())
-                .with_code("UnexpectedTraitFunction")
+            Diagnostic::error(code, error.path.clone(), error.impl_trait_span)
                 .with_label(
-                    Label::new(impl_trait_span.clone())
-                        .with_message(format!(
-                            "in the trait implementation {:?}",
-                            error.trait_name
-                        ))
-                        .with_color(colors.next()),
+                    error.impl_trait_span,
+                    format!("in the trait implementation {:?}", error.trait_name),
                 )
                 .with_label(
-                    Label::new(type_name_span.clone())
-                        .with_message(format!("for type {}", error.type_name))
-                        .with_color(colors.next()),
+                    error.type_name_span,
+                    format!("for type {}", error.type_name),
                 )
-                .with_label(
-                    Label::new(assoc_trait_span.clone())
-                        .with_message(format!("for the trait {} defined here", error.trait_name))
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path.clone(), error.trait_span)
+                        .with_message(format!("for the trait {} defined here", error.trait_name)),
                 )
-                .with_label(
-                    Label::new(assoc_type_name_span.clone())
-                        .with_message(format!("unexpected function: {}", error.func_name))
-                        .with_color(colors.next()),
+                .with_label_in(
+                    DiagnosticLabel::new(error.trait_path, error.func_name_span_def)
+                        .with_message(format!("unexpected function: {}", error.func_name)),
                 )
                 .with_message(format!(
                     "unexpected function {:?} in implementation for type {:?}",
                     error.func_name, error.type_name
                 ))
-                .with_help("Remove the unexpected function from the trait implementation");
-            report.finish()
+                .with_help("Remove the unexpected function from the trait implementation")
         }
-        LoweringError::CantInferType(error) => {
-            let path = error.path.display().to_string();
-            let filespan = FileSpan::new(path.clone(), error.span.into());
-            let report = Report::build(ReportKind::Error, filespan.clone())
-                .with_code("CantInferType")
-                .with_label(
-                    Label::new(filespan.clone())
-                        .with_message(error.message)
-                        .with_color(colors.next()),
-                )
-                .with_help("Specify the generic type at the function call.");
-            report.finish()
-        }
-        LoweringError::TraitBoundNotMet(error) => {
-            let path = error.path.display().to_string();
-            let func_span = FileSpan::new(path.clone(), error.func_name_span.into());
-            let param_span = FileSpan::new(path.clone(), error.param_span.into());
-            let trait_span = FileSpan::new(path.clone(), error.trait_span.into());
-            let report = Report::build(ReportKind::Error, func_span.clone())
-                .with_code("TraitBoundNotMet")
-                .with_label(
-                    Label::new(func_span.clone())
-                        .with_message(format!(
-                            "in the following function call {:?}",
-                            error.func_name
-                        ))
-                        .with_color(colors.next()),
-                )
-                .with_label(
-                    Label::new(param_span.clone())
-                        .with_message("this parameter type doesn't implement the required trait bounds")
-                        .with_color(colors.next()),
-                )
-                .with_label(
-                    Label::new(trait_span.clone())
-                        .with_message("This trait bound is not implemented for the given parameter type at the function call")
-                        .with_color(colors.next()),
-                )
-                .with_help(format!("Implement the trait {:?} for the passed parameter type.", error.trait_name));
-            report.finish()
-        }
-    }
+        LoweringError::CantInferType(error) => Diagnostic::error(code, error.path, error.span)
+            .with_label(error.span, error.message)
+            .with_help("Specify the generic type at the function call."),
+        LoweringError::TraitBoundNotMet(error) => Diagnostic::error(
+            code,
+            error.path,
+            error.func_name_span,
+        )
+        .with_label(
+            error.func_name_span,
+            format!("in the following function call {:?}", error.func_name),
+        )
+        .with_label(
+            error.param_span,
+            "this parameter type doesn't implement the required trait bounds",
+        )
+        .with_label(
+            error.trait_span,
+            "This trait bound is not implemented for the given parameter type at the function call",
+        )
+        .with_help(format!(
+            "Implement the trait {:?} for the passed parameter type.",
+            error.trait_name
+        )),
+    };
+
+    diagnostic.with_suggestions(suggestions)
 }
@@ -0,0 +1,214 @@
+//! The values partly moved by struct updates: the fields of linear types a struct update takes
+//! from its `..base` are moved out of it, so until they're assigned again, neither those fields
+//! nor the whole base can be used, while the other fields still can.
+//!
+//! Structs and enums are linear, and so are the tuples and arrays holding them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    ast::common::Span,
+    ir::{
+        BlockIndex, Function, IR, LocalIndex, Operand, Place, PlaceElem, Rvalue, StatementKind,
+        TerminatorKind, Type, TypeIndex, lowering::LoweringError,
+    },
+};
+
+use super::borrow_check::{rvalue_places, successors};
+
+/// Checks the moves of all the functions of the program.
+pub fn check_moves(ir: &IR) -> Result<(), LoweringError> {
+    for (_, function) in ir.functions.iter() {
+        if let Some(function) = function {
+            if !function.basic_blocks.is_empty() {
+                MoveChecker { ir, function }.check()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether values of the type are linear, moved rather than copied by a struct update.
+pub fn is_linear(ir: &IR, ty: TypeIndex) -> bool {
+    match &ir.types[ty] {
+        Some(Type::Adt(_)) => true,
+        Some(Type::Array(inner, _)) => is_linear(ir, *inner),
+        Some(Type::Tuple(fields)) => fields.iter().any(|x| is_linear(ir, *x)),
+        _ => false,
+    }
+}
+
+/// The places that may have been moved out of, by their local and projection, with where they
+/// were moved.
+type Moved = BTreeMap<(LocalIndex, Vec<PlaceElem>), Option<Span>>;
+
+struct MoveChecker<'a> {
+    ir: &'a IR,
+    function: &'a Function,
+}
+
+impl MoveChecker<'_> {
+    fn check(&self) -> Result<(), LoweringError> {
+        let blocks = self.function.basic_blocks.len();
+        let mut entry: Vec<Option<Moved>> = vec![None; blocks];
+        entry[0] = Some(Moved::new());
+
+        // The places moved at the start of each block, until they don't grow anymore. They only
+        // grow, so a use of a moved place found on the way is one in the end too.
+        let mut pending = BTreeSet::from([0]);
+        while let Some(block) = pending.pop_first() {
+            let mut moved = entry[block].clone().expect("pending blocks are reached");
+            self.visit_block(block, &mut moved)?;
+
+            for target in successors(&self.function.basic_blocks[block].terminator.kind) {
+                let changed = match &mut entry[target] {
+                    Some(target_moved) => {
+                        let mut changed = false;
+                        for (place, span) in &moved {
+                            if !target_moved.contains_key(place) {
+                                target_moved.insert(place.clone(), *span);
+                                changed = true;
+                            }
+                        }
+                        changed
+                    }
+                    target_moved => {
+                        *target_moved = Some(moved.clone());
+                        true
+                    }
+                };
+
+                if changed {
+                    pending.insert(target);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the places moved through the block, checking none of them is used.
+    fn visit_block(&self, block: BlockIndex, moved: &mut Moved) -> Result<(), LoweringError> {
+        let basic_block = &self.function.basic_blocks[block];
+
+        for statement in &basic_block.statements {
+            match &statement.kind {
+                StatementKind::Assign(place, rvalue) => {
+                    for used in rvalue_places(rvalue) {
+                        self.check_use(used, statement.span, moved)?;
+                    }
+                    assign(place, moved);
+                    if let Rvalue::Move(from) = rvalue {
+                        moved.insert((from.local, from.projection.clone()), statement.span);
+                    }
+                }
+                StatementKind::StorageLive(local) | StatementKind::StorageDead(local) => {
+                    moved.retain(|(moved_local, _), _| moved_local != local);
+                }
+            }
+        }
+
+        let span = basic_block.terminator.span;
+        match &basic_block.terminator.kind {
+            TerminatorKind::Call {
+                args, destination, ..
+            } => {
+                for used in args.iter().flat_map(rvalue_places) {
+                    self.check_use(used, span, moved)?;
+                }
+                assign(destination, moved);
+            }
+            TerminatorKind::TailCall { args, .. } => {
+                for used in args.iter().flat_map(rvalue_places) {
+                    self.check_use(used, span, moved)?;
+                }
+            }
+            TerminatorKind::SwitchInt {
+                discriminator: Operand::Place(place),
+                ..
+            }
+            | TerminatorKind::BoundsCheck {
+                cond: Operand::Place(place),
+                ..
+            }
+            | TerminatorKind::OverflowCheck {
+                cond: Operand::Place(place),
+                ..
+            } => self.check_use(place, span, moved)?,
+            TerminatorKind::UndefinedCheck { check, .. } => {
+                if let Operand::Place(place) = check.operand() {
+                    self.check_use(place, span, moved)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the place holds a moved place, or is within one.
+    fn check_use(
+        &self,
+        place: &Place,
+        span: Option<Span>,
+        moved: &Moved,
+    ) -> Result<(), LoweringError> {
+        let Some(span) = span else {
+            return Ok(());
+        };
+
+        let found = moved.iter().find(|((local, projection), _)| {
+            *local == place.local
+                && (projection.starts_with(&place.projection)
+                    || place.projection.starts_with(projection))
+        });
+
+        match found {
+            Some(((local, projection), move_span)) => Err(LoweringError::UseOfMovedValue {
+                span,
+                move_span: *move_span,
+                name: self.describe(*local, projection),
+                path: self.ir.modules[self.function.module_idx].file_path.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The name of the moved place, the variable and the struct fields it's in, e.g `point.x`.
+    fn describe(&self, local: LocalIndex, projection: &[PlaceElem]) -> String {
+        let local = &self.function.locals[local];
+        let mut name = local
+            .debug_name
+            .clone()
+            .unwrap_or_else(|| "value".to_string());
+        let mut ty = local.ty;
+
+        for elem in projection {
+            let PlaceElem::Field(idx) = elem else {
+                break;
+            };
+            let Some(Type::Adt(adt)) = &self.ir.types[ty] else {
+                break;
+            };
+            let Some(field) = self.ir.aggregates[*adt]
+                .as_ref()
+                .and_then(|x| x.variants.first())
+                .and_then(|x| x.fields.get(*idx))
+            else {
+                break;
+            };
+            name = format!("{name}.{}", field.name);
+            ty = field.ty;
+        }
+
+        name
+    }
+}
+
+/// Assigning a place gives back the places moved out of it.
+fn assign(place: &Place, moved: &mut Moved) {
+    moved.retain(|(local, projection), _| {
+        *local != place.local || !projection.starts_with(&place.projection)
+    });
+}
@@ -0,0 +1,143 @@
+//! SARIF 2.1.0 output, so code scanning tools can show the diagnostics inline.
+
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::ast::common::Span;
+
+use super::{
+    diagnostics::{Diagnostic, Severity, SourceCache},
+    suggestions::Applicability,
+};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Creates a SARIF log with a single run containing the given diagnostics.
+pub fn to_sarif(diagnostics: &[Diagnostic], sources: &mut SourceCache) -> Value {
+    let mut rules: Vec<&str> = diagnostics.iter().map(|x| x.code.as_str()).collect();
+    rules.sort_unstable();
+    rules.dedup();
+
+    let results: Vec<Value> = diagnostics
+        .iter()
+        .map(|x| result(x, &rules, sources))
+        .collect();
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "concrete",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|x| json!({ "id": x })).collect::<Vec<_>>(),
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+fn result(diagnostic: &Diagnostic, rules: &[&str], sources: &mut SourceCache) -> Value {
+    let related: Vec<Value> = diagnostic
+        .labels
+        .iter()
+        .enumerate()
+        .map(|(i, label)| {
+            let mut location = location(&label.path, label.span, sources);
+            location["id"] = json!(i);
+
+            if let Some(message) = &label.message {
+                location["message"] = json!({ "text": message });
+            }
+
+            location
+        })
+        .collect();
+
+    let fixes: Vec<Value> = diagnostic
+        .suggestions
+        .iter()
+        .filter(|x| x.applicability == Applicability::MachineApplicable)
+        .map(|x| {
+            let file = x.file(&diagnostic.path);
+            json!({
+                "description": { "text": x.message },
+                "artifactChanges": [{
+                    "artifactLocation": { "uri": uri(file) },
+                    "replacements": [{
+                        "deletedRegion": region(file, x.span, sources),
+                        "insertedContent": { "text": x.replacement },
+                    }],
+                }],
+            })
+        })
+        .collect();
+
+    let mut text = diagnostic.primary_message();
+    for note in diagnostic
+        .notes
+        .iter()
+        .chain(&diagnostic.helps)
+        .cloned()
+        .chain(diagnostic.suggestions.iter().map(|x| x.help()))
+    {
+        text.push('\n');
+        text.push_str(&note);
+    }
+
+    json!({
+        "ruleId": diagnostic.code,
+        "ruleIndex": rules.iter().position(|x| *x == diagnostic.code),
+        "level": match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        },
+        "message": { "text": text },
+        "locations": [location(&diagnostic.path, diagnostic.span, sources)],
+        "relatedLocations": related,
+        "fixes": fixes,
+    })
+}
+
+fn location(path: &Path, span: Span, sources: &mut SourceCache) -> Value {
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": uri(path) },
+            "region": region(path, span, sources),
+        }
+    })
+}
+
+fn region(path: &Path, span: Span, sources: &mut SourceCache) -> Value {
+    let path = path.display().to_string();
+    let mut region = json!({
+        "byteOffset": span.from,
+        "byteLength": span.to.saturating_sub(span.from),
+    });
+
+    if let Some((line, column)) = sources.line_col(&path, span.from) {
+        region["startLine"] = json!(line);
+        region["startColumn"] = json!(column);
+    }
+
+    if let Some((line, column)) = sources.line_col(&path, span.to) {
+        region["endLine"] = json!(line);
+        region["endColumn"] = json!(column);
+    }
+
+    region
+}
+
+/// The file path relative to the current directory, as code scanning tools resolve them against
+/// the repository root.
+fn uri(path: &Path) -> String {
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf());
+
+    relative.display().to_string().replace('\\', "/")
+}
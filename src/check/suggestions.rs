@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use crate::ast::common::Span;
+
+/// How confident the compiler is that applying a suggestion is correct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Applicability {
+    /// The suggestion is definitely what the user intended, `concrete fix` applies it.
+    MachineApplicable,
+    /// The suggestion may be what the user intended, but it may not even compile.
+    MaybeIncorrect,
+}
+
+/// A replacement for the source text at the given span, attached to a diagnostic.
+///
+/// An empty span inserts the replacement.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Suggestion {
+    pub message: String,
+    /// The file of the span, when it isn't the one of the primary span of the diagnostic, like
+    /// the definition of a private item used from another file.
+    pub path: Option<PathBuf>,
+    pub span: Span,
+    pub replacement: String,
+    pub applicability: Applicability,
+}
+
+impl Suggestion {
+    /// The file the suggestion applies to, given the one of the primary span of its diagnostic.
+    pub fn file<'a>(&'a self, primary: &'a Path) -> &'a Path {
+        self.path.as_deref().unwrap_or(primary)
+    }
+
+    /// The help text shown in the rendered report.
+    pub fn help(&self) -> String {
+        let mut help = if self.span.from == self.span.to {
+            format!("{}: insert `{}`", self.message, self.replacement)
+        } else {
+            format!("{}: try `{}`", self.message, self.replacement)
+        };
+
+        if let Some(path) = &self.path {
+            help.push_str(&format!(" in {}", path.display()));
+        }
+
+        help
+    }
+}
+
+/// Finds the candidate most similar to the given name, if any is close enough to be a likely typo.
+pub fn find_similar_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .filter(|x| *x != name)
+        .map(|x| (edit_distance(name, x), x))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, x)| x)
+}
+
+/// The error message for an unknown value given to the compiler, such as a profile or lint name.
+///
+/// Suggests the most similar known value and lists all of them.
+pub fn unknown_value_message<'a>(
+    kind: &str,
+    value: &str,
+    known: impl IntoIterator<Item = &'a str>,
+) -> String {
+    let mut known: Vec<&str> = known.into_iter().collect();
+    known.sort_unstable();
+
+    let mut message = format!("unknown {kind} `{value}`");
+
+    if let Some(similar) = find_similar_name(value, known.iter().copied()) {
+        message.push_str(&format!(", did you mean `{similar}`?"));
+    }
+
+    if known.is_empty() {
+        message.push_str(&format!("\nnote: there are no {kind}s available"));
+    } else {
+        message.push_str(&format!(
+            "\nnote: the available {kind}s are: {}",
+            known.join(", ")
+        ));
+    }
+
+    message
+}
+
+/// The Levenshtein distance between the two strings.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(row[j + 1])
+            };
+            prev = current;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Applies the machine applicable suggestions to the source.
+///
+/// Suggestions overlapping an already applied one are skipped, returns the number applied.
+pub fn apply_suggestions(source: &mut String, suggestions: &[Suggestion]) -> usize {
+    let mut suggestions: Vec<&Suggestion> = suggestions
+        .iter()
+        .filter(|x| x.applicability == Applicability::MachineApplicable)
+        .collect();
+    // Apply from the end of the file, so the spans of the remaining ones stay valid.
+    suggestions.sort_by_key(|x| std::cmp::Reverse((x.span.from, x.span.to)));
+
+    let mut applied = 0;
+    let mut last_from = usize::MAX;
+
+    for suggestion in suggestions {
+        if suggestion.span.to > last_from || suggestion.span.to > source.len() {
+            continue;
+        }
+
+        source.replace_range(
+            suggestion.span.from..suggestion.span.to,
+            &suggestion.replacement,
+        );
+        last_from = suggestion.span.from;
+        applied += 1;
+    }
+
+    applied
+}
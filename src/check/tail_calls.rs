@@ -0,0 +1,71 @@
+//! The tail calls of `become`, which run the callee in place of the function: the callee has to
+//! take the same parameters and return the same type as the function, for its frame to be reused.
+
+use crate::ir::{Function, IR, LangItem, TerminatorKind, Type, TypeIndex, lowering::LoweringError};
+
+/// Checks the tail calls of all the functions of the program.
+pub fn check_tail_calls(ir: &IR) -> Result<(), LoweringError> {
+    for (_, function) in ir.functions.iter() {
+        let Some(function) = function else {
+            continue;
+        };
+
+        for block in &function.basic_blocks {
+            let TerminatorKind::TailCall { func, .. } = &block.terminator.kind else {
+                continue;
+            };
+            let (Some(span), Some(Some(callee))) = (block.terminator.span, ir.functions.get(*func))
+            else {
+                continue;
+            };
+
+            let reason = if returns_exit_code(ir, function) {
+                Some("the value `main` returns is converted to the exit code".to_string())
+            } else if !same_types(ir, &function.args, &callee.args) {
+                Some(format!(
+                    "`{}` takes other parameters than the caller",
+                    callee.debug_name.as_ref().unwrap_or(&callee.name)
+                ))
+            } else if !same_types(ir, &[function.ret_ty], &[callee.ret_ty]) {
+                Some(format!(
+                    "`{}` returns another type than the caller",
+                    callee.debug_name.as_ref().unwrap_or(&callee.name)
+                ))
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                return Err(LoweringError::InvalidTailCall {
+                    span,
+                    reason,
+                    path: ir.modules[function.module_idx].file_path.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the function is a `main` returning a `Result`, its value being made the exit code.
+fn returns_exit_code(ir: &IR, function: &Function) -> bool {
+    function.name == "main"
+        && !function.is_extern
+        && matches!(
+            ir.types[function.ret_ty],
+            Some(Type::Adt(adt)) if ir.aggregates[adt]
+                .as_ref()
+                .is_some_and(|x| x.lang_item == Some(LangItem::Result))
+        )
+}
+
+fn same_types(ir: &IR, a: &[TypeIndex], b: &[TypeIndex]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| match (&ir.types[*a], &ir.types[*b]) {
+                (Some(a), Some(b)) => a.is_equal(b, ir),
+                _ => a == b,
+            })
+}
@@ -0,0 +1,588 @@
+//! The analyses behind the lints about unused code: variables whose values are never read,
+//! functions and types no root of the program reaches, imports never referred to and statements
+//! that can't run.
+
+use std::collections::HashSet;
+
+use crate::{
+    ast::{
+        common::{GenericParam, Span, TypeName},
+        expressions::{
+            Expression, FnCallOp, MatchCaseExpr, MatchExpr, PathOp, PathSegment, ValueExpr,
+        },
+        functions::FunctionDecl,
+        macros::{MacroCall, TokenTree},
+        modules::{Module, ModuleDefItem},
+        patterns::Pattern,
+        statements::{ForIterable, LetStmt, LetStmtTarget, Statement},
+        types::{ArraySize, TypeDescriptor},
+    },
+    ir::{
+        AdtIndex, FnIndex, Function, IR, LocalIndex, LocalKind, Operand, Place, PlaceElem,
+        StatementKind, TerminatorKind, Type, TypeIndex,
+    },
+    parser::tokens::Token,
+};
+
+use super::borrow_check::rvalue_places;
+
+/// The variables and parameters of the function whose values are never read.
+///
+/// The ones named with a leading underscore are meant to be unused, and `self` is left out.
+pub fn unused_locals(function: &Function) -> Vec<LocalIndex> {
+    let mut read = HashSet::new();
+
+    for block in &function.basic_blocks {
+        for statement in &block.statements {
+            if let StatementKind::Assign(place, rvalue) = &statement.kind {
+                write_place(place, &mut read);
+                for place in rvalue_places(rvalue) {
+                    read_place(place, &mut read);
+                }
+            }
+        }
+
+        match &block.terminator.kind {
+            TerminatorKind::Call {
+                args, destination, ..
+            } => {
+                write_place(destination, &mut read);
+                for place in args.iter().flat_map(rvalue_places) {
+                    read_place(place, &mut read);
+                }
+            }
+            TerminatorKind::TailCall { args, .. } => {
+                for place in args.iter().flat_map(rvalue_places) {
+                    read_place(place, &mut read);
+                }
+            }
+            TerminatorKind::SwitchInt {
+                discriminator: Operand::Place(place),
+                ..
+            }
+            | TerminatorKind::BoundsCheck {
+                cond: Operand::Place(place),
+                ..
+            }
+            | TerminatorKind::OverflowCheck {
+                cond: Operand::Place(place),
+                ..
+            } => read_place(place, &mut read),
+            TerminatorKind::UndefinedCheck { check, .. } => {
+                if let Operand::Place(place) = check.operand() {
+                    read_place(place, &mut read);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    function
+        .locals
+        .iter()
+        .enumerate()
+        .filter(|(idx, local)| {
+            matches!(local.kind, LocalKind::Temp | LocalKind::Arg)
+                && local.span.is_some()
+                && local
+                    .debug_name
+                    .as_ref()
+                    .is_some_and(|x| !x.starts_with('_') && x != "self")
+                && !read.contains(idx)
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Writing to a place only reads the references and indexes it goes through.
+fn write_place(place: &Place, read: &mut HashSet<LocalIndex>) {
+    if place.projection.contains(&PlaceElem::Deref) {
+        read.insert(place.local);
+    }
+
+    for elem in &place.projection {
+        if let PlaceElem::Index(local) = elem {
+            read.insert(*local);
+        }
+    }
+}
+
+fn read_place(place: &Place, read: &mut HashSet<LocalIndex>) {
+    read.insert(place.local);
+
+    for elem in &place.projection {
+        if let PlaceElem::Index(local) = elem {
+            read.insert(*local);
+        }
+    }
+}
+
+/// The functions reachable through calls from the roots, the roots included.
+pub fn reachable_functions(ir: &IR, roots: impl IntoIterator<Item = FnIndex>) -> HashSet<FnIndex> {
+    let mut reached = HashSet::new();
+    let mut pending: Vec<FnIndex> = roots.into_iter().collect();
+
+    while let Some(id) = pending.pop() {
+        if !reached.insert(id) {
+            continue;
+        }
+
+        let Some(Some(function)) = ir.functions.get(id) else {
+            continue;
+        };
+        for block in &function.basic_blocks {
+            if let TerminatorKind::Call { func, .. } | TerminatorKind::TailCall { func, .. } =
+                &block.terminator.kind
+            {
+                pending.push(*func);
+            }
+        }
+    }
+
+    reached
+}
+
+/// The structs and enums the functions and constants use, with the ones they're made of, like the
+/// types of the fields of a struct.
+pub fn used_adts(ir: &IR, functions: &HashSet<FnIndex>) -> HashSet<AdtIndex> {
+    let mut used = HashSet::new();
+    let mut adts = HashSet::new();
+    let mut pending: Vec<TypeIndex> = functions
+        .iter()
+        .filter_map(|id| ir.functions.get(*id).and_then(Option::as_ref))
+        .flat_map(|function| {
+            function
+                .locals
+                .iter()
+                .map(|local| local.ty)
+                .chain(function.args.iter().copied())
+                .chain([function.ret_ty])
+        })
+        .chain(
+            ir.constants
+                .iter()
+                .filter_map(|(_, constant)| constant.as_ref())
+                .map(|constant| constant.value.ty),
+        )
+        .collect();
+
+    while let Some(ty) = pending.pop() {
+        if !used.insert(ty) {
+            continue;
+        }
+
+        match ir.types.get(ty).and_then(Option::as_ref) {
+            Some(Type::Array(of, _) | Type::Slice(of) | Type::Ref(of, _) | Type::Ptr(of, _)) => {
+                pending.push(*of);
+            }
+            Some(Type::Adt(adt)) => {
+                adts.insert(*adt);
+                if let Some(Some(body)) = ir.aggregates.get(*adt) {
+                    for variant in &body.variants {
+                        pending.extend(variant.fields.iter().map(|field| field.ty));
+                    }
+                }
+            }
+            Some(Type::Tuple(fields)) => pending.extend(fields.iter().copied()),
+            _ => {}
+        }
+    }
+
+    adts
+}
+
+/// The names the items of the module refer to: the functions they call, the types they use, the
+/// constants they read and the modules in their paths. Submodules and imports are left out.
+pub fn item_names(module: &Module, names: &mut HashSet<String>) {
+    for item in &module.contents {
+        match item {
+            ModuleDefItem::Constant(x) => {
+                type_names(&x.decl.r#type, names);
+                expression_names(&x.value, names);
+            }
+            ModuleDefItem::Function(x) => function_names(&x.decl, &x.body, names),
+            ModuleDefItem::FunctionDecl(x) => function_names(x, &[], names),
+            ModuleDefItem::ExternBlock(x) => {
+                for function in &x.functions {
+                    function_names(function, &[], names);
+                }
+            }
+            ModuleDefItem::Impl(x) => {
+                type_names(&x.target, names);
+                generic_param_names(&x.generic_params, names);
+                for method in &x.methods {
+                    function_names(&method.decl, &method.body, names);
+                }
+            }
+            ModuleDefItem::ImplTrait(x) => {
+                type_name_names(&x.target_trait, names);
+                type_names(&x.target, names);
+                generic_param_names(&x.generic_params, names);
+                for assoc in &x.associated_types {
+                    type_names(&assoc.value, names);
+                }
+                for method in &x.methods {
+                    function_names(&method.decl, &method.body, names);
+                }
+            }
+            ModuleDefItem::Struct(x) => {
+                generic_param_names(&x.generics, names);
+                for field in &x.fields {
+                    type_names(&field.r#type, names);
+                    if let Some(default) = &field.default {
+                        expression_names(default, names);
+                    }
+                }
+            }
+            ModuleDefItem::Union(x) => {
+                generic_param_names(&x.generics, names);
+                for field in &x.variants {
+                    type_names(&field.r#type, names);
+                }
+            }
+            ModuleDefItem::Enum(x) => {
+                generic_param_names(&x.generics, names);
+                for variant in &x.variants {
+                    for field in &variant.fields {
+                        type_names(&field.r#type, names);
+                    }
+                    if let Some(discriminant) = &variant.discriminant {
+                        expression_names(discriminant, names);
+                    }
+                }
+            }
+            ModuleDefItem::Type(x) => type_names(&x.value, names),
+            ModuleDefItem::Trait(x) => {
+                generic_param_names(&x.generic_params, names);
+                for assoc in &x.associated_types {
+                    if let Some(value) = &assoc.value {
+                        type_names(value, names);
+                    }
+                }
+                for method in &x.methods {
+                    function_names(method, &[], names);
+                }
+            }
+            // The names of a template are resolved where the macro is called.
+            ModuleDefItem::Module(_)
+            | ModuleDefItem::ExternalModule(_)
+            | ModuleDefItem::Import(_)
+            | ModuleDefItem::Macro(_) => {}
+        }
+    }
+}
+
+fn function_names(decl: &FunctionDecl, body: &[Statement], names: &mut HashSet<String>) {
+    generic_param_names(&decl.generic_params, names);
+    for param in &decl.params {
+        type_names(&param.r#type, names);
+        if let Some(pattern) = &param.pattern {
+            pattern_names(pattern, names);
+        }
+    }
+    if let Some(ret_type) = &decl.ret_type {
+        type_names(ret_type, names);
+    }
+    referenced_names(body, names);
+}
+
+fn generic_param_names(params: &[GenericParam], names: &mut HashSet<String>) {
+    for bound in params.iter().flat_map(|x| &x.bounds) {
+        type_name_names(bound, names);
+    }
+}
+
+fn type_names(ty: &TypeDescriptor, names: &mut HashSet<String>) {
+    match ty {
+        TypeDescriptor::Type { name, .. } => type_name_names(name, names),
+        TypeDescriptor::Ref { of, .. }
+        | TypeDescriptor::MutRef { of, .. }
+        | TypeDescriptor::ConstPtr { of, .. }
+        | TypeDescriptor::MutPtr { of, .. }
+        | TypeDescriptor::Slice { of, .. } => type_names(of, names),
+        TypeDescriptor::Array { of, size, .. } => {
+            type_names(of, names);
+            if let ArraySize::Constant(size) = size {
+                expression_names(size, names);
+            }
+        }
+        TypeDescriptor::Tuple { of, .. } => {
+            for ty in of {
+                type_names(ty, names);
+            }
+        }
+        TypeDescriptor::SelfType { .. } => {}
+    }
+}
+
+fn type_name_names(name: &TypeName, names: &mut HashSet<String>) {
+    names.extend(name.path.iter().map(|x| x.name.clone()));
+    names.insert(name.name.name.clone());
+    for generic in &name.generics {
+        type_name_names(generic, names);
+    }
+}
+
+fn pattern_names(pattern: &Pattern, names: &mut HashSet<String>) {
+    match pattern {
+        Pattern::Binding(_) => {}
+        Pattern::Struct { name, fields, .. } | Pattern::Variant { name, fields, .. } => {
+            type_name_names(name, names);
+            for field in fields {
+                pattern_names(&field.pattern, names);
+            }
+        }
+    }
+}
+
+/// The names the statements refer to, like [`item_names`] does for the items of a module.
+pub fn referenced_names(body: &[Statement], names: &mut HashSet<String>) {
+    for statement in body {
+        match statement {
+            Statement::Assign(info) => {
+                path_names(&info.lvalue, names);
+                expression_names(&info.rvalue, names);
+            }
+            Statement::Match(info) => match_names(info, names),
+            Statement::For(info) => {
+                if let Some(init) = &info.init {
+                    let_names(init, names);
+                }
+                if let Some(condition) = &info.condition {
+                    expression_names(condition, names);
+                }
+                if let Some(post) = &info.post {
+                    path_names(&post.lvalue, names);
+                    expression_names(&post.rvalue, names);
+                }
+                referenced_names(&info.block_stmts, names);
+            }
+            Statement::ForIn(info) => {
+                match &info.iterable {
+                    ForIterable::Range { start, end } => {
+                        expression_names(start, names);
+                        expression_names(end, names);
+                    }
+                    ForIterable::Value(value) => expression_names(value, names),
+                }
+                referenced_names(&info.block_stmts, names);
+            }
+            Statement::If(info) => {
+                expression_names(&info.cond, names);
+                referenced_names(&info.block_stmts, names);
+                if let Some(else_stmts) = &info.else_stmts {
+                    referenced_names(else_stmts, names);
+                }
+            }
+            Statement::Let(info) => let_names(info, names),
+            Statement::Return(info) => {
+                if let Some(value) = &info.value {
+                    expression_names(value, names);
+                }
+            }
+            Statement::Become(info) => expression_names(&info.value, names),
+            Statement::While(info) => {
+                expression_names(&info.condition, names);
+                referenced_names(&info.block_stmts, names);
+            }
+            Statement::FnCall(info) => call_names(info, names),
+            Statement::PathOp(info) => path_names(info, names),
+            Statement::MacroCall(info) => macro_names(info, names),
+            Statement::Defer(info) => referenced_names(&info.block_stmts, names),
+            Statement::Unsafe(info) => referenced_names(&info.block_stmts, names),
+        }
+    }
+}
+
+fn let_names(info: &LetStmt, names: &mut HashSet<String>) {
+    match &info.target {
+        LetStmtTarget::Simple { r#type, .. } => type_names(r#type, names),
+        LetStmtTarget::Destructure(pattern) => pattern_names(pattern, names),
+    }
+    expression_names(&info.value, names);
+}
+
+fn match_names(info: &MatchExpr, names: &mut HashSet<String>) {
+    expression_names(&info.expr, names);
+    for variant in &info.variants {
+        match &variant.case {
+            MatchCaseExpr::Value(value) => value_names(value, names),
+            MatchCaseExpr::Enum(case) => {
+                type_name_names(&case.name, names);
+                for field in &case.field_values {
+                    pattern_names(&field.pattern, names);
+                }
+            }
+        }
+        referenced_names(&variant.block, names);
+    }
+}
+
+fn call_names(call: &FnCallOp, names: &mut HashSet<String>) {
+    names.extend(call.path.iter().map(|x| x.name.clone()));
+    names.insert(call.target.name.clone());
+    for generic in &call.generics {
+        type_name_names(generic, names);
+    }
+    for arg in &call.args {
+        expression_names(arg, names);
+    }
+}
+
+fn path_names(path: &PathOp, names: &mut HashSet<String>) {
+    names.insert(path.first.name.clone());
+    for segment in &path.extra {
+        match segment {
+            PathSegment::FieldAccess(..) => {}
+            PathSegment::ArrayIndex(value, _) => value_names(value, names),
+            PathSegment::Slice(start, end, _) => {
+                for value in start.iter().chain(end) {
+                    value_names(value, names);
+                }
+            }
+            PathSegment::MethodCall(call, _) => call_names(call, names),
+        }
+    }
+}
+
+fn value_names(value: &ValueExpr, names: &mut HashSet<String>) {
+    if let ValueExpr::Path(path) = value {
+        path_names(path, names);
+    }
+}
+
+fn expression_names(expr: &Expression, names: &mut HashSet<String>) {
+    match expr {
+        Expression::Value(value, _) => value_names(value, names),
+        Expression::AssocMethodCall(info) => {
+            type_name_names(&info.assoc_type, names);
+            call_names(&info.fn_call, names);
+        }
+        Expression::FnCall(info) => call_names(info, names),
+        Expression::Match(info) => match_names(info, names),
+        Expression::If(info) => {
+            expression_names(&info.cond, names);
+            referenced_names(&info.block_stmts, names);
+            if let Some(else_stmts) = &info.else_stmts {
+                referenced_names(else_stmts, names);
+            }
+        }
+        Expression::UnaryOp(_, value)
+        | Expression::Deref(value, _)
+        | Expression::Await(value, _)
+        | Expression::AsRef(value, _, _) => expression_names(value, names),
+        Expression::Cast(value, ty, _) => {
+            expression_names(value, names);
+            type_names(ty, names);
+        }
+        Expression::BinaryOp(lhs, _, rhs) => {
+            expression_names(lhs, names);
+            expression_names(rhs, names);
+        }
+        Expression::StructInit(info) => {
+            type_name_names(&info.name, names);
+            for field in info.fields.values() {
+                expression_names(&field.value, names);
+            }
+            if let Some(base) = &info.base {
+                expression_names(&base.value, names);
+            }
+        }
+        Expression::EnumInit(info) => {
+            type_name_names(&info.name, names);
+            for field in info.fields.values() {
+                expression_names(&field.value, names);
+            }
+        }
+        Expression::ArrayInit(info) => {
+            for value in &info.values {
+                expression_names(value, names);
+            }
+        }
+        Expression::Tuple(values, _) => {
+            for value in values {
+                expression_names(value, names);
+            }
+        }
+        Expression::Try(value, _) => expression_names(value, names),
+        Expression::MacroCall(info) => macro_names(info, names),
+    }
+}
+
+/// The names of a call left unexpanded, which are any identifier of its arguments.
+fn macro_names(call: &MacroCall, names: &mut HashSet<String>) {
+    fn tree_names(trees: &[TokenTree], names: &mut HashSet<String>) {
+        for tree in trees {
+            match tree {
+                TokenTree::Token(Token::Identifier(name), _) => {
+                    names.insert(name.clone());
+                }
+                TokenTree::Token(..) => {}
+                TokenTree::Group(_, trees, _) => tree_names(trees, names),
+            }
+        }
+    }
+
+    names.insert(call.name.name.clone());
+    tree_names(&call.args, names);
+}
+
+/// The statements that can't run because the ones before them in their block always return,
+/// only the first one of each block.
+pub fn unreachable_statements(body: &[Statement]) -> Vec<Span> {
+    let mut spans = Vec::new();
+    block_returns(body, &mut spans);
+    spans
+}
+
+/// Whether the block always returns, adding its unreachable statement to the spans.
+fn block_returns(block: &[Statement], spans: &mut Vec<Span>) -> bool {
+    let mut returns = false;
+
+    for statement in block {
+        if returns {
+            spans.push(statement.span());
+            break;
+        }
+
+        returns = match statement {
+            Statement::Return(_) | Statement::Become(_) => true,
+            Statement::Unsafe(info) => block_returns(&info.block_stmts, spans),
+            Statement::If(info) => {
+                let then_returns = block_returns(&info.block_stmts, spans);
+                let else_returns = info
+                    .else_stmts
+                    .as_ref()
+                    .is_some_and(|x| block_returns(x, spans));
+                then_returns && else_returns
+            }
+            // The arms aren't known to cover all the values, and loops may not run at all.
+            Statement::Match(info) => {
+                for variant in &info.variants {
+                    block_returns(&variant.block, spans);
+                }
+                false
+            }
+            Statement::For(info) => {
+                block_returns(&info.block_stmts, spans);
+                false
+            }
+            Statement::ForIn(info) => {
+                block_returns(&info.block_stmts, spans);
+                false
+            }
+            Statement::While(info) => {
+                block_returns(&info.block_stmts, spans);
+                false
+            }
+            Statement::Assign(_)
+            | Statement::Let(_)
+            | Statement::FnCall(_)
+            | Statement::PathOp(_)
+            | Statement::MacroCall(_)
+            | Statement::Defer(_) => false,
+        };
+    }
+
+    returns
+}
@@ -1,11 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::ir::{
-    AdtKind, BinOp, ConcreteIntrinsic, ConstValue, FnIndex, Function, IR, LocalKind, Module,
-    ModuleIndex, Operand, Place, PlaceElem, Rvalue, Span, Type as IRType, TypeIndex, UnOp,
-    ValueTree,
+    AdtIndex, AdtKind, AtomicOp, BinOp, ConcreteIntrinsic, ConstValue, FnIndex, Function, IR,
+    Inline, LangItem, LocalKind, Module, ModuleIndex, Operand, Place, PlaceElem, Rvalue, Span,
+    Type as IRType, TypeIndex, UnOp, UndefinedCheck, ValueTree,
+    source_map::{SourceLocation, SourceMap},
 };
-use ariadne::Source;
 use melior::helpers::{ArithBlockExt, BuiltinBlockExt, GepIndex, LlvmBlockExt};
 use melior::ir::{BlockLike, RegionLike};
 use melior::{
@@ -19,15 +19,21 @@ use melior::{
         Attribute, Block, Identifier, Location, Module as MeliorModule, Region, Type, Value,
         ValueLike,
         attribute::{
-            DenseI32ArrayAttribute, FlatSymbolRefAttribute, FloatAttribute, IntegerAttribute,
-            StringAttribute, TypeAttribute,
+            ArrayAttribute, DenseI32ArrayAttribute, DenseI64ArrayAttribute, FlatSymbolRefAttribute,
+            FloatAttribute, IntegerAttribute, StringAttribute, TypeAttribute,
         },
+        operation::OperationBuilder,
         r#type::{FunctionType, IntegerType},
     },
 };
 use tracing::info;
 
-use super::errors::CodegenError;
+use super::{
+    debug_info::{DebugInfo, FunctionDebugInfo},
+    errors::CodegenError,
+    panic::{PANIC, PANIC_BOUNDS, c_string},
+    ubsan,
+};
 
 /// Global codegen context
 #[derive(Debug, Clone, Copy)]
@@ -38,6 +44,23 @@ pub(crate) struct CodegenCtx<'a> {
     pub mlir_module: &'a MeliorModule<'a>,
     /// The program IR.
     pub program: &'a IR,
+    /// The sources of the program, to give the operations their locations.
+    pub source_map: &'a SourceMap,
+    /// Whether the indexes of arrays and slices are checked, and the `bounds_checks` intrinsic
+    /// returns true.
+    pub bounds_checks: bool,
+    /// Whether the integer arithmetic panics on overflow, and the `overflow_checks` intrinsic
+    /// returns true.
+    pub overflow_checks: bool,
+    /// Whether divisions by zero and accesses through null or misaligned raw pointers are
+    /// reported by the runtime of UndefinedBehaviorSanitizer, with `--sanitize undefined`.
+    pub undefined_checks: bool,
+    /// Whether the failed checks call the panic routines, which need libc, rather than trap.
+    pub panics: bool,
+    /// The debug info of the functions, in `DebugInfo::Full` builds.
+    pub debug_info: Option<&'a DebugInfo<'a>>,
+    /// Whether each block counts the times it runs, for `concrete cov report`.
+    pub coverage: bool,
 }
 
 /// Codegen context for a module
@@ -56,22 +79,16 @@ impl ModuleCodegenCtx<'_> {
 
     /// Gets a MLIR location from the given span, or unknown if the span is `None`.
     pub fn get_location(&self, span: Option<Span>) -> Location {
-        if let Some(span) = span {
-            let source = std::fs::read_to_string(&self.get_module_body().file_path).unwrap();
-            let (_, line, col) = Source::from(source).get_offset_line(span.from).unwrap();
-            Location::new(
+        let file_path = &self.get_module_body().file_path;
+
+        match span.and_then(|x| self.ctx.source_map.location(file_path, x)) {
+            Some(location) => Location::new(
                 self.ctx.mlir_context,
-                self.get_module_body()
-                    .file_path
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                line + 1,
-                col + 1,
-            )
-        } else {
-            Location::unknown(self.ctx.mlir_context)
+                &location.path.to_string_lossy(),
+                location.line,
+                location.column,
+            ),
+            None => Location::unknown(self.ctx.mlir_context),
         }
     }
 
@@ -94,16 +111,82 @@ impl ModuleCodegenCtx<'_> {
     }
 }
 
-/// Compiles the program within the context.
-pub(crate) fn compile_program(ctx: CodegenCtx) -> Result<(), CodegenError> {
+/// Compiles the given top level modules of the program within the context.
+pub(crate) fn compile_program(
+    ctx: CodegenCtx,
+    modules: &[ModuleIndex],
+) -> Result<(), CodegenError> {
     info!("compiling program");
-    for module_id in &ctx.program.top_level_modules {
+    for module_id in modules {
         let ctx = ModuleCodegenCtx {
             ctx,
             module_id: *module_id,
         };
         compile_module(ctx)?;
     }
+    declare_external_functions(ctx, modules)?;
+    if ctx.panics && (ctx.bounds_checks || ctx.overflow_checks) {
+        super::panic::define_panic_routines(ctx)?;
+    }
+    if ctx.undefined_checks {
+        super::ubsan::declare_handlers(ctx);
+    }
+    Ok(())
+}
+
+/// Declares the functions defined outside the given top level modules, so they can be called,
+/// the linker finds them in the objects of the other compile units.
+fn declare_external_functions(
+    ctx: CodegenCtx,
+    modules: &[ModuleIndex],
+) -> Result<(), CodegenError> {
+    let is_local =
+        |body: &Function| modules.contains(&ctx.program.top_level_module(body.module_idx));
+
+    // Extern functions can be declared by several modules, but only once in the MLIR module.
+    let mut declared: HashSet<String> = ctx
+        .program
+        .functions
+        .iter()
+        .filter_map(|(_, body)| body.as_ref())
+        .filter(|body| is_local(body))
+        .map(|body| body.get_mangled_name())
+        .collect();
+
+    for (fn_idx, body) in ctx.program.functions.iter() {
+        let Some(body) = body else {
+            continue;
+        };
+
+        if is_local(body) || !declared.insert(body.get_mangled_name()) {
+            continue;
+        }
+
+        let module = ModuleCodegenCtx {
+            ctx,
+            module_id: body.module_idx,
+        };
+        let (args, ret_ty) = module.get_fn_signature(fn_idx);
+        let param_types: Vec<_> = args.iter().map(|x| compile_type(module, x)).collect();
+        let return_type = match &ret_ty {
+            IRType::Unit => None,
+            _ => Some(compile_type(module, &ret_ty)),
+        };
+        let func_type = FunctionType::new(ctx.mlir_context, &param_types, return_type.as_slice());
+
+        ctx.mlir_module.body().append_operation(func::func(
+            ctx.mlir_context,
+            StringAttribute::new(ctx.mlir_context, &body.get_mangled_name()),
+            TypeAttribute::new(func_type.into()),
+            Region::new(),
+            &[(
+                Identifier::new(ctx.mlir_context, "sym_visibility"),
+                StringAttribute::new(ctx.mlir_context, "private").into(),
+            )],
+            Location::unknown(ctx.mlir_context),
+        ));
+    }
+
     Ok(())
 }
 
@@ -116,6 +199,7 @@ fn compile_module(ctx: ModuleCodegenCtx) -> Result<(), CodegenError> {
         let ctx = FunctionCodegenCtx {
             module: ctx,
             fn_idx: *fn_id,
+            span: None,
         };
         compile_function(ctx)?;
     }
@@ -134,9 +218,11 @@ fn compile_module(ctx: ModuleCodegenCtx) -> Result<(), CodegenError> {
 struct FunctionCodegenCtx<'a> {
     pub module: ModuleCodegenCtx<'a>,
     pub fn_idx: FnIndex,
+    /// The span of the instruction being compiled, for the location of its operations.
+    pub span: Option<Span>,
 }
 
-impl FunctionCodegenCtx<'_> {
+impl<'a> FunctionCodegenCtx<'a> {
     /// Gets the function IR body.
     pub fn get_fn_body(&self) -> &Function {
         self.module.ctx.program.functions[self.fn_idx]
@@ -156,6 +242,29 @@ impl FunctionCodegenCtx<'_> {
     pub fn context(&self) -> &MeliorContext {
         self.module.ctx.mlir_context
     }
+
+    /// The debug info of the function, in `DebugInfo::Full` builds.
+    pub fn debug_info(&self) -> Option<&'a FunctionDebugInfo<'a>> {
+        self.module.ctx.debug_info?.function(self.fn_idx)
+    }
+
+    /// The location of the instruction being compiled, the function's if it has none.
+    ///
+    /// With debug info it's in the innermost lexical scope, for debuggers to only show the
+    /// locals of the block it's in.
+    pub fn location(&self) -> Location {
+        let span = self.span.or(self.get_fn_body().span);
+        let location = self.module.get_location(span);
+
+        match (self.debug_info(), span) {
+            (Some(debug_info), Some(span)) => Location::fused(
+                self.module.ctx.mlir_context,
+                &[location],
+                debug_info.scope_of(&self.get_fn_body().scopes, span),
+            ),
+            _ => location,
+        }
+    }
 }
 
 /// Compiles the given function IR.
@@ -165,6 +274,9 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
         return Ok(());
     }
     let body = ctx.get_fn_body();
+    crate::activity::set_current_item(format_args!("compiling function `{}`", body.name));
+    let _span =
+        crate::driver::self_profile::span("codegen", format_args!("codegen `{}`", body.name));
 
     // Only codegen once.
     if !body.module_idx.eq(&ctx.module.module_id) {
@@ -176,6 +288,7 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
     }
 
     let body_signature = ctx.get_fn_signature();
+    let exit_result = main_exit_result(&ctx, body);
 
     info!("compiling function {}", body.name);
 
@@ -183,13 +296,16 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
     let region = Region::new();
 
     // Compile the parameter types.
+    let params = body.get_params();
     let params_ty: Vec<_> = body_signature
         .0
         .iter()
-        .map(|x| {
+        .enumerate()
+        .map(|(i, x)| {
             (
                 compile_type(ctx.module, x),
-                ctx.module.get_location(None), // Todo: add arg span vec to ir
+                ctx.module
+                    .get_location(params.get(i).and_then(|param| param.span)),
             )
         })
         .collect();
@@ -206,7 +322,9 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
         // Since all the locals are together in a unspecified order in the IR.
         let mut param_index = 0;
 
-        let location = Location::unknown(ctx.context());
+        // The stack allocations have the location of the function, where debuggers stop when
+        // entering it.
+        let location = ctx.location();
 
         let const1 = entry_block
             .append_operation(arith::constant(
@@ -288,6 +406,29 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
             }
         }
 
+        // Tell debuggers where the user-defined locals are stored.
+        if let Some(debug_info) = ctx.debug_info() {
+            for (index, local) in body.locals.iter().enumerate() {
+                let (Some(variable), Some(ptr)) =
+                    (debug_info.variables.get(&index), locals.get(&index))
+                else {
+                    continue;
+                };
+
+                let location = Location::fused(
+                    ctx.context(),
+                    &[ctx.module.get_location(local.span)],
+                    debug_info.scope(local.scope),
+                );
+                entry_block.append_operation(
+                    OperationBuilder::new("llvm.intr.dbg.declare", location)
+                        .add_operands(&[*ptr])
+                        .add_attributes(&[(Identifier::new(ctx.context(), "varInfo"), *variable)])
+                        .build()?,
+                );
+            }
+        }
+
         // Create in advance all the needed blocks, 1 block per IR block.
         // Since we use stack allocas we don't need to handle block arguments within.
         // The optimizer takes care of deciding if this allocas are better kept in the stack or in a register
@@ -301,13 +442,27 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
         }
 
         // Jump from the entry block to the first IR block.
-        entry_block.append_operation(cf::br(&blocks[0], &[], Location::unknown(ctx.context())));
+        entry_block.append_operation(cf::br(&blocks[0], &[], location));
+
+        if ctx.module.ctx.coverage {
+            declare_coverage_counters(&ctx, body)?;
+        }
 
         // Process each block.
-        for (block, mlir_block) in body.basic_blocks.iter().zip(blocks.iter()) {
+        for (index, (block, mlir_block)) in body.basic_blocks.iter().zip(blocks.iter()).enumerate()
+        {
+            if ctx.module.ctx.coverage {
+                increment_coverage_counter(&ctx, mlir_block, body, index)?;
+            }
+
             // Within blocks there is no control flow, so we simply give the current block to the
             // codegen functions.
             for statement in &block.statements {
+                let ctx = FunctionCodegenCtx {
+                    span: statement.span,
+                    ..ctx
+                };
+
                 match &statement.kind {
                     crate::ir::StatementKind::Assign(place, rvalue) => {
                         let (value, _ty) = compile_rvalue(&ctx, mlir_block, rvalue, &locals)?;
@@ -318,18 +473,23 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
                 }
             }
 
+            let ctx = FunctionCodegenCtx {
+                span: block.terminator.span,
+                ..ctx
+            };
+
             // Jump based on the terminator.
             match &block.terminator.kind {
                 crate::ir::TerminatorKind::Goto { target } => {
-                    mlir_block.append_operation(cf::br(
-                        &blocks[*target],
-                        &[],
-                        Location::unknown(ctx.context()),
-                    ));
+                    mlir_block.append_operation(cf::br(&blocks[*target], &[], ctx.location()));
                 }
                 crate::ir::TerminatorKind::Return => {
                     // Load the return value from the return local and return it.
-                    if let Some(ret_local) = return_local {
+                    if let (Some(ret_local), Some(adt_idx)) = (return_local, exit_result) {
+                        let ptr = locals[&ret_local];
+                        let value = compile_exit_code(&ctx, mlir_block, ptr, adt_idx)?;
+                        mlir_block.append_operation(func::r#return(&[value], ctx.location()));
+                    } else if let Some(ret_local) = return_local {
                         let ptr = locals.get(&ret_local).unwrap();
                         let ret_ty = ctx.module.get_type(body.locals[ret_local].ty);
                         let value = mlir_block
@@ -337,25 +497,74 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
                                 ctx.context(),
                                 *ptr,
                                 compile_type(ctx.module, &ret_ty),
-                                Location::unknown(ctx.context()),
+                                ctx.location(),
                                 LoadStoreOptions::default(),
                             ))
                             .result(0)?
                             .into();
-                        mlir_block.append_operation(func::r#return(
-                            &[value],
-                            Location::unknown(ctx.context()),
-                        ));
+                        mlir_block.append_operation(func::r#return(&[value], ctx.location()));
                     } else {
-                        mlir_block.append_operation(func::r#return(
+                        mlir_block.append_operation(func::r#return(&[], ctx.location()));
+                    }
+                }
+                crate::ir::TerminatorKind::Unreachable => {
+                    mlir_block.append_operation(llvm::unreachable(ctx.location()));
+                }
+                crate::ir::TerminatorKind::BoundsCheck { cond, target, .. }
+                | crate::ir::TerminatorKind::OverflowCheck { cond, target, .. } => {
+                    // A bounds check holds if its condition is true, an overflow check if it's
+                    // false.
+                    let (enabled, holds_if_true) = match &block.terminator.kind {
+                        crate::ir::TerminatorKind::BoundsCheck { .. } => {
+                            (ctx.module.ctx.bounds_checks, true)
+                        }
+                        _ => (ctx.module.ctx.overflow_checks, false),
+                    };
+
+                    if enabled {
+                        let (cond, _) = compile_load_operand(&ctx, mlir_block, cond, &locals)?;
+
+                        let panic_block = region.append_block(Block::new(&[]));
+                        compile_check_failure(&ctx, &panic_block, &block.terminator.kind, &locals)?;
+
+                        let (on_true, on_false) = if holds_if_true {
+                            (&blocks[*target], &panic_block)
+                        } else {
+                            (&panic_block, &blocks[*target])
+                        };
+                        mlir_block.append_operation(cf::cond_br(
+                            ctx.context(),
+                            cond,
+                            on_true,
+                            on_false,
                             &[],
-                            Location::unknown(ctx.context()),
+                            &[],
+                            ctx.location(),
                         ));
+                    } else {
+                        mlir_block.append_operation(cf::br(&blocks[*target], &[], ctx.location()));
                     }
                 }
-                crate::ir::TerminatorKind::Unreachable => {
-                    mlir_block
-                        .append_operation(llvm::unreachable(Location::unknown(ctx.context())));
+                crate::ir::TerminatorKind::UndefinedCheck { check, target } => {
+                    if ctx.module.ctx.undefined_checks {
+                        let is_undefined =
+                            compile_undefined_check(&ctx, mlir_block, check, &locals)?;
+
+                        let report_block = region.append_block(Block::new(&[]));
+                        compile_undefined_report(&ctx, &report_block, check, &locals)?;
+
+                        mlir_block.append_operation(cf::cond_br(
+                            ctx.context(),
+                            is_undefined,
+                            &report_block,
+                            &blocks[*target],
+                            &[],
+                            &[],
+                            ctx.location(),
+                        ));
+                    } else {
+                        mlir_block.append_operation(cf::br(&blocks[*target], &[], ctx.location()));
+                    }
                 }
                 // Function calls are terminators because a function may be diverging (i.e it doesn't return).
                 crate::ir::TerminatorKind::Call {
@@ -386,7 +595,7 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
                         fn_symbol,
                         &args,
                         ret_type.as_slice(),
-                        Location::unknown(ctx.context()),
+                        ctx.location(),
                     ));
 
                     if result.result_count() > 0 {
@@ -400,14 +609,77 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
                     }
 
                     if let Some(target) = target {
-                        mlir_block.append_operation(cf::br(
-                            &blocks[*target],
-                            &[],
-                            Location::unknown(ctx.context()),
-                        ));
+                        mlir_block.append_operation(cf::br(&blocks[*target], &[], ctx.location()));
                     } else {
-                        mlir_block
-                            .append_operation(llvm::unreachable(Location::unknown(ctx.context())));
+                        mlir_block.append_operation(llvm::unreachable(ctx.location()));
+                    }
+                }
+                // A tail call returns the value of the call, which must reuse the frame of the
+                // function, the callee having the same signature was checked after lowering.
+                crate::ir::TerminatorKind::TailCall { func, args } => {
+                    let target_fn_body = ctx.module.ctx.program.functions[*func]
+                        .as_ref()
+                        .expect("function call target body not found");
+                    let args: Vec<Value> = args
+                        .iter()
+                        .map(|x| compile_rvalue(&ctx, mlir_block, x, &locals).map(|x| x.0))
+                        .collect::<Result<_, _>>()?;
+                    let ret_type = match &ctx.module.get_fn_signature(*func).1 {
+                        IRType::Unit => None,
+                        ret_type => Some(compile_type(ctx.module, ret_type)),
+                    };
+
+                    // The callee is still a `func.func`, which an `llvm.call` can't name, so it's
+                    // called through its address, the constant callee of the generated call.
+                    let arg_types: Vec<Type> = args.iter().map(|x| x.r#type()).collect();
+                    let callee = mlir_block.append_op_result(func::constant(
+                        ctx.context(),
+                        FlatSymbolRefAttribute::new(
+                            ctx.context(),
+                            &target_fn_body.get_mangled_name(),
+                        ),
+                        FunctionType::new(ctx.context(), &arg_types, ret_type.as_slice()),
+                        ctx.location(),
+                    ))?;
+                    let callee = mlir_block.append_op_result(
+                        OperationBuilder::new("builtin.unrealized_conversion_cast", ctx.location())
+                            .add_operands(&[callee])
+                            .add_results(&[pointer(ctx.context(), 0)])
+                            .build()?,
+                    )?;
+
+                    let result = mlir_block.append_operation(
+                        OperationBuilder::new("llvm.call", ctx.location())
+                            .add_attributes(&[
+                                (
+                                    Identifier::new(ctx.context(), "TailCallKind"),
+                                    Attribute::parse(ctx.context(), "#llvm.tailcallkind<musttail>")
+                                        .unwrap(),
+                                ),
+                                (
+                                    Identifier::new(ctx.context(), "op_bundle_sizes"),
+                                    DenseI32ArrayAttribute::new(ctx.context(), &[]).into(),
+                                ),
+                                (
+                                    Identifier::new(ctx.context(), "operandSegmentSizes"),
+                                    DenseI32ArrayAttribute::new(
+                                        ctx.context(),
+                                        &[args.len() as i32 + 1, 0],
+                                    )
+                                    .into(),
+                                ),
+                            ])
+                            .add_operands(&[callee])
+                            .add_operands(&args)
+                            .add_results(ret_type.as_slice())
+                            .build()?,
+                    );
+
+                    if result.result_count() > 0 {
+                        let value = result.result(0)?.into();
+                        mlir_block.append_operation(func::r#return(&[value], ctx.location()));
+                    } else {
+                        mlir_block.append_operation(func::r#return(&[], ctx.location()));
                     }
                 }
                 // A switch int is used for branching by matching against 1 or multiple values.
@@ -446,7 +718,7 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
                         (&blocks[*targets.targets.last().unwrap()], &[]),
                         // Destinations
                         &dests,
-                        Location::unknown(ctx.context()),
+                        ctx.location(),
                     )?);
                 }
             }
@@ -457,6 +729,7 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
     // If the return type is unit, pass a empty slice to mlir.
     let return_type = match &body_signature.1 {
         IRType::Unit => None,
+        _ if exit_result.is_some() => Some(IntegerType::new(ctx.context(), 32).into()),
         _ => Some(compile_type(ctx.module, &body_signature.1)),
     };
 
@@ -471,15 +744,60 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
             Identifier::new(ctx.context(), "sym_visibility"),
             StringAttribute::new(ctx.context(), "private").into(),
         ));
+    } else if ctx
+        .module
+        .ctx
+        .program
+        .prebuilt_modules
+        .contains(&ctx.module.ctx.program.top_level_module(body.module_idx))
+    {
+        // The inlinable functions of a prebuilt dependency may be in its object too, the linker
+        // keeps one of them.
+        fn_attributes.push((
+            Identifier::new(ctx.context(), "llvm.linkage"),
+            Attribute::parse(ctx.context(), "#llvm.linkage<linkonce_odr>").unwrap(),
+        ));
     }
 
+    // `#[inline]` and `#[cold]` become the LLVM attributes of the function.
+    let mut passthrough = Vec::new();
+    match body.inline {
+        Some(Inline::Hint) => passthrough.push("inlinehint"),
+        Some(Inline::Always) => passthrough.push("alwaysinline"),
+        Some(Inline::Never) => passthrough.push("noinline"),
+        None => {}
+    }
+    if body.is_cold {
+        passthrough.push("cold");
+    }
+    if !passthrough.is_empty() {
+        let passthrough: Vec<Attribute> = passthrough
+            .into_iter()
+            .map(|x| StringAttribute::new(ctx.context(), x).into())
+            .collect();
+        fn_attributes.push((
+            Identifier::new(ctx.context(), "passthrough"),
+            ArrayAttribute::new(ctx.context(), &passthrough).into(),
+        ));
+    }
+
+    // The subprogram of the debug info is attached to the location of the function.
+    let location = match ctx.debug_info() {
+        Some(debug_info) => Location::fused(
+            ctx.context(),
+            &[ctx.module.get_location(body.span)],
+            debug_info.subprogram,
+        ),
+        None => Location::unknown(ctx.context()),
+    };
+
     let func_op = func::func(
         ctx.context(),
         StringAttribute::new(ctx.context(), &body.get_mangled_name()),
         TypeAttribute::new(func_type.into()),
         region,
         &fn_attributes,
-        Location::unknown(ctx.context()),
+        location,
     );
 
     ctx.module.ctx.mlir_module.body().append_operation(func_op);
@@ -487,6 +805,410 @@ fn compile_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenError> {
     Ok(())
 }
 
+/// Compiles the block the failed check of the terminator jumps to, which panics with the location
+/// of the check and why it failed. Without libc to print it, it only traps.
+fn compile_check_failure<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+    kind: &crate::ir::TerminatorKind,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
+) -> Result<(), CodegenError> {
+    // The panic has the location of the indexing or the arithmetic, for debuggers to show it.
+    let location = ctx.location();
+
+    match kind {
+        crate::ir::TerminatorKind::BoundsCheck { index, len, .. } if ctx.module.ctx.panics => {
+            // The index and the length are `u64`s, but for the size of an array, which is extended.
+            let i64_ty = IntegerType::new(ctx.context(), 64).into();
+            let mut args = vec![compile_panic_location(ctx, block)?];
+            for operand in [index, len] {
+                let (value, ty) = compile_load_operand(ctx, block, operand, locals)?;
+                let bits = ctx
+                    .module
+                    .get_type(ty)
+                    .get_bit_width(ctx.module.ctx.program);
+                let value = match bits {
+                    64 => value,
+                    _ => block.append_op_result(arith::extui(value, i64_ty, location))?,
+                };
+                args.push(value);
+            }
+            block.append_operation(func::call(
+                ctx.context(),
+                FlatSymbolRefAttribute::new(ctx.context(), PANIC_BOUNDS),
+                &args,
+                &[],
+                location,
+            ));
+        }
+        crate::ir::TerminatorKind::OverflowCheck { op, .. } if ctx.module.ctx.panics => {
+            let operation = match op {
+                BinOp::Add => "add",
+                BinOp::Sub => "subtract",
+                _ => "multiply",
+            };
+            let message = format!("attempt to {operation} with overflow");
+            let args = [
+                compile_panic_location(ctx, block)?,
+                c_string(ctx.context(), block, location, &message)?,
+            ];
+            block.append_operation(func::call(
+                ctx.context(),
+                FlatSymbolRefAttribute::new(ctx.context(), PANIC),
+                &args,
+                &[],
+                location,
+            ));
+        }
+        _ => {
+            block.append_operation(OperationBuilder::new("llvm.intr.trap", location).build()?);
+        }
+    }
+    block.append_operation(llvm::unreachable(location));
+
+    Ok(())
+}
+
+/// The `file:line:column` of the instruction being compiled as a C string, for its panic.
+fn compile_panic_location<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+) -> Result<Value<'c, 'b>, CodegenError> {
+    let text = match check_source_location(ctx) {
+        Some(x) => x.to_string(),
+        None => ctx.module.get_module_body().file_path.display().to_string(),
+    };
+
+    c_string(ctx.context(), block, ctx.location(), &text)
+}
+
+/// Where the instruction being compiled is in the sources, or its function if it has no span.
+fn check_source_location(ctx: &FunctionCodegenCtx) -> Option<SourceLocation> {
+    let file_path = &ctx.module.get_module_body().file_path;
+    ctx.span
+        .or(ctx.get_fn_body().span)
+        .and_then(|x| ctx.module.ctx.source_map.location(file_path, x))
+}
+
+/// Compiles whether the operation of an `UndefinedCheck` is undefined behavior: whether the
+/// divisor is zero, or the pointer null or misaligned for its pointee.
+fn compile_undefined_check<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+    check: &UndefinedCheck,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
+) -> Result<Value<'c, 'b>, CodegenError> {
+    let location = ctx.location();
+    let (value, ty) = compile_load_operand(ctx, block, check.operand(), locals)?;
+
+    Ok(match check {
+        UndefinedCheck::DivisionByZero(_) => {
+            let zero = block.const_int_from_type(ctx.context(), location, 0, value.r#type())?;
+            block.cmpi(
+                ctx.context(),
+                arith::CmpiPredicate::Eq,
+                value,
+                zero,
+                location,
+            )?
+        }
+        UndefinedCheck::Load(_) | UndefinedCheck::Store(_) => {
+            let i64_ty = IntegerType::new(ctx.context(), 64).into();
+            let address = block.append_op_result(
+                ods::llvm::ptrtoint(ctx.context(), i64_ty, value, location).into(),
+            )?;
+            let zero = block.const_int(ctx.context(), location, 0, 64)?;
+            let is_null = block.cmpi(
+                ctx.context(),
+                arith::CmpiPredicate::Eq,
+                address,
+                zero,
+                location,
+            )?;
+
+            let (_, align) = pointee_align(ctx, ty);
+            if align <= 1 {
+                is_null
+            } else {
+                let mask = block.const_int(ctx.context(), location, align - 1, 64)?;
+                let offset = block.andi(address, mask, location)?;
+                let is_misaligned = block.cmpi(
+                    ctx.context(),
+                    arith::CmpiPredicate::Ne,
+                    offset,
+                    zero,
+                    location,
+                )?;
+                block.ori(is_null, is_misaligned, location)?
+            }
+        }
+    })
+}
+
+/// Compiles the report of the undefined behavior found by an `UndefinedCheck`, by the handlers of
+/// the runtime of UndefinedBehaviorSanitizer, which end the program.
+fn compile_undefined_report<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+    check: &UndefinedCheck,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
+) -> Result<(), CodegenError> {
+    let location = ctx.location();
+    let program = ctx.module.ctx.program;
+    let i64_ty: Type = IntegerType::new(ctx.context(), 64).into();
+
+    // Without a span only the file is reported, the runtime leaves out a line and a column of 0.
+    let (file, line, column) = match check_source_location(ctx) {
+        Some(x) => (x.path, x.line, x.column),
+        None => (ctx.module.get_module_body().file_path.clone(), 0, 0),
+    };
+    let mut data = vec![
+        c_string(ctx.context(), block, location, &file.display().to_string())?,
+        block.const_int(ctx.context(), location, line, 32)?,
+        block.const_int(ctx.context(), location, column, 32)?,
+    ];
+
+    let (value, ty) = compile_load_operand(ctx, block, check.operand(), locals)?;
+    let (handler, args) = match check {
+        UndefinedCheck::DivisionByZero(_) => {
+            let ir_ty = ctx.module.get_type(ty);
+            let bits = ir_ty.get_bit_width(program);
+            let info = ((bits.trailing_zeros() as u16) << 1) | u16::from(ir_ty.is_signed());
+            let descriptor = ubsan::type_descriptor(
+                ctx.context(),
+                block,
+                location,
+                (ubsan::TYPE_KIND_INTEGER, info),
+                &ir_ty.display(program).unwrap(),
+            )?;
+            data.push(descriptor);
+            let data = ubsan::handler_data(ctx.context(), block, location, &data)?;
+
+            // The runtime takes the integers that fit in a pointer by value, and the wider ones
+            // behind one.
+            let divisor = if bits <= 64 {
+                block.const_int(ctx.context(), location, 0, 64)?
+            } else {
+                let ptr = block.alloca1(ctx.context(), location, value.r#type(), bits / 8)?;
+                block.store(ctx.context(), location, ptr, value)?;
+                block.append_op_result(
+                    ods::llvm::ptrtoint(ctx.context(), i64_ty, ptr, location).into(),
+                )?
+            };
+            // The dividend is only reported when dividing by -1, the divisor is zero.
+            (ubsan::DIVREM_OVERFLOW, vec![data, divisor, divisor])
+        }
+        UndefinedCheck::Load(_) | UndefinedCheck::Store(_) => {
+            let (pointee, align) = pointee_align(ctx, ty);
+            let descriptor = ubsan::type_descriptor(
+                ctx.context(),
+                block,
+                location,
+                (ubsan::TYPE_KIND_UNKNOWN, 0),
+                &ctx.module.get_type(pointee).display(program).unwrap(),
+            )?;
+            let access = match check {
+                UndefinedCheck::Store(_) => ubsan::TYPE_CHECK_STORE,
+                _ => ubsan::TYPE_CHECK_LOAD,
+            };
+            data.extend([
+                descriptor,
+                block.const_int(ctx.context(), location, align.trailing_zeros(), 8)?,
+                block.const_int(ctx.context(), location, access, 8)?,
+            ]);
+            let data = ubsan::handler_data(ctx.context(), block, location, &data)?;
+            let address = block.append_op_result(
+                ods::llvm::ptrtoint(ctx.context(), i64_ty, value, location).into(),
+            )?;
+            (ubsan::TYPE_MISMATCH, vec![data, address])
+        }
+    };
+
+    block.append_operation(func::call(
+        ctx.context(),
+        FlatSymbolRefAttribute::new(ctx.context(), handler),
+        &args,
+        &[],
+        location,
+    ));
+    block.append_operation(llvm::unreachable(location));
+
+    Ok(())
+}
+
+/// The pointee of the raw pointer type, with its alignment in bytes.
+fn pointee_align(ctx: &FunctionCodegenCtx, ptr_ty: TypeIndex) -> (TypeIndex, u64) {
+    let pointee = ctx
+        .module
+        .get_type(ptr_ty)
+        .get_inner_type()
+        .expect("should have inner");
+    let align = ctx
+        .module
+        .get_type(pointee)
+        .get_align(ctx.module.ctx.program)
+        / 8;
+
+    (pointee, align.max(1) as u64)
+}
+
+/// The `Result` returned by the `main` function, which returns the exit code of the program
+/// instead.
+fn main_exit_result(ctx: &FunctionCodegenCtx, body: &Function) -> Option<AdtIndex> {
+    if body.name != "main" || body.is_extern {
+        return None;
+    }
+    let IRType::Adt(adt_idx) = ctx.module.get_type(body.ret_ty) else {
+        return None;
+    };
+    let adt = ctx.module.ctx.program.aggregates[adt_idx].as_ref()?;
+    (adt.lang_item == Some(LangItem::Result)).then_some(adt_idx)
+}
+
+/// Compiles the exit code of the `Result` returned by `main`: the value of the `Ok` if it's an
+/// `i32`, otherwise 0, and 1 for an `Err`.
+fn compile_exit_code<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+    ptr: Value<'c, 'b>,
+    adt_idx: AdtIndex,
+) -> Result<Value<'c, 'b>, CodegenError> {
+    let location = ctx.location();
+    let i32_ty: Type = IntegerType::new(ctx.context(), 32).into();
+    let adt = ctx.module.ctx.program.aggregates[adt_idx].as_ref().unwrap();
+    let enum_ty = compile_type(ctx.module, &IRType::Adt(adt_idx));
+    let ok_idx = adt.variant_names["Ok"];
+
+    let tag_ptr = block.gep(
+        ctx.context(),
+        location,
+        ptr,
+        &[GepIndex::Const(0), GepIndex::Const(0)],
+        enum_ty,
+    )?;
+    let tag = block.load(ctx.context(), location, tag_ptr, i32_ty)?;
+    let ok_discriminant = i64::from(adt.discriminant(ok_idx));
+    let ok_tag = block.const_int_from_type(ctx.context(), location, ok_discriminant, i32_ty)?;
+    let is_ok = block.append_op_result(arith::cmpi(
+        ctx.context(),
+        arith::CmpiPredicate::Eq,
+        tag,
+        ok_tag,
+        location,
+    ))?;
+
+    let ok_code = match ctx.module.get_type(adt.variants[ok_idx].fields[0].ty) {
+        IRType::Int(crate::ir::IntTy::I32) => {
+            // The payload of the enum starts after the tag.
+            let value_ptr = block.gep(
+                ctx.context(),
+                location,
+                ptr,
+                &[GepIndex::Const(0), GepIndex::Const(1)],
+                enum_ty,
+            )?;
+            block.load(ctx.context(), location, value_ptr, i32_ty)?
+        }
+        _ => block.const_int_from_type(ctx.context(), location, 0, i32_ty)?,
+    };
+    let err_code = block.const_int_from_type(ctx.context(), location, 1, i32_ty)?;
+
+    Ok(block.append_op_result(arith::select(is_ok, ok_code, err_code, location))?)
+}
+
+/// Defines the coverage counters of the function, one per block, zeroed.
+///
+/// They are external globals named after the function, so `concrete cov report` can read them
+/// from the library once the tests ran.
+fn declare_coverage_counters(
+    ctx: &FunctionCodegenCtx,
+    body: &Function,
+) -> Result<(), CodegenError> {
+    let len = body.basic_blocks.len();
+    let counters_ty = llvm::r#type::array(IntegerType::new(ctx.context(), 64).into(), len as u32);
+    let symbol = crate::driver::coverage::counters_symbol(&body.get_mangled_name());
+
+    ctx.module.ctx.mlir_module.body().append_operation(
+        OperationBuilder::new("llvm.mlir.global", Location::unknown(ctx.context()))
+            .add_attributes(&[
+                (
+                    Identifier::new(ctx.context(), "sym_name"),
+                    StringAttribute::new(ctx.context(), &symbol).into(),
+                ),
+                (
+                    Identifier::new(ctx.context(), "global_type"),
+                    TypeAttribute::new(counters_ty).into(),
+                ),
+                (
+                    Identifier::new(ctx.context(), "linkage"),
+                    Attribute::parse(ctx.context(), "#llvm.linkage<external>").unwrap(),
+                ),
+                (
+                    Identifier::new(ctx.context(), "value"),
+                    Attribute::parse(ctx.context(), &format!("dense<0> : tensor<{len}xi64>"))
+                        .unwrap(),
+                ),
+            ])
+            .add_regions([Region::new()])
+            .build()?,
+    );
+
+    Ok(())
+}
+
+/// Adds one to the coverage counter of the given block of the function, at its start. The add is
+/// atomic, so blocks run by several threads at once count every run.
+fn increment_coverage_counter<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+    body: &Function,
+    index: usize,
+) -> Result<(), CodegenError> {
+    let location = ctx.location();
+    let i64_ty: Type = IntegerType::new(ctx.context(), 64).into();
+    let counters_ty = llvm::r#type::array(i64_ty, body.basic_blocks.len() as u32);
+    let symbol = crate::driver::coverage::counters_symbol(&body.get_mangled_name());
+
+    let counters = block.append_op_result(
+        OperationBuilder::new("llvm.mlir.addressof", location)
+            .add_attributes(&[(
+                Identifier::new(ctx.context(), "global_name"),
+                FlatSymbolRefAttribute::new(ctx.context(), &symbol).into(),
+            )])
+            .add_results(&[pointer(ctx.context(), 0)])
+            .build()?,
+    )?;
+    let counter = block.gep(
+        ctx.context(),
+        location,
+        counters,
+        &[GepIndex::Const(0), GepIndex::Const(index as i32)],
+        counters_ty,
+    )?;
+    let one = block.const_int(ctx.context(), location, 1, 64)?;
+    let attribute = |name: &str, value: i64| {
+        (
+            Identifier::new(ctx.context(), name),
+            Attribute::from(IntegerAttribute::new(i64_ty, value)),
+        )
+    };
+    // The counts are only read once the program is done, they don't order other memory accesses.
+    block.append_operation(
+        OperationBuilder::new("llvm.atomicrmw", location)
+            .add_operands(&[counter, one])
+            .add_attributes(&[
+                // LLVM's `AtomicBinOp::add`.
+                attribute("bin_op", 1),
+                attribute("ordering", ATOMIC_MONOTONIC),
+                attribute("alignment", 8),
+            ])
+            .add_results(&[i64_ty])
+            .build()?,
+    );
+
+    Ok(())
+}
+
 /// Compiles a rvalue.
 fn compile_rvalue<'c: 'b, 'b>(
     ctx: &'c FunctionCodegenCtx,
@@ -496,44 +1218,84 @@ fn compile_rvalue<'c: 'b, 'b>(
 ) -> Result<(Value<'c, 'b>, TypeIndex), CodegenError> {
     Ok(match info {
         Rvalue::Use(info) => compile_load_operand(ctx, block, info, locals)?,
+        Rvalue::Move(place) => {
+            compile_load_operand(ctx, block, &Operand::Place(place.clone()), locals)?
+        }
         Rvalue::LogicOp(_, _) => todo!(),
         Rvalue::BinaryOp(op, (lhs, rhs)) => compile_binop(ctx, block, op, lhs, rhs, locals)?,
+        Rvalue::Overflows(op, (lhs, rhs)) => {
+            let (lhs, lhs_type_idx) = compile_load_operand(ctx, block, lhs, locals)?;
+            let (rhs, _) = compile_load_operand(ctx, block, rhs, locals)?;
+            let is_signed = matches!(ctx.module.get_type(lhs_type_idx), IRType::Int(_));
+            let value = compile_overflows(
+                ctx.context(),
+                block,
+                *op,
+                is_signed,
+                lhs,
+                rhs,
+                ctx.location(),
+            )?;
+            (value, ctx.module.ctx.program.get_bool_ty())
+        }
         Rvalue::UnaryOp(op, lhs) => compile_unop(ctx, block, op, lhs, locals)?,
         Rvalue::Ref(_mutability, place) => {
-            let mut value = locals[&place.local];
-            let mut local_type_idx = ctx.get_fn_body().locals[place.local].ty;
-            let mut local_ty = ctx.module.get_type(local_type_idx);
-
-            // Handle the projection.
-            for projection in &place.projection {
-                match projection {
-                    PlaceElem::Deref => {
-                        local_type_idx = local_ty.get_inner_type().expect("should have inner type");
-                        local_ty = ctx.module.get_type(local_type_idx);
-
-                        value = block
-                            .append_operation(llvm::load(
-                                ctx.context(),
-                                value,
-                                compile_type(ctx.module, &local_ty),
-                                Location::unknown(ctx.context()),
-                                LoadStoreOptions::default(),
-                            ))
-                            .result(0)?
-                            .into();
-                    }
-                    PlaceElem::GetVariant => todo!(),
-                    PlaceElem::Variant(_) => todo!(),
-                    PlaceElem::Field(_) => todo!(),
-                    PlaceElem::Index(_) => todo!(),
-                    PlaceElem::ConstantIndex(_) => todo!(),
+            // The address of the place, through its projection, e.g a field behind a pointer.
+            compile_place_ptr(ctx, block, place, locals)?
+        }
+        Rvalue::Len(place) => {
+            let (ptr, type_idx) = compile_place_ptr(ctx, block, place, locals)?;
+            let u64_ty = ctx.module.ctx.program.get_u64_ty();
+
+            let len = match ctx.module.get_type(type_idx) {
+                IRType::Array(_, len) => {
+                    let len = len.eval_u64().ok_or_else(|| {
+                        CodegenError::NotImplemented(
+                            "array sizes not evaluated to an unsigned integer".to_string(),
+                        )
+                    })?;
+                    compile_value_tree(ctx, block, &ValueTree::Leaf(ConstValue::U64(len)))?
                 }
-            }
+                IRType::Slice(_) => {
+                    let len_ptr = block.gep(
+                        ctx.context(),
+                        ctx.location(),
+                        ptr,
+                        &[GepIndex::Const(0), GepIndex::Const(1)],
+                        compile_type(ctx.module, &ctx.module.get_type(type_idx)),
+                    )?;
+                    block.load(
+                        ctx.context(),
+                        ctx.location(),
+                        len_ptr,
+                        compile_type(ctx.module, &ctx.module.get_type(u64_ty)),
+                    )?
+                }
+                _ => unreachable!("only arrays and slices have a length"),
+            };
+
+            (len, u64_ty)
+        }
+        Rvalue::Slice(place, start, end, slice_type_idx) => {
+            let location = ctx.location();
+            let (ptr, type_idx) = compile_place_ptr(ctx, block, place, locals)?;
+            let ty = ctx.module.get_type(type_idx);
+            let (start, _) = compile_load_operand(ctx, block, start, locals)?;
+            let (end, _) = compile_load_operand(ctx, block, end, locals)?;
+
+            let data = compile_element_ptr(ctx, block, ptr, &ty, start)?;
+            let len = block.append_op_result(arith::subi(end, start, location))?;
+
+            let slice = block.append_op_result(llvm::undef(
+                compile_type(ctx.module, &ctx.module.get_type(*slice_type_idx)),
+                location,
+            ))?;
+            let slice = block.insert_values(ctx.context(), location, slice, &[data, len])?;
 
-            (value, local_type_idx)
+            (slice, *slice_type_idx)
         }
         Rvalue::Cast(op, target_type_idx, _span) => {
-            let location = Location::unknown(ctx.context());
+            let location = ctx.location();
             let target_type_idx = *target_type_idx;
             let target_ty = ctx.module.get_type(target_type_idx);
             let target_mlir_ty = compile_type(ctx.module, &target_ty);
@@ -594,50 +1356,41 @@ fn compile_rvalue<'c: 'b, 'b>(
                 }
             } else if target_ty.is_int() {
                 if current_ty.is_int() {
-                    // int to int casts
-                    match current_ty
-                        .get_bit_width(ctx.module.ctx.program)
-                        .cmp(&target_ty.get_bit_width(ctx.module.ctx.program))
-                    {
-                        std::cmp::Ordering::Less => {
-                            if is_signed {
-                                let value = block
-                                    .append_operation(arith::extsi(value, target_mlir_ty, location))
-                                    .result(0)?
-                                    .into();
-                                (value, target_type_idx)
-                            } else {
-                                let value = block
-                                    .append_operation(arith::extui(value, target_mlir_ty, location))
-                                    .result(0)?
-                                    .into();
-                                (value, target_type_idx)
-                            }
-                        }
-                        std::cmp::Ordering::Equal => (value, target_type_idx),
-                        std::cmp::Ordering::Greater => {
-                            let value = block
-                                .append_operation(arith::trunci(value, target_mlir_ty, location))
-                                .result(0)?
-                                .into();
-                            (value, target_type_idx)
-                        }
-                    }
+                    // Integers keep their low bits in narrower types, and are extended by the
+                    // sign of their own type in wider ones. A bool is 0 or 1.
+                    let program = ctx.module.ctx.program;
+                    let width = |ty: &IRType| match ty {
+                        IRType::Bool => 1,
+                        ty => ty.get_bit_width(program),
+                    };
+                    let value =
+                        match width(&current_ty).cmp(&width(&target_ty)) {
+                            std::cmp::Ordering::Less if is_signed => block
+                                .append_op_result(arith::extsi(value, target_mlir_ty, location))?,
+                            std::cmp::Ordering::Less => block.append_op_result(arith::extui(
+                                value,
+                                target_mlir_ty,
+                                location,
+                            ))?,
+                            std::cmp::Ordering::Equal => value,
+                            std::cmp::Ordering::Greater => block
+                                .append_op_result(arith::trunci(value, target_mlir_ty, location))?,
+                        };
+                    (value, target_type_idx)
                 } else if current_ty.is_float() {
-                    // float to int
-                    if is_signed {
-                        let value = block
-                            .append_operation(arith::fptosi(value, target_mlir_ty, location))
-                            .result(0)?
-                            .into();
-                        (value, target_type_idx)
+                    // Floats saturate to the bounds of the integer type, NaN is 0.
+                    let intrinsic = if target_ty.is_signed() {
+                        "llvm.intr.fptosi.sat"
                     } else {
-                        let value = block
-                            .append_operation(arith::fptoui(value, target_mlir_ty, location))
-                            .result(0)?
-                            .into();
-                        (value, target_type_idx)
-                    }
+                        "llvm.intr.fptoui.sat"
+                    };
+                    let value = block.append_op_result(
+                        OperationBuilder::new(intrinsic, location)
+                            .add_operands(&[value])
+                            .add_results(&[target_mlir_ty])
+                            .build()?,
+                    )?;
+                    (value, target_type_idx)
                 } else if current_ty.is_ptr_like() {
                     // ptr to int
                     let value = block
@@ -655,6 +1408,29 @@ fn compile_rvalue<'c: 'b, 'b>(
                         target_ty.display(ctx.module.ctx.program)
                     )
                 }
+            } else if target_ty.is_float() {
+                // Integers and wider floats are rounded to the nearest float.
+                let value = if current_ty.is_float() {
+                    match current_ty
+                        .get_bit_width(ctx.module.ctx.program)
+                        .cmp(&target_ty.get_bit_width(ctx.module.ctx.program))
+                    {
+                        std::cmp::Ordering::Less => {
+                            block.append_op_result(arith::extf(value, target_mlir_ty, location))?
+                        }
+                        std::cmp::Ordering::Equal => value,
+                        std::cmp::Ordering::Greater => block.append_op_result(arith::truncf(
+                            value,
+                            target_mlir_ty,
+                            location,
+                        ))?,
+                    }
+                } else if is_signed {
+                    block.append_op_result(arith::sitofp(value, target_mlir_ty, location))?
+                } else {
+                    block.append_op_result(arith::uitofp(value, target_mlir_ty, location))?
+                };
+                (value, target_type_idx)
             } else {
                 todo!(
                     "cast from {:?} to {:?}",
@@ -666,6 +1442,91 @@ fn compile_rvalue<'c: 'b, 'b>(
     })
 }
 
+// The values of LLVM's `AtomicOrdering`.
+const ATOMIC_MONOTONIC: i64 = 2;
+const ATOMIC_ACQUIRE: i64 = 4;
+const ATOMIC_RELEASE: i64 = 5;
+const ATOMIC_ACQ_REL: i64 = 6;
+const ATOMIC_SEQ_CST: i64 = 7;
+
+/// The LLVM orderings of the atomic operation for the relaxed, release, acquire, acquire-release
+/// and sequentially consistent orderings of the intrinsics. The ones that don't apply to the
+/// operation are strengthened, and a relaxed fence is no fence at all.
+fn atomic_orderings(op: AtomicOp) -> [Option<i64>; 5] {
+    match op {
+        AtomicOp::Load => [
+            ATOMIC_MONOTONIC,
+            ATOMIC_SEQ_CST,
+            ATOMIC_ACQUIRE,
+            ATOMIC_SEQ_CST,
+            ATOMIC_SEQ_CST,
+        ]
+        .map(Some),
+        AtomicOp::Store => [
+            ATOMIC_MONOTONIC,
+            ATOMIC_RELEASE,
+            ATOMIC_SEQ_CST,
+            ATOMIC_SEQ_CST,
+            ATOMIC_SEQ_CST,
+        ]
+        .map(Some),
+        AtomicOp::Fence => [
+            None,
+            Some(ATOMIC_RELEASE),
+            Some(ATOMIC_ACQUIRE),
+            Some(ATOMIC_ACQ_REL),
+            Some(ATOMIC_SEQ_CST),
+        ],
+        _ => [
+            ATOMIC_MONOTONIC,
+            ATOMIC_RELEASE,
+            ATOMIC_ACQUIRE,
+            ATOMIC_ACQ_REL,
+            ATOMIC_SEQ_CST,
+        ]
+        .map(Some),
+    }
+}
+
+/// Whether the `Add`, `Sub` or `Mul` of the integers overflows, with the LLVM intrinsics computing
+/// it along with the operation.
+fn compile_overflows<'c: 'b, 'b>(
+    context: &'c MeliorContext,
+    block: &'b Block<'c>,
+    op: BinOp,
+    is_signed: bool,
+    lhs: Value<'c, 'b>,
+    rhs: Value<'c, 'b>,
+    location: Location<'c>,
+) -> Result<Value<'c, 'b>, CodegenError> {
+    let name = match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        _ => unreachable!("only add, sub and mul overflow"),
+    };
+    let sign = if is_signed { "s" } else { "u" };
+    let flag_ty: Type = IntegerType::new(context, 1).into();
+    let result_ty = llvm::r#type::r#struct(context, &[lhs.r#type(), flag_ty], false);
+
+    let result = block.append_op_result(
+        OperationBuilder::new(&format!("llvm.intr.{sign}{name}.with.overflow"), location)
+            .add_operands(&[lhs, rhs])
+            .add_results(&[result_ty])
+            .build()?,
+    )?;
+    Ok(block.append_op_result(
+        OperationBuilder::new("llvm.extractvalue", location)
+            .add_operands(&[result])
+            .add_attributes(&[(
+                Identifier::new(context, "position"),
+                DenseI64ArrayAttribute::new(context, &[1]).into(),
+            )])
+            .add_results(&[flag_ty])
+            .build()?,
+    )?)
+}
+
 /// Compiles a binary operation.
 fn compile_binop<'c: 'b, 'b>(
     ctx: &'c FunctionCodegenCtx,
@@ -673,11 +1534,11 @@ fn compile_binop<'c: 'b, 'b>(
     op: &BinOp,
     lhs: &Operand,
     rhs: &Operand,
-    locals: &HashMap<usize, Value<'c, '_>>,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
 ) -> Result<(Value<'c, 'b>, TypeIndex), CodegenError> {
     let (lhs, lhs_type_idx) = compile_load_operand(ctx, block, lhs, locals)?;
     let (rhs, _rhs_type_idx) = compile_load_operand(ctx, block, rhs, locals)?;
-    let location = Location::unknown(ctx.context());
+    let location = ctx.location();
     let lhs_ty = ctx.module.get_type(lhs_type_idx);
 
     let is_float = matches!(lhs_ty, IRType::Float(_));
@@ -714,12 +1575,30 @@ fn compile_binop<'c: 'b, 'b>(
             (value, lhs_type_idx)
         }
         BinOp::Sub => {
-            if is_ptr.is_some() {
-                return Err(CodegenError::NotImplemented(
-                    "substracting from a pointer is not yet implemented".to_string(),
-                ));
-            }
-            let value = if is_float {
+            let value = if let Some(inner_type_idx) = is_ptr {
+                // The pointer is offset back by the negated count.
+                let zero = block
+                    .append_operation(arith::constant(
+                        ctx.context(),
+                        IntegerAttribute::new(rhs.r#type(), 0).into(),
+                        location,
+                    ))
+                    .result(0)?
+                    .into();
+                let count = block
+                    .append_operation(arith::subi(zero, rhs, location))
+                    .result(0)?
+                    .into();
+                let inner = ctx.module.get_type(inner_type_idx);
+                let inner_ty = compile_type(ctx.module, &inner);
+                block.gep(
+                    ctx.context(),
+                    location,
+                    lhs,
+                    &[GepIndex::Value(count)],
+                    inner_ty,
+                )?
+            } else if is_float {
                 block
                     .append_operation(arith::subf(lhs, rhs, location))
                     .result(0)?
@@ -1008,10 +1887,10 @@ fn compile_unop<'c: 'b, 'b>(
     block: &'b Block<'c>,
     op: &UnOp,
     lhs: &Operand,
-    locals: &HashMap<usize, Value<'c, '_>>,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
 ) -> Result<(Value<'c, 'b>, TypeIndex), CodegenError> {
     let (lhs, lhs_type_idx) = compile_load_operand(ctx, block, lhs, locals)?;
-    let location = Location::unknown(ctx.context());
+    let location = ctx.location();
     let lhs_ty = ctx.module.get_type(lhs_type_idx);
     let lhs_type = compile_type(ctx.module, &lhs_ty);
 
@@ -1055,7 +1934,7 @@ fn compile_load_operand<'c: 'b, 'b>(
     ctx: &'c FunctionCodegenCtx,
     block: &'b Block<'c>,
     info: &Operand,
-    locals: &HashMap<usize, Value<'c, '_>>,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
 ) -> Result<(Value<'c, 'b>, TypeIndex), CodegenError> {
     Ok(match info {
         Operand::Place(info) => compile_load_place(ctx, block, info, locals)?,
@@ -1072,7 +1951,7 @@ fn compile_store_place<'c: 'b, 'b>(
     block: &'b Block<'c>,
     info: &Place,
     value: Value<'c, 'b>,
-    locals: &HashMap<usize, Value<'c, '_>>,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
 ) -> Result<(), CodegenError> {
     let mut ptr = locals[&info.local];
     let local = &ctx.get_fn_body().locals[info.local];
@@ -1088,7 +1967,7 @@ fn compile_store_place<'c: 'b, 'b>(
                         ctx.context(),
                         ptr,
                         compile_type(ctx.module, &local_ty),
-                        Location::unknown(ctx.context()),
+                        ctx.location(),
                         LoadStoreOptions::default(),
                     ))
                     .result(0)?
@@ -1130,7 +2009,7 @@ fn compile_store_place<'c: 'b, 'b>(
                         ),
                         compile_type(ctx.module, &local_ty),
                         pointer(ctx.context(), 0),
-                        Location::unknown(ctx.context()),
+                        ctx.location(),
                     ))
                     .result(0)?
                     .into();
@@ -1145,6 +2024,7 @@ fn compile_store_place<'c: 'b, 'b>(
                             AdtKind::Union => todo!(),
                         }
                     }
+                    IRType::Tuple(ref fields) => fields[*field_idx],
                     _ => unreachable!(),
                 };
                 local_ty = ctx.module.get_type(local_type_idx);
@@ -1157,25 +2037,7 @@ fn compile_store_place<'c: 'b, 'b>(
 
                 let (index, _) = compile_load_place(ctx, block, &place, locals)?;
 
-                ptr = block
-                    .append_operation(
-                        {
-                            let mut op = ods::llvm::getelementptr(
-                                ctx.context(),
-                                pointer(ctx.context(), 0),
-                                ptr,
-                                &[index],
-                                DenseI32ArrayAttribute::new(ctx.context(), &[0, i32::MIN]),
-                                TypeAttribute::new(compile_type(ctx.module, &local_ty)),
-                                Location::unknown(ctx.context()),
-                            );
-                            op.set_inbounds(Attribute::unit(ctx.context()));
-                            op
-                        }
-                        .into(),
-                    )
-                    .result(0)?
-                    .into();
+                ptr = compile_element_ptr(ctx, block, ptr, &local_ty, index)?;
 
                 local_type_idx = local_ty.get_inner_type().expect("should have inner");
                 local_ty = ctx.module.get_type(local_type_idx);
@@ -1194,7 +2056,7 @@ fn compile_store_place<'c: 'b, 'b>(
                                     &[0, (*index).try_into().unwrap()],
                                 ),
                                 TypeAttribute::new(compile_type(ctx.module, &local_ty)),
-                                Location::unknown(ctx.context()),
+                                ctx.location(),
                             );
                             op.set_inbounds(Attribute::unit(ctx.context()));
                             op
@@ -1214,7 +2076,7 @@ fn compile_store_place<'c: 'b, 'b>(
         ctx.context(),
         value,
         ptr,
-        Location::unknown(ctx.context()),
+        ctx.location(),
         LoadStoreOptions::default(),
     ));
 
@@ -1226,7 +2088,31 @@ fn compile_load_place<'c: 'b, 'b>(
     ctx: &'c FunctionCodegenCtx,
     block: &'b Block<'c>,
     info: &Place,
-    locals: &HashMap<usize, Value<'c, '_>>,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
+) -> Result<(Value<'c, 'b>, TypeIndex), CodegenError> {
+    let (ptr, local_type_idx) = compile_place_ptr(ctx, block, info, locals)?;
+    let local_ty = ctx.module.get_type(local_type_idx);
+
+    let value = block
+        .append_operation(llvm::load(
+            ctx.context(),
+            ptr,
+            compile_type(ctx.module, &local_ty),
+            ctx.location(),
+            LoadStoreOptions::default(),
+        ))
+        .result(0)?
+        .into();
+
+    Ok((value, local_type_idx))
+}
+
+/// Compiles the address of a place.
+fn compile_place_ptr<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+    info: &Place,
+    locals: &'b HashMap<usize, Value<'c, '_>>,
 ) -> Result<(Value<'c, 'b>, TypeIndex), CodegenError> {
     let mut ptr = locals[&info.local];
     let body = ctx.get_fn_body();
@@ -1243,7 +2129,7 @@ fn compile_load_place<'c: 'b, 'b>(
                         ctx.context(),
                         ptr,
                         compile_type(ctx.module, &local_ty),
-                        Location::unknown(ctx.context()),
+                        ctx.location(),
                         LoadStoreOptions::default(),
                     ))
                     .result(0)?
@@ -1275,7 +2161,7 @@ fn compile_load_place<'c: 'b, 'b>(
                                         ),
                                         compile_type(ctx.module, &local_ty),
                                         pointer(ctx.context(), 0),
-                                        Location::unknown(ctx.context()),
+                                        ctx.location(),
                                     ))
                                     .result(0)?
                                     .into();
@@ -1295,7 +2181,7 @@ fn compile_load_place<'c: 'b, 'b>(
                                         ),
                                         compile_type(ctx.module, &local_ty),
                                         pointer(ctx.context(), 0),
-                                        Location::unknown(ctx.context()),
+                                        ctx.location(),
                                     ))
                                     .result(0)?
                                     .into();
@@ -1304,6 +2190,24 @@ fn compile_load_place<'c: 'b, 'b>(
                             AdtKind::Union => todo!(),
                         }
                     }
+                    IRType::Tuple(ref fields) => {
+                        let field_type_idx = fields[*field_idx];
+                        ptr = block
+                            .append_operation(llvm::get_element_ptr(
+                                ctx.context(),
+                                ptr,
+                                DenseI32ArrayAttribute::new(
+                                    ctx.context(),
+                                    &[0, (*field_idx).try_into().unwrap()],
+                                ),
+                                compile_type(ctx.module, &local_ty),
+                                pointer(ctx.context(), 0),
+                                ctx.location(),
+                            ))
+                            .result(0)?
+                            .into();
+                        field_type_idx
+                    }
                     _ => unreachable!(),
                 };
                 local_ty = ctx.module.get_type(local_type_idx);
@@ -1316,25 +2220,7 @@ fn compile_load_place<'c: 'b, 'b>(
 
                 let (index, _index_type_idx) = compile_load_place(ctx, block, &place, locals)?;
 
-                ptr = block
-                    .append_operation(
-                        {
-                            let mut op = ods::llvm::getelementptr(
-                                ctx.context(),
-                                pointer(ctx.context(), 0),
-                                ptr,
-                                &[index],
-                                DenseI32ArrayAttribute::new(ctx.context(), &[0, i32::MIN]),
-                                TypeAttribute::new(compile_type(ctx.module, &local_ty)),
-                                Location::unknown(ctx.context()),
-                            );
-                            op.set_inbounds(Attribute::unit(ctx.context()));
-                            op
-                        }
-                        .into(),
-                    )
-                    .result(0)?
-                    .into();
+                ptr = compile_element_ptr(ctx, block, ptr, &local_ty, index)?;
 
                 local_type_idx = local_ty.get_inner_type().expect("should have inner");
                 local_ty = ctx.module.get_type(local_type_idx);
@@ -1353,7 +2239,7 @@ fn compile_load_place<'c: 'b, 'b>(
                                     &[0, (*index).try_into().unwrap()],
                                 ),
                                 TypeAttribute::new(compile_type(ctx.module, &local_ty)),
-                                Location::unknown(ctx.context()),
+                                ctx.location(),
                             );
                             op.set_inbounds(Attribute::unit(ctx.context()));
                             op
@@ -1383,7 +2269,7 @@ fn compile_load_place<'c: 'b, 'b>(
                                         DenseI32ArrayAttribute::new(ctx.context(), &[0, 0]),
                                         compile_type(ctx.module, &local_ty),
                                         pointer(ctx.context(), 0),
-                                        Location::unknown(ctx.context()),
+                                        ctx.location(),
                                     ))
                                     .result(0)?
                                     .into();
@@ -1399,18 +2285,51 @@ fn compile_load_place<'c: 'b, 'b>(
         }
     }
 
-    let value = block
-        .append_operation(llvm::load(
-            ctx.context(),
-            ptr,
-            compile_type(ctx.module, &local_ty),
-            Location::unknown(ctx.context()),
-            LoadStoreOptions::default(),
-        ))
-        .result(0)?
-        .into();
+    Ok((ptr, local_type_idx))
+}
 
-    Ok((value, local_type_idx))
+/// Compiles the address of the element at the index of the array or slice at the pointer.
+fn compile_element_ptr<'c: 'b, 'b>(
+    ctx: &'c FunctionCodegenCtx,
+    block: &'b Block<'c>,
+    ptr: Value<'c, 'b>,
+    ty: &IRType,
+    index: Value<'c, 'b>,
+) -> Result<Value<'c, 'b>, CodegenError> {
+    // Slices point to their elements with their first field.
+    let (ptr, indices, elem_ty): (_, &[i32], _) = match ty {
+        IRType::Slice(inner) => (
+            block.load(
+                ctx.context(),
+                ctx.location(),
+                ptr,
+                pointer(ctx.context(), 0),
+            )?,
+            &[i32::MIN],
+            compile_type(ctx.module, &ctx.module.get_type(*inner)),
+        ),
+        _ => (ptr, &[0, i32::MIN], compile_type(ctx.module, ty)),
+    };
+
+    Ok(block
+        .append_operation(
+            {
+                let mut op = ods::llvm::getelementptr(
+                    ctx.context(),
+                    pointer(ctx.context(), 0),
+                    ptr,
+                    &[index],
+                    DenseI32ArrayAttribute::new(ctx.context(), indices),
+                    TypeAttribute::new(elem_ty),
+                    ctx.location(),
+                );
+                op.set_inbounds(Attribute::unit(ctx.context()));
+                op
+            }
+            .into(),
+        )
+        .result(0)?
+        .into())
 }
 
 /// Used in switch
@@ -1447,13 +2366,14 @@ fn compile_value_tree<'c: 'b, 'b>(
     Ok(match value {
         ValueTree::Leaf(value) => match value {
             crate::ir::ConstValue::String(data) => {
-                let location = Location::unknown(ctx.context());
+                let location = ctx.location();
                 let ty = compile_type(ctx.module, &IRType::String);
                 let len = data.len();
                 let u64_ty = IntegerType::new(ctx.context(), 64).into();
                 let u8_ty = IntegerType::new(ctx.context(), 8).into();
-                // + 1 for the null byte.
-                let len_value =
+                let len_value = block.const_int_from_type(ctx.context(), location, len, u64_ty)?;
+                // + 1 for the null byte, which isn't part of the length.
+                let cap_value =
                     block.const_int_from_type(ctx.context(), location, len + 1, u64_ty)?;
 
                 let arr_ty = llvm::r#type::array(u8_ty, len as u32);
@@ -1471,7 +2391,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 let ptr = block.append_op_result(func::call(
                     ctx.context(),
                     FlatSymbolRefAttribute::new(ctx.context(), "malloc"),
-                    &[len_value],
+                    &[cap_value],
                     &[llvm::r#type::pointer(ctx.context(), 0)],
                     location,
                 ))?;
@@ -1485,7 +2405,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                     u8_ty,
                 )?;
                 // add the null byte.
-                let const_0 = block.const_int(ctx.context(), location, 0, 64)?;
+                let const_0 = block.const_int(ctx.context(), location, 0, 8)?;
                 block.store(ctx.context(), location, ptr_off, const_0)?;
 
                 let struct_value = block.append_op_result(llvm::undef(ty, location))?;
@@ -1494,14 +2414,14 @@ fn compile_value_tree<'c: 'b, 'b>(
                     ctx.context(),
                     location,
                     struct_value,
-                    &[ptr, len_value, len_value],
+                    &[ptr, len_value, cap_value],
                 )?
             }
             crate::ir::ConstValue::Bool(value) => block
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i1", (*value) as u8)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1509,7 +2429,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i8", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1517,7 +2437,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i16", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1525,7 +2445,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i32", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1533,7 +2453,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i64", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1541,7 +2461,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i128", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1549,7 +2469,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i8", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1557,7 +2477,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i8", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1565,7 +2485,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i16", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1573,7 +2493,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i32", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1581,7 +2501,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i64", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1589,7 +2509,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                 .append_operation(arith::constant(
                     ctx.context(),
                     Attribute::parse(ctx.context(), &format!("{} : i128", value)).unwrap(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1602,7 +2522,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                         value.parse().unwrap(),
                     )
                     .into(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1615,7 +2535,7 @@ fn compile_value_tree<'c: 'b, 'b>(
                         value.parse().unwrap(),
                     )
                     .into(),
-                    Location::unknown(ctx.context()),
+                    ctx.location(),
                 ))
                 .result(0)?
                 .into(),
@@ -1667,6 +2587,15 @@ fn compile_type<'c>(ctx: ModuleCodegenCtx<'c>, ty: &IRType) -> Type<'c> {
 
             melior::dialect::llvm::r#type::array(inner_type, length as u32)
         }
+        // A pointer to the elements and their count.
+        crate::ir::Type::Slice(_) => llvm::r#type::r#struct(
+            ctx.ctx.mlir_context,
+            &[
+                llvm::r#type::pointer(ctx.ctx.mlir_context, 0),
+                IntegerType::new(ctx.ctx.mlir_context, 64).into(),
+            ],
+            false,
+        ),
         crate::ir::Type::Ref(_inner_ty, _) | crate::ir::Type::Ptr(_inner_ty, _) => {
             llvm::r#type::pointer(ctx.ctx.mlir_context, 0)
         }
@@ -1705,6 +2634,13 @@ fn compile_type<'c>(ctx: ModuleCodegenCtx<'c>, ty: &IRType) -> Type<'c> {
                 AdtKind::Union => todo!(),
             }
         }
+        crate::ir::Type::Tuple(fields) => {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|field| compile_type(ctx, &ctx.get_type(*field)))
+                .collect();
+            llvm::r#type::r#struct(ctx.ctx.mlir_context, &fields, false)
+        }
     }
 }
 
@@ -1764,6 +2700,365 @@ fn compile_intrinsic_function(ctx: FunctionCodegenCtx) -> Result<(), CodegenErro
             )?;
             entry_block.append_operation(func::r#return(&[ret_value], location));
         }
+        ConcreteIntrinsic::BoundsChecks => {
+            let ret_value = entry_block.const_int_from_type(
+                ctx.context(),
+                location,
+                u8::from(ctx.module.ctx.bounds_checks),
+                return_type.unwrap(),
+            )?;
+            entry_block.append_operation(func::r#return(&[ret_value], location));
+        }
+        ConcreteIntrinsic::OverflowChecks => {
+            let ret_value = entry_block.const_int_from_type(
+                ctx.context(),
+                location,
+                u8::from(ctx.module.ctx.overflow_checks),
+                return_type.unwrap(),
+            )?;
+            entry_block.append_operation(func::r#return(&[ret_value], location));
+        }
+        ConcreteIntrinsic::Wrapping(op) => {
+            let lhs: Value = entry_block.argument(0)?.into();
+            let rhs: Value = entry_block.argument(1)?.into();
+            let ret_value = entry_block.append_op_result(match op {
+                BinOp::Add => arith::addi(lhs, rhs, location),
+                BinOp::Sub => arith::subi(lhs, rhs, location),
+                _ => arith::muli(lhs, rhs, location),
+            })?;
+            entry_block.append_operation(func::r#return(&[ret_value], location));
+        }
+        ConcreteIntrinsic::Overflows(op) => {
+            let lhs: Value = entry_block.argument(0)?.into();
+            let rhs: Value = entry_block.argument(1)?.into();
+            let is_signed = matches!(body_signature.0[0], IRType::Int(_));
+            let ret_value = compile_overflows(
+                ctx.context(),
+                &entry_block,
+                *op,
+                is_signed,
+                lhs,
+                rhs,
+                location,
+            )?;
+            entry_block.append_operation(func::r#return(&[ret_value], location));
+        }
+        ConcreteIntrinsic::Atomic(op) => {
+            let context = ctx.context();
+            let i64_ty: Type = IntegerType::new(context, 64).into();
+            let arguments = (0..param_types.len())
+                .map(|i| entry_block.argument(i).map(Value::from))
+                .collect::<Result<Vec<_>, _>>()?;
+            let (ordering, operands) = arguments.split_last().expect("atomics take an ordering");
+            // LLVM needs the alignment of the atomic loads and stores.
+            let alignment = match body_signature.0.first() {
+                Some(IRType::Ptr(inner, _)) => {
+                    ctx.module
+                        .get_type(*inner)
+                        .get_align(ctx.module.ctx.program)
+                        / 8
+                }
+                _ => 0,
+            };
+            let attribute = |name: &str, value: i64| {
+                (
+                    Identifier::new(context, name),
+                    Attribute::from(IntegerAttribute::new(i64_ty, value)),
+                )
+            };
+
+            // The ordering is an argument but LLVM needs it to be a constant, so there's a block
+            // doing the operation for each one, inlining folds the switch when it's a literal.
+            let mut ordering_blocks = Vec::new();
+            for llvm_ordering in atomic_orderings(*op) {
+                let block = region.append_block(Block::new(&[]));
+                let mut results = Vec::new();
+
+                if let Some(llvm_ordering) = llvm_ordering {
+                    let ordering_attr = attribute("ordering", llvm_ordering);
+                    let alignment_attr = attribute("alignment", alignment as i64);
+                    match op {
+                        AtomicOp::Load => results.push(
+                            block.append_op_result(
+                                OperationBuilder::new("llvm.load", location)
+                                    .add_operands(&[operands[0]])
+                                    .add_attributes(&[ordering_attr, alignment_attr])
+                                    .add_results(&[return_type.unwrap()])
+                                    .build()?,
+                            )?,
+                        ),
+                        AtomicOp::Store => {
+                            block.append_operation(
+                                OperationBuilder::new("llvm.store", location)
+                                    .add_operands(&[operands[1], operands[0]])
+                                    .add_attributes(&[ordering_attr, alignment_attr])
+                                    .build()?,
+                            );
+                        }
+                        AtomicOp::Swap | AtomicOp::Rmw(_) => {
+                            // LLVM's `AtomicBinOp` values.
+                            let bin_op = match op {
+                                AtomicOp::Rmw(BinOp::Add) => 1,
+                                AtomicOp::Rmw(BinOp::Sub) => 2,
+                                AtomicOp::Rmw(BinOp::BitAnd) => 3,
+                                AtomicOp::Rmw(BinOp::BitOr) => 5,
+                                AtomicOp::Rmw(BinOp::BitXor) => 6,
+                                _ => 0,
+                            };
+                            results.push(
+                                block.append_op_result(
+                                    OperationBuilder::new("llvm.atomicrmw", location)
+                                        .add_operands(&[operands[0], operands[1]])
+                                        .add_attributes(&[
+                                            attribute("bin_op", bin_op),
+                                            ordering_attr,
+                                            alignment_attr,
+                                        ])
+                                        .add_results(&[operands[1].r#type()])
+                                        .build()?,
+                                )?,
+                            );
+                        }
+                        AtomicOp::CompareExchange => {
+                            // A failed exchange only loads, it can't have a release ordering.
+                            let failure_ordering = match llvm_ordering {
+                                ATOMIC_RELEASE => ATOMIC_MONOTONIC,
+                                ATOMIC_ACQ_REL => ATOMIC_ACQUIRE,
+                                ordering => ordering,
+                            };
+                            let flag_ty: Type = IntegerType::new(context, 1).into();
+                            let result_ty = llvm::r#type::r#struct(
+                                context,
+                                &[operands[1].r#type(), flag_ty],
+                                false,
+                            );
+                            let result = block.append_op_result(
+                                OperationBuilder::new("llvm.cmpxchg", location)
+                                    .add_operands(&[operands[0], operands[1], operands[2]])
+                                    .add_attributes(&[
+                                        attribute("success_ordering", llvm_ordering),
+                                        attribute("failure_ordering", failure_ordering),
+                                        alignment_attr,
+                                    ])
+                                    .add_results(&[result_ty])
+                                    .build()?,
+                            )?;
+                            results.push(
+                                block.append_op_result(
+                                    OperationBuilder::new("llvm.extractvalue", location)
+                                        .add_operands(&[result])
+                                        .add_attributes(&[(
+                                            Identifier::new(context, "position"),
+                                            DenseI64ArrayAttribute::new(context, &[1]).into(),
+                                        )])
+                                        .add_results(&[flag_ty])
+                                        .build()?,
+                                )?,
+                            );
+                        }
+                        AtomicOp::Fence => {
+                            block.append_operation(
+                                OperationBuilder::new("llvm.fence", location)
+                                    .add_attributes(&[ordering_attr])
+                                    .build()?,
+                            );
+                        }
+                    }
+                }
+
+                block.append_operation(func::r#return(&results, location));
+                ordering_blocks.push(block);
+            }
+
+            // Relaxed, release, acquire and acquire-release, anything else is sequentially
+            // consistent.
+            let case_values = [0, 1, 2, 3];
+            let mut dests: Vec<(&Block, _)> = Vec::new();
+            for block in &ordering_blocks[..case_values.len()] {
+                dests.push((block, [].as_slice()));
+            }
+            entry_block.append_operation(cf::switch(
+                context,
+                &case_values,
+                *ordering,
+                ordering.r#type(),
+                (&ordering_blocks[case_values.len()], &[]),
+                &dests,
+                location,
+            )?);
+        }
+        ConcreteIntrinsic::ThreadStart { run, .. } => {
+            let context = ctx.context();
+            let ptr_ty = pointer(context, 0);
+            let run = run.expect("the run method of the task is found when calling the intrinsic");
+            let run_body = ctx.module.ctx.program.functions[run]
+                .as_ref()
+                .expect("run method body not found");
+            let run_ret_type = match ctx.module.get_fn_signature(run).1 {
+                IRType::Unit => None,
+                ty => Some(compile_type(ctx.module, &ty)),
+            };
+
+            // A C function for the thread to start from, which runs the task behind its argument.
+            let start_name = format!("{}_thread_start", body.get_mangled_name());
+            let start_type = FunctionType::new(context, &[ptr_ty], &[ptr_ty]);
+            let start_region = Region::new();
+            let start_block = start_region.append_block(Block::new(&[(ptr_ty, location)]));
+            let task: Value = start_block.argument(0)?.into();
+            start_block.append_operation(func::call(
+                context,
+                FlatSymbolRefAttribute::new(context, &run_body.get_mangled_name()),
+                &[task],
+                run_ret_type.as_slice(),
+                location,
+            ));
+            let null = start_block.append_op_result(
+                OperationBuilder::new("llvm.mlir.zero", location)
+                    .add_results(&[ptr_ty])
+                    .build()?,
+            )?;
+            start_block.append_operation(func::r#return(&[null], location));
+            ctx.module
+                .ctx
+                .mlir_module
+                .body()
+                .append_operation(func::func(
+                    context,
+                    StringAttribute::new(context, &start_name),
+                    TypeAttribute::new(start_type.into()),
+                    start_region,
+                    &[(
+                        Identifier::new(context, "sym_visibility"),
+                        StringAttribute::new(context, "private").into(),
+                    )],
+                    location,
+                ));
+
+            // The function value becomes a pointer when the functions are converted to LLVM.
+            let start = entry_block.append_op_result(
+                OperationBuilder::new("func.constant", location)
+                    .add_attributes(&[(
+                        Identifier::new(context, "value"),
+                        FlatSymbolRefAttribute::new(context, &start_name).into(),
+                    )])
+                    .add_results(&[start_type.into()])
+                    .build()?,
+            )?;
+            let address = entry_block.append_op_result(
+                OperationBuilder::new("builtin.unrealized_conversion_cast", location)
+                    .add_operands(&[start])
+                    .add_results(&[return_type.unwrap()])
+                    .build()?,
+            )?;
+            entry_block.append_operation(func::r#return(&[address], location));
+        }
+        ConcreteIntrinsic::FuturePoll => {
+            let context = ctx.context();
+            let ptr_ty = pointer(context, 0);
+            let bool_ty: Type = IntegerType::new(context, 1).into();
+            let poll_type = FunctionType::new(context, &[ptr_ty, ptr_ty], &[bool_ty]);
+
+            // The pointer is the function value `AsyncStart` stored.
+            let poll = entry_block.append_op_result(
+                OperationBuilder::new("builtin.unrealized_conversion_cast", location)
+                    .add_operands(&[entry_block.argument(0)?.into()])
+                    .add_results(&[poll_type.into()])
+                    .build()?,
+            )?;
+            let ready = entry_block.append_op_result(func::call_indirect(
+                poll,
+                &[
+                    entry_block.argument(1)?.into(),
+                    entry_block.argument(2)?.into(),
+                ],
+                &[bool_ty],
+                location,
+            ))?;
+            entry_block.append_operation(func::r#return(&[ready], location));
+        }
+        ConcreteIntrinsic::AsyncStart { frame, poll } => {
+            let context = ctx.context();
+            let ptr_ty = pointer(context, 0);
+            let frame_ty = compile_type(ctx.module, &ctx.module.get_type(*frame));
+            let poll_body = ctx.module.ctx.program.functions[*poll]
+                .as_ref()
+                .expect("poll function body not found");
+
+            // The size of the frame is the offset of the one after it from null.
+            let null = entry_block.append_op_result(
+                OperationBuilder::new("llvm.mlir.zero", location)
+                    .add_results(&[ptr_ty])
+                    .build()?,
+            )?;
+            let end = entry_block.gep(context, location, null, &[GepIndex::Const(1)], frame_ty)?;
+            let size = entry_block.append_op_result(
+                OperationBuilder::new("llvm.ptrtoint", location)
+                    .add_operands(&[end])
+                    .add_results(&[IntegerType::new(context, 64).into()])
+                    .build()?,
+            )?;
+            let frame_ptr = entry_block.append_op_result(func::call(
+                context,
+                FlatSymbolRefAttribute::new(context, "malloc"),
+                &[size],
+                &[ptr_ty],
+                location,
+            ))?;
+
+            // The state machine starts from the beginning of the body, with the arguments after
+            // the state. Those of unit type aren't in the frame.
+            let state = entry_block.const_int(context, location, 0, 32)?;
+            let state_ptr = entry_block.gep(
+                context,
+                location,
+                frame_ptr,
+                &[GepIndex::Const(0), GepIndex::Const(0)],
+                frame_ty,
+            )?;
+            entry_block.store(context, location, state_ptr, state)?;
+            let mut field = 1;
+            for (i, arg_ty) in body_signature.0.iter().enumerate() {
+                if let IRType::Unit = arg_ty {
+                    continue;
+                }
+                let arg_ptr = entry_block.gep(
+                    context,
+                    location,
+                    frame_ptr,
+                    &[GepIndex::Const(0), GepIndex::Const(field)],
+                    frame_ty,
+                )?;
+                entry_block.store(context, location, arg_ptr, entry_block.argument(i)?.into())?;
+                field += 1;
+            }
+
+            let poll_type = FunctionType::new(
+                context,
+                &[ptr_ty, ptr_ty],
+                &[IntegerType::new(context, 1).into()],
+            );
+            let poll = entry_block.append_op_result(
+                OperationBuilder::new("func.constant", location)
+                    .add_attributes(&[(
+                        Identifier::new(context, "value"),
+                        FlatSymbolRefAttribute::new(context, &poll_body.get_mangled_name()).into(),
+                    )])
+                    .add_results(&[poll_type.into()])
+                    .build()?,
+            )?;
+            let poll = entry_block.append_op_result(
+                OperationBuilder::new("builtin.unrealized_conversion_cast", location)
+                    .add_operands(&[poll])
+                    .add_results(&[ptr_ty])
+                    .build()?,
+            )?;
+
+            let future =
+                entry_block.append_op_result(llvm::undef(return_type.unwrap(), location))?;
+            let future =
+                entry_block.insert_values(context, location, future, &[frame_ptr, poll])?;
+            entry_block.append_operation(func::r#return(&[future], location));
+        }
     }
 
     // Create the function mlir attribute.
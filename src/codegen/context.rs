@@ -1,5 +1,8 @@
-use crate::compile_unit_info::CompileUnitInfo;
-use crate::ir::IR;
+use std::sync::Once;
+
+use crate::compile_unit_info::{CompileUnitInfo, DebugInfo, OptLevel, Sanitizer};
+use crate::driver::timings::{self, Phase};
+use crate::ir::{IR, ModuleIndex, source_map::SourceMap};
 use melior::{
     Context as MeliorContext,
     dialect::DialectRegistry,
@@ -10,7 +13,9 @@ use melior::{
     utility::{register_all_dialects, register_all_llvm_translations, register_all_passes},
 };
 
-use super::{compiler::CodegenCtx, errors::CodegenError, get_data_layout_rep, get_target_triple};
+use super::{
+    compiler::CodegenCtx, debug_info, errors::CodegenError, get_data_layout_rep, get_target_triple,
+};
 
 use super::{module::MLIRModule, pass_manager::run_pass_manager};
 
@@ -34,10 +39,13 @@ impl Context {
         Self { melior_context }
     }
 
+    /// Generates the code of the given top level modules of the program.
     pub fn compile(
         &self,
         compile_unit_info: &CompileUnitInfo,
         program: &IR,
+        modules: &[ModuleIndex],
+        source_map: &SourceMap,
     ) -> Result<MLIRModule, CodegenError> {
         let location = Location::unknown(&self.melior_context);
         let target_triple = get_target_triple(compile_unit_info);
@@ -64,13 +72,36 @@ impl Context {
 
         let mut melior_module = MeliorModule::from_operation(op).expect("module failed to create");
 
+        let debug_info = match compile_unit_info.debug_info {
+            DebugInfo::Full => Some(debug_info::DebugInfo::new(
+                &self.melior_context,
+                program,
+                modules,
+                source_map,
+                compile_unit_info.optlevel != OptLevel::None,
+            )?),
+            DebugInfo::None => None,
+        };
+
         let codegen_ctx = CodegenCtx {
             mlir_context: &self.melior_context,
             mlir_module: &melior_module,
             program,
+            source_map,
+            bounds_checks: (!compile_unit_info.unchecked_indexing
+                && compile_unit_info.optlevel != OptLevel::Aggressive)
+                || compile_unit_info.sanitizers.contains(&Sanitizer::Undefined),
+            overflow_checks: compile_unit_info.optlevel == OptLevel::None
+                || compile_unit_info.sanitizers.contains(&Sanitizer::Undefined),
+            undefined_checks: compile_unit_info.sanitizers.contains(&Sanitizer::Undefined),
+            panics: compile_unit_info.has_libc(),
+            debug_info: debug_info.as_ref(),
+            coverage: compile_unit_info.coverage,
         };
 
-        super::compiler::compile_program(codegen_ctx)?;
+        let phase = timings::phase(Phase::Codegen);
+        super::compiler::compile_program(codegen_ctx, modules)?;
+        drop(phase);
 
         if compile_unit_info.output_mlir {
             std::fs::write(
@@ -84,7 +115,9 @@ impl Context {
         assert!(melior_module.as_operation().verify());
 
         // TODO: Add proper error handling.
+        let phase = timings::phase(Phase::MlirPasses);
         run_pass_manager(&self.melior_context, &mut melior_module).unwrap();
+        drop(phase);
 
         // The func to llvm pass has a bug where it sets the data layout string to ""
         // This works around it by setting it again.
@@ -118,7 +151,9 @@ pub fn initialize_mlir() -> MeliorContext {
         registry
     });
     context.load_all_available_dialects();
-    register_all_passes();
+    // The pass registry is global, contexts created on several threads register the passes once.
+    static PASSES: Once = Once::new();
+    PASSES.call_once(register_all_passes);
     register_all_llvm_translations(&context);
     context
 }
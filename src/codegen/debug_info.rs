@@ -0,0 +1,435 @@
+//! DWARF debug info, emitted for `DebugInfo::Full` builds so debuggers can set breakpoints by line
+//! and print the local variables.
+//!
+//! The metadata is written as the text of the LLVM dialect attributes and parsed at once: the
+//! compile unit and the subprograms are distinct attributes, which are only shared by the
+//! attributes referencing them when they're parsed together.
+
+use std::{collections::HashMap, fmt::Write, path::Path};
+
+use melior::{
+    Context as MeliorContext,
+    ir::{Attribute, attribute::ArrayAttribute},
+};
+
+use crate::ir::{
+    AdtKind, ConstKind, ConstValue, FloatTy, FnIndex, Function, IR, LexicalScope, LocalIndex,
+    LocalKind, ModuleIndex, ScopeIndex, Span, Type, TypeIndex, ValueTree, source_map::SourceMap,
+};
+
+use super::errors::CodegenError;
+
+/// The debug info of the functions of a MLIR module.
+#[derive(Debug)]
+pub(crate) struct DebugInfo<'c> {
+    functions: HashMap<FnIndex, FunctionDebugInfo<'c>>,
+}
+
+/// The debug info of a function.
+#[derive(Debug)]
+pub(crate) struct FunctionDebugInfo<'c> {
+    /// The `DISubprogram`, the location of the function is fused with it.
+    pub subprogram: Attribute<'c>,
+    /// The `DILexicalBlock` of each lexical scope of the function.
+    pub scopes: Vec<Attribute<'c>>,
+    /// The `DILocalVariable` of the user-defined locals.
+    pub variables: HashMap<LocalIndex, Attribute<'c>>,
+}
+
+impl<'c> DebugInfo<'c> {
+    /// Describes the functions defined in the given top level modules of the program.
+    pub fn new(
+        context: &'c MeliorContext,
+        program: &IR,
+        modules: &[ModuleIndex],
+        source_map: &SourceMap,
+        is_optimized: bool,
+    ) -> Result<Self, CodegenError> {
+        let unit_file = modules
+            .first()
+            .map(|x| file(&program.modules[*x].file_path))
+            .unwrap_or_else(|| file(Path::new("<unknown>")));
+        let mut writer = Writer {
+            program,
+            compile_unit: format!(
+                "#llvm.di_compile_unit<id = distinct[0]<>, sourceLanguage = DW_LANG_C, \
+                 file = {unit_file}, producer = {}, isOptimized = {is_optimized}, \
+                 emissionKind = Full>",
+                quote(&format!("concrete {}", env!("CARGO_PKG_VERSION")))
+            ),
+            types: HashMap::new(),
+            next_id: 1,
+        };
+
+        let mut described = Vec::new();
+        let mut text = String::from("[");
+
+        for (fn_idx, body) in program.functions.iter() {
+            let Some(body) = body else {
+                continue;
+            };
+
+            if body.is_extern
+                || body.is_intrinsic.is_some()
+                || !modules.contains(&program.top_level_module(body.module_idx))
+            {
+                continue;
+            }
+
+            let file_path = &program.modules[body.module_idx].file_path;
+            let Some(function) = writer.function(body, file_path, source_map) else {
+                continue;
+            };
+
+            if !described.is_empty() {
+                text.push_str(", ");
+            }
+            write!(
+                text,
+                "[{}, [{}], [{}]]",
+                function.subprogram,
+                function.scopes.join(", "),
+                function
+                    .variables
+                    .iter()
+                    .map(|x| x.1.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .unwrap();
+            described.push((fn_idx, function));
+        }
+        text.push(']');
+
+        let invalid = || CodegenError::LLVMCompileError("invalid debug info".to_string());
+        let parsed = Attribute::parse(context, &text).ok_or_else(invalid)?;
+        let parsed = ArrayAttribute::try_from(parsed).map_err(|_| invalid())?;
+        let mut functions = HashMap::new();
+
+        for (i, (fn_idx, function)) in described.into_iter().enumerate() {
+            let parts = ArrayAttribute::try_from(parsed.element(i)?).map_err(|_| invalid())?;
+            let scopes = ArrayAttribute::try_from(parts.element(1)?).map_err(|_| invalid())?;
+            let variables = ArrayAttribute::try_from(parts.element(2)?).map_err(|_| invalid())?;
+
+            functions.insert(
+                fn_idx,
+                FunctionDebugInfo {
+                    subprogram: parts.element(0)?,
+                    scopes: (0..scopes.len())
+                        .map(|x| scopes.element(x))
+                        .collect::<Result<_, _>>()?,
+                    variables: function
+                        .variables
+                        .iter()
+                        .enumerate()
+                        .map(|(x, (local, _))| Ok((*local, variables.element(x)?)))
+                        .collect::<Result<_, CodegenError>>()?,
+                },
+            );
+        }
+
+        Ok(Self { functions })
+    }
+
+    /// The debug info of the function, if it has a body in the module.
+    pub fn function(&self, fn_idx: FnIndex) -> Option<&FunctionDebugInfo<'c>> {
+        self.functions.get(&fn_idx)
+    }
+}
+
+impl<'c> FunctionDebugInfo<'c> {
+    /// The debug info scope of the given lexical scope, the subprogram for the function body.
+    pub fn scope(&self, scope: Option<ScopeIndex>) -> Attribute<'c> {
+        scope.map_or(self.subprogram, |x| self.scopes[x])
+    }
+
+    /// The debug info scope of the code with the given span: the innermost lexical scope that
+    /// contains it.
+    pub fn scope_of(&self, scopes: &[LexicalScope], span: Span) -> Attribute<'c> {
+        let innermost = scopes
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.span.from <= span.from && span.to <= x.span.to)
+            .min_by_key(|(_, x)| x.span.to - x.span.from)
+            .map(|x| x.0);
+
+        self.scope(innermost)
+    }
+}
+
+/// The text of the debug info of a function.
+struct FunctionText {
+    subprogram: String,
+    scopes: Vec<String>,
+    variables: Vec<(LocalIndex, String)>,
+}
+
+/// Writes the text of the attributes.
+struct Writer<'a> {
+    program: &'a IR,
+    compile_unit: String,
+    /// The described types, `None` if a type can't be described, or is being described, to
+    /// break the cycles of types that point to themselves.
+    types: HashMap<TypeIndex, Option<String>>,
+    /// The next id of a distinct attribute, 0 is the compile unit.
+    next_id: usize,
+}
+
+impl Writer<'_> {
+    fn function(
+        &mut self,
+        body: &Function,
+        file_path: &Path,
+        source_map: &SourceMap,
+    ) -> Option<FunctionText> {
+        let location = source_map.location(file_path, body.span?)?;
+        let file = file(file_path);
+        let line_column = |span: Option<Span>| {
+            span.and_then(|x| source_map.location(file_path, x))
+                .map_or((location.line, location.column), |x| (x.line, x.column))
+        };
+
+        let mut types = Vec::new();
+        for ty in std::iter::once(body.ret_ty).chain(body.args.iter().copied()) {
+            types.push(
+                self.describe(ty)
+                    .unwrap_or_else(|| "#llvm.di_null_type".to_string()),
+            );
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let name = body.debug_name.as_deref().unwrap_or(&body.name);
+        let subprogram = format!(
+            "#llvm.di_subprogram<id = distinct[{id}]<>, compileUnit = {}, scope = {file}, \
+             name = {}, linkageName = {}, file = {file}, line = {}, scopeLine = {}, \
+             subprogramFlags = Definition, type = #llvm.di_subroutine_type<types = {}>>",
+            self.compile_unit,
+            quote(name),
+            quote(&body.get_mangled_name()),
+            location.line,
+            location.line,
+            types.join(", ")
+        );
+
+        // The scopes are pushed when entered, so the parents come before their blocks.
+        let mut scopes: Vec<String> = Vec::new();
+        for scope in &body.scopes {
+            let (line, column) = line_column(Some(scope.span));
+            let parent = scope.parent.map_or(&subprogram, |x| &scopes[x]);
+            let block = format!(
+                "#llvm.di_lexical_block<scope = {parent}, file = {file}, line = {line}, \
+                 column = {column}>"
+            );
+            scopes.push(block);
+        }
+
+        let mut variables = Vec::new();
+        let mut arg = 0;
+        for (index, local) in body.locals.iter().enumerate() {
+            let arg = match local.kind {
+                LocalKind::Arg => {
+                    arg += 1;
+                    Some(arg)
+                }
+                LocalKind::Temp => None,
+                LocalKind::ReturnPointer => continue,
+            };
+            let Some(name) = &local.debug_name else {
+                continue;
+            };
+
+            let (line, _) = line_column(local.span);
+            let scope = local.scope.map_or(&subprogram, |x| &scopes[x]);
+            let mut variable = format!(
+                "#llvm.di_local_variable<scope = {scope}, name = {}, file = {file}, line = {line}",
+                quote(name)
+            );
+            if let Some(arg) = arg {
+                write!(variable, ", arg = {arg}").unwrap();
+            }
+            if let Some(ty) = self.describe(local.ty) {
+                write!(variable, ", type = {ty}").unwrap();
+            }
+            variable.push('>');
+            variables.push((index, variable));
+        }
+
+        Some(FunctionText {
+            subprogram,
+            scopes,
+            variables,
+        })
+    }
+
+    /// The debug info type of the given type, `None` if it can't be described, like enums.
+    fn describe(&mut self, ty_idx: TypeIndex) -> Option<String> {
+        if let Some(described) = self.types.get(&ty_idx) {
+            return described.clone();
+        }
+        self.types.insert(ty_idx, None);
+
+        let program = self.program;
+        let ty = program.types[ty_idx].as_ref()?;
+        let name = ty.display(program).ok()?;
+        let bits = |ty: &Type| ty.get_bit_width(program);
+
+        let described = match ty {
+            Type::Unit => None,
+            Type::Bool => Some(basic_type(&name, 8, "DW_ATE_boolean")),
+            Type::Char => Some(basic_type(&name, 8, "DW_ATE_unsigned_char")),
+            Type::Int(_) => Some(basic_type(&name, bits(ty), "DW_ATE_signed")),
+            Type::Uint(_) => Some(basic_type(&name, bits(ty), "DW_ATE_unsigned")),
+            Type::Float(FloatTy::F32) => Some(basic_type(&name, 32, "DW_ATE_float")),
+            Type::Float(FloatTy::F64) => Some(basic_type(&name, 64, "DW_ATE_float")),
+            Type::String => self.describe(*program.builtin_types.get(&Type::String)?),
+            Type::Ref(inner, _) | Type::Ptr(inner, _) => {
+                let mut pointer = format!(
+                    "#llvm.di_derived_type<tag = DW_TAG_pointer_type, name = {}",
+                    quote(&name)
+                );
+                if let Some(inner) = self.describe(*inner) {
+                    write!(pointer, ", baseType = {inner}").unwrap();
+                }
+                pointer.push_str(", sizeInBits = 64>");
+                Some(pointer)
+            }
+            Type::Array(inner, length) => {
+                let ConstKind::Value(ValueTree::Leaf(ConstValue::U64(length))) = length.data else {
+                    return None;
+                };
+                let inner = self.describe(*inner)?;
+                Some(format!(
+                    "#llvm.di_composite_type<tag = DW_TAG_array_type, baseType = {inner}, \
+                     sizeInBits = {}, elements = #llvm.di_subrange<count = {length} : i64>>",
+                    bits(ty)
+                ))
+            }
+            Type::Slice(inner) => {
+                let data = match self.describe(*inner) {
+                    Some(inner) => format!(
+                        "#llvm.di_derived_type<tag = DW_TAG_pointer_type, baseType = {inner}, \
+                         sizeInBits = 64>"
+                    ),
+                    None => "#llvm.di_derived_type<tag = DW_TAG_pointer_type, sizeInBits = 64>"
+                        .to_string(),
+                };
+                let len = basic_type("u64", 64, "DW_ATE_unsigned");
+                Some(format!(
+                    "#llvm.di_composite_type<tag = DW_TAG_structure_type, name = {}, \
+                     sizeInBits = 128, elements = {}, {}>",
+                    quote(&name),
+                    member("data", &data, 64, 0),
+                    member("len", &len, 64, 64)
+                ))
+            }
+            Type::Adt(adt_idx) => {
+                let adt = program.aggregates[*adt_idx].as_ref()?;
+                if adt.kind != AdtKind::Struct {
+                    return None;
+                }
+
+                let fields = adt.variants[0].fields.iter();
+                self.describe_struct(&name, ty, fields.map(|x| (x.name.clone(), x.ty)))
+            }
+            // Tuples are laid out like structs with the fields `0`, `1`, ...
+            Type::Tuple(fields) => {
+                let fields = fields.iter().enumerate();
+                self.describe_struct(&name, ty, fields.map(|(i, x)| (i.to_string(), *x)))
+            }
+        };
+
+        self.types.insert(ty_idx, described.clone());
+        described
+    }
+
+    /// The debug info type of a struct-like type with the given fields.
+    fn describe_struct(
+        &mut self,
+        name: &str,
+        ty: &Type,
+        fields: impl Iterator<Item = (String, TypeIndex)>,
+    ) -> Option<String> {
+        let program = self.program;
+
+        // The fields are laid out in order, each aligned to its type.
+        let mut members = Vec::new();
+        let mut offset = 0;
+        for (field_name, field_ty_idx) in fields {
+            let field_ty = program.types[field_ty_idx].as_ref()?;
+            let align = field_ty.get_align(program).max(8);
+            offset = offset.next_multiple_of(align);
+            let size = field_ty.get_bit_width(program);
+            if let Some(described) = self.describe(field_ty_idx) {
+                members.push(member(&field_name, &described, size, offset));
+            }
+            offset += size;
+        }
+
+        let mut composite = format!(
+            "#llvm.di_composite_type<tag = DW_TAG_structure_type, name = {}",
+            quote(name)
+        );
+        write!(
+            composite,
+            ", sizeInBits = {}, alignInBits = {}",
+            ty.get_bit_width(program),
+            ty.get_align(program)
+        )
+        .unwrap();
+        if !members.is_empty() {
+            write!(composite, ", elements = {}", members.join(", ")).unwrap();
+        }
+        composite.push('>');
+        Some(composite)
+    }
+}
+
+fn basic_type(name: &str, bits: usize, encoding: &str) -> String {
+    format!(
+        "#llvm.di_basic_type<tag = DW_TAG_base_type, name = {}, sizeInBits = {bits}, \
+         encoding = {encoding}>",
+        quote(name)
+    )
+}
+
+fn member(name: &str, ty: &str, bits: usize, offset: usize) -> String {
+    format!(
+        "#llvm.di_derived_type<tag = DW_TAG_member, name = {}, baseType = {ty}, \
+         sizeInBits = {bits}, offsetInBits = {offset}>",
+        quote(name)
+    )
+}
+
+/// The `DIFile` of the source file, with its directory made absolute for debuggers to find it.
+fn file(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    format!(
+        "#llvm.di_file<{} in {}>",
+        quote(&name),
+        quote(&directory.to_string_lossy())
+    )
+}
+
+/// A MLIR string literal.
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -10,4 +10,7 @@ pub enum CodegenError {
     MeliorError(#[from] melior::Error),
     #[error("not yet implemented: {0}")]
     NotImplemented(String),
+    /// A target triple with an architecture LLVM wasn't built with.
+    #[error("{0}")]
+    UnknownTarget(String),
 }
@@ -0,0 +1,138 @@
+//! Running the generated code in the compiler process with LLVM's ORC JIT, for `concrete repl`.
+//!
+//! The code goes through the same MLIR and LLVM pipeline as the objects of a build, but instead of
+//! being written to a file it's added to an LLJIT, which links it in memory. The symbols it
+//! doesn't define, like the functions of libc, are looked up in the compiler process.
+
+use std::{
+    ffi::{CStr, CString},
+    ptr::null_mut,
+};
+
+use llvm_sys::{
+    error::{LLVMConsumeError, LLVMDisposeErrorMessage, LLVMErrorRef, LLVMGetErrorMessage},
+    orc2::{
+        LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess, LLVMOrcCreateNewThreadSafeContext,
+        LLVMOrcCreateNewThreadSafeModule, LLVMOrcDisposeThreadSafeContext,
+        LLVMOrcDisposeThreadSafeModule, LLVMOrcJITDylibAddGenerator,
+        LLVMOrcThreadSafeContextGetContext,
+        lljit::{
+            LLVMOrcCreateLLJIT, LLVMOrcDisposeLLJIT, LLVMOrcLLJITAddLLVMIRModule,
+            LLVMOrcLLJITGetGlobalPrefix, LLVMOrcLLJITGetMainJITDylib, LLVMOrcLLJITLookup,
+            LLVMOrcLLJITRef,
+        },
+    },
+    target_machine::LLVMDisposeTargetMachine,
+    transforms::pass_builder::{
+        LLVMCreatePassBuilderOptions, LLVMDisposePassBuilderOptions, LLVMRunPasses,
+    },
+};
+use mlir_sys::mlirTranslateModuleToLLVMIR;
+
+use crate::compile_unit_info::CompileUnitInfo;
+use crate::driver::timings::{self, Phase};
+use crate::ir::{IR, source_map::SourceMap};
+
+use super::{
+    context::Context, create_target_machine, errors::CodegenError, initialize_llvm, opt_level,
+};
+
+/// The code of a program, compiled in memory.
+pub struct Jit {
+    jit: LLVMOrcLLJITRef,
+}
+
+impl Jit {
+    /// Generates the code of the program and adds it to a new JIT.
+    pub fn new(session: &CompileUnitInfo, program: &IR) -> Result<Self, CodegenError> {
+        initialize_llvm();
+
+        let context = Context::new();
+        let source_map = SourceMap::from_ir(program);
+        let mlir_module =
+            context.compile(session, program, &program.top_level_modules, &source_map)?;
+        assert!(mlir_module.melior_module.as_operation().verify());
+
+        let machine = create_target_machine(session)?;
+
+        unsafe {
+            let mut jit = null_mut();
+            check(LLVMOrcCreateLLJIT(&mut jit, null_mut()))?;
+            let jit = Self { jit };
+            let dylib = LLVMOrcLLJITGetMainJITDylib(jit.jit);
+
+            let mut generator = null_mut();
+            check(LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess(
+                &mut generator,
+                LLVMOrcLLJITGetGlobalPrefix(jit.jit),
+                None,
+                null_mut(),
+            ))?;
+            LLVMOrcJITDylibAddGenerator(dylib, generator);
+
+            let thread_safe_context = LLVMOrcCreateNewThreadSafeContext();
+            let llvm_context = LLVMOrcThreadSafeContextGetContext(thread_safe_context);
+            let op = mlir_module.melior_module.as_operation().to_raw();
+            let llvm_module = mlirTranslateModuleToLLVMIR(op, llvm_context as *mut _) as *mut _;
+
+            let opts = LLVMCreatePassBuilderOptions();
+            let passes = CString::new(format!("default<O{}>", opt_level(session))).unwrap();
+            let phase = timings::phase(Phase::LlvmOpt);
+            let error = LLVMRunPasses(llvm_module, passes.as_ptr(), machine, opts);
+            drop(phase);
+            LLVMDisposePassBuilderOptions(opts);
+            LLVMDisposeTargetMachine(machine);
+
+            // The module owns the context from here on.
+            let module = LLVMOrcCreateNewThreadSafeModule(llvm_module, thread_safe_context);
+            LLVMOrcDisposeThreadSafeContext(thread_safe_context);
+
+            if let Err(error) = check(error) {
+                LLVMOrcDisposeThreadSafeModule(module);
+                return Err(error);
+            }
+            check(LLVMOrcLLJITAddLLVMIRModule(jit.jit, dylib, module))?;
+
+            Ok(jit)
+        }
+    }
+
+    /// The address of the function with the given symbol, compiling it if it wasn't yet.
+    pub fn lookup(&self, symbol: &str) -> Result<u64, CodegenError> {
+        let name = CString::new(symbol)
+            .map_err(|_| CodegenError::LLVMCompileError(format!("invalid symbol {symbol}")))?;
+        let mut address = 0;
+
+        unsafe {
+            check(LLVMOrcLLJITLookup(self.jit, &mut address, name.as_ptr()))?;
+        }
+
+        Ok(address)
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        unsafe {
+            let error = LLVMOrcDisposeLLJIT(self.jit);
+            if !error.is_null() {
+                LLVMConsumeError(error);
+            }
+        }
+    }
+}
+
+/// Turns the error of an ORC call into a codegen error.
+fn check(error: LLVMErrorRef) -> Result<(), CodegenError> {
+    if error.is_null() {
+        return Ok(());
+    }
+
+    unsafe {
+        let message = LLVMGetErrorMessage(error);
+        let err = CStr::from_ptr(message).to_string_lossy().into_owned();
+        LLVMDisposeErrorMessage(message);
+        tracing::error!("jit error: {}", err);
+        Err(CodegenError::LLVMCompileError(err))
+    }
+}
@@ -0,0 +1,306 @@
+//! Link time optimization of the compile units, compiled to bitcode by [`super::compile_units`].
+//!
+//! Full LTO links all the units into a single module, optimized as a whole and compiled to a single
+//! object. ThinLTO keeps a module for each unit, optimized and compiled in parallel, with the
+//! functions of the other units linked in as `available_externally` so they can be inlined, and
+//! dropped once optimized.
+
+use std::{
+    collections::HashSet,
+    ffi::{CStr, CString},
+    path::{Path, PathBuf},
+    ptr::null_mut,
+};
+
+use llvm_sys::{
+    LLVMLinkage,
+    bit_reader::LLVMParseBitcodeInContext2,
+    core::{
+        LLVMContextCreate, LLVMContextDispose, LLVMCreateMemoryBufferWithContentsOfFile,
+        LLVMDisposeMemoryBuffer, LLVMDisposeMessage, LLVMDisposeModule, LLVMGetFirstFunction,
+        LLVMGetFirstGlobal, LLVMGetLinkage, LLVMGetNextFunction, LLVMGetNextGlobal,
+        LLVMGetValueName2, LLVMIsDeclaration, LLVMSetLinkage,
+    },
+    error::LLVMGetErrorMessage,
+    linker::LLVMLinkModules2,
+    prelude::{LLVMContextRef, LLVMModuleRef, LLVMValueRef},
+    target_machine::{
+        LLVMCodeGenFileType, LLVMDisposeTargetMachine, LLVMTargetMachineEmitToFile,
+        LLVMTargetMachineRef,
+    },
+    transforms::pass_builder::{
+        LLVMCreatePassBuilderOptions, LLVMDisposePassBuilderOptions, LLVMRunPasses,
+    },
+};
+
+use crate::{
+    compile_unit_info::{CompileUnitInfo, Lto},
+    driver::parallel,
+};
+
+use super::{CodegenError, create_target_machine, opt_level};
+
+/// The objects [`link_time_optimize`] compiles the bitcode of the given number of compile units
+/// to.
+pub fn lto_object_paths(session: &CompileUnitInfo, units: usize) -> Vec<PathBuf> {
+    match session.lto {
+        Lto::Off => Vec::new(),
+        Lto::Full => vec![session.output_file.with_extension("lto.o")],
+        Lto::Thin => (0..units)
+            .map(|unit| session.output_file.with_extension(format!("{unit}.lto.o")))
+            .collect(),
+    }
+}
+
+/// Optimizes the bitcode of the compile units together and compiles it, returning the objects to
+/// link.
+pub fn link_time_optimize(
+    session: &CompileUnitInfo,
+    bitcode: &[PathBuf],
+) -> Result<Vec<PathBuf>, CodegenError> {
+    let objects = lto_object_paths(session, bitcode.len());
+
+    match session.lto {
+        Lto::Off => {}
+        Lto::Full => unsafe {
+            let context = LLVMContextCreate();
+            let module = link_bitcode(context, bitcode);
+            let result = module.and_then(|module| {
+                let result = optimize_and_emit(session, module, "lto", &objects[0]);
+                LLVMDisposeModule(module);
+                result
+            });
+            LLVMContextDispose(context);
+            result?;
+        },
+        Lto::Thin => {
+            let units: Vec<usize> = (0..bitcode.len()).collect();
+            parallel::map(&units, |unit| unsafe {
+                // Each thread links its modules in its own context, they can't be shared.
+                let context = LLVMContextCreate();
+                let result = thin_unit(session, context, bitcode, *unit, &objects[*unit]);
+                LLVMContextDispose(context);
+                result
+            })
+            .into_iter()
+            .collect::<Result<Vec<()>, _>>()?;
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Optimizes a compile unit with the functions of the others available to inline.
+unsafe fn thin_unit(
+    session: &CompileUnitInfo,
+    context: LLVMContextRef,
+    bitcode: &[PathBuf],
+    unit: usize,
+    object: &Path,
+) -> Result<(), CodegenError> {
+    unsafe {
+        let module = read_bitcode(context, &bitcode[unit])?;
+        let own = exported_definitions(module);
+
+        for (i, path) in bitcode.iter().enumerate() {
+            if i == unit {
+                continue;
+            }
+            let other = read_bitcode(context, path);
+            if let Err(error) = other.and_then(|other| link_module(module, other, path)) {
+                LLVMDisposeModule(module);
+                return Err(error);
+            }
+        }
+
+        // The definitions of the other units are only there to be inlined, their objects have
+        // them.
+        for value in definitions(module) {
+            if LLVMGetLinkage(value) == LLVMLinkage::LLVMExternalLinkage
+                && !own.contains(&value_name(value))
+            {
+                LLVMSetLinkage(value, LLVMLinkage::LLVMAvailableExternallyLinkage);
+            }
+        }
+
+        let result = optimize_and_emit(session, module, "thinlto", object);
+        LLVMDisposeModule(module);
+        result
+    }
+}
+
+/// Links the bitcode of all the compile units into a single module.
+unsafe fn link_bitcode(
+    context: LLVMContextRef,
+    bitcode: &[PathBuf],
+) -> Result<LLVMModuleRef, CodegenError> {
+    unsafe {
+        let Some((first, rest)) = bitcode.split_first() else {
+            return Err(CodegenError::LLVMCompileError(
+                "no bitcode to link".to_string(),
+            ));
+        };
+
+        let module = read_bitcode(context, first)?;
+        for path in rest {
+            let other = read_bitcode(context, path);
+            if let Err(error) = other.and_then(|other| link_module(module, other, path)) {
+                LLVMDisposeModule(module);
+                return Err(error);
+            }
+        }
+
+        Ok(module)
+    }
+}
+
+/// Links `other` into `module`, `other` is consumed.
+unsafe fn link_module(
+    module: LLVMModuleRef,
+    other: LLVMModuleRef,
+    path: &Path,
+) -> Result<(), CodegenError> {
+    unsafe {
+        if LLVMLinkModules2(module, other) != 0 {
+            return Err(CodegenError::LLVMCompileError(format!(
+                "failed to link the bitcode of {}",
+                path.display()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+unsafe fn read_bitcode(
+    context: LLVMContextRef,
+    path: &Path,
+) -> Result<LLVMModuleRef, CodegenError> {
+    let filename = CString::new(path.as_os_str().to_string_lossy().as_bytes()).unwrap();
+
+    unsafe {
+        let mut buffer = null_mut();
+        let mut message = null_mut();
+        if LLVMCreateMemoryBufferWithContentsOfFile(filename.as_ptr(), &mut buffer, &mut message)
+            != 0
+        {
+            let error = CStr::from_ptr(message).to_string_lossy().to_string();
+            LLVMDisposeMessage(message);
+            return Err(CodegenError::LLVMCompileError(format!(
+                "failed to read {}: {error}",
+                path.display()
+            )));
+        }
+
+        let mut module = null_mut();
+        let failed = LLVMParseBitcodeInContext2(context, buffer, &mut module) != 0;
+        LLVMDisposeMemoryBuffer(buffer);
+
+        if failed {
+            return Err(CodegenError::LLVMCompileError(format!(
+                "invalid bitcode in {}",
+                path.display()
+            )));
+        }
+
+        Ok(module)
+    }
+}
+
+/// The functions and global variables the module defines, not just declares.
+unsafe fn definitions(module: LLVMModuleRef) -> Vec<LLVMValueRef> {
+    let mut values = Vec::new();
+
+    unsafe {
+        let mut function = LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            if LLVMIsDeclaration(function) == 0 {
+                values.push(function);
+            }
+            function = LLVMGetNextFunction(function);
+        }
+
+        let mut global = LLVMGetFirstGlobal(module);
+        while !global.is_null() {
+            if LLVMIsDeclaration(global) == 0 {
+                values.push(global);
+            }
+            global = LLVMGetNextGlobal(global);
+        }
+    }
+
+    values
+}
+
+/// The names of the definitions of the module other modules can use.
+unsafe fn exported_definitions(module: LLVMModuleRef) -> HashSet<String> {
+    unsafe {
+        definitions(module)
+            .into_iter()
+            .filter(|x| LLVMGetLinkage(*x) == LLVMLinkage::LLVMExternalLinkage)
+            .map(|x| value_name(x))
+            .collect()
+    }
+}
+
+unsafe fn value_name(value: LLVMValueRef) -> String {
+    unsafe {
+        let mut length = 0;
+        let name = LLVMGetValueName2(value, &mut length);
+        let bytes = std::slice::from_raw_parts(name.cast::<u8>(), length);
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/// Runs the given LTO pipeline, `lto` or `thinlto`, on the module and compiles it to an object.
+unsafe fn optimize_and_emit(
+    session: &CompileUnitInfo,
+    module: LLVMModuleRef,
+    pipeline: &str,
+    object: &Path,
+) -> Result<(), CodegenError> {
+    let machine = create_target_machine(session)?;
+    let result = unsafe { run_passes_and_emit(session, machine, module, pipeline, object) };
+    unsafe { LLVMDisposeTargetMachine(machine) };
+    result
+}
+
+unsafe fn run_passes_and_emit(
+    session: &CompileUnitInfo,
+    machine: LLVMTargetMachineRef,
+    module: LLVMModuleRef,
+    pipeline: &str,
+    object: &Path,
+) -> Result<(), CodegenError> {
+    unsafe {
+        let opts = LLVMCreatePassBuilderOptions();
+        let passes = CString::new(format!("{pipeline}<O{}>", opt_level(session))).unwrap();
+        let error = LLVMRunPasses(module, passes.as_ptr(), machine, opts);
+        LLVMDisposePassBuilderOptions(opts);
+        if !error.is_null() {
+            let message = CStr::from_ptr(LLVMGetErrorMessage(error));
+            return Err(CodegenError::LLVMCompileError(
+                message.to_string_lossy().into_owned(),
+            ));
+        }
+
+        let filename = CString::new(object.as_os_str().to_string_lossy().as_bytes()).unwrap();
+        let mut message = null_mut();
+        if LLVMTargetMachineEmitToFile(
+            machine,
+            module,
+            filename.as_ptr().cast_mut(),
+            LLVMCodeGenFileType::LLVMObjectFile,
+            &mut message,
+        ) != 0
+        {
+            let error = CStr::from_ptr(message).to_string_lossy().to_string();
+            LLVMDisposeMessage(message);
+            return Err(CodegenError::LLVMCompileError(error));
+        } else if !message.is_null() {
+            LLVMDisposeMessage(message);
+        }
+    }
+
+    Ok(())
+}
@@ -1,32 +1,42 @@
 #![allow(clippy::too_many_arguments)]
 
 use std::{
-    ffi::{CStr, CString},
+    ffi::{CStr, CString, c_char},
     mem::MaybeUninit,
+    ops::Range,
     path::PathBuf,
-    ptr::{addr_of_mut, null_mut},
+    ptr::{addr_of_mut, null, null_mut},
     sync::OnceLock,
     time::Instant,
 };
 
-use crate::compile_unit_info::{CompileUnitInfo, OptLevel};
-use crate::ir::IR;
+use crate::check::suggestions::unknown_value_message;
+use crate::compile_unit_info::{CompileUnitInfo, Lto, OptLevel, Sanitizer, TargetInfo};
+use crate::driver::timings::{self, Phase};
+use crate::driver::{parallel, self_profile};
+use crate::ir::{IR, ModuleIndex, source_map::SourceMap};
 use context::Context;
 use errors::CodegenError;
 use llvm_sys::{
+    LLVMAttributeFunctionIndex,
+    bit_writer::LLVMWriteBitcodeToFile,
     core::{
-        LLVMContextCreate, LLVMContextDispose, LLVMDisposeMessage, LLVMDisposeModule,
-        LLVMPrintModuleToFile,
+        LLVMAddAttributeToFunction, LLVMContextCreate, LLVMContextDispose, LLVMCreateEnumAttribute,
+        LLVMDisposeMessage, LLVMDisposeModule, LLVMGetEnumAttributeKindForName,
+        LLVMGetFirstFunction, LLVMGetNextFunction, LLVMIsDeclaration, LLVMPrintModuleToFile,
     },
     error::LLVMGetErrorMessage,
+    prelude::{LLVMContextRef, LLVMModuleRef},
+    support::LLVMParseCommandLineOptions,
     target::{
         LLVM_InitializeAllAsmPrinters, LLVM_InitializeAllTargetInfos, LLVM_InitializeAllTargetMCs,
         LLVM_InitializeAllTargets,
     },
     target_machine::{
         LLVMCodeGenFileType, LLVMCodeGenOptLevel, LLVMCodeModel, LLVMCreateTargetMachine,
-        LLVMDisposeTargetMachine, LLVMGetDefaultTargetTriple, LLVMGetHostCPUFeatures,
-        LLVMGetHostCPUName, LLVMGetTargetFromTriple, LLVMRelocMode, LLVMTargetMachineEmitToFile,
+        LLVMDisposeTargetMachine, LLVMGetDefaultTargetTriple, LLVMGetFirstTarget,
+        LLVMGetHostCPUFeatures, LLVMGetHostCPUName, LLVMGetNextTarget, LLVMGetTargetFromTriple,
+        LLVMGetTargetName, LLVMRelocMode, LLVMTargetMachineEmitToFile, LLVMTargetMachineRef,
         LLVMTargetRef,
     },
     transforms::pass_builder::{
@@ -38,29 +48,106 @@ use module::MLIRModule;
 
 mod compiler;
 mod context;
+mod debug_info;
 pub mod errors;
+pub mod jit;
+mod lto;
 mod module;
+mod panic;
 mod pass_manager;
+mod ubsan;
+
+pub use lto::{link_time_optimize, lto_object_paths};
 
 /// Compiles the given program and returns the object file path.
 pub fn compile(session: &CompileUnitInfo, program: &IR) -> Result<PathBuf, CodegenError> {
-    static INITIALIZED: OnceLock<()> = OnceLock::new();
-    INITIALIZED.get_or_init(|| unsafe {
-        LLVM_InitializeAllTargets();
-        LLVM_InitializeAllTargetInfos();
-        LLVM_InitializeAllTargetMCs();
-        LLVM_InitializeAllAsmPrinters();
-        tracing::debug!("initialized llvm targets");
-    });
+    let source_map = SourceMap::from_ir(program);
+    compile_modules(session, program, &program.top_level_modules, &source_map)
+}
+
+/// Compiles the given compile units of the program, each to its own object, in parallel, and
+/// returns the object file paths, for the linker to combine them with the other units.
+pub fn compile_units(
+    session: &CompileUnitInfo,
+    program: &IR,
+    units: &[usize],
+) -> Result<Vec<PathBuf>, CodegenError> {
+    if program.compile_units.len() <= 1 {
+        return match units {
+            [] => Ok(Vec::new()),
+            _ => Ok(vec![compile(session, program)?]),
+        };
+    }
+
+    let source_map = SourceMap::from_ir(program);
+
+    parallel::map(units, |i| {
+        let mut session = session.clone();
+        session.output_file = unit_object_path(&session, program.compile_units.len(), *i);
+        compile_modules(&session, program, &program.compile_units[*i], &source_map)
+    })
+    .into_iter()
+    .collect()
+}
 
+/// The compile units generated into each object when there are at most `objects` of them, see
+/// `codegen_units` in the profiles: each object gets consecutive units, as many as the others or
+/// one more.
+pub fn codegen_unit_groups(units: usize, objects: usize) -> Vec<Range<usize>> {
+    let objects = objects.clamp(1, units.max(1));
+    let (size, extra) = (units / objects, units % objects);
+
+    let mut start = 0;
+    (0..objects)
+        .map(|i| {
+            let end = start + size + usize::from(i < extra);
+            let group = start..end;
+            start = end;
+            group
+        })
+        .collect()
+}
+
+/// The object the compile unit with the given index, out of the given number of units of the
+/// program, is compiled to, or its bitcode with LTO.
+pub fn unit_object_path(session: &CompileUnitInfo, units: usize, unit: usize) -> PathBuf {
+    let extension = match session.lto {
+        Lto::Off => "o",
+        Lto::Thin | Lto::Full => "bc",
+    };
+
+    if units <= 1 {
+        session.output_file.with_extension(extension)
+    } else {
+        session
+            .output_file
+            .with_extension(format!("{unit}.{extension}"))
+    }
+}
+
+/// Compiles the given top level modules of the program to an object and returns its path, the
+/// functions of the other modules are only declared.
+fn compile_modules(
+    session: &CompileUnitInfo,
+    program: &IR,
+    modules: &[ModuleIndex],
+    source_map: &SourceMap,
+) -> Result<PathBuf, CodegenError> {
+    initialize_llvm();
+
+    // Each thread generates code in its own context, they can't be shared.
     let context = Context::new();
     let compile_codegen_time = Instant::now();
-    let mlir_module = context.compile(session, program)?;
+    let span = self_profile::span("codegen", "generate MLIR");
+    let mlir_module = context.compile(session, program, modules, source_map)?;
+    drop(span);
     let compile_codegen_time = compile_codegen_time.elapsed();
     assert!(mlir_module.melior_module.as_operation().verify());
 
     let compile_llvm_time = Instant::now();
+    let span = self_profile::span("codegen", "compile LLVM IR to an object");
     let object_path = compile_to_object(session, &mlir_module)?;
+    drop(span);
     let compile_llvm_time = compile_llvm_time.elapsed();
     tracing::debug!("Codegen time {:?}", compile_codegen_time);
     tracing::debug!("Compile llvm time {:?}", compile_llvm_time);
@@ -68,46 +155,81 @@ pub fn compile(session: &CompileUnitInfo, program: &IR) -> Result<PathBuf, Codeg
     Ok(object_path)
 }
 
-pub fn get_target_triple(_session: &CompileUnitInfo) -> String {
-    // TODO: use session to get the specified target triple
-    let target_triple = unsafe {
-        let value = LLVMGetDefaultTargetTriple();
-        CStr::from_ptr(value).to_string_lossy().into_owned()
-    };
-    target_triple
+/// Initializes the LLVM targets, once for the whole process.
+fn initialize_llvm() {
+    static INITIALIZED: OnceLock<()> = OnceLock::new();
+    INITIALIZED.get_or_init(|| unsafe {
+        LLVM_InitializeAllTargets();
+        LLVM_InitializeAllTargetInfos();
+        LLVM_InitializeAllTargetMCs();
+        LLVM_InitializeAllAsmPrinters();
+        tracing::debug!("initialized llvm targets");
+    });
+}
+
+/// The target triple of the session, the host one if no target was given.
+pub fn get_target_triple(session: &CompileUnitInfo) -> String {
+    match &session.target.triple {
+        Some(triple) => triple.clone(),
+        None => unsafe {
+            let value = LLVMGetDefaultTargetTriple();
+            let triple = CStr::from_ptr(value).to_string_lossy().into_owned();
+            LLVMDisposeMessage(value);
+            triple
+        },
+    }
 }
 
 pub fn get_data_layout_rep(session: &CompileUnitInfo) -> Result<String, CodegenError> {
+    let machine = create_target_machine(session)?;
+
     unsafe {
-        let mut null = null_mut();
-        let error_buffer = addr_of_mut!(null);
+        let data_layout = llvm_sys::target_machine::LLVMCreateTargetDataLayout(machine);
+        let value = llvm_sys::target::LLVMCopyStringRepOfTargetData(data_layout);
+        let data_layout_str = CStr::from_ptr(value).to_string_lossy().into_owned();
 
-        let target_triple = LLVMGetDefaultTargetTriple();
+        LLVMDisposeMessage(value);
+        llvm_sys::target::LLVMDisposeTargetData(data_layout);
+        LLVMDisposeTargetMachine(machine);
 
-        let target_cpu = LLVMGetHostCPUName();
+        Ok(data_layout_str)
+    }
+}
 
-        let target_cpu_features = LLVMGetHostCPUFeatures();
+/// Creates the LLVM target machine of the session: for the host CPU and its features, or a generic
+/// CPU of the target triple when cross compiling, as the host ones may not exist there.
+fn create_target_machine(session: &CompileUnitInfo) -> Result<LLVMTargetMachineRef, CodegenError> {
+    let target_triple = CString::new(get_target_triple(session))
+        .map_err(|_| CodegenError::LLVMCompileError("invalid target triple".to_string()))?;
 
-        let mut target: MaybeUninit<LLVMTargetRef> = MaybeUninit::uninit();
+    unsafe {
+        let (target_cpu, target_cpu_features) = if session.target.is_host() {
+            let cpu = LLVMGetHostCPUName();
+            let features = LLVMGetHostCPUFeatures();
+            let owned = (
+                CStr::from_ptr(cpu).to_owned(),
+                CStr::from_ptr(features).to_owned(),
+            );
+            LLVMDisposeMessage(cpu);
+            LLVMDisposeMessage(features);
+            owned
+        } else {
+            (c"generic".to_owned(), c"".to_owned())
+        };
+        tracing::debug!("Target triple: {:?}", target_triple);
+        tracing::debug!("Target CPU: {:?}", target_cpu);
+        tracing::debug!("Target CPU Features: {:?}", target_cpu_features);
 
-        if LLVMGetTargetFromTriple(target_triple, target.as_mut_ptr(), error_buffer) != 0 {
-            let error = CStr::from_ptr(*error_buffer);
-            let err = error.to_string_lossy().to_string();
+        let target = get_target(&target_triple).map_err(|err| {
             tracing::error!("error getting target triple: {}", err);
-            LLVMDisposeMessage(*error_buffer);
-            Err(CodegenError::LLVMCompileError(err))?;
-        }
-        if !(*error_buffer).is_null() {
-            LLVMDisposeMessage(*error_buffer);
-        }
-
-        let target = target.assume_init();
+            unknown_target(&target_triple)
+        })?;
 
-        let machine = LLVMCreateTargetMachine(
+        Ok(LLVMCreateTargetMachine(
             target,
-            target_triple.cast(),
-            target_cpu.cast(),
-            target_cpu_features.cast(),
+            target_triple.as_ptr(),
+            target_cpu.as_ptr(),
+            target_cpu_features.as_ptr(),
             match session.optlevel {
                 OptLevel::None => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
                 OptLevel::Less => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
@@ -120,20 +242,79 @@ pub fn get_data_layout_rep(session: &CompileUnitInfo) -> Result<String, CodegenE
                 LLVMRelocMode::LLVMRelocDefault
             },
             LLVMCodeModel::LLVMCodeModelDefault,
-        );
+        ))
+    }
+}
 
-        let data_layout = llvm_sys::target_machine::LLVMCreateTargetDataLayout(machine);
-        let data_layout_str =
-            CStr::from_ptr(llvm_sys::target::LLVMCopyStringRepOfTargetData(data_layout));
-        Ok(data_layout_str.to_string_lossy().into_owned())
+/// Checks LLVM was built with the architecture of the target triple, if one was given, suggesting
+/// the closest one it was built with otherwise.
+pub fn check_target(target: &TargetInfo) -> Result<(), CodegenError> {
+    let Some(triple) = &target.triple else {
+        return Ok(());
+    };
+    let triple = CString::new(triple.as_str())
+        .map_err(|_| CodegenError::LLVMCompileError("invalid target triple".to_string()))?;
+
+    initialize_llvm();
+    match get_target(&triple) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(unknown_target(&triple)),
     }
 }
 
+/// The LLVM target of the triple, or the error of LLVM when it wasn't built with its architecture.
+fn get_target(triple: &CStr) -> Result<LLVMTargetRef, String> {
+    unsafe {
+        let mut error_buffer = null_mut();
+        let mut target: MaybeUninit<LLVMTargetRef> = MaybeUninit::uninit();
+
+        if LLVMGetTargetFromTriple(triple.as_ptr(), target.as_mut_ptr(), &mut error_buffer) != 0 {
+            let err = CStr::from_ptr(error_buffer).to_string_lossy().to_string();
+            LLVMDisposeMessage(error_buffer);
+            return Err(err);
+        } else if !error_buffer.is_null() {
+            LLVMDisposeMessage(error_buffer);
+        }
+
+        Ok(target.assume_init())
+    }
+}
+
+/// The error for a triple with an architecture LLVM wasn't built with, suggesting the closest one
+/// of those it was built with, named like in triples.
+fn unknown_target(triple: &CStr) -> CodegenError {
+    let triple = triple.to_string_lossy();
+    let arch = triple.split('-').next().unwrap_or_default();
+
+    // The targets are named like `x86-64`, the ones that aren't valid in a triple, like `x86` for
+    // `i686`, are left out.
+    let mut known = Vec::new();
+    unsafe {
+        let mut target = LLVMGetFirstTarget();
+        while !target.is_null() {
+            let name = CStr::from_ptr(LLVMGetTargetName(target))
+                .to_string_lossy()
+                .replace('-', "_");
+            let triple = CString::new(format!("{name}-unknown-unknown")).unwrap();
+            if get_target(&triple).is_ok() && !known.contains(&name) {
+                known.push(name);
+            }
+            target = LLVMGetNextTarget(target);
+        }
+    }
+
+    CodegenError::UnknownTarget(unknown_value_message(
+        "target",
+        arch,
+        known.iter().map(String::as_str),
+    ))
+}
+
 /// Converts a module to an object.
 /// The object will be written to the specified target path.
 /// TODO: error handling
 ///
-/// Returns the path to the object.
+/// Returns the path to the object, or to the bitcode with LTO.
 pub fn compile_to_object(
     session: &CompileUnitInfo,
     module: &MLIRModule<'_>,
@@ -143,8 +324,7 @@ pub fn compile_to_object(
     let target_file = session.output_file.with_extension("o");
     tracing::debug!("Target file: {:?}", target_file);
 
-    // TODO: Rework so you can specify target and host features, etc.
-    // Right now it compiles for the native cpu feature set and arch
+    let machine = create_target_machine(session)?;
 
     unsafe {
         let llvm_context = LLVMContextCreate();
@@ -156,61 +336,36 @@ pub fn compile_to_object(
         let mut null = null_mut();
         let mut error_buffer = addr_of_mut!(null);
 
-        let target_triple = LLVMGetDefaultTargetTriple();
-        tracing::debug!("Target triple: {:?}", CStr::from_ptr(target_triple));
-
-        let target_cpu = LLVMGetHostCPUName();
-        tracing::debug!("Target CPU: {:?}", CStr::from_ptr(target_cpu));
-
-        let target_cpu_features = LLVMGetHostCPUFeatures();
-        tracing::debug!(
-            "Target CPU Features: {:?}",
-            CStr::from_ptr(target_cpu_features)
-        );
-
-        let mut target: MaybeUninit<LLVMTargetRef> = MaybeUninit::uninit();
-
-        if LLVMGetTargetFromTriple(target_triple, target.as_mut_ptr(), error_buffer) != 0 {
-            let error = CStr::from_ptr(*error_buffer);
-            let err = error.to_string_lossy().to_string();
-            tracing::error!("error getting target triple: {}", err);
-            LLVMDisposeMessage(*error_buffer);
-            Err(CodegenError::LLVMCompileError(err))?;
-        } else if !(*error_buffer).is_null() {
-            LLVMDisposeMessage(*error_buffer);
-            error_buffer = addr_of_mut!(null);
-        }
-
-        let target = target.assume_init();
-
-        let machine = LLVMCreateTargetMachine(
-            target,
-            target_triple.cast(),
-            target_cpu.cast(),
-            target_cpu_features.cast(),
-            match session.optlevel {
-                OptLevel::None => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
-                OptLevel::Less => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
-                OptLevel::Default => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
-                OptLevel::Aggressive => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
-            },
-            if session.library {
-                LLVMRelocMode::LLVMRelocDynamicNoPic
-            } else {
-                LLVMRelocMode::LLVMRelocDefault
-            },
-            LLVMCodeModel::LLVMCodeModelDefault,
-        );
-
         let opts = LLVMCreatePassBuilderOptions();
-        let opt = match session.optlevel {
-            OptLevel::None => 0,
-            OptLevel::Less => 1,
-            OptLevel::Default => 2,
-            OptLevel::Aggressive => 3,
+        // With LTO the rest of the optimizations run once the units are linked together.
+        let pipeline = match session.lto {
+            Lto::Off => "default",
+            Lto::Thin => "thinlto-pre-link",
+            Lto::Full => "lto-pre-link",
         };
-        let passes = CString::new(format!("default<O{opt}>")).unwrap();
+        let mut passes = format!("{pipeline}<O{}>", opt_level(session));
+        if session.fuzzing {
+            enable_sanitizer_coverage();
+            // Instrumented after optimizing, like clang does, so the edges are the ones of the
+            // generated code.
+            passes.push_str(",sancov-module");
+        }
+        if session.sanitizers.contains(&Sanitizer::Address) {
+            // AddressSanitizer only instruments the functions with its attribute.
+            add_function_attribute(llvm_context, llvm_module, "sanitize_address");
+        }
+        for sanitizer in &session.sanitizers {
+            passes.push_str(match sanitizer {
+                Sanitizer::Address => ",asan",
+                // Traps on the accesses to memory it can tell are out of bounds, like clang's
+                // `-fsanitize=bounds`.
+                Sanitizer::Undefined => ",function(bounds-checking)",
+            });
+        }
+        let passes = CString::new(passes).unwrap();
+        let phase = timings::phase(Phase::LlvmOpt);
         let error = LLVMRunPasses(llvm_module as *mut _, passes.as_ptr(), machine, opts);
+        drop(phase);
         if !error.is_null() {
             let msg = LLVMGetErrorMessage(error);
             let msg = CStr::from_ptr(msg);
@@ -242,6 +397,26 @@ pub fn compile_to_object(
             }
         }
 
+        if session.lto != Lto::Off {
+            let bitcode_file = target_file.with_extension("bc");
+            let filename =
+                CString::new(bitcode_file.as_os_str().to_string_lossy().as_bytes()).unwrap();
+            let failed = LLVMWriteBitcodeToFile(llvm_module, filename.as_ptr()) != 0;
+
+            LLVMDisposeTargetMachine(machine);
+            LLVMDisposeModule(llvm_module);
+            LLVMContextDispose(llvm_context);
+
+            if failed {
+                Err(CodegenError::LLVMCompileError(format!(
+                    "failed to write the bitcode to {}",
+                    bitcode_file.display()
+                )))?;
+            }
+            return Ok(bitcode_file);
+        }
+
+        let _phase = timings::phase(Phase::Codegen);
         let filename = CString::new(target_file.as_os_str().to_string_lossy().as_bytes()).unwrap();
         tracing::debug!("filename to llvm: {:?}", filename);
         let ok = LLVMTargetMachineEmitToFile(
@@ -297,3 +472,55 @@ pub fn compile_to_object(
         Ok(target_file)
     }
 }
+
+/// The number of the optimization level of the session, like in `-O2`.
+fn opt_level(session: &CompileUnitInfo) -> u8 {
+    match session.optlevel {
+        OptLevel::None => 0,
+        OptLevel::Less => 1,
+        OptLevel::Default => 2,
+        OptLevel::Aggressive => 3,
+    }
+}
+
+/// Adds the given attribute to the functions defined in the module.
+unsafe fn add_function_attribute(
+    llvm_context: LLVMContextRef,
+    llvm_module: LLVMModuleRef,
+    name: &str,
+) {
+    unsafe {
+        let kind = LLVMGetEnumAttributeKindForName(name.as_ptr() as *const c_char, name.len());
+        let attribute = LLVMCreateEnumAttribute(llvm_context, kind, 0);
+
+        let mut function = LLVMGetFirstFunction(llvm_module);
+        while !function.is_null() {
+            if LLVMIsDeclaration(function) == 0 {
+                LLVMAddAttributeToFunction(function, LLVMAttributeFunctionIndex, attribute);
+            }
+            function = LLVMGetNextFunction(function);
+        }
+    }
+}
+
+/// Sets the options of the sanitizer coverage pass to the ones libFuzzer expects, they are global to
+/// LLVM so they're only parsed once.
+fn enable_sanitizer_coverage() {
+    static PARSED: OnceLock<()> = OnceLock::new();
+    PARSED.get_or_init(|| {
+        let args: Vec<CString> = [
+            "concrete",
+            "-sanitizer-coverage-level=4",
+            "-sanitizer-coverage-inline-8bit-counters",
+            "-sanitizer-coverage-pc-table",
+            "-sanitizer-coverage-trace-compares",
+        ]
+        .into_iter()
+        .map(|x| CString::new(x).unwrap())
+        .collect();
+        let argv: Vec<*const c_char> = args.iter().map(|x| x.as_ptr()).collect();
+
+        unsafe { LLVMParseCommandLineOptions(argv.len() as i32, argv.as_ptr(), null()) };
+        tracing::debug!("enabled sanitizer coverage");
+    });
+}
@@ -0,0 +1,200 @@
+//! The panic routines the failed checks of the program call, which print where and why it failed
+//! to the standard error and abort.
+//!
+//! Each compile unit with checks defines them, with `linkonce_odr` linkage for the linker to keep
+//! one. They print with libc, the failed checks of programs without it only trap.
+
+use melior::{
+    Context as MeliorContext,
+    dialect::{
+        func,
+        llvm::{
+            self,
+            r#type::{array, function, pointer},
+        },
+        ods,
+    },
+    helpers::{ArithBlockExt, BuiltinBlockExt, LlvmBlockExt},
+    ir::{
+        Attribute, Block, BlockLike, Identifier, Location, Region, RegionLike, Type, Value,
+        attribute::{
+            ArrayAttribute, DenseI32ArrayAttribute, FlatSymbolRefAttribute, StringAttribute,
+            TypeAttribute,
+        },
+        operation::OperationBuilder,
+        r#type::{FunctionType, IntegerType},
+    },
+};
+
+use super::{compiler::CodegenCtx, errors::CodegenError};
+
+/// Prints `{location}: {message}` and aborts, both are C strings.
+pub(crate) const PANIC: &str = "__concrete_panic";
+
+/// Prints the location, a C string, with the out of bounds index and the length it's checked
+/// against, both `u64`s, and aborts.
+pub(crate) const PANIC_BOUNDS: &str = "__concrete_panic_bounds";
+
+/// Defines the panic routines in the module, and declares the libc functions they call unless the
+/// program already does.
+pub(crate) fn define_panic_routines(ctx: CodegenCtx) -> Result<(), CodegenError> {
+    let context = ctx.mlir_context;
+    let location = Location::unknown(context);
+    let ptr_ty = pointer(context, 0);
+    let i64_ty: Type = IntegerType::new(context, 64).into();
+
+    if !has_symbol(ctx, "dprintf") {
+        ctx.mlir_module.body().append_operation(llvm::func(
+            context,
+            StringAttribute::new(context, "dprintf"),
+            TypeAttribute::new(dprintf_type(context)),
+            Region::new(),
+            &[],
+            location,
+        ));
+    }
+    if !has_symbol(ctx, "abort") {
+        ctx.mlir_module.body().append_operation(func::func(
+            context,
+            StringAttribute::new(context, "abort"),
+            TypeAttribute::new(FunctionType::new(context, &[], &[]).into()),
+            Region::new(),
+            &[(
+                Identifier::new(context, "sym_visibility"),
+                StringAttribute::new(context, "private").into(),
+            )],
+            location,
+        ));
+    }
+
+    define_panic_routine(ctx, PANIC, &[ptr_ty, ptr_ty], "%s: %s\n")?;
+    define_panic_routine(
+        ctx,
+        PANIC_BOUNDS,
+        &[ptr_ty, i64_ty, i64_ty],
+        "%s: index out of bounds: the index is %llu but the length is %llu\n",
+    )?;
+
+    Ok(())
+}
+
+/// Defines a panic routine, which prints its arguments with the format and aborts.
+fn define_panic_routine(
+    ctx: CodegenCtx,
+    name: &str,
+    params: &[Type],
+    format: &str,
+) -> Result<(), CodegenError> {
+    let context = ctx.mlir_context;
+    let location = Location::unknown(context);
+    let region = Region::new();
+    let params_with_location: Vec<_> = params.iter().map(|x| (*x, location)).collect();
+    let block = region.append_block(Block::new(&params_with_location));
+
+    let format = c_string(context, &block, location, format)?;
+    let stderr = block.const_int(context, location, 2, 32)?;
+    let mut args = vec![stderr, format];
+    for i in 0..params.len() {
+        args.push(block.arg(i)?);
+    }
+
+    block.append_operation(
+        OperationBuilder::new("llvm.call", location)
+            .add_attributes(&[
+                (
+                    Identifier::new(context, "callee"),
+                    FlatSymbolRefAttribute::new(context, "dprintf").into(),
+                ),
+                (
+                    Identifier::new(context, "var_callee_type"),
+                    TypeAttribute::new(dprintf_type(context)).into(),
+                ),
+                (
+                    Identifier::new(context, "op_bundle_sizes"),
+                    DenseI32ArrayAttribute::new(context, &[]).into(),
+                ),
+                (
+                    Identifier::new(context, "operandSegmentSizes"),
+                    DenseI32ArrayAttribute::new(context, &[args.len() as i32, 0]).into(),
+                ),
+            ])
+            .add_operands(&args)
+            .add_results(&[IntegerType::new(context, 32).into()])
+            .build()?,
+    );
+    block.append_operation(func::call(
+        context,
+        FlatSymbolRefAttribute::new(context, "abort"),
+        &[],
+        &[],
+        location,
+    ));
+    block.append_operation(llvm::unreachable(location));
+
+    // They only run once the program failed, they're kept out of the way of the code calling them.
+    let passthrough: Vec<Attribute> = ["cold", "noinline", "noreturn"]
+        .into_iter()
+        .map(|x| StringAttribute::new(context, x).into())
+        .collect();
+    ctx.mlir_module.body().append_operation(func::func(
+        context,
+        StringAttribute::new(context, name),
+        TypeAttribute::new(FunctionType::new(context, params, &[]).into()),
+        region,
+        &[
+            (
+                Identifier::new(context, "llvm.linkage"),
+                Attribute::parse(context, "#llvm.linkage<linkonce_odr>").unwrap(),
+            ),
+            (
+                Identifier::new(context, "passthrough"),
+                ArrayAttribute::new(context, &passthrough).into(),
+            ),
+        ],
+        location,
+    ));
+
+    Ok(())
+}
+
+/// Stores the text with a null byte on the stack, returning its pointer.
+pub(crate) fn c_string<'c, 'b>(
+    context: &'c MeliorContext,
+    block: &'b Block<'c>,
+    location: Location<'c>,
+    value: &str,
+) -> Result<Value<'c, 'b>, CodegenError> {
+    let ty = array(IntegerType::new(context, 8).into(), value.len() as u32 + 1);
+    let constant = block.append_op_result(
+        ods::llvm::mlir_constant(
+            context,
+            ty,
+            StringAttribute::new(context, &format!("{value}\0")).into(),
+            location,
+        )
+        .into(),
+    )?;
+    let ptr = block.alloca1(context, location, ty, 1)?;
+    block.store(context, location, ptr, constant)?;
+
+    Ok(ptr)
+}
+
+/// The type of `int dprintf(int fd, const char *format, ...)`.
+fn dprintf_type(context: &MeliorContext) -> Type {
+    function(
+        IntegerType::new(context, 32).into(),
+        &[IntegerType::new(context, 32).into(), pointer(context, 0)],
+        true,
+    )
+}
+
+/// Whether the program has a function with the symbol, like the libc functions it can declare
+/// itself, every module of the program declares it.
+fn has_symbol(ctx: CodegenCtx, name: &str) -> bool {
+    ctx.program
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .any(|x| x.get_mangled_name() == name)
+}
@@ -0,0 +1,123 @@
+//! The reports of the undefined behavior found by the checks of `--sanitize undefined`, made by the
+//! runtime of UndefinedBehaviorSanitizer, `libclang_rt.ubsan_standalone`, linked with the binary.
+//!
+//! The failed checks call its handlers with the data clang gives them, the source location and the
+//! type of the operation. It's stored on the stack rather than in globals: the handlers end the
+//! program, so each report is only made once.
+
+use melior::{
+    Context as MeliorContext,
+    dialect::{
+        func,
+        llvm::{
+            self,
+            r#type::{array, pointer},
+        },
+        ods,
+    },
+    helpers::{ArithBlockExt, BuiltinBlockExt, LlvmBlockExt},
+    ir::{
+        Block, BlockLike, Identifier, Location, Region, Type, Value, ValueLike,
+        attribute::{StringAttribute, TypeAttribute},
+        r#type::{FunctionType, IntegerType},
+    },
+};
+
+use super::{compiler::CodegenCtx, errors::CodegenError};
+
+/// Reports a division or a remainder by zero, or of the minimum of a signed integer by -1. Takes
+/// the data, with the type of the operands, then the dividend and the divisor.
+pub(crate) const DIVREM_OVERFLOW: &str = "__ubsan_handle_divrem_overflow_abort";
+
+/// Reports an access through a null or misaligned pointer. Takes the data, with the pointee type,
+/// its alignment and the kind of access, then the address.
+pub(crate) const TYPE_MISMATCH: &str = "__ubsan_handle_type_mismatch_v1_abort";
+
+/// The kind of the descriptor of an integer type, its info is the log2 of its width and whether
+/// it's signed.
+pub(crate) const TYPE_KIND_INTEGER: u16 = 0;
+
+/// The kind of the descriptor of a type the runtime only reports the name of.
+pub(crate) const TYPE_KIND_UNKNOWN: u16 = 0xffff;
+
+/// The kind of access of a type mismatch reading through the pointer.
+pub(crate) const TYPE_CHECK_LOAD: u8 = 0;
+
+/// The kind of access of a type mismatch writing through the pointer.
+pub(crate) const TYPE_CHECK_STORE: u8 = 1;
+
+/// Declares the handlers of the runtime the failed checks call.
+pub(crate) fn declare_handlers(ctx: CodegenCtx) {
+    let context = ctx.mlir_context;
+    let ptr_ty = pointer(context, 0);
+    let i64_ty: Type = IntegerType::new(context, 64).into();
+
+    for (name, params) in [
+        (DIVREM_OVERFLOW, &[ptr_ty, i64_ty, i64_ty][..]),
+        (TYPE_MISMATCH, &[ptr_ty, i64_ty]),
+    ] {
+        ctx.mlir_module.body().append_operation(func::func(
+            context,
+            StringAttribute::new(context, name),
+            TypeAttribute::new(FunctionType::new(context, params, &[]).into()),
+            Region::new(),
+            &[(
+                Identifier::new(context, "sym_visibility"),
+                StringAttribute::new(context, "private").into(),
+            )],
+            Location::unknown(context),
+        ));
+    }
+}
+
+/// Stores the descriptor of the type the handlers report: its kind, its info and its name.
+pub(crate) fn type_descriptor<'c, 'b>(
+    context: &'c MeliorContext,
+    block: &'b Block<'c>,
+    location: Location<'c>,
+    (kind, info): (u16, u16),
+    name: &str,
+) -> Result<Value<'c, 'b>, CodegenError> {
+    // The name is quoted like in the descriptors of clang.
+    let name = format!("'{name}'\0");
+    let name_ty = array(IntegerType::new(context, 8).into(), name.len() as u32);
+    let name = block.append_op_result(
+        ods::llvm::mlir_constant(
+            context,
+            name_ty,
+            StringAttribute::new(context, &name).into(),
+            location,
+        )
+        .into(),
+    )?;
+
+    handler_data(
+        context,
+        block,
+        location,
+        &[
+            block.const_int(context, location, kind, 16)?,
+            block.const_int(context, location, info, 16)?,
+            name,
+        ],
+    )
+}
+
+/// Stores the fields of the data of a handler or of a type descriptor, returning its pointer. The
+/// data of the handlers starts with the `SourceLocation`: the file name as a C string, the line and
+/// the column.
+pub(crate) fn handler_data<'c, 'b>(
+    context: &'c MeliorContext,
+    block: &'b Block<'c>,
+    location: Location<'c>,
+    fields: &[Value<'c, 'b>],
+) -> Result<Value<'c, 'b>, CodegenError> {
+    let field_types: Vec<Type> = fields.iter().map(|x| x.r#type()).collect();
+    let ty = llvm::r#type::r#struct(context, &field_types, false);
+    let data = block.append_op_result(llvm::undef(ty, location))?;
+    let data = block.insert_values(context, location, data, fields)?;
+    let ptr = block.alloca1(context, location, ty, 8)?;
+    block.store(context, location, ptr, data)?;
+
+    Ok(ptr)
+}
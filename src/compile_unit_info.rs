@@ -20,7 +20,21 @@ pub struct CompileUnitInfo {
     pub output_ll: bool,
     /// Whether to output the generated assembly file for this compile unit.
     pub output_asm: bool,
-    // todo: include target, host, etc
+    /// Whether to instrument the code with the sanitizer coverage libFuzzer is guided by.
+    pub fuzzing: bool,
+    /// Whether to leave out the bounds checks of indexing arrays and slices, for release builds
+    /// that can't afford them.
+    pub unchecked_indexing: bool,
+    /// The platform to generate code for.
+    pub target: TargetInfo,
+    /// Whether to optimize the compile units together when linking them.
+    pub lto: Lto,
+    /// The sanitizers the code is instrumented with, see `concrete build --sanitize`.
+    pub sanitizers: Vec<Sanitizer>,
+    /// Whether each block counts the times it runs, see `concrete cov report`.
+    pub coverage: bool,
+    /// Whether the program is linked without libc, see `concrete build --freestanding`.
+    pub freestanding: bool,
 }
 
 impl CompileUnitInfo {
@@ -33,6 +47,81 @@ impl CompileUnitInfo {
             "so"
         }
     }
+
+    /// Whether the program is linked with libc, which the failed checks print their panic with.
+    /// Freestanding and `wasm32` programs don't have it, their failed checks only trap.
+    pub fn has_libc(&self) -> bool {
+        !self.freestanding && self.target.kind != TargetKind::Wasm32
+    }
+}
+
+/// The platform the code is generated for, the host unless a target triple is given with
+/// `--target`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TargetInfo {
+    /// The target triple, like `aarch64-unknown-linux-gnu`, `None` for the host.
+    pub triple: Option<String>,
+    pub kind: TargetKind,
+}
+
+/// The kind of objects and artifacts a target produces, which decides how they are linked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TargetKind {
+    /// Native objects, linked into executables and shared libraries.
+    #[default]
+    Native,
+    /// WebAssembly without an operating system, linked into a `.wasm` module for JS hosts.
+    Wasm32,
+    /// WebAssembly with the WASI system interface, linked into a `.wasm` module for WASI runtimes.
+    Wasi,
+}
+
+impl TargetInfo {
+    /// The target with the given triple, `wasm32` and `wasi` are short for `wasm32-unknown-unknown`
+    /// and `wasm32-wasi`.
+    pub fn new(triple: Option<String>) -> Self {
+        let triple = triple.map(|x| match x.as_str() {
+            "wasm32" => "wasm32-unknown-unknown".to_string(),
+            "wasi" => "wasm32-wasi".to_string(),
+            _ => x,
+        });
+
+        let kind = match triple.as_deref() {
+            Some(x) if x.starts_with("wasm32") && x.contains("wasi") => TargetKind::Wasi,
+            Some(x) if x.starts_with("wasm32") => TargetKind::Wasm32,
+            _ => TargetKind::Native,
+        };
+
+        Self { triple, kind }
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.triple.is_none()
+    }
+
+    pub fn is_wasm(&self) -> bool {
+        matches!(self.kind, TargetKind::Wasm32 | TargetKind::Wasi)
+    }
+
+    /// The extension of the shared libraries of the target.
+    pub fn library_ext(&self) -> &'static str {
+        match &self.triple {
+            _ if self.is_wasm() => "wasm",
+            None => CompileUnitInfo::get_platform_library_ext(),
+            Some(triple) if triple.contains("apple") || triple.contains("darwin") => "dylib",
+            Some(triple) if triple.contains("windows") => "dll",
+            Some(_) => "so",
+        }
+    }
+
+    /// The extension of the executables of the target, if they have one.
+    pub fn binary_ext(&self) -> Option<&'static str> {
+        match &self.triple {
+            _ if self.is_wasm() => Some("wasm"),
+            None => cfg!(target_os = "windows").then_some("exe"),
+            Some(triple) => triple.contains("windows").then_some("exe"),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Hash)]
@@ -43,6 +132,45 @@ pub enum OptLevel {
     Aggressive, // -O3
 }
 
+/// Link time optimization: the compile units are compiled to bitcode, optimized together, and only
+/// then compiled to objects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Lto {
+    #[default]
+    Off,
+    /// Each compile unit is optimized on its own, in parallel, with the functions of the others
+    /// it can inline.
+    Thin,
+    /// The compile units are merged into a single module, optimized as a whole.
+    Full,
+}
+
+/// A sanitizer, instrumenting the code to find bugs while it runs.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, clap::ValueEnum, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Sanitizer {
+    /// AddressSanitizer: out of bounds accesses, uses after free and leaks, it needs its runtime
+    /// linked with the binary.
+    Address,
+    /// UndefinedBehaviorSanitizer: divisions by zero and null or misaligned raw pointers are
+    /// reported by its runtime, linked with the binary, and out of bounds indexes and accesses
+    /// and integer overflows panic, even where they aren't checked otherwise, like with
+    /// `--unchecked-indexing` or at `-O3`.
+    Undefined,
+}
+
+impl Sanitizer {
+    /// The name of the sanitizer, like in `-fsanitize=address`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sanitizer::Address => "address",
+            Sanitizer::Undefined => "undefined",
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Hash)]
 pub enum DebugInfo {
     None,
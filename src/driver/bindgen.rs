@@ -0,0 +1,481 @@
+//! Generates Concrete declarations from C headers, so C libraries can be called without writing
+//! their `extern fn` declarations by hand.
+//!
+//! The header is parsed with libclang, loaded at runtime, into [`CDecl`]s, which are then printed
+//! as a Concrete module.
+
+// The libclang constants keep their C names.
+#![allow(non_upper_case_globals)]
+
+use std::{
+    ffi::{CStr, CString, c_void},
+    fmt::Write as _,
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use clang_sys::*;
+
+/// A C type, as far as Concrete can represent it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CType {
+    Void,
+    Bool,
+    Int {
+        signed: bool,
+        bits: u32,
+    },
+    Float {
+        bits: u32,
+    },
+    Pointer {
+        pointee: Box<CType>,
+        is_const: bool,
+    },
+    Array {
+        of: Box<CType>,
+        len: u64,
+    },
+    /// A struct, typedef or enum declared in the header.
+    Named(String),
+    /// A type Concrete has no equivalent for, with its C spelling.
+    Unsupported(String),
+}
+
+/// A declaration found in a C header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CDecl {
+    Function {
+        name: String,
+        params: Vec<(String, CType)>,
+        ret: CType,
+        is_variadic: bool,
+    },
+    Typedef {
+        name: String,
+        ty: CType,
+    },
+    Struct {
+        name: String,
+        fields: Vec<(String, CType)>,
+    },
+    Enum {
+        name: String,
+        ty: CType,
+        variants: Vec<(String, i64)>,
+    },
+}
+
+/// Words that can't be used as identifiers in Concrete, names using them get a `_` appended.
+const KEYWORDS: &[&str] = &[
+    "let", "const", "fn", "return", "struct", "union", "enum", "impl", "if", "else", "while",
+    "defer", "for", "match", "mod", "pub", "mut", "import", "extern", "as", "self", "trait",
+    "type", "true", "false", "unsafe", "async", "await", "become",
+];
+
+fn ident(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("{name}_")
+    } else {
+        name.to_string()
+    }
+}
+
+impl CType {
+    /// The Concrete spelling of the type, or why it can't be represented.
+    fn to_concrete(&self) -> Result<String, String> {
+        Ok(match self {
+            CType::Void => "()".to_string(),
+            CType::Bool => "bool".to_string(),
+            CType::Int { signed, bits } => {
+                format!("{}{}", if *signed { "i" } else { "u" }, bits)
+            }
+            CType::Float { bits } => format!("f{bits}"),
+            CType::Pointer { pointee, is_const } => {
+                let pointee = match pointee.as_ref() {
+                    // Concrete has no void, untyped pointers point to bytes.
+                    CType::Void => "u8".to_string(),
+                    other => other.to_concrete()?,
+                };
+                format!("*{} {}", if *is_const { "const" } else { "mut" }, pointee)
+            }
+            CType::Array { of, len } => format!("[{}; {}]", of.to_concrete()?, len),
+            CType::Named(name) => ident(name),
+            CType::Unsupported(spelling) => return Err(spelling.clone()),
+        })
+    }
+}
+
+/// Prints the declarations as a Concrete module with the given name.
+///
+/// Declarations using C features Concrete can't represent, like variadic functions, are skipped
+/// with a comment explaining why.
+pub fn generate_module(module_name: &str, decls: &[CDecl]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "// Generated by `concrete bindgen`, do not edit.");
+    let _ = writeln!(out, "mod {} {{", ident(module_name));
+
+    for (i, decl) in decls.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+
+        if let Err(reason) = generate_decl(&mut out, decl) {
+            let _ = writeln!(out, "    // skipped `{}`: {}", decl.name(), reason);
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+fn generate_decl(out: &mut String, decl: &CDecl) -> Result<(), String> {
+    match decl {
+        CDecl::Function {
+            name,
+            params,
+            ret,
+            is_variadic,
+        } => {
+            if *is_variadic {
+                return Err("variadic functions are not supported".to_string());
+            }
+
+            let params = params
+                .iter()
+                .enumerate()
+                .map(|(i, (name, ty))| {
+                    let name = if name.is_empty() {
+                        format!("arg{i}")
+                    } else {
+                        ident(name)
+                    };
+                    Ok(format!("{}: {}", name, unsupported(ty)?))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let ret = match ret {
+                CType::Void => String::new(),
+                ret => format!(" -> {}", unsupported(ret)?),
+            };
+
+            let _ = writeln!(
+                out,
+                "    pub extern fn {}({}){};",
+                ident(name),
+                params.join(", "),
+                ret
+            );
+        }
+        CDecl::Typedef { name, ty } => {
+            let _ = writeln!(out, "    pub type {} = {};", ident(name), unsupported(ty)?);
+        }
+        CDecl::Struct { name, fields } => {
+            let fields = fields
+                .iter()
+                .map(|(name, ty)| {
+                    Ok(format!(
+                        "        pub {}: {},",
+                        ident(name),
+                        unsupported(ty)?
+                    ))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let _ = writeln!(out, "    #[repr = \"C\"]");
+            let _ = writeln!(out, "    pub struct {} {{", ident(name));
+            for field in fields {
+                let _ = writeln!(out, "{field}");
+            }
+            let _ = writeln!(out, "    }}");
+        }
+        CDecl::Enum { name, ty, variants } => {
+            let ty = unsupported(ty)?;
+            let _ = writeln!(out, "    pub type {} = {};", ident(name), ty);
+            for (variant, value) in variants {
+                let _ = writeln!(out, "    pub const {}: {} = {};", ident(variant), ty, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn unsupported(ty: &CType) -> Result<String, String> {
+    ty.to_concrete()
+        .map_err(|spelling| format!("the C type `{spelling}` is not supported"))
+}
+
+impl CDecl {
+    pub fn name(&self) -> &str {
+        match self {
+            CDecl::Function { name, .. }
+            | CDecl::Typedef { name, .. }
+            | CDecl::Struct { name, .. }
+            | CDecl::Enum { name, .. } => name,
+        }
+    }
+}
+
+/// Parses the declarations of the header with libclang, ignoring the ones from included headers.
+pub fn parse_header(path: &Path, clang_args: &[String]) -> Result<Vec<CDecl>> {
+    if !is_loaded() {
+        clang_sys::load()
+            .map_err(|error| anyhow::anyhow!(error))
+            .context("failed to load libclang, set LIBCLANG_PATH to the directory containing it")?;
+    }
+
+    let path = CString::new(path.to_string_lossy().as_bytes())?;
+    let args = clang_args
+        .iter()
+        .map(|x| CString::new(x.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let arg_ptrs: Vec<_> = args.iter().map(|x| x.as_ptr()).collect();
+
+    // SAFETY: the strings outlive the translation unit, which is disposed before returning.
+    unsafe {
+        let index = clang_createIndex(0, 1);
+        let unit = clang_parseTranslationUnit(
+            index,
+            path.as_ptr(),
+            arg_ptrs.as_ptr(),
+            arg_ptrs.len() as i32,
+            std::ptr::null_mut(),
+            0,
+            CXTranslationUnit_SkipFunctionBodies,
+        );
+
+        if unit.is_null() {
+            clang_disposeIndex(index);
+            bail!("libclang failed to parse {}", path.to_string_lossy());
+        }
+
+        let errors = diagnostics_errors(unit);
+        let mut decls = Vec::new();
+
+        if errors.is_empty() {
+            clang_visitChildren(
+                clang_getTranslationUnitCursor(unit),
+                visit_top_level,
+                &mut decls as *mut Vec<CDecl> as *mut c_void,
+            );
+        }
+
+        clang_disposeTranslationUnit(unit);
+        clang_disposeIndex(index);
+
+        if !errors.is_empty() {
+            bail!("failed to parse the header:\n{}", errors.join("\n"));
+        }
+
+        Ok(decls)
+    }
+}
+
+unsafe fn diagnostics_errors(unit: CXTranslationUnit) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    unsafe {
+        for i in 0..clang_getNumDiagnostics(unit) {
+            let diagnostic = clang_getDiagnostic(unit, i);
+
+            if clang_getDiagnosticSeverity(diagnostic) >= CXDiagnostic_Error {
+                errors.push(to_string(clang_formatDiagnostic(
+                    diagnostic,
+                    clang_defaultDiagnosticDisplayOptions(),
+                )));
+            }
+
+            clang_disposeDiagnostic(diagnostic);
+        }
+    }
+
+    errors
+}
+
+/// Takes ownership of a libclang string.
+unsafe fn to_string(string: CXString) -> String {
+    unsafe {
+        let ptr = clang_getCString(string);
+        let result = if ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        };
+        clang_disposeString(string);
+        result
+    }
+}
+
+unsafe fn is_from_main_file(cursor: CXCursor) -> bool {
+    unsafe { clang_Location_isFromMainFile(clang_getCursorLocation(cursor)) != 0 }
+}
+
+extern "C" fn visit_top_level(
+    cursor: CXCursor,
+    _parent: CXCursor,
+    data: CXClientData,
+) -> CXChildVisitResult {
+    // SAFETY: the data is the declarations vector given to `clang_visitChildren`.
+    unsafe {
+        let decls = &mut *(data as *mut Vec<CDecl>);
+
+        if !is_from_main_file(cursor) {
+            return CXChildVisit_Continue;
+        }
+
+        let name = to_string(clang_getCursorSpelling(cursor));
+
+        match clang_getCursorKind(cursor) {
+            CXCursor_FunctionDecl => {
+                let params = (0..clang_Cursor_getNumArguments(cursor).max(0))
+                    .map(|i| {
+                        let arg = clang_Cursor_getArgument(cursor, i as u32);
+                        (
+                            to_string(clang_getCursorSpelling(arg)),
+                            convert_type(clang_getCursorType(arg)),
+                        )
+                    })
+                    .collect();
+
+                decls.push(CDecl::Function {
+                    name,
+                    params,
+                    ret: convert_type(clang_getCursorResultType(cursor)),
+                    is_variadic: clang_isFunctionTypeVariadic(clang_getCursorType(cursor)) != 0,
+                });
+            }
+            CXCursor_TypedefDecl => {
+                let ty = convert_type(clang_getTypedefDeclUnderlyingType(cursor));
+
+                // `typedef struct Foo Foo;` names the struct the same way in Concrete.
+                if ty != CType::Named(name.clone()) {
+                    decls.push(CDecl::Typedef { name, ty });
+                }
+            }
+            CXCursor_StructDecl if clang_isCursorDefinition(cursor) != 0 && !name.is_empty() => {
+                let mut fields: Vec<(String, CType)> = Vec::new();
+                clang_visitChildren(
+                    cursor,
+                    visit_field,
+                    &mut fields as *mut Vec<(String, CType)> as *mut c_void,
+                );
+                decls.push(CDecl::Struct { name, fields });
+            }
+            CXCursor_EnumDecl if clang_isCursorDefinition(cursor) != 0 && !name.is_empty() => {
+                let mut variants: Vec<(String, i64)> = Vec::new();
+                clang_visitChildren(
+                    cursor,
+                    visit_enum_constant,
+                    &mut variants as *mut Vec<(String, i64)> as *mut c_void,
+                );
+                decls.push(CDecl::Enum {
+                    name,
+                    ty: convert_type(clang_getEnumDeclIntegerType(cursor)),
+                    variants,
+                });
+            }
+            _ => {}
+        }
+
+        CXChildVisit_Continue
+    }
+}
+
+extern "C" fn visit_field(
+    cursor: CXCursor,
+    _parent: CXCursor,
+    data: CXClientData,
+) -> CXChildVisitResult {
+    // SAFETY: the data is the fields vector given to `clang_visitChildren`.
+    unsafe {
+        let fields = &mut *(data as *mut Vec<(String, CType)>);
+
+        if clang_getCursorKind(cursor) == CXCursor_FieldDecl {
+            let ty = if clang_Cursor_isBitField(cursor) != 0 {
+                CType::Unsupported("bit field".to_string())
+            } else {
+                convert_type(clang_getCursorType(cursor))
+            };
+            fields.push((to_string(clang_getCursorSpelling(cursor)), ty));
+        }
+
+        CXChildVisit_Continue
+    }
+}
+
+extern "C" fn visit_enum_constant(
+    cursor: CXCursor,
+    _parent: CXCursor,
+    data: CXClientData,
+) -> CXChildVisitResult {
+    // SAFETY: the data is the variants vector given to `clang_visitChildren`.
+    unsafe {
+        let variants = &mut *(data as *mut Vec<(String, i64)>);
+
+        if clang_getCursorKind(cursor) == CXCursor_EnumConstantDecl {
+            variants.push((
+                to_string(clang_getCursorSpelling(cursor)),
+                clang_getEnumConstantDeclValue(cursor),
+            ));
+        }
+
+        CXChildVisit_Continue
+    }
+}
+
+unsafe fn convert_type(ty: CXType) -> CType {
+    unsafe {
+        let bits = || (clang_Type_getSizeOf(ty).max(0) * 8) as u32;
+
+        match ty.kind {
+            CXType_Void => CType::Void,
+            CXType_Bool => CType::Bool,
+            CXType_Char_U | CXType_UChar | CXType_UShort | CXType_UInt | CXType_ULong
+            | CXType_ULongLong | CXType_UInt128 => CType::Int {
+                signed: false,
+                bits: bits(),
+            },
+            // Concrete strings are bytes, so plain C chars are unsigned.
+            CXType_Char_S => CType::Int {
+                signed: false,
+                bits: 8,
+            },
+            CXType_SChar | CXType_Short | CXType_Int | CXType_Long | CXType_LongLong
+            | CXType_Int128 => CType::Int {
+                signed: true,
+                bits: bits(),
+            },
+            CXType_Float | CXType_Double => CType::Float { bits: bits() },
+            CXType_Pointer => {
+                let pointee = clang_getPointeeType(ty);
+                CType::Pointer {
+                    is_const: clang_isConstQualifiedType(pointee) != 0,
+                    pointee: Box::new(convert_type(pointee)),
+                }
+            }
+            CXType_ConstantArray => CType::Array {
+                of: Box::new(convert_type(clang_getArrayElementType(ty))),
+                len: clang_getArraySize(ty).max(0) as u64,
+            },
+            CXType_Elaborated => convert_type(clang_Type_getNamedType(ty)),
+            CXType_Typedef | CXType_Record | CXType_Enum => {
+                let decl = clang_getTypeDeclaration(ty);
+
+                // Types from other headers, like `size_t`, are replaced by what they stand for.
+                let name = to_string(clang_getCursorSpelling(decl));
+
+                if is_from_main_file(decl) && !name.is_empty() {
+                    CType::Named(name)
+                } else if ty.kind == CXType_Typedef {
+                    convert_type(clang_getCanonicalType(ty))
+                } else if ty.kind == CXType_Enum {
+                    convert_type(clang_getEnumDeclIntegerType(decl))
+                } else {
+                    CType::Unsupported(to_string(clang_getTypeSpelling(ty)))
+                }
+            }
+            _ => CType::Unsupported(to_string(clang_getTypeSpelling(ty))),
+        }
+    }
+}
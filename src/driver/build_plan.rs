@@ -0,0 +1,213 @@
+//! The build plan printed by `concrete build --build-plan`: the steps the driver would run to build
+//! the package, as JSON, so other build systems can run them or track their inputs and outputs
+//! without building it.
+//!
+//! The compile units of a program are lowered together, so they're compiled by a single step with
+//! an object for each of them. It's followed by the link time optimization of the objects if the
+//! profile asks for it, and by the steps linking them.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::{
+    ast::{
+        CompilationUnit,
+        modules::{Module, ModuleDefItem},
+    },
+    codegen::{codegen_unit_groups, lto_object_paths, unit_object_path},
+    compile_unit_info::{Lto, TargetInfo},
+};
+
+use super::{
+    CompilerArgs,
+    config::{DependencyOrigin, LibKind},
+};
+
+/// The steps of a build, each one after the ones it depends on.
+#[derive(Debug, Default, Serialize)]
+pub struct BuildPlan {
+    pub invocations: Vec<Invocation>,
+}
+
+/// A step of the build.
+#[derive(Debug, Serialize)]
+pub struct Invocation {
+    /// The package of the program built.
+    pub package: String,
+    pub kind: InvocationKind,
+    /// The name of the profile the program is built with.
+    pub profile: String,
+    /// The target triple the program is built for, `None` for the host.
+    pub target: Option<String>,
+    /// The indexes of the invocations that must run before this one.
+    pub deps: Vec<usize>,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+    /// The compile units of a compile step, the ones of the dependencies first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub units: Vec<UnitPlan>,
+    /// The linker of a link step, the default one of the target if `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linker: Option<String>,
+    /// The arguments passed to the linker after the objects.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// The directories the linked program looks for shared libraries in.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rpath: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvocationKind {
+    /// Checks the program and compiles its compile units.
+    Compile,
+    /// Optimizes the bitcode of the compile units together.
+    Lto,
+    /// Archives the objects into a static library.
+    Archive,
+    /// Links the objects into a binary or a shared library.
+    Link,
+}
+
+/// A compile unit compiled by a compile step.
+#[derive(Debug, Serialize)]
+pub struct UnitPlan {
+    /// The package the unit belongs to.
+    pub package: String,
+    /// The version of the package, `None` for the package being built.
+    pub version: Option<String>,
+    /// The source files of its modules.
+    pub sources: Vec<PathBuf>,
+    /// The object the unit is compiled to, or its bitcode with LTO, shared with the units merged
+    /// with it by `codegen_units`.
+    pub object: PathBuf,
+}
+
+impl BuildPlan {
+    /// Adds the steps building the program of the compile units, like [`super::compile`] and
+    /// [`super::link`] would.
+    pub fn add_program(
+        &mut self,
+        args: &CompilerArgs,
+        units: &[CompilationUnit],
+        package: &str,
+        profile: &str,
+        dependencies: &[DependencyOrigin],
+    ) {
+        let session = args.compile_unit_info();
+        let invocation = |kind, deps, inputs, outputs| Invocation {
+            package: package.to_string(),
+            kind,
+            profile: profile.to_string(),
+            target: args.target.clone(),
+            deps,
+            inputs,
+            outputs,
+            units: Vec::new(),
+            linker: None,
+            args: Vec::new(),
+            rpath: Vec::new(),
+        };
+
+        // The units merged by `codegen_units` share their object.
+        let groups = match args.codegen_units {
+            Some(objects) if units.len() > objects => codegen_unit_groups(units.len(), objects),
+            _ => (0..units.len()).map(|x| x..x + 1).collect(),
+        };
+        let unit_object = |i: usize| {
+            let group = groups.iter().position(|x| x.contains(&i)).unwrap_or(i);
+            unit_object_path(&session, groups.len(), group)
+        };
+
+        let units: Vec<UnitPlan> = units
+            .iter()
+            .enumerate()
+            .map(|(i, unit)| {
+                let origin = unit
+                    .modules
+                    .first()
+                    .and_then(|x| DependencyOrigin::innermost(dependencies, &x.file_path));
+                let mut sources = Vec::new();
+                for module in &unit.modules {
+                    module_sources(module, &mut sources);
+                }
+
+                UnitPlan {
+                    package: origin.map_or(package, |x| &x.name).to_string(),
+                    version: origin.and_then(|x| x.version.clone()),
+                    sources,
+                    object: unit_object(i),
+                }
+            })
+            .collect();
+
+        let mut inputs: Vec<PathBuf> = Vec::new();
+        for source in units.iter().flat_map(|x| &x.sources) {
+            if !inputs.contains(source) {
+                inputs.push(source.clone());
+            }
+        }
+        let mut objects: Vec<PathBuf> = Vec::new();
+        for unit in &units {
+            if !objects.contains(&unit.object) {
+                objects.push(unit.object.clone());
+            }
+        }
+
+        let mut compile = invocation(InvocationKind::Compile, Vec::new(), inputs, objects.clone());
+        compile.units = units;
+        self.invocations.push(compile);
+        let mut last = self.invocations.len() - 1;
+
+        if session.lto != Lto::Off {
+            let lto_objects = lto_object_paths(&session, objects.len());
+            self.invocations.push(invocation(
+                InvocationKind::Lto,
+                vec![last],
+                objects,
+                lto_objects.clone(),
+            ));
+            last = self.invocations.len() - 1;
+            objects = lto_objects;
+        }
+
+        if args.library && args.lib_kinds.contains(&LibKind::Static) {
+            self.invocations.push(invocation(
+                InvocationKind::Archive,
+                vec![last],
+                objects.clone(),
+                vec![args.output.with_extension("a")],
+            ));
+        }
+        if args.library && !args.lib_kinds.contains(&LibKind::Shared) {
+            return;
+        }
+
+        // Shared libraries for the host get their extension when linked if they have none.
+        let target = TargetInfo::new(args.target.clone());
+        let output = match args.library && target.is_host() && args.output.extension().is_none() {
+            true => args.output.with_extension(target.library_ext()),
+            false => args.output.clone(),
+        };
+        let options = args.link_options();
+        let mut link = invocation(InvocationKind::Link, vec![last], objects, vec![output]);
+        link.linker = options.linker;
+        link.args = options.args;
+        link.rpath = options.rpath;
+        self.invocations.push(link);
+    }
+}
+
+fn module_sources(module: &Module, sources: &mut Vec<PathBuf>) {
+    if !sources.contains(&module.file_path) {
+        sources.push(module.file_path.clone());
+    }
+
+    for item in &module.contents {
+        if let ModuleDefItem::Module(submodule) = item {
+            module_sources(submodule, sources);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! The build scripts of the packages: a `build.con` next to the `Concrete.toml` of a package is
+//! compiled for the host and run before its sources are parsed, like the `build.rs` of a crate.
+//!
+//! The script prints directives to its stdout, the other lines are ignored:
+//!
+//! - `concrete:link-lib=<name>` links the program with a native library, like `-l<name>`.
+//! - `concrete:link-search=<dir>` looks for native libraries in the directory, relative to the
+//!   package.
+//! - `concrete:cfg=<name>` and `concrete:cfg=<name>="<value>"` set a cfg option of the package,
+//!   checked with `#[cfg(<name>)]` and `#[cfg(<name> = "<value>")]`.
+//! - `concrete:warning=<message>` prints a warning.
+//!
+//! It runs in the directory of its package, with the directory of `CONCRETE_OUT_DIR`,
+//! `build/generated/<package>`, to write sources to: the modules declared with `mod name;` that
+//! aren't found next to the file declaring them are looked up there. The script is compiled again
+//! when one of its files, the compiler or the std of the sysroot changes.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, bail};
+use owo_colors::OwoColorize;
+
+use super::{
+    CompilerArgs, DiagnosticArgs, LintArgs, compile, config::LibKind, incremental::collect_files,
+    link, parse_file, sources::SourceExtensions, sysroot::Sysroot, watch::Snapshot,
+};
+use crate::{
+    ast::cfg::Cfg, check::suggestions::unknown_value_message, compile_unit_info::Lto,
+    ir::lowering::is_builtin_cfg,
+};
+
+/// The file of the build script, next to the `Concrete.toml` of its package.
+pub const BUILD_SCRIPT: &str = "build.con";
+
+/// The prefix of the lines of the output of a script that are directives.
+const DIRECTIVE_PREFIX: &str = "concrete:";
+
+/// The directives printed by a build script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Directives {
+    pub link_libs: Vec<String>,
+    pub lib_dirs: Vec<PathBuf>,
+    /// The cfg options set, with their values, none for the ones set by name alone.
+    pub cfgs: BTreeMap<String, BTreeSet<String>>,
+    pub warnings: Vec<String>,
+}
+
+/// What the build script of a package left for its build.
+#[derive(Debug, Clone)]
+pub struct ScriptOutput {
+    pub cfgs: BTreeMap<String, BTreeSet<String>>,
+    /// The directory the script wrote its sources to.
+    pub generated_dir: PathBuf,
+}
+
+/// Builds and runs the build scripts of the packages, keeping the native libraries they link.
+#[derive(Debug)]
+pub struct BuildScripts {
+    /// The directory the scripts are built in, each in the one of its package.
+    scripts_dir: PathBuf,
+    /// The directory the scripts write their sources to, each in the one of its package.
+    generated_dir: PathBuf,
+    sysroot: Option<Sysroot>,
+    /// The options of the diagnostics of the build, whose emitter reports the ones of the scripts.
+    diagnostics: DiagnosticArgs,
+    /// The native libraries the scripts link the program with.
+    pub link_libs: Vec<String>,
+    /// The directories the scripts look for native libraries in.
+    pub lib_dirs: Vec<PathBuf>,
+}
+
+impl BuildScripts {
+    /// The build scripts of a build into the given directory, built with the std of the sysroot.
+    pub fn new(build_dir: &Path, sysroot: Option<Sysroot>, diagnostics: DiagnosticArgs) -> Self {
+        Self {
+            scripts_dir: build_dir.join("scripts"),
+            generated_dir: build_dir.join("generated"),
+            sysroot,
+            diagnostics,
+            link_libs: Vec::new(),
+            lib_dirs: Vec::new(),
+        }
+    }
+
+    /// Builds and runs the build script of the package in the directory, if it has one. `name`
+    /// tells its directories apart from the ones of the other packages.
+    pub fn run(
+        &mut self,
+        package_dir: &Path,
+        name: &str,
+        cfg: &Cfg,
+    ) -> Result<Option<ScriptOutput>> {
+        let script = package_dir.join(BUILD_SCRIPT);
+        if !script.is_file() {
+            return Ok(None);
+        }
+
+        let binary = self.scripts_dir.join(name).join("build");
+        if is_outdated(&binary, self.sysroot.as_ref()) {
+            self.build(&script, &binary)?;
+        }
+
+        let generated_dir = self.generated_dir.join(name);
+        std::fs::create_dir_all(&generated_dir)
+            .with_context(|| format!("failed to create {}", generated_dir.display()))?;
+
+        eprintln!(
+            "     {} {} ({})",
+            "Running".green().bold(),
+            BUILD_SCRIPT,
+            script.display()
+        );
+        let output = Command::new(&binary)
+            .current_dir(package_dir)
+            .env("CONCRETE_OUT_DIR", &generated_dir)
+            .env("CONCRETE_PACKAGE_NAME", name)
+            .env("CONCRETE_TARGET_OS", &cfg.target_os)
+            .env("CONCRETE_TARGET_ARCH", &cfg.target_arch)
+            .env("CONCRETE_TARGET_FAMILY", &cfg.target_family)
+            .env("CONCRETE_PROFILE", &cfg.profile)
+            .env(
+                "CONCRETE_FEATURES",
+                cfg.features.iter().cloned().collect::<Vec<_>>().join(" "),
+            )
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("failed to run the build script {}", script.display()))?;
+        if !output.status.success() {
+            bail!(
+                "the build script {} failed with {}",
+                script.display(),
+                output.status
+            );
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let directives = parse_directives(&stdout, package_dir)
+            .with_context(|| format!("in the output of {}", script.display()))?;
+        for warning in &directives.warnings {
+            eprintln!("{}: {warning}", "warning".yellow().bold());
+        }
+        for lib in directives.link_libs {
+            if !self.link_libs.contains(&lib) {
+                self.link_libs.push(lib);
+            }
+        }
+        self.lib_dirs.extend(directives.lib_dirs);
+
+        Ok(Some(ScriptOutput {
+            cfgs: directives.cfgs,
+            generated_dir,
+        }))
+    }
+
+    /// Compiles the script into a binary for the host.
+    fn build(&self, script: &Path, binary: &Path) -> Result<()> {
+        let dir = binary.parent().context("the binary has no directory")?;
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+
+        eprintln!(
+            "   {} {} ({})",
+            "Compiling".green().bold(),
+            BUILD_SCRIPT,
+            script.display()
+        );
+        let mut unit = parse_file(script.to_path_buf(), &SourceExtensions::default())?;
+        unit.cfg = Cfg::default();
+
+        let args = CompilerArgs {
+            input: script.to_path_buf(),
+            output: binary.to_path_buf(),
+            release: false,
+            optlevel: None,
+            debug_info: None,
+            library: false,
+            target: None,
+            freestanding: false,
+            entry: None,
+            unchecked_indexing: false,
+            sanitize: Vec::new(),
+            coverage: false,
+            ast: false,
+            ir: false,
+            llvm: false,
+            asm: false,
+            object: false,
+            js_bindings: false,
+            emit_header: false,
+            emit: Vec::new(),
+            mlir: false,
+            check: false,
+            lints: LintArgs::default(),
+            diagnostics: self.diagnostics.clone(),
+            dependencies: Vec::new(),
+            symbol_map: false,
+            plugins: Arc::default(),
+            fuzz_target: None,
+            fingerprint: None,
+            link_libs: Vec::new(),
+            lib_dirs: Vec::new(),
+            lib_kinds: vec![LibKind::Shared],
+            linker: None,
+            linker_args: Vec::new(),
+            rpath: Vec::new(),
+            lto: Lto::Off,
+            codegen_units: None,
+            doc_tests: false,
+            sysroot: self.sysroot.clone(),
+            cache: None,
+            package_units: None,
+            prebuilt_dependencies: None,
+        };
+        let mut files = BTreeSet::new();
+        for module in &unit.modules {
+            collect_files(module, &mut files);
+        }
+
+        let (objects, _, libs) = compile(&args, &[unit])?;
+        link(&args, &objects, &libs)?;
+        for object in objects {
+            std::fs::remove_file(object)?;
+        }
+
+        let inputs: String = files.iter().map(|x| format!("{}\n", x.display())).collect();
+        let path = inputs_path(binary);
+        std::fs::write(&path, inputs)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// The file listing the source files the binary was built from, one per line.
+fn inputs_path(binary: &Path) -> PathBuf {
+    binary.with_extension("inputs")
+}
+
+/// Whether the binary of a script has to be built again: it isn't there, or it's older than one of
+/// the files it was built from, the compiler or the std of the sysroot.
+pub fn is_outdated(binary: &Path, sysroot: Option<&Sysroot>) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|x| x.modified()).ok();
+    let Some(built) = modified(binary) else {
+        return true;
+    };
+    let Ok(inputs) = std::fs::read_to_string(inputs_path(binary)) else {
+        return true;
+    };
+
+    // A file that's gone is outdated too, like a module the script no longer finds.
+    let newer = |time: Option<SystemTime>| time.is_none_or(|x| x > built);
+    let compiler = std::env::current_exe().ok();
+    inputs.lines().any(|x| newer(modified(Path::new(x))))
+        || compiler.is_some_and(|x| newer(modified(&x)))
+        || sysroot.is_some_and(|x| {
+            Snapshot::take(&x.std_dir(), &SourceExtensions::default())
+                .modified()
+                .any(newer)
+        })
+}
+
+/// The directives in the output of a build script, the directories relative to its package.
+pub fn parse_directives(output: &str, package_dir: &Path) -> Result<Directives> {
+    let mut directives = Directives::default();
+
+    for line in output.lines() {
+        let Some(directive) = line.trim_end().strip_prefix(DIRECTIVE_PREFIX) else {
+            continue;
+        };
+        let Some((name, value)) = directive.split_once('=') else {
+            bail!(
+                "the directive `{line}` has no value, write it `{DIRECTIVE_PREFIX}<name>=<value>`"
+            );
+        };
+
+        match name {
+            "link-lib" if !value.is_empty() => directives.link_libs.push(value.to_string()),
+            "link-search" if !value.is_empty() => directives.lib_dirs.push(package_dir.join(value)),
+            "cfg" => {
+                let (option, value) = match value.split_once('=') {
+                    Some((option, value)) => {
+                        let value = value
+                            .strip_prefix('"')
+                            .and_then(|x| x.strip_suffix('"'))
+                            .with_context(|| {
+                                format!("the value of the cfg option in `{line}` isn't quoted")
+                            })?;
+                        (option, Some(value))
+                    }
+                    None => (value, None),
+                };
+                if option.is_empty()
+                    || !option
+                        .chars()
+                        .all(|x| x.is_ascii_alphanumeric() || x == '_')
+                {
+                    bail!("`{option}` in `{line}` isn't a valid cfg option name");
+                }
+                if is_builtin_cfg(option) {
+                    bail!("the cfg option `{option}` in `{line}` is set by the compiler");
+                }
+
+                let values = directives.cfgs.entry(option.to_string()).or_default();
+                values.extend(value.map(str::to_string));
+            }
+            "warning" => directives.warnings.push(value.to_string()),
+            "link-lib" | "link-search" => bail!("the directive `{line}` has an empty value"),
+            name => bail!(
+                "{}",
+                unknown_value_message(
+                    "directive",
+                    name,
+                    ["link-lib", "link-search", "cfg", "warning"]
+                )
+            ),
+        }
+    }
+
+    Ok(directives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_scripts_are_built_again_when_their_modules_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("build");
+        let module = dir.path().join("helpers.con");
+        std::fs::write(&module, "mod helpers {}").unwrap();
+        assert!(is_outdated(&binary, None));
+
+        // Built after its files, from the ones listed next to it.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&binary, "").unwrap();
+        assert!(is_outdated(&binary, None), "the inputs aren't listed");
+        std::fs::write(
+            binary.with_extension("inputs"),
+            format!("{}\n", module.display()),
+        )
+        .unwrap();
+        assert!(!is_outdated(&binary, None));
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(&module, "mod helpers { fn f() {} }").unwrap();
+        assert!(is_outdated(&binary, None));
+
+        std::fs::write(&binary, "").unwrap();
+        std::fs::remove_file(&module).unwrap();
+        assert!(is_outdated(&binary, None));
+    }
+}
@@ -0,0 +1,214 @@
+//! C header of a library, so C and C++ programs can call its public functions when linking with
+//! the shared library built by `concrete build --lib`.
+//!
+//! Functions are declared under their symbol names, like `_C1_4Math3addh...`, with a comment naming
+//! the Concrete function. Structs are only seen through pointers, as opaque types.
+
+use std::{collections::BTreeSet, fmt::Write as _};
+
+use crate::ir::{FloatTy, Function, IR, IntTy, Mutability, Type, TypeIndex, UintTy};
+
+/// A declared function.
+#[derive(Debug)]
+struct Declaration {
+    name: String,
+    symbol: String,
+    params: Vec<(String, String)>,
+    ret: String,
+}
+
+/// Generates the header of the public functions of the library, with its include guard named
+/// after the library.
+///
+/// Functions that can't be called from C, like the ones taking structs by value, are skipped with
+/// a comment explaining why.
+pub fn generate_header(ir: &IR, library: &str) -> String {
+    let mut declarations = Vec::new();
+    let mut skipped = Vec::new();
+    let mut opaque = BTreeSet::new();
+
+    let functions = ir
+        .functions
+        .iter()
+        .filter(|(id, _)| !ir.tests.contains(id) && !ir.property_tests.contains(id))
+        .filter_map(|(_, function)| function.as_ref())
+        .filter(|function| {
+            function.is_pub && !function.is_extern && function.is_intrinsic.is_none()
+        });
+
+    for function in functions {
+        match declaration(ir, function, &mut opaque) {
+            Ok(declaration) => declarations.push(declaration),
+            Err(reason) => skipped.push((debug_name(function).to_string(), reason)),
+        }
+    }
+
+    declarations.sort_by(|a, b| a.name.cmp(&b.name));
+    skipped.sort();
+
+    let guard = format!(
+        "{}_H",
+        library
+            .chars()
+            .map(|x| match x.is_ascii_alphanumeric() {
+                true => x.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect::<String>()
+    );
+
+    let mut out = String::new();
+    let _ = writeln!(out, "// Generated by concrete, do not edit.");
+    for (name, reason) in &skipped {
+        let _ = writeln!(out, "// skipped `{name}`: {reason}");
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(out, "#ifndef {guard}");
+    let _ = writeln!(out, "#define {guard}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "#include <stdbool.h>");
+    let _ = writeln!(out, "#include <stdint.h>");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "#ifdef __cplusplus");
+    let _ = writeln!(out, "extern \"C\" {{");
+    let _ = writeln!(out, "#endif");
+
+    if !opaque.is_empty() {
+        let _ = writeln!(out);
+        for name in &opaque {
+            let _ = writeln!(out, "typedef struct {name} {name};");
+        }
+    }
+
+    for declaration in &declarations {
+        let params = match declaration.params.as_slice() {
+            [] => "void".to_string(),
+            params => params
+                .iter()
+                .map(|(name, ty)| declarator(ty, name))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        let _ = writeln!(out);
+        let _ = writeln!(out, "// {}", declaration.name);
+        let _ = writeln!(
+            out,
+            "{}({});",
+            declarator(&declaration.ret, &declaration.symbol),
+            params
+        );
+    }
+
+    let _ = writeln!(out);
+    let _ = writeln!(out, "#ifdef __cplusplus");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out, "#endif");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "#endif // {guard}");
+    out
+}
+
+/// Writes the name after its type, next to the `*` of pointers.
+fn declarator(ty: &str, name: &str) -> String {
+    match ty.ends_with('*') {
+        true => format!("{ty}{name}"),
+        false => format!("{ty} {name}"),
+    }
+}
+
+fn debug_name(function: &Function) -> &str {
+    function.debug_name.as_deref().unwrap_or(&function.name)
+}
+
+fn declaration(
+    ir: &IR,
+    function: &Function,
+    opaque: &mut BTreeSet<String>,
+) -> Result<Declaration, String> {
+    let params = function
+        .get_params()
+        .into_iter()
+        .enumerate()
+        .map(|(i, param)| {
+            let name = param
+                .debug_name
+                .clone()
+                .unwrap_or_else(|| format!("arg{i}"));
+
+            match c_type(ir, param.ty, opaque) {
+                Some(ty) if ty != "void" => Ok((name, ty)),
+                _ => Err(format!(
+                    "the parameter `{name}` can't be passed from C, only numbers, booleans and pointers can"
+                )),
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let ret = c_type(ir, function.ret_ty, opaque).ok_or_else(|| {
+        "the return type can't be passed to C, only numbers, booleans and pointers can".to_string()
+    })?;
+
+    // The mangled names of types are escaped with `$`, which C doesn't allow in identifiers.
+    if function.name.contains('$') {
+        return Err(
+            "its symbol isn't a C identifier, give it one with `#[export_name]`".to_string(),
+        );
+    }
+
+    Ok(Declaration {
+        name: debug_name(function).to_string(),
+        symbol: function.name.clone(),
+        params,
+        ret,
+    })
+}
+
+/// The C type of a value, `None` if it can't be passed by value to or from C.
+fn c_type(ir: &IR, ty: TypeIndex, opaque: &mut BTreeSet<String>) -> Option<String> {
+    Some(match ir.types[ty].as_ref()? {
+        Type::Unit => "void".to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Char => "char".to_string(),
+        Type::Int(ty) => match ty {
+            IntTy::I8 => "int8_t",
+            IntTy::I16 => "int16_t",
+            IntTy::I32 => "int32_t",
+            IntTy::I64 => "int64_t",
+            IntTy::I128 => return None,
+        }
+        .to_string(),
+        Type::Uint(ty) => match ty {
+            UintTy::U8 => "uint8_t",
+            UintTy::U16 => "uint16_t",
+            UintTy::U32 => "uint32_t",
+            UintTy::U64 => "uint64_t",
+            UintTy::U128 => return None,
+        }
+        .to_string(),
+        Type::Float(FloatTy::F32) => "float".to_string(),
+        Type::Float(FloatTy::F64) => "double".to_string(),
+        Type::Ref(of, mutability) | Type::Ptr(of, mutability) => {
+            let pointee = match ir.types[*of].as_ref()? {
+                Type::Adt(idx) => {
+                    let name = ir.aggregates[*idx].as_ref()?.name.clone();
+                    opaque.insert(name.clone());
+                    name
+                }
+                _ => match c_type(ir, *of, opaque) {
+                    Some(ty) => ty,
+                    None => "void".to_string(),
+                },
+            };
+
+            match (pointee.ends_with('*'), mutability) {
+                (false, Mutability::Not) => format!("const {pointee} *"),
+                (false, Mutability::Mut) => format!("{pointee} *"),
+                (true, Mutability::Not) => format!("{pointee}const *"),
+                (true, Mutability::Mut) => format!("{pointee}*"),
+            }
+        }
+        Type::String | Type::Array(..) | Type::Slice(_) | Type::Adt(_) | Type::Tuple(_) => {
+            return None;
+        }
+    })
+}
@@ -0,0 +1,183 @@
+//! The global cache, in the `cache` directory of `CONCRETE_HOME` (`~/.concrete/cache` by default),
+//! shared by the builds of all the projects of the user:
+//!
+//! ```text
+//! cache/git/db/<repository>-<hash>                        a clone of the repository of each git dependency
+//! cache/units/<triple>/<package>-<hash>/<commit>-<hash>.o  the compile units of the git dependencies
+//! ```
+//!
+//! The checkouts of a project in `.bricks` are cloned from the clones in the cache, so a
+//! repository is only downloaded once, and fetched again only when a build needs commits it doesn't
+//! have. The objects of the compile units of git dependencies are kept for each source, commit and
+//! target, named after the hash of what they're generated from, which covers the profile, so the
+//! builds of other projects generating the same code reuse them instead of compiling them again.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use git2::{Repository, build::RepoBuilder};
+
+use super::{
+    config::{DependencyOrigin, DependencySource},
+    sysroot::home_dir,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+    pub dir: PathBuf,
+}
+
+impl Cache {
+    /// The cache in `CONCRETE_HOME`, `None` if there's no home directory.
+    pub fn locate() -> Option<Self> {
+        home_dir().map(|x| Self {
+            dir: x.join("cache"),
+        })
+    }
+
+    /// The clone of the repository in the cache.
+    pub fn git_db(&self, url: &str) -> PathBuf {
+        let name = url
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .rsplit(['/', ':'])
+            .next()
+            .unwrap_or_default();
+        let source = DependencySource::Git {
+            url: url.to_string(),
+            r#ref: None,
+        };
+
+        self.dir
+            .join("git")
+            .join("db")
+            .join(format!("{name}-{}", source.short_hash()))
+    }
+
+    /// Where the compile unit of the dependency with the given hash is kept for the target, with
+    /// the extension of the object or bitcode it's compiled to. `None` unless it's a git
+    /// dependency, the sources at a path can change without a commit. The hash is the one of
+    /// [`shared_unit_hashes`](super::incremental::shared_unit_hashes), so the programs using the
+    /// same code of the dependency share its object.
+    pub fn dependency_unit(
+        &self,
+        origin: &DependencyOrigin,
+        triple: &str,
+        hash: u64,
+        extension: &str,
+    ) -> Option<PathBuf> {
+        let DependencySource::Git { .. } = &origin.resolved_source else {
+            return None;
+        };
+        let commit = Repository::open(&origin.dir).ok()?.head().ok()?.target()?;
+
+        Some(
+            self.dir
+                .join("units")
+                .join(triple)
+                .join(format!(
+                    "{}-{}",
+                    origin.name,
+                    origin.resolved_source.short_hash()
+                ))
+                .join(format!("{commit}-{hash:016x}.{extension}")),
+        )
+    }
+}
+
+/// Clones the repository into the cache if it's not there yet.
+pub fn clone_db(db: &Path, url: &str) -> Result<()> {
+    if db.exists() {
+        return Ok(());
+    }
+
+    let result = RepoBuilder::new().bare(true).clone(url, db);
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(db);
+    }
+    result.with_context(|| format!("failed to clone {url} into {}", db.display()))?;
+
+    Ok(())
+}
+
+/// Fetches the branches of the repository into its clone in the cache.
+pub fn fetch_db(db: &Path) -> Result<()> {
+    let repo =
+        Repository::open_bare(db).with_context(|| format!("failed to open {}", db.display()))?;
+    repo.find_remote("origin")?
+        .fetch(&["+refs/heads/*:refs/heads/*"], None, None)
+        .with_context(|| format!("failed to fetch into {}", db.display()))
+}
+
+/// Copies the prebuilt compile unit to the object of the build, returning whether it was there.
+pub fn reuse_unit(prebuilt: &Path, object: &Path) -> bool {
+    match std::fs::copy(prebuilt, object) {
+        Ok(_) => {
+            tracing::debug!("reused {}", prebuilt.display());
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Keeps the object of a compile unit for other builds, unless it can't be written there, like
+/// when the directory is read-only.
+pub fn store_unit(object: &Path, prebuilt: &Path) {
+    // It's copied next to it and renamed, so other builds don't read it half written.
+    let partial = prebuilt.with_extension(format!("{}.partial", std::process::id()));
+    let result = prebuilt
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::copy(object, &partial))
+        .and_then(|_| std::fs::rename(&partial, prebuilt));
+
+    if let Err(error) = result {
+        tracing::debug!("couldn't keep {}: {error}", object.display());
+        let _ = std::fs::remove_file(&partial);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_cache_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache {
+            dir: dir.path().join("cache"),
+        };
+
+        let db = cache.git_db("https://github.com/lambdaclass/concrete.git");
+        assert!(db.starts_with(dir.path().join("cache/git/db")));
+        assert!(
+            db.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("concrete-")
+        );
+        assert_ne!(db, cache.git_db("https://gitlab.com/lambdaclass/concrete"));
+
+        // The sources at a path aren't cached, they can change without a commit.
+        let origin = DependencyOrigin {
+            name: "json".to_string(),
+            version: None,
+            source: "path ../json".to_string(),
+            resolved_source: DependencySource::Path(dir.path().join("json")),
+            key: "json".to_string(),
+            dir: dir.path().join("json"),
+            required_by: vec!["app".to_string()],
+        };
+        assert_eq!(cache.dependency_unit(&origin, "x86_64", 1, "o"), None);
+
+        let object = dir.path().join("json.o");
+        let prebuilt = cache.dir.join("units/x86_64/json/1.o");
+        std::fs::write(&object, "object").unwrap();
+        assert!(!reuse_unit(&prebuilt, &object));
+        store_unit(&object, &prebuilt);
+
+        let other = dir.path().join("other.o");
+        assert!(reuse_unit(&prebuilt, &other));
+        assert_eq!(std::fs::read_to_string(other).unwrap(), "object");
+    }
+}
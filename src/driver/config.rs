@@ -1,14 +1,131 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use toml_edit::{ImDocument, TableLike};
+
+use crate::{
+    ast::common::Span,
+    check::{
+        lints::{Lint, Warning},
+        suggestions::{Applicability, Suggestion, find_similar_name, unknown_value_message},
+    },
+    compile_unit_info::{Lto, Sanitizer},
+};
 
 /// A package config file. Namely Concrete.toml
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
-    pub package: Package,
-    pub profile: HashMap<String, Profile>,
+    /// The package, only the root of a workspace can be without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<Package>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<Workspace>,
+    /// The profiles, the ones of the workspace root are also used by its members.
+    #[serde(default)]
+    pub profile: HashMap<String, ProfileConfig>,
     #[serde(default)]
     pub dependencies: HashMap<String, Dependency>,
+    /// The features of the package, each with the ones it enables: other features of the package,
+    /// or features of its dependencies written `dependency/feature`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<String, Vec<String>>,
+    /// The compiler plugins, sorted so their passes always run in the same order.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub plugins: BTreeMap<String, PluginConfig>,
+    /// How the package is built and linked.
+    #[serde(default, skip_serializing_if = "BuildConfig::is_empty")]
+    pub build: BuildConfig,
+    /// How the library of the package is built.
+    #[serde(default, skip_serializing_if = "LibConfig::is_default")]
+    pub lib: LibConfig,
+    /// How `concrete run` runs the program.
+    #[serde(default, skip_serializing_if = "RunConfig::is_empty")]
+    pub run: RunConfig,
+}
+
+impl Config {
+    /// Reads the config file at the given path.
+    pub fn read(path: &Path) -> anyhow::Result<Config> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&source).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn package(&self) -> anyhow::Result<&Package> {
+        self.package.as_ref().context(
+            "Concrete.toml has no [package] table, only the root of a workspace can be without it",
+        )
+    }
+
+    /// The features enabled by the requested ones, and the ones they enable in the dependencies of
+    /// the package. Requesting [`DEFAULT_FEATURE`] is fine even if the package doesn't define it.
+    pub fn enabled_features(
+        &self,
+        requested: &BTreeSet<String>,
+    ) -> anyhow::Result<EnabledFeatures> {
+        let mut enabled = EnabledFeatures::default();
+        let mut pending: Vec<&str> = requested.iter().map(String::as_str).collect();
+
+        while let Some(feature) = pending.pop() {
+            if let Some((dependency, dependency_feature)) = feature.split_once('/') {
+                if !self.dependencies.contains_key(dependency) {
+                    anyhow::bail!(
+                        "{}",
+                        unknown_value_message(
+                            "dependency",
+                            dependency,
+                            self.dependencies.keys().map(String::as_str)
+                        )
+                    );
+                }
+
+                enabled
+                    .dependencies
+                    .entry(dependency.to_string())
+                    .or_default()
+                    .insert(dependency_feature.to_string());
+                continue;
+            }
+
+            let Some(implied) = self.features.get(feature) else {
+                if feature == DEFAULT_FEATURE {
+                    continue;
+                }
+
+                anyhow::bail!(
+                    "{}",
+                    unknown_value_message(
+                        "feature",
+                        feature,
+                        self.features.keys().map(String::as_str)
+                    )
+                );
+            };
+
+            if enabled.features.insert(feature.to_string()) {
+                pending.extend(implied.iter().map(String::as_str));
+            }
+        }
+
+        Ok(enabled)
+    }
+
+    /// Gets the profile with the given name, with the values of the ones it inherits from,
+    /// suggesting a similar one if it doesn't exist.
+    pub fn get_profile(&self, name: &str) -> anyhow::Result<Profile> {
+        resolve_profile(name, |x| self.profile.get(x), self.profile.keys())
+    }
+}
+
+/// The packages of a repository built together, see [`super::workspace`].
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Workspace {
+    /// The directories of the member packages, relative to the workspace root.
+    pub members: Vec<PathBuf>,
 }
 
 /// Meta information about the package.
@@ -20,9 +137,227 @@ pub struct Package {
     pub version: String,
     /// The SPDX license name.
     pub license: String,
+    /// What the package is for, in a sentence, required to publish it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Other file extensions source files can have besides `.con`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extension_aliases: Vec<String>,
+    /// Whether it's built without std and libc, for kernels and firmware. Its binaries have no
+    /// startup files and start at the `entry` symbol.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_std: bool,
+    /// The symbol a freestanding binary starts at, `_start` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+}
+
+/// The `[build]` section of the manifest.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct BuildConfig {
+    /// The native libraries linked with the package, like `m` for `-lm`. The ones of its
+    /// dependencies are linked too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libs: Vec<String>,
+    /// Other directories the native libraries are looked for in, relative to the package.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lib_dirs: Vec<PathBuf>,
+}
+
+impl BuildConfig {
+    /// Whether nothing is set, so the section isn't written.
+    pub fn is_empty(&self) -> bool {
+        self.libs.is_empty() && self.lib_dirs.is_empty()
+    }
+}
+
+/// The `[run]` section of the manifest.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RunConfig {
+    /// The environment variables set for the program, besides the ones it inherits.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl RunConfig {
+    /// Whether nothing is set, so the section isn't written.
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty()
+    }
+}
+
+/// The `[lib]` section of the manifest.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LibConfig {
+    /// The kinds of library built from `src/lib.con`, all of them in the same build.
+    #[serde(default = "LibConfig::default_kind")]
+    pub kind: Vec<LibKind>,
+}
+
+impl LibConfig {
+    fn default_kind() -> Vec<LibKind> {
+        vec![LibKind::Shared]
+    }
+
+    fn is_default(&self) -> bool {
+        self.kind == Self::default_kind()
+    }
+}
+
+impl Default for LibConfig {
+    fn default() -> Self {
+        Self {
+            kind: Self::default_kind(),
+        }
+    }
+}
+
+/// A kind of library output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LibKind {
+    /// A `.a` archive of the objects, linked into the programs using it.
+    Static,
+    /// A `.so`, `.dylib` or `.wasm` loaded when the programs using it run.
+    Shared,
+}
+
+/// The profiles every package has, even if its config doesn't define them.
+pub const BUILTIN_PROFILES: &[&str] = &["dev", "release"];
+
+/// A profile as written in the config: the values it sets, the others come from the profile it
+/// inherits from.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ProfileConfig {
+    /// The profile it takes the values it doesn't set from. Without it, they come from the
+    /// built-in profile of the same name, `dev` for the custom ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherits: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opt_level: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_info: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_assertions: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codegen_units: Option<usize>,
+    /// What a failed check does, only `"abort"` as there's no unwinding.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linker: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linker_args: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpath: Option<Vec<String>>,
+    #[serde(
+        default,
+        with = "lto_setting::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub lto: Option<Lto>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sanitizers: Option<Vec<Sanitizer>>,
+}
+
+impl ProfileConfig {
+    /// Sets the values of the profile this one sets.
+    fn apply(&self, name: &str, profile: &mut Profile) -> anyhow::Result<()> {
+        if let Some(panic) = self.panic.as_ref().filter(|x| *x != "abort") {
+            anyhow::bail!(
+                "`panic = \"{panic}\"` in [profile.{name}] isn't supported, failed checks always \
+                 abort, set it to \"abort\""
+            );
+        }
+        if self.codegen_units == Some(0) {
+            anyhow::bail!("`codegen_units` in [profile.{name}] has to be at least 1");
+        }
+
+        let Self {
+            inherits: _,
+            release,
+            opt_level,
+            debug_info,
+            debug_assertions,
+            codegen_units,
+            panic: _,
+            linker,
+            linker_args,
+            rpath,
+            lto,
+            sanitizers,
+        } = self.clone();
+        profile.release = release.unwrap_or(profile.release);
+        profile.opt_level = opt_level.unwrap_or(profile.opt_level);
+        profile.debug_info = debug_info.unwrap_or(profile.debug_info);
+        profile.debug_assertions = debug_assertions.unwrap_or(profile.debug_assertions);
+        profile.codegen_units = codegen_units.or(profile.codegen_units);
+        profile.linker = linker.or(profile.linker.take());
+        profile.linker_args = linker_args.unwrap_or(std::mem::take(&mut profile.linker_args));
+        profile.rpath = rpath.unwrap_or(std::mem::take(&mut profile.rpath));
+        profile.lto = lto.unwrap_or(profile.lto);
+        profile.sanitizers = sanitizers.unwrap_or(std::mem::take(&mut profile.sanitizers));
+        Ok(())
+    }
 }
 
-/// Defines a compilation profile.
+/// Resolves the profile with the given name from the profiles `lookup` finds, applying each one
+/// over the one it inherits from, down to a built-in profile. `names` are the profiles defined,
+/// to suggest one if the name is unknown.
+pub fn resolve_profile<'a>(
+    name: &str,
+    lookup: impl Fn(&str) -> Option<&'a ProfileConfig>,
+    names: impl IntoIterator<Item = &'a String>,
+) -> anyhow::Result<Profile> {
+    let mut names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+    names.extend(BUILTIN_PROFILES);
+    names.sort_unstable();
+    names.dedup();
+
+    let mut chain: Vec<(&str, &ProfileConfig)> = Vec::new();
+    let mut current = name;
+    let mut profile = loop {
+        if chain.iter().any(|(x, _)| *x == current) {
+            let cycle: Vec<&str> = chain.iter().map(|(x, _)| *x).chain([current]).collect();
+            anyhow::bail!(
+                "the profile `{name}` inherits from itself: {}",
+                cycle.join(" -> ")
+            );
+        }
+
+        match lookup(current) {
+            Some(config) => {
+                chain.push((current, config));
+                match &config.inherits {
+                    Some(parent) => current = parent,
+                    None => break Profile::builtin(current).unwrap_or_else(Profile::dev),
+                }
+            }
+            None => match Profile::builtin(current) {
+                Some(profile) => break profile,
+                None => {
+                    let message = unknown_value_message("profile", current, names);
+                    match chain.last() {
+                        Some((child, _)) => {
+                            anyhow::bail!("`inherits` of [profile.{child}]: {message}")
+                        }
+                        None => anyhow::bail!("{message}"),
+                    }
+                }
+            },
+        }
+    };
+
+    for (name, config) in chain.iter().rev() {
+        config.apply(name, &mut profile)?;
+    }
+
+    Ok(profile)
+}
+
+/// A compilation profile, with the values of the ones it inherits from.
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Profile {
     /// Whether this profile is the --release profile.
@@ -31,6 +366,131 @@ pub struct Profile {
     pub opt_level: u8,
     /// Whether to enable debug info.
     pub debug_info: bool,
+    /// Whether `#[cfg(debug_assertions)]` holds, for checks only wanted while developing.
+    #[serde(default)]
+    pub debug_assertions: bool,
+    /// The most objects the compile units are generated into, `None` for one per compile unit.
+    /// Fewer objects generate code in fewer threads, but let LLVM inline across the units merged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codegen_units: Option<usize>,
+    /// The linker used instead of the default one of the target, like `mold` or `ld.lld`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linker: Option<String>,
+    /// Arguments passed as is to the linker, after the objects.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub linker_args: Vec<String>,
+    /// The directories binaries look for shared libraries in when they run, like
+    /// `$ORIGIN/../lib`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rpath: Vec<String>,
+    /// Link time optimization: `true` or `"full"`, `"thin"`, or `false`.
+    #[serde(
+        default,
+        with = "lto_setting",
+        skip_serializing_if = "lto_setting::is_off"
+    )]
+    pub lto: Lto,
+    /// The sanitizers the code is instrumented with, like `["address", "undefined"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sanitizers: Vec<Sanitizer>,
+}
+
+impl Profile {
+    /// The `dev` profile: unoptimized, with debug info and debug assertions.
+    pub fn dev() -> Self {
+        Self {
+            release: false,
+            opt_level: 0,
+            debug_info: true,
+            debug_assertions: true,
+            ..Default::default()
+        }
+    }
+
+    /// The `release` profile: optimized, without debug info or debug assertions.
+    pub fn release() -> Self {
+        Self {
+            release: true,
+            opt_level: 3,
+            debug_info: false,
+            debug_assertions: false,
+            ..Default::default()
+        }
+    }
+
+    /// The built-in profile with the given name, see [`BUILTIN_PROFILES`].
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dev" => Some(Self::dev()),
+            "release" => Some(Self::release()),
+            _ => None,
+        }
+    }
+}
+
+/// `lto` is a boolean like in cargo, or the kind of LTO.
+mod lto_setting {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+    use crate::compile_unit_info::Lto;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Enabled(bool),
+        Kind(String),
+    }
+
+    pub fn is_off(lto: &Lto) -> bool {
+        *lto == Lto::Off
+    }
+
+    pub fn serialize<S: Serializer>(lto: &Lto, serializer: S) -> Result<S::Ok, S::Error> {
+        match lto {
+            Lto::Off => Setting::Enabled(false),
+            Lto::Thin => Setting::Kind("thin".to_string()),
+            Lto::Full => Setting::Enabled(true),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Lto, D::Error> {
+        match Setting::deserialize(deserializer)? {
+            Setting::Enabled(false) => Ok(Lto::Off),
+            Setting::Enabled(true) => Ok(Lto::Full),
+            Setting::Kind(kind) => match kind.as_str() {
+                "off" => Ok(Lto::Off),
+                "thin" => Ok(Lto::Thin),
+                "full" => Ok(Lto::Full),
+                _ => Err(D::Error::custom(format!(
+                    "unknown lto `{kind}`, expected true, false, \"thin\" or \"full\""
+                ))),
+            },
+        }
+    }
+
+    /// The setting of a profile that may not set it.
+    pub mod option {
+        use serde::{Deserializer, Serializer};
+
+        use crate::compile_unit_info::Lto;
+
+        pub fn serialize<S: Serializer>(
+            lto: &Option<Lto>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match lto {
+                Some(lto) => super::serialize(lto, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Lto>, D::Error> {
+            super::deserialize(deserializer).map(Some)
+        }
+    }
 }
 
 /// Defines a package dependency
@@ -44,4 +504,606 @@ pub struct Dependency {
     pub r#ref: Option<String>,
     /// The version of the dependency.
     pub version: Option<String>,
+    /// The features of the dependency to enable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// Whether to enable the default features of the dependency, true if it's not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_features: Option<bool>,
+}
+
+/// The feature enabling the ones a package has by default, unless `--no-default-features` or
+/// `default_features = false` disable them.
+pub const DEFAULT_FEATURE: &str = "default";
+
+/// The features enabled in a package, see [`Config::enabled_features`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnabledFeatures {
+    pub features: BTreeSet<String>,
+    /// The features enabled in its dependencies, by name.
+    pub dependencies: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Dependency {
+    /// The features requested from the dependency, with its default ones unless disabled.
+    pub fn requested_features(&self) -> BTreeSet<String> {
+        let mut features: BTreeSet<String> = self.features.iter().cloned().collect();
+        if self.default_features.unwrap_or(true) {
+            features.insert(DEFAULT_FEATURE.to_string());
+        }
+
+        features
+    }
+
+    /// Describes where the dependency is fetched from.
+    pub fn source(&self) -> String {
+        match (&self.path, &self.git) {
+            (Some(path), _) => format!("path {}", path.display()),
+            (None, Some(git)) => match &self.r#ref {
+                Some(r#ref) => format!("git {git}#{ref}"),
+                None => format!("git {git}"),
+            },
+            (None, None) => "unknown source".to_string(),
+        }
+    }
+
+    /// Where the dependency is fetched from, path dependencies being relative to the given
+    /// project directory.
+    pub fn resolve_source(&self, project_dir: &Path) -> anyhow::Result<DependencySource> {
+        match (&self.path, &self.git) {
+            (Some(path), _) => {
+                let dir = project_dir.join(path);
+                Ok(DependencySource::Path(dir.canonicalize().unwrap_or(dir)))
+            }
+            (None, Some(url)) => Ok(DependencySource::Git {
+                url: url.clone(),
+                r#ref: self.r#ref.clone(),
+            }),
+            (None, None) => anyhow::bail!("No path or git specified for dependency."),
+        }
+    }
+}
+
+/// Where a dependency is fetched from, the requirements of a package from the same one share it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DependencySource {
+    /// The project directory, canonicalized when it exists so the paths written by different
+    /// packages match.
+    Path(PathBuf),
+    /// The repository and the ref requested.
+    Git { url: String, r#ref: Option<String> },
+}
+
+impl DependencySource {
+    /// A short hash telling apart the copies of a package built from different sources, stable
+    /// across builds of the compiler, unlike the std hasher.
+    pub fn short_hash(&self) -> String {
+        let text = match self {
+            DependencySource::Path(dir) => dir.display().to_string(),
+            DependencySource::Git { url, r#ref } => {
+                format!("{url}#{}", r#ref.as_deref().unwrap_or_default())
+            }
+        };
+
+        // 32 bit FNV-1a.
+        let mut hash: u32 = 0x811c9dc5;
+        for byte in text.bytes() {
+            hash ^= u32::from(byte);
+            hash = hash.wrapping_mul(0x01000193);
+        }
+
+        format!("{hash:08x}")
+    }
+}
+
+/// Defines a compiler plugin.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct PluginConfig {
+    /// The (relative or absolute) path to the dynamic library of the plugin.
+    pub path: PathBuf,
+}
+
+/// A dependency added to the build, to tell the user when a diagnostic points into it.
+#[derive(Debug, Clone)]
+pub struct DependencyOrigin {
+    pub name: String,
+    /// The version of the package, once its config file is read.
+    pub version: Option<String>,
+    pub source: String,
+    pub resolved_source: DependencySource,
+    /// The name it's checked out and locked as, the name followed by the hash of its source if
+    /// the build has the package from another source too.
+    pub key: String,
+    /// The project directory of the dependency.
+    pub dir: PathBuf,
+    /// The packages that required this one, starting from the one being built.
+    pub required_by: Vec<String>,
+}
+
+impl DependencyOrigin {
+    /// The suffix added to the names of its top level modules, so they don't clash with the ones
+    /// of the package built from another source.
+    pub fn module_suffix(&self) -> Option<String> {
+        (self.key != self.name).then(|| self.resolved_source.short_hash())
+    }
+
+    /// Whether the file belongs to this dependency.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.dir)
+    }
+
+    /// The dependency the file belongs to, the innermost one as dependencies can be checked out
+    /// inside others.
+    pub fn innermost<'a>(dependencies: &'a [DependencyOrigin], path: &Path) -> Option<&'a Self> {
+        dependencies
+            .iter()
+            .filter(|x| x.contains(path))
+            .max_by_key(|x| x.dir.components().count())
+    }
+
+    /// The note added to the diagnostics found in this dependency.
+    pub fn note(&self) -> String {
+        let version = match &self.version {
+            Some(version) => format!(" v{version}"),
+            None => String::new(),
+        };
+
+        format!(
+            "while compiling dependency `{}`{} ({}), required by {}",
+            self.name,
+            version,
+            self.source,
+            self.required_by
+                .iter()
+                .map(|x| format!("`{x}`"))
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        )
+    }
+}
+
+/// The keys each table of the config file accepts.
+const CONFIG_KEYS: &[&str] = &[
+    "package",
+    "workspace",
+    "profile",
+    "dependencies",
+    "features",
+    "plugins",
+    "build",
+    "lib",
+    "run",
+];
+const WORKSPACE_KEYS: &[&str] = &["members"];
+const PACKAGE_KEYS: &[&str] = &[
+    "name",
+    "version",
+    "license",
+    "description",
+    "extension_aliases",
+    "no_std",
+    "entry",
+];
+const PROFILE_KEYS: &[&str] = &[
+    "inherits",
+    "release",
+    "opt_level",
+    "debug_info",
+    "debug_assertions",
+    "codegen_units",
+    "panic",
+    "linker",
+    "linker_args",
+    "rpath",
+    "lto",
+    "sanitizers",
+];
+const DEPENDENCY_KEYS: &[&str] = &[
+    "path",
+    "git",
+    "ref",
+    "version",
+    "features",
+    "default_features",
+];
+const PLUGIN_KEYS: &[&str] = &["path"];
+const RUN_KEYS: &[&str] = &["env"];
+
+/// Finds the keys of the config file that aren't part of its format, which serde silently ignores.
+///
+/// The source must be a valid config file, as it's only parsed again to get the spans of the keys.
+pub fn unknown_config_keys(path: &Path, source: &str) -> Vec<Warning> {
+    let Ok(document) = ImDocument::parse(source) else {
+        return Vec::new();
+    };
+
+    let mut warnings = Vec::new();
+    let root = document.as_table();
+    check_keys(path, root, "", CONFIG_KEYS, &mut warnings);
+
+    if let Some(package) = root.get("package").and_then(|x| x.as_table_like()) {
+        check_keys(path, package, "package", PACKAGE_KEYS, &mut warnings);
+    }
+
+    if let Some(workspace) = root.get("workspace").and_then(|x| x.as_table_like()) {
+        check_keys(path, workspace, "workspace", WORKSPACE_KEYS, &mut warnings);
+    }
+
+    if let Some(run) = root.get("run").and_then(|x| x.as_table_like()) {
+        check_keys(path, run, "run", RUN_KEYS, &mut warnings);
+    }
+
+    if let Some(profiles) = root.get("profile").and_then(|x| x.as_table_like()) {
+        for (name, profile) in profiles.iter() {
+            if let Some(profile) = profile.as_table_like() {
+                let table = format!("profile.{name}");
+                check_keys(path, profile, &table, PROFILE_KEYS, &mut warnings);
+            }
+        }
+    }
+
+    if let Some(dependencies) = root.get("dependencies").and_then(|x| x.as_table_like()) {
+        for (name, dependency) in dependencies.iter() {
+            if let Some(dependency) = dependency.as_table_like() {
+                let table = format!("dependencies.{name}");
+                check_keys(path, dependency, &table, DEPENDENCY_KEYS, &mut warnings);
+            }
+        }
+    }
+
+    if let Some(plugins) = root.get("plugins").and_then(|x| x.as_table_like()) {
+        for (name, plugin) in plugins.iter() {
+            if let Some(plugin) = plugin.as_table_like() {
+                let table = format!("plugins.{name}");
+                check_keys(path, plugin, &table, PLUGIN_KEYS, &mut warnings);
+            }
+        }
+    }
+
+    warnings
+}
+
+fn check_keys(
+    path: &Path,
+    table: &dyn TableLike,
+    name: &str,
+    known: &[&str],
+    warnings: &mut Vec<Warning>,
+) {
+    for (key, _) in table.iter() {
+        if known.contains(&key) {
+            continue;
+        }
+
+        let Some(span) = table
+            .get_key_value(key)
+            .and_then(|(key, _)| key.span())
+            .map(|x| Span::new(x.start, x.end))
+        else {
+            continue;
+        };
+
+        let suggestion = find_similar_name(key, known.iter().copied()).map(|x| Suggestion {
+            message: "a key with a similar name exists".to_string(),
+            path: None,
+            span,
+            replacement: x.to_string(),
+            applicability: Applicability::MaybeIncorrect,
+        });
+
+        let message = if name.is_empty() {
+            format!("unknown key '{key}' in Concrete.toml")
+        } else {
+            format!("unknown key '{key}' in [{name}]")
+        };
+
+        warnings.push(Warning {
+            lint: Lint::UnknownManifestKeys,
+            span,
+            message,
+            suggestion,
+            path: path.to_path_buf(),
+            level: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_keys_are_warned() {
+        let source = r#"
+[package]
+name = "example"
+version = "0.1.0"
+license = "MIT"
+description = "An example package"
+
+[profile.release]
+release = true
+opt-level = 3
+debug_info = false
+
+[dependecies]
+"#;
+        let warnings = unknown_config_keys(Path::new("Concrete.toml"), source);
+
+        assert_eq!(warnings.len(), 2, "{:#?}", warnings);
+        assert_eq!(
+            warnings[0].message,
+            "unknown key 'dependecies' in Concrete.toml"
+        );
+        assert_eq!(
+            warnings[0]
+                .suggestion
+                .as_ref()
+                .map(|x| x.replacement.as_str()),
+            Some("dependencies")
+        );
+        assert_eq!(
+            warnings[1].message,
+            "unknown key 'opt-level' in [profile.release]"
+        );
+        assert_eq!(
+            &source[warnings[1].span.from..warnings[1].span.to],
+            "opt-level"
+        );
+        assert_eq!(
+            warnings[1]
+                .suggestion
+                .as_ref()
+                .map(|x| x.replacement.as_str()),
+            Some("opt_level")
+        );
+    }
+
+    #[test]
+    fn dependency_origin_note() {
+        let origin = DependencyOrigin {
+            name: "std".to_string(),
+            version: Some("0.1.0".to_string()),
+            source: "git https://example.com/std.git#abc123".to_string(),
+            resolved_source: DependencySource::Git {
+                url: "https://example.com/std.git".to_string(),
+                r#ref: Some("abc123".to_string()),
+            },
+            key: "std".to_string(),
+            dir: ".bricks/std".into(),
+            required_by: vec!["app".to_string(), "utils".to_string()],
+        };
+
+        assert!(origin.contains(Path::new(".bricks/std/src/lib.con")));
+        assert!(!origin.contains(Path::new("src/main.con")));
+        assert_eq!(
+            origin.note(),
+            "while compiling dependency `std` v0.1.0 (git https://example.com/std.git#abc123), required by `app` -> `utils`"
+        );
+    }
+
+    #[test]
+    fn dependency_sources_get_distinct_module_suffixes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("app")).unwrap();
+        std::fs::create_dir(dir.path().join("utils")).unwrap();
+
+        let dependency = Dependency {
+            path: Some("./app/../utils".into()),
+            ..Default::default()
+        };
+        let source = dependency.resolve_source(dir.path()).unwrap();
+        assert_eq!(
+            source,
+            DependencySource::Path(dir.path().join("utils").canonicalize().unwrap())
+        );
+
+        let git = |r#ref: &str| DependencySource::Git {
+            url: "https://example.com/utils.git".to_string(),
+            r#ref: Some(r#ref.to_string()),
+        };
+        assert_eq!(git("v1").short_hash(), git("v1").short_hash());
+        assert_ne!(git("v1").short_hash(), git("v2").short_hash());
+        assert_eq!(git("v1").short_hash().len(), 8);
+
+        let origin = |key: &str| DependencyOrigin {
+            name: "utils".to_string(),
+            version: None,
+            source: "git https://example.com/utils.git#v2".to_string(),
+            resolved_source: git("v2"),
+            key: key.to_string(),
+            dir: ".bricks/utils".into(),
+            required_by: vec!["app".to_string()],
+        };
+        assert_eq!(origin("utils").module_suffix(), None);
+        let key = format!("utils-{}", git("v2").short_hash());
+        assert_eq!(origin(&key).module_suffix(), Some(git("v2").short_hash()));
+    }
+
+    #[test]
+    fn unknown_profile_suggestion() {
+        let config: Config = toml::from_str(
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n\n[profile.release]\nrelease = true\nopt_level = 3\ndebug_info = false\n\n[profile.dev]\nrelease = false\nopt_level = 0\ndebug_info = true\n",
+        )
+        .unwrap();
+
+        assert!(config.get_profile("release").is_ok());
+        assert_eq!(
+            config.get_profile("relase").unwrap_err().to_string(),
+            "unknown profile `relase`, did you mean `release`?\nnote: the available profiles are: dev, release"
+        );
+    }
+
+    #[test]
+    fn profiles_inherit_values() {
+        let source = r#"
+[package]
+name = "app"
+version = "0.1.0"
+license = "MIT"
+
+[profile.release]
+lto = "thin"
+
+[profile.profiling]
+inherits = "release"
+debug_info = true
+codegen_units = 1
+
+[profile.ci]
+inherits = "profiling"
+opt_level = 1
+debug_assertions = true
+panic = "abort"
+"#;
+        let config: Config = toml::from_str(source).unwrap();
+        assert!(
+            unknown_config_keys(Path::new("Concrete.toml"), source).is_empty(),
+            "the profile keys are known"
+        );
+
+        let release = config.get_profile("release").unwrap();
+        assert!(release.release && !release.debug_info && !release.debug_assertions);
+        assert_eq!((release.opt_level, release.lto), (3, Lto::Thin));
+
+        let ci = config.get_profile("ci").unwrap();
+        assert!(ci.release && ci.debug_info && ci.debug_assertions);
+        assert_eq!(
+            (ci.opt_level, ci.lto, ci.codegen_units),
+            (1, Lto::Thin, Some(1))
+        );
+
+        // The built-in profiles exist without being defined.
+        let dev = config.get_profile("dev").unwrap();
+        assert!(!dev.release && dev.debug_info && dev.debug_assertions);
+
+        let error = |profiles: &str| {
+            let config: Config = toml::from_str(&format!(
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n{profiles}"
+            ))
+            .unwrap();
+            config.get_profile("ci").unwrap_err().to_string()
+        };
+        assert_eq!(
+            error("[profile.ci]\ninherits = \"relase\""),
+            "`inherits` of [profile.ci]: unknown profile `relase`, did you mean `release`?\nnote: the available profiles are: ci, dev, release"
+        );
+        assert_eq!(
+            error("[profile.ci]\ninherits = \"bench\"\n[profile.bench]\ninherits = \"ci\""),
+            "the profile `ci` inherits from itself: ci -> bench -> ci"
+        );
+        assert!(error("[profile.ci]\npanic = \"unwind\"").contains("isn't supported"));
+        assert!(error("[profile.ci]\ncodegen_units = 0").contains("at least 1"));
+    }
+
+    #[test]
+    fn features_enable_their_features_and_dependency_ones() {
+        use std::collections::BTreeSet;
+
+        let config: Config = toml::from_str(
+            r#"
+        [package]
+        name = "app"
+        version = "0.1.0"
+        license = "MIT"
+
+        [dependencies]
+        json = { path = "../json", features = ["fast"], default_features = false }
+        log = { path = "../log" }
+
+        [features]
+        default = ["pretty"]
+        pretty = ["color", "json/pretty"]
+        color = []
+        "#,
+        )
+        .unwrap();
+
+        let set = |x: &[&str]| -> BTreeSet<String> { x.iter().map(|x| x.to_string()).collect() };
+
+        let enabled = config.enabled_features(&set(&[DEFAULT_FEATURE])).unwrap();
+        assert_eq!(enabled.features, set(&["default", "pretty", "color"]));
+        assert_eq!(enabled.dependencies["json"], set(&["pretty"]));
+
+        let enabled = config.enabled_features(&set(&["color"])).unwrap();
+        assert_eq!(enabled.features, set(&["color"]));
+        assert!(enabled.dependencies.is_empty());
+
+        assert_eq!(
+            config.dependencies["json"].requested_features(),
+            set(&["fast"])
+        );
+        assert_eq!(
+            config.dependencies["log"].requested_features(),
+            set(&[DEFAULT_FEATURE])
+        );
+
+        let error = config.enabled_features(&set(&["colour"])).unwrap_err();
+        assert!(
+            error.to_string().contains("did you mean `color`?"),
+            "{error}"
+        );
+        let error = config.enabled_features(&set(&["jsn/pretty"])).unwrap_err();
+        assert!(
+            error.to_string().contains("did you mean `json`?"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn lib_kinds_default_to_shared() {
+        let package = "[package]\nname = \"app\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n";
+        let config: Config = toml::from_str(package).unwrap();
+        assert_eq!(config.lib.kind, [LibKind::Shared]);
+        assert!(!toml::to_string(&config).unwrap().contains("[lib]"));
+
+        let config: Config = toml::from_str(&format!(
+            "{package}\n[lib]\nkind = [\"static\", \"shared\"]\n"
+        ))
+        .unwrap();
+        assert_eq!(config.lib.kind, [LibKind::Static, LibKind::Shared]);
+    }
+
+    #[test]
+    fn run_env_config() {
+        let source = r#"
+[package]
+name = "app"
+version = "0.1.0"
+license = "MIT"
+
+[run.env]
+RUST_LOG = "debug"
+APP_MODE = "test"
+
+[profile.dev]
+release = false
+opt_level = 0
+debug_info = true
+lto = "thin"
+
+[build]
+libs = ["m"]
+"#;
+        let config: Config = toml::from_str(source).unwrap();
+        assert_eq!(
+            config.run.env.iter().collect::<Vec<_>>(),
+            [
+                (&"APP_MODE".to_string(), &"test".to_string()),
+                (&"RUST_LOG".to_string(), &"debug".to_string())
+            ]
+        );
+
+        let warnings = unknown_config_keys(Path::new("Concrete.toml"), source);
+        assert!(warnings.is_empty(), "{:#?}", warnings);
+
+        let warnings = unknown_config_keys(
+            Path::new("Concrete.toml"),
+            "[run]
+envs = {}
+",
+        );
+        assert_eq!(warnings.len(), 1, "{:#?}", warnings);
+        assert_eq!(warnings[0].message, "unknown key 'envs' in [run]");
+    }
 }
@@ -0,0 +1,239 @@
+//! `concrete cov report`: the line coverage of the tests.
+//!
+//! Built with `--coverage`, each block of a function counts the times it runs with an atomic add,
+//! so the tests running in parallel don't lose counts, in a global of the library named after the
+//! function (see [`counters_symbol`]). The build writes the coverage map next to the library, with
+//! the source lines of each block. Once the tests ran, the counters are read from the loaded
+//! library into a profile, merged with the saved ones, and each line is covered if one of its
+//! blocks ran.
+//!
+//! It isn't LLVM's source-based coverage, there are no `.profraw` files for `llvm-cov`: the map and
+//! the profile are JSON, see the "Coverage" section of the book.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::ir::{IR, source_map::SourceMap};
+
+/// The symbol of the coverage counters of the function with the given mangled name.
+pub fn counters_symbol(function: &str) -> String {
+    format!("__concrete_coverage_{function}")
+}
+
+/// The source lines of the blocks of the instrumented functions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageMap {
+    /// The functions by their mangled name.
+    functions: BTreeMap<String, FunctionMap>,
+}
+
+/// Where the blocks of a function are, the counters have one entry per block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FunctionMap {
+    path: PathBuf,
+    /// The lines of each block, sorted and without duplicates.
+    blocks: Vec<Vec<usize>>,
+}
+
+impl CoverageMap {
+    /// The map of the functions the program defines, the ones instrumented.
+    pub fn from_ir(ir: &IR, source_map: &SourceMap) -> Self {
+        let mut functions = BTreeMap::new();
+
+        for (fn_idx, function) in ir.functions.iter() {
+            let Some(function) = function else {
+                continue;
+            };
+
+            if function.is_extern || function.is_intrinsic.is_some() {
+                continue;
+            }
+
+            let mut blocks = vec![Vec::new(); function.basic_blocks.len()];
+            for (index, location) in source_map.function_locations(ir, fn_idx) {
+                blocks[index.block].push(location.line);
+            }
+            for lines in &mut blocks {
+                lines.sort_unstable();
+                lines.dedup();
+            }
+
+            functions.insert(
+                function.get_mangled_name(),
+                FunctionMap {
+                    path: ir.modules[function.module_idx].file_path.clone(),
+                    blocks,
+                },
+            );
+        }
+
+        Self { functions }
+    }
+
+    /// The path of the coverage map of the given library.
+    pub fn path_for(library: &Path) -> PathBuf {
+        library.with_extension("covmap.json")
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&file).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// The times each block of the functions ran.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageProfile {
+    /// The counts of the blocks of each function, by its mangled name.
+    counts: BTreeMap<String, Vec<u64>>,
+}
+
+impl CoverageProfile {
+    /// Reads the counters of the functions of the map from the loaded library. The functions whose
+    /// counters it doesn't have, like the ones of prebuilt units, are left out.
+    ///
+    /// # Safety
+    ///
+    /// The library must have been built with `--coverage` along with the map.
+    pub unsafe fn read(library: &libloading::Library, map: &CoverageMap) -> Self {
+        let mut counts = BTreeMap::new();
+
+        for (name, function) in &map.functions {
+            let symbol = counters_symbol(name);
+            let Ok(counters) = (unsafe { library.get::<*const u64>(symbol.as_bytes()) }) else {
+                continue;
+            };
+
+            let counters = unsafe { std::slice::from_raw_parts(*counters, function.blocks.len()) };
+            counts.insert(name.clone(), counters.to_vec());
+        }
+
+        Self { counts }
+    }
+
+    /// Adds the counts of the other profile to these.
+    pub fn merge(&mut self, other: &CoverageProfile) {
+        for (name, counts) in &other.counts {
+            let merged = self.counts.entry(name.clone()).or_default();
+            if merged.len() < counts.len() {
+                merged.resize(counts.len(), 0);
+            }
+
+            for (merged, count) in merged.iter_mut().zip(counts) {
+                *merged += count;
+            }
+        }
+    }
+
+    /// The path of the profile of the given library.
+    pub fn path_for(library: &Path) -> PathBuf {
+        library.with_extension("profile.json")
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&file).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// The line coverage of each source file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// The times each line of the files ran, the most of the blocks it's in.
+    pub files: BTreeMap<PathBuf, BTreeMap<usize, u64>>,
+}
+
+impl CoverageReport {
+    /// The coverage of the functions of the map that are in the profile.
+    pub fn new(map: &CoverageMap, profile: &CoverageProfile) -> Self {
+        let mut files: BTreeMap<PathBuf, BTreeMap<usize, u64>> = BTreeMap::new();
+
+        for (name, function) in &map.functions {
+            let Some(counts) = profile.counts.get(name) else {
+                continue;
+            };
+            let lines = files.entry(function.path.clone()).or_default();
+
+            for (block, count) in function.blocks.iter().zip(counts) {
+                for line in block {
+                    let line = lines.entry(*line).or_default();
+                    *line = (*line).max(*count);
+                }
+            }
+        }
+
+        Self { files }
+    }
+
+    /// The lines of the file that ran and the ones that have code.
+    pub fn file_coverage(&self, path: &Path) -> (usize, usize) {
+        self.files
+            .get(path)
+            .map(|lines| (lines.values().filter(|x| **x > 0).count(), lines.len()))
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .files
+            .keys()
+            .map(|x| x.display().to_string().len())
+            .chain(std::iter::once("TOTAL".len()))
+            .max()
+            .unwrap_or_default();
+        let percent = |covered: usize, lines: usize| match lines {
+            0 => 100.0,
+            _ => covered as f64 / lines as f64 * 100.0,
+        };
+
+        writeln!(
+            f,
+            "{:width$}  {:>6}  {:>6}  {:>7}",
+            "File", "Lines", "Missed", "Cover"
+        )?;
+
+        let mut total = (0, 0);
+        for path in self.files.keys() {
+            let (covered, lines) = self.file_coverage(path);
+            total = (total.0 + covered, total.1 + lines);
+
+            writeln!(
+                f,
+                "{:width$}  {:>6}  {:>6}  {:>6.2}%",
+                path.display().to_string(),
+                lines,
+                lines - covered,
+                percent(covered, lines)
+            )?;
+        }
+
+        writeln!(
+            f,
+            "{:width$}  {:>6}  {:>6}  {:>6.2}%",
+            "TOTAL",
+            total.1,
+            total.1 - total.0,
+            percent(total.0, total.1)
+        )
+    }
+}
@@ -0,0 +1,245 @@
+//! `concrete debug`: runs the program under a Debug Adapter Protocol server, so editors can debug
+//! it without setting up the debugger themselves.
+//!
+//! The debug adapter of lldb or gdb does the actual debugging, this module sits between it and the
+//! editor, translating the mangled function names to the Concrete ones and back.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::ir::IR;
+
+/// The debuggers with a debug adapter that can be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Debugger {
+    #[default]
+    Lldb,
+    Gdb,
+}
+
+impl Debugger {
+    /// The command starting the debug adapter, and its arguments.
+    fn adapter_command(&self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Debugger::Lldb => ("lldb-dap", &[]),
+            Debugger::Gdb => ("gdb", &["--interpreter=dap"]),
+        }
+    }
+}
+
+/// Maps the mangled symbols of the functions to their Concrete names, like `_C1_4Main3addh...` to
+/// `Main::add`.
+///
+/// It's written next to the binary when building for `concrete debug`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolMap {
+    symbols: HashMap<String, String>,
+}
+
+impl SymbolMap {
+    pub fn from_ir(ir: &IR) -> Self {
+        let symbols = ir
+            .functions
+            .iter()
+            .filter_map(|(_, function)| function.as_ref())
+            .filter_map(|function| {
+                function
+                    .debug_name
+                    .as_ref()
+                    .filter(|name| **name != function.name)
+                    .map(|name| (function.name.clone(), name.clone()))
+            })
+            .collect();
+
+        Self { symbols }
+    }
+
+    /// The path of the symbol map of the given binary.
+    pub fn path_for(binary: &Path) -> PathBuf {
+        binary.with_extension("symbols.json")
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&file).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// The Concrete name of the mangled symbol.
+    pub fn demangle(&self, symbol: &str) -> Option<&str> {
+        self.symbols.get(symbol).map(String::as_str)
+    }
+
+    /// The mangled symbol of the function with the given Concrete name, if it's not generic, as
+    /// generic functions have a symbol for each instance.
+    pub fn mangle(&self, name: &str) -> Option<&str> {
+        let mut symbols = self
+            .symbols
+            .iter()
+            .filter(|(_, x)| *x == name)
+            .map(|(symbol, _)| symbol.as_str());
+
+        match (symbols.next(), symbols.next()) {
+            (Some(symbol), None) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    /// Rewrites a request from the editor for the debug adapter.
+    pub fn rewrite_request(&self, message: &mut Value, program: &Path) {
+        match message["command"].as_str() {
+            Some("launch") => {
+                let arguments = &mut message["arguments"];
+                arguments["program"] = json!(program);
+
+                if arguments["cwd"].is_null() {
+                    if let Ok(cwd) = std::env::current_dir() {
+                        arguments["cwd"] = json!(cwd);
+                    }
+                }
+            }
+            Some("setFunctionBreakpoints") => {
+                if let Some(breakpoints) = message["arguments"]["breakpoints"].as_array_mut() {
+                    for breakpoint in breakpoints {
+                        let symbol = breakpoint["name"].as_str().and_then(|x| self.mangle(x));
+
+                        if let Some(symbol) = symbol {
+                            breakpoint["name"] = json!(symbol);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Rewrites a message from the debug adapter for the editor.
+    pub fn rewrite_response(&self, message: &mut Value) {
+        if message["type"] != "response" || message["command"] != "stackTrace" {
+            return;
+        }
+
+        if let Some(frames) = message["body"]["stackFrames"].as_array_mut() {
+            for frame in frames {
+                let name = frame["name"].as_str().and_then(|x| self.demangle(x));
+
+                if let Some(name) = name {
+                    frame["name"] = json!(name);
+                }
+            }
+        }
+    }
+}
+
+/// Reads a message framed with a `Content-Length` header, `None` when the stream ends.
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let Some(length) = length else {
+        bail!("message without a Content-Length header");
+    };
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> Result<()> {
+    let body = serde_json::to_string(message)?;
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serves the debug adapter protocol on stdin and stdout for the given program, until the editor
+/// disconnects.
+pub fn serve(program: &Path, debugger: Debugger, adapter: Option<PathBuf>) -> Result<()> {
+    let symbols = match SymbolMap::load(&SymbolMap::path_for(program)) {
+        Ok(symbols) => symbols,
+        Err(error) => {
+            tracing::warn!("function names won't be demangled: {error:#}");
+            SymbolMap::default()
+        }
+    };
+
+    let (default_command, args) = debugger.adapter_command();
+    let command = adapter.unwrap_or_else(|| PathBuf::from(default_command));
+
+    let mut child = Command::new(&command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| {
+            format!(
+                "failed to start the debug adapter {}, install it or give its path with --adapter",
+                command.display()
+            )
+        })?;
+
+    let mut adapter_in = child.stdin.take().context("no debug adapter stdin")?;
+    let mut adapter_out = BufReader::new(child.stdout.take().context("no debug adapter stdout")?);
+
+    std::thread::scope(|scope| -> Result<()> {
+        let symbols = &symbols;
+
+        let responses = scope.spawn(move || -> Result<()> {
+            let mut stdout = std::io::stdout().lock();
+
+            while let Some(mut message) = read_message(&mut adapter_out)? {
+                symbols.rewrite_response(&mut message);
+                write_message(&mut stdout, &message)?;
+            }
+
+            Ok(())
+        });
+
+        let mut stdin = std::io::stdin().lock();
+
+        while let Some(mut message) = read_message(&mut stdin)? {
+            symbols.rewrite_request(&mut message, program);
+            write_message(&mut adapter_in, &message)?;
+        }
+
+        // Closing the adapter input ends the session.
+        drop(adapter_in);
+
+        responses
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })?;
+
+    child.wait()?;
+
+    Ok(())
+}
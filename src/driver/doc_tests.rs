@@ -0,0 +1,337 @@
+//! Doc tests, the code blocks of doc comments compiled and run by `concrete test --doc`.
+//!
+//! Each fenced block of a doc comment becomes a test function in the module of the documented
+//! item, named `doctest_<item>_<line>`. The block is the body of the function, which returns 0
+//! once it runs to completion unless the block returns something itself.
+//!
+//! Blocks with an info string other than `con` or `concrete`, like `text` or `ignore`, aren't run.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+
+use crate::{
+    ast::{
+        CompilationUnit,
+        common::{Attribute, DocString, Ident, Span, TypeName},
+        functions::{FunctionDecl, FunctionDef},
+        modules::{Module, ModuleDefItem},
+        statements::Statement,
+        types::TypeDescriptor,
+    },
+    check::unused::unreachable_statements,
+    parser::ProgramSource,
+};
+
+use super::CompileError;
+
+/// The prefix of the names of the generated test functions.
+pub const DOC_TEST_PREFIX: &str = "doctest_";
+
+/// The info strings of the code blocks that are run, besides blocks without one.
+const RUN_INFO_STRINGS: &[&str] = &["con", "concrete"];
+
+/// Whether the test, like `Math::doctest_add_12`, was generated from a doc comment.
+pub fn is_doc_test(symbol: &str) -> bool {
+    symbol
+        .rsplit("::")
+        .next()
+        .is_some_and(|x| x.starts_with(DOC_TEST_PREFIX))
+}
+
+/// Adds the doc tests of the items of the unit as test functions of their modules.
+pub fn add_doc_tests(unit: &mut CompilationUnit) -> Result<()> {
+    let mut sources = HashMap::new();
+
+    for module in &mut unit.modules {
+        add_module_doc_tests(module, &mut sources)?;
+    }
+
+    Ok(())
+}
+
+fn add_module_doc_tests(module: &mut Module, sources: &mut HashMap<PathBuf, String>) -> Result<()> {
+    let mut documented: Vec<(&str, &DocString)> = Vec::new();
+
+    if let Some(doc) = &module.doc_string {
+        documented.push((&module.name.name, doc));
+    }
+
+    for item in &module.contents {
+        match item {
+            ModuleDefItem::Constant(x) => {
+                documented.extend(x.decl.doc_string.as_ref().map(|d| (&*x.decl.name.name, d)));
+            }
+            ModuleDefItem::Function(x) => {
+                documented.extend(x.decl.doc_string.as_ref().map(|d| (&*x.decl.name.name, d)));
+            }
+            ModuleDefItem::FunctionDecl(x) => {
+                documented.extend(x.doc_string.as_ref().map(|d| (&*x.name.name, d)));
+            }
+            ModuleDefItem::Impl(x) => {
+                for method in &x.methods {
+                    documented.extend(
+                        method
+                            .decl
+                            .doc_string
+                            .as_ref()
+                            .map(|d| (&*method.decl.name.name, d)),
+                    );
+                }
+            }
+            ModuleDefItem::ImplTrait(x) => {
+                for method in &x.methods {
+                    documented.extend(
+                        method
+                            .decl
+                            .doc_string
+                            .as_ref()
+                            .map(|d| (&*method.decl.name.name, d)),
+                    );
+                }
+            }
+            ModuleDefItem::Struct(x) => {
+                documented.extend(x.doc_string.as_ref().map(|d| (&*x.name.name, d)));
+            }
+            ModuleDefItem::Type(x) => {
+                documented.extend(x.doc_string.as_ref().map(|d| (&*x.name.name, d)));
+            }
+            ModuleDefItem::Trait(x) => {
+                documented.extend(x.doc_string.as_ref().map(|d| (&*x.name.name, d)));
+            }
+            ModuleDefItem::Macro(x) => {
+                documented.extend(x.doc_string.as_ref().map(|d| (&*x.name.name, d)));
+            }
+            ModuleDefItem::ExternBlock(_)
+            | ModuleDefItem::Union(_)
+            | ModuleDefItem::Enum(_)
+            | ModuleDefItem::Module(_)
+            | ModuleDefItem::ExternalModule(_)
+            | ModuleDefItem::Import(_) => {}
+        }
+    }
+
+    let mut tests = Vec::new();
+    if !documented.is_empty() {
+        if !sources.contains_key(&module.file_path) {
+            let source = crate::parser::read_source(&module.file_path)?;
+            sources.insert(module.file_path.clone(), source);
+        }
+        let source = &sources[&module.file_path];
+
+        for (item, doc) in documented {
+            for block in code_blocks(source, doc) {
+                tests.push(doc_test(&module.file_path, source, item, &block)?);
+            }
+        }
+    }
+
+    for item in &mut module.contents {
+        if let ModuleDefItem::Module(submodule) = item {
+            add_module_doc_tests(Arc::make_mut(submodule), sources)?;
+        }
+    }
+
+    module.contents.extend(tests);
+
+    Ok(())
+}
+
+/// A code block of a doc comment.
+struct CodeBlock {
+    /// The line of the opening fence, starting at 1.
+    line: usize,
+    /// The byte ranges of the code lines in the file, without the `///`.
+    lines: Vec<(usize, usize)>,
+    span: Span,
+}
+
+/// The code blocks of the doc comment that are run.
+fn code_blocks(source: &str, doc: &DocString) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    // The block being read and whether it's run.
+    let mut current: Option<(CodeBlock, bool)> = None;
+
+    let mut offset = doc.span.from;
+    for line in source[doc.span.from..doc.span.to].split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+
+        let Some(position) = line.find("///") else {
+            continue;
+        };
+        let content_start = start + position + 3;
+        let content = line[position + 3..].trim_end_matches(['\n', '\r']);
+
+        if let Some(info) = content.trim_start().strip_prefix("```") {
+            match current.take() {
+                Some((mut block, run)) => {
+                    if run {
+                        block.span.to = start + line.trim_end().len();
+                        blocks.push(block);
+                    }
+                }
+                None => {
+                    let info = info.trim();
+                    let run = info.is_empty() || RUN_INFO_STRINGS.contains(&info);
+                    let block = CodeBlock {
+                        line: source[..start].lines().count() + 1,
+                        lines: Vec::new(),
+                        span: Span::new(start + position, start + line.trim_end().len()),
+                    };
+                    current = Some((block, run));
+                }
+            }
+        } else if let Some((block, _)) = &mut current {
+            block
+                .lines
+                .push((content_start, content_start + content.len()));
+        }
+    }
+
+    // A block left open ends with the doc comment.
+    if let Some((mut block, true)) = current {
+        block.span.to = doc.span.to;
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+/// The test function running the code block.
+fn doc_test(
+    path: &std::path::Path,
+    source: &str,
+    item: &str,
+    block: &CodeBlock,
+) -> Result<ModuleDefItem> {
+    // The code is parsed in place, with the rest of the file blanked out, so its spans point into
+    // the file.
+    let mut input = source
+        .bytes()
+        .map(|x| if x == b'\n' { b'\n' } else { b' ' })
+        .collect::<Vec<u8>>();
+    for (from, to) in &block.lines {
+        input[*from..*to].copy_from_slice(&source.as_bytes()[*from..*to]);
+    }
+    let mut input = String::from_utf8(input).expect("the code is copied along char boundaries");
+    input.push_str("\nreturn 0;\n");
+
+    let program = ProgramSource::new(input, path);
+    let mut body = match crate::parser::parse_statements(&program) {
+        Ok(x) => x,
+        Err(error) => {
+            return Err(CompileError::Parse {
+                diagnostic: Box::new(error.to_diagnostic(&program)),
+                input: program.input,
+            }
+            .into());
+        }
+    };
+
+    // The block returns its own exit code.
+    if let Some(appended) = body.last().map(Statement::span) {
+        if unreachable_statements(&body).contains(&appended) {
+            body.pop();
+        }
+    }
+
+    let span = block.span;
+    let ident = |name: String| Ident { name, span };
+    let decl = FunctionDecl {
+        doc_string: None,
+        generic_params: Vec::new(),
+        name: ident(format!("{DOC_TEST_PREFIX}{item}_{}", block.line)),
+        params: Vec::new(),
+        ret_type: Some(TypeDescriptor::Type {
+            name: TypeName {
+                path: Vec::new(),
+                name: ident("i32".to_string()),
+                generics: Vec::new(),
+                span,
+            },
+            span,
+        }),
+        is_extern: false,
+        is_const: false,
+        is_unsafe: false,
+        is_async: false,
+        is_pub: false,
+        vis_span: Span::new(span.from, span.from),
+        attributes: vec![Attribute {
+            name: "test".to_string(),
+            value: None,
+            args: Vec::new(),
+            span,
+        }],
+        span,
+    };
+
+    Ok(ModuleDefItem::Function(Arc::new(FunctionDef {
+        decl: Arc::new(decl),
+        body,
+        is_prebuilt: false,
+        span,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doc_tests_from_code_blocks() {
+        use crate::driver::{parse_file, sources::SourceExtensions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.con");
+        std::fs::write(
+            &path,
+            "mod Math {
+    /// Adds two numbers:
+    /// ```
+    /// let sum: i32 = add(1, 2);
+    /// if sum != 3 {
+    ///     return 1;
+    /// }
+    /// ```
+    ///
+    /// ```text
+    /// add(a, b) = a + b
+    /// ```
+    pub fn add(a: i32, b: i32) -> i32 {
+        return a + b;
+    }
+}
+",
+        )
+        .unwrap();
+
+        let mut unit = parse_file(path.clone(), &SourceExtensions::default()).unwrap();
+        add_doc_tests(&mut unit).unwrap();
+        let ir = lower_compile_units(&[unit]).expect("failed to lower");
+
+        let tests: Vec<&str> = ir
+            .tests
+            .iter()
+            .filter_map(|x| ir.functions[*x].as_ref())
+            .filter_map(|x| x.debug_name.as_deref())
+            .collect();
+        assert_eq!(tests.len(), 1, "{:?}", tests);
+        assert!(tests[0].ends_with("doctest_add_3"), "{:?}", tests);
+        assert!(is_doc_test(tests[0]));
+
+        // The errors of the code blocks point into the file.
+        std::fs::write(&path, "mod Math {\n    /// ```\n    /// let x: i32 = ;\n    /// ```\n    fn f() {\n        return;\n    }\n}\n").unwrap();
+        let mut unit = parse_file(path, &SourceExtensions::default()).unwrap();
+        let error = add_doc_tests(&mut unit).unwrap_err();
+        let Some(crate::driver::CompileError::Parse { diagnostic, .. }) = error.downcast_ref()
+        else {
+            panic!("{error:?}");
+        };
+        assert!(
+            diagnostic.span.from > "mod Math {\n    /// ```\n".len(),
+            "{diagnostic:?}"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! `concrete fix`: applies the machine applicable suggestions of the diagnostics to the source files.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use owo_colors::OwoColorize;
+
+use crate::{
+    ast::{CompilationUnit, cfg::Cfg},
+    check::{
+        lints::LintLevels,
+        suggestions::{Suggestion, apply_suggestions},
+    },
+    ir::lowering::lower_compile_units,
+};
+
+use super::{
+    CompileError, DiagnosticArgs, build_script::BuildScripts, compile_project,
+    config::DEFAULT_FEATURE, find_config_path, lockfile::Lockfile, parse_file,
+    sources::SourceExtensions, sysroot::Sysroot, workspace,
+};
+
+/// Fixing some errors may uncover others, like the ones of the items lowering stopped at, so the
+/// files are checked again after applying fixes, up to this many times.
+const MAX_FIX_PASSES: usize = 10;
+
+pub fn handle_fix(path: Option<PathBuf>) -> Result<()> {
+    // Only the files of the project being fixed are touched, not its dependencies.
+    let root = match &path {
+        Some(input) => input
+            .parent()
+            .context("could not get the file parent dir")?
+            .to_path_buf(),
+        None => find_config_path()?
+            .parent()
+            .context("couldn't get config parent dir")?
+            .join("src"),
+    };
+
+    let mut total = 0;
+
+    for _ in 0..MAX_FIX_PASSES {
+        let mut suggestions: HashMap<PathBuf, Vec<Suggestion>> = HashMap::new();
+        let mut add = |primary: &Path, suggestion: Suggestion| {
+            suggestions
+                .entry(suggestion.file(primary).to_path_buf())
+                .or_default()
+                .push(suggestion);
+        };
+
+        match parse_units(path.as_ref()) {
+            Ok(compile_units) => match lower_compile_units(&compile_units) {
+                Ok(ir) => {
+                    for warning in ir.warnings {
+                        if let Some(suggestion) = warning.suggestion {
+                            add(&warning.path, suggestion);
+                        }
+                    }
+                }
+                Err(error) => {
+                    let path = error.primary_span().1.clone();
+                    for suggestion in error.suggestions() {
+                        add(&path, suggestion);
+                    }
+                }
+            },
+            // A syntax error may be fixed too, like a missing `;`.
+            Err(error) => match error.downcast_ref::<CompileError>() {
+                Some(CompileError::Parse { diagnostic, .. }) => {
+                    for suggestion in diagnostic.suggestions.iter().cloned() {
+                        add(&diagnostic.path, suggestion);
+                    }
+                }
+                _ => return Err(error),
+            },
+        }
+
+        let applied = apply_file_suggestions(&root, suggestions)?;
+
+        if applied == 0 {
+            break;
+        }
+
+        total += applied;
+    }
+
+    println!(
+        "   {} {} suggestion(s), build again to see the remaining diagnostics",
+        "Applied".green().bold(),
+        total
+    );
+
+    Ok(())
+}
+
+/// The compile units of the file, or of the project when there's none. The build scripts of the
+/// project run as in a build, for the cfg options they set and the modules they generate.
+fn parse_units(path: Option<&PathBuf>) -> Result<Vec<CompilationUnit>> {
+    let Some(input) = path else {
+        let config_path = find_config_path()?;
+        let base_dir = config_path
+            .parent()
+            .context("couldn't get config parent dir")?;
+        let root_dir = workspace::root_dir(base_dir)?;
+        let mut lockfile = Lockfile::read(&root_dir)?;
+        let sysroot = Sysroot::locate(None)?;
+        let mut scripts = BuildScripts::new(
+            &root_dir.join("build"),
+            sysroot.clone(),
+            DiagnosticArgs::default(),
+        );
+        let compile_units = compile_project(
+            base_dir,
+            &root_dir,
+            &mut HashMap::new(),
+            &mut lockfile,
+            &DiagnosticArgs::default(),
+            &LintLevels::default(),
+            &Cfg {
+                features: BTreeSet::from([DEFAULT_FEATURE.to_string()]),
+                ..Cfg::default()
+            },
+            sysroot.as_ref(),
+            Some(&mut scripts),
+            None,
+        )?;
+        lockfile.write(&root_dir)?;
+        return Ok(compile_units);
+    };
+
+    Ok(vec![parse_file(
+        input.clone(),
+        &SourceExtensions::default(),
+    )?])
+}
+
+fn apply_file_suggestions(
+    root: &Path,
+    suggestions: HashMap<PathBuf, Vec<Suggestion>>,
+) -> Result<usize> {
+    let mut total = 0;
+
+    for (file, suggestions) in suggestions {
+        if !file.starts_with(root) {
+            continue;
+        }
+
+        let mut source = std::fs::read_to_string(&file)
+            .with_context(|| format!("failed to read {}", file.display()))?;
+        let applied = apply_suggestions(&mut source, &suggestions);
+
+        if applied > 0 {
+            std::fs::write(&file, source)
+                .with_context(|| format!("failed to write {}", file.display()))?;
+            println!(
+                "   {} {} ({} fixes)",
+                "Fixed".green().bold(),
+                file.display(),
+                applied
+            );
+            total += applied;
+        }
+    }
+
+    Ok(total)
+}
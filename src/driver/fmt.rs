@@ -0,0 +1,246 @@
+//! `concrete fmt`: formats the source files of a project, or with `--check` prints how they would
+//! change and fails if any isn't formatted, for CI.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use owo_colors::OwoColorize;
+
+use super::{
+    DiagnosticArgs, config::Config, find_config_path, sources::SourceExtensions,
+    workspace::Workspace,
+};
+use crate::parser::{
+    ProgramSource,
+    format::{FormatError, format_source},
+    read_source,
+};
+
+/// The unchanged lines shown around the changes of a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// The most pairs of changed lines compared to find the smallest diff.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+pub fn handle_fmt(path: Option<PathBuf>, check: bool) -> Result<()> {
+    let files = match path {
+        Some(path) if path.is_dir() => {
+            let mut files = Vec::new();
+            collect_source_files(&path, &SourceExtensions::default(), &mut files)?;
+            files
+        }
+        Some(path) => vec![path],
+        None => project_files()?,
+    };
+
+    let mut unformatted = 0;
+    let mut failed = 0;
+
+    for file in &files {
+        let input = read_source(file)?;
+        let source = ProgramSource::new(input, file);
+
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(FormatError::Syntax(error)) => {
+                let mut emitter = DiagnosticArgs::default().emitter();
+                emitter
+                    .sources_mut()
+                    .insert(file.display().to_string(), source.input.clone());
+                emitter.emit(&error.to_diagnostic(&source))?;
+                failed += 1;
+                continue;
+            }
+            Err(FormatError::Changed) => {
+                eprintln!(
+                    "{}: formatting {} would change its program, it was left as it is",
+                    "error".red().bold(),
+                    file.display()
+                );
+                failed += 1;
+                continue;
+            }
+        };
+
+        if formatted == source.input {
+            continue;
+        }
+
+        unformatted += 1;
+
+        if check {
+            print_diff(file, &source.input, &formatted);
+        } else {
+            std::fs::write(file, formatted)
+                .with_context(|| format!("failed to write {}", file.display()))?;
+            eprintln!("   {} {}", "Formatted".green().bold(), file.display());
+        }
+    }
+
+    if failed > 0 {
+        bail!("failed to format {failed} file(s)");
+    }
+
+    if check && unformatted > 0 {
+        bail!("{unformatted} file(s) are not formatted, run `concrete fmt` to format them");
+    }
+
+    Ok(())
+}
+
+/// The source files of the project, or of all the packages if it's the root of a workspace.
+fn project_files() -> Result<Vec<PathBuf>> {
+    let config_path = find_config_path()?;
+    let project_dir = config_path
+        .parent()
+        .context("couldn't get config parent dir")?;
+
+    let packages = match Workspace::find(project_dir)? {
+        Some(workspace) => workspace.select(project_dir, None)?,
+        None => vec![project_dir.to_path_buf()],
+    };
+
+    let mut files = Vec::new();
+    for package in packages {
+        let config = Config::read(&package.join("Concrete.toml"))?;
+        let extensions = SourceExtensions::with_aliases(&config.package()?.extension_aliases);
+        collect_source_files(&package.join("src"), &extensions, &mut files)?;
+    }
+
+    Ok(files)
+}
+
+fn collect_source_files(
+    dir: &Path,
+    extensions: &SourceExtensions,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory {}", dir.display()))?
+        .map(|x| x.map(|x| x.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_source_files(&path, extensions, files)?;
+        } else if extensions.is_source(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Prints the changed lines, with a few unchanged ones around them, like `rustfmt --check`.
+fn print_diff(path: &Path, original: &str, formatted: &str) {
+    let original: Vec<&str> = original.lines().collect();
+    let formatted: Vec<&str> = formatted.lines().collect();
+    let lines = diff_lines(&original, &formatted);
+
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|x| !matches!(lines[*x], DiffLine::Same(_)))
+        .collect();
+
+    if changed.is_empty() {
+        println!(
+            "Diff in {} at line {}: only the whitespace at the end of the file changes",
+            path.display(),
+            original.len()
+        );
+        return;
+    }
+
+    let mut i = 0;
+    while i < changed.len() {
+        // Changes closer than the context on both sides are shown together.
+        let mut j = i;
+        while j + 1 < changed.len() && changed[j + 1] - changed[j] <= DIFF_CONTEXT * 2 {
+            j += 1;
+        }
+
+        let start = changed[i].saturating_sub(DIFF_CONTEXT);
+        let end = (changed[j] + DIFF_CONTEXT + 1).min(lines.len());
+        let line = lines[..start]
+            .iter()
+            .filter(|x| !matches!(x, DiffLine::Added(_)))
+            .count()
+            + 1;
+
+        println!("Diff in {} at line {}:", path.display(), line);
+        for line in &lines[start..end] {
+            match line {
+                DiffLine::Same(text) => println!(" {text}"),
+                DiffLine::Removed(text) => println!("{}", format!("-{text}").red()),
+                DiffLine::Added(text) => println!("{}", format!("+{text}").green()),
+            }
+        }
+
+        i = j + 1;
+    }
+}
+
+/// The lines removed and added to turn one text into the other, keeping the longest common
+/// subsequence of lines.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(x, y)| x == y).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut lines: Vec<DiffLine> = old[..prefix].iter().map(|x| DiffLine::Same(x)).collect();
+
+    // Too many lines to compare, they're all shown as changed.
+    if old_middle.len() * new_middle.len() > MAX_DIFF_CELLS {
+        lines.extend(old_middle.iter().map(|x| DiffLine::Removed(x)));
+        lines.extend(new_middle.iter().map(|x| DiffLine::Added(x)));
+        lines.extend(old[old.len() - suffix..].iter().map(|x| DiffLine::Same(x)));
+        return lines;
+    }
+
+    // lengths[i * width + j] is the length of the longest common subsequence of old_middle[i..]
+    // and new_middle[j..].
+    let width = new_middle.len() + 1;
+    let mut lengths = vec![0usize; (old_middle.len() + 1) * width];
+    for i in (0..old_middle.len()).rev() {
+        for j in (0..new_middle.len()).rev() {
+            lengths[i * width + j] = if old_middle[i] == new_middle[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old_middle.len() || j < new_middle.len() {
+        if i < old_middle.len() && j < new_middle.len() && old_middle[i] == new_middle[j] {
+            lines.push(DiffLine::Same(old_middle[i]));
+            i += 1;
+            j += 1;
+        } else if i < old_middle.len()
+            && (j == new_middle.len() || lengths[(i + 1) * width + j] >= lengths[i * width + j + 1])
+        {
+            lines.push(DiffLine::Removed(old_middle[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new_middle[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|x| DiffLine::Same(x)));
+
+    lines
+}
@@ -0,0 +1,160 @@
+//! `concrete fuzz`: builds a fuzz target, a function marked with `#[fuzz_target]`, instrumented with
+//! sanitizer coverage and linked with libFuzzer, then runs it over its corpus, the way cargo-fuzz
+//! does for Rust crates.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail};
+use owo_colors::OwoColorize;
+
+use super::{FuzzArgs, find_config_path, handle_build_one};
+use crate::{
+    check::suggestions::unknown_value_message,
+    ir::{FnIndex, IR},
+};
+
+/// The function libFuzzer calls with each input.
+pub const ENTRY_SYMBOL: &str = "LLVMFuzzerTestOneInput";
+
+/// The symbol the `main` function of the program gets in fuzzing builds, libFuzzer defines its own.
+pub const RENAMED_MAIN_SYMBOL: &str = "__concrete_main";
+
+/// Makes the fuzz target with the given name the entry point libFuzzer calls. The name is its full
+/// path, like `Parser::fuzz_header`, or only the function name if no other fuzz target has it.
+pub fn use_fuzz_target(ir: &mut IR, name: &str) -> Result<()> {
+    let targets: Vec<(FnIndex, &str)> = ir
+        .fuzz_targets
+        .iter()
+        .filter_map(|id| {
+            let function = ir.functions[*id].as_ref()?;
+            Some((*id, function.debug_name.as_deref()?))
+        })
+        .collect();
+
+    let mut matches: Vec<&(FnIndex, &str)> = targets.iter().filter(|(_, x)| *x == name).collect();
+    if matches.is_empty() {
+        matches = targets
+            .iter()
+            .filter(|(_, x)| x.rsplit("::").next() == Some(name))
+            .collect();
+    }
+
+    let target = match matches.as_slice() {
+        [(id, _)] => *id,
+        [] => bail!(
+            "{}",
+            unknown_value_message("fuzz target", name, targets.iter().map(|(_, x)| *x))
+        ),
+        _ => bail!(
+            "the fuzz target `{}` is ambiguous, it can be {}, use its full path",
+            name,
+            matches
+                .iter()
+                .map(|(_, x)| format!("`{x}`"))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        ),
+    };
+
+    for (_, function) in ir.functions.iter_mut() {
+        if let Some(function) = function {
+            if function.name == "main" && !function.is_extern {
+                function.name = RENAMED_MAIN_SYMBOL.to_string();
+            }
+        }
+    }
+
+    if let Some(function) = ir.functions[target].as_mut() {
+        function.name = ENTRY_SYMBOL.to_string();
+    }
+
+    Ok(())
+}
+
+/// The name of the directories and binary of a fuzz target.
+pub fn dir_name(target: &str) -> String {
+    target.replace("::", "-")
+}
+
+pub fn handle_fuzz(args: FuzzArgs) -> Result<()> {
+    let fuzz_dir = match &args.build.path {
+        Some(_) => std::env::current_dir()?,
+        None => find_config_path()?
+            .parent()
+            .context("couldn't get config parent dir")?
+            .to_path_buf(),
+    }
+    .join("fuzz");
+    let corpus = args
+        .corpus
+        .unwrap_or_else(|| fuzz_dir.join("corpus").join(dir_name(&args.target)));
+    let artifacts = args
+        .artifacts
+        .unwrap_or_else(|| fuzz_dir.join("artifacts").join(dir_name(&args.target)));
+
+    let file = args
+        .build
+        .path
+        .as_ref()
+        .map(|x| format!(" {}", x.display()))
+        .unwrap_or_default();
+
+    let mut build = args.build;
+    build.fuzz_target = Some(args.target.clone());
+    let (binary, _) = handle_build_one(build)?;
+
+    let mut fuzzer = Command::new(&binary);
+
+    if args.reproduce.is_empty() {
+        for dir in [&corpus, &artifacts] {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+
+        // The trailing slash makes libFuzzer write the crashes inside the directory.
+        fuzzer
+            .arg(format!("-artifact_prefix={}/", artifacts.display()))
+            .arg(&corpus);
+    } else {
+        // Given files instead of directories, libFuzzer runs the target once with each of them.
+        fuzzer.args(&args.reproduce);
+    }
+
+    fuzzer.args(&args.fuzzer_args);
+
+    eprintln!("     {} {}", "Running".green().bold(), binary.display());
+    let status = fuzzer
+        .status()
+        .with_context(|| format!("failed to run {}", binary.display()))?;
+
+    if !status.success() {
+        if args.reproduce.is_empty() {
+            if let Some(crash) = newest_file(&artifacts) {
+                eprintln!(
+                    "{}: reproduce it with `concrete fuzz {}{} --reproduce {}`",
+                    "note".bold(),
+                    args.target,
+                    file,
+                    crash.display()
+                );
+            }
+        }
+
+        bail!("the fuzz target `{}` failed ({})", args.target, status);
+    }
+
+    Ok(())
+}
+
+/// The last modified file of the directory, like the crash libFuzzer just wrote.
+fn newest_file(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|x| x.ok())
+        .filter_map(|x| Some((x.metadata().ok()?.modified().ok()?, x.path())))
+        .max()
+        .map(|(_, path)| path)
+}
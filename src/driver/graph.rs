@@ -0,0 +1,619 @@
+//! `concrete graph`: prints the modules of a package and their imports, or the packages it depends
+//! on, as a DOT or Mermaid graph, to audit the architecture and spot unwanted coupling.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+use super::{
+    DiagnosticArgs, STD_PACKAGE,
+    config::{Config, DependencyOrigin, DependencySource},
+    find_config_path, find_project_files,
+    lockfile::Lockfile,
+    parse_file,
+    sources::SourceExtensions,
+    sysroot::Sysroot,
+    workspace::Workspace,
+};
+use crate::ast::{
+    CompilationUnit,
+    modules::{Module, ModuleDefItem},
+};
+use crate::check::lints::{Lint, LintLevel, LintLevels};
+
+/// The graphs that can be printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GraphKind {
+    /// The modules of the package, with the modules they import from.
+    #[default]
+    Modules,
+    /// The package and its dependencies.
+    Packages,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz.
+    #[default]
+    Dot,
+    Mermaid,
+    /// The nodes and edges, with the commits of the git dependencies, for scripts.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EdgeKind {
+    /// A module declared inside another.
+    Contains,
+    /// A module importing from another.
+    Imports,
+    /// A package depending on another.
+    DependsOn,
+}
+
+/// A directed graph, kept sorted so the output is stable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Graph {
+    /// The nodes, with whether they're outside of the package, like the modules of dependencies.
+    nodes: BTreeMap<String, bool>,
+    edges: BTreeSet<(String, String, EdgeKind)>,
+    /// The commits the git dependencies were checked out at, by their node.
+    commits: BTreeMap<String, String>,
+}
+
+impl Graph {
+    /// The graph of the modules declared in the compile units, after resolving the external
+    /// modules, and the imports between them.
+    pub fn modules(units: &[CompilationUnit]) -> Self {
+        let mut graph = Graph::default();
+        let mut paths = HashSet::new();
+
+        for unit in units {
+            for module in &unit.modules {
+                collect_module_paths(module, &[], &mut paths);
+            }
+        }
+
+        for unit in units {
+            for module in &unit.modules {
+                graph.add_module(module, &[], &paths);
+            }
+        }
+
+        graph
+    }
+
+    /// The graph of the package in the given directory and its dependencies, resolved like a build
+    /// resolves them, checking them out if they're not yet. At the root of a workspace, it's the
+    /// graph of all its packages.
+    pub fn packages(project_dir: &Path, sysroot: Option<&Sysroot>) -> Result<Self> {
+        let mut graph = Graph::default();
+
+        let (root_dir, packages) = match Workspace::find(project_dir)? {
+            Some(workspace) => (workspace.root.clone(), workspace.select(project_dir, None)?),
+            None => (project_dir.to_path_buf(), vec![project_dir.to_path_buf()]),
+        };
+
+        let mut lockfile = Lockfile::read(&root_dir)?;
+        let mut added_deps = HashMap::new();
+        // The nodes of the packages by their key, the dependencies are found before the packages
+        // depending on them.
+        let mut nodes: HashMap<Option<String>, String> = HashMap::new();
+        // The manifests are only read here, their warnings are for the build to report.
+        let mut lint_levels = LintLevels::default();
+        lint_levels.set(Lint::UnknownManifestKeys, LintLevel::Allow);
+
+        for package in packages {
+            let files = find_project_files(
+                &package,
+                &root_dir,
+                &[],
+                &mut added_deps,
+                &mut lockfile,
+                &DiagnosticArgs::default(),
+                &lint_levels,
+                sysroot,
+            )?;
+
+            for file in &files {
+                let package = file.config.package()?;
+                let mut name = format!("{} v{}", package.name, package.version);
+                // The copies of a package from other sources are told apart by their key.
+                if let Some(key) = file.package.as_ref().filter(|x| **x != package.name) {
+                    name = format!("{name} ({key})");
+                }
+                graph.add_node(&name, false);
+
+                let locked = file
+                    .package
+                    .as_ref()
+                    .filter(|x| {
+                        matches!(added_deps[*x].resolved_source, DependencySource::Git { .. })
+                    })
+                    .and_then(|x| lockfile.get(x));
+                if let Some(locked) = locked {
+                    graph.commits.insert(name.clone(), locked.commit.clone());
+                }
+
+                for dependency in &file.dependencies {
+                    let Some(dependency) = nodes.get(&Some(dependency.clone())) else {
+                        bail!(
+                            "{name} depends on `{dependency}`, which has no library, src/lib.con"
+                        );
+                    };
+                    graph.add_edge(&name, dependency, EdgeKind::DependsOn);
+                }
+
+                nodes.insert(file.package.clone(), name);
+            }
+
+            // A package with only the binaries of src/bin has no files of its own.
+            let key = added_deps
+                .values()
+                .find(|x| x.dir == package)
+                .map(|x| x.key.clone());
+            if !files.iter().any(|x| x.package == key) {
+                let config = Config::read(&package.join("Concrete.toml"))?;
+                let info = config.package()?;
+                let name = format!("{} v{}", info.name, info.version);
+                graph.add_node(&name, false);
+
+                for dependency in dependency_keys(&package, &config, &added_deps, sysroot)? {
+                    let Some(dependency) = nodes.get(&Some(dependency.clone())) else {
+                        bail!(
+                            "{name} depends on `{dependency}`, which has no library, src/lib.con"
+                        );
+                    };
+                    graph.add_edge(&name, dependency, EdgeKind::DependsOn);
+                }
+
+                nodes.insert(key, name);
+            }
+        }
+
+        lockfile.write(&root_dir)?;
+
+        Ok(graph)
+    }
+
+    /// The commit the git dependency of the node was checked out at.
+    pub fn commit(&self, node: &str) -> Option<&str> {
+        self.commits.get(node).map(String::as_str)
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (&str, &str, EdgeKind)> {
+        self.edges
+            .iter()
+            .map(|(from, to, kind)| (from.as_str(), to.as_str(), *kind))
+    }
+
+    fn add_node(&mut self, name: &str, external: bool) {
+        self.nodes.entry(name.to_string()).or_insert(external);
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str, kind: EdgeKind) {
+        self.edges.insert((from.to_string(), to.to_string(), kind));
+    }
+
+    fn add_module(&mut self, module: &Module, parents: &[String], paths: &HashSet<Vec<String>>) {
+        let mut path = parents.to_vec();
+        path.push(module.name.name.clone());
+        let name = path.join("::");
+        self.add_node(&name, false);
+
+        if !parents.is_empty() {
+            self.add_edge(&parents.join("::"), &name, EdgeKind::Contains);
+        }
+
+        for item in &module.contents {
+            match item {
+                ModuleDefItem::Module(submodule) => self.add_module(submodule, &path, paths),
+                ModuleDefItem::Import(import) => {
+                    let segments: Vec<&str> =
+                        import.module.iter().map(|x| x.name.as_str()).collect();
+                    let target = resolve_import(&path, parents, &segments, paths);
+                    let target_name = target.join("::");
+
+                    if target_name != name {
+                        self.add_node(&target_name, !paths.contains(&target));
+                        self.add_edge(&name, &target_name, EdgeKind::Imports);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph {{");
+        let _ = writeln!(out, "    node [shape=box];");
+
+        for (name, external) in &self.nodes {
+            let mut attributes = Vec::new();
+            if *external {
+                attributes.push("style=dashed".to_string());
+            }
+            if let Some(commit) = self.commit(name) {
+                attributes.push(format!("label=\"{}\\n@{}\"", escape(name), short(commit)));
+            }
+
+            if attributes.is_empty() {
+                let _ = writeln!(out, "    \"{}\";", escape(name));
+            } else {
+                let _ = writeln!(out, "    \"{}\" [{}];", escape(name), attributes.join(", "));
+            }
+        }
+
+        for (from, to, kind) in &self.edges {
+            let style = match kind {
+                EdgeKind::Contains => " [style=dashed, arrowhead=none]",
+                EdgeKind::Imports | EdgeKind::DependsOn => "",
+            };
+            let _ = writeln!(
+                out,
+                "    \"{}\" -> \"{}\"{};",
+                escape(from),
+                escape(to),
+                style
+            );
+        }
+
+        let _ = writeln!(out, "}}");
+        out
+    }
+
+    fn to_mermaid(&self) -> String {
+        // Mermaid ids can't contain `::`, so the nodes are numbered and labeled with their name.
+        let ids: BTreeMap<&str, usize> = self
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+
+        let mut out = String::new();
+        let _ = writeln!(out, "graph LR");
+
+        for (name, external) in &self.nodes {
+            let id = ids[name.as_str()];
+            let label = match self.commit(name) {
+                Some(commit) => format!("{}<br>@{}", escape(name), short(commit)),
+                None => escape(name),
+            };
+            if *external {
+                let _ = writeln!(out, "    n{}([\"{}\"])", id, label);
+            } else {
+                let _ = writeln!(out, "    n{}[\"{}\"]", id, label);
+            }
+        }
+
+        for (from, to, kind) in &self.edges {
+            let arrow = match kind {
+                EdgeKind::Contains => "-.-",
+                EdgeKind::Imports | EdgeKind::DependsOn => "-->",
+            };
+            let _ = writeln!(
+                out,
+                "    n{} {} n{}",
+                ids[from.as_str()],
+                arrow,
+                ids[to.as_str()]
+            );
+        }
+
+        out
+    }
+
+    fn to_json(&self) -> String {
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(name, external)| {
+                serde_json::json!({
+                    "name": name,
+                    "external": external,
+                    "commit": self.commit(name),
+                })
+            })
+            .collect();
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|(from, to, kind)| {
+                serde_json::json!({
+                    "from": from,
+                    "to": to,
+                    "kind": match kind {
+                        EdgeKind::Contains => "contains",
+                        EdgeKind::Imports => "imports",
+                        EdgeKind::DependsOn => "depends_on",
+                    },
+                })
+            })
+            .collect();
+
+        let graph = serde_json::json!({ "nodes": nodes, "edges": edges });
+        format!("{graph:#}\n")
+    }
+}
+
+pub fn handle_graph(
+    path: Option<PathBuf>,
+    kind: GraphKind,
+    format: GraphFormat,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let graph = match (kind, path) {
+        (GraphKind::Modules, Some(input)) => {
+            Graph::modules(&[parse_file(input, &SourceExtensions::default())?])
+        }
+        (GraphKind::Packages, Some(_)) => {
+            bail!("the package graph can only be printed for a project")
+        }
+        (kind, None) => {
+            let config_path = find_config_path()?;
+            let base_dir = config_path
+                .parent()
+                .context("couldn't get config parent dir")?;
+
+            match kind {
+                GraphKind::Packages => Graph::packages(base_dir, Sysroot::locate(None)?.as_ref())?,
+                GraphKind::Modules => {
+                    let config: Config = toml::from_str(&std::fs::read_to_string(&config_path)?)
+                        .context("failed to parse Concrete.toml")?;
+                    let extensions =
+                        SourceExtensions::with_aliases(&config.package()?.extension_aliases);
+                    let src_dir = base_dir.join("src");
+
+                    let mut units = Vec::new();
+                    for stem in ["main", "lib"] {
+                        if let Some(file) = extensions.find_file(&src_dir.join(stem))? {
+                            units.push(parse_file(file, &extensions)?);
+                        }
+                    }
+
+                    Graph::modules(&units)
+                }
+            }
+        }
+    };
+
+    let rendered = graph.render(format);
+
+    match output {
+        Some(output) => std::fs::write(&output, rendered)
+            .with_context(|| format!("failed to write {}", output.display()))?,
+        None => print!("{rendered}"),
+    }
+
+    Ok(())
+}
+
+/// The keys the dependencies of the package in the directory were added with, the way
+/// [`find_project_files`] resolves them, with the std of the sysroot unless it declares its own.
+fn dependency_keys(
+    package_dir: &Path,
+    config: &Config,
+    added_deps: &HashMap<String, DependencyOrigin>,
+    sysroot: Option<&Sysroot>,
+) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+
+    for (name, info) in &config.dependencies {
+        let resolved_source = info.resolve_source(package_dir)?;
+        if let Some(copy) = added_deps
+            .values()
+            .find(|x| x.name == *name && x.resolved_source == resolved_source)
+        {
+            keys.push(copy.key.clone());
+        }
+    }
+
+    let implicit_std = sysroot.is_some()
+        && !config.dependencies.contains_key(STD_PACKAGE)
+        && config.package()?.name != STD_PACKAGE;
+    if implicit_std {
+        if let Some(std) = added_deps.values().find(|x| x.name == STD_PACKAGE) {
+            keys.push(std.key.clone());
+        }
+    }
+
+    Ok(keys)
+}
+
+fn collect_module_paths(module: &Module, parents: &[String], paths: &mut HashSet<Vec<String>>) {
+    let mut path = parents.to_vec();
+    path.push(module.name.name.clone());
+
+    for item in &module.contents {
+        if let ModuleDefItem::Module(submodule) = item {
+            collect_module_paths(submodule, &path, paths);
+        }
+    }
+
+    paths.insert(path);
+}
+
+/// Resolves the module path of an import the way lowering does: `super` is the parent module,
+/// `root` the top level, and other names are a submodule of the importing module or else a top
+/// level module.
+fn resolve_import(
+    module: &[String],
+    parents: &[String],
+    segments: &[&str],
+    paths: &HashSet<Vec<String>>,
+) -> Vec<String> {
+    let mut segments = segments.iter();
+    let mut target = match segments.next() {
+        Some(&"super") => parents.to_vec(),
+        Some(&"root") => segments
+            .next()
+            .map(|x| vec![x.to_string()])
+            .unwrap_or_default(),
+        Some(name) => {
+            let mut submodule = module.to_vec();
+            submodule.push(name.to_string());
+
+            if paths.contains(&submodule) {
+                submodule
+            } else {
+                vec![name.to_string()]
+            }
+        }
+        None => module.to_vec(),
+    };
+
+    target.extend(segments.map(|x| x.to_string()));
+    target
+}
+
+/// The abbreviated commit, like git shows it.
+fn short(commit: &str) -> &str {
+    commit.get(..7).unwrap_or(commit)
+}
+
+fn escape(name: &str) -> String {
+    name.replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_graph_resolves_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = |dir: &Path, name: &str, version: &str, dependencies: &str| {
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            std::fs::write(
+                dir.join("Concrete.toml"),
+                format!(
+                    "[package]\nname = \"{name}\"\nversion = \"{version}\"\nlicense = \"MIT\"\n\n[dependencies]\n{dependencies}"
+                ),
+            )
+            .unwrap();
+            std::fs::write(
+                dir.join("src").join("lib.con"),
+                format!("mod {name} {{}}\n"),
+            )
+            .unwrap();
+        };
+
+        let origin = dir.path().join("json");
+        package(&origin, "json", "0.2.0", "");
+        let repo = git2::Repository::init(&origin).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "json", &tree, &[])
+            .unwrap()
+            .to_string();
+
+        package(&dir.path().join("util"), "util", "0.1.0", "");
+        let app = dir.path().join("app");
+        package(
+            &app,
+            "app",
+            "0.1.0",
+            &format!(
+                "util = {{ path = \"../util\" }}\njson = {{ git = \"{}\" }}\n",
+                origin.display()
+            ),
+        );
+
+        let graph = Graph::packages(&app, None).unwrap();
+        let edges: Vec<_> = graph.edges().collect();
+        assert_eq!(
+            edges,
+            [
+                ("app v0.1.0", "json v0.2.0", EdgeKind::DependsOn),
+                ("app v0.1.0", "util v0.1.0", EdgeKind::DependsOn),
+            ]
+        );
+        assert_eq!(graph.commit("json v0.2.0"), Some(commit.as_str()));
+        assert_eq!(graph.commit("util v0.1.0"), None);
+
+        let dot = graph.render(GraphFormat::Dot);
+        assert!(
+            dot.contains(&format!(
+                "\"json v0.2.0\" [label=\"json v0.2.0\\n@{}\"];",
+                &commit[..7]
+            )),
+            "{dot}"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&graph.render(GraphFormat::Json)).unwrap();
+        assert_eq!(json["nodes"][1]["name"], "json v0.2.0");
+        assert_eq!(json["nodes"][1]["commit"], commit.as_str());
+        assert_eq!(json["edges"][0]["kind"], "depends_on");
+    }
+
+    #[test]
+    fn package_graph_of_binaries_and_dependencies_without_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = |dir: &Path, name: &str, dependencies: &str| {
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            std::fs::write(
+                dir.join("Concrete.toml"),
+                format!(
+                    "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n\n[dependencies]\n{dependencies}"
+                ),
+            )
+            .unwrap();
+        };
+
+        let util = dir.path().join("util");
+        manifest(&util, "util", "");
+        std::fs::write(util.join("src").join("lib.con"), "mod util {}\n").unwrap();
+
+        // Only the binaries of src/bin.
+        let app = dir.path().join("app");
+        manifest(&app, "app", "util = { path = \"../util\" }\n");
+        std::fs::create_dir_all(app.join("src").join("bin")).unwrap();
+        std::fs::write(
+            app.join("src").join("bin").join("tool.con"),
+            "mod tool { fn main() -> i32 { return 0; } }\n",
+        )
+        .unwrap();
+
+        let graph = Graph::packages(&app, None).unwrap();
+        let edges: Vec<_> = graph.edges().collect();
+        assert_eq!(edges, [("app v0.1.0", "util v0.1.0", EdgeKind::DependsOn)]);
+
+        let empty = dir.path().join("empty");
+        manifest(&empty, "empty", "");
+        let cli = dir.path().join("cli");
+        manifest(&cli, "cli", "empty = { path = \"../empty\" }\n");
+        std::fs::write(
+            cli.join("src").join("main.con"),
+            "mod cli { fn main() -> i32 { return 0; } }\n",
+        )
+        .unwrap();
+
+        let error = Graph::packages(&cli, None).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("depends on `empty`, which has no library"),
+            "{error:#}"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Internal compiler errors: when the compiler panics, a report with what it was doing is written to
+//! a file and the user is asked to report it, instead of showing the raw panic.
+
+use std::{
+    backtrace::Backtrace,
+    fmt::Write as _,
+    panic::PanicHookInfo,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use owo_colors::OwoColorize;
+
+use crate::activity::current_item;
+
+const BUG_REPORT_URL: &str = "https://github.com/lambdaclass/concrete/issues/new";
+
+/// Replaces the default panic hook with one that writes an internal compiler error report.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::force_capture();
+        let report = ice_report(&panic_message(info), &backtrace);
+
+        eprintln!(
+            "{}: the compiler unexpectedly panicked, this is a bug in the compiler.",
+            "error".red().bold()
+        );
+        eprintln!("{}: {}", "note".bold(), panic_message(info));
+
+        match write_report(&report) {
+            Ok(path) => eprintln!(
+                "{}: a report was saved to {}, please attach it to a bug report at {}",
+                "note".bold(),
+                path.display(),
+                BUG_REPORT_URL
+            ),
+            Err(_) => {
+                eprintln!(
+                    "{}: please submit a bug report with the following at {}",
+                    "note".bold(),
+                    BUG_REPORT_URL
+                );
+                eprintln!("{report}");
+            }
+        }
+    }));
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|x| x.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic payload".to_string());
+
+    match info.location() {
+        Some(location) => format!("{message} at {location}"),
+        None => message,
+    }
+}
+
+/// The report of a panic, run in the panicking thread so it names the item of that thread.
+fn ice_report(message: &str, backtrace: &Backtrace) -> String {
+    let current_item = current_item().unwrap_or_else(|| "unknown".to_string());
+
+    let mut report = String::new();
+    let _ = writeln!(report, "concrete version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        report,
+        "host: {}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    );
+    let _ = writeln!(
+        report,
+        "arguments: {}",
+        std::env::args().collect::<Vec<_>>().join(" ")
+    );
+    let _ = writeln!(
+        report,
+        "thread: {}",
+        std::thread::current().name().unwrap_or("unnamed")
+    );
+    let _ = writeln!(report, "while: {current_item}");
+    let _ = writeln!(report, "panic: {message}");
+    let _ = writeln!(report, "\nbacktrace:\n{backtrace}");
+    report
+}
+
+/// Writes the report to the current directory, or the temporary one if it's not writable.
+fn write_report(report: &str) -> std::io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let name = format!("concrete-ice-{}-{}.txt", timestamp, std::process::id());
+
+    let current_dir = std::env::current_dir().map(|x| x.join(&name));
+
+    match current_dir.and_then(|path| std::fs::write(&path, report).map(|_| path)) {
+        Ok(path) => Ok(path),
+        Err(_) => {
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, report)?;
+            Ok(path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activity::set_current_item;
+
+    #[test]
+    fn report_names_the_item_of_its_thread() {
+        set_current_item("lowering function `main`");
+        std::thread::spawn(|| set_current_item("compiling function `other`"))
+            .join()
+            .unwrap();
+
+        let report = ice_report("oops at src/main.rs:1:1", &Backtrace::disabled());
+        assert!(report.contains("while: lowering function `main`\n"));
+        assert!(report.contains("panic: oops at src/main.rs:1:1\n"));
+
+        let report = std::thread::spawn(|| ice_report("oops", &Backtrace::disabled()))
+            .join()
+            .unwrap();
+        assert!(report.contains("while: unknown\n"));
+    }
+}
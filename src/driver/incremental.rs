@@ -0,0 +1,488 @@
+//! The incremental build cache: the fingerprints in `build/.fingerprints` record what each output
+//! of a project was built from, so a build only redoes the work its changes need.
+//!
+//! When no source file, option or the compiler itself changed, lowering and code generation are
+//! skipped and the output is linked again from the cached objects. Otherwise the program is
+//! lowered again, which needs all the compile units at once, but the code of a compile unit is only
+//! generated again if what its object is generated from changed: its functions and sources, the
+//! types of the program and the signatures of the functions it can call.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::TestInfo;
+use crate::{
+    ast::{
+        CompilationUnit,
+        modules::{Module, ModuleDefItem},
+    },
+    ir::{
+        AdtIndex, ConstData, ConstExpr, ConstKind, FnIndex, Function, IR, ModuleIndex, Operand,
+        Rvalue, StatementKind, TerminatorKind, Type, TypeIndex,
+    },
+};
+
+/// What an output was built from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// The hash of the source files, the options and the compiler.
+    pub sources: u64,
+    pub units: Vec<UnitFingerprint>,
+    pub tests: Vec<TestInfo>,
+    /// The native libraries the objects are linked with.
+    #[serde(default)]
+    pub libs: Vec<String>,
+}
+
+/// What the object of a compile unit was generated from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitFingerprint {
+    pub hash: u64,
+    pub object: PathBuf,
+}
+
+impl Fingerprint {
+    /// Reads the fingerprint at the given path, `None` if there's none or it can't be read, like
+    /// the ones written by other versions of the compiler.
+    pub fn read(path: &Path) -> Option<Self> {
+        let source = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&source).ok()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Whether the output can be linked again from the cached objects, the sources having the
+    /// given hash.
+    pub fn is_fresh(&self, sources: u64) -> bool {
+        self.sources == sources && self.units.iter().all(|x| x.object.exists())
+    }
+
+    /// The cached object of the compile unit, if it was generated from the same hash.
+    pub fn cached_object(&self, unit: usize, hash: u64) -> Option<&Path> {
+        self.units
+            .get(unit)
+            .filter(|x| x.hash == hash && x.object.exists())
+            .map(|x| x.object.as_path())
+    }
+}
+
+/// Hashes the source files of the compile units, the options they're compiled with and the
+/// compiler.
+pub fn sources_hash(units: &[CompilationUnit], options: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_compiler(&mut hasher);
+    options.hash(&mut hasher);
+
+    for unit in units {
+        let mut files = BTreeSet::new();
+        for module in &unit.modules {
+            collect_files(module, &mut files);
+        }
+
+        for file in files {
+            hash_file(&file, &mut hasher);
+        }
+    }
+
+    hasher.finish()
+}
+
+/// Hashes what the object of each compile unit of the program is generated from.
+pub fn unit_hashes(ir: &IR, options: &str) -> Vec<u64> {
+    // What every unit sees: the layout of the types and the signatures of the functions.
+    let mut common = DefaultHasher::new();
+    hash_compiler(&mut common);
+    options.hash(&mut common);
+
+    for (idx, ty) in ir.types.iter() {
+        format!("{idx:?} {ty:?}").hash(&mut common);
+    }
+
+    // Only the parts of the ADTs that are kept in order, their name maps aren't.
+    for (idx, adt) in ir.aggregates.iter() {
+        let Some(adt) = adt else {
+            continue;
+        };
+
+        format!("{idx:?} {} {:?}", adt.name, adt.kind).hash(&mut common);
+        for variant in &adt.variants {
+            format!(
+                "{} {:?} {:?}",
+                variant.name, variant.fields, variant.discriminant
+            )
+            .hash(&mut common);
+        }
+    }
+
+    for (idx, constant) in ir.constants.iter() {
+        format!("{idx:?} {constant:?}").hash(&mut common);
+    }
+
+    for (idx, function) in ir.functions.iter() {
+        if let Some(function) = function {
+            format!(
+                "{idx:?} {} {:?} {:?} {}",
+                function.name, function.args, function.ret_ty, function.is_extern
+            )
+            .hash(&mut common);
+        }
+    }
+
+    let common = common.finish();
+
+    ir.compile_units
+        .iter()
+        .map(|modules| {
+            let mut hasher = DefaultHasher::new();
+            common.hash(&mut hasher);
+            hash_unit_files(ir, modules, &mut hasher);
+
+            for (idx, function) in ir.functions.iter() {
+                if let Some(function) = function {
+                    if modules.contains(&ir.top_level_module(function.module_idx)) {
+                        format!("{idx:?} {function:?}").hash(&mut hasher);
+                    }
+                }
+            }
+
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Hashes what the object of each compile unit of the program is generated from like
+/// [`unit_hashes`], but only from the unit itself and the options its code is generated with, so a
+/// unit of the std or of a dependency hashes the same in the builds of every program using it. The
+/// indices of the types and functions differ between programs, so what they refer to is hashed in
+/// their place.
+pub fn shared_unit_hashes(ir: &IR, options: &str) -> Vec<u64> {
+    ir.compile_units
+        .iter()
+        .map(|modules| {
+            let mut unit = UnitHasher {
+                ir,
+                hasher: DefaultHasher::new(),
+                types: HashMap::new(),
+            };
+            hash_compiler(&mut unit.hasher);
+            options.hash(&mut unit.hasher);
+            hash_unit_files(ir, modules, &mut unit.hasher);
+
+            // By name, the instances of the generic functions are added in the order other units
+            // use them.
+            let mut functions: Vec<&Function> = ir
+                .functions
+                .iter()
+                .filter_map(|(_, function)| function.as_ref())
+                .filter(|x| modules.contains(&ir.top_level_module(x.module_idx)))
+                .collect();
+            functions.sort_by(|a, b| a.name.cmp(&b.name));
+            for function in functions {
+                unit.function(function);
+            }
+
+            unit.hasher.finish()
+        })
+        .collect()
+}
+
+/// The sources give the lines of the debug info locations.
+fn hash_unit_files(ir: &IR, modules: &[ModuleIndex], hasher: &mut impl Hasher) {
+    let files: BTreeSet<&Path> = ir
+        .modules
+        .iter()
+        .filter(|(idx, _)| modules.contains(&ir.top_level_module(*idx)))
+        .map(|(_, module)| module.file_path.as_path())
+        .collect();
+    for file in files {
+        hash_file(file, hasher);
+    }
+}
+
+/// Hashes the functions of a compile unit with the types and functions they refer to in place of
+/// their indices.
+struct UnitHasher<'a> {
+    ir: &'a IR,
+    hasher: DefaultHasher,
+    types: HashMap<TypeIndex, String>,
+}
+
+impl UnitHasher<'_> {
+    fn function(&mut self, function: &Function) {
+        let module = &self.ir.modules[function.module_idx];
+        format!(
+            "{} {:?} {} {} {:?} {:?} {} {:?} {:?} {} {}",
+            function.name,
+            function.debug_name,
+            function.is_extern,
+            function.is_pub,
+            function.is_intrinsic,
+            function.inline,
+            function.is_cold,
+            function.span,
+            function.scopes,
+            module.name,
+            module.file_path.display()
+        )
+        .hash(&mut self.hasher);
+
+        for ty in function.args.iter().chain([&function.ret_ty]) {
+            self.ty(*ty);
+        }
+
+        for local in &function.locals {
+            format!(
+                "{:?} {:?} {:?} {} {:?}",
+                local.span, local.debug_name, local.kind, local.mutable, local.scope
+            )
+            .hash(&mut self.hasher);
+            self.ty(local.ty);
+        }
+
+        for block in &function.basic_blocks {
+            for statement in &block.statements {
+                format!("{:?}", statement.span).hash(&mut self.hasher);
+                match &statement.kind {
+                    StatementKind::Assign(place, value) => {
+                        format!("assign {place:?}").hash(&mut self.hasher);
+                        self.rvalue(value);
+                    }
+                    kind => format!("{kind:?}").hash(&mut self.hasher),
+                }
+            }
+
+            format!("{:?}", block.terminator.span).hash(&mut self.hasher);
+            match &block.terminator.kind {
+                TerminatorKind::Call {
+                    func,
+                    args,
+                    destination,
+                    target,
+                } => {
+                    format!("call {destination:?} {target:?}").hash(&mut self.hasher);
+                    self.callee(*func);
+                    args.iter().for_each(|x| self.rvalue(x));
+                }
+                TerminatorKind::TailCall { func, args } => {
+                    "tail call".hash(&mut self.hasher);
+                    self.callee(*func);
+                    args.iter().for_each(|x| self.rvalue(x));
+                }
+                TerminatorKind::BoundsCheck {
+                    cond,
+                    index,
+                    len,
+                    target,
+                } => {
+                    format!("bounds check {target}").hash(&mut self.hasher);
+                    self.operand(cond);
+                    self.operand(index);
+                    self.operand(len);
+                }
+                TerminatorKind::OverflowCheck { cond, op, target } => {
+                    format!("overflow check {op:?} {target}").hash(&mut self.hasher);
+                    self.operand(cond);
+                }
+                TerminatorKind::UndefinedCheck { check, target } => {
+                    format!("undefined check {target}").hash(&mut self.hasher);
+                    std::mem::discriminant(check).hash(&mut self.hasher);
+                    self.operand(check.operand());
+                }
+                TerminatorKind::SwitchInt {
+                    discriminator,
+                    targets,
+                } => {
+                    format!("switch {targets:?}").hash(&mut self.hasher);
+                    self.operand(discriminator);
+                }
+                kind => format!("{kind:?}").hash(&mut self.hasher),
+            }
+        }
+    }
+
+    /// What a call needs of the function: its symbol and signature.
+    fn callee(&mut self, idx: FnIndex) {
+        let Some(function) = self.ir.functions[idx].as_ref() else {
+            return;
+        };
+        format!(
+            "{} {} {:?}",
+            function.name, function.is_extern, function.is_intrinsic
+        )
+        .hash(&mut self.hasher);
+
+        for ty in function.args.iter().chain([&function.ret_ty]) {
+            self.ty(*ty);
+        }
+    }
+
+    fn rvalue(&mut self, value: &Rvalue) {
+        match value {
+            Rvalue::Use(operand) => self.operand(operand),
+            Rvalue::LogicOp(op, (a, b)) => self.operands(format!("logic {op:?}"), a, b),
+            Rvalue::BinaryOp(op, (a, b)) => self.operands(format!("binary {op:?}"), a, b),
+            Rvalue::Overflows(op, (a, b)) => self.operands(format!("overflows {op:?}"), a, b),
+            Rvalue::UnaryOp(op, operand) => {
+                format!("{op:?}").hash(&mut self.hasher);
+                self.operand(operand);
+            }
+            Rvalue::Ref(..) | Rvalue::Move(_) | Rvalue::Len(_) => {
+                format!("{value:?}").hash(&mut self.hasher)
+            }
+            Rvalue::Cast(operand, ty, span) => {
+                format!("cast {span:?}").hash(&mut self.hasher);
+                self.operand(operand);
+                self.ty(*ty);
+            }
+            Rvalue::Slice(place, start, end, ty) => {
+                format!("slice {place:?}").hash(&mut self.hasher);
+                self.operand(start);
+                self.operand(end);
+                self.ty(*ty);
+            }
+        }
+    }
+
+    fn operands(&mut self, operation: String, a: &Operand, b: &Operand) {
+        operation.hash(&mut self.hasher);
+        self.operand(a);
+        self.operand(b);
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Place(place) => format!("{place:?}").hash(&mut self.hasher),
+            Operand::Const(data) => self.constant(data),
+        }
+    }
+
+    fn constant(&mut self, data: &ConstData) {
+        format!("const {:?}", data.span).hash(&mut self.hasher);
+        self.ty(data.ty);
+        match &data.data {
+            ConstKind::Value(value) => format!("{value:?}").hash(&mut self.hasher),
+            ConstKind::Expr(expr) => match expr.as_ref() {
+                ConstExpr::Binop(op, a, b) => {
+                    format!("{op:?}").hash(&mut self.hasher);
+                    self.constant(a);
+                    self.constant(b);
+                }
+                ConstExpr::UnOp(op, value) => {
+                    format!("{op:?}").hash(&mut self.hasher);
+                    self.constant(value);
+                }
+                ConstExpr::FunctionCall(function, args) => {
+                    self.constant(function);
+                    args.iter().for_each(|x| self.constant(x));
+                }
+            },
+        }
+    }
+
+    fn ty(&mut self, idx: TypeIndex) {
+        if !self.types.contains_key(&idx) {
+            let description = self.describe_type(idx, &mut Vec::new());
+            self.types.insert(idx, description);
+        }
+        self.types[&idx].hash(&mut self.hasher);
+    }
+
+    /// The layout of the type, the ADTs already being described only by their name.
+    fn describe_type(&self, idx: TypeIndex, adts: &mut Vec<AdtIndex>) -> String {
+        let Some(ty) = self.ir.types[idx].as_ref() else {
+            return "unknown".to_string();
+        };
+
+        match ty {
+            Type::Array(element, len) => {
+                format!("[{}; {:?}]", self.describe_type(*element, adts), len.data)
+            }
+            Type::Slice(element) => format!("[{}]", self.describe_type(*element, adts)),
+            Type::Ref(inner, mutability) => {
+                format!("&{mutability:?} {}", self.describe_type(*inner, adts))
+            }
+            Type::Ptr(inner, mutability) => {
+                format!("*{mutability:?} {}", self.describe_type(*inner, adts))
+            }
+            Type::Tuple(types) => {
+                let types: Vec<String> =
+                    types.iter().map(|x| self.describe_type(*x, adts)).collect();
+                format!("({})", types.join(", "))
+            }
+            Type::Adt(adt) => self.describe_adt(*adt, adts),
+            ty => format!("{ty:?}"),
+        }
+    }
+
+    fn describe_adt(&self, idx: AdtIndex, adts: &mut Vec<AdtIndex>) -> String {
+        let Some(body) = self.ir.aggregates[idx].as_ref() else {
+            return "unknown".to_string();
+        };
+        if adts.contains(&idx) {
+            return body.name.clone();
+        }
+
+        adts.push(idx);
+        let mut description = format!("{} {:?} {:?}", body.name, body.kind, body.lang_item);
+        for (i, variant) in body.variants.iter().enumerate() {
+            description.push_str(&format!(
+                " {} {:?} {{",
+                variant.name,
+                body.checked_discriminant(i)
+            ));
+            for field in &variant.fields {
+                description.push_str(&format!(
+                    " {} {}: {},",
+                    field.name,
+                    field.is_pub,
+                    self.describe_type(field.ty, adts)
+                ));
+            }
+            description.push_str(" }");
+        }
+        adts.pop();
+
+        description
+    }
+}
+
+/// The hasher is only stable within a build of the compiler, so the fingerprints include it.
+pub(crate) fn hash_compiler(hasher: &mut impl Hasher) {
+    env!("CARGO_PKG_VERSION").hash(hasher);
+
+    let modified = std::env::current_exe()
+        .and_then(|x| x.metadata())
+        .and_then(|x| x.modified())
+        .ok();
+    modified.hash(hasher);
+}
+
+fn hash_file(path: &Path, hasher: &mut impl Hasher) {
+    path.hash(hasher);
+    // A file that can't be read hashes differently than any content.
+    std::fs::read(path).ok().hash(hasher);
+}
+
+pub(crate) fn collect_files(module: &Module, files: &mut BTreeSet<PathBuf>) {
+    files.insert(module.file_path.clone());
+
+    for item in &module.contents {
+        if let ModuleDefItem::Module(submodule) = item {
+            collect_files(submodule, files);
+        }
+    }
+}
@@ -0,0 +1,363 @@
+//! JavaScript bindings of a library, so its public functions can be called from JS hosts when it's
+//! compiled to WebAssembly, without writing the glue by hand.
+//!
+//! The bindings are an ES module instantiating the wasm module and exposing its functions under
+//! their Concrete paths, like `lib.Math.add(1, 2)`, with a TypeScript declaration file describing
+//! their signatures.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+};
+
+use crate::ir::{FloatTy, Function, IR, IntTy, Type, TypeIndex, UintTy};
+
+/// Words that can't be used as parameter names in JavaScript, names using them get a `_` appended.
+const RESERVED_WORDS: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// The generated bindings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsBindings {
+    /// The ES module instantiating the library, written to a `.js` file.
+    pub javascript: String,
+    /// The declarations of the module, written to a `.d.ts` file.
+    pub typescript: String,
+}
+
+/// How a wasm value is seen from JavaScript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsType {
+    Void,
+    Boolean,
+    Number,
+    /// An `u32`, which wasm returns as a signed `i32`.
+    Unsigned,
+    BigInt,
+    UnsignedBigInt,
+    /// An address in the memory of the module.
+    Pointer,
+}
+
+impl JsType {
+    fn from_ir(ir: &IR, ty: TypeIndex) -> Option<Self> {
+        Some(match ir.types[ty].as_ref()? {
+            Type::Unit => JsType::Void,
+            Type::Bool => JsType::Boolean,
+            Type::Char
+            | Type::Int(IntTy::I8 | IntTy::I16 | IntTy::I32)
+            | Type::Uint(UintTy::U8 | UintTy::U16)
+            | Type::Float(FloatTy::F32 | FloatTy::F64) => JsType::Number,
+            Type::Uint(UintTy::U32) => JsType::Unsigned,
+            Type::Int(IntTy::I64) => JsType::BigInt,
+            Type::Uint(UintTy::U64) => JsType::UnsignedBigInt,
+            Type::Ref(..) | Type::Ptr(..) => JsType::Pointer,
+            Type::Int(IntTy::I128)
+            | Type::Uint(UintTy::U128)
+            | Type::String
+            | Type::Array(..)
+            | Type::Slice(_)
+            | Type::Adt(_)
+            | Type::Tuple(_) => return None,
+        })
+    }
+
+    fn typescript(&self) -> &'static str {
+        match self {
+            JsType::Void => "void",
+            JsType::Boolean => "boolean",
+            JsType::Number | JsType::Unsigned | JsType::Pointer => "number",
+            JsType::BigInt | JsType::UnsignedBigInt => "bigint",
+        }
+    }
+
+    /// Converts the value returned by wasm to this type.
+    fn convert_return(&self, call: &str) -> String {
+        match self {
+            JsType::Boolean => format!("{call} !== 0"),
+            JsType::Unsigned | JsType::Pointer => format!("{call} >>> 0"),
+            JsType::UnsignedBigInt => format!("BigInt.asUintN(64, {call})"),
+            JsType::Void | JsType::Number | JsType::BigInt => call.to_string(),
+        }
+    }
+}
+
+/// An exported function.
+#[derive(Debug)]
+struct Export {
+    symbol: String,
+    params: Vec<(String, JsType)>,
+    ret: JsType,
+}
+
+#[derive(Debug)]
+enum Node {
+    Module(BTreeMap<String, Node>),
+    Function(Export),
+}
+
+/// Generates the bindings of the public functions of the library.
+///
+/// Functions that can't be called from JavaScript, like the ones taking structs or generic ones,
+/// are skipped with a comment explaining why.
+pub fn generate_bindings(ir: &IR) -> JsBindings {
+    let functions: Vec<&Function> = ir
+        .functions
+        .iter()
+        .filter(|(id, _)| !ir.tests.contains(id) && !ir.property_tests.contains(id))
+        .filter_map(|(_, function)| function.as_ref())
+        .filter(|function| {
+            function.is_pub && !function.is_extern && function.is_intrinsic.is_none()
+        })
+        .collect();
+
+    // Generic functions have one instance for each type they're used with.
+    let mut instances: HashMap<&str, usize> = HashMap::new();
+    for function in &functions {
+        *instances.entry(debug_name(function)).or_default() += 1;
+    }
+
+    let mut root = BTreeMap::new();
+    let mut skipped = Vec::new();
+
+    for function in functions {
+        let name = debug_name(function);
+
+        if instances[name] > 1 {
+            if !skipped.iter().any(|(x, _)| x == name) {
+                skipped.push((
+                    name.to_string(),
+                    "generic functions are not exported".to_string(),
+                ));
+            }
+            continue;
+        }
+
+        match export(ir, function).and_then(|x| insert(&mut root, name, x)) {
+            Ok(()) => {}
+            Err(reason) => skipped.push((name.to_string(), reason)),
+        }
+    }
+
+    skipped.sort();
+
+    JsBindings {
+        javascript: javascript(&root, &skipped),
+        typescript: typescript(&root, &skipped),
+    }
+}
+
+fn debug_name(function: &Function) -> &str {
+    function.debug_name.as_deref().unwrap_or(&function.name)
+}
+
+fn export(ir: &IR, function: &Function) -> Result<Export, String> {
+    let params = function
+        .get_params()
+        .into_iter()
+        .enumerate()
+        .map(|(i, param)| {
+            let name = match &param.debug_name {
+                Some(name) if RESERVED_WORDS.contains(&name.as_str()) => format!("{name}_"),
+                Some(name) => name.clone(),
+                None => format!("arg{i}"),
+            };
+
+            match JsType::from_ir(ir, param.ty) {
+                Some(JsType::Void) | None => Err(format!(
+                    "the parameter `{name}` can't be passed from JavaScript, only numbers, booleans and pointers can"
+                )),
+                Some(ty) => Ok((name, ty)),
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let ret = JsType::from_ir(ir, function.ret_ty).ok_or_else(|| {
+        "the return type can't be passed to JavaScript, only numbers, booleans and pointers can"
+            .to_string()
+    })?;
+
+    Ok(Export {
+        symbol: function.name.clone(),
+        params,
+        ret,
+    })
+}
+
+/// Adds the function to the tree of modules, under its path.
+fn insert(root: &mut BTreeMap<String, Node>, path: &str, export: Export) -> Result<(), String> {
+    let mut segments: Vec<&str> = path.split("::").collect();
+    let name = segments.pop().unwrap_or(path);
+    let mut current = root;
+
+    for segment in segments {
+        let node = current
+            .entry(segment.to_string())
+            .or_insert_with(|| Node::Module(BTreeMap::new()));
+
+        current = match node {
+            Node::Module(children) => children,
+            Node::Function(_) => {
+                return Err(format!(
+                    "a function and a module are both named `{segment}`"
+                ));
+            }
+        };
+    }
+
+    if current.contains_key(name) {
+        return Err(format!("a module is also named `{name}`"));
+    }
+
+    current.insert(name.to_string(), Node::Function(export));
+    Ok(())
+}
+
+fn javascript(root: &BTreeMap<String, Node>, skipped: &[(String, String)]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "// Generated by concrete, do not edit.");
+    write_skipped(&mut out, skipped);
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "export async function instantiate(source, imports = {{}}) {{"
+    );
+    let _ = writeln!(
+        out,
+        "  const {{ instance }} = await WebAssembly.instantiate(source, imports);"
+    );
+    let _ = writeln!(out, "  const wasm = instance.exports;");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "  return {{");
+    let _ = writeln!(out, "    memory: wasm.memory,");
+    write_js_nodes(&mut out, root, 2);
+    let _ = writeln!(out, "  }};");
+    let _ = writeln!(out, "}}");
+    out
+}
+
+fn write_js_nodes(out: &mut String, nodes: &BTreeMap<String, Node>, depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for (name, node) in nodes {
+        match node {
+            Node::Module(children) => {
+                let _ = writeln!(out, "{indent}{name}: {{");
+                write_js_nodes(out, children, depth + 1);
+                let _ = writeln!(out, "{indent}}},");
+            }
+            Node::Function(export) => {
+                let params = export
+                    .params
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let call = format!("wasm[\"{}\"]({})", export.symbol, params);
+                let _ = writeln!(
+                    out,
+                    "{indent}{name}: ({params}) => {},",
+                    export.ret.convert_return(&call)
+                );
+            }
+        }
+    }
+}
+
+fn typescript(root: &BTreeMap<String, Node>, skipped: &[(String, String)]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "// Generated by concrete, do not edit.");
+    write_skipped(&mut out, skipped);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "export interface Exports {{");
+    let _ = writeln!(out, "  memory: WebAssembly.Memory;");
+    write_ts_nodes(&mut out, root, 1);
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "export function instantiate(source: BufferSource, imports?: WebAssembly.Imports): Promise<Exports>;"
+    );
+    out
+}
+
+fn write_ts_nodes(out: &mut String, nodes: &BTreeMap<String, Node>, depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for (name, node) in nodes {
+        match node {
+            Node::Module(children) => {
+                let _ = writeln!(out, "{indent}{name}: {{");
+                write_ts_nodes(out, children, depth + 1);
+                let _ = writeln!(out, "{indent}}};");
+            }
+            Node::Function(export) => {
+                let params = export
+                    .params
+                    .iter()
+                    .map(|(name, ty)| format!("{}: {}", name, ty.typescript()))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let _ = writeln!(
+                    out,
+                    "{indent}{name}({params}): {};",
+                    export.ret.typescript()
+                );
+            }
+        }
+    }
+}
+
+fn write_skipped(out: &mut String, skipped: &[(String, String)]) {
+    for (name, reason) in skipped {
+        let _ = writeln!(out, "// skipped `{name}`: {reason}");
+    }
+}
@@ -1,10 +1,72 @@
 use std::path::{Path, PathBuf};
 
-use crate::compile_unit_info::CompileUnitInfo;
+use crate::compile_unit_info::{CompileUnitInfo, Sanitizer, TargetInfo, TargetKind};
 use tracing::instrument;
 
+/// How the objects are linked, besides what the target always needs.
+#[derive(Debug, Clone, Default)]
+pub struct LinkOptions {
+    /// The linker run instead of the default one of the target, for the targets linked through
+    /// clang it's passed with `-fuse-ld`.
+    pub linker: Option<String>,
+    /// Arguments passed to the linker after the objects, like the native libraries.
+    pub args: Vec<String>,
+    /// The directories binaries look for shared libraries in when they run.
+    pub rpath: Vec<String>,
+    /// The sanitizers the code is instrumented with, the runtimes of the ones that have one are
+    /// linked when linking through clang.
+    pub sanitizers: Vec<Sanitizer>,
+}
+
+impl LinkOptions {
+    /// The arguments when running the linker directly.
+    fn ld_args(&self) -> Vec<String> {
+        let mut args = self.args.clone();
+        for dir in &self.rpath {
+            args.extend(["-rpath".to_string(), dir.clone()]);
+        }
+        args
+    }
+
+    /// The arguments when linking through clang.
+    fn driver_args(&self) -> Vec<String> {
+        let mut args = self.args.clone();
+        args.extend(self.rpath.iter().map(|dir| format!("-Wl,-rpath,{dir}")));
+        args.extend(
+            self.sanitizers
+                .iter()
+                .map(|x| format!("-fsanitize={}", x.name())),
+        );
+        args
+    }
+
+    fn use_linker(&self, clang: &mut std::process::Command) {
+        if let Some(linker) = &self.linker {
+            clang.arg(format!("-fuse-ld={linker}"));
+        }
+    }
+}
+
+/// Finds the given linker, a path or a program in the `PATH`.
+pub fn find_linker(linker: &str) -> Option<PathBuf> {
+    let path = Path::new(linker);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(linker))
+        .find(|path| path.is_file())
+}
+
 #[instrument(level = "debug")]
-pub fn link_shared_lib(objects: &[PathBuf], output_filename: &Path) -> std::io::Result<()> {
+pub fn link_shared_lib(
+    objects: &[PathBuf],
+    options: &LinkOptions,
+    output_filename: &Path,
+) -> std::io::Result<()> {
+    crate::activity::set_current_item(format_args!("linking {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
     let mut output_filename = output_filename.to_path_buf();
     let objects: Vec<_> = objects.iter().map(|x| x.display().to_string()).collect();
 
@@ -13,6 +75,7 @@ pub fn link_shared_lib(objects: &[PathBuf], output_filename: &Path) -> std::io::
             output_filename.with_extension(CompileUnitInfo::get_platform_library_ext());
     }
     let output_filename = output_filename.to_string_lossy().to_string();
+    let extra_args = options.ld_args();
 
     let args: Vec<_> = {
         #[cfg(target_os = "macos")]
@@ -25,6 +88,7 @@ pub fn link_shared_lib(objects: &[PathBuf], output_filename: &Path) -> std::io::
             ];
 
             args.extend(objects.iter().map(|x| x.as_str()));
+            args.extend(extra_args.iter().map(|x| x.as_str()));
             args.extend(&["-o", &output_filename, "-lSystem"]);
 
             args
@@ -38,6 +102,7 @@ pub fn link_shared_lib(objects: &[PathBuf], output_filename: &Path) -> std::io::
             args.extend(&["-L/lib/../lib64", "-L/usr/lib/../lib64", "-lc", "-O1"]);
 
             args.extend(objects.iter().map(|x| x.as_str()));
+            args.extend(extra_args.iter().map(|x| x.as_str()));
 
             args
         }
@@ -47,7 +112,7 @@ pub fn link_shared_lib(objects: &[PathBuf], output_filename: &Path) -> std::io::
         }
     };
 
-    let mut linker = std::process::Command::new("ld");
+    let mut linker = std::process::Command::new(options.linker.as_deref().unwrap_or("ld"));
     let proc = linker.args(args.iter()).spawn()?;
     let output = proc.wait_with_output()?;
     tracing::debug!("Linker result ok: {}", output.status.success());
@@ -61,10 +126,53 @@ pub fn link_shared_lib(objects: &[PathBuf], output_filename: &Path) -> std::io::
     Ok(())
 }
 
+/// Archives the objects into a static library with `llvm-ar`, which reads the objects of any
+/// target, or the system `ar` if it's not installed.
 #[instrument(level = "debug")]
-pub fn link_binary(objects: &[PathBuf], output_filename: &Path) -> std::io::Result<()> {
+pub fn link_static_lib(objects: &[PathBuf], output_filename: &Path) -> anyhow::Result<()> {
+    crate::activity::set_current_item(format_args!("archiving {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
+
+    // `ar` adds to the existing archive, which may have objects that aren't built anymore.
+    if output_filename.exists() {
+        std::fs::remove_file(output_filename)?;
+    }
+
+    let archive = |program: &str| {
+        std::process::Command::new(program)
+            .arg("rcs")
+            .arg(output_filename)
+            .args(objects)
+            .spawn()
+    };
+    let proc = match archive("llvm-ar") {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => archive("ar")?,
+        proc => proc?,
+    };
+    let output = proc.wait_with_output()?;
+    tracing::debug!("Archiver result ok: {}", output.status.success());
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "failed to archive {}:\n{}",
+            output_filename.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+#[instrument(level = "debug")]
+pub fn link_binary(
+    objects: &[PathBuf],
+    options: &LinkOptions,
+    output_filename: &Path,
+) -> std::io::Result<()> {
+    crate::activity::set_current_item(format_args!("linking {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
     let objects: Vec<_> = objects.iter().map(|x| x.display().to_string()).collect();
     let output_filename = output_filename.to_string_lossy().to_string();
+    let extra_args = options.ld_args();
 
     let args: Vec<_> = {
         #[cfg(target_os = "macos")]
@@ -75,6 +183,7 @@ pub fn link_binary(objects: &[PathBuf], output_filename: &Path) -> std::io::Resu
             ];
 
             args.extend(objects.iter().map(|x| x.as_str()));
+            args.extend(extra_args.iter().map(|x| x.as_str()));
 
             args.extend(&["-o", &output_filename, "-lSystem"]);
 
@@ -124,6 +233,7 @@ pub fn link_binary(objects: &[PathBuf], output_filename: &Path) -> std::io::Resu
             ]);
 
             args.extend(objects.iter().map(|x| x.as_str()));
+            args.extend(extra_args.iter().map(|x| x.as_str()));
 
             args
         }
@@ -133,7 +243,7 @@ pub fn link_binary(objects: &[PathBuf], output_filename: &Path) -> std::io::Resu
         }
     };
 
-    let mut linker = std::process::Command::new("ld");
+    let mut linker = std::process::Command::new(options.linker.as_deref().unwrap_or("ld"));
     let proc = linker.args(args.iter()).spawn()?;
     let output = proc.wait_with_output()?;
     tracing::debug!("Linker result ok: {}", output.status.success());
@@ -147,7 +257,257 @@ pub fn link_binary(objects: &[PathBuf], output_filename: &Path) -> std::io::Resu
     Ok(())
 }
 
+/// Links a fuzzing binary with the libFuzzer runtime, which defines its `main`, using clang as it
+/// knows where its runtime is installed.
+#[instrument(level = "debug")]
+pub fn link_fuzzer(
+    objects: &[PathBuf],
+    options: &LinkOptions,
+    output_filename: &Path,
+) -> std::io::Result<()> {
+    crate::activity::set_current_item(format_args!("linking {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
+
+    let mut linker = std::process::Command::new("clang");
+    options.use_linker(&mut linker);
+    let proc = linker
+        .arg("-fsanitize=fuzzer")
+        .args(objects)
+        .args(options.driver_args())
+        .arg("-o")
+        .arg(output_filename)
+        .spawn()?;
+    let output = proc.wait_with_output()?;
+    tracing::debug!("Linker result ok: {}", output.status.success());
+
+    if !output.status.success() {
+        tracing::error!(
+            "Linker error:\n{}",
+            String::from_utf8_lossy(&output.stderr).to_string()
+        );
+    }
+    Ok(())
+}
+
+/// Links a binary instrumented with sanitizers with their runtimes, using clang as it knows where
+/// they are installed.
+#[instrument(level = "debug")]
+pub fn link_sanitized(
+    objects: &[PathBuf],
+    options: &LinkOptions,
+    output_filename: &Path,
+) -> std::io::Result<()> {
+    crate::activity::set_current_item(format_args!("linking {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
+
+    let mut linker = std::process::Command::new("clang");
+    options.use_linker(&mut linker);
+    let proc = linker
+        .args(objects)
+        .args(options.driver_args())
+        .arg("-o")
+        .arg(output_filename)
+        .spawn()?;
+    let output = proc.wait_with_output()?;
+    tracing::debug!("Linker result ok: {}", output.status.success());
+
+    if !output.status.success() {
+        tracing::error!(
+            "Linker error:\n{}",
+            String::from_utf8_lossy(&output.stderr).to_string()
+        );
+    }
+    Ok(())
+}
+
+/// Links the objects built for another target with clang, which finds the cross toolchain and
+/// sysroot of the target triple.
+#[instrument(level = "debug")]
+pub fn link_cross(
+    objects: &[PathBuf],
+    options: &LinkOptions,
+    output_filename: &Path,
+    target: &TargetInfo,
+    library: bool,
+) -> std::io::Result<()> {
+    crate::activity::set_current_item(format_args!("linking {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
+
+    let mut linker = std::process::Command::new("clang");
+    options.use_linker(&mut linker);
+    if let Some(triple) = &target.triple {
+        linker.arg(format!("--target={triple}"));
+    }
+    if library {
+        linker.arg("-shared");
+    }
+
+    let proc = linker
+        .args(objects)
+        .args(options.driver_args())
+        .arg("-o")
+        .arg(output_filename)
+        .spawn()?;
+    let output = proc.wait_with_output()?;
+    tracing::debug!("Linker result ok: {}", output.status.success());
+
+    if !output.status.success() {
+        tracing::error!(
+            "Linker error:\n{}",
+            String::from_utf8_lossy(&output.stderr).to_string()
+        );
+    }
+    Ok(())
+}
+
+/// The symbol freestanding binaries start at, unless the package names another one.
+pub const DEFAULT_ENTRY: &str = "_start";
+
+/// Links objects without libc and the startup files, for kernels and firmware. Binaries start at
+/// the given entry symbol, libraries have none. The host objects are linked with `ld` directly,
+/// the ones of other targets with clang, which finds the linker of the target.
+#[instrument(level = "debug")]
+pub fn link_freestanding(
+    objects: &[PathBuf],
+    options: &LinkOptions,
+    output_filename: &Path,
+    target: &TargetInfo,
+    entry: Option<&str>,
+) -> std::io::Result<()> {
+    crate::activity::set_current_item(format_args!("linking {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
+
+    let mut output_filename = output_filename.to_path_buf();
+    if entry.is_none() && target.is_host() && output_filename.extension().is_none() {
+        output_filename =
+            output_filename.with_extension(CompileUnitInfo::get_platform_library_ext());
+    }
+
+    let mut linker = if target.is_host() {
+        let mut linker = std::process::Command::new(options.linker.as_deref().unwrap_or("ld"));
+        linker.arg("-nostdlib");
+        match entry {
+            Some(entry) => linker.args(["-static", "-e", entry]),
+            None => linker.arg("-shared"),
+        };
+        linker.args(objects).args(options.ld_args());
+        linker
+    } else {
+        let mut linker = std::process::Command::new("clang");
+        options.use_linker(&mut linker);
+        if let Some(triple) = &target.triple {
+            linker.arg(format!("--target={triple}"));
+        }
+        linker.arg("-nostdlib");
+        match entry {
+            Some(entry) => linker.args(["-static".to_string(), format!("-Wl,-e,{entry}")]),
+            None => linker.arg("-shared"),
+        };
+        linker.args(objects).args(options.driver_args());
+        linker
+    };
+
+    let proc = linker.arg("-o").arg(&output_filename).spawn()?;
+    let output = proc.wait_with_output()?;
+    tracing::debug!("Linker result ok: {}", output.status.success());
+
+    if !output.status.success() {
+        tracing::error!(
+            "Linker error:\n{}",
+            String::from_utf8_lossy(&output.stderr).to_string()
+        );
+    }
+    Ok(())
+}
+
+/// Where the WASI libc is looked for when `WASI_SYSROOT` isn't set, the one of the wasi-sdk.
+const DEFAULT_WASI_SYSROOT: &str = "/opt/wasi-sdk/share/wasi-sysroot";
+
+/// Links WebAssembly objects into a `.wasm` module with `wasm-ld`, or `lld -flavor wasm` if it's
+/// not installed.
+///
+/// WASI programs are linked with the libc of the WASI sysroot, which defines their `_start`. The
+/// other modules have no entry point, their functions and memory are exported for the host to
+/// call, and the functions they import are left for the host to provide.
+#[instrument(level = "debug")]
+pub fn link_wasm(
+    objects: &[PathBuf],
+    options: &LinkOptions,
+    output_filename: &Path,
+    target: &TargetInfo,
+    library: bool,
+) -> std::io::Result<()> {
+    crate::activity::set_current_item(format_args!("linking {}", output_filename.display()));
+    let _span = super::self_profile::span("link", output_filename.display());
+    let objects: Vec<_> = objects.iter().map(|x| x.display().to_string()).collect();
+    let output_filename = output_filename.to_string_lossy().to_string();
+
+    let mut args = Vec::new();
+
+    if target.kind == TargetKind::Wasi && !library {
+        let sysroot =
+            std::env::var("WASI_SYSROOT").unwrap_or_else(|_| DEFAULT_WASI_SYSROOT.to_string());
+        let lib_dir = format!("{sysroot}/lib/wasm32-wasi");
+
+        args.push(format!("{lib_dir}/crt1-command.o"));
+        args.extend(objects);
+        args.extend(options.args.iter().cloned());
+        args.extend([format!("-L{lib_dir}"), "-lc".to_string()]);
+    } else {
+        args.extend(["--no-entry", "--export-dynamic", "--allow-undefined"].map(str::to_string));
+        if !library {
+            args.push("--export=main".to_string());
+        }
+        args.extend(objects);
+        args.extend(options.args.iter().cloned());
+    }
+
+    args.extend(["-o".to_string(), output_filename]);
+
+    let proc = match std::process::Command::new(options.linker.as_deref().unwrap_or("wasm-ld"))
+        .args(&args)
+        .spawn()
+    {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound && options.linker.is_none() => {
+            std::process::Command::new("lld")
+                .args(["-flavor", "wasm"])
+                .args(&args)
+                .spawn()?
+        }
+        proc => proc?,
+    };
+    let output = proc.wait_with_output()?;
+    tracing::debug!("Linker result ok: {}", output.status.success());
+
+    if !output.status.success() {
+        tracing::error!(
+            "Linker error:\n{}",
+            String::from_utf8_lossy(&output.stderr).to_string()
+        );
+    }
+    Ok(())
+}
+
 #[cfg(target_os = "linux")]
 fn file_exists(path: &str) -> bool {
     Path::new(path).exists()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linkers_are_found_by_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let linker = dir.path().join("my-ld");
+        std::fs::write(&linker, "").unwrap();
+
+        assert_eq!(find_linker(linker.to_str().unwrap()), Some(linker.clone()));
+        assert_eq!(
+            find_linker(dir.path().join("missing").to_str().unwrap()),
+            None
+        );
+        assert_eq!(find_linker("concrete-missing-linker"), None);
+    }
+}
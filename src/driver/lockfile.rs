@@ -0,0 +1,383 @@
+//! `Concrete.lock`: the exact commits the git dependencies were checked out at, written next to
+//! the `Concrete.toml` of the project or workspace root after resolving them, so every build uses
+//! the same sources until `concrete update` moves them.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use git2::{Oid, Repository, build::CheckoutBuilder};
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    STD_PACKAGE,
+    cache::{self, Cache},
+    checkout_dependency,
+    config::{Config, Dependency, DependencySource},
+    find_config_path,
+    workspace::Workspace,
+};
+use crate::check::suggestions::unknown_value_message;
+
+pub const LOCKFILE_NAME: &str = "Concrete.lock";
+
+/// Bumped when the format of the lockfile changes.
+const LOCKFILE_VERSION: u32 = 1;
+
+const HEADER: &str = "# This file is generated by concrete, do not edit it.\n";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    /// The repository it was cloned from.
+    pub git: String,
+    /// The ref requested in the manifest, the package is resolved again if it changes.
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub r#ref: Option<String>,
+    /// The commit checked out.
+    pub commit: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Lockfile {
+    packages: BTreeMap<String, LockedPackage>,
+    /// The packages `concrete update` is moving to the latest commit of their repository.
+    updating: Updating,
+    /// The packages locked since the lockfile was read.
+    locked: BTreeSet<String>,
+    /// Whether the lockfile changed since it was read.
+    changed: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+enum Updating {
+    #[default]
+    None,
+    All,
+    Packages(BTreeSet<String>),
+}
+
+/// The packages are kept in a map, but written as an array of tables like other lockfiles.
+#[derive(Serialize, Deserialize)]
+struct LockfileToml {
+    version: u32,
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+    /// Reads the lockfile in the given directory, or an empty one if there's none yet.
+    pub fn read(root_dir: &Path) -> Result<Self> {
+        let path = root_dir.join(LOCKFILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let lockfile: LockfileToml = toml::from_str(&source)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        if lockfile.version != LOCKFILE_VERSION {
+            bail!(
+                "{} has version {} but this compiler reads version {}, remove it to lock the dependencies again",
+                path.display(),
+                lockfile.version,
+                LOCKFILE_VERSION
+            );
+        }
+
+        Ok(Self {
+            packages: lockfile
+                .package
+                .into_iter()
+                .map(|x| (x.name.clone(), x))
+                .collect(),
+            ..Self::default()
+        })
+    }
+
+    /// Writes the lockfile in the given directory, if it changed.
+    pub fn write(&self, root_dir: &Path) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let lockfile = LockfileToml {
+            version: LOCKFILE_VERSION,
+            package: self.packages.values().cloned().collect(),
+        };
+        let path = root_dir.join(LOCKFILE_NAME);
+        let source = format!("{HEADER}\n{}", toml::to_string_pretty(&lockfile)?);
+
+        std::fs::write(&path, source).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.get(name)
+    }
+
+    /// The commit the dependency is locked to, if it's locked and its source didn't change.
+    fn locked_commit(&self, name: &str, git: &str, r#ref: Option<&str>) -> Option<Oid> {
+        if self.is_updating(name) {
+            return None;
+        }
+
+        self.packages
+            .get(name)
+            .filter(|x| x.git == git && x.r#ref.as_deref() == r#ref)
+            .and_then(|x| Oid::from_str(&x.commit).ok())
+    }
+
+    fn is_updating(&self, name: &str) -> bool {
+        match &self.updating {
+            Updating::None => false,
+            Updating::All => true,
+            Updating::Packages(names) => names.contains(name),
+        }
+    }
+
+    /// Locks the dependency to the given commit, returning the commit it was locked to before.
+    fn lock(&mut self, name: &str, git: &str, r#ref: Option<&str>, commit: Oid) -> Option<String> {
+        self.locked.insert(name.to_string());
+
+        let package = LockedPackage {
+            name: name.to_string(),
+            git: git.to_string(),
+            r#ref: r#ref.map(str::to_string),
+            commit: commit.to_string(),
+        };
+
+        match self.packages.insert(name.to_string(), package.clone()) {
+            Some(previous) if previous == package => None,
+            previous => {
+                self.changed = true;
+                previous.map(|x| x.commit)
+            }
+        }
+    }
+
+    /// Makes the given packages, or all of them if none are given, check out the latest commit of
+    /// their repository again instead of the locked one.
+    pub fn unlock(&mut self, names: Vec<String>) -> Result<()> {
+        if names.is_empty() {
+            self.updating = Updating::All;
+            return Ok(());
+        }
+
+        for name in &names {
+            if !self.packages.contains_key(name) {
+                bail!(
+                    "{}",
+                    unknown_value_message(
+                        "locked package",
+                        name,
+                        self.packages.keys().map(String::as_str)
+                    )
+                );
+            }
+        }
+
+        self.updating = Updating::Packages(names.into_iter().collect());
+        Ok(())
+    }
+
+    /// Removes the packages that weren't locked since the lockfile was read, like the
+    /// dependencies removed from the manifests.
+    fn prune(&mut self) {
+        let len = self.packages.len();
+        self.packages.retain(|name, _| self.locked.contains(name));
+        self.changed |= self.packages.len() != len;
+    }
+}
+
+/// Checks out the git dependency in the given directory at the commit it's locked to, or else
+/// at the requested ref or the latest commit of its repository, and locks it to that commit.
+pub fn checkout_git_dependency(
+    dir: &Path,
+    name: &str,
+    git: &str,
+    dep: &Dependency,
+    lockfile: &mut Lockfile,
+) -> Result<()> {
+    let requested = dep.r#ref.as_deref();
+    let locked = lockfile.locked_commit(name, git, requested);
+
+    // The checkout is cloned from the clone of the repository in the global cache, unless it
+    // can't be written there.
+    let db = Cache::locate().map(|x| x.git_db(git));
+    let repo = if dir.exists() {
+        Repository::open(dir)
+            .with_context(|| format!("failed to open the checkout of `{name}`, remove it"))?
+    } else {
+        if db.as_ref().is_none_or(|db| !db.exists()) {
+            eprintln!(
+                "   {} {} ({})",
+                "Downloading".green().bold(),
+                name,
+                requested.unwrap_or("head"),
+            );
+        }
+
+        let url = match db.as_ref().filter(|db| clone_db(db, git)) {
+            Some(db) => db.display().to_string(),
+            None => git.to_string(),
+        };
+        Repository::clone_recurse(&url, dir).context("Failed to clone dependency")?
+    };
+
+    // The clone in the cache gets the new commits first, if the checkout was cloned from it.
+    let db = db.filter(|db| {
+        repo.find_remote("origin")
+            .ok()
+            .is_some_and(|x| x.url().is_some_and(|url| Path::new(url) == db))
+    });
+    let fetch = |repo: &Repository, refspecs: &[&str]| {
+        if let Some(db) = &db {
+            cache::fetch_db(db)?;
+        }
+        fetch(repo, refspecs)
+    };
+
+    let commit = match locked {
+        Some(commit) => commit,
+        None if lockfile.is_updating(name) => {
+            eprintln!("    {} {}", "Fetching".green().bold(), name);
+            fetch(&repo, &["HEAD"])?;
+
+            match requested {
+                Some(r#ref) => Oid::from_str(r#ref)?,
+                None => repo.find_reference("FETCH_HEAD")?.peel_to_commit()?.id(),
+            }
+        }
+        None => match requested {
+            Some(r#ref) => Oid::from_str(r#ref)?,
+            None => repo.head()?.peel_to_commit()?.id(),
+        },
+    };
+
+    let head = repo.head().ok().and_then(|x| x.target());
+    if head != Some(commit) {
+        if repo.find_commit(commit).is_err() {
+            // Fetching with the refspecs of the clone gets the commits of all its branches.
+            fetch(&repo, &[] as &[&str])?;
+        }
+
+        let object = repo
+            .find_commit(commit)
+            .with_context(|| format!("the commit {commit} of `{name}` is not in {git}"))?;
+        repo.checkout_tree(object.as_object(), Some(CheckoutBuilder::new().force()))?;
+        repo.set_head_detached(commit)?;
+    }
+
+    if let Some(previous) = lockfile.lock(name, git, requested, commit) {
+        if previous != commit.to_string() {
+            eprintln!(
+                "    {} {} {} -> {}",
+                "Updating".green().bold(),
+                name,
+                short_commit(&previous),
+                short_commit(&commit.to_string())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Clones the repository into the global cache if it's not there yet, returning whether it's
+/// there.
+fn clone_db(db: &Path, git: &str) -> bool {
+    match cache::clone_db(db, git) {
+        Ok(()) => true,
+        Err(error) => {
+            tracing::debug!("{error:#}");
+            false
+        }
+    }
+}
+
+fn fetch(repo: &Repository, refspecs: &[&str]) -> Result<()> {
+    repo.find_remote("origin")?
+        .fetch(refspecs, None, None)
+        .context("failed to fetch dependency")
+}
+
+fn short_commit(commit: &str) -> &str {
+    commit.get(..10).unwrap_or(commit)
+}
+
+/// `concrete update`: moves the given git dependencies, or all of them, to the latest commit of
+/// their repository, or the ref given in the manifest, and writes the new commits to the lockfile.
+pub fn handle_update(names: Vec<String>) -> Result<()> {
+    let config_path = find_config_path()?;
+    let project_dir = config_path
+        .parent()
+        .context("couldn't get config parent dir")?;
+
+    // All the packages of the workspace are updated, they share the lockfile.
+    let (root_dir, packages) = match Workspace::find(project_dir)? {
+        Some(workspace) => (workspace.root.clone(), workspace.packages.clone()),
+        None => (project_dir.to_path_buf(), vec![project_dir.to_path_buf()]),
+    };
+
+    let mut lockfile = Lockfile::read(&root_dir)?;
+    let update_all = names.is_empty();
+    lockfile.unlock(names)?;
+
+    let mut visited = BTreeSet::new();
+    for package in &packages {
+        // Each package is built with its own dependencies, which get the same keys here.
+        let mut sources = HashMap::new();
+        update_package(
+            package,
+            &root_dir,
+            &mut lockfile,
+            &mut visited,
+            &mut sources,
+        )?;
+    }
+
+    if update_all {
+        lockfile.prune();
+    }
+
+    lockfile.write(&root_dir)
+}
+
+/// Checks out the dependencies of the package, with the keys a build gives them: a package
+/// required from another source than its first one is locked with the hash of the source.
+fn update_package(
+    project_dir: &Path,
+    root_dir: &Path,
+    lockfile: &mut Lockfile,
+    visited: &mut BTreeSet<PathBuf>,
+    sources: &mut HashMap<String, DependencySource>,
+) -> Result<()> {
+    if !visited.insert(project_dir.to_path_buf()) {
+        return Ok(());
+    }
+
+    let config = Config::read(&project_dir.join("Concrete.toml"))?;
+
+    // In the order of a build, std first.
+    let mut dependencies: Vec<_> = config.dependencies.iter().collect();
+    dependencies.sort_by_key(|(name, _)| (name.as_str() != STD_PACKAGE, name.as_str()));
+
+    for (name, dep) in dependencies {
+        let source = dep.resolve_source(project_dir)?;
+        let key = match sources.get(name) {
+            Some(first) if *first != source => format!("{name}-{}", source.short_hash()),
+            _ => name.clone(),
+        };
+        sources.entry(name.clone()).or_insert(source);
+
+        let path = checkout_dependency(project_dir, root_dir, &key, dep, lockfile)?;
+        update_package(&path, root_dir, lockfile, visited, sources)?;
+    }
+
+    Ok(())
+}
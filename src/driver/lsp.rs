@@ -0,0 +1,982 @@
+//! `concrete lsp`: serves the Language Server Protocol on stdin and stdout, so editors can show the
+//! diagnostics of a project when its files are opened or saved, go to the definitions of names and
+//! list the symbols of a file.
+//!
+//! The messages are framed like the ones of the debug adapter protocol, see [`super::debug`]. The
+//! project is checked again from the open documents and the files on disk on every save, through a
+//! [`CompileSession`], which reports its errors instead of exiting.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail};
+use logos::Logos;
+use serde_json::{Value, json};
+
+use super::{
+    config::Config,
+    debug::{read_message, write_message},
+    sources::SourceExtensions,
+    workspace,
+};
+use crate::{
+    ast::{
+        CompilationUnit,
+        common::{Ident, Span},
+        functions::FunctionDef,
+        imports::ImportStmt,
+        modules::{Module, ModuleDefItem},
+        statements::{LetStmt, LetStmtTarget, Statement},
+    },
+    check::diagnostics::{Diagnostic, Severity},
+    parser::{ProgramSource, parse_ast, tokens::Token},
+    session::{CompileSession, debug_build},
+};
+
+/// The JSON-RPC error of the requests the server doesn't handle.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The kinds of the document symbols, as numbered by the protocol.
+mod symbol_kind {
+    pub const MODULE: u32 = 2;
+    pub const METHOD: u32 = 6;
+    pub const FIELD: u32 = 8;
+    pub const ENUM: u32 = 10;
+    pub const INTERFACE: u32 = 11;
+    pub const FUNCTION: u32 = 12;
+    pub const CONSTANT: u32 = 14;
+    pub const OBJECT: u32 = 19;
+    pub const ENUM_MEMBER: u32 = 22;
+    pub const STRUCT: u32 = 23;
+    pub const TYPE_PARAMETER: u32 = 26;
+}
+
+/// Serves the language server protocol on stdin and stdout, until the editor sends `exit`.
+pub fn serve() -> Result<()> {
+    let mut server = Server::default();
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+
+    while let Some(message) = read_message(&mut stdin)? {
+        if message["method"] == "exit" {
+            break;
+        }
+
+        for reply in server.handle(&message) {
+            write_message(&mut stdout, &reply)?;
+        }
+    }
+
+    if !server.shut_down {
+        bail!("the editor exited without a shutdown request");
+    }
+
+    Ok(())
+}
+
+/// The state of the server between messages.
+#[derive(Debug, Default)]
+pub struct Server {
+    /// The text of the open documents, which may differ from the files on disk.
+    documents: HashMap<PathBuf, String>,
+    /// The files with diagnostics published, to clear them once they have none.
+    published: HashSet<PathBuf>,
+    shut_down: bool,
+}
+
+impl Server {
+    /// Handles a message from the editor, returning the messages to send back: the response of a
+    /// request and the notifications it causes.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let Some(method) = message["method"].as_str() else {
+            // The responses to requests of the server, it doesn't send any.
+            return Vec::new();
+        };
+        let params = &message["params"];
+
+        let Some(id) = message.get("id") else {
+            return self.notification(method, params);
+        };
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1, "save": true },
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "concrete", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => {
+                self.shut_down = true;
+                Value::Null
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            _ => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unsupported request {method}"),
+                    },
+                })];
+            }
+        };
+
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let Some(path) = document_path(params) else {
+            return Vec::new();
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(path.clone(), text.to_string());
+                self.publish_diagnostics(&path)
+            }
+            "textDocument/didChange" => {
+                // The whole text is sent on every change, the last one is the current text.
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|x| x.last())
+                    .and_then(|x| x["text"].as_str())
+                {
+                    self.documents.insert(path, text.to_string());
+                }
+                Vec::new()
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = params["text"].as_str() {
+                    self.documents.insert(path.clone(), text.to_string());
+                }
+                self.publish_diagnostics(&path)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&path);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The session checking the project of the given file, with the open documents instead of
+    /// their files on disk.
+    fn session(&self, file: &Path) -> CompileSession {
+        let (roots, extensions) = project_roots(file);
+
+        let mut session =
+            CompileSession::new(debug_build(PathBuf::from("lsp.o"))).with_extensions(extensions);
+        for (path, text) in &self.documents {
+            session.add_module_source(path.clone(), text.clone());
+        }
+        for root in roots {
+            session.add_file(root);
+        }
+
+        session
+    }
+
+    /// Checks the project of the given file, publishing the diagnostics of each of its files.
+    fn publish_diagnostics(&mut self, file: &Path) -> Vec<Value> {
+        let output = self.session(file).check();
+
+        let mut files: HashMap<PathBuf, Vec<Value>> = HashMap::new();
+        for diagnostic in &output.diagnostics {
+            files
+                .entry(diagnostic.path.clone())
+                .or_default()
+                .push(self.lsp_diagnostic(diagnostic));
+        }
+
+        // The files fixed since the last check are published without diagnostics, to clear them.
+        for path in self.published.drain() {
+            files.entry(path).or_default();
+        }
+
+        let mut files: Vec<_> = files.into_iter().collect();
+        files.sort_by(|(x, _), (y, _)| x.cmp(y));
+
+        self.published = files
+            .iter()
+            .filter(|(_, diagnostics)| !diagnostics.is_empty())
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        files
+            .into_iter()
+            .map(|(path, diagnostics)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": path_to_uri(&path), "diagnostics": diagnostics },
+                })
+            })
+            .collect()
+    }
+
+    fn lsp_diagnostic(&self, diagnostic: &Diagnostic) -> Value {
+        // Editors show a single message, the notes and helps are added to it.
+        let mut message = diagnostic
+            .message
+            .clone()
+            .or_else(|| diagnostic.labels.iter().find_map(|x| x.message.clone()))
+            .unwrap_or_else(|| diagnostic.code.clone());
+        for note in &diagnostic.notes {
+            message.push_str(&format!("\nnote: {note}"));
+        }
+        for help in &diagnostic.helps {
+            message.push_str(&format!("\nhelp: {help}"));
+        }
+
+        let related: Vec<Value> = diagnostic
+            .labels
+            .iter()
+            // The label at the diagnostic itself is already shown by its range.
+            .filter(|x| x.path != diagnostic.path || x.span != diagnostic.span)
+            .filter_map(|label| {
+                Some(json!({
+                    "location": self.location(&label.path, label.span),
+                    "message": label.message.as_ref()?,
+                }))
+            })
+            .collect();
+
+        json!({
+            "range": self.range(&diagnostic.path, diagnostic.span),
+            "severity": match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            },
+            "code": diagnostic.code,
+            "source": "concrete",
+            "message": message,
+            "relatedInformation": related,
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let Some(file) = document_path(params) else {
+            return Value::Null;
+        };
+        let text = self.text(&file);
+        let offset = LineIndex::new(&text).offset(&params["position"]);
+        let Some(name) = identifier_at(&text, offset) else {
+            return Value::Null;
+        };
+
+        let (mut units, _) = self.session(&file).parse_roots();
+
+        // A file outside of the project, or one it doesn't declare yet, is parsed by itself.
+        if find_module(&units, &file, offset).is_none() {
+            if let Ok(unit) = parse_ast(&ProgramSource::new(text.clone(), &file)) {
+                units.push(unit);
+            }
+        }
+
+        let locations: Vec<Value> = definitions(&units, &file, offset, &name)
+            .into_iter()
+            .map(|(path, span)| self.location(&path, span))
+            .collect();
+
+        json!(locations)
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let Some(file) = document_path(params) else {
+            return Value::Null;
+        };
+        let text = self.text(&file);
+        let Ok(unit) = parse_ast(&ProgramSource::new(text.clone(), &file)) else {
+            return Value::Null;
+        };
+
+        let index = LineIndex::new(&text);
+        let symbols: Vec<Value> = unit
+            .modules
+            .iter()
+            .map(|x| module_symbol(x, &index))
+            .collect();
+
+        json!(symbols)
+    }
+
+    /// The text of the file, from the editor if it's open.
+    fn text(&self, path: &Path) -> String {
+        match self.documents.get(path) {
+            Some(text) => text.clone(),
+            None => crate::parser::read_source(path).unwrap_or_default(),
+        }
+    }
+
+    fn range(&self, path: &Path, span: Span) -> Value {
+        let text = self.text(path);
+        LineIndex::new(&text).range(span)
+    }
+
+    fn location(&self, path: &Path, span: Span) -> Value {
+        json!({ "uri": path_to_uri(path), "range": self.range(path, span) })
+    }
+}
+
+/// The files checked with the given one: the main and library files of its project, after the
+/// library files of its dependencies, or only the file itself if it's not in a project.
+fn project_roots(file: &Path) -> (Vec<PathBuf>, SourceExtensions) {
+    let project = file
+        .ancestors()
+        .skip(1)
+        .filter(|x| x.join("Concrete.toml").is_file())
+        .find_map(|dir| {
+            let config = Config::read(&dir.join("Concrete.toml")).ok()?;
+            config
+                .package
+                .is_some()
+                .then(|| (dir.to_path_buf(), config))
+        });
+
+    let Some((project_dir, config)) = project else {
+        return (vec![file.to_path_buf()], SourceExtensions::default());
+    };
+
+    let root_dir = workspace::root_dir(&project_dir).unwrap_or_else(|_| project_dir.clone());
+    let mut roots = Vec::new();
+    let mut visited = HashSet::new();
+    dependency_roots(&project_dir, &config, &root_dir, &mut roots, &mut visited);
+
+    let extensions = SourceExtensions::with_aliases(
+        &config
+            .package
+            .map(|x| x.extension_aliases)
+            .unwrap_or_default(),
+    );
+    for name in ["main", "lib"] {
+        if let Ok(Some(path)) = extensions.find_file(&project_dir.join("src").join(name)) {
+            roots.push(path);
+        }
+    }
+
+    if roots.is_empty() {
+        roots.push(file.to_path_buf());
+    }
+
+    (roots, extensions)
+}
+
+/// Adds the library files of the dependencies of the package, the ones of their own dependencies
+/// first. Git dependencies are only used once a build checked them out.
+fn dependency_roots(
+    project_dir: &Path,
+    config: &Config,
+    root_dir: &Path,
+    roots: &mut Vec<PathBuf>,
+    visited: &mut HashSet<PathBuf>,
+) {
+    let mut dependencies: Vec<_> = config.dependencies.iter().collect();
+    dependencies.sort_by_key(|(name, _)| name.as_str());
+
+    for (name, dep) in dependencies {
+        let dir = match (&dep.path, &dep.git) {
+            (Some(path), _) => project_dir.join(path),
+            (None, Some(_)) => root_dir.join(".bricks").join(name),
+            (None, None) => continue,
+        };
+
+        if !visited.insert(dir.clone()) {
+            continue;
+        }
+
+        let Ok(config) = Config::read(&dir.join("Concrete.toml")) else {
+            continue;
+        };
+        dependency_roots(&dir, &config, root_dir, roots, visited);
+
+        let Some(package) = &config.package else {
+            continue;
+        };
+        let extensions = SourceExtensions::with_aliases(&package.extension_aliases);
+        if let Ok(Some(path)) = extensions.find_file(&dir.join("src").join("lib")) {
+            roots.push(path);
+        }
+    }
+}
+
+/// The name of the identifier at the byte offset, or just before it, like at the end of a word.
+fn identifier_at(text: &str, offset: usize) -> Option<String> {
+    Token::lexer(text)
+        .spanned()
+        .find_map(|(token, span)| match token {
+            Ok(Token::Identifier(name)) if span.start <= offset && offset <= span.end => Some(name),
+            _ => None,
+        })
+}
+
+/// Where the name at the offset of the file may be defined: the variables declared before it in
+/// its function, else the items of its module and the ones it imports, else any item, method,
+/// field or variant with that name.
+pub fn definitions(
+    units: &[CompilationUnit],
+    file: &Path,
+    offset: usize,
+    name: &str,
+) -> Vec<(PathBuf, Span)> {
+    let module = find_module(units, file, offset);
+
+    if let Some(module) = module {
+        if let Some(function) = enclosing_function(module, offset) {
+            let mut variables = Vec::new();
+            for param in &function.decl.params {
+                match &param.pattern {
+                    Some(pattern) => variables.extend(pattern.bindings()),
+                    None => variables.push(&param.name),
+                }
+            }
+            collect_variables(&function.body, offset, &mut variables);
+
+            // The last one declared shadows the others.
+            if let Some(variable) = variables.into_iter().rev().find(|x| x.name == name) {
+                return vec![(file.to_path_buf(), variable.span)];
+            }
+        }
+
+        let items: Vec<_> = module
+            .contents
+            .iter()
+            .filter_map(item_name)
+            .filter(|x| x.name == name)
+            .map(|x| (module.file_path.clone(), x.span))
+            .collect();
+        if !items.is_empty() {
+            return items;
+        }
+
+        for item in &module.contents {
+            let ModuleDefItem::Import(import) = item else {
+                continue;
+            };
+            if let Some(imported_name) = imported_name(import, name) {
+                if let Some(imported) = module_by_path(units, &import.module) {
+                    let items: Vec<_> = imported
+                        .contents
+                        .iter()
+                        .filter_map(item_name)
+                        .filter(|x| x.name == imported_name)
+                        .map(|x| (imported.file_path.clone(), x.span))
+                        .collect();
+                    if !items.is_empty() {
+                        return items;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut definitions = Vec::new();
+    for unit in units {
+        for module in &unit.modules {
+            collect_definitions(module, name, &mut definitions);
+        }
+    }
+    definitions
+}
+
+/// The name in the imported module of the symbol known by the given name in the importing one.
+fn imported_name<'a>(import: &'a ImportStmt, name: &'a str) -> Option<&'a str> {
+    if import.glob.is_some() {
+        return Some(name);
+    }
+
+    import
+        .symbols
+        .iter()
+        .find(|x| x.local_name().name == name)
+        .map(|x| x.name.name.as_str())
+}
+
+/// The innermost module of the file containing the offset.
+fn find_module<'a>(units: &'a [CompilationUnit], file: &Path, offset: usize) -> Option<&'a Module> {
+    fn find<'a>(module: &'a Module, file: &Path, offset: usize) -> Option<&'a Module> {
+        if module.file_path != file || !contains(module.span, offset) {
+            return None;
+        }
+
+        module
+            .contents
+            .iter()
+            .find_map(|item| match item {
+                ModuleDefItem::Module(submodule) => find(submodule, file, offset),
+                _ => None,
+            })
+            .or(Some(module))
+    }
+
+    units
+        .iter()
+        .flat_map(|x| &x.modules)
+        .find_map(|x| find(x, file, offset))
+}
+
+/// The module at the path of an import, like `Std.Mem`.
+fn module_by_path<'a>(units: &'a [CompilationUnit], path: &[Ident]) -> Option<&'a Module> {
+    let (first, rest) = path.split_first()?;
+    let mut module = units
+        .iter()
+        .flat_map(|x| &x.modules)
+        .find(|x| x.name.name == first.name)?;
+
+    for name in rest {
+        module = module.contents.iter().find_map(|item| match item {
+            ModuleDefItem::Module(submodule) if submodule.name.name == name.name => {
+                Some(submodule.as_ref())
+            }
+            _ => None,
+        })?;
+    }
+
+    Some(module)
+}
+
+fn enclosing_function(module: &Module, offset: usize) -> Option<&FunctionDef> {
+    module.contents.iter().find_map(|item| {
+        let methods = match item {
+            ModuleDefItem::Function(function) => std::slice::from_ref(function),
+            ModuleDefItem::Impl(block) => &block.methods,
+            ModuleDefItem::ImplTrait(block) => &block.methods,
+            _ => return None,
+        };
+
+        methods
+            .iter()
+            .find(|x| contains(x.span, offset))
+            .map(|x| x.as_ref())
+    })
+}
+
+/// The variables declared by the statements before the offset, in the order they're declared.
+fn collect_variables<'a>(stmts: &'a [Statement], offset: usize, variables: &mut Vec<&'a Ident>) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Let(stmt) if stmt.span.from < offset => let_variables(stmt, variables),
+            Statement::If(stmt) if contains(stmt.span, offset) => {
+                collect_variables(&stmt.block_stmts, offset, variables);
+                if let Some(else_stmts) = &stmt.else_stmts {
+                    collect_variables(else_stmts, offset, variables);
+                }
+            }
+            Statement::Match(stmt) => {
+                for variant in &stmt.variants {
+                    if !contains(variant.span, offset) {
+                        continue;
+                    }
+
+                    if let crate::ast::expressions::MatchCaseExpr::Enum(case) = &variant.case {
+                        for field in &case.field_values {
+                            variables.extend(field.pattern.bindings());
+                        }
+                    }
+                    collect_variables(&variant.block, offset, variables);
+                }
+            }
+            Statement::For(stmt) if contains(stmt.span, offset) => {
+                if let Some(init) = &stmt.init {
+                    let_variables(init, variables);
+                }
+                collect_variables(&stmt.block_stmts, offset, variables);
+            }
+            Statement::ForIn(stmt) if contains(stmt.span, offset) => {
+                variables.push(&stmt.name);
+                collect_variables(&stmt.block_stmts, offset, variables);
+            }
+            Statement::While(stmt) if contains(stmt.span, offset) => {
+                collect_variables(&stmt.block_stmts, offset, variables);
+            }
+            Statement::Defer(stmt) if contains(stmt.span, offset) => {
+                collect_variables(&stmt.block_stmts, offset, variables);
+            }
+            Statement::Unsafe(stmt) if contains(stmt.span, offset) => {
+                collect_variables(&stmt.block_stmts, offset, variables);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn let_variables<'a>(stmt: &'a LetStmt, variables: &mut Vec<&'a Ident>) {
+    match &stmt.target {
+        LetStmtTarget::Simple { id, .. } => variables.push(id),
+        LetStmtTarget::Destructure(pattern) => variables.extend(pattern.bindings()),
+    }
+}
+
+/// The items, methods, fields and variants with the name in the module and its submodules.
+fn collect_definitions(module: &Module, name: &str, definitions: &mut Vec<(PathBuf, Span)>) {
+    let mut add = |ident: &Ident| {
+        if ident.name == name {
+            definitions.push((module.file_path.clone(), ident.span));
+        }
+    };
+
+    for item in &module.contents {
+        if let Some(ident) = item_name(item) {
+            add(ident);
+        }
+
+        match item {
+            ModuleDefItem::Impl(block) => block.methods.iter().for_each(|x| add(&x.decl.name)),
+            ModuleDefItem::ImplTrait(block) => block.methods.iter().for_each(|x| add(&x.decl.name)),
+            ModuleDefItem::Struct(decl) => decl.fields.iter().for_each(|x| add(&x.name)),
+            ModuleDefItem::Union(decl) => decl.variants.iter().for_each(|x| add(&x.name)),
+            ModuleDefItem::Enum(decl) => decl.variants.iter().for_each(|x| {
+                add(&x.name);
+                x.fields.iter().for_each(|x| add(&x.name));
+            }),
+            ModuleDefItem::Trait(decl) => decl.methods.iter().for_each(|x| add(&x.name)),
+            ModuleDefItem::ExternBlock(block) => block.functions.iter().for_each(|x| add(&x.name)),
+            _ => {}
+        }
+    }
+
+    for item in &module.contents {
+        if let ModuleDefItem::Module(submodule) = item {
+            collect_definitions(submodule, name, definitions);
+        }
+    }
+}
+
+/// The name an item is referred to by, if it's one that can be.
+fn item_name(item: &ModuleDefItem) -> Option<&Ident> {
+    Some(match item {
+        ModuleDefItem::Constant(def) => &def.decl.name,
+        ModuleDefItem::Function(def) => &def.decl.name,
+        ModuleDefItem::FunctionDecl(decl) => &decl.name,
+        ModuleDefItem::Struct(decl) => &decl.name,
+        ModuleDefItem::Union(decl) => &decl.name,
+        ModuleDefItem::Enum(decl) => &decl.name,
+        ModuleDefItem::Type(decl) => &decl.name,
+        ModuleDefItem::Trait(decl) => &decl.name,
+        ModuleDefItem::Module(module) => &module.name,
+        ModuleDefItem::Macro(def) => &def.name,
+        ModuleDefItem::Impl(_)
+        | ModuleDefItem::ImplTrait(_)
+        | ModuleDefItem::ExternBlock(_)
+        | ModuleDefItem::ExternalModule(_)
+        | ModuleDefItem::Import(_) => return None,
+    })
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.from <= offset && offset <= span.to
+}
+
+fn module_symbol(module: &Module, index: &LineIndex) -> Value {
+    let children = module
+        .contents
+        .iter()
+        .filter_map(|x| item_symbol(x, index))
+        .collect();
+
+    symbol(
+        &module.name,
+        symbol_kind::MODULE,
+        module.span,
+        children,
+        index,
+    )
+}
+
+fn item_symbol(item: &ModuleDefItem, index: &LineIndex) -> Option<Value> {
+    let leaf = |name: &Ident, kind, span| symbol(name, kind, span, Vec::new(), index);
+    let methods = |methods: &[std::sync::Arc<FunctionDef>]| {
+        methods
+            .iter()
+            .map(|x| leaf(&x.decl.name, symbol_kind::METHOD, x.span))
+            .collect()
+    };
+
+    Some(match item {
+        ModuleDefItem::Constant(def) => {
+            leaf(&def.decl.name, symbol_kind::CONSTANT, def.decl.name.span)
+        }
+        ModuleDefItem::Function(def) => leaf(&def.decl.name, symbol_kind::FUNCTION, def.span),
+        ModuleDefItem::FunctionDecl(decl) => leaf(&decl.name, symbol_kind::FUNCTION, decl.span),
+        ModuleDefItem::ExternBlock(block) => impl_symbol(
+            format!("extern {:?}", block.abi),
+            block.span,
+            block
+                .functions
+                .iter()
+                .map(|x| leaf(&x.name, symbol_kind::FUNCTION, x.span))
+                .collect(),
+            index,
+        ),
+        ModuleDefItem::Impl(block) => impl_symbol(
+            format!("impl {}", block.target),
+            block.span,
+            methods(&block.methods),
+            index,
+        ),
+        ModuleDefItem::ImplTrait(block) => impl_symbol(
+            format!("impl {} for {}", block.target_trait, block.target),
+            block.span,
+            methods(&block.methods),
+            index,
+        ),
+        ModuleDefItem::Struct(decl) => symbol(
+            &decl.name,
+            symbol_kind::STRUCT,
+            decl.span,
+            decl.fields
+                .iter()
+                .map(|x| leaf(&x.name, symbol_kind::FIELD, x.span))
+                .collect(),
+            index,
+        ),
+        ModuleDefItem::Union(decl) => symbol(
+            &decl.name,
+            symbol_kind::STRUCT,
+            decl.span,
+            decl.variants
+                .iter()
+                .map(|x| leaf(&x.name, symbol_kind::FIELD, x.span))
+                .collect(),
+            index,
+        ),
+        ModuleDefItem::Enum(decl) => symbol(
+            &decl.name,
+            symbol_kind::ENUM,
+            decl.span,
+            decl.variants
+                .iter()
+                .map(|x| leaf(&x.name, symbol_kind::ENUM_MEMBER, x.span))
+                .collect(),
+            index,
+        ),
+        ModuleDefItem::Type(decl) => leaf(&decl.name, symbol_kind::TYPE_PARAMETER, decl.span),
+        ModuleDefItem::Macro(def) => leaf(&def.name, symbol_kind::FUNCTION, def.span),
+        ModuleDefItem::Trait(decl) => symbol(
+            &decl.name,
+            symbol_kind::INTERFACE,
+            decl.span,
+            decl.methods
+                .iter()
+                .map(|x| leaf(&x.name, symbol_kind::METHOD, x.span))
+                .collect(),
+            index,
+        ),
+        ModuleDefItem::Module(module) => module_symbol(module, index),
+        ModuleDefItem::ExternalModule(name) => leaf(name, symbol_kind::MODULE, name.span),
+        ModuleDefItem::Import(_) => return None,
+    })
+}
+
+/// The symbol of a named item spanning `span`, its name is selected when the editor goes to it.
+fn symbol(name: &Ident, kind: u32, span: Span, children: Vec<Value>, index: &LineIndex) -> Value {
+    json!({
+        "name": name.name,
+        "kind": kind,
+        "range": index.range(span),
+        "selectionRange": index.range(name.span),
+        "children": children,
+    })
+}
+
+/// Impl and extern blocks have no name, the start of the block is selected instead.
+fn impl_symbol(name: String, span: Span, children: Vec<Value>, index: &LineIndex) -> Value {
+    json!({
+        "name": name,
+        "kind": symbol_kind::OBJECT,
+        "range": index.range(span),
+        "selectionRange": index.range(Span::new(span.from, span.from)),
+        "children": children,
+    })
+}
+
+/// Converts between the byte offsets of the spans and the positions of the protocol, which count
+/// the characters of a line in UTF-16 code units.
+struct LineIndex<'a> {
+    text: &'a str,
+    /// The offset where each line starts.
+    lines: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let lines = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, lines }
+    }
+
+    fn position(&self, offset: usize) -> Value {
+        let offset = offset.min(self.text.len());
+        let line = self.lines.partition_point(|x| *x <= offset) - 1;
+        let character: usize = self.text[self.lines[line]..]
+            .char_indices()
+            .take_while(|(i, _)| self.lines[line] + i < offset)
+            .map(|(_, c)| c.len_utf16())
+            .sum();
+
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, span: Span) -> Value {
+        json!({ "start": self.position(span.from), "end": self.position(span.to) })
+    }
+
+    /// The offset of a position, positions past the end of a line are at its end.
+    fn offset(&self, position: &Value) -> usize {
+        let line = position["line"].as_u64().unwrap_or_default() as usize;
+        let Some(start) = self.lines.get(line).copied() else {
+            return self.text.len();
+        };
+
+        let mut character = position["character"].as_u64().unwrap_or_default() as usize;
+        for (i, c) in self.text[start..].char_indices() {
+            if c == '\n' || character < c.len_utf16() {
+                return start + i;
+            }
+            character -= c.len_utf16();
+        }
+
+        self.text.len()
+    }
+}
+
+/// The path of the document a notification or request is about.
+fn document_path(params: &Value) -> Option<PathBuf> {
+    uri_to_path(params["textDocument"]["uri"].as_str()?)
+}
+
+/// The path of a `file://` URI, decoding its escaped characters.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = match tail.get(..2).filter(|_| byte == b'%') {
+            Some(hex) => u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok(),
+            None => None,
+        };
+        if let Some(byte) = escaped {
+            bytes.push(byte);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// The `file://` URI of a path, escaping the characters URIs can't have.
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lsp_publishes_diagnostics_and_finds_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(
+            root.join("Concrete.toml"),
+            "[package]\nname = \"lsp\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+        )
+        .unwrap();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(
+            root.join("src").join("util.con"),
+            "mod util {\n    pub fn add(a: i32, b: i32) -> i32 {\n        return a + b;\n    }\n}\n",
+        )
+        .unwrap();
+
+        let main = root.join("src").join("main.con");
+        let text = "mod main {\n    mod util;\n    import main.util.{add};\n\n    fn main() -> i32 {\n        let x: i32 = 1;\n        return add(x, y);\n    }\n}\n";
+        std::fs::write(&main, text).unwrap();
+        let uri = path_to_uri(&main);
+        assert_eq!(uri_to_path(&uri), Some(main.clone()));
+
+        let mut server = Server::default();
+        let opened = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "concrete", "version": 1, "text": text } },
+        }));
+        assert_eq!(opened.len(), 1);
+        let diagnostics = &opened[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["code"], "UseOfUndeclaredVariable");
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 6, "character": 22 }, "end": { "line": 6, "character": 23 } })
+        );
+
+        let definition = |server: &mut Server, line: u32, character: u32| {
+            server.handle(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "textDocument/definition",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": line, "character": character } },
+            }))[0]["result"]
+                .clone()
+        };
+
+        // The variable `x`, then the imported function `add`.
+        assert_eq!(
+            definition(&mut server, 6, 19),
+            json!([{ "uri": uri, "range": { "start": { "line": 5, "character": 12 }, "end": { "line": 5, "character": 13 } } }])
+        );
+        assert_eq!(
+            definition(&mut server, 6, 16),
+            json!([{ "uri": path_to_uri(&root.join("src").join("util.con")), "range": { "start": { "line": 1, "character": 11 }, "end": { "line": 1, "character": 14 } } }])
+        );
+
+        // Fixing the error clears the diagnostics of the file on save.
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": text.replace("x, y", "x, x") }] },
+        }));
+        let saved = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didSave",
+            "params": { "textDocument": { "uri": uri } },
+        }));
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0]["params"]["diagnostics"], json!([]));
+
+        let symbols = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/documentSymbol",
+            "params": { "textDocument": { "uri": uri } },
+        }));
+        let module = &symbols[0]["result"][0];
+        assert_eq!(module["name"], "main");
+        let children: Vec<_> = module["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| (x["name"].as_str().unwrap(), x["kind"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(children, vec![("util", 2), ("main", 12)]);
+    }
+}
@@ -0,0 +1,386 @@
+//! The metadata of the dependencies of a build, in `build/.cmeta`, so the builds after the first
+//! neither parse their sources again nor generate their code with the rest of the program.
+//!
+//! The metadata of the dependencies has their compile units with their items configured and their
+//! macros and derives expanded, but without the bodies of their functions: the program is lowered
+//! with their signatures and calls them in the object the dependencies are generated to, once for
+//! each set of options. Only the inlinable functions keep their bodies, the program getting its own
+//! copy of them: the generic ones, instantiated with the types of the program, the `const` and
+//! `async` ones, and the ones marked `#[inline]` or `#[inline(always)]`.
+//!
+//! A metadata file is named after the hash of what the dependencies are built with besides their
+//! sources: the paths of their main files, their configurations and the compiler. It lists the
+//! source files of the dependencies with the hashes of their contents, and is only loaded if none
+//! of them changed. Next to it, `<key>.full.cmeta` keeps the units as they were parsed, their
+//! object is generated from them.
+
+use std::{
+    collections::BTreeSet,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::{
+    CompilerArgs, check,
+    incremental::{collect_files, hash_compiler},
+};
+use crate::{
+    ast::{
+        CompilationUnit,
+        cfg::Cfg,
+        common::AttributeArg,
+        functions::{FunctionDecl, FunctionDef, ImplBlock, ImplTraitBlock},
+        modules::{Module, ModuleDefItem},
+    },
+    compile_unit_info::{CompileUnitInfo, Lto},
+    ir::lowering::expand_compile_units,
+};
+
+/// The extension of the metadata files.
+pub const METADATA_EXT: &str = "cmeta";
+
+/// The metadata of the dependencies of the builds into a directory.
+#[derive(Debug)]
+pub struct DependencyMetadata {
+    dir: PathBuf,
+    /// The dependencies of the build, once their units were loaded from their metadata or it was
+    /// kept.
+    pub prebuilt: Option<PrebuiltDependencies>,
+}
+
+/// The dependencies of a program lowered from their metadata, their code being generated into an
+/// object of their own.
+#[derive(Debug, Clone)]
+pub struct PrebuiltDependencies {
+    /// The metadata of their units as they were parsed.
+    units: PathBuf,
+    /// The hash of their sources, telling their objects apart.
+    hash: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    /// The source files of the units, with the hashes of their contents.
+    sources: Vec<(PathBuf, u64)>,
+    units: Vec<CompilationUnit>,
+}
+
+impl DependencyMetadata {
+    /// The metadata of the dependencies of the builds into the directory.
+    pub fn new(build_dir: &Path) -> Self {
+        Self {
+            dir: build_dir.join(format!(".{METADATA_EXT}")),
+            prebuilt: None,
+        }
+    }
+
+    /// The key of the metadata of the dependencies with the given main files and configurations.
+    pub fn key<'a>(dependencies: impl IntoIterator<Item = (&'a Path, &'a Cfg)>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hash_compiler(&mut hasher);
+        for (file, cfg) in dependencies {
+            file.hash(&mut hasher);
+            cfg.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Loads the units of the dependencies with the key, `None` if there's no metadata of them, it
+    /// can't be read, like the ones written by other versions of the compiler, or their sources
+    /// changed.
+    pub fn load(&mut self, key: u64) -> Option<Vec<CompilationUnit>> {
+        let metadata: Metadata = read(&self.path(key, "")).ok()?;
+        let full = self.path(key, ".full");
+        let fresh = full.is_file()
+            && metadata
+                .sources
+                .iter()
+                .all(|(path, hash)| hash_contents(path) == *hash);
+        if !fresh {
+            return None;
+        }
+
+        self.prebuilt = Some(PrebuiltDependencies {
+            units: full,
+            hash: hash_sources(&metadata.sources),
+        });
+        Some(metadata.units)
+    }
+
+    /// Keeps the metadata of the units of the dependencies with the key, as they were parsed, and
+    /// returns the units the program is lowered with, the ones loaded from it. They're returned as
+    /// they are if they can't be expanded, lowering reports the errors then, or their metadata
+    /// can't be kept, the dependencies being parsed again next time.
+    pub fn store(&mut self, key: u64, units: Vec<CompilationUnit>) -> Vec<CompilationUnit> {
+        let Ok(expanded) = expand_compile_units(&units) else {
+            return units;
+        };
+
+        let mut files = BTreeSet::new();
+        for module in units.iter().flat_map(|x| &x.modules) {
+            collect_files(module, &mut files);
+        }
+        let sources: Vec<(PathBuf, u64)> = files
+            .into_iter()
+            .map(|path| {
+                let hash = hash_contents(&path);
+                (path, hash)
+            })
+            .collect();
+        let hash = hash_sources(&sources);
+
+        let prebuilt: Vec<CompilationUnit> = expanded
+            .into_iter()
+            .map(|unit| CompilationUnit {
+                modules: unit.modules.iter().map(strip_module).collect(),
+                is_prebuilt: true,
+                ..unit
+            })
+            .collect();
+
+        // The units they were parsed to are kept first, the metadata isn't loaded without them.
+        let full = Metadata {
+            sources: sources.clone(),
+            units,
+        };
+        let full_path = self.path(key, ".full");
+        let metadata = Metadata {
+            sources,
+            units: prebuilt,
+        };
+        let result = write(&full_path, &full).and_then(|_| write(&self.path(key, ""), &metadata));
+
+        match result {
+            Ok(()) => {
+                self.prebuilt = Some(PrebuiltDependencies {
+                    units: full_path,
+                    hash,
+                });
+                metadata.units
+            }
+            Err(error) => {
+                tracing::debug!("couldn't keep the metadata of the dependencies: {error}");
+                full.units
+            }
+        }
+    }
+
+    /// Where the metadata with the given key is kept, `suffix` telling apart the files of a key.
+    fn path(&self, key: u64, suffix: &str) -> PathBuf {
+        self.dir.join(format!("{key:016x}{suffix}.{METADATA_EXT}"))
+    }
+}
+
+impl PrebuiltDependencies {
+    /// The object of the dependencies built with the options of the program, generated from the
+    /// units they were parsed to unless it already was.
+    pub fn object(&self, args: &CompilerArgs, session: &CompileUnitInfo) -> Result<PathBuf> {
+        // Its name only depends on the options its code is generated with, not on where the
+        // program puts its output or the extra files it asks for.
+        let mut hasher = DefaultHasher::new();
+        self.hash.hash(&mut hasher);
+        session.optlevel.hash(&mut hasher);
+        session.debug_info.hash(&mut hasher);
+        session.library.hash(&mut hasher);
+        session.target.hash(&mut hasher);
+        session.lto.hash(&mut hasher);
+        session.unchecked_indexing.hash(&mut hasher);
+        session.sanitizers.hash(&mut hasher);
+        session.coverage.hash(&mut hasher);
+        session.fuzzing.hash(&mut hasher);
+        session.freestanding.hash(&mut hasher);
+        let session = CompileUnitInfo {
+            output_file: self
+                .units
+                .with_file_name(format!("{:016x}", hasher.finish())),
+            ..session.clone()
+        };
+        let object = session.output_file.with_extension(match session.lto {
+            Lto::Off => "o",
+            Lto::Thin | Lto::Full => "bc",
+        });
+        if object.is_file() {
+            return Ok(object);
+        }
+
+        let metadata: Metadata = read(&self.units)
+            .with_context(|| format!("failed to read {}", self.units.display()))?;
+        let ir = check(args, &metadata.units)?;
+        crate::codegen::compile(&session, &ir)?;
+
+        Ok(object)
+    }
+}
+
+/// The module with the bodies of its functions left out, except the ones of the inlinable ones.
+fn strip_module(module: &Module) -> Module {
+    let contents = module
+        .contents
+        .iter()
+        .map(|item| match item {
+            ModuleDefItem::Function(function) => {
+                ModuleDefItem::Function(strip_function(function, false))
+            }
+            ModuleDefItem::Impl(block) => ModuleDefItem::Impl(Arc::new(ImplBlock {
+                methods: block
+                    .methods
+                    .iter()
+                    .map(|x| strip_function(x, !block.generic_params.is_empty()))
+                    .collect(),
+                ..block.as_ref().clone()
+            })),
+            ModuleDefItem::ImplTrait(block) => ModuleDefItem::ImplTrait(Arc::new(ImplTraitBlock {
+                methods: block
+                    .methods
+                    .iter()
+                    .map(|x| strip_function(x, !block.generic_params.is_empty()))
+                    .collect(),
+                ..block.as_ref().clone()
+            })),
+            ModuleDefItem::Module(module) => ModuleDefItem::Module(Arc::new(strip_module(module))),
+            item => item.clone(),
+        })
+        .collect();
+
+    Module {
+        contents,
+        ..module.clone()
+    }
+}
+
+/// The function without its body, unless it's inlinable, `is_generic` if it's in a generic impl.
+fn strip_function(function: &Arc<FunctionDef>, is_generic: bool) -> Arc<FunctionDef> {
+    if is_generic || is_inlinable(&function.decl) {
+        return function.clone();
+    }
+
+    Arc::new(FunctionDef {
+        body: Vec::new(),
+        is_prebuilt: true,
+        ..function.as_ref().clone()
+    })
+}
+
+fn is_inlinable(decl: &FunctionDecl) -> bool {
+    let is_inline = decl.attributes.iter().any(|attr| {
+        attr.name == "inline"
+            && !matches!(attr.args.as_slice(), [AttributeArg::Word(x)] if x.name == "never")
+    });
+
+    is_inline || !decl.generic_params.is_empty() || decl.is_const || decl.is_async
+}
+
+fn read<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let source = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&source)?)
+}
+
+/// Writes the value as JSON to the path.
+fn write<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    // It's written next to it and renamed, so other builds don't read it half written.
+    let partial = path.with_extension(format!("{METADATA_EXT}.{}", std::process::id()));
+    let result = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| serde_json::to_string(value).map_err(std::io::Error::other))
+        .and_then(|x| std::fs::write(&partial, x))
+        .and_then(|_| std::fs::rename(&partial, path));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    result
+}
+
+fn hash_contents(path: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    // A file that can't be read hashes differently than any content.
+    std::fs::read(path).ok().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn hash_sources(sources: &[(PathBuf, u64)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    sources.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        driver::{parse_file, sources::SourceExtensions},
+        ir::lowering::lower_compile_units,
+    };
+
+    #[test]
+    fn dependency_is_lowered_from_its_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let parse = |name: &str, source: &str| {
+            let path = dir.path().join(format!("{name}.con"));
+            std::fs::write(&path, source).unwrap();
+            parse_file(path, &SourceExtensions::default()).unwrap()
+        };
+
+        let mut dependency = parse(
+            "dep",
+            "mod Dep {
+                pub fn double(x: i32) -> i32 {
+                    return x * 2;
+                }
+
+                pub fn id<T>(x: T) -> T {
+                    return x;
+                }
+            }",
+        );
+        dependency.is_dependency = true;
+        let main = parse(
+            "main",
+            "mod Main {
+                import Dep.{double, id};
+
+                fn main() -> i32 {
+                    return double(id::<i32>(2));
+                }
+            }",
+        );
+
+        let path = dir.path().join("dep.con");
+        let key = DependencyMetadata::key([(path.as_path(), &dependency.cfg)]);
+        DependencyMetadata::new(dir.path()).store(key, vec![dependency]);
+
+        let mut metadata = DependencyMetadata::new(dir.path());
+        let mut units = metadata.load(key).expect("its sources didn't change");
+        assert!(metadata.prebuilt.is_some());
+        assert!(units.iter().all(|x| x.is_prebuilt));
+
+        // Only the metadata is left of the dependency.
+        std::fs::remove_file(&path).unwrap();
+        assert!(DependencyMetadata::new(dir.path()).load(key).is_none());
+
+        units.push(main);
+        let ir = lower_compile_units(&units).expect("the program is valid");
+        assert_eq!(ir.prebuilt_modules.len(), 1);
+        let function = |name: &str| {
+            ir.functions
+                .iter()
+                .filter_map(|(_, x)| x.as_ref())
+                .find(|x| x.debug_name.as_deref() == Some(name))
+                .unwrap()
+        };
+
+        // The function is called in the object of the dependency, under its mangled name.
+        let double = function("Dep::double");
+        assert!(double.is_extern && double.basic_blocks.is_empty());
+        assert_ne!(double.name, "double");
+
+        // The instance of the generic function is generated with the program.
+        let id = function("Dep::id<i32>");
+        assert!(!id.is_extern && !id.basic_blocks.is_empty());
+    }
+}
@@ -1,34 +1,143 @@
+use crate::activity::set_current_item;
 use crate::ast::CompilationUnit;
+use crate::ast::cfg::Cfg;
 use crate::ast::modules::ModuleDefItem;
-use crate::compile_unit_info::{CompileUnitInfo, DebugInfo, OptLevel};
-use crate::ir::lowering::lower_compile_units;
+use crate::check::diagnostics::Diagnostic;
+use crate::check::emitter::{DEFAULT_ERROR_LIMIT, Emitter, MessageFormat};
+use crate::check::lints::{Lint, LintLevel, LintLevels, Warning, warning_to_diagnostic};
+use crate::check::suggestions::unknown_value_message;
+use crate::compile_unit_info::{CompileUnitInfo, DebugInfo, Lto, OptLevel, Sanitizer, TargetInfo};
+use crate::ir::lowering::lower_compile_units_with_sink;
+use crate::ir::mangling::{demangle, demangle_text};
+use crate::ir::source_map::{SourceMap, display_ir_with_locations};
+use crate::ir::{IR, IntTy, TerminatorKind, Type, UintTy};
 use crate::parser::ProgramSource;
+use crate::plugin::Plugins;
 use anyhow::Context;
 use anyhow::Result;
 use anyhow::bail;
 use clap::Args;
 use clap::{Parser, Subcommand};
-use config::{Dependency, Package, Profile};
-use git2::{IndexAddOption, Oid, Repository};
+use config::{
+    BuildConfig, DEFAULT_FEATURE, Dependency, DependencyOrigin, LibKind, Package, ProfileConfig,
+};
+use git2::{IndexAddOption, Repository};
+use incremental::{Fingerprint, UnitFingerprint};
 use owo_colors::OwoColorize;
 use std::io::Read;
 use std::os::unix::process::CommandExt;
 use std::path::Path;
-use std::sync::Arc;
-use std::{collections::HashMap, fs::File, path::PathBuf, time::Instant};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::File,
+    path::PathBuf,
+    time::Instant,
+};
 use tracing::debug;
 
+use build_plan::BuildPlan;
+use build_script::{BuildScripts, ScriptOutput};
+use c_header::generate_header;
+use cache::Cache;
 use config::Config;
-use linker::{link_binary, link_shared_lib};
-
+use coverage::{CoverageMap, CoverageProfile, CoverageReport};
+use debug::{Debugger, SymbolMap};
+use graph::{GraphFormat, GraphKind};
+use js_bindings::generate_bindings;
+use linker::{
+    DEFAULT_ENTRY, LinkOptions, find_linker, link_binary, link_cross, link_freestanding,
+    link_fuzzer, link_sanitized, link_shared_lib, link_static_lib, link_wasm,
+};
+use lockfile::Lockfile;
+use metadata::{DependencyMetadata, PrebuiltDependencies};
+use property::{DEFAULT_PROPERTY_CASES, Generator, MAX_PROPERTY_PARAMS, PropertyResult};
+use sources::{
+    ModuleDeclaration, ModuleFiles, SourceExtensions, SourceLookupError, module_declarations,
+};
+use sysroot::Sysroot;
+use testing::{TestFilter, run_captured};
+use timings::{Phase, TimingsFormat};
+use watch::WatchMode;
+use workspace::Workspace;
+
+pub mod bindgen;
+pub mod build_plan;
+pub mod build_script;
+pub mod c_header;
+pub mod cache;
 pub mod config;
+pub mod coverage;
+pub mod debug;
+pub mod doc_tests;
+pub mod fix;
+pub mod fmt;
+pub mod fuzz;
+pub mod graph;
+pub mod ice;
+pub mod incremental;
+pub mod js_bindings;
 pub mod linker;
+pub mod lockfile;
+pub mod lsp;
+pub mod metadata;
+pub mod parallel;
+pub mod property;
+pub mod publish;
+pub mod repl;
+pub mod self_profile;
+pub mod sources;
+pub mod sysroot;
+pub mod testing;
+pub mod timings;
+pub mod ui;
+pub mod watch;
+pub mod workspace;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "The Concrete Programming Language", long_about = None, bin_name = "concrete")]
 pub struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Unstable options, `-Z self-profile[=<dir>]` writes a Chrome trace of the time and memory
+    /// used by each pass.
+    #[arg(short = 'Z', global = true, value_name = "OPTION")]
+    unstable: Vec<String>,
+}
+
+/// The options given with `-Z`.
+#[derive(Debug, Default)]
+pub struct UnstableOptions {
+    /// The directory to write the self profile to.
+    pub self_profile: Option<PathBuf>,
+}
+
+impl UnstableOptions {
+    const NAMES: &[&str] = &["self-profile"];
+
+    pub fn parse(options: &[String]) -> Result<Self> {
+        let mut unstable = Self::default();
+
+        for option in options {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (option.as_str(), None),
+            };
+
+            match name {
+                "self-profile" => {
+                    unstable.self_profile = Some(value.map(PathBuf::from).unwrap_or_default());
+                }
+                _ => bail!(
+                    "{}",
+                    unknown_value_message("unstable option", name, Self::NAMES.iter().copied())
+                ),
+            }
+        }
+
+        Ok(unstable)
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -47,13 +156,127 @@ enum Commands {
     },
     /// Build a project or file
     Build(BuildArgs),
+    /// Check a project or file for errors, without generating code.
+    Check(BuildArgs),
     /// Run a project or file
-    Run(BuildArgs),
+    Run(RunArgs),
     /// Test a project or file.
-    Test(BuildArgs),
+    Test(TestArgs),
+    /// Measure the code coverage of the tests.
+    Cov {
+        #[command(subcommand)]
+        command: CovCommand,
+    },
+    /// Build a project or file and debug it, serving the Debug Adapter Protocol on stdin and stdout.
+    Debug(DebugArgs),
+    /// Build a fuzz target with libFuzzer and run it over its corpus.
+    Fuzz(FuzzArgs),
+    /// Check, build and package a project, and upload the archive to a registry.
+    Publish(PublishArgs),
+    /// Update the commits the git dependencies are locked to in Concrete.lock.
+    Update {
+        /// Update only the given dependencies, all of them if none are given.
+        names: Vec<String>,
+    },
+    /// Apply the suggested fixes of the diagnostics to a project or file.
+    Fix {
+        /// Fix a specific file
+        path: Option<PathBuf>,
+    },
+    /// Format the source files of a project, or a file or directory.
+    Fmt {
+        /// Format a specific file, or the source files in a directory
+        path: Option<PathBuf>,
+
+        /// Print the changes formatting would make instead of writing them, failing if there are
+        /// any.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print the module graph of a project or file, or the dependency graph of a project.
+    Graph {
+        /// Print the graph of a specific file
+        path: Option<PathBuf>,
+
+        /// The graph to print.
+        #[arg(long, value_enum, default_value_t = GraphKind::Modules)]
+        kind: GraphKind,
+
+        /// The format of the graph.
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+
+        /// The file to write the graph to, printed to stdout if not given.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Serve the Language Server Protocol on stdin and stdout, for editors.
+    Lsp,
+    /// Read items and statements interactively, compiling each input with the JIT and running it.
+    Repl {
+        #[command(flatten)]
+        diagnostics: DiagnosticArgs,
+    },
+    /// Generate a module of extern declarations from a C header, using libclang.
+    Bindgen {
+        /// The C header to read.
+        header: PathBuf,
+
+        /// The file to write the module to, printed to stdout if not given.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// The name of the generated module, defaults to the header file name.
+        #[arg(long)]
+        module: Option<String>,
+
+        /// Extra arguments for clang, like include directories, given after `--`.
+        #[arg(last = true)]
+        clang_args: Vec<String>,
+    },
+    /// Demangle the symbols of Concrete functions, like the ones of a backtrace or a profile.
+    Demangle {
+        /// The symbols to demangle, the ones found in the text read from stdin if none are given.
+        symbols: Vec<String>,
+    },
 }
 
-#[derive(Args, Debug)]
+impl Commands {
+    /// The options diagnostics are printed with.
+    fn diagnostic_args(&self) -> DiagnosticArgs {
+        match self {
+            Commands::Build(args) | Commands::Check(args) => args.diagnostics.clone(),
+            Commands::Run(args) => args.build.diagnostics.clone(),
+            Commands::Test(args) => args.build.diagnostics.clone(),
+            Commands::Cov {
+                command: CovCommand::Report(args),
+            } => args.test.build.diagnostics.clone(),
+            Commands::Debug(args) => args.build.diagnostics.clone(),
+            Commands::Fuzz(args) => args.build.diagnostics.clone(),
+            Commands::Publish(args) => args.build.diagnostics.clone(),
+            Commands::Repl { diagnostics } => diagnostics.clone(),
+            _ => DiagnosticArgs::default(),
+        }
+    }
+
+    /// How the timings of the build are reported, if they were asked for.
+    fn timings(&self) -> Option<TimingsFormat> {
+        match self {
+            Commands::Build(args) | Commands::Check(args) => args.timings,
+            Commands::Run(args) => args.build.timings,
+            Commands::Test(args) => args.build.timings,
+            Commands::Cov {
+                command: CovCommand::Report(args),
+            } => args.test.build.timings,
+            Commands::Debug(args) => args.build.timings,
+            Commands::Fuzz(args) => args.build.timings,
+            Commands::Publish(args) => args.build.timings,
+            _ => None,
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
 pub struct BuildArgs {
     /// Build specific file
     #[arg(required = false)]
@@ -71,6 +294,70 @@ pub struct BuildArgs {
     #[arg(short, long)]
     profile: Option<String>,
 
+    /// Build only the given package of the workspace.
+    #[arg(long = "package", value_name = "NAME")]
+    package: Option<String>,
+
+    /// Build only the given binary of `src/bin`, the one run by `concrete run`.
+    #[arg(long, value_name = "NAME")]
+    bin: Option<String>,
+
+    /// The number of threads to compile with, one per CPU by default.
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Build for the given target triple, like `aarch64-unknown-linux-gnu`, instead of the host.
+    /// `wasm32` and `wasi` build WebAssembly modules.
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Build without std and libc, for kernels and firmware, like a package with `no_std = true`.
+    #[arg(long, default_value_t = false)]
+    freestanding: bool,
+
+    /// Enable the given features of the package, separated by commas or spaces. Features of its
+    /// dependencies are written `dependency/feature`.
+    #[arg(short = 'F', long, value_delimiter = ',', value_name = "FEATURES")]
+    features: Vec<String>,
+
+    /// Don't enable the default features of the package.
+    #[arg(long, default_value_t = false)]
+    no_default_features: bool,
+
+    /// Link the given native libraries, separated by commas, besides the ones of the `[build]`
+    /// section of the manifest.
+    #[arg(long, value_delimiter = ',', value_name = "LIBS")]
+    link_lib: Vec<String>,
+
+    /// Look for the native libraries in the given directory too.
+    #[arg(short = 'L', long, value_name = "DIR")]
+    lib_dir: Vec<PathBuf>,
+
+    /// Link with the given linker, like `mold` or `ld.lld`, instead of the one of the profile or
+    /// the default one of the target.
+    #[arg(long, value_name = "LINKER")]
+    linker: Option<String>,
+
+    /// Find std in the given sysroot instead of the one of `CONCRETE_HOME`, `~/.concrete` by
+    /// default.
+    #[arg(long, value_name = "DIR")]
+    sysroot: Option<PathBuf>,
+
+    /// Leave out the bounds checks of indexing arrays and slices, out of bounds indexes are
+    /// undefined behavior.
+    #[arg(long, default_value_t = false)]
+    unchecked_indexing: bool,
+
+    /// Instrument the code with the given sanitizers, separated by commas, like
+    /// `address,undefined`.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    sanitize: Vec<Sanitizer>,
+
+    /// Count the times each block of the code runs, and write the source lines of the blocks next
+    /// to the output, see `concrete cov report`.
+    #[arg(long, default_value_t = false)]
+    coverage: bool,
+
     /// Also output the ast.
     #[arg(long, default_value_t = false)]
     ast: bool,
@@ -95,9 +382,358 @@ pub struct BuildArgs {
     #[arg(long, default_value_t = false)]
     object: bool,
 
+    /// Also output JavaScript bindings and TypeScript declarations of the public functions, to
+    /// call a WebAssembly library from JS hosts.
+    #[arg(long, default_value_t = false)]
+    js_bindings: bool,
+
+    /// Also output a C header declaring the public functions of the library, to call them from C
+    /// and C++.
+    #[arg(long, default_value_t = false)]
+    emit_header: bool,
+
+    /// Extra outputs, separated by commas.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    emit: Vec<Emit>,
+
     /// This option is for checking the program for linearity.
     #[arg(long, default_value_t = false)]
     check: bool,
+
+    /// Print the steps of the build as JSON instead of building: the compile, archive and link
+    /// steps with their inputs and outputs, in the order they run.
+    #[arg(long, default_value_t = false)]
+    build_plan: bool,
+
+    /// Run the program in the compiler process with the JIT, without writing objects or linking
+    /// them, only with `concrete run`.
+    #[arg(long, default_value_t = false)]
+    jit: bool,
+
+    /// Report how long each phase of the build took, summed over the compile units: as a table
+    /// printed once it finishes, or written to `concrete-timings.json` or `concrete-timings.html`.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "table"
+    )]
+    timings: Option<TimingsFormat>,
+
+    /// Build again when a source file or a Concrete.toml changes, restarting the program with
+    /// `concrete run`.
+    #[arg(short, long, default_value_t = false)]
+    watch: bool,
+
+    #[command(flatten)]
+    lints: LintArgs,
+
+    #[command(flatten)]
+    diagnostics: DiagnosticArgs,
+
+    /// Build with full debug info and write the symbol map used by `concrete debug`.
+    #[arg(skip)]
+    debug: bool,
+
+    /// Build the given fuzz target with libFuzzer, see `concrete fuzz`.
+    #[arg(skip)]
+    fuzz_target: Option<String>,
+
+    /// Stop once the program is checked, without generating code or linking, see `concrete check`.
+    #[arg(skip)]
+    no_codegen: bool,
+
+    /// Build the package to be tested, enabling its `#[cfg(test)]` items, see `concrete test`.
+    #[arg(skip)]
+    test: bool,
+
+    /// Add the code blocks of the doc comments of the package as tests, see `concrete test --doc`.
+    #[arg(skip)]
+    doc_tests: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    #[command(flatten)]
+    build: BuildArgs,
+
+    /// The arguments passed to the program, after `--`.
+    #[arg(last = true, value_name = "ARGS")]
+    run_args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct TestArgs {
+    #[command(flatten)]
+    build: BuildArgs,
+
+    /// Only run the tests whose name contains one of the filters, like `Parser::`.
+    #[arg(value_name = "FILTER")]
+    filters: Vec<String>,
+
+    /// Only run the tests whose name is equal to one of the filters.
+    #[arg(long, default_value_t = false)]
+    exact: bool,
+
+    /// List the tests that would run instead of running them.
+    #[arg(long, default_value_t = false)]
+    list: bool,
+
+    /// Print the output of the tests as they run, instead of only showing it for the failing ones.
+    #[arg(long, default_value_t = false)]
+    nocapture: bool,
+
+    /// Run the UI tests in the given directory instead: each file is checked for the errors annotated with `//~ ERROR <code>`.
+    #[arg(long, value_name = "DIR")]
+    ui: Option<PathBuf>,
+
+    /// The number of generated inputs each property test is run with.
+    #[arg(long, default_value_t = DEFAULT_PROPERTY_CASES)]
+    cases: usize,
+
+    /// The seed used to generate the property test inputs, random by default.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Retry failing tests up to N times, a test that passes on a retry is reported as flaky instead of failed.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Run the code blocks of the doc comments instead, each one as a test.
+    #[arg(long, default_value_t = false)]
+    doc: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum CovCommand {
+    /// Run the tests built with `--coverage` and print the lines of each file they ran.
+    Report(CovArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct CovArgs {
+    #[command(flatten)]
+    test: TestArgs,
+
+    /// Add the counts of the given profiles, saved by earlier reports, to the ones of this run.
+    #[arg(long, value_name = "FILE")]
+    merge: Vec<PathBuf>,
+}
+
+/// The extra outputs that can be requested with `--emit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Emit {
+    /// The IR with the source location of each instruction.
+    IrWithLocs,
+}
+
+#[derive(Args, Debug)]
+pub struct FuzzArgs {
+    /// The fuzz target to run, a function marked with `#[fuzz_target]`, by its name or full path.
+    target: String,
+
+    #[command(flatten)]
+    build: BuildArgs,
+
+    /// The directory of the inputs to start from, where the interesting inputs found are added,
+    /// `fuzz/corpus/<target>` by default.
+    #[arg(long, value_name = "DIR")]
+    corpus: Option<PathBuf>,
+
+    /// The directory where the inputs crashing the target are written, `fuzz/artifacts/<target>`
+    /// by default.
+    #[arg(long, value_name = "DIR")]
+    artifacts: Option<PathBuf>,
+
+    /// Run the target once with the given input, like a crash found before, instead of fuzzing.
+    #[arg(long, value_name = "INPUT")]
+    reproduce: Vec<PathBuf>,
+
+    /// Extra options for libFuzzer, like `-max_total_time=60`, given after `--`.
+    #[arg(last = true)]
+    fuzzer_args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct PublishArgs {
+    #[command(flatten)]
+    build: BuildArgs,
+
+    /// Only write the archive to `build/package`, without uploading it.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// The URL of the registry to upload the package to, the one of `CONCRETE_REGISTRY` by
+    /// default.
+    #[arg(long, value_name = "URL")]
+    registry: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct DebugArgs {
+    #[command(flatten)]
+    build: BuildArgs,
+
+    /// The debugger whose debug adapter is used.
+    #[arg(long, value_enum, default_value_t = Debugger::Lldb)]
+    debugger: Debugger,
+
+    /// The path of the debug adapter, if it's not found in the PATH.
+    #[arg(long)]
+    adapter: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DiagnosticArgs {
+    /// Stop printing errors after N distinct ones, the rest are only counted, 0 prints them all.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_ERROR_LIMIT)]
+    error_limit: usize,
+
+    /// How to print the diagnostics: `short` prints one line per diagnostic, `json` prints a JSON object per
+    /// diagnostic to stdout for editors and CI, `sarif` prints a SARIF log to stdout for code scanning tools.
+    #[arg(long, alias = "error-format", value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+
+    /// The emitter of the build, created when it's first used and shared by the clones of the
+    /// arguments, so the summary is printed once for all the compilations.
+    #[arg(skip)]
+    shared: Arc<OnceLock<Mutex<Emitter>>>,
+}
+
+impl Default for DiagnosticArgs {
+    fn default() -> Self {
+        Self {
+            error_limit: DEFAULT_ERROR_LIMIT,
+            message_format: MessageFormat::default(),
+            shared: Arc::default(),
+        }
+    }
+}
+
+impl DiagnosticArgs {
+    pub fn emitter(&self) -> Emitter {
+        Emitter::new((self.error_limit > 0).then_some(self.error_limit))
+            .with_format(self.message_format)
+    }
+
+    /// The emitter shared by the compilations of the build.
+    pub fn shared_emitter(&self) -> MutexGuard<'_, Emitter> {
+        self.shared
+            .get_or_init(|| Mutex::new(self.emitter()))
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The same options, with an emitter of their own for a new build.
+    fn for_build(&self) -> Self {
+        Self {
+            shared: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Prints the summary of the diagnostics of the build, or its SARIF log, if it emitted any.
+    pub fn print_summary(&self) {
+        if let Some(emitter) = self.shared.get() {
+            emitter
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .print_summary();
+        }
+    }
+}
+
+/// Why the program couldn't be compiled. It's returned instead of exiting, so the driver can be
+/// used as a library, and the command line prints it and exits with an error.
+#[derive(Debug, thiserror::Error)]
+pub enum CompileError {
+    /// A source couldn't be read or parsed, the diagnostic isn't printed yet.
+    #[error("{}", diagnostic.primary_message())]
+    Parse {
+        diagnostic: Box<Diagnostic>,
+        /// The source the diagnostic points into.
+        input: String,
+    },
+    /// The errors were printed as they were found, like the ones of lowering.
+    #[error("could not compile due to the previous errors")]
+    Reported,
+}
+
+impl CompileError {
+    /// Emits the diagnostics that weren't printed yet with the emitter of the build, which prints
+    /// them with its summary.
+    pub fn emit(&self, diagnostics: &DiagnosticArgs) -> Result<()> {
+        if let CompileError::Parse { diagnostic, input } = self {
+            let mut emitter = diagnostics.shared_emitter();
+            emitter
+                .sources_mut()
+                .insert(diagnostic.path.display().to_string(), input.clone());
+            emitter.emit(diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct LintArgs {
+    /// Report the given lint as a warning.
+    #[arg(short = 'W', long = "warn", value_name = "LINT")]
+    warn: Vec<String>,
+
+    /// Don't report the given lint.
+    #[arg(short = 'A', long = "allow", value_name = "LINT")]
+    allow: Vec<String>,
+
+    /// Report the given lint as an error, `-D warnings` denies all warnings.
+    #[arg(short = 'D', long = "deny", value_name = "LINT")]
+    deny: Vec<String>,
+
+    /// Treat all warnings as errors, failing the build, useful for CI.
+    #[arg(long, default_value_t = false)]
+    deny_warnings: bool,
+}
+
+impl LintArgs {
+    /// Gets the lint levels from the flags, later levels override earlier ones in the order: allow, warn, deny.
+    pub fn levels(&self) -> Result<LintLevels> {
+        let mut levels = LintLevels::default();
+        levels.deny_warnings = self.deny_warnings;
+
+        for (names, level) in [
+            (&self.allow, LintLevel::Allow),
+            (&self.warn, LintLevel::Warn),
+            (&self.deny, LintLevel::Deny),
+        ] {
+            for name in names {
+                if name == "warnings" && level == LintLevel::Deny {
+                    levels.deny_warnings = true;
+                    continue;
+                }
+
+                // The lints of plugins are only known once they're loaded.
+                if name.contains("::") {
+                    levels.set_plugin(name, level);
+                    continue;
+                }
+
+                match Lint::from_name(name) {
+                    Some(lint) => levels.set(lint, level),
+                    None => bail!(
+                        "{}",
+                        unknown_value_message(
+                            "lint",
+                            name,
+                            Lint::ALL.iter().map(|x| x.name()).chain(["warnings"])
+                        )
+                    ),
+                }
+            }
+        }
+
+        Ok(levels)
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -125,6 +761,35 @@ pub struct CompilerArgs {
     #[arg(short, long, default_value_t = false)]
     library: bool,
 
+    /// Generate code for the given target triple, like `aarch64-unknown-linux-gnu`, instead of the
+    /// host.
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Link without libc and the startup files, for kernels and firmware: the binary starts at the
+    /// entry symbol.
+    #[arg(long, default_value_t = false)]
+    freestanding: bool,
+
+    /// The symbol a freestanding binary starts at, `_start` by default.
+    #[arg(long, value_name = "SYMBOL")]
+    entry: Option<String>,
+
+    /// Leave out the bounds checks of indexing arrays and slices, out of bounds indexes are
+    /// undefined behavior.
+    #[arg(long, default_value_t = false)]
+    unchecked_indexing: bool,
+
+    /// Instrument the code with the given sanitizers, separated by commas, like
+    /// `address,undefined`.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    sanitize: Vec<Sanitizer>,
+
+    /// Count the times each block of the code runs, and write the source lines of the blocks next
+    /// to the output, see `concrete cov report`.
+    #[arg(long, default_value_t = false)]
+    coverage: bool,
+
     /// Also output the ast.
     #[arg(long, default_value_t = false)]
     ast: bool,
@@ -149,17 +814,228 @@ pub struct CompilerArgs {
     #[arg(long, default_value_t = false)]
     object: bool,
 
+    /// Also output JavaScript bindings and TypeScript declarations of the public functions, to
+    /// call a WebAssembly library from JS hosts.
+    #[arg(long, default_value_t = false)]
+    js_bindings: bool,
+
+    /// Also output a C header declaring the public functions of the library, to call them from C
+    /// and C++.
+    #[arg(long, default_value_t = false)]
+    emit_header: bool,
+
+    /// Extra outputs, separated by commas.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    emit: Vec<Emit>,
+
     /// This option is for checking the program for linearity.
     #[arg(long, default_value_t = false)]
     check: bool,
+
+    #[command(flatten)]
+    lints: LintArgs,
+
+    #[command(flatten)]
+    diagnostics: DiagnosticArgs,
+
+    /// The dependencies being compiled, to point out the diagnostics found in them.
+    #[arg(skip)]
+    dependencies: Vec<DependencyOrigin>,
+
+    /// Write the symbol map used by `concrete debug` next to the output.
+    #[arg(skip)]
+    symbol_map: bool,
+
+    /// The plugins listed in the manifest.
+    #[arg(skip)]
+    plugins: Arc<Plugins>,
+
+    /// The fuzz target libFuzzer calls, instrumenting the code for it.
+    #[arg(skip)]
+    fuzz_target: Option<String>,
+
+    /// Where the fingerprint of the output is kept, to build it incrementally.
+    #[arg(skip)]
+    fingerprint: Option<PathBuf>,
+
+    /// The native libraries linked with the output.
+    #[arg(skip)]
+    link_libs: Vec<String>,
+
+    /// Where the native libraries are looked for, besides the system directories.
+    #[arg(skip)]
+    lib_dirs: Vec<PathBuf>,
+
+    /// The kinds of library built, with `library`.
+    #[arg(skip)]
+    lib_kinds: Vec<LibKind>,
+
+    /// The linker used instead of the default one of the target.
+    #[arg(skip)]
+    linker: Option<String>,
+
+    /// Extra arguments of the linker, from the profile.
+    #[arg(skip)]
+    linker_args: Vec<String>,
+
+    /// The directories the binary looks for shared libraries in, from the profile.
+    #[arg(skip)]
+    rpath: Vec<String>,
+
+    /// Link time optimization, from the profile.
+    #[arg(skip)]
+    lto: Lto,
+
+    /// The most objects the compile units are generated into, from the profile.
+    #[arg(skip)]
+    codegen_units: Option<usize>,
+
+    /// Whether the doc tests of the package were added to it, see `concrete test --doc`.
+    #[arg(skip)]
+    doc_tests: bool,
+
+    /// The sysroot keeping the prebuilt compile units of its std.
+    #[arg(skip)]
+    sysroot: Option<Sysroot>,
+
+    /// The global cache keeping the compile units of the git dependencies.
+    #[arg(skip)]
+    cache: Option<Cache>,
+
+    /// Where the other compile units are kept, the ones of the package and of its path
+    /// dependencies, so the binaries of `src/bin` reuse the ones compiled for the first binary.
+    #[arg(skip)]
+    package_units: Option<PathBuf>,
+
+    /// The dependencies lowered from their metadata, whose code is generated into an object of
+    /// their own.
+    #[arg(skip)]
+    prebuilt_dependencies: Option<PrebuiltDependencies>,
+}
+
+impl CompilerArgs {
+    /// Whether outputs other than the object are asked for, they are only written by a full
+    /// build.
+    fn has_extra_outputs(&self) -> bool {
+        self.ast
+            || self.ir
+            || self.llvm
+            || self.mlir
+            || self.asm
+            || self.js_bindings
+            || self.emit_header
+            || self.symbol_map
+            || !self.emit.is_empty()
+    }
+
+    /// The session the compile units are compiled with.
+    fn compile_unit_info(&self) -> CompileUnitInfo {
+        CompileUnitInfo {
+            debug_info: if let Some(debug_info) = self.debug_info {
+                if debug_info {
+                    DebugInfo::Full
+                } else {
+                    DebugInfo::None
+                }
+            } else if self.release {
+                DebugInfo::None
+            } else {
+                DebugInfo::Full
+            },
+            optlevel: if let Some(optlevel) = self.optlevel {
+                match optlevel {
+                    0 => OptLevel::None,
+                    1 => OptLevel::Less,
+                    2 => OptLevel::Default,
+                    _ => OptLevel::Aggressive,
+                }
+            } else if self.release {
+                OptLevel::Aggressive
+            } else {
+                OptLevel::None
+            },
+            library: self.library,
+            output_file: self.output.with_extension("o"),
+            output_asm: self.asm,
+            output_ll: self.llvm,
+            output_mlir: self.mlir,
+            fuzzing: self.fuzz_target.is_some(),
+            unchecked_indexing: self.unchecked_indexing,
+            target: TargetInfo::new(self.target.clone()),
+            lto: self.lto,
+            sanitizers: self.sanitize.clone(),
+            coverage: self.coverage,
+            freestanding: self.freestanding,
+        }
+    }
+
+    /// The symbol a freestanding binary starts at.
+    fn entry_symbol(&self) -> &str {
+        self.entry.as_deref().unwrap_or(DEFAULT_ENTRY)
+    }
+
+    /// How the objects are linked: the native libraries, and the linker and its arguments of the
+    /// profile.
+    fn link_options(&self) -> LinkOptions {
+        LinkOptions {
+            linker: self.linker.clone(),
+            args: self
+                .lib_dirs
+                .iter()
+                .map(|x| format!("-L{}", x.display()))
+                .chain(self.link_libs.iter().map(|x| format!("-l{x}")))
+                .chain(self.linker_args.iter().cloned())
+                .collect(),
+            rpath: self.rpath.clone(),
+            sanitizers: self.sanitize.clone(),
+        }
+    }
 }
 
 pub fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
+    ice::install_panic_hook();
 
     let cli = Cli::parse();
+    let unstable = UnstableOptions::parse(&cli.unstable)?;
+    let diagnostics = cli.command.diagnostic_args();
+    let timings_format = cli.command.timings();
+
+    if let Some(dir) = &unstable.self_profile {
+        self_profile::enable(dir)?;
+    }
+    if timings_format.is_some() {
+        timings::enable();
+    }
+
+    let result = run(cli.command);
+
+    if let Some(path) = self_profile::finish() {
+        eprintln!(
+            "{}: the self profile was written to {}",
+            "note".bold(),
+            path.display()
+        );
+    }
+
+    if let (Some(format), Some(timings)) = (timings_format, timings::finish()) {
+        timings.report(format)?;
+    }
+
+    // The errors of the program are printed as diagnostics, not as an error of the compiler.
+    match result.map_err(|x| x.downcast::<CompileError>()) {
+        Err(Ok(error)) => {
+            error.emit(&diagnostics)?;
+            diagnostics.print_summary();
+            std::process::exit(1);
+        }
+        Err(Err(error)) => Err(error),
+        Ok(()) => Ok(()),
+    }
+}
 
-    match cli.command {
+fn run(command: Commands) -> Result<()> {
+    match command {
         Commands::New { path, name, lib } => {
             let name = name.unwrap_or_else(|| {
                 path.file_name()
@@ -180,30 +1056,39 @@ pub fn main() -> Result<()> {
 
             profiles.insert(
                 "release".to_string(),
-                Profile {
-                    release: true,
-                    opt_level: 3,
-                    debug_info: false,
+                ProfileConfig {
+                    release: Some(true),
+                    opt_level: Some(3),
+                    debug_info: Some(false),
+                    ..Default::default()
                 },
             );
 
             profiles.insert(
                 "dev".to_string(),
-                Profile {
-                    release: false,
-                    opt_level: 0,
-                    debug_info: true,
+                ProfileConfig {
+                    release: Some(false),
+                    opt_level: Some(0),
+                    debug_info: Some(true),
+                    ..Default::default()
                 },
             );
 
             let config = Config {
-                package: Package {
+                package: Some(Package {
                     name: name.clone(),
                     version: "0.1.0".to_string(),
                     license: "MIT".to_string(),
-                },
+                    ..Default::default()
+                }),
+                workspace: None,
                 profile: profiles,
                 dependencies: HashMap::new(),
+                features: Default::default(),
+                plugins: Default::default(),
+                build: Default::default(),
+                lib: Default::default(),
+                run: Default::default(),
             };
 
             std::fs::write(config_path, toml::to_string_pretty(&config)?)
@@ -270,99 +1155,491 @@ pub fn main() -> Result<()> {
                 println!("  {} library `{}` package", "Created".green(), name);
             }
         }
+        Commands::Build(args) if args.watch => {
+            watch::watch(args, &[], WatchMode::Build)?;
+        }
+        Commands::Check(args) if args.watch => {
+            watch::watch(args, &[], WatchMode::Check)?;
+        }
+        Commands::Run(args) if args.build.watch => {
+            watch::watch(args.build, &args.run_args, WatchMode::Run)?;
+        }
         Commands::Build(args) => {
+            if args.jit {
+                bail!("`--jit` runs the program, use it with `concrete run`");
+            }
             handle_build(args)?;
         }
-        Commands::Run(args) => {
-            let output = handle_build(args)?.0;
-            println!();
-            Err(std::process::Command::new(output).exec())?;
+        Commands::Check(mut args) => {
+            args.no_codegen = true;
+            handle_build(args)?;
         }
-        Commands::Test(mut args) => {
-            args.lib = true;
-            let (output, tests) = handle_build(args)?;
-            println!();
-
-            let tests = Arc::new(tests);
-
-            println!("Running {} tests", tests.len());
-
-            let mut passed = 0;
-
-            if !tests.is_empty() {
-                let lib = unsafe { libloading::Library::new(output).expect("failed to load") };
-
-                for test in tests.iter() {
-                    print!("test {} ... ", test.symbol);
-                    let test_fn = unsafe {
-                        lib.get::<unsafe extern "C" fn() -> i32>(test.mangled_symbol.as_bytes())
-                    };
-
-                    if test_fn.is_err() {
-                        println!("{}", "err".red());
-                        eprintln!("Symbol not found: {:?}", test_fn);
-                        continue;
-                    }
-
-                    let test_fn = test_fn.unwrap();
-
-                    let result = unsafe { (test_fn)() };
+        Commands::Update { names } => {
+            lockfile::handle_update(names)?;
+        }
+        Commands::Fix { path } => {
+            fix::handle_fix(path)?;
+        }
+        Commands::Fmt { path, check } => {
+            fmt::handle_fmt(path, check)?;
+        }
+        Commands::Graph {
+            path,
+            kind,
+            format,
+            output,
+        } => {
+            graph::handle_graph(path, kind, format, output)?;
+        }
+        Commands::Debug(args) => {
+            let mut build = args.build;
+            build.debug = true;
+            let (output, _) = handle_build_one(build)?;
+            debug::serve(&output, args.debugger, args.adapter)?;
+        }
+        Commands::Fuzz(args) => {
+            fuzz::handle_fuzz(args)?;
+        }
+        Commands::Publish(args) => {
+            publish::handle_publish(args)?;
+        }
+        Commands::Lsp => {
+            lsp::serve()?;
+        }
+        Commands::Repl { diagnostics } => {
+            repl::serve(&diagnostics)?;
+        }
+        Commands::Bindgen {
+            header,
+            output,
+            module,
+            clang_args,
+        } => {
+            let module = match module {
+                Some(module) => module,
+                None => header
+                    .file_stem()
+                    .context("the header has no file name")?
+                    .to_string_lossy()
+                    .replace(|c: char| !c.is_alphanumeric(), "_"),
+            };
+            let decls = bindgen::parse_header(&header, &clang_args)?;
+            let generated = bindgen::generate_module(&module, &decls);
 
-                    if result == 0 {
+            match output {
+                Some(output) => std::fs::write(&output, generated)
+                    .with_context(|| format!("failed to write {}", output.display()))?,
+                None => print!("{generated}"),
+            }
+        }
+        Commands::Demangle { symbols } if symbols.is_empty() => {
+            for line in std::io::stdin().lines() {
+                println!("{}", demangle_text(&line?));
+            }
+        }
+        Commands::Demangle { symbols } => {
+            for symbol in symbols {
+                println!("{}", demangle(&symbol).unwrap_or(symbol));
+            }
+        }
+        Commands::Run(RunArgs { build, run_args }) if build.jit => {
+            if !run_args.is_empty() {
+                bail!("the program can't be given arguments when run with `--jit`");
+            }
+            // The program runs in this process, which exits once it returns.
+            for (key, value) in run_env(&build)? {
+                unsafe { std::env::set_var(key, value) };
+            }
+            handle_build_one(build)?;
+        }
+        Commands::Run(RunArgs { build, run_args }) => {
+            let env = run_env(&build)?;
+            let output = handle_build_one(build)?.0;
+            println!();
+            Err(std::process::Command::new(output)
+                .args(run_args)
+                .envs(env)
+                .exec())?;
+        }
+        Commands::Test(args) => {
+            handle_test(args)?;
+        }
+        Commands::Cov {
+            command: CovCommand::Report(args),
+        } => {
+            if args.test.ui.is_some() {
+                bail!("the UI tests only check the diagnostics, they have no coverage");
+            }
+
+            let mut test = args.test;
+            test.build.coverage = true;
+
+            let Some((output, library)) = handle_test(test)? else {
+                println!("no tests ran, there is no coverage to report");
+                return Ok(());
+            };
+
+            let map = CoverageMap::load(&CoverageMap::path_for(&output))?;
+            let mut profile = unsafe { CoverageProfile::read(&library, &map) };
+            for path in &args.merge {
+                profile.merge(&CoverageProfile::load(path)?);
+            }
+
+            let profile_path = CoverageProfile::path_for(&output);
+            profile.save(&profile_path)?;
+
+            println!();
+            print!("{}", CoverageReport::new(&map, &profile));
+            println!("\nthe profile was saved to {}", profile_path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the tests and runs them, returning the library they were loaded from and its path, to
+/// read what they left in it, `None` if no test ran.
+fn handle_test(args: TestArgs) -> Result<Option<(PathBuf, libloading::Library)>> {
+    if let Some(dir) = args.ui {
+        ui::handle_ui_tests(&dir)?;
+        return Ok(None);
+    }
+
+    let cases = args.cases;
+    let seed = args.seed.unwrap_or_else(property::random_seed);
+    let retries = args.retries;
+    let list = args.list;
+    let nocapture = args.nocapture;
+    let doc = args.doc;
+    let mut filter = TestFilter {
+        filters: args.filters,
+        exact: args.exact,
+    };
+    let mut args = args.build;
+    args.lib = true;
+    args.test = true;
+    args.doc_tests = doc;
+
+    // The first filter is parsed as the file to build, it's only one if it's a source file.
+    if let Some(path) = args
+        .path
+        .take_if(|x| x.extension().is_none_or(|x| x != "con") || !x.is_file())
+    {
+        filter
+            .filters
+            .insert(0, path.to_string_lossy().into_owned());
+    }
+
+    let (output, tests) = handle_build_one(args)?;
+
+    let found = tests.len();
+    let tests: Vec<TestInfo> = tests
+        .into_iter()
+        .filter(|x| !doc || doc_tests::is_doc_test(&x.symbol))
+        .filter(|x| filter.matches(&x.symbol))
+        .collect();
+    let filtered_out = found - tests.len();
+
+    if list {
+        for test in &tests {
+            println!("{}: test", test.symbol);
+        }
+        println!("\n{} tests", tests.len());
+        return Ok(None);
+    }
+
+    let tests = Arc::new(tests);
+
+    println!("Running {} tests", tests.len());
+
+    let mut passed = 0;
+    let mut flaky = Vec::new();
+    // The failing tests and what they printed.
+    let mut failures: Vec<(String, String)> = Vec::new();
+    let mut library = None;
+
+    if !tests.is_empty() {
+        let lib = unsafe { libloading::Library::new(&output).expect("failed to load") };
+
+        for test in tests.iter() {
+            print!("test {} ... ", test.symbol);
+
+            if let Some(generators) = &test.property {
+                let (result, output) = run_captured(nocapture, || unsafe {
+                    property::run_property_test(&lib, &test.mangled_symbol, generators, cases, seed)
+                })?;
+                let result = result?;
+
+                match result {
+                    PropertyResult::Passed { cases } => {
                         passed += 1;
-                        println!("{}", "ok".green());
-                    } else {
+                        println!("{} ({} cases)", "ok".green(), cases);
+                    }
+                    PropertyResult::Failed {
+                        original,
+                        shrunk,
+                        seed,
+                    } => {
                         println!("{}", "err".red());
+                        failures.push((test.symbol.clone(), output));
+                        eprintln!(
+                            "  failed with input ({}), shrunk from ({})",
+                            property::display_input(&shrunk),
+                            property::display_input(&original)
+                        );
+                        eprintln!("  rerun with --seed {}", seed);
                     }
                 }
+                continue;
             }
+            let test_fn =
+                unsafe { lib.get::<unsafe extern "C" fn() -> i32>(test.mangled_symbol.as_bytes()) };
 
-            println!();
-            if !tests.is_empty() {
-                println!(
-                    "test result: {}. {} passed; {} failed; ({:.2}%)",
-                    if passed == tests.len() {
-                        "ok".green().to_string()
-                    } else {
-                        "err".red().to_string()
-                    },
-                    passed,
-                    tests.len() - passed,
-                    ((passed as f64 / tests.len() as f64) * 100.0).bold()
-                );
+            if test_fn.is_err() {
+                println!("{}", "err".red());
+                eprintln!("Symbol not found: {:?}", test_fn);
+                continue;
+            }
+
+            let test_fn = test_fn.unwrap();
+
+            let retries = retries.max(test.retries);
+            let mut attempts = 0;
+            let (mut result, mut output) = run_captured(nocapture, || unsafe { (test_fn)() })?;
+
+            while result != 0 && attempts < retries {
+                attempts += 1;
+                (result, output) = run_captured(nocapture, || unsafe { (test_fn)() })?;
             }
 
-            return Ok(());
+            if result == 0 && attempts == 0 {
+                passed += 1;
+                println!("{}", "ok".green());
+            } else if result == 0 {
+                passed += 1;
+                flaky.push(test.symbol.clone());
+                println!("{} (passed after {} retries)", "flaky".yellow(), attempts);
+            } else {
+                println!("{}", "err".red());
+                failures.push((test.symbol.clone(), output));
+            }
         }
+
+        library = Some(lib);
     }
 
-    Ok(())
+    let failures: Vec<_> = failures
+        .into_iter()
+        .filter(|(_, output)| !output.is_empty())
+        .collect();
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, output) in &failures {
+            println!("\n---- {} output ----", name);
+            println!("{}", output.trim_end());
+        }
+    }
+
+    println!();
+    if !flaky.is_empty() {
+        println!("flaky tests:");
+        for name in &flaky {
+            println!("    {}", name);
+        }
+        println!();
+    }
+
+    if !tests.is_empty() {
+        println!(
+            "test result: {}. {} passed; {} failed; {} flaky; {} filtered out; ({:.2}%)",
+            if passed == tests.len() {
+                "ok".green().to_string()
+            } else {
+                "err".red().to_string()
+            },
+            passed,
+            tests.len() - passed,
+            flaky.len(),
+            filtered_out,
+            ((passed as f64 / tests.len() as f64) * 100.0).bold()
+        );
+    }
+
+    Ok(library.map(|x| (output, x)))
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TestInfo {
     pub mangled_symbol: String,
     pub symbol: String,
+    /// The generators of the parameters, if it's a property test.
+    pub property: Option<Vec<Generator>>,
+    /// The times the test is retried if it fails, from its `#[retries]` attribute.
+    pub retries: u32,
+}
+
+/// Builds a single package or file, like the one to run.
+fn handle_build_one(args: BuildArgs) -> Result<(PathBuf, Vec<TestInfo>)> {
+    if args.build_plan {
+        bail!("`--build-plan` only describes a build, use it with `concrete build`");
+    }
+
+    if let Some(target) = &args.target {
+        bail!("the output is run after building, it can't be built for another target ({target})");
+    }
+
+    if args.path.is_none() && args.package.is_none() {
+        let config_path = find_config_path()?;
+        let project_dir = config_path
+            .parent()
+            .context("couldn't get config parent dir")?;
+
+        if let Some(workspace) = Workspace::find(project_dir)? {
+            if workspace.select(project_dir, None)?.len() > 1 {
+                bail!("the workspace has several packages, choose one with `--package <name>`");
+            }
+        }
+    }
+
+    handle_build(args)?
+        .pop()
+        .context("the workspace has no packages")
+}
+
+/// The environment variables of the `[run]` section of the package `concrete run` runs, none for
+/// a single file.
+fn run_env(args: &BuildArgs) -> Result<BTreeMap<String, String>> {
+    if args.path.is_some() {
+        return Ok(BTreeMap::new());
+    }
+
+    let config_path = find_config_path()?;
+    let project_dir = config_path
+        .parent()
+        .context("couldn't get config parent dir")?;
+    let package_dir = match Workspace::find(project_dir)? {
+        Some(workspace) => workspace
+            .select(project_dir, args.package.as_deref())?
+            .pop()
+            .context("the workspace has no packages")?,
+        None => project_dir.to_path_buf(),
+    };
+
+    Ok(Config::read(&package_dir.join("Concrete.toml"))?.run.env)
 }
 
-fn handle_build(
+/// Builds the file or the project, printing the summary of its diagnostics once it's done.
+fn handle_build(mut args: BuildArgs) -> Result<Vec<(PathBuf, Vec<TestInfo>)>> {
+    args.diagnostics = args.diagnostics.for_build();
+    let diagnostics = args.diagnostics.clone();
+
+    let result = match build(args).map_err(|x| x.downcast::<CompileError>()) {
+        Ok(outputs) => Ok(outputs),
+        Err(Ok(error)) => {
+            error.emit(&diagnostics)?;
+            Err(CompileError::Reported.into())
+        }
+        Err(Err(error)) => Err(error),
+    };
+    diagnostics.print_summary();
+
+    result
+}
+
+fn build(
     BuildArgs {
         path,
         release,
         profile,
+        package,
+        bin,
+        jobs,
+        target,
+        freestanding,
+        features,
+        no_default_features,
+        link_lib,
+        lib_dir,
+        linker,
+        sysroot,
+        unchecked_indexing,
+        sanitize,
+        coverage,
         ast,
         ir,
         llvm,
         mlir,
         asm,
         object,
+        js_bindings,
+        emit_header,
+        emit,
         lib,
         check,
+        build_plan,
+        jit,
+        timings: _,
+        watch,
+        lints,
+        diagnostics,
+        debug,
+        fuzz_target,
+        no_codegen,
+        test,
+        doc_tests,
     }: BuildArgs,
-) -> Result<(PathBuf, Vec<TestInfo>)> {
+) -> Result<Vec<(PathBuf, Vec<TestInfo>)>> {
+    if watch {
+        bail!(
+            "`--watch` builds again when the files change, use it with `concrete build`, `check` or `run`"
+        );
+    }
+    if build_plan && no_codegen {
+        bail!("`--build-plan` only describes a build, use it with `concrete build`");
+    }
+    if jit && (no_codegen || build_plan || test || debug || fuzz_target.is_some()) {
+        bail!("`--jit` runs the program, use it with `concrete run`");
+    }
+    if jit && target.is_some() {
+        bail!("`--jit` runs the program on the host, it can't be built for another target");
+    }
+    if jit && coverage {
+        bail!(
+            "`--coverage` counts are read from the library by `concrete cov report`, it can't run with `--jit`"
+        );
+    }
+    if jit && !sanitize.is_empty() {
+        bail!(
+            "sanitized programs need the runtimes of the sanitizers, they can't run with `--jit`"
+        );
+    }
+    if freestanding {
+        check_freestanding(jit, fuzz_target.is_some(), &sanitize)?;
+    }
+
+    parallel::set_jobs(jobs);
+    let target_info = TargetInfo::new(target.clone());
+    crate::codegen::check_target(&target_info)?;
+    if target_info.is_wasm() && !sanitize.is_empty() {
+        bail!("the sanitizers aren't supported on WebAssembly targets");
+    }
+    let mut features: BTreeSet<String> = features
+        .iter()
+        .flat_map(|x| x.split_whitespace())
+        .map(str::to_string)
+        .collect();
+    if !no_default_features {
+        features.insert(DEFAULT_FEATURE.to_string());
+    }
+    let sysroot = Sysroot::locate(sysroot.as_deref())?;
+
     match path {
         // Single file compilation
         Some(input) => {
+            if emit_header && !lib {
+                bail!("`--emit-header` declares the functions of a library, build it with `--lib`");
+            }
+
             let input_stem = input
                 .file_stem()
                 .context("could not get file stem")?
@@ -371,387 +1648,1413 @@ fn handle_build(
 
             let build_dir = std::env::current_dir()?;
             let output = build_dir.join(input_stem);
+            // The host linker adds the extension of shared libraries itself.
+            let output = match target_info.binary_ext() {
+                Some(ext) if !lib => output.with_extension(ext),
+                _ if lib && !target_info.is_host() => {
+                    output.with_extension(target_info.library_ext())
+                }
+                _ => output,
+            };
 
             let compile_args = CompilerArgs {
                 input: input.clone(),
                 output: output.clone(),
                 release,
                 optlevel: None,
-                debug_info: None,
+                debug_info: debug.then_some(true),
                 library: lib,
+                target: target.clone(),
+                freestanding,
+                entry: None,
+                unchecked_indexing,
+                sanitize: sanitize.clone(),
+                coverage,
                 ast,
                 ir,
                 llvm,
                 asm,
                 object,
+                js_bindings,
+                emit_header,
+                emit,
                 mlir,
                 check,
+                lints,
+                diagnostics,
+                dependencies: Vec::new(),
+                symbol_map: debug,
+                plugins: Arc::default(),
+                fuzz_target: fuzz_target.clone(),
+                fingerprint: None,
+                link_libs: link_lib.clone(),
+                lib_dirs: lib_dir.clone(),
+                lib_kinds: vec![LibKind::Shared],
+                linker: linker.clone(),
+                linker_args: Vec::new(),
+                rpath: Vec::new(),
+                lto: Lto::Off,
+                codegen_units: None,
+                doc_tests,
+                sysroot: sysroot.clone(),
+                cache: None,
+                package_units: None,
+                prebuilt_dependencies: None,
             };
+            if !no_codegen && !build_plan {
+                check_linker(compile_args.linker.as_deref())?;
+            }
 
-            println!(
+            eprintln!(
                 "   {} {} ({})",
-                "Compiling".green().bold(),
+                if no_codegen { "Checking" } else { "Compiling" }
+                    .green()
+                    .bold(),
                 input_stem,
                 input.display()
             );
 
             let start = Instant::now();
-            let ast_file = parse_file(input.clone())?;
-            let (object, tests) = compile(&compile_args, &[ast_file])?;
+            let mut ast_file = parse_file(input.clone(), &SourceExtensions::default())?;
+            if doc_tests {
+                doc_tests::add_doc_tests(&mut ast_file)?;
+            }
+            // A single file has no feature table, the requested features are the enabled ones.
+            features.remove(DEFAULT_FEATURE);
+            ast_file.cfg = Cfg {
+                profile: if release { "release" } else { "dev" }.to_string(),
+                debug_assertions: !release,
+                test,
+                features,
+                ..Cfg::for_target(target_info.triple.as_deref())
+            };
 
-            if lib {
-                link_shared_lib(&[object.clone()], &output)?;
-            } else {
-                link_binary(&[object.clone()], &output)?;
+            if build_plan {
+                let mut plan = BuildPlan::default();
+                let profile = if release { "release" } else { "dev" };
+                plan.add_program(&compile_args, &[ast_file], input_stem, profile, &[]);
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+                return Ok(vec![(output, Vec::new())]);
+            }
+
+            if no_codegen {
+                self::check(&compile_args, &[ast_file])?;
+                eprintln!(
+                    "   {} {} in {:?}",
+                    "Finished".green().bold(),
+                    if release { "release" } else { "dev" },
+                    start.elapsed()
+                );
+                return Ok(vec![(output, Vec::new())]);
+            }
+
+            if jit {
+                let program = self::check(&compile_args, &[ast_file])?;
+                return run_jit(&compile_args, &program);
             }
 
+            let (objects, tests, libs) = compile(&compile_args, &[ast_file])?;
+            link(&compile_args, &objects, &libs)?;
+
             if !compile_args.object {
-                std::fs::remove_file(object)?;
+                for object in objects {
+                    std::fs::remove_file(object)?;
+                }
             }
 
             let elapsed = start.elapsed();
 
-            println!(
+            eprintln!(
                 "   {} {} in {elapsed:?}",
                 "Finished".green().bold(),
                 if release { "release" } else { "dev" },
             );
 
-            Ok((output, tests))
+            Ok(vec![(output, tests)])
         }
         // Project compilation.
         None => {
-            let mut current_dir = std::env::current_dir()?;
-            let mut config_path = None;
-            for _ in 0..3 {
-                if !current_dir.join("Concrete.toml").exists() {
-                    current_dir = if let Some(parent) = current_dir.parent() {
-                        parent.to_path_buf()
-                    } else {
-                        bail!("couldn't find Concrete.toml");
-                    };
-                } else {
-                    config_path = Some(current_dir.join("Concrete.toml"));
-                    break;
-                }
-            }
-            let config_path = match config_path {
-                Some(x) => x,
-                None => bail!("couldn't find Concrete.toml"),
-            };
-            let base_dir = config_path
+            let config_path = find_config_path()?;
+            let project_dir = config_path
                 .parent()
                 .context("couldn't get config parent dir")?;
-            let mut config = File::open(&config_path).context("failed to open Concrete.toml")?;
-            let mut buf = String::new();
-            config.read_to_string(&mut buf)?;
-            let config: Config = toml::from_str(&buf).context("failed to parse Concrete.toml")?;
-            let src_dir = base_dir.join("src");
-            let target_dir = base_dir.join("build");
+            let workspace = Workspace::find(project_dir)?;
+
+            // The members of a workspace are built into the build directory of its root.
+            let (root_dir, packages) = match &workspace {
+                Some(workspace) => (
+                    workspace.root.clone(),
+                    workspace.select(project_dir, package.as_deref())?,
+                ),
+                None => {
+                    let config = Config::read(&config_path)?;
+                    let name = &config.package()?.name;
+
+                    if let Some(package) = &package {
+                        if package != name {
+                            bail!(
+                                "{}",
+                                unknown_value_message("package", package, [name.as_str()])
+                            );
+                        }
+                    }
+
+                    (project_dir.to_path_buf(), vec![project_dir.to_path_buf()])
+                }
+            };
+
+            // Builds for other targets are kept apart, like cargo does.
+            let target_dir = match &target {
+                Some(triple) => root_dir.join("build").join(triple),
+                None => root_dir.join("build"),
+            };
             if !target_dir.exists() {
                 std::fs::create_dir_all(&target_dir)?;
             }
-            let mut output = target_dir.join(config.package.name);
-            let (profile, profile_name) = if let Some(profile) = profile {
-                (
-                    config
-                        .profile
-                        .get(&profile)
-                        .context("couldn't get requested profile")?,
-                    profile,
-                )
-            } else if release {
-                (
-                    config
-                        .profile
-                        .get("release")
-                        .context("couldn't get profile: release")?,
-                    "release".to_string(),
-                )
-            } else {
-                (
-                    config
-                        .profile
-                        .get("dev")
-                        .context("couldn't get profile: dev")?,
-                    "dev".to_string(),
-                )
-            };
 
-            let lib_ed = src_dir.join("lib.con");
-            let main_ed = src_dir.join("main.con");
+            let mut outputs = Vec::new();
+            let mut lockfile = Lockfile::read(&root_dir)?;
+            let mut plan = BuildPlan::default();
 
-            let start = Instant::now();
+            for base_dir in &packages {
+                let config = Config::read(&base_dir.join("Concrete.toml"))?;
+                let src_dir = base_dir.join("src");
+                let no_std = freestanding || config.package()?.no_std;
+                if no_std && config.dependencies.contains_key(STD_PACKAGE) {
+                    bail!(
+                        "`{}` is built without std, it can't depend on `{STD_PACKAGE}`",
+                        config.package()?.name
+                    );
+                }
+                let mut output = target_dir.join(&config.package()?.name);
+                let get_profile = |name: &str| match &workspace {
+                    Some(workspace) => workspace.get_profile(&config, name),
+                    None => config.get_profile(name),
+                };
+                let (profile, profile_name) = if let Some(profile) = &profile {
+                    (get_profile(profile)?, profile.clone())
+                } else if release {
+                    (get_profile("release")?, "release".to_string())
+                } else {
+                    (get_profile("dev")?, "dev".to_string())
+                };
 
-            let mut tests = Vec::new();
+                let extensions =
+                    SourceExtensions::with_aliases(&config.package()?.extension_aliases);
+                let lib_ed = extensions.find_file(&src_dir.join("lib"))?;
+                let main_ed = extensions.find_file(&src_dir.join("main"))?;
+                if lib_ed.is_some() && config.lib.kind.is_empty() {
+                    bail!(
+                        "`kind` of the [lib] table is empty, set it to \"static\", \"shared\" or both"
+                    );
+                }
 
-            let mut added_deps = HashMap::new();
-            let compile_units_ast = compile_project(base_dir, false, &mut added_deps)?;
+                let start = Instant::now();
 
-            for file in [main_ed, lib_ed] {
-                if file.exists() {
-                    let is_lib = file.file_stem().unwrap() == "lib";
+                let mut tests = Vec::new();
 
-                    let compile_args = CompilerArgs {
-                        input: file,
-                        output: if is_lib {
-                            let name = output.file_stem().unwrap().to_string_lossy().to_string();
-                            let name = format!("lib{name}");
-                            output
-                                .with_file_name(name)
-                                .with_extension(CompileUnitInfo::get_platform_library_ext())
-                        } else {
-                            output.clone()
-                        },
+                let cfg = Cfg {
+                    profile: profile_name.clone(),
+                    debug_assertions: profile.debug_assertions,
+                    test,
+                    features: features.clone(),
+                    ..Cfg::for_target(target_info.triple.as_deref())
+                };
+
+                let mut added_deps = HashMap::new();
+                let mut scripts =
+                    BuildScripts::new(&target_dir, sysroot.clone(), diagnostics.clone());
+                // The JIT runs the program without the object of its dependencies, which the build
+                // plan doesn't list either, and the coverage counters of their inlinable functions
+                // would be defined twice.
+                let mut metadata = (!jit && !coverage && !build_plan)
+                    .then(|| DependencyMetadata::new(&target_dir));
+                let mut compile_units_ast = compile_project(
+                    base_dir,
+                    &root_dir,
+                    &mut added_deps,
+                    &mut lockfile,
+                    &diagnostics,
+                    &lints.levels()?,
+                    &cfg,
+                    sysroot.as_ref().filter(|_| !no_std),
+                    Some(&mut scripts),
+                    metadata.as_mut(),
+                )?;
+                lockfile.write(&root_dir)?;
+                let prebuilt_dependencies = metadata.and_then(|x| x.prebuilt);
+                if doc_tests {
+                    // Only the package is tested, not its dependencies.
+                    for unit in &mut compile_units_ast {
+                        if unit
+                            .modules
+                            .first()
+                            .is_some_and(|x| x.file_path.starts_with(&src_dir))
+                        {
+                            doc_tests::add_doc_tests(unit)?;
+                        }
+                    }
+                }
+                let dependencies: Vec<DependencyOrigin> = added_deps.into_values().collect();
+                let plugins = Arc::new(load_plugins(base_dir, &config)?);
+                let (link_libs, lib_dirs) = native_libs(
+                    base_dir,
+                    &config,
+                    &dependencies,
+                    &[link_lib.as_slice(), &scripts.link_libs].concat(),
+                    &[lib_dir.as_slice(), &scripts.lib_dirs].concat(),
+                )?;
+
+                // The binaries of `src/bin` aren't fuzzed, the fuzz target is in the library.
+                let mut bins = match &fuzz_target {
+                    Some(_) => Vec::new(),
+                    None => find_bins(&src_dir.join("bin"), &extensions)?,
+                };
+                let package_name = &config.package()?.name;
+                if let Some((name, _)) = bins.iter().find(|(x, _)| x == package_name) {
+                    if main_ed.is_some() {
+                        bail!(
+                            "the binary `{name}` of src/bin has the same name as the one of src/main"
+                        );
+                    }
+                }
+
+                let files = match (&bin, &fuzz_target) {
+                    (Some(name), _) => {
+                        let Some(position) = bins.iter().position(|(x, _)| x == name) else {
+                            bail!(
+                                "{}",
+                                unknown_value_message(
+                                    "bin target",
+                                    name,
+                                    bins.iter().map(|(x, _)| x.as_str())
+                                )
+                            );
+                        };
+                        bins = vec![bins.swap_remove(position)];
+                        [None, None]
+                    }
+                    // The fuzz target is built with the library if there's one, libFuzzer
+                    // replaces the `main` of the binary anyway.
+                    (None, Some(_)) => [lib_ed.or(main_ed.clone()), None],
+                    (None, None) => [main_ed.clone(), lib_ed],
+                };
+
+                let linker = linker.clone().or_else(|| profile.linker.clone());
+                let mut sanitizers = profile.sanitizers.clone();
+                sanitizers.extend(sanitize.iter().filter(|x| !profile.sanitizers.contains(x)));
+                if !no_codegen && !build_plan {
+                    check_linker(linker.as_deref())?;
+                }
+                if no_std {
+                    check_freestanding(jit, fuzz_target.is_some(), &sanitizers)?;
+                }
+
+                let compiler_args = |input: PathBuf, output: PathBuf, library: bool| {
+                    let name = output.strip_prefix(&target_dir)?.display().to_string();
+
+                    anyhow::Ok(CompilerArgs {
+                        input,
                         release,
                         optlevel: Some(profile.opt_level),
-                        debug_info: Some(profile.debug_info),
-                        library: is_lib,
+                        debug_info: Some(profile.debug_info || debug),
+                        library,
+                        target: target.clone(),
+                        freestanding: no_std,
+                        entry: config.package()?.entry.clone(),
+                        unchecked_indexing,
+                        sanitize: sanitizers.clone(),
+                        coverage,
                         ast,
                         ir,
                         llvm,
                         asm,
                         object,
+                        js_bindings,
+                        emit_header,
+                        emit: emit.clone(),
                         mlir,
                         check,
-                    };
-                    let (object, file_tests) = compile(&compile_args, &compile_units_ast)?;
-                    tests.extend(file_tests);
+                        lints: lints.clone(),
+                        diagnostics: diagnostics.clone(),
+                        dependencies: dependencies.clone(),
+                        symbol_map: debug,
+                        plugins: plugins.clone(),
+                        fuzz_target: fuzz_target.clone(),
+                        fingerprint: Some(
+                            target_dir
+                                .join(".fingerprints")
+                                .join(format!("{name}.json")),
+                        ),
+                        link_libs: link_libs.clone(),
+                        lib_dirs: lib_dirs.clone(),
+                        lib_kinds: config.lib.kind.clone(),
+                        linker: linker.clone(),
+                        linker_args: profile.linker_args.clone(),
+                        rpath: profile.rpath.clone(),
+                        lto: profile.lto,
+                        codegen_units: profile.codegen_units,
+                        doc_tests,
+                        sysroot: sysroot.clone(),
+                        cache: Cache::locate(),
+                        package_units: Some(target_dir.join(".units")),
+                        prebuilt_dependencies: prebuilt_dependencies.clone(),
+                        output,
+                    })
+                };
+                let binary_path = |path: PathBuf| match target_info.binary_ext() {
+                    Some(ext) => path.with_extension(ext),
+                    None => path,
+                };
+
+                for file in files.into_iter().flatten() {
+                    let is_lib = file.file_stem().unwrap() == "lib";
+
+                    let compile_args = compiler_args(
+                        file,
+                        if let Some(target) = &fuzz_target {
+                            let fuzz_dir = target_dir.join("fuzz");
+                            std::fs::create_dir_all(&fuzz_dir)?;
+                            fuzz_dir.join(fuzz::dir_name(target))
+                        } else if is_lib {
+                            let name = output.file_stem().unwrap().to_string_lossy().to_string();
+                            let name = format!("lib{name}");
+                            output
+                                .with_file_name(name)
+                                .with_extension(target_info.library_ext())
+                        } else {
+                            binary_path(output.clone())
+                        },
+                        is_lib && fuzz_target.is_none(),
+                    )?;
+
+                    if no_codegen {
+                        // The binary and the library are lowered from the same compile units, so
+                        // checking them once is enough.
+                        self::check(&compile_args, &compile_units_ast)?;
+                        break;
+                    }
+
+                    if jit {
+                        if is_lib {
+                            continue;
+                        }
+                        let program = self::check(&compile_args, &compile_units_ast)?;
+                        return run_jit(&compile_args, &program);
+                    }
 
-                    if compile_args.library {
-                        link_shared_lib(&[object], &compile_args.output)?;
+                    if build_plan {
+                        plan.add_program(
+                            &compile_args,
+                            &compile_units_ast,
+                            package_name,
+                            &profile_name,
+                            &dependencies,
+                        );
                     } else {
-                        link_binary(&[object], &compile_args.output)?;
+                        let (objects, file_tests, libs) =
+                            compile(&compile_args, &compile_units_ast)?;
+                        tests.extend(file_tests);
+                        link(&compile_args, &objects, &libs)?;
                     }
 
-                    if is_lib {
+                    if is_lib || compile_args.fuzz_target.is_some() {
                         output = compile_args.output;
                     }
                 }
-            }
-            let elapsed = start.elapsed();
-            println!(
-                "   {} {} [{}{}] in {elapsed:?}",
-                "Finished".green().bold(),
-                profile_name,
-                if profile.opt_level > 0 {
-                    "optimized"
-                } else {
-                    "unoptimized"
-                },
-                if profile.debug_info {
-                    " + debuginfo"
-                } else {
-                    ""
-                }
-            );
-
-            Ok((output, tests))
-        }
-    }
-}
-
-pub fn compile_project(
-    project_dir: &Path,
-    is_dep: bool,
-    added_deps: &mut HashMap<String, Dependency>,
-) -> Result<Vec<CompilationUnit>> {
-    let config_path = project_dir.join("Concrete.toml");
-    let mut config = File::open(&config_path).context("failed to open Concrete.toml")?;
-    let mut buf = String::new();
-    config.read_to_string(&mut buf)?;
-    let config: Config = toml::from_str(&buf).context("failed to parse Concrete.toml")?;
-
-    let mut deps = Vec::new();
-
-    for (name, info) in config.dependencies.iter() {
-        if added_deps.contains_key(name) {
-            // TODO: better dependency unification.
-            // Maybe allow duplicate dependencies, however we can't allow duplicate stds due to lang items.
-            continue;
-        }
-
-        let path = checkout_dependency(project_dir, name, info)?;
-
-        added_deps.insert(name.clone(), info.clone());
 
-        let compile_units = compile_project(&path, true, added_deps)?;
+                for (name, file) in bins {
+                    // Each binary is its own program, made of the project without its main file.
+                    let mut units: Vec<CompilationUnit> = compile_units_ast
+                        .iter()
+                        .filter(|x| {
+                            x.modules
+                                .first()
+                                .is_none_or(|x| Some(&x.file_path) != main_ed.as_ref())
+                        })
+                        .cloned()
+                        .collect();
+                    let mut unit = parse_file(file.clone(), &extensions)?;
+                    // The units of the project are last, the binary uses the same dependencies.
+                    if let Some(project_unit) = compile_units_ast.last() {
+                        unit.module_aliases = project_unit.module_aliases.clone();
+                        unit.cfg = project_unit.cfg.clone();
+                    }
+                    units.push(unit);
 
-        deps.extend(compile_units);
-    }
+                    let compile_args =
+                        compiler_args(file, binary_path(target_dir.join(&name)), false)?;
 
-    println!(
-        "   {} {} v{} ({})",
-        "Compiling".green().bold(),
-        config.package.name,
-        config.package.version,
-        project_dir.display()
-    );
+                    if no_codegen {
+                        self::check(&compile_args, &units)?;
+                        continue;
+                    }
 
-    let src_dir = project_dir.join("src");
+                    if jit {
+                        if bin.is_none() {
+                            continue;
+                        }
+                        let program = self::check(&compile_args, &units)?;
+                        return run_jit(&compile_args, &program);
+                    }
 
-    let lib_ed = src_dir.join("lib.con");
-    let main_ed = src_dir.join("main.con");
+                    if build_plan {
+                        plan.add_program(
+                            &compile_args,
+                            &units,
+                            package_name,
+                            &profile_name,
+                            &dependencies,
+                        );
+                    } else {
+                        // The tests are run from the library, the binaries only have copies of
+                        // them. The units of the package other than the binary are compiled
+                        // once, for the first binary, and reused by the others.
+                        let (objects, _, libs) = compile(&compile_args, &units)?;
+                        link(&compile_args, &objects, &libs)?;
+                    }
 
-    for file in [main_ed, lib_ed] {
-        if file.exists() {
-            let is_lib = file.file_stem().unwrap() == "lib";
+                    if bin.is_some() {
+                        output = compile_args.output;
+                    }
+                }
+                let elapsed = start.elapsed();
+                eprintln!(
+                    "   {} {} [{}{}] in {elapsed:?}",
+                    "Finished".green().bold(),
+                    profile_name,
+                    if profile.opt_level > 0 {
+                        "optimized"
+                    } else {
+                        "unoptimized"
+                    },
+                    if profile.debug_info {
+                        " + debuginfo"
+                    } else {
+                        ""
+                    }
+                );
 
-            if !is_lib && is_dep {
-                continue;
+                outputs.push((output, tests));
             }
 
-            let compile_unit_ir = parse_file(file)?;
+            if build_plan {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            }
+            if jit {
+                bail!("there's no binary to run, the package only has a library");
+            }
 
-            deps.push(compile_unit_ir);
+            Ok(outputs)
         }
     }
-
-    Ok(deps)
 }
 
-pub fn checkout_dependency(base_dir: &Path, name: &str, dep: &Dependency) -> Result<PathBuf> {
-    if let Some(path) = &dep.path {
-        return Ok(path.clone());
+/// The binaries in `src/bin`, by name, sorted.
+fn find_bins(dir: &Path, extensions: &SourceExtensions) -> Result<Vec<(String, PathBuf)>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
     }
 
-    if let Some(git) = &dep.git {
-        let bricks_folder = base_dir.join(".bricks");
-
-        if !bricks_folder.exists() {
-            std::fs::create_dir_all(&bricks_folder)?;
+    let mut bins = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && extensions.is_source(&path) {
+            let name = path
+                .file_stem()
+                .context("could not get file stem")?
+                .to_string_lossy()
+                .into_owned();
+            bins.push((name, path));
         }
+    }
+    bins.sort();
 
-        let dir = bricks_folder.join(name);
+    Ok(bins)
+}
 
-        if dir.exists() {
-            return Ok(dir);
-        }
+/// Loads the plugins listed in the manifest, their paths are relative to the project directory.
+fn load_plugins(project_dir: &Path, config: &Config) -> Result<Plugins> {
+    let mut plugins = Plugins::default();
 
-        println!(
-            "   {} {} ({})",
-            "Downloading".green().bold(),
-            name,
-            dep.r#ref.clone().unwrap_or("head".to_string()),
-        );
+    for (name, plugin) in &config.plugins {
+        plugins.load(name, &project_dir.join(&plugin.path))?;
+    }
 
-        let repo = Repository::clone_recurse(git, &dir).context("Failed to clone dependency")?;
+    Ok(plugins)
+}
 
-        if let Some(commit) = &dep.r#ref {
-            let comm = repo.find_commit(Oid::from_str(commit)?)?;
-            repo.checkout_tree(comm.as_object(), None)?;
+/// The native libraries to link a package with and the directories to look for them in: the
+/// ones of the command line, of the package manifest and of the manifests of its dependencies.
+fn native_libs(
+    base_dir: &Path,
+    config: &Config,
+    dependencies: &[DependencyOrigin],
+    link_lib: &[String],
+    lib_dir: &[PathBuf],
+) -> Result<(Vec<String>, Vec<PathBuf>)> {
+    let mut libs = link_lib.to_vec();
+    let mut dirs = lib_dir.to_vec();
+
+    let mut add = |dir: &Path, build: &BuildConfig| {
+        for lib in &build.libs {
+            if !libs.contains(lib) {
+                libs.push(lib.clone());
+            }
         }
+        dirs.extend(build.lib_dirs.iter().map(|x| dir.join(x)));
+    };
 
-        Ok(dir)
+    add(base_dir, &config.build);
+    for dependency in dependencies {
+        let config = Config::read(&dependency.dir.join("Concrete.toml"))?;
+        add(&dependency.dir, &config.build);
+    }
+
+    Ok((libs, dirs))
+}
+
+/// Finds the Concrete.toml of the project in the current directory or its parents.
+pub fn find_config_path() -> Result<PathBuf> {
+    let mut current_dir = std::env::current_dir()?;
+    for _ in 0..3 {
+        if current_dir.join("Concrete.toml").exists() {
+            return Ok(current_dir.join("Concrete.toml"));
+        }
+
+        current_dir = if let Some(parent) = current_dir.parent() {
+            parent.to_path_buf()
+        } else {
+            bail!("couldn't find Concrete.toml");
+        };
+    }
+    bail!("couldn't find Concrete.toml")
+}
+
+/// Parses the project and its dependencies, in parallel once they're all checked out.
+///
+/// `root_dir` is where the git dependencies are checked out, the root of the workspace or the
+/// project being built, and locked to a commit in `lockfile`. The units of the project are built
+/// with `cfg`, the ones of its dependencies with it too but without `test`. Its `features` are the
+/// ones requested for the project, each unit gets the ones enabled in its package. The packages
+/// not declaring a `std` dependency get the one of the `sysroot`. The build scripts of the packages
+/// are run with `scripts`. The units of the dependencies are loaded from their `metadata` when their
+/// sources didn't change, and their metadata is kept otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn compile_project(
+    project_dir: &Path,
+    root_dir: &Path,
+    added_deps: &mut HashMap<String, DependencyOrigin>,
+    lockfile: &mut Lockfile,
+    diagnostics: &DiagnosticArgs,
+    lint_levels: &LintLevels,
+    cfg: &Cfg,
+    sysroot: Option<&Sysroot>,
+    scripts: Option<&mut BuildScripts>,
+    metadata: Option<&mut DependencyMetadata>,
+) -> Result<Vec<CompilationUnit>> {
+    let files = find_project_files(
+        project_dir,
+        root_dir,
+        &[],
+        added_deps,
+        lockfile,
+        diagnostics,
+        lint_levels,
+        sysroot,
+    )?;
+
+    // The dependencies first, as their files are.
+    let mut printed = HashSet::new();
+    for file in &files {
+        if !printed.insert(&file.package) {
+            continue;
+        }
+
+        let package = file.config.package()?;
+        let dir = match &file.package {
+            Some(key) => &added_deps[key].dir,
+            None => project_dir,
+        };
+        eprintln!(
+            "   {} {} v{} ({})",
+            "Compiling".green().bold(),
+            package.name,
+            package.version,
+            dir.display()
+        );
+    }
+
+    let mut features = enabled_features(&files, added_deps, &cfg.features)?;
+    let mut cfgs: Vec<Cfg> = files
+        .iter()
+        .map(|file| Cfg {
+            test: cfg.test && file.package.is_none(),
+            features: features.remove(&file.package).unwrap_or_default(),
+            ..cfg.clone()
+        })
+        .collect();
+
+    // The build scripts run before parsing, they generate modules and set cfg options.
+    let mut generated_dirs = vec![None; files.len()];
+    if let Some(scripts) = scripts {
+        let mut outputs: HashMap<&Option<String>, Option<ScriptOutput>> = HashMap::new();
+        for (i, file) in files.iter().enumerate() {
+            if !outputs.contains_key(&file.package) {
+                let (dir, name) = match &file.package {
+                    Some(key) => (added_deps[key].dir.as_path(), key.as_str()),
+                    None => (project_dir, file.config.package()?.name.as_str()),
+                };
+                outputs.insert(&file.package, scripts.run(dir, name, &cfgs[i])?);
+            }
+
+            if let Some(output) = &outputs[&file.package] {
+                cfgs[i].custom = Some(output.cfgs.clone());
+                generated_dirs[i] = Some(output.generated_dir.clone());
+            }
+        }
+    }
+
+    // The dependencies are first, they have metadata unless a package is built from several sources,
+    // as its modules are renamed after parsing.
+    let dependencies = files.iter().take_while(|x| x.package.is_some()).count();
+    let any_renamed = files
+        .iter()
+        .filter_map(|x| added_deps.get(x.package.as_ref()?))
+        .any(|x| x.module_suffix().is_some());
+    let mut metadata = metadata.filter(|_| dependencies > 0 && !any_renamed);
+    let key = DependencyMetadata::key(
+        files[..dependencies]
+            .iter()
+            .zip(&cfgs)
+            .map(|(file, cfg)| (file.path.as_path(), cfg)),
+    );
+    let mut units = metadata
+        .as_mut()
+        .and_then(|x| x.load(key))
+        .unwrap_or_default();
+
+    let parsed = parallel::map(
+        &files
+            .iter()
+            .zip(&generated_dirs)
+            .skip(units.len())
+            .collect::<Vec<_>>(),
+        |(file, generated_dir)| match generated_dir {
+            Some(dir) => parse_module_file(
+                file.path.clone(),
+                &file.extensions,
+                &mut ModuleFiles::with_generated_dir(dir.clone()),
+            ),
+            None => parse_file(file.path.clone(), &file.extensions),
+        },
+    )
+    .into_iter()
+    .collect::<Result<Vec<CompilationUnit>>>()?;
+    units.extend(parsed);
+
+    for ((file, unit), cfg) in files.iter().zip(&mut units).zip(cfgs) {
+        unit.is_dependency = file.package.is_some();
+        unit.cfg = cfg;
+    }
+
+    // The top level modules of a package built from several sources are renamed in its copies
+    // after the first, and referred to by their names in the units of the packages using them.
+    let mut renamed: HashMap<&str, HashMap<String, String>> = HashMap::new();
+    for (file, unit) in files.iter().zip(&mut units) {
+        let Some(origin) = file.package.as_ref().and_then(|x| added_deps.get(x)) else {
+            continue;
+        };
+        let Some(suffix) = origin.module_suffix() else {
+            continue;
+        };
+
+        let names = renamed.entry(&origin.key).or_default();
+        for module in &mut unit.modules {
+            let name = format!("{}_{suffix}", module.name.name);
+            names.insert(module.name.name.clone(), name.clone());
+            module.name.name = name;
+        }
+    }
+
+    for (file, unit) in files.iter().zip(&mut units) {
+        for key in file.package.iter().chain(&file.dependencies) {
+            if let Some(names) = renamed.get(key.as_str()) {
+                unit.module_aliases.extend(names.clone());
+            }
+        }
+    }
+
+    // The dependencies parsed again get their metadata kept, the program is lowered with the units
+    // loaded from it.
+    if let Some(metadata) = metadata.filter(|x| x.prebuilt.is_none()) {
+        let parsed: Vec<CompilationUnit> = units.drain(..dependencies).collect();
+        units.splice(..0, metadata.store(key, parsed));
+    }
+
+    Ok(units)
+}
+
+/// The features enabled in each package of the build, by key, `None` for the project: the ones
+/// requested for it and the ones its dependents enable, with the default ones unless all of them
+/// disable these.
+fn enabled_features(
+    files: &[ProjectFile],
+    added_deps: &HashMap<String, DependencyOrigin>,
+    requested: &BTreeSet<String>,
+) -> Result<HashMap<Option<String>, BTreeSet<String>>> {
+    let mut requested_by_package: HashMap<Option<String>, BTreeSet<String>> =
+        HashMap::from([(None, requested.clone())]);
+    let mut enabled = HashMap::new();
+
+    // A feature enabled in a package can enable features of the packages it depends on, which are
+    // before it, so it's repeated until no package gets new ones.
+    loop {
+        let mut changed = false;
+
+        for file in files {
+            let name = &file.config.package()?.name;
+            let requested = requested_by_package
+                .get(&file.package)
+                .cloned()
+                .unwrap_or_default();
+            let features = file
+                .config
+                .enabled_features(&requested)
+                .with_context(|| format!("failed to enable the features of `{name}`"))?;
+
+            for key in &file.dependencies {
+                let dependency = &added_deps[key].name;
+                let mut requested = file.config.dependencies[dependency].requested_features();
+                requested.extend(
+                    features
+                        .dependencies
+                        .get(dependency)
+                        .into_iter()
+                        .flatten()
+                        .cloned(),
+                );
+
+                let features = requested_by_package.entry(Some(key.clone())).or_default();
+                for feature in requested {
+                    changed |= features.insert(feature);
+                }
+            }
+
+            enabled.insert(file.package.clone(), features.features);
+        }
+
+        if !changed {
+            return Ok(enabled);
+        }
+    }
+}
+
+/// A file of a compile unit of the build.
+struct ProjectFile {
+    path: PathBuf,
+    /// The extensions its modules are looked up with.
+    extensions: SourceExtensions,
+    /// The key of the dependency it belongs to, `None` for the project being built.
+    package: Option<String>,
+    /// The keys of the dependencies of its package.
+    dependencies: Vec<String>,
+    /// The config of its package.
+    config: Arc<Config>,
+}
+
+/// Finds the files of the compile units of the project and its dependencies, the dependencies
+/// first.
+///
+/// A package required from the same source by several packages is only added once. The same
+/// package from different sources is added once per source, except for `std`, whose lang items
+/// can only be defined once: the packages getting the one of the sysroot use the one already added.
+#[allow(clippy::too_many_arguments)]
+fn find_project_files(
+    project_dir: &Path,
+    root_dir: &Path,
+    required_by: &[String],
+    added_deps: &mut HashMap<String, DependencyOrigin>,
+    lockfile: &mut Lockfile,
+    diagnostics: &DiagnosticArgs,
+    lint_levels: &LintLevels,
+    sysroot: Option<&Sysroot>,
+) -> Result<Vec<ProjectFile>> {
+    let is_dep = !required_by.is_empty();
+    let config_path = project_dir.join("Concrete.toml");
+    let mut config = File::open(&config_path).context("failed to open Concrete.toml")?;
+    let mut buf = String::new();
+    config.read_to_string(&mut buf)?;
+    let mut config: Config = toml::from_str(&buf).context("failed to parse Concrete.toml")?;
+
+    // Packages get the std of the sysroot unless they declare their own.
+    let implicit_std = sysroot.is_some_and(|_| {
+        !config.dependencies.contains_key(STD_PACKAGE)
+            && config.package().is_ok_and(|x| x.name != STD_PACKAGE)
+    });
+    if let Some(sysroot) = sysroot.filter(|_| implicit_std) {
+        config
+            .dependencies
+            .insert(STD_PACKAGE.to_string(), sysroot.std_dependency());
+    }
+    let config = Arc::new(config);
+
+    let package = config.package()?;
+
+    if let Some(origin) = added_deps.values_mut().find(|x| x.dir == project_dir) {
+        origin.version = Some(package.version.clone());
+    }
+
+    let mut chain = required_by.to_vec();
+    chain.push(package.name.clone());
+
+    // The config files of dependencies are not the user's to fix.
+    if !is_dep {
+        let warnings = config::unknown_config_keys(&config_path, &buf);
+        emit_warnings(&warnings, lint_levels, diagnostics)?;
+    }
+
+    let mut deps = Vec::new();
+    let mut package_deps = Vec::new();
+
+    let mut dependencies: Vec<_> = config.dependencies.iter().collect();
+    // std goes first, so a package declaring one gets it before its dependencies get the
+    // implicit one.
+    dependencies.sort_by_key(|(name, _)| (name.as_str() != STD_PACKAGE, name.as_str()));
+
+    for (name, info) in dependencies {
+        let resolved_source = info.resolve_source(project_dir)?;
+
+        let mut copies = added_deps.values().filter(|x| x.name == *name);
+        // The implicit std is whichever one the build already has.
+        let is_implicit = implicit_std && name == STD_PACKAGE;
+        if let Some(copy) = copies
+            .clone()
+            .find(|x| x.resolved_source == resolved_source || is_implicit)
+        {
+            package_deps.push(copy.key.clone());
+            continue;
+        }
+
+        let key = match copies.next() {
+            None => name.clone(),
+            Some(other) if name == STD_PACKAGE => bail!(
+                "conflicting requirements for `{name}`: {} requires it from {}, but {} requires it from {}\nnote: a build can only have one `{name}`, it defines the lang items",
+                display_chain(&other.required_by),
+                other.source,
+                display_chain(&chain),
+                info.source()
+            ),
+            Some(_) => format!("{name}-{}", resolved_source.short_hash()),
+        };
+
+        let path = checkout_dependency(project_dir, root_dir, &key, info, lockfile)?;
+
+        added_deps.insert(
+            key.clone(),
+            DependencyOrigin {
+                name: name.clone(),
+                version: None,
+                source: info.source(),
+                resolved_source,
+                key: key.clone(),
+                dir: path.clone(),
+                required_by: chain.clone(),
+            },
+        );
+        package_deps.push(key);
+
+        let files = find_project_files(
+            &path,
+            root_dir,
+            &chain,
+            added_deps,
+            lockfile,
+            diagnostics,
+            lint_levels,
+            sysroot,
+        )?;
+
+        deps.extend(files);
+    }
+
+    let src_dir = project_dir.join("src");
+
+    let extensions = SourceExtensions::with_aliases(&package.extension_aliases);
+    let lib_ed = extensions.find_file(&src_dir.join("lib"))?;
+    let main_ed = if is_dep {
+        None
+    } else {
+        extensions.find_file(&src_dir.join("main"))?
+    };
+
+    let package_key = added_deps
+        .values()
+        .find(|x| x.dir == project_dir)
+        .map(|x| x.key.clone());
+    for file in [main_ed, lib_ed].into_iter().flatten() {
+        deps.push(ProjectFile {
+            path: file,
+            extensions: extensions.clone(),
+            package: package_key.clone(),
+            dependencies: package_deps.clone(),
+            config: config.clone(),
+        });
+    }
+
+    Ok(deps)
+}
+
+/// Shows the packages that required a dependency, like `app -> json`.
+fn display_chain(chain: &[String]) -> String {
+    chain
+        .iter()
+        .map(|x| format!("`{x}`"))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// The package defining the lang items, which can only be built from one source.
+pub(crate) const STD_PACKAGE: &str = "std";
+
+/// Lowering recurses over the AST, so it gets a bigger stack than the default one of spawned threads.
+pub(crate) const LOWERING_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Notes which dependency the diagnostic points into, if it's not in the project being built.
+fn with_provenance(mut diagnostic: Diagnostic, dependencies: &[DependencyOrigin]) -> Diagnostic {
+    if let Some(origin) = DependencyOrigin::innermost(dependencies, &diagnostic.path) {
+        diagnostic.notes.insert(0, origin.note());
+    }
+
+    diagnostic
+}
+
+/// Emits the warnings at the level of their lint with the emitter of the build, failing with
+/// [`CompileError::Reported`] if any of them is denied.
+fn emit_warnings(
+    warnings: &[Warning],
+    lint_levels: &LintLevels,
+    diagnostics: &DiagnosticArgs,
+) -> Result<()> {
+    let mut emitter = diagnostics.shared_emitter();
+    let previous_errors = emitter.error_count();
+
+    for warning in warnings {
+        if lint_levels.warning_level(warning) != LintLevel::Allow {
+            emitter.emit(&warning_to_diagnostic(warning, lint_levels))?;
+        }
+    }
+
+    if emitter.error_count() > previous_errors {
+        return Err(CompileError::Reported.into());
+    }
+
+    Ok(())
+}
+
+/// Gets the directory of the dependency of the project, checking out git dependencies in the
+/// `.bricks` directory of the root.
+pub fn checkout_dependency(
+    project_dir: &Path,
+    root_dir: &Path,
+    name: &str,
+    dep: &Dependency,
+    lockfile: &mut Lockfile,
+) -> Result<PathBuf> {
+    if let Some(path) = &dep.path {
+        return Ok(project_dir.join(path));
+    }
+
+    if let Some(git) = &dep.git {
+        let bricks_folder = root_dir.join(".bricks");
+
+        if !bricks_folder.exists() {
+            std::fs::create_dir_all(&bricks_folder)?;
+        }
+
+        let dir = bricks_folder.join(name);
+        lockfile::checkout_git_dependency(&dir, name, git, dep, lockfile)?;
+
+        Ok(dir)
     } else {
         anyhow::bail!("No path or git specified for dependency.")
     }
 }
 
-pub fn parse_file(mut path: PathBuf) -> Result<CompilationUnit> {
+/// Parses the file and the modules it declares in other files, returning a [`CompileError`] if
+/// any of them can't be read or parsed.
+pub fn parse_file(path: PathBuf, extensions: &SourceExtensions) -> Result<CompilationUnit> {
+    parse_module_file(path, extensions, &mut ModuleFiles::default())
+}
+
+fn parse_module_file(
+    mut path: PathBuf,
+    extensions: &SourceExtensions,
+    files: &mut ModuleFiles,
+) -> Result<CompilationUnit> {
     if path.is_dir() {
-        path = path.join("mod.ed");
+        path = extensions.find_dir_module(&path)?;
     }
+    files.enter(&path)?;
 
-    let real_source = std::fs::read_to_string(&path)?;
-    let source = ProgramSource::new(real_source.clone(), &path);
+    set_current_item(format_args!("parsing {}", path.display()));
+    let _span = self_profile::span("parse", path.display());
+    // Not kept while parsing the submodules, which time themselves.
+    let phase = timings::phase(Phase::Parse);
+    let real_source = match crate::parser::read_source(&path) {
+        Ok(x) => x,
+        Err(error) => {
+            let Some((diagnostic, lossy)) = error.to_diagnostic() else {
+                return Err(error.into());
+            };
+            return Err(CompileError::Parse {
+                diagnostic: Box::new(diagnostic),
+                input: lossy.to_string(),
+            }
+            .into());
+        }
+    };
+    let source = ProgramSource::new(real_source, &path);
 
     let mut compile_unit = match crate::parser::parse_ast(&source) {
         Ok(x) => x,
-        Err(diagnostic) => {
-            diagnostic.render(&source);
-
-            std::process::exit(1);
+        Err(error) => {
+            return Err(CompileError::Parse {
+                diagnostic: Box::new(error.to_diagnostic(&source)),
+                input: source.input,
+            }
+            .into());
         }
     };
+    drop(phase);
+
+    let mut modules_to_add = Vec::new();
+    for declaration in module_declarations(&compile_unit, &path) {
+        let module_path = extensions
+            .find_module(&declaration.dir, &declaration.name.name)
+            .or_else(|error| match (&error, files.generated_dir()) {
+                (SourceLookupError::NotFound { .. }, Some(dir)) => extensions
+                    .find_module(dir, &declaration.name.name)
+                    .map_err(|_| error),
+                _ => Err(error),
+            })
+            .with_context(|| {
+                format!(
+                    "failed to find external module '{}' declared in {}",
+                    declaration.name.name,
+                    path.display()
+                )
+            })?;
 
-    let mut modules_to_add: HashMap<String, Vec<CompilationUnit>> = HashMap::new();
-    for module in &compile_unit.modules {
-        let mut list = Vec::new();
-        for stmt in &module.contents {
-            if let ModuleDefItem::ExternalModule(external_module) = stmt {
-                let base_path = path.parent().unwrap();
-                let mut module_path = base_path.join(&external_module.name).with_extension("con");
-
-                debug!(
-                    "Checking module {:?} at {}",
-                    external_module.name,
-                    module_path.display()
-                );
-                if !module_path.exists() {
-                    module_path = base_path.join(&external_module.name).join("mod.con");
-                }
+        debug!(
+            "Parsing externally declared module '{}'",
+            module_path.display()
+        );
+        let parsed_unit = parse_module_file(module_path, extensions, files)?;
+        modules_to_add.push((declaration, parsed_unit));
+    }
+    files.leave();
 
-                if !module_path.exists() {
-                    bail!(
-                        "External module '{}' not found at {}",
-                        external_module.name,
-                        module_path.display()
-                    );
+    add_external_modules(&mut compile_unit, modules_to_add);
+
+    Ok(compile_unit)
+}
+
+/// Adds the modules parsed from other files as submodules of the modules that declare them.
+pub(crate) fn add_external_modules(
+    compile_unit: &mut CompilationUnit,
+    modules_to_add: Vec<(ModuleDeclaration, CompilationUnit)>,
+) {
+    for (declaration, subunit) in modules_to_add {
+        let Some((top, parents)) = declaration.parents.split_first() else {
+            continue;
+        };
+        let Some(mut module) = compile_unit
+            .modules
+            .iter_mut()
+            .find(|x| x.name.name == *top)
+        else {
+            continue;
+        };
+
+        for name in parents {
+            let submodule = module.contents.iter_mut().find_map(|x| match x {
+                ModuleDefItem::Module(submodule) if submodule.name.name == *name => {
+                    Some(Arc::make_mut(submodule))
                 }
+                _ => None,
+            });
+            module = submodule.expect("the declaring module is in the unit");
+        }
 
-                debug!(
-                    "Parsing externally declared module '{}'",
-                    module_path.display()
-                );
-                let parsed_unit = parse_file(module_path.clone())?;
-                list.push(parsed_unit);
-            }
+        for submodule in subunit.modules {
+            module
+                .contents
+                .push(ModuleDefItem::Module(submodule.into()));
         }
-        modules_to_add.insert(module.name.name.clone(), list);
     }
+}
 
-    for (name, list) in modules_to_add.into_iter() {
-        for module in &mut compile_unit.modules {
-            if module.name.name == *name {
-                for subunit in list.into_iter() {
-                    for submodule in subunit.modules {
-                        module
-                            .contents
-                            .push(ModuleDefItem::Module(submodule.into()));
-                    }
-                }
-                break;
+/// Lowers the compile units, which type checks them, and runs the lints, reporting the diagnostics
+/// found with the emitter of the build and failing with [`CompileError::Reported`] on errors,
+/// without generating any code.
+pub fn check(args: &CompilerArgs, ir: &[CompilationUnit]) -> Result<IR> {
+    let lint_levels = args.lints.levels()?;
+    let lowering_span = self_profile::span("lowering", args.input.display());
+    let lowering_phase = timings::phase(Phase::Lower);
+
+    // The other compilations of the build share the emitter, only the errors found here count.
+    let previous_errors = args.diagnostics.shared_emitter().error_count();
+    let emit = |diagnostic: Diagnostic| {
+        args.diagnostics
+            .shared_emitter()
+            .emit(&with_provenance(diagnostic, &args.dependencies))
+    };
+
+    for diagnostic in args.plugins.check_ast(ir, &lint_levels) {
+        emit(diagnostic)?;
+    }
+
+    // Lower in another thread, so the warnings are reported as soon as they are found, even if an
+    // error stops lowering later on.
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let lowered = std::thread::scope(|scope| -> Result<_> {
+        let lowering = std::thread::Builder::new()
+            .name("lowering".to_string())
+            .stack_size(LOWERING_STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                set_current_item(format_args!("lowering {}", args.input.display()));
+                lower_compile_units_with_sink(ir, Some(sender))
+            })?;
+
+        for warning in receiver {
+            if lint_levels.warning_level(&warning) == LintLevel::Allow {
+                continue;
             }
+
+            emit(warning_to_diagnostic(&warning, &lint_levels))?;
         }
+
+        Ok(lowering
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+    })?;
+
+    let compile_unit_ir = match lowered {
+        Ok(ir) => ir,
+        Err(error) => {
+            for diagnostic in crate::check::lowering_error_to_diagnostics(error) {
+                emit(diagnostic)?;
+            }
+            return Err(CompileError::Reported.into());
+        }
+    };
+
+    drop(lowering_span);
+    drop(lowering_phase);
+
+    for diagnostic in args.plugins.check_ir(&compile_unit_ir, &lint_levels) {
+        emit(diagnostic)?;
     }
 
-    Ok(compile_unit)
+    if args.diagnostics.shared_emitter().error_count() > previous_errors {
+        return Err(CompileError::Reported.into());
+    }
+
+    Ok(compile_unit_ir)
 }
 
-pub fn compile(args: &CompilerArgs, ir: &[CompilationUnit]) -> Result<(PathBuf, Vec<TestInfo>)> {
-    let start_time = Instant::now();
+/// Runs the `main` of the checked program in the compiler process with the JIT, see [`jit_main`],
+/// and exits with the code it returns.
+fn run_jit(args: &CompilerArgs, program: &IR) -> Result<Vec<(PathBuf, Vec<TestInfo>)>> {
+    let code = jit_main(args, program)?;
 
-    let session = CompileUnitInfo {
-        debug_info: if let Some(debug_info) = args.debug_info {
-            if debug_info {
-                DebugInfo::Full
-            } else {
-                DebugInfo::None
+    // Like returning from the `main` of a linked binary, which flushes the output of libc.
+    std::process::exit(code);
+}
+
+/// Runs the `main` of the checked program in the compiler process with the JIT, without writing
+/// objects or linking them, returning the code it exits with. The native libraries the program
+/// links are loaded into the process first, so the JIT finds their symbols.
+fn jit_main(args: &CompilerArgs, program: &IR) -> Result<i32> {
+    let main = program
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .find(|x| x.name == "main" && !x.is_extern)
+        .context("the program has no `main` function to run")?;
+    if !main.args.is_empty() {
+        bail!("the `main` function can't take parameters when run with `--jit`");
+    }
+
+    let _libraries = load_native_libs(&args.link_libs, &args.lib_dirs)?;
+    let session = args.compile_unit_info();
+
+    set_current_item(format_args!("generating code for {}", args.input.display()));
+    let span = self_profile::span("codegen", "JIT compile");
+    let jit = crate::codegen::jit::Jit::new(&session, program)?;
+    let address = jit.lookup("main")?;
+    drop(span);
+    eprintln!("   {} {}", "Running".green().bold(), args.input.display());
+
+    let code = unsafe {
+        match program.types[main.ret_ty].as_ref() {
+            Some(Type::Unit) => {
+                let main: unsafe extern "C" fn() = std::mem::transmute(address as usize);
+                main();
+                0
             }
-        } else if args.release {
-            DebugInfo::None
-        } else {
-            DebugInfo::Full
-        },
-        optlevel: if let Some(optlevel) = args.optlevel {
-            match optlevel {
-                0 => OptLevel::None,
-                1 => OptLevel::Less,
-                2 => OptLevel::Default,
-                _ => OptLevel::Aggressive,
-            }
-        } else if args.release {
-            OptLevel::Aggressive
-        } else {
-            OptLevel::None
-        },
-        library: args.library,
-        output_file: args.output.with_extension("o"),
-        output_asm: args.asm,
-        output_ll: args.llvm,
-        output_mlir: args.mlir,
+            Some(Type::Int(IntTy::I64) | Type::Uint(UintTy::U64)) => {
+                let main: unsafe extern "C" fn() -> i64 = std::mem::transmute(address as usize);
+                main() as i32
+            }
+            _ => {
+                let main: unsafe extern "C" fn() -> i32 = std::mem::transmute(address as usize);
+                main()
+            }
+        }
     };
+
+    Ok(code)
+}
+
+/// Loads the shared native libraries into the process, from the given directories or the ones of
+/// the system, returning them to keep them loaded.
+fn load_native_libs(libs: &[String], dirs: &[PathBuf]) -> Result<Vec<libloading::Library>> {
+    let ext = CompileUnitInfo::get_platform_library_ext();
+
+    libs.iter()
+        .map(|name| {
+            let file = format!("lib{name}.{ext}");
+            dirs.iter()
+                .map(|dir| dir.join(&file))
+                .filter(|x| x.exists())
+                .chain([PathBuf::from(&file)])
+                .find_map(|x| unsafe { libloading::Library::new(x).ok() })
+                .with_context(|| {
+                    format!(
+                        "failed to load the native library `{name}`, only shared libraries can be \
+                         loaded by the JIT"
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Checks a freestanding build doesn't need libc: to run with the JIT, which calls `main` in the
+/// compiler process, to be fuzzed or to link the runtimes of the sanitizers.
+fn check_freestanding(jit: bool, fuzzing: bool, sanitizers: &[Sanitizer]) -> Result<()> {
+    if jit {
+        bail!("freestanding programs start at their entry symbol, they can't run with `--jit`");
+    }
+    if fuzzing {
+        bail!("libFuzzer needs libc, freestanding programs can't be fuzzed");
+    }
+    if !sanitizers.is_empty() {
+        bail!("the runtimes of the sanitizers need libc, freestanding programs can't link them");
+    }
+
+    Ok(())
+}
+
+/// Checks a freestanding binary defines the symbol it starts at, as the linker only warns about it.
+pub fn check_entry(program: &IR, entry: &str) -> Result<()> {
+    let defined = program
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .any(|x| x.name == entry && !x.is_extern);
+    if !defined {
+        bail!(
+            "the freestanding binary has no `{entry}` function to start at, define one with `#[no_mangle]` or `#[export_name = \"{entry}\"]`"
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks the linker asked for exists before building, so a typo isn't found after compiling.
+fn check_linker(linker: Option<&str>) -> Result<()> {
+    if let Some(linker) = linker {
+        if find_linker(linker).is_none() {
+            bail!("couldn't find the linker `{linker}`, it's not a file or a program in the PATH");
+        }
+    }
+
+    Ok(())
+}
+
+/// Links the objects of the output and its native libraries, with the linker of its target, and
+/// the libraries the program needs, see [`program_libs`].
+fn link(args: &CompilerArgs, objects: &[PathBuf], libs: &[String]) -> Result<()> {
+    let _phase = timings::phase(Phase::Link);
+    let target = TargetInfo::new(args.target.clone());
+    let mut options = args.link_options();
+    options.args.extend(libs.iter().map(|x| format!("-l{x}")));
+
+    if args.library && args.lib_kinds.contains(&LibKind::Static) {
+        link_static_lib(objects, &args.output.with_extension("a"))?;
+    }
+    if args.library && !args.lib_kinds.contains(&LibKind::Shared) {
+        return Ok(());
+    }
+
+    if args.fuzz_target.is_some() {
+        link_fuzzer(objects, &options, &args.output)?;
+    } else if target.is_wasm() {
+        link_wasm(objects, &options, &args.output, &target, args.library)?;
+    } else if args.freestanding {
+        link_freestanding(
+            objects,
+            &options,
+            &args.output,
+            &target,
+            (!args.library).then(|| args.entry_symbol()),
+        )?;
+    } else if !target.is_host() {
+        link_cross(objects, &options, &args.output, &target, args.library)?;
+    } else if args.library {
+        link_shared_lib(objects, &options, &args.output)?;
+    } else if !args.sanitize.is_empty() {
+        link_sanitized(objects, &options, &args.output)?;
+    } else {
+        link_binary(objects, &options, &args.output)?;
+    }
+
+    Ok(())
+}
+
+/// The native libraries the program needs besides libc: pthread when it calls it, like
+/// `std.thread` does to spawn threads.
+pub fn program_libs(ir: &IR) -> Vec<String> {
+    let threads = ir
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .flat_map(|x| &x.basic_blocks)
+        .any(|block| match &block.terminator.kind {
+            TerminatorKind::Call { func, .. } | TerminatorKind::TailCall { func, .. } => ir
+                .functions[*func]
+                .as_ref()
+                .is_some_and(|x| x.is_extern && x.name.starts_with("pthread_")),
+            _ => false,
+        });
+
+    match threads {
+        true => vec!["pthread".to_string()],
+        false => Vec::new(),
+    }
+}
+
+/// Compiles the compile units, returning the objects to link, one for each of them, the tests and
+/// the native libraries to link them with.
+pub fn compile(
+    args: &CompilerArgs,
+    ir: &[CompilationUnit],
+) -> Result<(Vec<PathBuf>, Vec<TestInfo>, Vec<String>)> {
+    let start_time = Instant::now();
+
+    let session = args.compile_unit_info();
     tracing::debug!("Output file: {:#?}", session.output_file);
     tracing::debug!("Is library: {:#?}", session.library);
     tracing::debug!("Optlevel: {:#?}", session.optlevel);
@@ -764,17 +3067,73 @@ pub fn compile(args: &CompilerArgs, ir: &[CompilationUnit]) -> Result<(PathBuf,
         )?;
     }
 
-    let compile_unit_ir = match lower_compile_units(ir) {
-        Ok(ir) => ir,
-        Err(error) => {
-            let report = crate::check::lowering_error_to_report(error);
-            report.eprint(ariadne::FnCache::new(|x: &String| {
-                std::fs::read_to_string(Path::new(x.as_str()))
-            }))?;
-            //report.eprint(ariadne::sources(path_cache))?;
-            std::process::exit(1);
-        }
-    };
+    // The lints of plugins and the extra outputs need a full build.
+    let fingerprint_path = args
+        .fingerprint
+        .as_ref()
+        .filter(|_| args.plugins.is_empty() && !args.has_extra_outputs());
+    let previous = fingerprint_path.and_then(|x| Fingerprint::read(x));
+    let cfgs: Vec<&Cfg> = ir.iter().map(|x| &x.cfg).collect();
+    let options = format!(
+        "{session:?} {:?} {} {} {:?} {cfgs:?} {:?}",
+        args.fuzz_target,
+        args.check,
+        args.doc_tests,
+        args.codegen_units,
+        args.prebuilt_dependencies
+    );
+    // The warnings are only reported by a full build, so the levels of the lints are part of the
+    // fingerprint too: a fresh build given `-D warnings` is checked again.
+    let sources = incremental::sources_hash(ir, &format!("{options} {:?}", args.lints));
+
+    // The dependencies lowered from their metadata are linked from their own object.
+    let dependencies_object = args
+        .prebuilt_dependencies
+        .as_ref()
+        .map(|x| x.object(args, &session))
+        .transpose()?;
+
+    if let Some(previous) = previous.as_ref().filter(|x| x.is_fresh(sources)) {
+        tracing::debug!("{} is fresh", args.output.display());
+        let objects = match session.lto {
+            Lto::Off => previous
+                .units
+                .iter()
+                .map(|x| x.object.clone())
+                .chain(dependencies_object)
+                .collect(),
+            Lto::Thin | Lto::Full => crate::codegen::lto_object_paths(
+                &session,
+                previous.units.len() + usize::from(dependencies_object.is_some()),
+            ),
+        };
+        return Ok((objects, previous.tests.clone(), previous.libs.clone()));
+    }
+
+    let mut compile_unit_ir = check(args, ir)?;
+
+    args.plugins.run_passes(&mut compile_unit_ir)?;
+
+    if let Some(target) = &args.fuzz_target {
+        fuzz::use_fuzz_target(&mut compile_unit_ir, target)?;
+    }
+    if args.freestanding && !args.library {
+        check_entry(&compile_unit_ir, args.entry_symbol())?;
+    }
+    // The LLVM IR, MLIR and assembly are of the whole program, each in a file named after the output.
+    let single_module = args.llvm || args.mlir || args.asm;
+    if single_module {
+        compile_unit_ir.compile_units = vec![compile_unit_ir.compile_units.concat()];
+    } else if let Some(objects) = args
+        .codegen_units
+        .filter(|x| compile_unit_ir.compile_units.len() > *x)
+    {
+        let units = &compile_unit_ir.compile_units;
+        compile_unit_ir.compile_units = crate::codegen::codegen_unit_groups(units.len(), objects)
+            .into_iter()
+            .map(|x| units[x].concat())
+            .collect();
+    }
 
     if args.ir {
         std::fs::write(
@@ -783,7 +3142,105 @@ pub fn compile(args: &CompilerArgs, ir: &[CompilationUnit]) -> Result<(PathBuf,
         )?;
     }
 
-    let object_path = crate::codegen::compile(&session, &compile_unit_ir).unwrap();
+    if args.emit.contains(&Emit::IrWithLocs) {
+        let source_map = SourceMap::from_ir(&compile_unit_ir);
+        std::fs::write(
+            session.output_file.with_extension("locs.ir"),
+            display_ir_with_locations(&compile_unit_ir, &source_map),
+        )?;
+    }
+
+    if args.js_bindings {
+        let bindings = generate_bindings(&compile_unit_ir);
+        std::fs::write(args.output.with_extension("js"), bindings.javascript)?;
+        std::fs::write(args.output.with_extension("d.ts"), bindings.typescript)?;
+    }
+
+    // Binaries have no functions for C to call.
+    if args.emit_header && args.library {
+        let library = args
+            .output
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        std::fs::write(
+            args.output.with_extension("h"),
+            generate_header(&compile_unit_ir, &library),
+        )?;
+    }
+
+    if args.symbol_map {
+        SymbolMap::from_ir(&compile_unit_ir).save(&SymbolMap::path_for(&args.output))?;
+    }
+
+    if args.coverage {
+        let source_map = SourceMap::from_ir(&compile_unit_ir);
+        CoverageMap::from_ir(&compile_unit_ir, &source_map)
+            .save(&CoverageMap::path_for(&args.output))?;
+    }
+
+    let unit_hashes = incremental::unit_hashes(&compile_unit_ir, &options);
+    let units: Vec<UnitFingerprint> = unit_hashes
+        .iter()
+        .enumerate()
+        .map(|(i, hash)| UnitFingerprint {
+            hash: *hash,
+            object: crate::codegen::unit_object_path(
+                &session,
+                compile_unit_ir.compile_units.len(),
+                i,
+            ),
+        })
+        .collect();
+    let stale: Vec<usize> = (0..units.len())
+        .filter(|i| {
+            previous
+                .as_ref()
+                .and_then(|x| x.cached_object(*i, units[*i].hash))
+                .is_none()
+        })
+        .collect();
+    tracing::debug!("{} of {} compile units changed", stale.len(), units.len());
+
+    // The module of the whole program isn't shared with other builds.
+    let prebuilt = if single_module {
+        HashMap::new()
+    } else {
+        prebuilt_units(args, &session, &compile_unit_ir, &units)
+    };
+    let stale: Vec<usize> = stale
+        .into_iter()
+        .filter(|i| match prebuilt.get(i) {
+            Some(path) => !cache::reuse_unit(path, &units[*i].object),
+            None => true,
+        })
+        .collect();
+
+    set_current_item(format_args!("generating code for {}", args.input.display()));
+    let span = self_profile::span("codegen", args.input.display());
+    crate::codegen::compile_units(&session, &compile_unit_ir, &stale).unwrap();
+    drop(span);
+
+    for i in &stale {
+        if let Some(path) = prebuilt.get(i) {
+            cache::store_unit(&units[*i].object, path);
+        }
+    }
+
+    // With LTO the units are bitcode, optimized together into the objects linked.
+    let objects: Vec<PathBuf> = units
+        .iter()
+        .map(|x| x.object.clone())
+        .chain(dependencies_object)
+        .collect();
+    let objects = match session.lto {
+        Lto::Off => objects,
+        Lto::Thin | Lto::Full => {
+            let _span = self_profile::span("codegen", "link time optimization");
+            let _phase = timings::phase(Phase::LlvmOpt);
+            crate::codegen::link_time_optimize(&session, &objects)?
+        }
+    };
 
     let elapsed = start_time.elapsed();
     tracing::debug!("Done in {:?}", elapsed);
@@ -794,8 +3251,455 @@ pub fn compile(args: &CompilerArgs, ir: &[CompilationUnit]) -> Result<(PathBuf,
         test_names.push(TestInfo {
             mangled_symbol: f.name.clone(),
             symbol: f.debug_name.clone().unwrap(),
+            property: None,
+            retries: compile_unit_ir.test_retries.get(t).copied().unwrap_or(0),
         });
     }
 
-    Ok((object_path, test_names))
+    for t in &compile_unit_ir.property_tests {
+        let f = compile_unit_ir.functions[*t].as_ref().unwrap();
+        let symbol = f.debug_name.clone().unwrap();
+
+        if f.args.len() > MAX_PROPERTY_PARAMS {
+            bail!(
+                "property test {} has more than {} parameters",
+                symbol,
+                MAX_PROPERTY_PARAMS
+            );
+        }
+
+        let mut generators = Vec::new();
+        for arg in &f.args {
+            match Generator::from_type(&compile_unit_ir, *arg) {
+                Some(generator) => generators.push(generator),
+                None => bail!(
+                    "property test {} has a parameter of type {}, which can't be generated",
+                    symbol,
+                    compile_unit_ir.types[*arg]
+                        .as_ref()
+                        .unwrap()
+                        .display(&compile_unit_ir)?
+                ),
+            }
+        }
+
+        test_names.push(TestInfo {
+            mangled_symbol: f.name.clone(),
+            symbol,
+            property: Some(generators),
+            retries: 0,
+        });
+    }
+
+    let libs = program_libs(&compile_unit_ir);
+    if let Some(path) = fingerprint_path {
+        Fingerprint {
+            sources,
+            units,
+            tests: test_names.clone(),
+            libs: libs.clone(),
+        }
+        .write(path)?;
+    }
+
+    Ok((objects, test_names, libs))
+}
+
+/// Where the compile units shared with other builds are kept, by index: the ones of the std of the
+/// sysroot and of the git dependencies in the global cache, shared with the builds of other
+/// projects, and the others in the units directory of the package, shared with its other binaries.
+fn prebuilt_units(
+    args: &CompilerArgs,
+    session: &CompileUnitInfo,
+    ir: &IR,
+    units: &[UnitFingerprint],
+) -> HashMap<usize, PathBuf> {
+    if args.sysroot.is_none() && args.cache.is_none() && args.package_units.is_none() {
+        return HashMap::new();
+    }
+
+    let triple = crate::codegen::get_target_triple(session);
+    // Their keys can't depend on the rest of the program nor on where this build puts its output.
+    let options = CompileUnitInfo {
+        output_file: PathBuf::new(),
+        ..session.clone()
+    };
+    let hashes = incremental::shared_unit_hashes(ir, &format!("{options:?}"));
+    let mut prebuilt = HashMap::new();
+
+    for (i, modules) in ir.compile_units.iter().enumerate() {
+        let Some(module) = modules.first() else {
+            continue;
+        };
+        let path = &ir.modules[*module].file_path;
+        let extension = units[i].object.extension().unwrap_or_default();
+        let extension = extension.to_string_lossy();
+
+        let unit = match &args.sysroot {
+            Some(sysroot) if sysroot.contains(path) => {
+                Some(sysroot.prebuilt_unit(&triple, hashes[i], &extension))
+            }
+            _ => args
+                .cache
+                .as_ref()
+                .zip(DependencyOrigin::innermost(&args.dependencies, path))
+                .and_then(|(cache, x)| cache.dependency_unit(x, &triple, hashes[i], &extension))
+                .or_else(|| {
+                    let name = format!("{:016x}.{extension}", hashes[i]);
+                    args.package_units.as_ref().map(|dir| dir.join(name))
+                }),
+        };
+        prebuilt.extend(unit.map(|x| (i, x)));
+    }
+
+    prebuilt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the source into `dir` and parses it.
+    fn parse(dir: &Path, name: &str, source: &str) -> CompilationUnit {
+        let path = dir.join(format!("{name}.con"));
+        std::fs::write(&path, source).unwrap();
+        parse_file(path, &SourceExtensions::default()).unwrap()
+    }
+
+    #[test]
+    fn check_lowers_without_generating_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("main");
+
+        let args = CompilerArgs::try_parse_from([
+            "concrete".as_ref(),
+            "main.con".as_ref(),
+            output.as_os_str(),
+        ])
+        .expect("valid arguments");
+
+        let program = parse(
+            dir.path(),
+            "main",
+            "mod Main {
+            fn main() -> i32 {
+                let x: i32 = 2;
+                return x;
+            }
+        }",
+        );
+
+        let ir = check(&args, &[program]).expect("the program is valid");
+        assert!(ir.functions.iter().any(|(_, x)| {
+            x.as_ref()
+                .is_some_and(|x| x.debug_name.as_deref() == Some("main"))
+        }));
+        assert!(!output.with_extension("o").exists());
+    }
+
+    #[test]
+    fn find_bins_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["second.con", "first.con", "notes.txt"] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("nested.con")).unwrap();
+
+        let bins = find_bins(dir.path(), &SourceExtensions::default()).unwrap();
+        assert_eq!(
+            bins,
+            vec![
+                ("first".to_string(), dir.path().join("first.con")),
+                ("second".to_string(), dir.path().join("second.con")),
+            ]
+        );
+
+        let missing = dir.path().join("bin");
+        assert!(
+            find_bins(&missing, &SourceExtensions::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn bins_reuse_the_package_units() {
+        let dir = tempfile::tempdir().unwrap();
+        let units_dir = dir.path().join(".units");
+        let lib = parse(
+            dir.path(),
+            "lib",
+            "mod Lib { pub fn double(x: i32) -> i32 { return x * 2; } }",
+        );
+
+        for (name, value) in [("first", 2), ("second", 5)] {
+            let bin = parse(
+                dir.path(),
+                name,
+                &format!(
+                    "mod Main {{ import Lib.{{double}}; fn main() -> i32 {{ return double({value}); }} }}"
+                ),
+            );
+            let output = dir.path().join(name);
+            let mut args = CompilerArgs::parse_from([
+                "concrete",
+                &format!("{name}.con"),
+                &output.display().to_string(),
+            ]);
+            args.package_units = Some(units_dir.clone());
+
+            let (objects, _, libs) = compile(&args, &[lib.clone(), bin]).unwrap();
+            link(&args, &objects, &libs).unwrap();
+
+            let status = std::process::Command::new(&output).status().unwrap();
+            assert_eq!(status.code(), Some(value * 2));
+        }
+
+        // The library was compiled for the first binary only, each binary has its own unit.
+        let kept = std::fs::read_dir(&units_dir).unwrap().count();
+        assert_eq!(kept, 3);
+    }
+
+    /// Checks the program and runs its `main` with the JIT.
+    fn jit(source: &str) -> Result<i32> {
+        let dir = tempfile::tempdir().unwrap();
+        let unit = parse(dir.path(), "main", source);
+        let args = CompilerArgs::parse_from(["concrete", "main.con", "main"]);
+
+        let program = self::check(&args, &[unit])?;
+        jit_main(&args, &program)
+    }
+
+    #[test]
+    fn jit_runs_main() {
+        let code = jit(
+            "mod Main { fn triple(x: i32) -> i32 { return x * 3; } fn main() -> i32 { return triple(14); } }",
+        );
+        assert_eq!(code.unwrap(), 42);
+
+        let code = jit("mod Main { fn main() -> i64 { return 7; } }");
+        assert_eq!(code.unwrap(), 7);
+    }
+
+    #[test]
+    fn jit_needs_main_without_parameters() {
+        let error = jit("mod Main { fn start() -> i32 { return 0; } }").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the program has no `main` function to run"
+        );
+
+        let error = jit("mod Main { fn main(x: i32) -> i32 { return x; } }").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "the `main` function can't take parameters when run with `--jit`"
+        );
+    }
+
+    #[test]
+    fn lockfile_pins_git_dependencies() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = dir.path().join("origin");
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(&project).unwrap();
+
+        let repo = git2::Repository::init(&origin).unwrap();
+        let commit = |message: &str| {
+            std::fs::write(origin.join("lib.con"), message).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("lib.con")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let signature = git2::Signature::now("test", "test@example.com").unwrap();
+            let parents: Vec<_> = repo
+                .head()
+                .ok()
+                .and_then(|x| x.peel_to_commit().ok())
+                .into_iter()
+                .collect();
+            let parents: Vec<_> = parents.iter().collect();
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+        };
+
+        let first = commit("first");
+        let dep = Dependency {
+            git: Some(origin.display().to_string()),
+            ..Default::default()
+        };
+        let checked_out = |path: &Path| std::fs::read_to_string(path.join("lib.con")).unwrap();
+
+        let mut lockfile = Lockfile::read(&project).unwrap();
+        let path = checkout_dependency(&project, &project, "dep", &dep, &mut lockfile).unwrap();
+        lockfile.write(&project).unwrap();
+        assert_eq!(lockfile.get("dep").unwrap().commit, first.to_string());
+        assert!(project.join("Concrete.lock").exists());
+
+        // A new clone still checks out the locked commit.
+        let second = commit("second");
+        std::fs::remove_dir_all(&path).unwrap();
+        let mut lockfile = Lockfile::read(&project).unwrap();
+        checkout_dependency(&project, &project, "dep", &dep, &mut lockfile).unwrap();
+        assert_eq!(checked_out(&path), "first");
+
+        lockfile.unlock(vec!["dep".to_string()]).unwrap();
+        checkout_dependency(&project, &project, "dep", &dep, &mut lockfile).unwrap();
+        assert_eq!(checked_out(&path), "second");
+        assert_eq!(lockfile.get("dep").unwrap().commit, second.to_string());
+
+        assert!(lockfile.unlock(vec!["dap".to_string()]).is_err());
+    }
+
+    #[test]
+    fn parse_file_returns_syntax_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.con");
+        std::fs::write(
+            &path,
+            "mod Broken {\n    fn f() -> i32 { return 1 +; }\n}\n",
+        )
+        .unwrap();
+
+        let error = parse_file(path.clone(), &SourceExtensions::default()).unwrap_err();
+        match error.downcast_ref::<CompileError>() {
+            Some(CompileError::Parse { diagnostic, input }) => {
+                assert_eq!(diagnostic.path, path);
+                assert_eq!(&input[diagnostic.span.from..diagnostic.span.to], ";");
+            }
+            _ => panic!("expected a parse error, got {error:#}"),
+        }
+    }
+
+    #[test]
+    fn parse_file_resolves_nested_module_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        let path = dir.path().join("main.con");
+        std::fs::write(
+            &path,
+            "mod main {\n    mod a {\n        mod b {\n            mod c;\n        }\n        mod d;\n    }\n}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("a/b/c.con"), "mod c {\n}\n").unwrap();
+        std::fs::write(dir.path().join("a/d.con"), "mod d {\n}\n").unwrap();
+
+        let unit = parse_file(path, &SourceExtensions::default()).unwrap();
+        let submodule = |module: &crate::ast::modules::Module, name: &str| {
+            module
+                .contents
+                .iter()
+                .find_map(|x| match x {
+                    ModuleDefItem::Module(x) if x.name.name == name => Some(x.clone()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| panic!("{name} is not a submodule of {}", module.name.name))
+        };
+        let a = submodule(&unit.modules[0], "a");
+        let c = submodule(&submodule(&a, "b"), "c");
+        let d = submodule(&a, "d");
+        assert_eq!(c.file_path, dir.path().join("a/b/c.con"));
+        assert_eq!(d.file_path, dir.path().join("a/d.con"));
+    }
+
+    #[test]
+    fn parse_file_rejects_module_cycles_and_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.con");
+        std::fs::write(&path, "mod main {\n    mod a;\n}\n").unwrap();
+        std::fs::write(dir.path().join("a.con"), "mod a {\n    mod main;\n}\n").unwrap();
+
+        let error = parse_file(path.clone(), &SourceExtensions::default()).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("module files declare each other"),
+            "{error:#}"
+        );
+
+        std::fs::write(&path, "mod main {\n    mod a;\n    mod b;\n}\n").unwrap();
+        std::fs::write(dir.path().join("a.con"), "mod a {\n    mod c;\n}\n").unwrap();
+        std::fs::write(dir.path().join("b.con"), "mod b {\n    mod c;\n}\n").unwrap();
+        std::fs::write(dir.path().join("c.con"), "mod c {\n}\n").unwrap();
+
+        let error = parse_file(path, &SourceExtensions::default()).unwrap_err();
+        assert!(
+            error.to_string().contains("is declared as a module twice"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn sysroot_std_is_implicit() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let package = |dir: &Path, name: &str, dependencies: &str, source: &str| {
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            std::fs::write(
+                dir.join("Concrete.toml"),
+                format!(
+                    "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n\n[dependencies]\n{dependencies}"
+                ),
+            )
+            .unwrap();
+            std::fs::write(dir.join("src").join(source), "mod x { }").unwrap();
+        };
+
+        let home = root.join("home");
+        package(&home.join("lib").join("std"), "std", "", "lib.con");
+        package(&root.join("app"), "app", "", "main.con");
+        package(
+            &root.join("own"),
+            "own",
+            &format!("std = {{ path = {:?} }}", root.join("other_std")),
+            "main.con",
+        );
+        package(&root.join("other_std"), "std", "", "lib.con");
+
+        assert!(Sysroot::locate(Some(&root)).is_err());
+        let sysroot = Sysroot::locate(Some(&home)).unwrap().expect("it has std");
+        assert_eq!(sysroot.std_dir(), home.join("lib").join("std"));
+
+        let files = |project: &str| {
+            let mut added_deps = HashMap::new();
+            let units = compile_project(
+                &root.join(project),
+                &root,
+                &mut added_deps,
+                &mut Lockfile::default(),
+                &DiagnosticArgs::default(),
+                &LintLevels::default(),
+                &Cfg::default(),
+                Some(&sysroot),
+                None,
+                None,
+            )
+            .unwrap();
+            units
+                .iter()
+                .map(|x| x.modules[0].file_path.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            files("app"),
+            [
+                home.join("lib/std/src/lib.con"),
+                root.join("app/src/main.con")
+            ]
+        );
+        // A package declaring std doesn't get the one of the sysroot.
+        assert_eq!(
+            files("own"),
+            [
+                root.join("other_std/src/lib.con"),
+                root.join("own/src/main.con")
+            ]
+        );
+    }
 }
@@ -0,0 +1,75 @@
+//! Runs independent work, like parsing packages or generating the code of compile units, on a
+//! pool of scoped threads.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// The number of threads set with `-j`, 0 to use one per CPU.
+static JOBS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the number of threads to use, one per CPU if it's `None`.
+pub fn set_jobs(jobs: Option<usize>) {
+    JOBS.store(jobs.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// The number of threads to use.
+pub fn jobs() -> usize {
+    match JOBS.load(Ordering::Relaxed) {
+        0 => std::thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1),
+        jobs => jobs,
+    }
+}
+
+/// Calls the function with each item on up to [`jobs`] threads, returning the results in the order
+/// of the items. A panic in any of the threads is resumed on the calling one.
+pub fn map<T, R>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    let threads = jobs().min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+
+                        let result = f(item);
+                        results.lock().unwrap()[i] = Some(result);
+                    }
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|x| x.expect("every item is processed"))
+        .collect()
+}
@@ -0,0 +1,478 @@
+//! Property based tests: functions marked with `#[property_test]` take parameters, the test runner
+//! calls them repeatedly with generated inputs and, when one fails, shrinks the input before
+//! reporting it together with the seed needed to reproduce the run.
+//!
+//! Supported parameter types are booleans, integers up to 64 bits and references to fixed size
+//! arrays of those, which are passed as a pointer to the generated array.
+
+use std::fmt;
+
+use anyhow::{Result, bail};
+use libloading::Library;
+use serde::{Deserialize, Serialize};
+
+use crate::ir::{ConstKind, ConstValue, IR, IntTy, Type, TypeIndex, UintTy, ValueTree};
+
+/// The maximum number of parameters a property test can have, all of them are passed in registers.
+pub const MAX_PROPERTY_PARAMS: usize = 6;
+
+/// The number of inputs each property test is called with by default.
+pub const DEFAULT_PROPERTY_CASES: usize = 100;
+
+/// Upper bound on the shrinking steps taken for a failing input.
+const MAX_SHRINK_STEPS: usize = 1000;
+
+/// Generates the values of a property test parameter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Generator {
+    Bool,
+    Int {
+        bits: u32,
+        signed: bool,
+    },
+    /// A reference to a fixed size array.
+    Array {
+        element: Box<Generator>,
+        len: usize,
+    },
+}
+
+/// A generated value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Int(i128),
+    Array(Vec<Value>),
+}
+
+/// The outcome of running a property test.
+#[derive(Debug, Clone)]
+pub enum PropertyResult {
+    Passed {
+        cases: usize,
+    },
+    Failed {
+        /// The input of the first failing case.
+        original: Vec<Value>,
+        /// The smallest failing input found by shrinking the original one.
+        shrunk: Vec<Value>,
+        seed: u64,
+    },
+}
+
+impl Generator {
+    /// Returns the generator for the given parameter type, if it's supported.
+    pub fn from_type(ir: &IR, ty: TypeIndex) -> Option<Self> {
+        Some(match ir.types[ty].as_ref()? {
+            Type::Bool => Generator::Bool,
+            Type::Int(ty) => Generator::Int {
+                bits: match ty {
+                    IntTy::I8 => 8,
+                    IntTy::I16 => 16,
+                    IntTy::I32 => 32,
+                    IntTy::I64 => 64,
+                    IntTy::I128 => return None,
+                },
+                signed: true,
+            },
+            Type::Uint(ty) => Generator::Int {
+                bits: match ty {
+                    UintTy::U8 => 8,
+                    UintTy::U16 => 16,
+                    UintTy::U32 => 32,
+                    UintTy::U64 => 64,
+                    UintTy::U128 => return None,
+                },
+                signed: false,
+            },
+            Type::Ref(inner, _) => match ir.types[*inner].as_ref()? {
+                Type::Array(element, size) => {
+                    let len = match &size.data {
+                        ConstKind::Value(ValueTree::Leaf(ConstValue::U64(len))) => *len as usize,
+                        _ => return None,
+                    };
+
+                    let element = Self::from_type(ir, *element)?;
+
+                    if matches!(element, Generator::Array { .. }) {
+                        return None;
+                    }
+
+                    Generator::Array {
+                        element: Box::new(element),
+                        len,
+                    }
+                }
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    fn int_range(bits: u32, signed: bool) -> (i128, i128) {
+        if signed {
+            (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+        } else {
+            (0, (1i128 << bits) - 1)
+        }
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Value {
+        match self {
+            Generator::Bool => Value::Bool(rng.next_u64() & 1 == 1),
+            Generator::Int { bits, signed } => {
+                let (min, max) = Self::int_range(*bits, *signed);
+
+                // Edge cases are where most bugs are, so pick them often.
+                if rng.next_u64() & 7 == 0 {
+                    let edges = [0, 1, min, max, if *signed { -1 } else { max - 1 }];
+                    return Value::Int(edges[(rng.next_u64() % edges.len() as u64) as usize]);
+                }
+
+                let raw = rng.next_u64() as i128;
+                let value = if *bits == 64 {
+                    if *signed {
+                        raw as u64 as i64 as i128
+                    } else {
+                        raw
+                    }
+                } else {
+                    let masked = raw & ((1i128 << bits) - 1);
+                    if *signed && masked > max {
+                        masked - (1i128 << bits)
+                    } else {
+                        masked
+                    }
+                };
+
+                Value::Int(value)
+            }
+            Generator::Array { element, len } => {
+                Value::Array((0..*len).map(|_| element.generate(rng)).collect())
+            }
+        }
+    }
+
+    /// The bytes of an array element of this generator, as laid out in memory.
+    fn element_bytes(&self, value: &Value) -> Vec<u8> {
+        match (self, value) {
+            (Generator::Bool, Value::Bool(value)) => vec![*value as u8],
+            (Generator::Int { bits, .. }, Value::Int(value)) => match bits {
+                8 => (*value as u8).to_ne_bytes().to_vec(),
+                16 => (*value as u16).to_ne_bytes().to_vec(),
+                32 => (*value as u32).to_ne_bytes().to_vec(),
+                _ => (*value as u64).to_ne_bytes().to_vec(),
+            },
+            _ => unreachable!("array elements are scalars"),
+        }
+    }
+}
+
+impl Value {
+    /// Simpler values to try in place of this one when shrinking.
+    fn shrink(&self) -> Vec<Value> {
+        match self {
+            Value::Bool(true) => vec![Value::Bool(false)],
+            Value::Bool(false) => Vec::new(),
+            Value::Int(value) => {
+                let mut candidates = Vec::new();
+
+                for candidate in [0, value / 2, value - value.signum()] {
+                    let candidate = Value::Int(candidate);
+                    if candidate != *self && !candidates.contains(&candidate) {
+                        candidates.push(candidate);
+                    }
+                }
+
+                candidates
+            }
+            Value::Array(values) => {
+                let mut candidates = Vec::new();
+
+                for (i, value) in values.iter().enumerate() {
+                    for candidate in value.shrink() {
+                        let mut values = values.clone();
+                        values[i] = candidate;
+                        candidates.push(Value::Array(values));
+                    }
+                }
+
+                candidates
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Formats the arguments of a property test call.
+pub fn display_input(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A small splitmix64 generator, so runs are reproducible from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Returns a seed for a run when none was given.
+pub fn random_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Calls the property test with the given input, returning whether it passed.
+///
+/// # Safety
+///
+/// The symbol must be a property test function whose parameters match the generators.
+unsafe fn call_property_test(
+    lib: &Library,
+    symbol: &str,
+    generators: &[Generator],
+    values: &[Value],
+) -> Result<bool> {
+    // Keep the arrays alive until the call returns, only pointers to them are passed.
+    let mut arrays: Vec<Vec<u8>> = Vec::new();
+    let mut args: Vec<u64> = Vec::with_capacity(values.len());
+
+    for (generator, value) in generators.iter().zip(values) {
+        match (generator, value) {
+            (Generator::Bool, Value::Bool(value)) => args.push(*value as u64),
+            // Sign or zero extend the value to the full register.
+            (Generator::Int { .. }, Value::Int(value)) => args.push(*value as u64),
+            (Generator::Array { element, .. }, Value::Array(values)) => {
+                let bytes: Vec<u8> = values
+                    .iter()
+                    .flat_map(|x| element.element_bytes(x))
+                    .collect();
+                arrays.push(bytes);
+                args.push(arrays.last().unwrap().as_ptr() as u64);
+            }
+            _ => unreachable!("values are generated from the generators"),
+        }
+    }
+
+    let symbol = symbol.as_bytes();
+
+    let result = unsafe {
+        match args.as_slice() {
+            [] => (lib.get::<unsafe extern "C" fn() -> i32>(symbol)?)(),
+            [a] => (lib.get::<unsafe extern "C" fn(u64) -> i32>(symbol)?)(*a),
+            [a, b] => (lib.get::<unsafe extern "C" fn(u64, u64) -> i32>(symbol)?)(*a, *b),
+            [a, b, c] => {
+                (lib.get::<unsafe extern "C" fn(u64, u64, u64) -> i32>(symbol)?)(*a, *b, *c)
+            }
+            [a, b, c, d] => (lib.get::<unsafe extern "C" fn(u64, u64, u64, u64) -> i32>(symbol)?)(
+                *a, *b, *c, *d,
+            ),
+            [a, b, c, d, e] => (lib
+                .get::<unsafe extern "C" fn(u64, u64, u64, u64, u64) -> i32>(symbol)?)(
+                *a, *b, *c, *d, *e,
+            ),
+            [a, b, c, d, e, f] => (lib
+                .get::<unsafe extern "C" fn(u64, u64, u64, u64, u64, u64) -> i32>(symbol)?)(
+                *a, *b, *c, *d, *e, *f,
+            ),
+            _ => bail!(
+                "property tests can have at most {} parameters",
+                MAX_PROPERTY_PARAMS
+            ),
+        }
+    };
+
+    Ok(result == 0)
+}
+
+/// Shrinks a failing input, taking each simpler input that still fails until none does.
+fn shrink_input(
+    input: &[Value],
+    mut passes: impl FnMut(&[Value]) -> Result<bool>,
+) -> Result<Vec<Value>> {
+    let mut shrunk = input.to_vec();
+    let mut steps = 0;
+
+    'shrink: while steps < MAX_SHRINK_STEPS {
+        for i in 0..shrunk.len() {
+            for candidate in shrunk[i].shrink() {
+                steps += 1;
+
+                let mut next = shrunk.clone();
+                next[i] = candidate;
+
+                if !passes(&next)? {
+                    shrunk = next;
+                    continue 'shrink;
+                }
+            }
+        }
+        break;
+    }
+
+    Ok(shrunk)
+}
+
+/// Runs a property test `cases` times with inputs generated from `seed`, shrinking the first failing input.
+///
+/// # Safety
+///
+/// The symbol must be a property test function whose parameters match the generators.
+pub unsafe fn run_property_test(
+    lib: &Library,
+    symbol: &str,
+    generators: &[Generator],
+    cases: usize,
+    seed: u64,
+) -> Result<PropertyResult> {
+    let mut rng = Rng(seed);
+
+    for _ in 0..cases {
+        let input: Vec<Value> = generators.iter().map(|x| x.generate(&mut rng)).collect();
+
+        if unsafe { call_property_test(lib, symbol, generators, &input)? } {
+            continue;
+        }
+
+        let shrunk = shrink_input(&input, |x| unsafe {
+            call_property_test(lib, symbol, generators, x)
+        })?;
+
+        return Ok(PropertyResult::Failed {
+            original: input,
+            shrunk,
+            seed,
+        });
+    }
+
+    Ok(PropertyResult::Passed { cases })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generators() -> Vec<Generator> {
+        vec![
+            Generator::Bool,
+            Generator::Int {
+                bits: 8,
+                signed: true,
+            },
+            Generator::Int {
+                bits: 64,
+                signed: false,
+            },
+            Generator::Array {
+                element: Box::new(Generator::Int {
+                    bits: 16,
+                    signed: false,
+                }),
+                len: 4,
+            },
+        ]
+    }
+
+    fn generate(seed: u64, runs: usize) -> Vec<Vec<Value>> {
+        let mut rng = Rng(seed);
+        (0..runs)
+            .map(|_| generators().iter().map(|x| x.generate(&mut rng)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        assert_eq!(generate(42, 50), generate(42, 50));
+        assert_ne!(generate(42, 50), generate(43, 50));
+
+        for input in generate(7, 200) {
+            let Value::Int(small) = input[1] else {
+                panic!("expected an integer, found {}", input[1]);
+            };
+            assert!((-128..=127).contains(&small));
+
+            let Value::Array(values) = &input[3] else {
+                panic!("expected an array, found {}", input[3]);
+            };
+            assert_eq!(values.len(), 4);
+            assert!(
+                values
+                    .iter()
+                    .all(|x| matches!(x, Value::Int(x) if (0..=0xffff).contains(x)))
+            );
+        }
+    }
+
+    #[test]
+    fn shrinking_reaches_minimal_counterexample() {
+        // Fails when the flag is set and the number is at least 10.
+        let property = |input: &[Value]| {
+            Ok(match input {
+                [Value::Bool(flag), Value::Int(x)] => !*flag || *x < 10,
+                _ => unreachable!(),
+            })
+        };
+
+        let shrunk = shrink_input(&[Value::Bool(true), Value::Int(1000)], property).unwrap();
+        assert_eq!(shrunk, [Value::Bool(true), Value::Int(10)]);
+
+        // Fails when an element is at least 5.
+        let shrunk = shrink_input(
+            &[Value::Array(vec![Value::Int(-7), Value::Int(300)])],
+            |input| {
+                Ok(match input {
+                    [Value::Array(values)] => {
+                        !values.iter().any(|x| matches!(x, Value::Int(x) if *x >= 5))
+                    }
+                    _ => unreachable!(),
+                })
+            },
+        )
+        .unwrap();
+        assert_eq!(shrunk, [Value::Array(vec![Value::Int(0), Value::Int(5)])]);
+    }
+
+    #[test]
+    fn more_than_six_parameters_is_an_error() {
+        let lib: Library = libloading::os::unix::Library::this().into();
+        let generators = vec![Generator::Bool; MAX_PROPERTY_PARAMS + 1];
+        let values = vec![Value::Bool(false); MAX_PROPERTY_PARAMS + 1];
+
+        let error =
+            unsafe { call_property_test(&lib, "no_such_test", &generators, &values) }.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "property tests can have at most 6 parameters"
+        );
+    }
+}
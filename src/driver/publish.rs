@@ -0,0 +1,391 @@
+//! `concrete publish`: checks the manifest of a package, builds it, and packages its sources into
+//! an archive uploaded to a registry.
+//!
+//! The archive is a tar file, `build/package/<name>-<version>.tar`, with the files of the package
+//! in a `<name>-<version>` directory, except the outputs of the builds and the checkouts of the
+//! git dependencies. It's reproducible: the files are sorted, and their times, owners and
+//! permissions fixed, so packaging the same sources always gives the same archive.
+//!
+//! The archive is uploaded with `curl`, as a `PUT` to `<registry>/packages/<name>/<version>`,
+//! authorized with the token of `CONCRETE_REGISTRY_TOKEN` if it's set.
+
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail};
+use owo_colors::OwoColorize;
+
+use super::{
+    PublishArgs,
+    config::{Config, Package},
+    find_config_path, handle_build,
+    workspace::{self, Workspace},
+};
+
+/// The environment variable with the URL of the registry packages are published to, unless
+/// `--registry` is given.
+pub const REGISTRY_ENV: &str = "CONCRETE_REGISTRY";
+
+/// The environment variable with the token the uploads are authorized with.
+pub const TOKEN_ENV: &str = "CONCRETE_REGISTRY_TOKEN";
+
+/// The directories of the package left out of the archive: the outputs of the builds and the
+/// checkouts of the git dependencies.
+const EXCLUDED_DIRS: &[&str] = &["build", ".bricks"];
+
+/// The size of the blocks of a tar file.
+const BLOCK: usize = 512;
+
+pub fn handle_publish(args: PublishArgs) -> Result<()> {
+    if args.build.path.is_some() {
+        bail!("only projects can be published, not single files");
+    }
+
+    let config_path = find_config_path()?;
+    let project_dir = config_path
+        .parent()
+        .context("couldn't get config parent dir")?;
+    let package_dir = match Workspace::find(project_dir)? {
+        Some(workspace) => {
+            let mut packages = workspace.select(project_dir, args.build.package.as_deref())?;
+            if packages.len() > 1 {
+                bail!("the workspace has several packages, choose one with `--package <name>`");
+            }
+            packages.pop().context("the workspace has no packages")?
+        }
+        None => project_dir.to_path_buf(),
+    };
+
+    let config = Config::read(&package_dir.join("Concrete.toml"))?;
+    validate(&config)?;
+    let package = config.package()?;
+
+    let registry = match args.registry {
+        Some(registry) => Some(registry),
+        None => std::env::var(REGISTRY_ENV).ok(),
+    };
+    let registry = match registry {
+        Some(registry) => Some(registry),
+        None if args.dry_run => None,
+        None => bail!(
+            "there's no registry to publish to, give its URL with `--registry` or {REGISTRY_ENV}"
+        ),
+    };
+
+    // The package is built as it's published, it has to build.
+    handle_build(args.build)?;
+
+    let name = archive_name(package);
+    let files = package_files(&package_dir)?;
+    let archive = archive(&package_dir, &name, &files)?;
+
+    let dir = workspace::root_dir(&package_dir)?
+        .join("build")
+        .join("package");
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = dir.join(format!("{name}.tar"));
+    std::fs::write(&path, archive)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    eprintln!(
+        "   {} {} files into {}",
+        "Packaged".green().bold(),
+        files.len(),
+        path.display()
+    );
+
+    let Some(registry) = registry else {
+        eprintln!("{}: the package isn't uploaded in a dry run", "note".bold());
+        return Ok(());
+    };
+
+    let url = format!(
+        "{}/packages/{}/{}",
+        registry.trim_end_matches('/'),
+        package.name,
+        package.version
+    );
+    eprintln!("   {} {name} to {url}", "Uploading".green().bold());
+    upload(&path, &url)?;
+    eprintln!("   {} {name}", "Published".green().bold());
+
+    Ok(())
+}
+
+/// Checks the package can be published: it has a license, a description and a semver version,
+/// and its dependencies can be found without its sources.
+pub fn validate(config: &Config) -> Result<()> {
+    let package = config.package()?;
+    let mut problems = Vec::new();
+
+    if package.license.trim().is_empty() {
+        problems.push("`license` is empty, set it to the SPDX name of the license".to_string());
+    }
+    if !is_semver(&package.version) {
+        problems.push(format!(
+            "`version` is \"{}\", it has to be a semver version like \"1.2.3\"",
+            package.version
+        ));
+    }
+    if package
+        .description
+        .as_deref()
+        .is_none_or(|x| x.trim().is_empty())
+    {
+        problems.push("`description` is missing, describe the package in a sentence".to_string());
+    }
+
+    let mut dependencies: Vec<_> = config.dependencies.iter().collect();
+    dependencies.sort_by_key(|(name, _)| *name);
+    for (name, dependency) in dependencies {
+        if dependency.git.is_none() && dependency.version.is_none() {
+            problems.push(format!(
+                "the dependency `{name}` is only found by its path, give it a `version` or `git`"
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "the package can't be published, in its Concrete.toml:\n{}",
+            problems
+                .iter()
+                .map(|x| format!("  - {x}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+/// The name of the archive of the package, and of the directory its files are in.
+pub fn archive_name(package: &Package) -> String {
+    format!("{}-{}", package.name, package.version)
+}
+
+/// Whether the version is `MAJOR.MINOR.PATCH`, with an optional pre-release and build metadata.
+fn is_semver(version: &str) -> bool {
+    let (version, build) = match version.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (version, None),
+    };
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+
+    let is_identifier =
+        |x: &str| !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let is_number = |x: &str| {
+        !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()) && (x == "0" || !x.starts_with('0'))
+    };
+
+    let parts: Vec<&str> = core.split('.').collect();
+    parts.len() == 3
+        && parts.iter().all(|x| is_number(x))
+        && pre.is_none_or(|x| x.split('.').all(is_identifier))
+        && build.is_none_or(|x| x.split('.').all(is_identifier))
+}
+
+/// The files of the package, relative to its directory and sorted, except the ones of the
+/// [`EXCLUDED_DIRS`] and of git repositories.
+pub fn package_files(package_dir: &Path) -> Result<Vec<PathBuf>> {
+    fn add_dir(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let relative = relative.join(&name);
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                let excluded = name == ".git"
+                    || (relative.parent() == Some(Path::new(""))
+                        && EXCLUDED_DIRS.iter().any(|x| name == *x));
+                if !excluded {
+                    add_dir(&entry.path(), &relative, files)?;
+                }
+            } else if file_type.is_file() {
+                files.push(relative);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    add_dir(package_dir, Path::new(""), &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// The tar archive of the files of the package, in the directory with the given name.
+pub fn archive(package_dir: &Path, name: &str, files: &[PathBuf]) -> Result<Vec<u8>> {
+    let mut archive = Vec::new();
+
+    for file in files {
+        let path = package_dir.join(file);
+        let contents =
+            std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let executable = is_executable(&path)?;
+
+        let archived = Path::new(name).join(file);
+        let archived = archived
+            .to_str()
+            .with_context(|| format!("the path {} isn't UTF-8", file.display()))?;
+        archive.extend(tar_header(archived, contents.len(), executable)?);
+        archive.extend(&contents);
+        archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+    }
+
+    // The end of the archive.
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    Ok(archive)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(std::fs::metadata(path)?.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// The ustar header of a file, with its time and owner zeroed so the archive is reproducible.
+fn tar_header(path: &str, size: usize, executable: bool) -> Result<[u8; BLOCK]> {
+    let mut header = [0; BLOCK];
+
+    // Paths longer than the name field are split into a prefix and a name, at a `/`.
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        path.char_indices()
+            .filter(|(i, c)| *c == '/' && *i <= 155 && path.len() - i - 1 <= 100)
+            .map(|(i, _)| (&path[..i], &path[i + 1..]))
+            .next()
+            .with_context(|| format!("the path {path} is too long for the archive"))?
+    };
+
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    field(0, name.as_bytes());
+    field(100, if executable { b"0000755" } else { b"0000644" });
+    field(108, b"0000000");
+    field(116, b"0000000");
+    field(124, format!("{size:011o}").as_bytes());
+    field(136, b"00000000000");
+    field(156, b"0");
+    field(257, b"ustar\x0000");
+    field(345, prefix.as_bytes());
+
+    // The checksum is computed with its own field filled with spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|x| u32::from(*x)).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+    Ok(header)
+}
+
+/// Uploads the archive to the URL with `curl`, passing the token in its stdin so it isn't seen in
+/// its command line.
+fn upload(archive: &Path, url: &str) -> Result<()> {
+    let token = std::env::var(TOKEN_ENV).ok();
+
+    let mut curl = Command::new("curl");
+    curl.args(["--fail", "--silent", "--show-error", "--upload-file"])
+        .arg(archive)
+        .arg(url);
+    if token.is_some() {
+        curl.args(["--header", "@-"]).stdin(Stdio::piped());
+    }
+
+    let mut child = curl
+        .spawn()
+        .context("failed to run curl, it uploads the package")?;
+    if let (Some(token), Some(mut stdin)) = (token, child.stdin.take()) {
+        writeln!(stdin, "Authorization: Bearer {token}")?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("failed to upload the package to {url}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packages_are_validated_and_archived() {
+        let manifest = |package: &str, dependencies: &str| -> Config {
+            toml::from_str(&format!(
+                "[package]\nname = \"app\"\n{package}\n\n[dependencies]\n{dependencies}"
+            ))
+            .unwrap()
+        };
+
+        let config = manifest(
+            "version = \"1.2.3-beta.1\"\nlicense = \"MIT\"\ndescription = \"An app.\"",
+            "log = { git = \"https://example.com/log.git\" }\njson = { path = \"../json\", version = \"0.1.0\" }",
+        );
+        validate(&config).unwrap();
+        assert_eq!(archive_name(config.package().unwrap()), "app-1.2.3-beta.1");
+
+        let error = validate(&manifest(
+            "version = \"1.02\"\nlicense = \"\"",
+            "json = { path = \"../json\" }",
+        ))
+        .unwrap_err()
+        .to_string();
+        for problem in ["`license`", "`version`", "`description`", "`json`"] {
+            assert!(error.contains(problem), "{error}");
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        for (file, contents) in [
+            ("Concrete.toml", "[package]"),
+            ("src/main.con", "mod app {}"),
+            ("build/app", "binary"),
+            (".bricks/log/src/lib.con", "mod log {}"),
+            ("docs/build/index.md", "# Docs"),
+            (".git/HEAD", "ref: refs/heads/main"),
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        // Only the build outputs and checkouts of the package itself are left out.
+        let files = package_files(dir.path()).unwrap();
+        assert_eq!(
+            files,
+            [
+                Path::new("Concrete.toml"),
+                Path::new("docs/build/index.md"),
+                Path::new("src/main.con")
+            ]
+        );
+
+        let tar = archive(dir.path(), "app-0.1.0", &files).unwrap();
+        assert_eq!(tar.len() % 512, 0);
+        assert_eq!(&tar[..24], b"app-0.1.0/Concrete.toml\0");
+        assert_eq!(&tar[257..263], b"ustar\0");
+        assert!(tar.windows(10).any(|x| x == b"mod app {}"));
+
+        // Packaging the same files again gives the same archive.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(dir.path().join("src/main.con"), "mod app {}").unwrap();
+        assert_eq!(archive(dir.path(), "app-0.1.0", &files).unwrap(), tar);
+    }
+}
@@ -0,0 +1,508 @@
+//! The REPL of `concrete repl`: items and statements are read from stdin, compiled with the JIT
+//! and run in the compiler process, one input at a time.
+//!
+//! The items, like functions and structs, are kept in a scratch `repl` module, compiled again with
+//! every input. The statements of an input become the body of a function of that module, which
+//! loads the bindings of the previous inputs when it starts and stores them back when it ends, so
+//! they persist between inputs. Each binding lives in its own allocation, its slot, allocated by
+//! the input declaring it, which outlives the code of that input.
+//!
+//! ```text
+//! > let mut x: i32 = 2;
+//! x: i32 = 2
+//! > fn square(x: i32) -> i32 { return x * x; }
+//! > let y: i32 = square(x) + 1;
+//! y: i32 = 5
+//! ```
+
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+    ptr::null_mut,
+};
+
+use anyhow::Result;
+use owo_colors::OwoColorize;
+
+use crate::{
+    ast::{
+        statements::{LetStmtTarget, Statement},
+        types::TypeDescriptor,
+    },
+    check::lowering_error_to_diagnostics,
+    codegen::jit::Jit,
+    compile_unit_info::{CompileUnitInfo, DebugInfo, Lto, OptLevel, TargetInfo},
+    ir::{IR, lowering::lower_compile_units},
+    parser::ProgramSource,
+};
+
+use super::{CompileError, DiagnosticArgs};
+
+/// The name of the scratch module the inputs are compiled in.
+pub const REPL_MODULE: &str = "repl";
+
+/// The path the diagnostics of the inputs point to.
+const REPL_PATH: &str = "<repl>";
+
+/// The keywords an input declaring items starts with, the rest are statements.
+const ITEM_KEYWORDS: &[&str] = &[
+    "fn", "pub", "struct", "enum", "union", "const", "import", "extern", "impl", "trait", "type",
+    "mod", "#[",
+];
+
+/// The functions the code of the inputs uses to reach the slots of the bindings, in a submodule so
+/// they don't clash with the items of the user.
+const RUNTIME: &str = "    mod repl_runtime {
+        extern \"C\" {
+            fn malloc(size: u64) -> *mut u8;
+        }
+
+        #[intrinsic = \"sizeof\"]
+        fn sizeof<T>() -> u64;
+
+        pub fn load<T>(slots: *mut *mut u8, index: u64) -> T {
+            unsafe {
+                let slot: *mut *mut u8 = slots + index;
+                let value: *mut T = *slot as *mut T;
+                return *value;
+            }
+        }
+
+        pub fn alloc<T>(slots: *mut *mut u8, index: u64) {
+            unsafe {
+                let slot: *mut *mut u8 = slots + index;
+                *slot = malloc(sizeof::<T>());
+            }
+        }
+
+        pub fn store<T>(slots: *mut *mut u8, index: u64, value: T) {
+            unsafe {
+                let slot: *mut *mut u8 = slots + index;
+                let target: *mut T = *slot as *mut T;
+                *target = value;
+            }
+        }
+    }
+";
+
+/// A binding declared with `let name: Type` at the top level of an input.
+#[derive(Debug, Clone)]
+pub struct Binding {
+    pub name: String,
+    pub r#type: TypeDescriptor,
+    pub is_mutable: bool,
+    /// The index of its slot.
+    pub slot: usize,
+}
+
+/// The statements of an input, checked and ready to run.
+#[derive(Debug)]
+pub struct Line {
+    /// The scratch program, with the items and the function running the statements.
+    pub ir: IR,
+    /// The symbol of the function running the statements.
+    pub symbol: String,
+    /// The bindings the statements declare, shadowing the previous ones with their names.
+    pub declared: Vec<Binding>,
+}
+
+pub struct Repl {
+    session: CompileUnitInfo,
+    /// The sources of the items declared so far.
+    items: Vec<String>,
+    bindings: Vec<Binding>,
+    /// The allocations of the bindings, null until the input declaring them runs.
+    slots: Vec<*mut u8>,
+    /// The code of the inputs run, kept as the bindings may point into it, like to its strings.
+    jits: Vec<Jit>,
+    /// The number of inputs with statements checked, naming their functions.
+    lines: usize,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            session: CompileUnitInfo {
+                debug_info: DebugInfo::None,
+                optlevel: OptLevel::None,
+                library: false,
+                output_file: std::env::temp_dir().join("concrete-repl.o"),
+                output_mlir: false,
+                output_ll: false,
+                output_asm: false,
+                fuzzing: false,
+                unchecked_indexing: false,
+                target: TargetInfo::default(),
+                lto: Lto::Off,
+                sanitizers: Vec::new(),
+                coverage: false,
+                freestanding: false,
+            },
+            items: Vec::new(),
+            bindings: Vec::new(),
+            slots: Vec::new(),
+            jits: Vec::new(),
+            lines: 0,
+        }
+    }
+
+    /// The bindings of the inputs run so far.
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// Checks the input and runs it, printing the bindings it declares. The errors of the input
+    /// are printed as diagnostics, failing with [`CompileError::Reported`].
+    pub fn eval(&mut self, input: &str, diagnostics: &DiagnosticArgs) -> Result<()> {
+        if let Some(line) = self.prepare(input, diagnostics)? {
+            for (binding, value) in self.run(line)? {
+                match value {
+                    Some(value) => println!("{}: {} = {}", binding.name, binding.r#type, value),
+                    None => println!("{}: {}", binding.name, binding.r#type),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the input. Items are kept for the next inputs, statements are returned as a line to
+    /// run.
+    pub fn prepare(&mut self, input: &str, diagnostics: &DiagnosticArgs) -> Result<Option<Line>> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(None);
+        }
+
+        if is_item(input) {
+            let source = self.source(Some(input), "");
+            self.lower(source, diagnostics)?;
+            self.items.push(input.to_string());
+            return Ok(None);
+        }
+
+        let statements = ProgramSource::new(input.to_string(), Path::new(REPL_PATH));
+        let statements = match crate::parser::parse_statements(&statements) {
+            Ok(x) => x,
+            Err(error) => {
+                return Err(CompileError::Parse {
+                    diagnostic: Box::new(error.to_diagnostic(&statements)),
+                    input: statements.input,
+                }
+                .into());
+            }
+        };
+
+        let mut declared: Vec<Binding> = Vec::new();
+        for statement in statements {
+            if let Statement::Let(stmt) = statement {
+                if let LetStmtTarget::Simple { id, r#type } = stmt.target {
+                    declared.retain(|x| x.name != id.name);
+                    declared.push(Binding {
+                        name: id.name,
+                        r#type,
+                        is_mutable: stmt.is_mutable,
+                        slot: 0,
+                    });
+                }
+            }
+        }
+        for (i, binding) in declared.iter_mut().enumerate() {
+            binding.slot = self.slots.len() + i;
+        }
+
+        let name = format!("repl_line_{}", self.lines);
+        let source = self.source(None, &self.function(&name, input, &declared));
+        let ir = self.lower(source, diagnostics)?;
+        self.lines += 1;
+
+        let symbol = ir
+            .functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref())
+            .find(|x| x.debug_name.as_deref() == Some(&format!("{REPL_MODULE}::{name}")))
+            .map(|x| x.name.clone())
+            .expect("the function of the line should be lowered");
+
+        Ok(Some(Line {
+            ir,
+            symbol,
+            declared,
+        }))
+    }
+
+    /// Compiles the line and runs it, returning the bindings it declared and their values, if
+    /// they're of a primitive type that can be printed.
+    pub fn run(&mut self, line: Line) -> Result<Vec<(Binding, Option<String>)>> {
+        let jit = Jit::new(&self.session, &line.ir)?;
+        let address = jit.lookup(&line.symbol)?;
+        self.jits.push(jit);
+
+        self.slots
+            .resize(self.slots.len() + line.declared.len(), null_mut());
+
+        unsafe {
+            let function: unsafe extern "C" fn(*mut *mut u8) =
+                std::mem::transmute(address as usize);
+            function(self.slots.as_mut_ptr());
+        }
+
+        let mut values = Vec::new();
+        for binding in line.declared {
+            self.bindings.retain(|x| x.name != binding.name);
+            let value = display_value(&binding.r#type, self.slots[binding.slot]);
+            self.bindings.push(binding.clone());
+            values.push((binding, value));
+        }
+
+        Ok(values)
+    }
+
+    /// The source of the scratch module, with the items so far, the new one if any, and the given
+    /// function.
+    fn source(&self, item: Option<&str>, function: &str) -> String {
+        let mut source = format!("mod {REPL_MODULE} {{\n{RUNTIME}");
+
+        for item in self.items.iter().map(|x| x.as_str()).chain(item) {
+            source.push('\n');
+            source.push_str(item);
+            source.push('\n');
+        }
+        source.push_str(function);
+        source.push_str("}\n");
+
+        source
+    }
+
+    /// The function running the statements of the input: it loads the previous bindings not
+    /// shadowed by the input, and stores back the mutable ones and the ones it declares.
+    fn function(&self, name: &str, input: &str, declared: &[Binding]) -> String {
+        let previous: Vec<&Binding> = self
+            .bindings
+            .iter()
+            .filter(|x| !declared.iter().any(|d| d.name == x.name))
+            .collect();
+
+        let mut function = format!("\n    pub fn {name}(slots: *mut *mut u8) {{\n");
+        for binding in &previous {
+            let mutable = if binding.is_mutable { "mut " } else { "" };
+            function.push_str(&format!(
+                "        let {mutable}{}: {} = repl_runtime::load::<{}>(slots, {});\n",
+                binding.name, binding.r#type, binding.r#type, binding.slot
+            ));
+        }
+
+        function.push_str(input);
+        function.push('\n');
+
+        for binding in declared {
+            function.push_str(&format!(
+                "        repl_runtime::alloc::<{}>(slots, {});\n",
+                binding.r#type, binding.slot
+            ));
+        }
+        for binding in previous
+            .into_iter()
+            .filter(|x| x.is_mutable)
+            .chain(declared)
+        {
+            function.push_str(&format!(
+                "        repl_runtime::store::<{}>(slots, {}, {});\n",
+                binding.r#type, binding.slot, binding.name
+            ));
+        }
+        function.push_str("    }\n");
+
+        function
+    }
+
+    /// Parses and lowers the scratch program, printing its errors.
+    fn lower(&self, source: String, diagnostics: &DiagnosticArgs) -> Result<IR> {
+        let source = ProgramSource::new(source, Path::new(REPL_PATH));
+        let program = match crate::parser::parse_ast(&source) {
+            Ok(x) => x,
+            Err(error) => {
+                return Err(CompileError::Parse {
+                    diagnostic: Box::new(error.to_diagnostic(&source)),
+                    input: source.input,
+                }
+                .into());
+            }
+        };
+
+        match lower_compile_units(&[program]) {
+            Ok(ir) => Ok(ir),
+            Err(error) => {
+                let mut emitter = diagnostics.emitter();
+                emitter.sources_mut().insert(REPL_PATH, source.input);
+                for diagnostic in lowering_error_to_diagnostics(error) {
+                    emitter.emit(&diagnostic)?;
+                }
+                emitter.print_summary();
+                Err(CompileError::Reported.into())
+            }
+        }
+    }
+}
+
+impl Drop for Repl {
+    fn drop(&mut self) {
+        for slot in self.slots.drain(..).filter(|x| !x.is_null()) {
+            unsafe { libc::free(slot.cast()) };
+        }
+    }
+}
+
+/// Reads inputs from stdin until it ends or `:quit` is given, running each one. An input goes on
+/// for as many lines as it needs to close its braces.
+pub fn serve(diagnostics: &DiagnosticArgs) -> Result<()> {
+    println!(
+        "Concrete {} REPL, enter items or statements, {} to exit.",
+        env!("CARGO_PKG_VERSION"),
+        ":quit".bold()
+    );
+
+    let mut repl = Repl::new();
+    let mut input = String::new();
+    let mut lines = std::io::stdin().lock().lines();
+
+    loop {
+        print!("{} ", if input.is_empty() { ">" } else { "|" });
+        std::io::stdout().flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        if input.is_empty() && line.trim() == ":quit" {
+            break;
+        }
+
+        input.push_str(&line);
+        input.push('\n');
+        if open_braces(&input) > 0 {
+            continue;
+        }
+
+        let result = repl.eval(&input, diagnostics);
+        input.clear();
+
+        match result.map_err(|x| x.downcast::<CompileError>()) {
+            Ok(()) => {}
+            Err(Ok(error)) => {
+                let diagnostics = diagnostics.for_build();
+                error.emit(&diagnostics)?;
+                diagnostics.print_summary();
+            }
+            Err(Err(error)) => eprintln!("{}: {error:#}", "error".red().bold()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the input declares items instead of running statements.
+fn is_item(input: &str) -> bool {
+    ITEM_KEYWORDS.iter().any(|keyword| {
+        input.strip_prefix(keyword).is_some_and(|rest| {
+            keyword.ends_with('[') || rest.starts_with(|x: char| x.is_whitespace())
+        })
+    })
+}
+
+/// The braces opened by the input and not closed yet, outside of strings.
+fn open_braces(input: &str) -> i64 {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for c in input.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth
+}
+
+/// The value in the slot, if the type is a primitive one that can be printed.
+fn display_value(r#type: &TypeDescriptor, slot: *mut u8) -> Option<String> {
+    let TypeDescriptor::Type { name, .. } = r#type else {
+        return None;
+    };
+    if slot.is_null() || !name.path.is_empty() || !name.generics.is_empty() {
+        return None;
+    }
+
+    unsafe {
+        Some(match name.name.name.as_str() {
+            "i8" => (*slot.cast::<i8>()).to_string(),
+            "i16" => (*slot.cast::<i16>()).to_string(),
+            "i32" => (*slot.cast::<i32>()).to_string(),
+            "i64" => (*slot.cast::<i64>()).to_string(),
+            "u8" => (*slot.cast::<u8>()).to_string(),
+            "u16" => (*slot.cast::<u16>()).to_string(),
+            "u32" => (*slot.cast::<u32>()).to_string(),
+            "u64" => (*slot.cast::<u64>()).to_string(),
+            "f32" => (*slot.cast::<f32>()).to_string(),
+            "f64" => (*slot.cast::<f64>()).to_string(),
+            "bool" => (*slot != 0).to_string(),
+            "char" => format!("{:?}", *slot as char),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repl_checks_items_and_statements() {
+        let diagnostics = DiagnosticArgs::default();
+        let mut repl = Repl::new();
+
+        let item = "fn square(x: i32) -> i32 {\n    return x * x;\n}";
+        assert!(repl.prepare(item, &diagnostics).unwrap().is_none());
+
+        let line = repl
+            .prepare(
+                "let mut x: i32 = square(3);\nx = x + 1;\nlet done: bool = x > 9;",
+                &diagnostics,
+            )
+            .unwrap()
+            .expect("statements should be a line to run");
+        let declared: Vec<(String, String, usize)> = line
+            .declared
+            .iter()
+            .map(|x| (x.name.clone(), x.r#type.to_string(), x.slot))
+            .collect();
+        assert_eq!(
+            declared,
+            vec![
+                ("x".to_string(), "i32".to_string(), 0),
+                ("done".to_string(), "bool".to_string(), 1)
+            ]
+        );
+        assert!(line.symbol.contains("repl_line_0"), "{}", line.symbol);
+
+        let error = repl
+            .prepare("let y: i32 = square(true);", &diagnostics)
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<CompileError>(),
+            Some(CompileError::Reported)
+        ));
+        assert!(repl.bindings().is_empty());
+    }
+}
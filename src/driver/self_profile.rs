@@ -0,0 +1,213 @@
+//! `-Z self-profile`: records how long each pass of the compiler takes and the memory used after it,
+//! to a Chrome trace file that can be opened in Perfetto, `chrome://tracing` or speedscope.
+//!
+//! Events are written as soon as they end, the trace uses the JSON array format whose closing `]`
+//! is optional, so the file is still readable when the compiler exits early on errors.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::ThreadId,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+
+/// Checked before taking the lock, so profiling costs nothing when it's disabled.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static PROFILER: Mutex<Option<Profiler>> = Mutex::new(None);
+
+struct Profiler {
+    file: File,
+    path: PathBuf,
+    start: Instant,
+    /// The ids given to the threads in the trace, in the order they were seen.
+    threads: HashMap<ThreadId, usize>,
+}
+
+impl Profiler {
+    fn write(&mut self, event: Value) {
+        // One write for each event, so they're never interleaved or lost in a buffer.
+        let _ = self.file.write_all(format!("{event},\n").as_bytes());
+    }
+
+    fn thread_id(&mut self) -> usize {
+        let thread = std::thread::current();
+
+        if let Some(id) = self.threads.get(&thread.id()) {
+            return *id;
+        }
+
+        let id = self.threads.len();
+        self.threads.insert(thread.id(), id);
+        self.write(json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": std::process::id(),
+            "tid": id,
+            "args": { "name": thread.name().unwrap_or("unnamed") },
+        }));
+        id
+    }
+}
+
+/// Starts recording, to a trace file in the given directory, and returns its path.
+pub fn enable(dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let path = dir.join(format!("concrete-{}.trace.json", std::process::id()));
+    let mut file =
+        File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+    file.write_all(b"[\n")?;
+
+    let mut profiler = Profiler {
+        file,
+        path: path.clone(),
+        start: Instant::now(),
+        threads: HashMap::new(),
+    };
+    profiler.write(json!({
+        "name": "process_name",
+        "ph": "M",
+        "pid": std::process::id(),
+        "args": { "name": "concrete" },
+    }));
+
+    *PROFILER.lock().unwrap_or_else(|x| x.into_inner()) = Some(profiler);
+    ENABLED.store(true, Ordering::Relaxed);
+
+    Ok(path)
+}
+
+/// Stops recording and returns the path of the trace, if it was enabled.
+pub fn finish() -> Option<PathBuf> {
+    if !ENABLED.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+
+    let mut profiler = PROFILER.lock().unwrap_or_else(|x| x.into_inner());
+    let mut profiler = profiler.take()?;
+    // Replace the comma after the last event, so the finished trace is valid JSON.
+    let _ = profiler.file.seek(SeekFrom::End(-2));
+    let _ = profiler.file.write_all(b"\n]\n");
+
+    Some(profiler.path)
+}
+
+/// Records the time spent until the returned guard is dropped, as an event of the given category,
+/// like `parse` or `codegen`.
+pub fn span(category: &'static str, name: impl Display) -> ProfileSpan {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return ProfileSpan { event: None };
+    }
+
+    ProfileSpan {
+        event: Some((category, name.to_string(), Instant::now())),
+    }
+}
+
+/// An event being recorded, see [`span`].
+#[must_use = "the event ends when the span is dropped"]
+pub struct ProfileSpan {
+    event: Option<(&'static str, String, Instant)>,
+}
+
+impl Drop for ProfileSpan {
+    fn drop(&mut self) {
+        let Some((category, name, start)) = self.event.take() else {
+            return;
+        };
+
+        let end = Instant::now();
+        let mut profiler = PROFILER.lock().unwrap_or_else(|x| x.into_inner());
+        let Some(profiler) = profiler.as_mut() else {
+            return;
+        };
+
+        let tid = profiler.thread_id();
+        let ts = start.duration_since(profiler.start).as_micros() as u64;
+        let dur = end.duration_since(start).as_micros() as u64;
+
+        profiler.write(json!({
+            "name": name,
+            "cat": category,
+            "ph": "X",
+            "ts": ts,
+            "dur": dur,
+            "pid": std::process::id(),
+            "tid": tid,
+        }));
+
+        if let Some(rss) = resident_memory() {
+            profiler.write(json!({
+                "name": "memory",
+                "ph": "C",
+                "ts": ts + dur,
+                "pid": std::process::id(),
+                "args": { "resident bytes": rss },
+            }));
+        }
+    }
+}
+
+/// The resident memory of the process, only known on Linux.
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|x| x.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_profile_writes_chrome_trace() {
+        let dir =
+            std::env::temp_dir().join(format!("concrete-self-profile-{}", std::process::id()));
+        let path = enable(&dir).expect("failed to enable the self profile");
+
+        {
+            let _outer = span("lowering", "outer");
+            let _inner = span("parse", "inner");
+        }
+
+        assert_eq!(finish(), Some(path.clone()));
+        assert_eq!(finish(), None);
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<serde_json::Value> = serde_json::from_str(&trace).unwrap();
+
+        let spans: Vec<_> = events.iter().filter(|x| x["ph"] == "X").collect();
+        assert!(
+            spans
+                .iter()
+                .any(|x| x["name"] == "outer" && x["cat"] == "lowering")
+        );
+        assert!(
+            spans
+                .iter()
+                .any(|x| x["name"] == "inner" && x["cat"] == "parse")
+        );
+        assert!(events.iter().any(|x| x["name"] == "thread_name"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
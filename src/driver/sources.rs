@@ -0,0 +1,354 @@
+//! Where source files are looked up, the only place that knows their file extensions.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::ast::{
+    CompilationUnit,
+    common::Ident,
+    modules::{Module, ModuleDefItem},
+};
+
+/// The extension of Concrete source files.
+pub const SOURCE_EXTENSION: &str = "con";
+
+/// Every extension source files have used, to tell the user when a file uses one that isn't enabled.
+const KNOWN_EXTENSIONS: &[&str] = &["con", "ed"];
+
+/// The extensions source files are looked up with, in order of preference.
+#[derive(Debug, Clone)]
+pub struct SourceExtensions {
+    extensions: Vec<String>,
+}
+
+impl Default for SourceExtensions {
+    fn default() -> Self {
+        Self {
+            extensions: vec![SOURCE_EXTENSION.to_string()],
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SourceLookupError {
+    #[error("{name} not found, looked for {}", display_paths(candidates))]
+    NotFound {
+        name: String,
+        candidates: Vec<PathBuf>,
+    },
+    #[error(
+        "found {}, but `{extension}` is not a source file extension\nhelp: rename it to {} or add \"{extension}\" to `extension_aliases` in the [package] section of Concrete.toml",
+        found.display(),
+        expected.display()
+    )]
+    DisabledExtension {
+        found: PathBuf,
+        extension: String,
+        expected: PathBuf,
+    },
+    #[error("module files declare each other: {}", display_cycle(cycle))]
+    ModuleCycle { cycle: Vec<PathBuf> },
+    #[error(
+        "{} is declared as a module twice, in {} and in {}",
+        file.display(),
+        first.display(),
+        second.display()
+    )]
+    DuplicateModule {
+        file: PathBuf,
+        first: PathBuf,
+        second: PathBuf,
+    },
+}
+
+impl SourceLookupError {
+    /// The code of the diagnostic reporting it.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SourceLookupError::NotFound { .. } | SourceLookupError::DisabledExtension { .. } => {
+                "ModuleFileNotFound"
+            }
+            SourceLookupError::ModuleCycle { .. } => "ModuleCycle",
+            SourceLookupError::DuplicateModule { .. } => "DuplicateModule",
+        }
+    }
+}
+
+/// A module declared in another file with `mod name;`.
+#[derive(Debug, Clone)]
+pub struct ModuleDeclaration {
+    /// The names of the modules it's declared in, from the top level module of the file.
+    pub parents: Vec<String>,
+    pub name: Ident,
+    /// The directory its file is looked up in.
+    pub dir: PathBuf,
+}
+
+/// The modules the file declares in other files, at any depth. The ones declared in the top level
+/// modules are looked up next to the file, the ones declared in their inline submodules in the
+/// directories named after them: `mod a { mod b; }` in `src/main.con` is `src/a/b.con`.
+pub fn module_declarations(unit: &CompilationUnit, file: &Path) -> Vec<ModuleDeclaration> {
+    fn find(
+        module: &Module,
+        parents: &mut Vec<String>,
+        dir: &Path,
+        found: &mut Vec<ModuleDeclaration>,
+    ) {
+        parents.push(module.name.name.clone());
+        for item in &module.contents {
+            match item {
+                ModuleDefItem::ExternalModule(name) => found.push(ModuleDeclaration {
+                    parents: parents.clone(),
+                    name: name.clone(),
+                    dir: dir.to_path_buf(),
+                }),
+                ModuleDefItem::Module(submodule) => {
+                    find(submodule, parents, &dir.join(&submodule.name.name), found)
+                }
+                _ => {}
+            }
+        }
+        parents.pop();
+    }
+
+    let dir = file.parent().unwrap_or(Path::new(""));
+    let mut found = Vec::new();
+    for module in &unit.modules {
+        find(module, &mut Vec::new(), dir, &mut found);
+    }
+    found
+}
+
+/// The files of the modules declared while parsing a file and the ones it declares, to find the
+/// ones declaring each other and the ones declared twice.
+#[derive(Debug, Default)]
+pub struct ModuleFiles {
+    /// The files being parsed, each one declared by the previous one.
+    parsing: Vec<PathBuf>,
+    /// The files declared as modules, with the file declaring them.
+    declared: HashMap<PathBuf, PathBuf>,
+    /// Where the build script of the package generates sources, see [`super::build_script`].
+    generated_dir: Option<PathBuf>,
+}
+
+impl ModuleFiles {
+    /// The files of the modules of a package whose build script generates sources in the
+    /// directory, the modules not found next to the file declaring them are looked up there.
+    pub fn with_generated_dir(dir: PathBuf) -> Self {
+        Self {
+            generated_dir: Some(dir),
+            ..Self::default()
+        }
+    }
+
+    /// The directory the sources of the build script of the package are in, if it has one.
+    pub fn generated_dir(&self) -> Option<&Path> {
+        self.generated_dir.as_deref()
+    }
+
+    /// The files of the modules declared while parsing the given one.
+    pub fn new(file: &Path) -> Self {
+        let mut files = Self::default();
+        files.parsing.push(canonical(file));
+        files
+    }
+
+    /// Starts parsing the file, declared as a module by the one being parsed if there's one.
+    pub fn enter(&mut self, file: &Path) -> Result<(), SourceLookupError> {
+        let key = canonical(file);
+
+        if let Some(start) = self.parsing.iter().position(|x| *x == key) {
+            let mut cycle = self.parsing[start..].to_vec();
+            cycle.push(key);
+            return Err(SourceLookupError::ModuleCycle { cycle });
+        }
+
+        if let Some(declaring) = self.parsing.last() {
+            if let Some(first) = self.declared.get(&key) {
+                return Err(SourceLookupError::DuplicateModule {
+                    file: file.to_path_buf(),
+                    first: first.clone(),
+                    second: declaring.clone(),
+                });
+            }
+            self.declared.insert(key.clone(), declaring.clone());
+        }
+
+        self.parsing.push(key);
+        Ok(())
+    }
+
+    /// Finishes parsing the last file entered.
+    pub fn leave(&mut self) {
+        self.parsing.pop();
+    }
+}
+
+impl SourceExtensions {
+    /// The default extension followed by the given aliases, as set by `extension_aliases` in
+    /// Concrete.toml.
+    pub fn with_aliases(aliases: &[String]) -> Self {
+        let mut extensions = Self::default();
+
+        for alias in aliases {
+            let alias = alias.trim_start_matches('.');
+            if !extensions.extensions.iter().any(|x| x == alias) {
+                extensions.extensions.push(alias.to_string());
+            }
+        }
+
+        extensions
+    }
+
+    pub fn is_source(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| self.extensions.iter().any(|x| ext == x.as_str()))
+    }
+
+    /// Finds the source file with the given path minus its extension, if there is one.
+    ///
+    /// Fails if the file only exists with an extension that isn't enabled.
+    pub fn find_file(&self, stem: &Path) -> Result<Option<PathBuf>, SourceLookupError> {
+        self.find_file_in(stem, &|x| x.is_file())
+    }
+
+    /// Like [`SourceExtensions::find_file`], with `exists` telling which files exist, so sources
+    /// that are only in memory can be found too.
+    pub fn find_file_in(
+        &self,
+        stem: &Path,
+        exists: &dyn Fn(&Path) -> bool,
+    ) -> Result<Option<PathBuf>, SourceLookupError> {
+        if let Some(path) = self
+            .extensions
+            .iter()
+            .map(|x| stem.with_extension(x))
+            .find(|x| exists(x))
+        {
+            return Ok(Some(path));
+        }
+
+        for extension in KNOWN_EXTENSIONS {
+            let found = stem.with_extension(extension);
+
+            if exists(&found) {
+                return Err(SourceLookupError::DisabledExtension {
+                    found,
+                    extension: extension.to_string(),
+                    expected: stem.with_extension(&self.extensions[0]),
+                });
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Finds the file of the module declared in the given directory, `name.con` or `name/mod.con`.
+    pub fn find_module(&self, dir: &Path, name: &str) -> Result<PathBuf, SourceLookupError> {
+        self.find_module_in(dir, name, &|x| x.is_file())
+    }
+
+    /// Like [`SourceExtensions::find_module`], with `exists` telling which files exist.
+    pub fn find_module_in(
+        &self,
+        dir: &Path,
+        name: &str,
+        exists: &dyn Fn(&Path) -> bool,
+    ) -> Result<PathBuf, SourceLookupError> {
+        let stems = [dir.join(name), dir.join(name).join("mod")];
+
+        for stem in &stems {
+            if let Some(path) = self.find_file_in(stem, exists)? {
+                return Ok(path);
+            }
+        }
+
+        Err(SourceLookupError::NotFound {
+            name: format!("module '{name}'"),
+            candidates: self.candidates(&stems),
+        })
+    }
+
+    /// Finds the root file of a directory given as a module, `mod.con`.
+    pub fn find_dir_module(&self, dir: &Path) -> Result<PathBuf, SourceLookupError> {
+        let stem = dir.join("mod");
+
+        self.find_file(&stem)?
+            .ok_or_else(|| SourceLookupError::NotFound {
+                name: format!("root module of {}", dir.display()),
+                candidates: self.candidates(&[stem]),
+            })
+    }
+
+    fn candidates(&self, stems: &[PathBuf]) -> Vec<PathBuf> {
+        stems
+            .iter()
+            .flat_map(|stem| self.extensions.iter().map(|x| stem.with_extension(x)))
+            .collect()
+    }
+}
+
+/// The path files are compared by, the same for all the paths of a file if it exists, they're
+/// compared as they're given otherwise, like sources only in memory.
+fn canonical(file: &Path) -> PathBuf {
+    file.canonicalize().unwrap_or_else(|_| file.to_path_buf())
+}
+
+fn display_cycle(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|x| x.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|x| x.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_file_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Library.con"), "mod Library {}\n").unwrap();
+        std::fs::create_dir(dir.path().join("Nested")).unwrap();
+        std::fs::write(dir.path().join("Nested").join("mod.ed"), "mod Nested {}\n").unwrap();
+
+        let extensions = SourceExtensions::default();
+        assert_eq!(
+            extensions.find_module(dir.path(), "Library").unwrap(),
+            dir.path().join("Library.con")
+        );
+        assert!(matches!(
+            extensions.find_module(dir.path(), "Missing"),
+            Err(SourceLookupError::NotFound { .. })
+        ));
+
+        let error = extensions.find_module(dir.path(), "Nested").unwrap_err();
+        assert!(
+            matches!(&error, SourceLookupError::DisabledExtension { extension, expected, .. }
+                if extension == "ed" && *expected == dir.path().join("Nested").join("mod.con")),
+            "{:#?}",
+            error
+        );
+        assert!(matches!(
+            extensions.find_dir_module(&dir.path().join("Nested")),
+            Err(SourceLookupError::DisabledExtension { .. })
+        ));
+
+        let extensions = SourceExtensions::with_aliases(&[".ed".to_string()]);
+        assert_eq!(
+            extensions.find_module(dir.path(), "Nested").unwrap(),
+            dir.path().join("Nested").join("mod.ed")
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! The sysroot: the directory the compiler finds `std` in, given with `--sysroot` or located from
+//! the `CONCRETE_HOME` environment variable, `~/.concrete` by default.
+//!
+//! ```text
+//! <sysroot>/lib/std/Concrete.toml    the std package, the implicit `std` dependency
+//! <sysroot>/lib/<triple>/std-<hash>.o  its prebuilt compile units for each target
+//! ```
+//!
+//! Packages that don't declare a `std` dependency get the one of the sysroot. The objects of its
+//! compile units are kept in the sysroot, named after the hash of what they're generated from, so
+//! the builds of other projects generating the same code reuse them instead of compiling std again,
+//! see [`super::cache::reuse_unit`].
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+
+use super::config::Dependency;
+
+/// The environment variable with the directory of the sysroot.
+pub const HOME_ENV: &str = "CONCRETE_HOME";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sysroot {
+    pub dir: PathBuf,
+}
+
+impl Sysroot {
+    /// Locates the sysroot: the given one, or the one of `CONCRETE_HOME` or `~/.concrete`. `None`
+    /// if it's not given and the located one has no `std`.
+    pub fn locate(sysroot: Option<&Path>) -> Result<Option<Self>> {
+        if let Some(dir) = sysroot {
+            let sysroot = Self::new(dir);
+            if !sysroot.has_std() {
+                bail!(
+                    "the sysroot {} has no std, expected it in {}",
+                    dir.display(),
+                    sysroot.std_dir().display()
+                );
+            }
+            return Ok(Some(sysroot));
+        }
+
+        Ok(home_dir().map(|x| Self::new(&x)).filter(Self::has_std))
+    }
+
+    fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf()),
+        }
+    }
+
+    /// The directory of the std package.
+    pub fn std_dir(&self) -> PathBuf {
+        self.dir.join("lib").join("std")
+    }
+
+    pub fn has_std(&self) -> bool {
+        self.std_dir().join("Concrete.toml").exists()
+    }
+
+    /// The `std` dependency of the packages that don't declare one.
+    pub fn std_dependency(&self) -> Dependency {
+        Dependency {
+            path: Some(self.std_dir()),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the source file is part of the std of the sysroot.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(self.std_dir())
+    }
+
+    /// Where the compile unit of std with the given hash is kept for the target, with the extension
+    /// of the object or bitcode it's compiled to. The hash is the one of
+    /// [`shared_unit_hashes`](super::incremental::shared_unit_hashes), of the unit's own code and
+    /// the options it's generated with, the same for every program using it.
+    pub fn prebuilt_unit(&self, triple: &str, hash: u64, extension: &str) -> PathBuf {
+        self.dir
+            .join("lib")
+            .join(triple)
+            .join(format!("std-{hash:016x}.{extension}"))
+    }
+}
+
+/// The directory of `CONCRETE_HOME`, or `~/.concrete` if it's not set.
+pub fn home_dir() -> Option<PathBuf> {
+    match std::env::var_os(HOME_ENV) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".concrete")),
+    }
+}
@@ -0,0 +1,191 @@
+//! Running the tests of `concrete test`: selecting them by their names, like `concrete test Parser::`
+//! to only run the tests of the `Parser` module, and capturing what they print.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::fd::AsRawFd,
+    ptr::null_mut,
+};
+
+/// The filters given to `concrete test`.
+#[derive(Debug, Clone, Default)]
+pub struct TestFilter {
+    pub filters: Vec<String>,
+    /// Whether the names have to be equal to a filter instead of containing it.
+    pub exact: bool,
+}
+
+impl TestFilter {
+    /// Whether the test with the given name is run: all of them are if there are no filters.
+    pub fn matches(&self, name: &str) -> bool {
+        self.filters.is_empty()
+            || self.filters.iter().any(|filter| {
+                if self.exact {
+                    name == filter
+                } else {
+                    name.contains(filter.as_str())
+                }
+            })
+    }
+}
+
+/// Calls the function, which runs a test in this process, with what it prints to stdout and stderr
+/// captured, and returns its result together with the captured output. With `nocapture` the output
+/// is printed as usual and none is returned.
+pub fn run_captured<R>(nocapture: bool, f: impl FnOnce() -> R) -> io::Result<(R, String)> {
+    if nocapture {
+        return Ok((f(), String::new()));
+    }
+
+    let capture = Capture::start()?;
+    let result = f();
+    let output = capture.finish()?;
+
+    Ok((result, output))
+}
+
+/// Redirects the stdout and stderr file descriptors of the process to a file, as the tests write to
+/// them directly.
+struct Capture {
+    file: File,
+    /// Duplicates of the original stdout and stderr, to restore them.
+    stdout: libc::c_int,
+    stderr: libc::c_int,
+    restored: bool,
+}
+
+impl Capture {
+    fn start() -> io::Result<Self> {
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+
+        let path = std::env::temp_dir().join(format!("concrete-test-{}.out", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        // The file is only reached through the open descriptor.
+        std::fs::remove_file(&path)?;
+
+        unsafe {
+            libc::fflush(null_mut());
+
+            let stdout = libc::dup(libc::STDOUT_FILENO);
+            let stderr = libc::dup(libc::STDERR_FILENO);
+            if stdout < 0 || stderr < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let capture = Self {
+                file,
+                stdout,
+                stderr,
+                restored: false,
+            };
+
+            if libc::dup2(capture.file.as_raw_fd(), libc::STDOUT_FILENO) < 0
+                || libc::dup2(capture.file.as_raw_fd(), libc::STDERR_FILENO) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(capture)
+        }
+    }
+
+    /// Restores stdout and stderr, returning what was written to them.
+    fn finish(mut self) -> io::Result<String> {
+        self.restore();
+
+        let mut output = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut output)?;
+
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    fn restore(&mut self) {
+        if self.restored {
+            return;
+        }
+        self.restored = true;
+
+        unsafe {
+            // What the test left in the buffers of the C library belongs to the capture.
+            libc::fflush(null_mut());
+            libc::dup2(self.stdout, libc::STDOUT_FILENO);
+            libc::dup2(self.stderr, libc::STDERR_FILENO);
+            libc::close(self.stdout);
+            libc::close(self.stderr);
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let all = TestFilter::default();
+        assert!(all.matches("Parser::parse_header"));
+
+        let filter = TestFilter {
+            filters: vec!["Parser::".to_string(), "lex".to_string()],
+            exact: false,
+        };
+        assert!(filter.matches("Parser::parse_header"));
+        assert!(filter.matches("Lexer::lex_number"));
+        assert!(!filter.matches("Main::add"));
+
+        let exact = TestFilter {
+            filters: vec!["Main::add".to_string()],
+            exact: true,
+        };
+        assert!(exact.matches("Main::add"));
+        assert!(!exact.matches("Main::add_many"));
+    }
+
+    #[test]
+    fn test_output_is_captured() {
+        // The capture redirects the stdout of the whole process, which would take the output of the
+        // tests running next to it, so it runs alone in a process of its own.
+        if std::env::var_os("CONCRETE_CAPTURE_TEST").is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "driver::testing::tests::test_output_is_captured"])
+                .env("CONCRETE_CAPTURE_TEST", "1")
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+            return;
+        }
+
+        // Written to the file descriptor directly, like the compiled tests do.
+        let (result, output) = run_captured(false, || {
+            let mut stdout = std::io::stdout();
+            write!(stdout, "hello from a test").unwrap();
+            stdout.flush().unwrap();
+            7
+        })
+        .unwrap();
+
+        assert_eq!(result, 7);
+        assert_eq!(output, "hello from a test");
+
+        let (_, output) = run_captured(true, || ()).unwrap();
+        assert!(output.is_empty());
+    }
+}
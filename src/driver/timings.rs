@@ -0,0 +1,273 @@
+//! `--timings`: how long each phase of the build took, summed over the compile units, printed as a
+//! table once the build finishes or written as a JSON or HTML report.
+//!
+//! Unlike `-Z self-profile`, which traces every pass of every unit, this only keeps a total for each
+//! phase, so it's cheap enough to leave on and easy to read.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use owo_colors::OwoColorize;
+use serde::Serialize;
+
+/// Checked before taking the lock, so timing costs nothing when it's disabled.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TIMINGS: Mutex<Timings> = Mutex::new(Timings {
+    phases: BTreeMap::new(),
+});
+
+/// How the timings are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TimingsFormat {
+    /// A table printed to stderr.
+    #[default]
+    Table,
+    /// `concrete-timings.json`, for scripts.
+    Json,
+    /// `concrete-timings.html`, with a bar for each phase.
+    Html,
+}
+
+impl TimingsFormat {
+    /// The file the report is written to, `None` if it's printed.
+    pub fn file_name(&self) -> Option<&'static str> {
+        match self {
+            TimingsFormat::Table => None,
+            TimingsFormat::Json => Some("concrete-timings.json"),
+            TimingsFormat::Html => Some("concrete-timings.html"),
+        }
+    }
+}
+
+/// A phase of the build, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Parse,
+    Lower,
+    /// Generating the MLIR of the IR, and the machine code of the LLVM IR.
+    Codegen,
+    MlirPasses,
+    LlvmOpt,
+    Link,
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Lower => "lower",
+            Phase::Codegen => "codegen",
+            Phase::MlirPasses => "MLIR passes",
+            Phase::LlvmOpt => "LLVM opt",
+            Phase::Link => "link",
+        }
+    }
+}
+
+/// The time spent in a phase.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub total: Duration,
+    /// The times it ran, like once for each compile unit.
+    pub count: usize,
+}
+
+/// The time spent in each phase.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub phases: BTreeMap<Phase, PhaseTiming>,
+}
+
+impl Timings {
+    pub fn add(&mut self, phase: Phase, time: Duration) {
+        let timing = self.phases.entry(phase).or_default();
+        timing.total += time;
+        timing.count += 1;
+    }
+
+    /// The time spent in all the phases. As the compile units are built in parallel, it's usually
+    /// more than the time the build took.
+    pub fn total(&self) -> Duration {
+        self.phases.values().map(|x| x.total).sum()
+    }
+
+    /// Prints the timings, or writes them to the file of the format in the current directory.
+    pub fn report(&self, format: TimingsFormat) -> Result<()> {
+        let contents = match format {
+            TimingsFormat::Table => {
+                eprint!("\n{self}");
+                return Ok(());
+            }
+            TimingsFormat::Json => serde_json::to_string_pretty(&self.to_json())?,
+            TimingsFormat::Html => self.to_html(),
+        };
+
+        let path = std::env::current_dir()?.join(format.file_name().unwrap_or_default());
+        std::fs::write(&path, contents)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        eprintln!(
+            "{}: the timings were written to {}",
+            "note".bold(),
+            path.display()
+        );
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let phases: Vec<_> = self
+            .phases
+            .iter()
+            .map(|(phase, timing)| {
+                serde_json::json!({
+                    "phase": phase,
+                    "seconds": timing.total.as_secs_f64(),
+                    "count": timing.count,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "phases": phases,
+            "total_seconds": self.total().as_secs_f64(),
+        })
+    }
+
+    pub fn to_html(&self) -> String {
+        let total = self.total().as_secs_f64().max(f64::EPSILON);
+        let mut rows = String::new();
+
+        for (phase, timing) in &self.phases {
+            let seconds = timing.total.as_secs_f64();
+            rows.push_str(&format!(
+                "<tr><td>{}</td><td>{:.3}s</td><td>{}</td>\
+                 <td><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>\n",
+                phase.name(),
+                seconds,
+                timing.count,
+                seconds / total * 100.0
+            ));
+        }
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Concrete build timings</title>\n<style>\n\
+             body {{ font-family: sans-serif; }}\n\
+             td {{ padding: 2px 12px; }}\n\
+             td:last-child {{ width: 400px; }}\n\
+             .bar {{ height: 12px; background: #4c8bf5; }}\n\
+             </style>\n</head>\n<body>\n<h1>Build timings</h1>\n<table>\n\
+             <tr><th>Phase</th><th>Time</th><th>Runs</th><th></th></tr>\n\
+             {rows}<tr><th>Total</th><th>{:.3}s</th><th></th><th></th></tr>\n\
+             </table>\n</body>\n</html>\n",
+            self.total().as_secs_f64()
+        )
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().as_secs_f64().max(f64::EPSILON);
+
+        writeln!(
+            f,
+            "{:<12}  {:>9}  {:>6}  {:>5}",
+            "Phase", "Time", "Share", "Runs"
+        )?;
+        for (phase, timing) in &self.phases {
+            let seconds = timing.total.as_secs_f64();
+            writeln!(
+                f,
+                "{:<12}  {:>8.3}s  {:>5.1}%  {:>5}",
+                phase.name(),
+                seconds,
+                seconds / total * 100.0,
+                timing.count
+            )?;
+        }
+        writeln!(f, "{:<12}  {:>8.3}s", "total", self.total().as_secs_f64())
+    }
+}
+
+/// Starts collecting the timings.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stops collecting and returns the timings, if they were enabled.
+pub fn finish() -> Option<Timings> {
+    if !ENABLED.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+
+    let mut timings = TIMINGS.lock().unwrap_or_else(|x| x.into_inner());
+    Some(std::mem::take(&mut *timings))
+}
+
+/// Adds the time spent until the returned guard is dropped to the given phase.
+pub fn phase(phase: Phase) -> PhaseGuard {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return PhaseGuard { start: None };
+    }
+
+    PhaseGuard {
+        start: Some((phase, Instant::now())),
+    }
+}
+
+/// A phase being timed, see [`phase`].
+#[must_use = "the phase ends when the guard is dropped"]
+pub struct PhaseGuard {
+    start: Option<(Phase, Instant)>,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        let Some((phase, start)) = self.start.take() else {
+            return;
+        };
+
+        TIMINGS
+            .lock()
+            .unwrap_or_else(|x| x.into_inner())
+            .add(phase, start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_report() {
+        let mut timings = Timings::default();
+        timings.add(Phase::Codegen, Duration::from_millis(300));
+        timings.add(Phase::Codegen, Duration::from_millis(100));
+        timings.add(Phase::Parse, Duration::from_millis(100));
+
+        assert_eq!(timings.total(), Duration::from_millis(500));
+        assert_eq!(timings.phases[&Phase::Codegen].count, 2);
+
+        // The phases are listed in the order they run.
+        let table = timings.to_string();
+        assert!(table.find("parse").unwrap() < table.find("codegen").unwrap());
+        assert!(table.contains("80.0%"), "{table}");
+
+        let json = timings.to_json();
+        assert_eq!(json["phases"][1]["phase"], "codegen");
+        assert_eq!(json["phases"][1]["count"], 2);
+        assert_eq!(json["total_seconds"], 0.5);
+
+        assert!(timings.to_html().contains("<td>parse</td>"));
+    }
+}
@@ -0,0 +1,261 @@
+//! UI tests: compile `.con` files annotated with the diagnostics they are
+//! expected to produce and compare them against the ones the compiler emits.
+//!
+//! An annotation is a comment of the form `//~ ERROR <code>`, which expects a
+//! diagnostic with the given code whose primary span starts on the same line,
+//! or `//~ WARNING <lint>` for warnings reported at the default lint levels.
+//! Each `^` after the `~` moves the expected line one up, so
+//! `//~^ ERROR NotMutable` refers to the line right above the comment.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use owo_colors::OwoColorize;
+
+use crate::check::{
+    diagnostics::{Diagnostic, Severity},
+    lints::{LintLevel, LintLevels, warning_to_diagnostic},
+    lowering_error_to_diagnostics,
+};
+use crate::ir::lowering::lower_compile_units;
+use crate::parser::ProgramSource;
+
+use super::sources::SourceExtensions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+}
+
+impl DiagnosticLevel {
+    fn from_annotation(word: &str) -> Option<Self> {
+        match word {
+            "ERROR" => Some(DiagnosticLevel::Error),
+            "WARNING" => Some(DiagnosticLevel::Warning),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DiagnosticLevel::Error => "error",
+            DiagnosticLevel::Warning => "warning",
+        }
+    }
+}
+
+/// A diagnostic, identified by its level, code and the line (1-based) it points at.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExpectedDiagnostic {
+    pub line: usize,
+    pub level: DiagnosticLevel,
+    pub code: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UiTestResult {
+    pub path: PathBuf,
+    /// Diagnostics annotated in the file but not emitted.
+    pub missing: Vec<ExpectedDiagnostic>,
+    /// Diagnostics emitted but not annotated in the file.
+    pub unexpected: Vec<ExpectedDiagnostic>,
+}
+
+impl UiTestResult {
+    pub fn passed(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// Parses the `//~` annotations of the given source.
+pub fn parse_annotations(source: &str) -> Vec<ExpectedDiagnostic> {
+    let mut expected = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let Some((_, annotation)) = line.split_once("//~") else {
+            continue;
+        };
+
+        let up = annotation.chars().take_while(|c| *c == '^').count();
+        let mut words = annotation[up..].split_whitespace();
+
+        let Some(level) = words.next().and_then(DiagnosticLevel::from_annotation) else {
+            continue;
+        };
+
+        if let Some(code) = words.next() {
+            expected.push(ExpectedDiagnostic {
+                line: (i + 1).saturating_sub(up),
+                level,
+                code: code.to_string(),
+            });
+        }
+    }
+
+    expected
+}
+
+/// Parses and lowers the given file, returning the diagnostics it produces.
+///
+/// Only diagnostics pointing into the file itself are returned.
+pub fn emitted_diagnostics(path: &Path) -> Result<Vec<ExpectedDiagnostic>> {
+    let input = match crate::parser::read_source(path) {
+        Ok(x) => x,
+        Err(error) => match error.to_diagnostic() {
+            Some((diagnostic, lossy)) => {
+                return Ok(vec![ExpectedDiagnostic {
+                    line: lossy[..diagnostic.span.from].matches('\n').count() + 1,
+                    level: DiagnosticLevel::Error,
+                    code: diagnostic.code,
+                }]);
+            }
+            None => return Err(error.into()),
+        },
+    };
+    let source = ProgramSource::new(input, path);
+
+    let line_of = |offset: usize| {
+        source.input[..offset.min(source.input.len())]
+            .matches('\n')
+            .count()
+            + 1
+    };
+
+    let diagnostics: Vec<Diagnostic> = match crate::parser::parse_ast(&source) {
+        Ok(compile_unit) => match lower_compile_units(&[compile_unit]) {
+            Ok(ir) => {
+                let levels = LintLevels::default();
+
+                ir.warnings
+                    .iter()
+                    .filter(|x| levels.warning_level(x) != LintLevel::Allow)
+                    .map(|x| warning_to_diagnostic(x, &levels))
+                    .collect()
+            }
+            Err(error) => lowering_error_to_diagnostics(error),
+        },
+        Err(error) => vec![error.to_diagnostic(&source)],
+    };
+
+    Ok(diagnostics
+        .into_iter()
+        .filter(|x| x.path == path)
+        .map(|x| ExpectedDiagnostic {
+            line: line_of(x.span.from),
+            level: match x.severity {
+                Severity::Error => DiagnosticLevel::Error,
+                Severity::Warning => DiagnosticLevel::Warning,
+            },
+            code: x.code,
+        })
+        .collect())
+}
+
+/// Runs a single UI test file.
+pub fn run_ui_test(path: &Path) -> Result<UiTestResult> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    let mut missing = parse_annotations(&source);
+    let mut unexpected = Vec::new();
+
+    for diagnostic in emitted_diagnostics(path)? {
+        if let Some(pos) = missing.iter().position(|x| *x == diagnostic) {
+            missing.remove(pos);
+        } else {
+            unexpected.push(diagnostic);
+        }
+    }
+
+    Ok(UiTestResult {
+        path: path.to_path_buf(),
+        missing,
+        unexpected,
+    })
+}
+
+/// Runs all the `.con` files found in the given directory, recursively, in path order.
+pub fn run_ui_tests(dir: &Path) -> Result<Vec<UiTestResult>> {
+    let mut files = Vec::new();
+    collect_ui_files(dir, &mut files)?;
+    files.sort();
+
+    files.iter().map(|file| run_ui_test(file)).collect()
+}
+
+fn collect_ui_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_ui_files(&path, files)?;
+        } else if SourceExtensions::default().is_source(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles `concrete test --ui <dir>`.
+pub fn handle_ui_tests(dir: &Path) -> Result<()> {
+    let results = run_ui_tests(dir)?;
+
+    println!("Running {} ui tests", results.len());
+
+    let mut passed = 0;
+
+    for result in &results {
+        print!("test {} ... ", result.path.display());
+
+        if result.passed() {
+            passed += 1;
+            println!("{}", "ok".green());
+            continue;
+        }
+
+        println!("{}", "err".red());
+
+        for diagnostic in &result.missing {
+            eprintln!(
+                "  expected {} {} at line {} was not emitted",
+                diagnostic.level.name(),
+                diagnostic.code,
+                diagnostic.line
+            );
+        }
+
+        for diagnostic in &result.unexpected {
+            eprintln!(
+                "  unexpected {} {} at line {}",
+                diagnostic.level.name(),
+                diagnostic.code,
+                diagnostic.line
+            );
+        }
+    }
+
+    println!();
+    if !results.is_empty() {
+        println!(
+            "test result: {}. {} passed; {} failed; ({:.2}%)",
+            if passed == results.len() {
+                "ok".green().to_string()
+            } else {
+                "err".red().to_string()
+            },
+            passed,
+            results.len() - passed,
+            ((passed as f64 / results.len() as f64) * 100.0).bold()
+        );
+    }
+
+    if passed != results.len() {
+        bail!("{} ui tests failed", results.len() - passed);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+//! `--watch`: builds again whenever a source file or a `Concrete.toml` changes, and with
+//! `concrete run`, restarts the program with the new build.
+//!
+//! The files are polled instead of watched with the notifications of the operating system, which
+//! is portable and cheap enough for the size of a project. The build only starts once the files
+//! stop changing for a moment, so saving several files, or an editor writing a file in steps,
+//! builds once.
+
+use std::{
+    collections::BTreeMap,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+use owo_colors::OwoColorize;
+
+use super::{
+    BuildArgs, CompileError, config::Config, find_config_path, handle_build, handle_build_one,
+    run_env, sources::SourceExtensions, workspace::Workspace,
+};
+
+/// How often the files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the files must stay unchanged before building.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// The directories skipped when looking for files to watch: the outputs of the builds and the
+/// checkouts of the git dependencies.
+const IGNORED_DIRS: &[&str] = &["build", "target", ".bricks"];
+
+/// What is done with each build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    Build,
+    Check,
+    /// Build and run the program, restarting it after each build.
+    Run,
+}
+
+/// The modification times of the watched files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Snapshot {
+    /// The sources and `Concrete.toml` files within the directory, recursively.
+    pub fn take(dir: &Path, extensions: &SourceExtensions) -> Self {
+        let mut snapshot = Self::default();
+        snapshot.add_dir(dir, extensions);
+        snapshot
+    }
+
+    fn add_dir(&mut self, dir: &Path, extensions: &SourceExtensions) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+
+            if file_type.is_dir() {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if !name.starts_with('.') && !IGNORED_DIRS.contains(&name.as_ref()) {
+                    self.add_dir(&path, extensions);
+                }
+            } else if extensions.is_source(&path)
+                || path.file_name().is_some_and(|x| x == "Concrete.toml")
+            {
+                let modified = entry.metadata().and_then(|x| x.modified()).ok();
+                self.files.insert(path, modified);
+            }
+        }
+    }
+
+    /// The modification times of the files, none for the ones whose time can't be read.
+    pub fn modified(&self) -> impl Iterator<Item = Option<SystemTime>> + '_ {
+        self.files.values().copied()
+    }
+
+    /// The files added, removed or modified since the other snapshot.
+    pub fn changes<'a>(&'a self, other: &'a Snapshot) -> Vec<&'a Path> {
+        let modified = self
+            .files
+            .iter()
+            .filter(|(path, time)| other.files.get(*path) != Some(time));
+        let removed = other
+            .files
+            .iter()
+            .filter(|(path, _)| !self.files.contains_key(*path));
+
+        modified
+            .chain(removed)
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+}
+
+/// Builds, and runs the program if asked for, every time the watched files change, until the
+/// compiler is interrupted.
+pub fn watch(args: BuildArgs, run_args: &[String], mode: WatchMode) -> Result<()> {
+    if args.jit {
+        bail!("`--watch` restarts the program after each build, it can't run with `--jit`");
+    }
+
+    let (dir, extensions) = watched_dir(&args)?;
+    let mut snapshot = Snapshot::take(&dir, &extensions);
+    let mut program: Option<Child> = None;
+
+    loop {
+        if std::io::stderr().is_terminal() {
+            // The errors of the last build are replaced by the ones of this one.
+            eprint!("\x1b[2J\x1b[H");
+        }
+
+        if let Some(mut child) = program.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        match build(&args, mode) {
+            Ok(Some(output)) => {
+                println!();
+                let mut command = Command::new(&output);
+                command.args(run_args).envs(run_env(&args)?);
+                match command.spawn() {
+                    Ok(child) => program = Some(child),
+                    Err(error) => eprintln!(
+                        "{}: failed to run {}: {error}",
+                        "error".red().bold(),
+                        output.display()
+                    ),
+                }
+            }
+            Ok(None) => {}
+            Err(error) => match error.downcast::<CompileError>() {
+                Ok(error) => error.emit(&args.diagnostics)?,
+                Err(error) => eprintln!("{}: {error:#}", "error".red().bold()),
+            },
+        }
+
+        eprintln!(
+            "\n   {} for changes in {}, press Ctrl-C to stop",
+            "Watching".cyan().bold(),
+            dir.display()
+        );
+
+        snapshot = wait_for_changes(&dir, &extensions, &snapshot);
+    }
+}
+
+/// Runs the build of the mode, returning the program to run.
+fn build(args: &BuildArgs, mode: WatchMode) -> Result<Option<PathBuf>> {
+    let mut args = args.clone();
+    args.watch = false;
+
+    match mode {
+        WatchMode::Build => {
+            handle_build(args)?;
+            Ok(None)
+        }
+        WatchMode::Check => {
+            args.no_codegen = true;
+            handle_build(args)?;
+            Ok(None)
+        }
+        WatchMode::Run => Ok(Some(handle_build_one(args)?.0)),
+    }
+}
+
+/// Waits until the files change and stop changing, returning their new snapshot.
+fn wait_for_changes(dir: &Path, extensions: &SourceExtensions, snapshot: &Snapshot) -> Snapshot {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let mut current = Snapshot::take(dir, extensions);
+        if current.changes(snapshot).is_empty() {
+            continue;
+        }
+
+        loop {
+            thread::sleep(DEBOUNCE);
+            let later = Snapshot::take(dir, extensions);
+            if later.changes(&current).is_empty() {
+                return later;
+            }
+            current = later;
+        }
+    }
+}
+
+/// The directory whose files are watched, with the extensions of its sources: the directory of
+/// the file built, or the project, or the root of its workspace.
+fn watched_dir(args: &BuildArgs) -> Result<(PathBuf, SourceExtensions)> {
+    if let Some(path) = &args.path {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => std::env::current_dir()?,
+        };
+        let extension = path.extension().map(|x| x.to_string_lossy().into_owned());
+        return Ok((dir, SourceExtensions::with_aliases(extension.as_slice())));
+    }
+
+    let config_path = find_config_path()?;
+    let project_dir = config_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let dir = match Workspace::find(&project_dir)? {
+        Some(workspace) => workspace.root,
+        None => project_dir,
+    };
+    let aliases = Config::read(&config_path)?
+        .package()
+        .map(|x| x.extension_aliases.clone())
+        .unwrap_or_default();
+
+    Ok((dir, SourceExtensions::with_aliases(&aliases)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_snapshot_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let extensions = SourceExtensions::default();
+        std::fs::create_dir(dir.path().join("build")).unwrap();
+        std::fs::write(dir.path().join("main.con"), "mod main {}").unwrap();
+        std::fs::write(dir.path().join("Concrete.toml"), "").unwrap();
+
+        let before = Snapshot::take(dir.path(), &extensions);
+        assert!(before.changes(&before).is_empty());
+
+        // Outputs and files that aren't sources don't trigger a build.
+        std::fs::write(dir.path().join("build").join("lib.con"), "").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        assert!(
+            Snapshot::take(dir.path(), &extensions)
+                .changes(&before)
+                .is_empty()
+        );
+
+        let added = dir.path().join("lib.con");
+        std::fs::write(&added, "mod lib {}").unwrap();
+        let after = Snapshot::take(dir.path(), &extensions);
+        assert_eq!(after.changes(&before), vec![added.as_path()]);
+
+        std::fs::remove_file(&added).unwrap();
+        let removed = Snapshot::take(dir.path(), &extensions);
+        assert_eq!(removed.changes(&after), vec![added.as_path()]);
+    }
+}
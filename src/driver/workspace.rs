@@ -0,0 +1,198 @@
+//! Workspaces: the `Concrete.toml` at the root of a repository can list the directories of its
+//! packages in a `[workspace]` table, they are built together into the `build` directory of the
+//! root, share the dependencies checked out in its `.bricks` directory, and use its profiles.
+//!
+//! ```toml
+//! [workspace]
+//! members = ["core", "cli"]
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use super::config::{Config, Profile, resolve_profile};
+use crate::check::suggestions::unknown_value_message;
+
+#[derive(Debug)]
+pub struct Workspace {
+    /// The directory of the root config file.
+    pub root: PathBuf,
+    pub config: Config,
+    /// The directories of the packages, starting with the root if it's a package too.
+    pub packages: Vec<PathBuf>,
+}
+
+impl Workspace {
+    /// Finds the workspace the package in the given directory is a member or the root of.
+    pub fn find(package_dir: &Path) -> Result<Option<Self>> {
+        let package_dir = canonicalize(package_dir)?;
+
+        for dir in package_dir.ancestors() {
+            let config_path = dir.join("Concrete.toml");
+            if !config_path.exists() {
+                continue;
+            }
+
+            let config = Config::read(&config_path)?;
+            if config.workspace.is_none() {
+                continue;
+            }
+
+            let workspace = Self::load(dir, config)?;
+
+            // The closest workspace is the only one the package can be in, like the packages
+            // checked out in its `.bricks` that aren't members.
+            let is_member =
+                workspace.root == package_dir || workspace.packages.contains(&package_dir);
+            return Ok(is_member.then_some(workspace));
+        }
+
+        Ok(None)
+    }
+
+    fn load(root: &Path, config: Config) -> Result<Self> {
+        let mut packages = Vec::new();
+
+        if config.package.is_some() {
+            packages.push(root.to_path_buf());
+        }
+
+        for member in config.workspace.iter().flat_map(|x| &x.members) {
+            let dir = root.join(member);
+
+            if !dir.join("Concrete.toml").exists() {
+                bail!(
+                    "the workspace member `{}` has no Concrete.toml",
+                    member.display()
+                );
+            }
+
+            packages.push(canonicalize(&dir)?);
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            config,
+            packages,
+        })
+    }
+
+    /// The packages to build, given the directory of the package the command runs in and the
+    /// name of the package selected with `--package`. All the packages are built from the root.
+    pub fn select(&self, package_dir: &Path, name: Option<&str>) -> Result<Vec<PathBuf>> {
+        if let Some(name) = name {
+            let mut names = Vec::new();
+
+            for dir in &self.packages {
+                let config = Config::read(&dir.join("Concrete.toml"))?;
+                let package = config.package()?;
+
+                if package.name == name {
+                    return Ok(vec![dir.clone()]);
+                }
+
+                names.push(package.name.clone());
+            }
+
+            bail!(
+                "{}",
+                unknown_value_message("package", name, names.iter().map(String::as_str))
+            );
+        }
+
+        let package_dir = canonicalize(package_dir)?;
+
+        if package_dir == self.root {
+            Ok(self.packages.clone())
+        } else {
+            Ok(vec![package_dir])
+        }
+    }
+
+    /// Gets the profile with the given name, the profiles of the root taking precedence over the
+    /// ones of the package, also for the ones it inherits from.
+    pub fn get_profile(&self, package: &Config, name: &str) -> Result<Profile> {
+        resolve_profile(
+            name,
+            |x| {
+                self.config
+                    .profile
+                    .get(x)
+                    .or_else(|| package.profile.get(x))
+            },
+            self.config.profile.keys().chain(package.profile.keys()),
+        )
+    }
+}
+
+/// The directory where the dependencies of the package are checked out and the outputs written:
+/// the root of its workspace, or the package itself.
+pub fn root_dir(package_dir: &Path) -> Result<PathBuf> {
+    Ok(match Workspace::find(package_dir)? {
+        Some(workspace) => workspace.root,
+        None => package_dir.to_path_buf(),
+    })
+}
+
+fn canonicalize(dir: &Path) -> Result<PathBuf> {
+    dir.canonicalize()
+        .with_context(|| format!("failed to find {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workspace_selects_its_members() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(
+            root.join("Concrete.toml"),
+            "[workspace]\nmembers = [\"core\", \"cli\"]\n\n[profile.release]\nrelease = true\nopt_level = 2\ndebug_info = false\n",
+        )
+        .unwrap();
+
+        for name in ["core", "cli", "other"] {
+            std::fs::create_dir(root.join(name)).unwrap();
+            std::fs::write(
+                root.join(name).join("Concrete.toml"),
+                format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n"),
+            )
+            .unwrap();
+        }
+
+        let workspace = Workspace::find(&root.join("core"))
+            .unwrap()
+            .expect("core is a member");
+        assert_eq!(workspace.root, root);
+        assert_eq!(
+            workspace.select(&root, None).unwrap(),
+            vec![root.join("core"), root.join("cli")]
+        );
+        assert_eq!(
+            workspace.select(&root.join("cli"), None).unwrap(),
+            vec![root.join("cli")]
+        );
+        assert_eq!(
+            workspace.select(&root, Some("cli")).unwrap(),
+            vec![root.join("cli")]
+        );
+        assert!(
+            workspace
+                .select(&root, Some("clu"))
+                .unwrap_err()
+                .to_string()
+                .starts_with("unknown package `clu`, did you mean `cli`?")
+        );
+
+        let core = Config::read(&root.join("core").join("Concrete.toml")).unwrap();
+        assert_eq!(
+            workspace.get_profile(&core, "release").unwrap().opt_level,
+            2
+        );
+
+        assert!(Workspace::find(&root.join("other")).unwrap().is_none());
+    }
+}
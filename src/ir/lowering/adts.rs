@@ -9,11 +9,15 @@ use crate::{
 
 use super::{
     IRBuilder, Symbol,
+    constants::{evaluate_discriminant, lower_constant_expression},
     errors::LoweringError,
     ir::{AdtBody, AdtIndex, VariantDef},
     types::lower_type,
 };
 
+/// The attributes a field can have.
+const FIELD_ATTRIBUTES: &[&str] = &["cfg"];
+
 /// Lowers a struct.
 ///
 /// If the struct is generic, `builder.context.generics_mapping` should contain the generic specific types already.
@@ -87,6 +91,7 @@ pub(crate) fn lower_struct(
         kind: AdtKind::Struct,
         span: info.span,
         generics_used,
+        lang_item: None,
     };
 
     let mut struct_variant = VariantDef {
@@ -98,10 +103,17 @@ pub(crate) fn lower_struct(
     };
 
     for field in info.fields.iter() {
+        builder.warn_unknown_attributes(&field.attributes, FIELD_ATTRIBUTES);
+        let ty = lower_type(builder, &field.r#type)?;
+        let default = match &field.default {
+            Some(value) => Some(lower_constant_expression(builder, value, ty)?),
+            None => None,
+        };
         let variant = FieldDef {
             name: field.name.name.clone(),
             is_pub: field.is_pub,
-            ty: lower_type(builder, &field.r#type)?,
+            ty,
+            default,
         };
         struct_variant.fields.push(variant);
         struct_variant
@@ -195,18 +207,30 @@ pub(crate) fn lower_enum(
         kind: AdtKind::Enum,
         span: info.span,
         generics_used,
+        lang_item: builder.lang_items.get(&poly_idx).copied(),
     };
 
+    // The variants without a discriminant take the one after the previous variant.
+    let mut last_explicit = 0;
     for (i, variant) in info.variants.iter().enumerate() {
+        let discriminant = match &variant.discriminant {
+            Some(value) => {
+                last_explicit = i;
+                crate::ir::VariantDiscr::Explicit(evaluate_discriminant(builder, value)?)
+            }
+            None => crate::ir::VariantDiscr::Relative((i - last_explicit) as u32),
+        };
+
         let mut vardef = VariantDef {
             name: variant.name.name.clone(),
             field_names: HashMap::default(),
             fields: Vec::new(),
-            discriminant: crate::ir::VariantDiscr::Relative(i as u32),
+            discriminant,
             span: variant.span,
         };
 
         for (field_idx, field) in variant.fields.iter().enumerate() {
+            builder.warn_unknown_attributes(&field.attributes, FIELD_ATTRIBUTES);
             vardef
                 .field_names
                 .insert(field.name.name.clone(), field_idx);
@@ -214,10 +238,30 @@ pub(crate) fn lower_enum(
                 name: field.name.name.clone(),
                 is_pub: field.is_pub,
                 ty: lower_type(builder, &field.r#type)?,
+                default: None,
             });
         }
         body.variant_names.insert(variant.name.name.clone(), i);
         body.variants.push(vardef);
+
+        let Some(value) = body.checked_discriminant(i) else {
+            return Err(LoweringError::DiscriminantOverflow {
+                span: variant.span,
+                name: variant.name.name.clone(),
+                previous: body.variants[last_explicit].name.clone(),
+                path: builder.get_current_module().file_path.clone(),
+            });
+        };
+        if let Some(first) = (0..i).find(|x| body.discriminant(*x) == value) {
+            return Err(LoweringError::DuplicateDiscriminant {
+                span: variant.span,
+                first_span: body.variants[first].span,
+                value,
+                first: body.variants[first].name.clone(),
+                second: variant.name.name.clone(),
+                path: builder.get_current_module().file_path.clone(),
+            });
+        }
     }
 
     builder.ir.aggregates[idx] = Some(body);
@@ -0,0 +1,310 @@
+//! Conditional compilation: the items, parameters and fields whose `#[cfg(...)]` predicate
+//! doesn't hold for the configuration of their compile unit are removed before lowering, so the
+//! rest of it never sees them.
+
+use std::{path::Path, sync::Arc};
+
+use crate::{
+    ast::{
+        CompilationUnit,
+        cfg::Cfg,
+        common::{Attribute, AttributeArg},
+        functions::{FunctionDecl, FunctionDef},
+        modules::{Module, ModuleDefItem},
+        structs::Field,
+    },
+    check::suggestions::unknown_value_message,
+};
+
+use super::errors::LoweringError;
+
+/// The options a `name = "value"` predicate can check.
+const CFG_OPTIONS: &[&str] = &[
+    "feature",
+    "profile",
+    "target_arch",
+    "target_family",
+    "target_os",
+];
+
+/// The options a predicate can check by name alone.
+const CFG_WORDS: &[&str] = &["test", "debug_assertions"];
+
+/// Whether the cfg option is one the compiler sets, which build scripts can't.
+pub fn is_builtin_cfg(name: &str) -> bool {
+    CFG_OPTIONS.contains(&name) || CFG_WORDS.contains(&name)
+}
+
+/// The compile unit without the items disabled by its configuration.
+pub(crate) fn configure_unit(unit: &CompilationUnit) -> Result<CompilationUnit, LoweringError> {
+    let mut modules = Vec::with_capacity(unit.modules.len());
+    for module in &unit.modules {
+        if let Some(module) = configure_module(module, &unit.cfg)? {
+            modules.push(module);
+        }
+    }
+
+    Ok(CompilationUnit {
+        modules,
+        module_aliases: unit.module_aliases.clone(),
+        cfg: unit.cfg.clone(),
+        is_dependency: unit.is_dependency,
+        is_prebuilt: unit.is_prebuilt,
+    })
+}
+
+/// The module without its disabled items, `None` if it's disabled itself.
+fn configure_module(module: &Module, cfg: &Cfg) -> Result<Option<Module>, LoweringError> {
+    let path = module.file_path.as_path();
+    if !is_enabled(&module.attributes, cfg, path)? {
+        return Ok(None);
+    }
+
+    let mut contents = Vec::with_capacity(module.contents.len());
+    for item in &module.contents {
+        let attributes: &[Attribute] = match item {
+            ModuleDefItem::Constant(x) => &x.decl.attributes,
+            ModuleDefItem::Function(x) => &x.decl.attributes,
+            ModuleDefItem::FunctionDecl(x) => &x.attributes,
+            ModuleDefItem::ExternBlock(x) => &x.attributes,
+            ModuleDefItem::Impl(x) => &x.attributes,
+            ModuleDefItem::ImplTrait(x) => &x.attributes,
+            ModuleDefItem::Struct(x) => &x.attributes,
+            ModuleDefItem::Union(x) => &x.attributes,
+            ModuleDefItem::Enum(x) => &x.attributes,
+            ModuleDefItem::Type(x) => &x.attributes,
+            ModuleDefItem::Trait(x) => &x.attributes,
+            ModuleDefItem::Import(x) => &x.attributes,
+            ModuleDefItem::Macro(x) => &x.attributes,
+            ModuleDefItem::Module(_) | ModuleDefItem::ExternalModule(_) => &[],
+        };
+        if !is_enabled(attributes, cfg, path)? {
+            continue;
+        }
+
+        // The methods and extern functions can be disabled one by one too, and the parameters and
+        // fields of every item.
+        let item = match item {
+            ModuleDefItem::Module(x) => match configure_module(x, cfg)? {
+                Some(x) => ModuleDefItem::Module(Arc::new(x)),
+                None => continue,
+            },
+            ModuleDefItem::Function(x) => {
+                ModuleDefItem::Function(configure_function(x, cfg, path)?)
+            }
+            ModuleDefItem::FunctionDecl(x) => {
+                ModuleDefItem::FunctionDecl(configure_function_decl(x, cfg, path)?)
+            }
+            ModuleDefItem::Impl(x) => {
+                let mut block = x.as_ref().clone();
+                block.methods = enabled(&x.methods, |x| &x.decl.attributes, cfg, path)?
+                    .iter()
+                    .map(|x| configure_function(x, cfg, path))
+                    .collect::<Result<_, _>>()?;
+                ModuleDefItem::Impl(Arc::new(block))
+            }
+            ModuleDefItem::ImplTrait(x) => {
+                let mut block = x.as_ref().clone();
+                block.methods = enabled(&x.methods, |x| &x.decl.attributes, cfg, path)?
+                    .iter()
+                    .map(|x| configure_function(x, cfg, path))
+                    .collect::<Result<_, _>>()?;
+                ModuleDefItem::ImplTrait(Arc::new(block))
+            }
+            ModuleDefItem::Trait(x) => {
+                let mut decl = x.as_ref().clone();
+                decl.methods = enabled(&x.methods, |x| &x.attributes, cfg, path)?
+                    .iter()
+                    .map(|x| configure_function_decl(x, cfg, path))
+                    .collect::<Result<_, _>>()?;
+                ModuleDefItem::Trait(Arc::new(decl))
+            }
+            ModuleDefItem::ExternBlock(x) => {
+                let mut block = x.as_ref().clone();
+                block.functions = enabled(&x.functions, |x| &x.attributes, cfg, path)?
+                    .iter()
+                    .map(|x| configure_function_decl(x, cfg, path))
+                    .collect::<Result<_, _>>()?;
+                ModuleDefItem::ExternBlock(Arc::new(block))
+            }
+            ModuleDefItem::Struct(x) => {
+                let mut decl = x.as_ref().clone();
+                decl.fields = configure_fields(&x.fields, cfg, path)?;
+                ModuleDefItem::Struct(Arc::new(decl))
+            }
+            ModuleDefItem::Union(x) => {
+                let mut decl = x.as_ref().clone();
+                decl.variants = configure_fields(&x.variants, cfg, path)?;
+                ModuleDefItem::Union(Arc::new(decl))
+            }
+            ModuleDefItem::Enum(x) => {
+                let mut decl = x.as_ref().clone();
+                for variant in &mut decl.variants {
+                    variant.fields = configure_fields(&variant.fields, cfg, path)?;
+                }
+                ModuleDefItem::Enum(Arc::new(decl))
+            }
+            item => item.clone(),
+        };
+        contents.push(item);
+    }
+
+    Ok(Some(Module {
+        contents,
+        ..module.clone()
+    }))
+}
+
+fn enabled<T>(
+    items: &[Arc<T>],
+    attributes: impl Fn(&T) -> &Vec<Attribute>,
+    cfg: &Cfg,
+    path: &Path,
+) -> Result<Vec<Arc<T>>, LoweringError> {
+    let mut enabled = Vec::with_capacity(items.len());
+    for item in items {
+        if is_enabled(attributes(item), cfg, path)? {
+            enabled.push(item.clone());
+        }
+    }
+
+    Ok(enabled)
+}
+
+/// The function without its disabled parameters.
+fn configure_function(
+    function: &Arc<FunctionDef>,
+    cfg: &Cfg,
+    path: &Path,
+) -> Result<Arc<FunctionDef>, LoweringError> {
+    let decl = configure_function_decl(&function.decl, cfg, path)?;
+    if Arc::ptr_eq(&decl, &function.decl) {
+        return Ok(function.clone());
+    }
+
+    Ok(Arc::new(FunctionDef {
+        decl,
+        ..function.as_ref().clone()
+    }))
+}
+
+fn configure_function_decl(
+    decl: &Arc<FunctionDecl>,
+    cfg: &Cfg,
+    path: &Path,
+) -> Result<Arc<FunctionDecl>, LoweringError> {
+    if decl.params.iter().all(|x| x.attributes.is_empty()) {
+        return Ok(decl.clone());
+    }
+
+    let mut params = Vec::with_capacity(decl.params.len());
+    for param in &decl.params {
+        if is_enabled(&param.attributes, cfg, path)? {
+            params.push(param.clone());
+        }
+    }
+
+    Ok(Arc::new(FunctionDecl {
+        params,
+        ..decl.as_ref().clone()
+    }))
+}
+
+fn configure_fields(fields: &[Field], cfg: &Cfg, path: &Path) -> Result<Vec<Field>, LoweringError> {
+    let mut enabled = Vec::with_capacity(fields.len());
+    for field in fields {
+        if is_enabled(&field.attributes, cfg, path)? {
+            enabled.push(field.clone());
+        }
+    }
+
+    Ok(enabled)
+}
+
+/// Whether the predicates of all the `#[cfg(...)]` attributes hold.
+fn is_enabled(attributes: &[Attribute], cfg: &Cfg, path: &Path) -> Result<bool, LoweringError> {
+    for attr in attributes.iter().filter(|x| x.name == "cfg") {
+        let [predicate] = attr.args.as_slice() else {
+            return Err(LoweringError::InvalidAttribute {
+                span: attr.span,
+                name: attr.name.clone(),
+                reason: "expected a single predicate, e.g: #[cfg(target_os = \"linux\")]"
+                    .to_string(),
+                path: path.to_path_buf(),
+            });
+        };
+
+        if !evaluate(predicate, cfg, path)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn evaluate(predicate: &AttributeArg, cfg: &Cfg, path: &Path) -> Result<bool, LoweringError> {
+    let invalid = |reason: String| LoweringError::InvalidAttribute {
+        span: predicate.span(),
+        name: "cfg".to_string(),
+        reason,
+        path: path.to_path_buf(),
+    };
+
+    match predicate {
+        AttributeArg::Word(name) => match name.name.as_str() {
+            "test" => Ok(cfg.test),
+            "debug_assertions" => Ok(cfg.debug_assertions),
+            name if CFG_OPTIONS.contains(&name) => Err(invalid(format!(
+                "`{name}` needs a value, e.g: {name} = \"...\""
+            ))),
+            // The options a build script doesn't set don't hold.
+            name if cfg.custom.is_some() => {
+                Ok(cfg.custom.as_ref().is_some_and(|x| x.contains_key(name)))
+            }
+            name => Err(invalid(unknown_value_message(
+                "cfg option",
+                name,
+                CFG_WORDS.iter().chain(CFG_OPTIONS).copied(),
+            ))),
+        },
+        AttributeArg::NameValue { name, value, .. } => match name.name.as_str() {
+            "target_os" => Ok(cfg.target_os == *value),
+            "target_arch" => Ok(cfg.target_arch == *value),
+            "target_family" => Ok(cfg.target_family == *value),
+            "profile" => Ok(cfg.profile == *value),
+            "feature" => Ok(cfg.features.contains(value)),
+            name if cfg.custom.is_some() => Ok(cfg
+                .custom
+                .as_ref()
+                .and_then(|x| x.get(name))
+                .is_some_and(|x| x.contains(value))),
+            name => Err(invalid(unknown_value_message(
+                "cfg option",
+                name,
+                CFG_OPTIONS.iter().copied(),
+            ))),
+        },
+        AttributeArg::List { name, args, .. } => match name.name.as_str() {
+            "not" => match args.as_slice() {
+                [arg] => Ok(!evaluate(arg, cfg, path)?),
+                _ => Err(invalid("`not` expects a single predicate".to_string())),
+            },
+            // All the predicates are evaluated, so the invalid ones are found in any configuration.
+            "all" | "any" => {
+                let values = args
+                    .iter()
+                    .map(|x| evaluate(x, cfg, path))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(if name.name == "all" {
+                    values.iter().all(|x| *x)
+                } else {
+                    values.iter().any(|x| *x)
+                })
+            }
+            name => Err(invalid(format!(
+                "unknown predicate `{name}`, expected `all`, `any` or `not`"
+            ))),
+        },
+    }
+}
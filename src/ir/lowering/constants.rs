@@ -1,20 +1,41 @@
+//! Constants and their evaluation: the values of constants, array sizes and enum discriminants
+//! are computed at compile time from literals, other constants, operators, casts and calls to
+//! `const fn`, whose bodies are run by the evaluator here.
+
+use std::collections::HashMap;
+
 use tracing::{debug, instrument};
 
 use crate::{
     ast::{
+        common::Span,
         constants::ConstantDef,
-        expressions::{Expression, PathOp, ValueExpr},
+        expressions::{
+            ArithOp, BinaryOp, BitwiseOp, CmpOp, Expression, FnCallOp, LogicOp, MatchExpr, PathOp,
+            UnaryOp, ValueExpr,
+        },
+        functions::FunctionDef,
+        statements::{DeferStmt, ForInStmt, ForStmt, LetStmtTarget, Statement, UnsafeBlock},
     },
     ir::{ConstKind, ConstValue, FloatTy, IntTy, Type, UintTy, ValueTree},
 };
 
 use super::{
-    FnIrBuilder, IRBuilder,
-    errors::LoweringError,
-    ir::{ConstBody, ConstData, TypeIndex},
+    FnIrBuilder, IRBuilder, Symbol,
+    errors::{LoweringError, UnexpectedTypeError},
+    expressions::find_expression_span,
+    ir::{ConstBody, ConstData, ConstIndex, TypeIndex},
+    macros::unexpanded,
     types::lower_type,
 };
 
+/// How many statements the `const fn` calls of an evaluation can run, so a loop that never ends
+/// is reported instead of hanging the compiler.
+const MAX_STEPS: usize = 1_000_000;
+
+/// How deep the `const fn` calls can nest, so an endless recursion is reported.
+const MAX_CALL_DEPTH: usize = 256;
+
 pub(crate) fn lower_constant(
     builder: &mut IRBuilder,
     info: &ConstantDef,
@@ -26,107 +47,95 @@ pub(crate) fn lower_constant(
         .get(&info.decl.name.name)
         .expect("should exist");
 
-    if builder.ir.constants[idx].is_some() {
-        return Ok(());
+    lower_constant_idx(builder, idx)?;
+
+    Ok(())
+}
+
+/// The value of the constant, evaluated first if it isn't yet, since a constant can be used
+/// before it's defined.
+pub(crate) fn lower_constant_idx(
+    builder: &mut IRBuilder,
+    idx: ConstIndex,
+) -> Result<ConstData, LoweringError> {
+    if let Some(body) = &builder.ir.constants[idx] {
+        return Ok(body.value.clone());
+    }
+
+    let (info, module_idx) = builder.bodies.constants[&idx].clone();
+
+    if builder.context.constants_stack.contains(&idx) {
+        builder.enter_module_context(module_idx);
+        let error = LoweringError::NotConst {
+            span: info.decl.name.span,
+            reason: format!("the value of `{}` depends on itself", info.decl.name.name),
+            path: builder.get_current_module().file_path.clone(),
+        };
+        builder.leave_module_context();
+        return Err(error);
     }
 
-    let value_ty = lower_type(builder, &info.decl.r#type)?;
-    let value = lower_constant_expression(builder, &info.value, value_ty)?;
+    builder.context.constants_stack.push(idx);
+    builder.enter_module_context(module_idx);
+
+    let value = lower_type(builder, &info.decl.r#type)
+        .and_then(|value_ty| lower_constant_expression(builder, &info.value, value_ty));
 
-    let body = ConstBody {
+    builder.leave_module_context();
+    builder.context.constants_stack.pop();
+
+    let value = value?;
+    builder.ir.constants[idx] = Some(ConstBody {
         name: info.decl.name.name.clone(),
-        value,
+        value: value.clone(),
         span: info.decl.name.span,
-    };
-
-    builder.ir.constants[idx] = Some(body);
+    });
     builder.ir.modules[module_idx].constants.insert(idx);
 
-    Ok(())
+    Ok(value)
 }
 
+/// Evaluates the expression into a value of the given type.
 #[instrument(level = "debug", skip_all)]
 pub(crate) fn lower_constant_expression(
-    builder: &IRBuilder,
+    builder: &mut IRBuilder,
     expression: &Expression,
     type_idx: TypeIndex,
 ) -> Result<ConstData, LoweringError> {
-    let ty = builder.ir.types[type_idx].as_ref().expect("should exist");
     debug!("lowering const expression");
-    let data = match expression {
-        Expression::Value(value, _) => match value {
-            ValueExpr::ConstBool(value, span) => ConstData {
-                data: ConstKind::Value(ValueTree::Leaf(ConstValue::Bool(*value))),
-                ty: type_idx,
-                span: *span,
-            },
-            ValueExpr::ConstChar(value, span) => ConstData {
-                data: ConstKind::Value(ValueTree::Leaf(ConstValue::U32((*value) as u32))),
-                ty: type_idx,
-                span: *span,
-            },
-            ValueExpr::ConstInt(value, span) => ConstData {
-                data: ConstKind::Value(ValueTree::Leaf(match &ty {
-                    Type::Int(ty) => match ty {
-                        IntTy::I8 => {
-                            ConstValue::I8((*value).try_into().expect("value out of range"))
-                        }
-                        IntTy::I16 => {
-                            ConstValue::I16((*value).try_into().expect("value out of range"))
-                        }
-                        IntTy::I32 => {
-                            ConstValue::I32((*value).try_into().expect("value out of range"))
-                        }
-                        IntTy::I64 => {
-                            ConstValue::I64((*value).try_into().expect("value out of range"))
-                        }
-                        IntTy::I128 => {
-                            ConstValue::I128((*value).try_into().expect("value out of range"))
-                        }
-                    },
-                    Type::Uint(ty) => match ty {
-                        UintTy::U8 => {
-                            ConstValue::U8((*value).try_into().expect("value out of range"))
-                        }
-                        UintTy::U16 => {
-                            ConstValue::U16((*value).try_into().expect("value out of range"))
-                        }
-                        UintTy::U32 => {
-                            ConstValue::U32((*value).try_into().expect("value out of range"))
-                        }
-                        UintTy::U64 => {
-                            ConstValue::U64((*value).try_into().expect("value out of range"))
-                        }
-                        UintTy::U128 => ConstValue::U128(*value),
-                    },
-                    Type::Bool => ConstValue::Bool(*value != 0),
-                    x => unreachable!("{:?}", x),
-                })),
-                ty: type_idx,
-                span: *span,
-            },
-            ValueExpr::ConstFloat(value, span) => ConstData {
-                data: ConstKind::Value(ValueTree::Leaf(match &ty {
-                    Type::Float(ty) => match ty {
-                        FloatTy::F32 => {
-                            ConstValue::F32(value.parse().expect("error parsing float"))
-                        }
-                        FloatTy::F64 => {
-                            ConstValue::F64(value.parse().expect("error parsing float"))
-                        }
-                    },
-                    x => unreachable!("{:?}", x),
-                })),
-                ty: type_idx,
-                span: *span,
-            },
-            ValueExpr::ConstStr(_, _) => todo!(),
-            _ => unimplemented!(),
-        },
-        _ => unimplemented!(),
-    };
+    let span = find_expression_span(expression);
+    let mut evaluator = ConstEvaluator::new(builder);
+    let value = evaluator.expression(expression)?;
+    let leaf = evaluator.leaf(value, type_idx, span)?;
+
+    Ok(ConstData {
+        ty: type_idx,
+        span,
+        data: ConstKind::Value(ValueTree::Leaf(leaf)),
+    })
+}
 
-    Ok(data)
+/// The size of an array type given by a constant expression.
+pub(crate) fn evaluate_array_size(
+    builder: &mut IRBuilder,
+    expression: &Expression,
+) -> Result<u64, LoweringError> {
+    let u64_ty = builder.ir.get_u64_ty();
+    let size = lower_constant_expression(builder, expression, u64_ty)?;
+
+    match size.data {
+        ConstKind::Value(ValueTree::Leaf(ConstValue::U64(size))) => Ok(size),
+        _ => unreachable!("should be an u64"),
+    }
+}
+
+/// The discriminant of an enum variant given by a constant expression.
+pub(crate) fn evaluate_discriminant(
+    builder: &mut IRBuilder,
+    expression: &Expression,
+) -> Result<ConstData, LoweringError> {
+    let u32_ty = builder.ir.get_u32_ty();
+    lower_constant_expression(builder, expression, u32_ty)
 }
 
 pub(crate) fn lower_constant_ref(
@@ -142,9 +151,608 @@ pub(crate) fn lower_constant_ref(
         });
     };
 
-    let constant_value = fn_builder.builder.get_constant(constant_id).value.clone();
+    let constant_value = lower_constant_idx(fn_builder.builder, constant_id)?;
 
     let ty = constant_value.ty;
 
     Ok((constant_value, ty))
 }
+
+/// A value computed at compile time. The integers of all the types are kept as an `i128`, checked
+/// to fit their type when they're stored.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+        }
+    }
+}
+
+/// A typed local of a `const fn` call.
+type ConstLocal = (Value, TypeIndex);
+
+struct ConstEvaluator<'a> {
+    builder: &'a mut IRBuilder,
+    /// The locals of the `const fn` calls being evaluated, the innermost last.
+    frames: Vec<HashMap<String, ConstLocal>>,
+    /// The statements run so far.
+    steps: usize,
+}
+
+impl<'a> ConstEvaluator<'a> {
+    fn new(builder: &'a mut IRBuilder) -> Self {
+        Self {
+            builder,
+            frames: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    fn not_const(&self, span: Span, reason: impl Into<String>) -> LoweringError {
+        LoweringError::NotConst {
+            span,
+            reason: reason.into(),
+            path: self.builder.get_current_module().file_path.clone(),
+        }
+    }
+
+    fn unexpected_type(&self, span: Span, found: &str, expected: String) -> LoweringError {
+        LoweringError::UnexpectedType(Box::new(UnexpectedTypeError {
+            found_span: span,
+            found: found.to_string(),
+            expected,
+            expected_span: None,
+            expected_path: None,
+            difference: None,
+            path: self.builder.get_current_module().file_path.clone(),
+        }))
+    }
+
+    /// The value stored as the given type, if it's of that type and fits in it.
+    fn leaf(&self, value: Value, ty: TypeIndex, span: Span) -> Result<ConstValue, LoweringError> {
+        Ok(match (value, self.builder.get_type(ty)) {
+            (Value::Bool(value), Type::Bool) => ConstValue::Bool(value),
+            (Value::Int(value), Type::Char) => ConstValue::U32(self.fit(value, ty, span)?),
+            (Value::Int(value), Type::Int(int_ty)) => match int_ty {
+                IntTy::I8 => ConstValue::I8(self.fit(value, ty, span)?),
+                IntTy::I16 => ConstValue::I16(self.fit(value, ty, span)?),
+                IntTy::I32 => ConstValue::I32(self.fit(value, ty, span)?),
+                IntTy::I64 => ConstValue::I64(self.fit(value, ty, span)?),
+                IntTy::I128 => ConstValue::I128(value),
+            },
+            (Value::Int(value), Type::Uint(uint_ty)) => match uint_ty {
+                UintTy::U8 => ConstValue::U8(self.fit(value, ty, span)?),
+                UintTy::U16 => ConstValue::U16(self.fit(value, ty, span)?),
+                UintTy::U32 => ConstValue::U32(self.fit(value, ty, span)?),
+                UintTy::U64 => ConstValue::U64(self.fit(value, ty, span)?),
+                UintTy::U128 => ConstValue::U128(self.fit(value, ty, span)?),
+            },
+            (Value::Float(value), Type::Float(FloatTy::F32)) => {
+                ConstValue::F32((value as f32).to_string())
+            }
+            (Value::Float(value), Type::Float(FloatTy::F64)) => ConstValue::F64(value.to_string()),
+            (value, _) => {
+                return Err(self.unexpected_type(
+                    span,
+                    value.kind(),
+                    self.builder.display_typename(ty),
+                ));
+            }
+        })
+    }
+
+    fn fit<T: TryFrom<i128>>(
+        &self,
+        value: i128,
+        ty: TypeIndex,
+        span: Span,
+    ) -> Result<T, LoweringError> {
+        T::try_from(value).map_err(|_| {
+            self.not_const(
+                span,
+                format!(
+                    "{value} doesn't fit in {}",
+                    self.builder.display_typename(ty)
+                ),
+            )
+        })
+    }
+
+    /// Checks the value can be stored as the given type.
+    fn check(&self, value: Value, ty: TypeIndex, span: Span) -> Result<Value, LoweringError> {
+        self.leaf(value, ty, span)?;
+        Ok(value)
+    }
+
+    /// The value of an evaluated constant.
+    fn read(&self, data: &ConstData, span: Span) -> Result<Value, LoweringError> {
+        let ConstKind::Value(ValueTree::Leaf(leaf)) = &data.data else {
+            return Err(self.not_const(span, "only constants of scalar types can be used"));
+        };
+
+        Ok(match leaf {
+            ConstValue::Bool(value) => Value::Bool(*value),
+            ConstValue::Char(value) => Value::Int((*value).into()),
+            ConstValue::I8(value) => Value::Int((*value).into()),
+            ConstValue::I16(value) => Value::Int((*value).into()),
+            ConstValue::I32(value) => Value::Int((*value).into()),
+            ConstValue::I64(value) => Value::Int((*value).into()),
+            ConstValue::I128(value) => Value::Int(*value),
+            ConstValue::U8(value) => Value::Int((*value).into()),
+            ConstValue::U16(value) => Value::Int((*value).into()),
+            ConstValue::U32(value) => Value::Int((*value).into()),
+            ConstValue::U64(value) => Value::Int((*value).into()),
+            ConstValue::U128(value) => Value::Int(
+                (*value)
+                    .try_into()
+                    .map_err(|_| self.not_const(span, format!("{value} is too big")))?,
+            ),
+            ConstValue::F32(value) | ConstValue::F64(value) => {
+                Value::Float(value.parse().expect("error parsing float"))
+            }
+            ConstValue::String(_) => {
+                return Err(self.not_const(span, "strings can't be used in constant expressions"));
+            }
+        })
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<Value, LoweringError> {
+        match expr {
+            Expression::Value(value, _) => match value {
+                ValueExpr::ConstBool(value, _) => Ok(Value::Bool(*value)),
+                ValueExpr::ConstChar(value, _) => Ok(Value::Int((*value as u32).into())),
+                ValueExpr::ConstInt(value, span) => (*value)
+                    .try_into()
+                    .map(Value::Int)
+                    .map_err(|_| self.not_const(*span, format!("{value} is too big"))),
+                ValueExpr::ConstFloat(value, _) => {
+                    Ok(Value::Float(value.parse().expect("error parsing float")))
+                }
+                ValueExpr::ConstStr(_, span) => {
+                    Err(self.not_const(*span, "strings can't be used in constant expressions"))
+                }
+                ValueExpr::Path(path) => self.path(path),
+            },
+            Expression::UnaryOp(op, operand) => {
+                let value = self.expression(operand)?;
+                match (op, value) {
+                    (UnaryOp::ArithNeg, Value::Int(value)) => Ok(Value::Int(-value)),
+                    (UnaryOp::ArithNeg, Value::Float(value)) => Ok(Value::Float(-value)),
+                    (UnaryOp::LogicalNot, Value::Bool(value)) => Ok(Value::Bool(!value)),
+                    (UnaryOp::BitwiseNot, Value::Int(value)) => Ok(Value::Int(!value)),
+                    (_, value) => Err(LoweringError::InvalidUnaryOp {
+                        found_span: find_expression_span(operand),
+                        found: value.kind().to_string(),
+                        path: self.builder.get_current_module().file_path.clone(),
+                    }),
+                }
+            }
+            Expression::BinaryOp(lhs, op, rhs) => self.binary_op(lhs, *op, rhs),
+            Expression::Cast(value, ty, span) => {
+                let value = self.expression(value)?;
+                let ty = lower_type(self.builder, ty)?;
+                self.cast(value, ty, *span)
+            }
+            Expression::FnCall(call) => self.fn_call(call),
+            Expression::MacroCall(call) => Err(unexpanded(
+                call,
+                &self.builder.get_current_module().file_path,
+            )),
+            expr => Err(self.not_const(
+                find_expression_span(expr),
+                "only literals, constants, operators, casts and calls to `const fn` can be \
+                 evaluated at compile time",
+            )),
+        }
+    }
+
+    fn path(&mut self, path: &PathOp) -> Result<Value, LoweringError> {
+        if !path.extra.is_empty() {
+            return Err(self.not_const(
+                path.span,
+                "only the names of constants and locals can be used at compile time",
+            ));
+        }
+
+        let local = self
+            .frames
+            .last()
+            .and_then(|frame| frame.get(&path.first.name));
+        if let Some((value, _)) = local {
+            return Ok(*value);
+        }
+
+        let Some(&idx) = self
+            .builder
+            .get_current_symbols()
+            .constants
+            .get(&path.first.name)
+        else {
+            return Err(LoweringError::UseOfUndeclaredVariable {
+                span: path.span,
+                name: path.first.name.clone(),
+                path: self.builder.get_current_module().file_path.clone(),
+            });
+        };
+
+        let data = lower_constant_idx(self.builder, idx)?;
+        self.read(&data, path.span)
+    }
+
+    fn binary_op(
+        &mut self,
+        lhs: &Expression,
+        op: BinaryOp,
+        rhs: &Expression,
+    ) -> Result<Value, LoweringError> {
+        let lhs_value = self.expression(lhs)?;
+
+        // The right side isn't evaluated when the left one decides, like at runtime.
+        match (op, lhs_value) {
+            (BinaryOp::Logic(LogicOp::And), Value::Bool(false)) => return Ok(Value::Bool(false)),
+            (BinaryOp::Logic(LogicOp::Or), Value::Bool(true)) => return Ok(Value::Bool(true)),
+            _ => {}
+        }
+
+        let rhs_value = self.expression(rhs)?;
+        let span = Span::new(find_expression_span(lhs).from, find_expression_span(rhs).to);
+
+        match (lhs_value, rhs_value) {
+            (Value::Int(a), Value::Int(b)) => match op {
+                BinaryOp::Arith(op) => {
+                    let value = match op {
+                        ArithOp::Add => a.checked_add(b),
+                        ArithOp::Sub => a.checked_sub(b),
+                        ArithOp::Mul => a.checked_mul(b),
+                        ArithOp::Div => a.checked_div(b),
+                        ArithOp::Mod => a.checked_rem(b),
+                    };
+                    value.map(Value::Int).ok_or_else(|| {
+                        if b == 0 && matches!(op, ArithOp::Div | ArithOp::Mod) {
+                            self.not_const(span, "division by zero")
+                        } else {
+                            self.not_const(span, "the operation overflows")
+                        }
+                    })
+                }
+                BinaryOp::Compare(op) => Ok(Value::Bool(compare(op, a, b))),
+                BinaryOp::Bitwise(op) => Ok(Value::Int(match op {
+                    BitwiseOp::And => a & b,
+                    BitwiseOp::Or => a | b,
+                    BitwiseOp::Xor => a ^ b,
+                })),
+                BinaryOp::Logic(_) => {
+                    Err(self.unexpected_type(find_expression_span(lhs), "integer", "bool".into()))
+                }
+            },
+            (Value::Float(a), Value::Float(b)) => match op {
+                BinaryOp::Arith(op) => Ok(Value::Float(match op {
+                    ArithOp::Add => a + b,
+                    ArithOp::Sub => a - b,
+                    ArithOp::Mul => a * b,
+                    ArithOp::Div => a / b,
+                    ArithOp::Mod => a % b,
+                })),
+                BinaryOp::Compare(op) => Ok(Value::Bool(compare(op, a, b))),
+                BinaryOp::Logic(_) | BinaryOp::Bitwise(_) => {
+                    Err(self.unexpected_type(find_expression_span(lhs), "float", "bool".into()))
+                }
+            },
+            (Value::Bool(a), Value::Bool(b)) => match op {
+                BinaryOp::Logic(LogicOp::And) | BinaryOp::Bitwise(BitwiseOp::And) => {
+                    Ok(Value::Bool(a & b))
+                }
+                BinaryOp::Logic(LogicOp::Or) | BinaryOp::Bitwise(BitwiseOp::Or) => {
+                    Ok(Value::Bool(a | b))
+                }
+                BinaryOp::Bitwise(BitwiseOp::Xor) => Ok(Value::Bool(a ^ b)),
+                BinaryOp::Compare(op @ (CmpOp::Eq | CmpOp::NotEq)) => {
+                    Ok(Value::Bool(compare(op, a, b)))
+                }
+                BinaryOp::Compare(_) | BinaryOp::Arith(_) => {
+                    Err(self.unexpected_type(find_expression_span(lhs), "bool", "integer".into()))
+                }
+            },
+            (lhs_value, rhs_value) => Err(self.unexpected_type(
+                find_expression_span(rhs),
+                rhs_value.kind(),
+                lhs_value.kind().to_string(),
+            )),
+        }
+    }
+
+    /// The value converted like a cast does at runtime, integers are truncated to the type.
+    fn cast(&self, value: Value, ty: TypeIndex, span: Span) -> Result<Value, LoweringError> {
+        let target = self.builder.get_type(ty);
+        let int = match (value, target) {
+            (Value::Int(value), _) => value,
+            (Value::Bool(value), _) => value.into(),
+            (Value::Float(value), Type::Float(FloatTy::F32)) => {
+                return Ok(Value::Float(f64::from(value as f32)));
+            }
+            (Value::Float(value), Type::Float(FloatTy::F64)) => return Ok(Value::Float(value)),
+            (Value::Float(value), _) => value as i128,
+        };
+
+        Ok(match target {
+            Type::Int(IntTy::I8) => Value::Int((int as i8).into()),
+            Type::Int(IntTy::I16) => Value::Int((int as i16).into()),
+            Type::Int(IntTy::I32) => Value::Int((int as i32).into()),
+            Type::Int(IntTy::I64) => Value::Int((int as i64).into()),
+            Type::Int(IntTy::I128) | Type::Uint(UintTy::U128) => Value::Int(int),
+            Type::Uint(UintTy::U8) => Value::Int((int as u8).into()),
+            Type::Uint(UintTy::U16) => Value::Int((int as u16).into()),
+            Type::Uint(UintTy::U32) | Type::Char => Value::Int((int as u32).into()),
+            Type::Uint(UintTy::U64) => Value::Int((int as u64).into()),
+            Type::Float(_) => Value::Float(int as f64),
+            Type::Bool if matches!(value, Value::Bool(_)) => value,
+            _ => {
+                return Err(self.not_const(
+                    span,
+                    format!(
+                        "can't cast a {} to {} at compile time",
+                        value.kind(),
+                        self.builder.display_typename(ty)
+                    ),
+                ));
+            }
+        })
+    }
+
+    fn fn_call(&mut self, call: &FnCallOp) -> Result<Value, LoweringError> {
+        let module_idx = self.builder.get_path_module_idx(&call.path)?;
+        let symbol = Symbol {
+            name: call.target.name.clone(),
+            method_of: None,
+            generics: Vec::new(),
+        };
+        let Some(&(fn_idx, fn_module_idx)) =
+            self.builder.symbols[&module_idx].functions.get(&symbol)
+        else {
+            return Err(LoweringError::FunctionNotFound {
+                span: call.span,
+                function: call.target.name.clone(),
+                path: self.builder.get_current_module().file_path.clone(),
+            });
+        };
+
+        let function = match self.builder.bodies.functions.get(&fn_idx) {
+            Some(function) if function.decl.is_const => function.clone(),
+            _ => {
+                return Err(self.not_const(
+                    call.target.span,
+                    format!(
+                        "`{}` isn't a `const fn`, only those can be called at compile time",
+                        call.target.name
+                    ),
+                ));
+            }
+        };
+
+        self.builder.const_fn_calls.insert(fn_idx);
+
+        if !function.decl.generic_params.is_empty() {
+            return Err(self.not_const(
+                call.span,
+                "generic functions can't be called at compile time",
+            ));
+        }
+
+        if function.decl.params.len() != call.args.len() {
+            return Err(LoweringError::CallParamCountMismatch {
+                span: call.span,
+                found: call.args.len(),
+                needs: function.decl.params.len(),
+                path: self.builder.get_current_module().file_path.clone(),
+                fn_span: function.decl.name.span,
+                fn_path: self.builder.ir.modules[fn_module_idx].file_path.clone(),
+            });
+        }
+
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(self.not_const(call.span, "too many nested `const fn` calls"));
+        }
+
+        // The types are the ones in the module of the function.
+        self.builder.enter_module_context(fn_module_idx);
+        let types = function
+            .decl
+            .params
+            .iter()
+            .map(|param| lower_type(self.builder, &param.r#type))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|params| {
+                let ret = match &function.decl.ret_type {
+                    Some(ret_type) => Some(lower_type(self.builder, ret_type)?),
+                    None => None,
+                };
+                Ok((params, ret))
+            });
+        self.builder.leave_module_context();
+        let (param_types, ret_type) = types?;
+
+        let mut frame = HashMap::new();
+        for ((param, ty), arg) in function.decl.params.iter().zip(param_types).zip(&call.args) {
+            let value = self.expression(arg)?;
+            let value = self.check(value, ty, find_expression_span(arg))?;
+            frame.insert(param.name.name.clone(), (value, ty));
+        }
+
+        self.builder.enter_module_context(fn_module_idx);
+        let result = self.call(&function, frame);
+        self.builder.leave_module_context();
+
+        match (result?, ret_type) {
+            (Some(value), Some(ret_type)) => self.check(value, ret_type, call.span),
+            _ => Err(self.not_const(
+                call.span,
+                format!("`{}` doesn't return a value", call.target.name),
+            )),
+        }
+    }
+
+    fn call(
+        &mut self,
+        function: &FunctionDef,
+        frame: HashMap<String, ConstLocal>,
+    ) -> Result<Option<Value>, LoweringError> {
+        if let Some(param) = function.decl.params.iter().find(|x| x.pattern.is_some()) {
+            return Err(self.not_const(
+                param.r#type.get_span(),
+                "parameters with patterns can't be evaluated at compile time",
+            ));
+        }
+
+        self.frames.push(frame);
+        let result = self.block(&function.body);
+        self.frames.pop();
+        result
+    }
+
+    fn frame(&mut self) -> &mut HashMap<String, ConstLocal> {
+        self.frames.last_mut().expect("should be in a call")
+    }
+
+    fn step(&mut self, span: Span) -> Result<(), LoweringError> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(self.not_const(
+                span,
+                format!("the evaluation didn't end after {MAX_STEPS} steps"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn condition(&mut self, expr: &Expression) -> Result<bool, LoweringError> {
+        match self.expression(expr)? {
+            Value::Bool(value) => Ok(value),
+            value => Err(self.unexpected_type(
+                find_expression_span(expr),
+                value.kind(),
+                "bool".to_string(),
+            )),
+        }
+    }
+
+    /// Runs the statements of a block, the value is the one returned if they return.
+    fn block(&mut self, statements: &[Statement]) -> Result<Option<Value>, LoweringError> {
+        let outer_names: Vec<String> = self.frame().keys().cloned().collect();
+        let result = self.statements(statements);
+
+        // The locals declared in the block go out of scope.
+        self.frame().retain(|name, _| outer_names.contains(name));
+
+        result
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<Option<Value>, LoweringError> {
+        for statement in statements {
+            match statement {
+                Statement::Let(stmt) => {
+                    self.step(stmt.span)?;
+                    let LetStmtTarget::Simple { id, r#type } = &stmt.target else {
+                        return Err(self
+                            .not_const(stmt.span, "patterns can't be evaluated at compile time"));
+                    };
+                    let ty = lower_type(self.builder, r#type)?;
+                    let value = self.expression(&stmt.value)?;
+                    let value = self.check(value, ty, find_expression_span(&stmt.value))?;
+                    self.frame().insert(id.name.clone(), (value, ty));
+                }
+                Statement::Assign(stmt) => {
+                    self.step(stmt.span)?;
+                    if !stmt.lvalue.extra.is_empty() || stmt.derefs > 0 {
+                        return Err(self.not_const(
+                            stmt.lvalue.span,
+                            "only locals can be assigned at compile time",
+                        ));
+                    }
+                    let name = &stmt.lvalue.first.name;
+                    let Some(&(_, ty)) = self.frame().get(name) else {
+                        return Err(LoweringError::UseOfUndeclaredVariable {
+                            span: stmt.lvalue.span,
+                            name: name.clone(),
+                            path: self.builder.get_current_module().file_path.clone(),
+                        });
+                    };
+                    let value = self.expression(&stmt.rvalue)?;
+                    let value = self.check(value, ty, find_expression_span(&stmt.rvalue))?;
+                    self.frame().insert(name.clone(), (value, ty));
+                }
+                Statement::If(expr) => {
+                    self.step(expr.span)?;
+                    let result = if self.condition(&expr.cond)? {
+                        self.block(&expr.block_stmts)?
+                    } else if let Some(else_stmts) = &expr.else_stmts {
+                        self.block(else_stmts)?
+                    } else {
+                        None
+                    };
+
+                    if result.is_some() {
+                        return Ok(result);
+                    }
+                }
+                Statement::While(stmt) => {
+                    self.step(stmt.span)?;
+                    while self.condition(&stmt.condition)? {
+                        self.step(stmt.span)?;
+                        if let Some(value) = self.block(&stmt.block_stmts)? {
+                            return Ok(Some(value));
+                        }
+                    }
+                }
+                Statement::Return(stmt) => {
+                    return match &stmt.value {
+                        Some(value) => self.expression(value).map(Some),
+                        None => Ok(None),
+                    };
+                }
+                Statement::Become(stmt) => return self.expression(&stmt.value).map(Some),
+                Statement::MacroCall(call) => {
+                    return Err(unexpanded(
+                        call,
+                        &self.builder.get_current_module().file_path,
+                    ));
+                }
+                Statement::Match(MatchExpr { span, .. })
+                | Statement::For(ForStmt { span, .. })
+                | Statement::ForIn(ForInStmt { span, .. })
+                | Statement::FnCall(FnCallOp { span, .. })
+                | Statement::PathOp(PathOp { span, .. })
+                | Statement::Defer(DeferStmt { span, .. })
+                | Statement::Unsafe(UnsafeBlock { span, .. }) => {
+                    return Err(self.not_const(
+                        *span,
+                        "only lets, assignments, ifs, whiles and returns can be evaluated at \
+                         compile time",
+                    ));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn compare<T: PartialOrd>(op: CmpOp, a: T, b: T) -> bool {
+    match op {
+        CmpOp::Eq => a == b,
+        CmpOp::NotEq => a != b,
+        CmpOp::Lt => a < b,
+        CmpOp::LtEq => a <= b,
+        CmpOp::Gt => a > b,
+        CmpOp::GtEq => a >= b,
+    }
+}
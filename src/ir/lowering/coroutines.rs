@@ -0,0 +1,403 @@
+//! The state machines of the async functions.
+//!
+//! An async function is lowered like the others, its `await`s ending in blocks where it suspends,
+//! and once its borrows are checked the body is moved to a poll function. The locals live in a
+//! frame allocated when the function is called, after a state telling the block to resume from,
+//! so the poll function starts by jumping to it. Suspending stores the state of the block the
+//! function resumes from and returns false, returning from the body writes its value to the
+//! output and returns true.
+//!
+//! The async function itself becomes the `AsyncStart` intrinsic, which allocates the frame with
+//! its arguments and returns the `Future` of the frame and the poll function.
+
+use std::collections::HashMap;
+
+use crate::ir::{
+    BasicBlock, BlockIndex, ConcreteIntrinsic, ConstData, ConstKind, ConstValue, FieldIndex,
+    FnIndex, Function, Local, LocalIndex, LocalKind, Mutability, Operand, Place, PlaceElem, Rvalue,
+    Statement, StatementKind, SwitchTargets, Terminator, TerminatorKind, Type, TypeIndex,
+    UndefinedCheck, ValueTree,
+};
+
+use super::IRBuilder;
+
+/// The local of the poll function pointing to the frame.
+const FRAME_LOCAL: LocalIndex = 1;
+/// The local of the poll function pointing to where the value is written once it's ready.
+const OUTPUT_LOCAL: LocalIndex = 2;
+
+/// Turns the async functions lowered into state machines.
+pub(crate) fn lower_async_fns(builder: &mut IRBuilder) {
+    for (fn_idx, suspends) in std::mem::take(&mut builder.async_fns) {
+        lower_async_fn(builder, fn_idx, &suspends);
+    }
+}
+
+fn lower_async_fn(builder: &mut IRBuilder, fn_idx: FnIndex, suspends: &[BlockIndex]) {
+    let mut body = builder.ir.functions[fn_idx]
+        .take()
+        .expect("async functions are lowered");
+    let span = body.span.expect("async functions have a body");
+    let output_ty = body.locals[0].ty;
+    let is_unit = |ty: TypeIndex| matches!(builder.get_type(ty), Type::Unit);
+
+    // The arguments come first, where `AsyncStart` stores them. The locals of unit type hold
+    // nothing, they stay locals of the poll function.
+    let bool_ty = builder.ir.get_bool_ty();
+    let u32_ty = builder.ir.get_u32_ty();
+    let mut frame_fields = vec![u32_ty];
+    let mut layout = FrameLayout {
+        fields: HashMap::new(),
+        locals: HashMap::new(),
+        shadows: HashMap::new(),
+        body_locals: body.locals.iter().map(|x| x.ty).collect(),
+        poll_locals: Vec::new(),
+    };
+    let mut unit_locals = Vec::new();
+    let args = body
+        .locals
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| matches!(x.kind, LocalKind::Arg));
+    let others = body
+        .locals
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| !matches!(x.kind, LocalKind::Arg));
+    for (index, local) in args.chain(others) {
+        if is_unit(local.ty) {
+            unit_locals.push((index, local.clone()));
+        } else {
+            layout.fields.insert(index, frame_fields.len());
+            frame_fields.push(local.ty);
+        }
+    }
+
+    let frame_ty = builder.ir.types.insert(Some(Type::Tuple(frame_fields)));
+    let frame_ptr_ty = builder
+        .ir
+        .types
+        .insert(Some(Type::Ptr(frame_ty, Mutability::Mut)));
+    let output_ptr_ty = builder
+        .ir
+        .types
+        .insert(Some(Type::Ptr(output_ty, Mutability::Mut)));
+
+    layout.poll_locals = vec![
+        Local::new(None, LocalKind::ReturnPointer, bool_ty, None, true),
+        Local::new(
+            None,
+            LocalKind::Arg,
+            frame_ptr_ty,
+            Some("frame".to_string()),
+            false,
+        ),
+        Local::new(
+            None,
+            LocalKind::Arg,
+            output_ptr_ty,
+            Some("output".to_string()),
+            false,
+        ),
+    ];
+    for (index, local) in unit_locals {
+        layout.locals.insert(index, layout.poll_locals.len());
+        layout.poll_locals.push(Local {
+            kind: LocalKind::Temp,
+            ..local
+        });
+    }
+
+    let frame_field = |field: FieldIndex| Place {
+        local: FRAME_LOCAL,
+        projection: vec![PlaceElem::Deref, PlaceElem::Field(field)],
+    };
+    let assign = |place: Place, value: Rvalue| Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(place, value),
+    };
+    let constant = |ty: TypeIndex, value: ConstValue| {
+        Rvalue::Use(Operand::Const(ConstData {
+            ty,
+            span,
+            data: ConstKind::Value(ValueTree::Leaf(value)),
+        }))
+    };
+    let ret_place = Place {
+        local: 0,
+        projection: vec![],
+    };
+
+    // The blocks of the body follow the one jumping to the block to resume from.
+    let mut basic_blocks = vec![BasicBlock {
+        statements: Vec::new(),
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Unreachable,
+        }),
+    }];
+    let mut resume_targets = vec![1];
+    for (block_idx, block) in body.basic_blocks.iter().enumerate() {
+        let mut statements = Vec::new();
+        for statement in &block.statements {
+            // The frame outlives the storage of the locals in it.
+            if let StatementKind::Assign(place, value) = &statement.kind {
+                let value = layout.rvalue(value, &mut statements);
+                let place = layout.place(place, &mut statements);
+                statements.push(Statement {
+                    span: statement.span,
+                    kind: StatementKind::Assign(place, value),
+                });
+            }
+        }
+
+        let kind = if let Some(state) = suspends.iter().position(|x| *x == block_idx) {
+            let TerminatorKind::Goto { target } = block.terminator.kind else {
+                unreachable!("the blocks suspending go on to poll the future again")
+            };
+            resume_targets.push(target + 1);
+            statements.push(assign(
+                frame_field(0),
+                constant(u32_ty, ConstValue::U32((state + 1) as u32)),
+            ));
+            statements.push(assign(
+                ret_place.clone(),
+                constant(bool_ty, ConstValue::Bool(false)),
+            ));
+            TerminatorKind::Return
+        } else {
+            match &block.terminator.kind {
+                TerminatorKind::Goto { target } => TerminatorKind::Goto { target: target + 1 },
+                TerminatorKind::Return => {
+                    if let Some(field) = layout.fields.get(&0) {
+                        statements.push(assign(
+                            Place {
+                                local: OUTPUT_LOCAL,
+                                projection: vec![PlaceElem::Deref],
+                            },
+                            Rvalue::Use(Operand::Place(frame_field(*field))),
+                        ));
+                    }
+                    statements.push(assign(
+                        ret_place.clone(),
+                        constant(bool_ty, ConstValue::Bool(true)),
+                    ));
+                    TerminatorKind::Return
+                }
+                TerminatorKind::Unreachable => TerminatorKind::Unreachable,
+                TerminatorKind::TailCall { .. } => {
+                    unreachable!("async functions can't make tail calls")
+                }
+                TerminatorKind::Call {
+                    func,
+                    args,
+                    destination,
+                    target,
+                } => {
+                    let args = args
+                        .iter()
+                        .map(|x| layout.rvalue(x, &mut statements))
+                        .collect();
+                    TerminatorKind::Call {
+                        func: *func,
+                        args,
+                        destination: layout.place(destination, &mut statements),
+                        target: target.map(|x| x + 1),
+                    }
+                }
+                TerminatorKind::BoundsCheck {
+                    cond,
+                    index,
+                    len,
+                    target,
+                } => TerminatorKind::BoundsCheck {
+                    cond: layout.operand(cond, &mut statements),
+                    index: layout.operand(index, &mut statements),
+                    len: layout.operand(len, &mut statements),
+                    target: target + 1,
+                },
+                TerminatorKind::OverflowCheck { cond, op, target } => {
+                    TerminatorKind::OverflowCheck {
+                        cond: layout.operand(cond, &mut statements),
+                        op: *op,
+                        target: target + 1,
+                    }
+                }
+                TerminatorKind::UndefinedCheck { check, target } => {
+                    let operand = layout.operand(check.operand(), &mut statements);
+                    let check = match check {
+                        UndefinedCheck::DivisionByZero(_) => {
+                            UndefinedCheck::DivisionByZero(operand)
+                        }
+                        UndefinedCheck::Load(_) => UndefinedCheck::Load(operand),
+                        UndefinedCheck::Store(_) => UndefinedCheck::Store(operand),
+                    };
+                    TerminatorKind::UndefinedCheck {
+                        check,
+                        target: target + 1,
+                    }
+                }
+                TerminatorKind::SwitchInt {
+                    discriminator,
+                    targets,
+                } => TerminatorKind::SwitchInt {
+                    discriminator: layout.operand(discriminator, &mut statements),
+                    targets: SwitchTargets {
+                        values: targets.values.clone(),
+                        targets: targets.targets.iter().map(|x| x + 1).collect(),
+                    },
+                },
+            }
+        };
+
+        basic_blocks.push(BasicBlock {
+            statements,
+            terminator: Box::new(Terminator {
+                span: block.terminator.span,
+                kind,
+            }),
+        });
+    }
+
+    // A future isn't polled once it's ready, so the other states can't happen.
+    resume_targets.push(basic_blocks.len());
+    basic_blocks.push(BasicBlock {
+        statements: Vec::new(),
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Unreachable,
+        }),
+    });
+    basic_blocks[0].terminator.kind = TerminatorKind::SwitchInt {
+        discriminator: Operand::Place(frame_field(0)),
+        targets: SwitchTargets {
+            values: (0..resume_targets.len() - 1)
+                .map(|x| ValueTree::Leaf(ConstValue::U32(x as u32)))
+                .collect(),
+            targets: resume_targets,
+        },
+    };
+
+    let poll = Function {
+        // A suffix of the symbol, kept as is by the demangler.
+        name: format!("{}.poll", body.name),
+        debug_name: body.debug_name.as_ref().map(|x| format!("{x}::poll")),
+        args: vec![frame_ptr_ty, output_ptr_ty],
+        ret_ty: bool_ty,
+        is_extern: false,
+        is_pub: false,
+        is_intrinsic: None,
+        inline: body.inline,
+        is_cold: body.is_cold,
+        basic_blocks,
+        module_idx: body.module_idx,
+        locals: layout.poll_locals,
+        scopes: std::mem::take(&mut body.scopes),
+        span: body.span,
+    };
+    let poll_idx = builder.ir.functions.insert(Some(poll));
+    builder.ir.modules[body.module_idx]
+        .functions
+        .insert(poll_idx);
+
+    body.is_intrinsic = Some(ConcreteIntrinsic::AsyncStart {
+        frame: frame_ty,
+        poll: poll_idx,
+    });
+    body.basic_blocks = Vec::new();
+    builder.ir.functions[fn_idx] = Some(body);
+}
+
+/// Where the places of the body are in the poll function: the fields of the frame, and the locals
+/// of unit type.
+struct FrameLayout {
+    fields: HashMap<LocalIndex, FieldIndex>,
+    locals: HashMap<LocalIndex, LocalIndex>,
+    /// The locals of the poll function the indexes in the frame are copied to, to index arrays.
+    shadows: HashMap<LocalIndex, LocalIndex>,
+    /// The types of the locals of the body.
+    body_locals: Vec<TypeIndex>,
+    poll_locals: Vec<Local>,
+}
+
+impl FrameLayout {
+    fn place(&mut self, place: &Place, loads: &mut Vec<Statement>) -> Place {
+        let (local, mut projection) = match self.fields.get(&place.local) {
+            Some(field) => (
+                FRAME_LOCAL,
+                vec![PlaceElem::Deref, PlaceElem::Field(*field)],
+            ),
+            None => (self.locals[&place.local], Vec::new()),
+        };
+        for elem in &place.projection {
+            projection.push(match elem {
+                PlaceElem::Index(index) => PlaceElem::Index(self.index(*index, loads)),
+                elem => elem.clone(),
+            });
+        }
+        Place { local, projection }
+    }
+
+    /// The local holding the index, which is a plain local, so one in the frame is copied first.
+    fn index(&mut self, index: LocalIndex, loads: &mut Vec<Statement>) -> LocalIndex {
+        let Some(field) = self.fields.get(&index).copied() else {
+            return self.locals[&index];
+        };
+        let shadow = match self.shadows.get(&index) {
+            Some(shadow) => *shadow,
+            None => {
+                let shadow = self.poll_locals.len();
+                self.poll_locals.push(Local::temp(self.body_locals[index]));
+                self.shadows.insert(index, shadow);
+                shadow
+            }
+        };
+        loads.push(Statement {
+            span: None,
+            kind: StatementKind::Assign(
+                Place {
+                    local: shadow,
+                    projection: vec![],
+                },
+                Rvalue::Use(Operand::Place(Place {
+                    local: FRAME_LOCAL,
+                    projection: vec![PlaceElem::Deref, PlaceElem::Field(field)],
+                })),
+            ),
+        });
+        shadow
+    }
+
+    fn operand(&mut self, operand: &Operand, loads: &mut Vec<Statement>) -> Operand {
+        match operand {
+            Operand::Place(place) => Operand::Place(self.place(place, loads)),
+            Operand::Const(data) => Operand::Const(data.clone()),
+        }
+    }
+
+    fn rvalue(&mut self, value: &Rvalue, loads: &mut Vec<Statement>) -> Rvalue {
+        match value {
+            Rvalue::Use(op) => Rvalue::Use(self.operand(op, loads)),
+            Rvalue::LogicOp(op, (lhs, rhs)) => {
+                Rvalue::LogicOp(*op, (self.operand(lhs, loads), self.operand(rhs, loads)))
+            }
+            Rvalue::BinaryOp(op, (lhs, rhs)) => {
+                Rvalue::BinaryOp(*op, (self.operand(lhs, loads), self.operand(rhs, loads)))
+            }
+            Rvalue::Overflows(op, (lhs, rhs)) => {
+                Rvalue::Overflows(*op, (self.operand(lhs, loads), self.operand(rhs, loads)))
+            }
+            Rvalue::UnaryOp(op, value) => Rvalue::UnaryOp(*op, self.operand(value, loads)),
+            Rvalue::Ref(mutability, place) => Rvalue::Ref(*mutability, self.place(place, loads)),
+            Rvalue::Cast(value, ty, span) => Rvalue::Cast(self.operand(value, loads), *ty, *span),
+            Rvalue::Move(place) => Rvalue::Move(self.place(place, loads)),
+            Rvalue::Len(place) => Rvalue::Len(self.place(place, loads)),
+            Rvalue::Slice(place, start, end, ty) => Rvalue::Slice(
+                self.place(place, loads),
+                self.operand(start, loads),
+                self.operand(end, loads),
+                *ty,
+            ),
+        }
+    }
+}
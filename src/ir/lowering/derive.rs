@@ -0,0 +1,600 @@
+//! `#[derive(...)]`: the implementations of `Eq`, `Clone` and `Debug` for a struct or an enum,
+//! built from its fields and added to its module before lowering, as if they were written by hand.
+//!
+//! The implementations call the methods of the trait on the fields, except for the fields of
+//! primitive types: numbers, booleans, characters and pointers are compared with `==`, copied,
+//! and written by the functions of `std.fmt`. The trait must be in scope, like for any `impl`.
+
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use crate::{
+    ast::{
+        CompilationUnit,
+        common::{Attribute, AttributeArg, Ident, Span, TypeName},
+        enums::EnumDecl,
+        expressions::{
+            BinaryOp, CmpOp, EnumInitExpr, EnumMatchExpr, Expression, FnCallOp, LogicOp,
+            MatchCaseExpr, MatchExpr, MatchVariant, PathOp, PathSegment, StructInitExpr,
+            StructInitField, ValueExpr,
+        },
+        functions::{FunctionDecl, FunctionDef, ImplTraitBlock, Param},
+        modules::{Module, ModuleDefItem},
+        patterns::{FieldPattern, Pattern},
+        statements::{ReturnStmt, Statement},
+        structs::Field,
+        types::TypeDescriptor,
+    },
+    check::suggestions::unknown_value_message,
+};
+
+use super::errors::LoweringError;
+
+/// The traits that can be derived.
+const DERIVABLE: &[&str] = &["Eq", "Clone", "Debug"];
+
+const SIGNED: &[&str] = &["i8", "i16", "i32", "i64", "i128"];
+const UNSIGNED: &[&str] = &["u8", "u16", "u32", "u64", "u128"];
+const FLOATS: &[&str] = &["f32", "f64"];
+
+/// The compile unit with the implementations its derive attributes ask for.
+pub(crate) fn expand_unit(unit: CompilationUnit) -> Result<CompilationUnit, LoweringError> {
+    let modules = unit
+        .modules
+        .iter()
+        .map(expand_module)
+        .collect::<Result<_, _>>()?;
+
+    Ok(CompilationUnit { modules, ..unit })
+}
+
+fn expand_module(module: &Module) -> Result<Module, LoweringError> {
+    let path = module.file_path.as_path();
+    let mut contents = Vec::with_capacity(module.contents.len());
+
+    for item in &module.contents {
+        contents.push(item.clone());
+
+        let (attributes, generics, adt) = match item {
+            ModuleDefItem::Struct(x) => {
+                (&x.attributes, &x.generics, Adt::Struct(&x.name, &x.fields))
+            }
+            ModuleDefItem::Enum(x) => (&x.attributes, &x.generics, Adt::Enum(x)),
+            ModuleDefItem::Module(x) => {
+                *contents.last_mut().unwrap() = ModuleDefItem::Module(Arc::new(expand_module(x)?));
+                continue;
+            }
+            _ => continue,
+        };
+
+        for attr in attributes.iter().filter(|x| x.name == "derive") {
+            for (name, span) in derived_traits(attr, path)? {
+                if !generics.is_empty() {
+                    return Err(LoweringError::InvalidAttribute {
+                        span,
+                        name: attr.name.clone(),
+                        reason: "traits can't be derived for generic types yet".to_string(),
+                        path: path.to_path_buf(),
+                    });
+                }
+
+                let builder = Builder { span, path };
+                let block = builder.derive(&name, &adt)?;
+                contents.push(ModuleDefItem::ImplTrait(Arc::new(block)));
+            }
+        }
+    }
+
+    Ok(Module {
+        contents,
+        ..module.clone()
+    })
+}
+
+/// The traits listed by the attribute, with their spans.
+fn derived_traits(attr: &Attribute, path: &Path) -> Result<Vec<(String, Span)>, LoweringError> {
+    let invalid = |span: Span, reason: String| LoweringError::InvalidAttribute {
+        span,
+        name: attr.name.clone(),
+        reason,
+        path: path.to_path_buf(),
+    };
+
+    if attr.args.is_empty() {
+        return Err(invalid(
+            attr.span,
+            "expected the traits to derive, e.g: #[derive(Eq, Clone)]".to_string(),
+        ));
+    }
+
+    attr.args
+        .iter()
+        .map(|arg| match arg {
+            AttributeArg::Word(name) if DERIVABLE.contains(&name.name.as_str()) => {
+                Ok((name.name.clone(), name.span))
+            }
+            AttributeArg::Word(name) => Err(invalid(
+                name.span,
+                unknown_value_message("derivable trait", &name.name, DERIVABLE.iter().copied()),
+            )),
+            arg => Err(invalid(
+                arg.span(),
+                "expected the name of a trait, e.g: #[derive(Eq)]".to_string(),
+            )),
+        })
+        .collect()
+}
+
+/// The type a trait is derived for.
+enum Adt<'a> {
+    Struct(&'a Ident, &'a [Field]),
+    Enum(&'a EnumDecl),
+}
+
+impl Adt<'_> {
+    fn name(&self) -> &Ident {
+        match self {
+            Adt::Struct(name, _) => name,
+            Adt::Enum(decl) => &decl.name,
+        }
+    }
+}
+
+/// How a field is compared, copied and written.
+enum FieldKind {
+    /// A number, boolean, character or pointer, with the `std.fmt` function writing it and the
+    /// type it's cast to for it.
+    Primitive(&'static str, &'static str),
+    /// A struct, enum or string, whose methods are called.
+    Methods,
+}
+
+/// Builds the AST of the implementations, every node at the span of the derived trait, so the
+/// errors in them point at it.
+struct Builder<'a> {
+    span: Span,
+    path: &'a Path,
+}
+
+impl Builder<'_> {
+    fn derive(&self, name: &str, adt: &Adt) -> Result<ImplTraitBlock, LoweringError> {
+        let (method, params, ret_type, body) = match name {
+            "Eq" => (
+                "eq",
+                vec![self.param("other", self.reference(adt.name(), false))],
+                Some(self.ty("bool")),
+                self.eq_body(adt)?,
+            ),
+            "Clone" => (
+                "clone",
+                Vec::new(),
+                Some(self.ty(&adt.name().name)),
+                self.clone_body(adt)?,
+            ),
+            "Debug" => (
+                "fmt",
+                vec![self.param("out", self.reference(&self.ident("String"), true))],
+                None,
+                self.debug_body(adt)?,
+            ),
+            _ => unreachable!("the traits are checked when reading the attribute"),
+        };
+
+        let mut all_params = vec![Param {
+            attributes: Vec::new(),
+            name: self.ident("self"),
+            r#type: TypeDescriptor::SelfType {
+                is_ref: true,
+                is_mut: false,
+                span: self.span,
+            },
+            pattern: None,
+        }];
+        all_params.extend(params);
+
+        let decl = FunctionDecl {
+            doc_string: None,
+            generic_params: Vec::new(),
+            name: self.ident(method),
+            params: all_params,
+            ret_type,
+            is_extern: false,
+            is_const: false,
+            is_unsafe: false,
+            is_async: false,
+            is_pub: true,
+            vis_span: self.span,
+            attributes: Vec::new(),
+            span: self.span,
+        };
+
+        Ok(ImplTraitBlock {
+            attributes: Vec::new(),
+            target_trait: self.type_name(name),
+            target: self.ty(&adt.name().name),
+            generic_params: Vec::new(),
+            associated_types: Vec::new(),
+            methods: vec![Arc::new(FunctionDef {
+                decl: Arc::new(decl),
+                body,
+                is_prebuilt: false,
+                span: self.span,
+            })],
+            span: self.span,
+        })
+    }
+
+    /// `self.a == other.a && self.b.eq(&other.b)`, and for enums, the same comparison of the
+    /// fields of the variant if both values are of the same one.
+    fn eq_body(&self, adt: &Adt) -> Result<Vec<Statement>, LoweringError> {
+        let compare =
+            |fields: &[Field], left: &dyn Fn(&str) -> PathOp, right: &dyn Fn(&str) -> PathOp| {
+                let mut result: Option<Expression> = None;
+                for field in fields {
+                    let name = field.name.name.as_str();
+                    let comparison = match self.field_kind(field, "Eq")? {
+                        FieldKind::Primitive(..) => Expression::BinaryOp(
+                            Box::new(self.path_expr(left(name))),
+                            BinaryOp::Compare(CmpOp::Eq),
+                            Box::new(self.path_expr(right(name))),
+                        ),
+                        FieldKind::Methods => self.method_call(
+                            left(name),
+                            "eq",
+                            vec![Expression::AsRef(
+                                Box::new(self.path_expr(right(name))),
+                                false,
+                                self.span,
+                            )],
+                        ),
+                    };
+                    result = Some(match result {
+                        Some(result) => Expression::BinaryOp(
+                            Box::new(result),
+                            BinaryOp::Logic(LogicOp::And),
+                            Box::new(comparison),
+                        ),
+                        None => comparison,
+                    });
+                }
+
+                Ok::<_, LoweringError>(result.unwrap_or(Expression::Value(
+                    ValueExpr::ConstBool(true, self.span),
+                    self.span,
+                )))
+            };
+
+        match adt {
+            Adt::Struct(_, fields) => Ok(vec![self.ret(compare(
+                fields,
+                &|x| self.path("self", &[x]),
+                &|x| self.path("other", &[x]),
+            )?)]),
+            Adt::Enum(decl) => {
+                let mut arms = Vec::with_capacity(decl.variants.len());
+                for variant in &decl.variants {
+                    let mut inner_arms = Vec::with_capacity(decl.variants.len());
+                    for other in &decl.variants {
+                        let block = if other.name.name == variant.name.name {
+                            vec![self.ret(compare(
+                                &variant.fields,
+                                &|x| self.path(&format!("self_{x}"), &[]),
+                                &|x| self.path(&format!("other_{x}"), &[]),
+                            )?)]
+                        } else {
+                            vec![self.ret(Expression::Value(
+                                ValueExpr::ConstBool(false, self.span),
+                                self.span,
+                            ))]
+                        };
+                        let bound = match other.name.name == variant.name.name {
+                            true => other.fields.as_slice(),
+                            false => &[],
+                        };
+                        inner_arms.push(self.arm(decl, &other.name, bound, "other_", block));
+                    }
+
+                    let block = vec![self.match_stmt("other", inner_arms)];
+                    arms.push(self.arm(decl, &variant.name, &variant.fields, "self_", block));
+                }
+
+                Ok(vec![self.match_stmt("self", arms)])
+            }
+        }
+    }
+
+    /// `Name { a: self.a, b: self.b.clone() }`, and for enums, the variant with the fields of
+    /// `self` cloned.
+    fn clone_body(&self, adt: &Adt) -> Result<Vec<Statement>, LoweringError> {
+        let fields = |fields: &[Field], value: &dyn Fn(&str) -> PathOp| {
+            let mut values = HashMap::new();
+            for field in fields {
+                let name = field.name.name.as_str();
+                let value = match self.field_kind(field, "Clone")? {
+                    FieldKind::Primitive(..) => self.path_expr(value(name)),
+                    FieldKind::Methods => self.method_call(value(name), "clone", Vec::new()),
+                };
+                values.insert(
+                    self.ident(name),
+                    StructInitField {
+                        value,
+                        span: self.span,
+                    },
+                );
+            }
+
+            Ok::<_, LoweringError>(values)
+        };
+
+        match adt {
+            Adt::Struct(name, struct_fields) => {
+                let fields = fields(struct_fields, &|x| self.path("self", &[x]))?;
+                Ok(vec![self.ret(Expression::StructInit(StructInitExpr {
+                    name: self.type_name(&name.name),
+                    fields,
+                    base: None,
+                    span: self.span,
+                }))])
+            }
+            Adt::Enum(decl) => {
+                let mut arms = Vec::with_capacity(decl.variants.len());
+                for variant in &decl.variants {
+                    let fields =
+                        fields(&variant.fields, &|x| self.path(&format!("self_{x}"), &[]))?;
+                    let value = Expression::EnumInit(EnumInitExpr {
+                        name: self.type_name(&decl.name.name),
+                        variant: self.ident(&variant.name.name),
+                        fields,
+                        span: self.span,
+                    });
+                    let block = vec![self.ret(value)];
+                    arms.push(self.arm(decl, &variant.name, &variant.fields, "self_", block));
+                }
+
+                Ok(vec![self.match_stmt("self", arms)])
+            }
+        }
+    }
+
+    /// Writes `Name { a: 1, b: ... }`, or `Name#Variant { ... }` for enums, to `out`.
+    fn debug_body(&self, adt: &Adt) -> Result<Vec<Statement>, LoweringError> {
+        let fields = |prefix: &str, fields: &[Field], value: &dyn Fn(&str) -> PathOp| {
+            let mut statements = Vec::new();
+            if fields.is_empty() {
+                statements.push(self.write_str(prefix));
+                return Ok(statements);
+            }
+
+            for (i, field) in fields.iter().enumerate() {
+                let name = field.name.name.as_str();
+                let separator = if i == 0 { " { " } else { ", " };
+                let label = match i {
+                    0 => format!("{prefix}{separator}{name}: "),
+                    _ => format!("{separator}{name}: "),
+                };
+                statements.push(self.write_str(&label));
+
+                statements.push(match self.field_kind(field, "Debug")? {
+                    FieldKind::Primitive(function, cast) => Statement::FnCall(self.fmt_call(
+                        function,
+                        vec![
+                            self.path_expr(self.path("out", &[])),
+                            Expression::Cast(
+                                Box::new(self.path_expr(value(name))),
+                                self.ty(cast),
+                                self.span,
+                            ),
+                        ],
+                    )),
+                    FieldKind::Methods => {
+                        let mut path = value(name);
+                        path.extra.push(PathSegment::MethodCall(
+                            self.fn_call(&[], "fmt", vec![self.path_expr(self.path("out", &[]))]),
+                            self.span,
+                        ));
+                        Statement::PathOp(path)
+                    }
+                });
+            }
+            statements.push(self.write_str(" }"));
+
+            Ok::<_, LoweringError>(statements)
+        };
+
+        match adt {
+            Adt::Struct(name, struct_fields) => {
+                fields(&name.name, struct_fields, &|x| self.path("self", &[x]))
+            }
+            Adt::Enum(decl) => {
+                let mut arms = Vec::with_capacity(decl.variants.len());
+                for variant in &decl.variants {
+                    let prefix = format!("{}#{}", decl.name.name, variant.name.name);
+                    let block = fields(&prefix, &variant.fields, &|x| {
+                        self.path(&format!("self_{x}"), &[])
+                    })?;
+                    arms.push(self.arm(decl, &variant.name, &variant.fields, "self_", block));
+                }
+
+                Ok(vec![self.match_stmt("self", arms)])
+            }
+        }
+    }
+
+    fn field_kind(&self, field: &Field, derived: &str) -> Result<FieldKind, LoweringError> {
+        let primitive = match &field.r#type {
+            TypeDescriptor::Type { name, .. } if name.path.is_empty() => {
+                let name = name.name.name.as_str();
+                if SIGNED.contains(&name) {
+                    Some(("write_int", "i64"))
+                } else if UNSIGNED.contains(&name) {
+                    Some(("write_uint", "u64"))
+                } else if FLOATS.contains(&name) {
+                    Some(("write_float", "f64"))
+                } else if name == "bool" {
+                    Some(("write_bool", "bool"))
+                } else if name == "char" {
+                    Some(("write_char", "char"))
+                } else {
+                    None
+                }
+            }
+            TypeDescriptor::Type { .. } => None,
+            TypeDescriptor::ConstPtr { .. } | TypeDescriptor::MutPtr { .. } => {
+                Some(("write_uint", "u64"))
+            }
+            ty => {
+                return Err(LoweringError::InvalidAttribute {
+                    span: self.span,
+                    name: "derive".to_string(),
+                    reason: format!(
+                        "`{derived}` can't be derived for the field `{}` of type `{ty}`",
+                        field.name.name
+                    ),
+                    path: self.path.to_path_buf(),
+                });
+            }
+        };
+
+        Ok(match primitive {
+            Some((function, cast)) => FieldKind::Primitive(function, cast),
+            None => FieldKind::Methods,
+        })
+    }
+
+    fn ident(&self, name: &str) -> Ident {
+        Ident {
+            name: name.to_string(),
+            span: self.span,
+        }
+    }
+
+    fn type_name(&self, name: &str) -> TypeName {
+        TypeName {
+            path: Vec::new(),
+            name: self.ident(name),
+            generics: Vec::new(),
+            span: self.span,
+        }
+    }
+
+    fn ty(&self, name: &str) -> TypeDescriptor {
+        self.type_name(name).into()
+    }
+
+    fn reference(&self, name: &Ident, is_mut: bool) -> TypeDescriptor {
+        let of = Box::new(self.ty(&name.name));
+        match is_mut {
+            true => TypeDescriptor::MutRef {
+                of,
+                span: self.span,
+            },
+            false => TypeDescriptor::Ref {
+                of,
+                span: self.span,
+            },
+        }
+    }
+
+    fn param(&self, name: &str, r#type: TypeDescriptor) -> Param {
+        Param {
+            attributes: Vec::new(),
+            name: self.ident(name),
+            r#type,
+            pattern: None,
+        }
+    }
+
+    /// `first.a.b`
+    fn path(&self, first: &str, fields: &[&str]) -> PathOp {
+        PathOp {
+            first: self.ident(first),
+            extra: fields
+                .iter()
+                .map(|x| PathSegment::FieldAccess(self.ident(x), self.span))
+                .collect(),
+            span: self.span,
+        }
+    }
+
+    fn path_expr(&self, path: PathOp) -> Expression {
+        Expression::Value(ValueExpr::Path(path), self.span)
+    }
+
+    fn fn_call(&self, path: &[&str], target: &str, args: Vec<Expression>) -> FnCallOp {
+        FnCallOp {
+            path: path.iter().map(|x| self.ident(x)).collect(),
+            target: self.ident(target),
+            generics: Vec::new(),
+            args,
+            span: self.span,
+        }
+    }
+
+    fn method_call(&self, mut receiver: PathOp, method: &str, args: Vec<Expression>) -> Expression {
+        receiver.extra.push(PathSegment::MethodCall(
+            self.fn_call(&[], method, args),
+            self.span,
+        ));
+        self.path_expr(receiver)
+    }
+
+    /// A call to a function of `std.fmt`.
+    fn fmt_call(&self, function: &str, args: Vec<Expression>) -> FnCallOp {
+        self.fn_call(&["std", "fmt"], function, args)
+    }
+
+    /// `std::fmt::write_str(out, "text");`
+    fn write_str(&self, text: &str) -> Statement {
+        Statement::FnCall(self.fmt_call(
+            "write_str",
+            vec![
+                self.path_expr(self.path("out", &[])),
+                Expression::Value(ValueExpr::ConstStr(text.to_string(), self.span), self.span),
+            ],
+        ))
+    }
+
+    fn ret(&self, value: Expression) -> Statement {
+        Statement::Return(ReturnStmt {
+            value: Some(value),
+            span: self.span,
+        })
+    }
+
+    fn match_stmt(&self, on: &str, variants: Vec<MatchVariant>) -> Statement {
+        Statement::Match(MatchExpr {
+            expr: Box::new(self.path_expr(self.path(on, &[]))),
+            variants,
+            span: self.span,
+        })
+    }
+
+    /// `Name#Variant { a: prefix_a, ... } => { block }`, binding the given fields.
+    fn arm(
+        &self,
+        decl: &EnumDecl,
+        variant: &Ident,
+        fields: &[Field],
+        prefix: &str,
+        block: Vec<Statement>,
+    ) -> MatchVariant {
+        let field_values = fields
+            .iter()
+            .map(|field| FieldPattern {
+                name: self.ident(&field.name.name),
+                pattern: Pattern::Binding(self.ident(&format!("{prefix}{}", field.name.name))),
+                span: self.span,
+            })
+            .collect();
+
+        MatchVariant {
+            case: MatchCaseExpr::Enum(EnumMatchExpr {
+                name: self.type_name(&decl.name.name),
+                variant: self.ident(&variant.name),
+                field_values,
+                span: self.span,
+            }),
+            block,
+            span: self.span,
+        }
+    }
+}
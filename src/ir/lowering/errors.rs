@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use crate::ast::common::{Ident, Span};
+use crate::check::suggestions::{Applicability, Suggestion};
 use thiserror::Error;
 
 #[derive(Debug, Error, Clone)]
@@ -41,6 +42,14 @@ pub enum LoweringError {
         name: String,
         path: PathBuf,
     },
+    #[error("variant {name:?} not found in {type_name:?}")]
+    VariantNotFound {
+        span: Span,
+        name: String,
+        type_name: String,
+        suggestion: Option<Box<Suggestion>>,
+        path: PathBuf,
+    },
     #[error("symbol {:?} not found", symbol.name)]
     ImportNotFound {
         module_span: Span,
@@ -48,6 +57,10 @@ pub enum LoweringError {
         symbol: Ident,
         path: PathBuf,
     },
+    #[error("glob import of {:?} is ambiguous", _0.name)]
+    AmbiguousImport(Box<AmbiguousImport>),
+    #[error("{} {:?} is private", _0.kind, _0.name)]
+    PrivateItem(Box<PrivateItem>),
     #[error("use of underclared variable {name:?}")]
     UseOfUndeclaredVariable {
         span: Span,
@@ -65,12 +78,14 @@ pub enum LoweringError {
     NotMutable {
         span: Span,
         declare_span: Option<Span>,
+        suggestion: Option<Box<Suggestion>>,
         path: PathBuf,
     },
     #[error("can't take a mutable borrow to this value because it's not declared mutable")]
     CantTakeMutableBorrow {
         span: Span,
         declare_span: Option<Span>,
+        suggestion: Option<Box<Suggestion>>,
         path: PathBuf,
     },
     #[error("unrecognized type {name}")]
@@ -85,14 +100,8 @@ pub enum LoweringError {
         message: &'static str,
         path: PathBuf,
     },
-    #[error("unexpected type")]
-    UnexpectedType {
-        found_span: Span,
-        found: String,
-        expected: String,
-        expected_span: Option<Span>,
-        path: PathBuf,
-    },
+    #[error("mismatched types, expected {}, found {}", .0.expected, .0.found)]
+    UnexpectedType(Box<UnexpectedTypeError>),
     #[error("invalid unary op on given type")]
     InvalidUnaryOp {
         found_span: Span,
@@ -111,6 +120,9 @@ pub enum LoweringError {
         found: usize,
         needs: usize,
         path: PathBuf,
+        /// The span of the called function name, in the file it's declared in.
+        fn_span: Span,
+        fn_path: PathBuf,
     },
     #[error("function generic parameter count mismatch, found {found}, needs {needs}")]
     GenericCountMismatch {
@@ -118,6 +130,9 @@ pub enum LoweringError {
         found: usize,
         needs: usize,
         path: PathBuf,
+        /// The span of the called function name, in the file it's declared in.
+        fn_span: Span,
+        fn_path: PathBuf,
     },
     #[error("unknown lang item {item}")]
     UnknownLangItem {
@@ -125,6 +140,12 @@ pub enum LoweringError {
         item: String,
         path: PathBuf,
     },
+    #[error("the `{item}` lang item isn't defined")]
+    MissingLangItem {
+        span: Span,
+        item: String,
+        path: PathBuf,
+    },
     #[error("invalid match, reason: {reason}")]
     InvalidMatch {
         span: Span,
@@ -139,6 +160,189 @@ pub enum LoweringError {
     },
     #[error("missing variant")]
     MissingVariant(Box<MissingVariantError>),
+    #[error("invalid attribute {name:?}: {reason}")]
+    InvalidAttribute {
+        span: Span,
+        name: String,
+        reason: String,
+        path: PathBuf,
+    },
+    #[error("can't {access} {name:?} while it's borrowed")]
+    BorrowConflict {
+        span: Span,
+        /// Where the borrow that is still used was taken.
+        borrow_span: Option<Span>,
+        name: String,
+        /// What was done to the value: "use", "assign to", "borrow" or "mutably borrow".
+        access: &'static str,
+        mutable_borrow: bool,
+        path: PathBuf,
+    },
+    #[error("use of {name:?} after it was moved")]
+    UseOfMovedValue {
+        span: Span,
+        /// Where the struct update moved it.
+        move_span: Option<Span>,
+        name: String,
+        path: PathBuf,
+    },
+    #[error("can't index into {found}")]
+    NotIndexable {
+        span: Span,
+        found: String,
+        path: PathBuf,
+    },
+    #[error("refutable pattern {variant} in a binding")]
+    RefutablePattern {
+        span: Span,
+        variant: String,
+        type_name: String,
+        path: PathBuf,
+    },
+    #[error("can't iterate over {found}")]
+    NotIterable {
+        span: Span,
+        found: String,
+        path: PathBuf,
+    },
+    #[error("invalid use of `?`: {reason}")]
+    InvalidTry {
+        span: Span,
+        reason: String,
+        path: PathBuf,
+    },
+    #[error("invalid use of `await`: {reason}")]
+    InvalidAwait {
+        span: Span,
+        reason: String,
+        path: PathBuf,
+    },
+    #[error("{name:?} can't be async: {reason}")]
+    InvalidAsyncFn {
+        span: Span,
+        name: String,
+        reason: String,
+        path: PathBuf,
+    },
+    #[error("invalid tail call: {reason}")]
+    InvalidTailCall {
+        span: Span,
+        reason: String,
+        path: PathBuf,
+    },
+    #[error("can't return from the statements of a defer")]
+    ReturnInDefer {
+        span: Span,
+        defer_span: Span,
+        path: PathBuf,
+    },
+    #[error("{operation} is unsafe and needs an unsafe block or function")]
+    UnsafeOperation {
+        span: Span,
+        operation: String,
+        path: PathBuf,
+    },
+    #[error("unknown ABI {abi:?}")]
+    UnknownAbi {
+        span: Span,
+        abi: String,
+        path: PathBuf,
+    },
+    #[error("can't evaluate at compile time: {reason}")]
+    NotConst {
+        span: Span,
+        reason: String,
+        path: PathBuf,
+    },
+    #[error("discriminant {value} is used by {first:?} and {second:?}")]
+    DuplicateDiscriminant {
+        span: Span,
+        first_span: Span,
+        value: u32,
+        first: String,
+        second: String,
+        path: PathBuf,
+    },
+    #[error("the discriminant of {name:?} is past the largest u32")]
+    DiscriminantOverflow {
+        span: Span,
+        name: String,
+        /// The variant with the explicit discriminant it follows.
+        previous: String,
+        path: PathBuf,
+    },
+    #[error("the type alias {name:?} refers to itself")]
+    RecursiveTypeAlias {
+        span: Span,
+        name: String,
+        path: PathBuf,
+    },
+    #[error("invalid use of macro {name:?}: {reason}")]
+    InvalidMacro {
+        span: Span,
+        name: String,
+        reason: String,
+        path: PathBuf,
+    },
+    #[error("{}", _0.error)]
+    InMacroExpansion(Box<MacroExpansion>),
+    /// The errors of several items, lowering goes on with the next item after one has an error.
+    /// It's never empty, see [`LoweringError::from_errors`].
+    #[error("{} errors, the first one: {}", _0.len(), _0[0])]
+    Multiple(Vec<LoweringError>),
+    #[error("can't cast {from} to {to}")]
+    InvalidCast {
+        span: Span,
+        from: String,
+        to: String,
+        /// How the conversion is written instead, if it can be.
+        help: Option<&'static str>,
+        path: PathBuf,
+    },
+    #[error("the literal doesn't fit in {ty}")]
+    LiteralOutOfRange {
+        span: Span,
+        ty: String,
+        /// The smallest and the largest values of the type.
+        range: (String, String),
+        path: PathBuf,
+    },
+    #[error("missing fields {fields:?} in the initialization of {name}")]
+    MissingFields {
+        span: Span,
+        name: String,
+        fields: Vec<String>,
+        path: PathBuf,
+    },
+    #[error("no function {name:?} in the impl blocks of {ty}")]
+    MethodNotFound {
+        span: Span,
+        name: String,
+        ty: String,
+        path: PathBuf,
+    },
+    #[error("{name:?} doesn't take self, it can't be called as a method")]
+    NotAMethod {
+        span: Span,
+        name: String,
+        ty: String,
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct UnexpectedTypeError {
+    pub found_span: Span,
+    pub found: String,
+    pub expected: String,
+    /// What imposed the expected type, like the declared type of a variable.
+    pub expected_span: Option<Span>,
+    /// The file of the expected span when it's not the one of the error, like the parameter of a
+    /// function declared in another file.
+    pub expected_path: Option<PathBuf>,
+    /// Where the types differ, when they only differ inside a compound type.
+    pub difference: Option<TypeDifference>,
+    pub path: PathBuf,
 }
 
 #[derive(Debug, Clone)]
@@ -152,6 +356,24 @@ pub struct MissingVariantError {
     pub path: PathBuf,
 }
 
+/// The part that differs between two types with the same structure, like `u8` and `i8` in
+/// `&mut [u8; 4]` and `&mut [i8; 4]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeDifference {
+    /// The common structure, with a `_` where the types differ, like `&mut [_; 4]`.
+    pub context: String,
+    pub expected: String,
+    pub found: String,
+}
+
+impl TypeDifference {
+    /// Nests the difference inside an outer type, built from the current context.
+    pub(crate) fn within(mut self, outer: impl FnOnce(&str) -> String) -> Self {
+        self.context = outer(&self.context);
+        self
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MissingTraitType {
     pub trait_name: String,
@@ -200,6 +422,44 @@ pub struct CantInferType {
     pub path: PathBuf,
 }
 
+/// A glob import of a name the importing module already has for another item.
+#[derive(Debug, Clone)]
+pub struct AmbiguousImport {
+    /// The span of the `*` of the glob import.
+    pub span: Span,
+    pub name: String,
+    pub path: PathBuf,
+    /// The definition of the item the module already has.
+    pub def_span: Span,
+    pub def_path: PathBuf,
+}
+
+/// An error found in the code a macro expanded to, reported with the call and the definition of the
+/// macro.
+#[derive(Debug, Clone)]
+pub struct MacroExpansion {
+    pub error: LoweringError,
+    pub name: String,
+    pub call_span: Span,
+    pub path: PathBuf,
+    pub def_span: Span,
+    pub def_path: PathBuf,
+}
+
+/// A use of an item without `pub` outside of the module it's defined in.
+#[derive(Debug, Clone)]
+pub struct PrivateItem {
+    pub span: Span,
+    /// The kind of item, like `function` or `struct`.
+    pub kind: String,
+    pub name: String,
+    pub path: PathBuf,
+    pub def_span: Span,
+    pub def_path: PathBuf,
+    /// Where `pub` goes in the definition.
+    pub vis_span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct TraitBoundNotMet {
     pub trait_name: String,
@@ -210,3 +470,201 @@ pub struct TraitBoundNotMet {
     pub param_span: Span,
     pub path: PathBuf,
 }
+
+impl LoweringError {
+    /// The error of the errors found, `None` if there are none. Errors told apart only by the
+    /// item they were found lowering, like the ones of a function also found lowering its callers,
+    /// are kept once.
+    pub fn from_errors(errors: Vec<LoweringError>) -> Option<Self> {
+        let mut distinct: Vec<LoweringError> = Vec::new();
+        for error in errors.into_iter().flat_map(LoweringError::into_errors) {
+            let is_duplicate = distinct.iter().any(|x| {
+                x.code() == error.code()
+                    && x.primary_span() == error.primary_span()
+                    && x.to_string() == error.to_string()
+            });
+            if !is_duplicate {
+                distinct.push(error);
+            }
+        }
+
+        match distinct.len() {
+            0 => None,
+            1 => distinct.pop(),
+            _ => Some(LoweringError::Multiple(distinct)),
+        }
+    }
+
+    /// The errors this one holds, in the order they were found.
+    pub fn into_errors(self) -> Vec<LoweringError> {
+        match self {
+            LoweringError::Multiple(errors) => errors,
+            error => vec![error],
+        }
+    }
+
+    /// The stable code of this error, as shown in the rendered report.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LoweringError::ModuleNotFound { .. } => "ModuleNotFound",
+            LoweringError::TraitNotFound { .. } => "TraitNotFound",
+            LoweringError::MissingTraitType(_) => "MissingTraitType",
+            LoweringError::UnexpectedTraitType(_) => "UnexpectedTraitType",
+            LoweringError::MissingTraitFunction(_) => "MissingTraitFunction",
+            LoweringError::UnexpectedTraitFunction(_) => "UnexpectedTraitFunction",
+            LoweringError::CantInferType(_) => "CantInferType",
+            LoweringError::TraitBoundNotMet(_) => "TraitBoundNotMet",
+            LoweringError::FunctionNotFound { .. } => "FunctionNotFound",
+            LoweringError::FieldNotFound { .. } => "FieldNotFound",
+            LoweringError::VariantNotFound { .. } => "VariantNotFound",
+            LoweringError::ImportNotFound { .. } => "ImportNotFound",
+            LoweringError::AmbiguousImport(_) => "AmbiguousImport",
+            LoweringError::PrivateItem(_) => "PrivateItem",
+            LoweringError::UseOfUndeclaredVariable { .. } => "UseOfUndeclaredVariable",
+            LoweringError::BorrowNotMutable { .. } => "BorrowNotMutable",
+            LoweringError::NotMutable { .. } => "NotMutable",
+            LoweringError::CantTakeMutableBorrow { .. } => "CantTakeMutableBorrow",
+            LoweringError::UnrecognizedType { .. } => "UnrecognizedType",
+            LoweringError::NotYetImplemented { .. } => "NotYetImplemented",
+            LoweringError::UnexpectedType(_) => "UnexpectedType",
+            LoweringError::InvalidUnaryOp { .. } => "InvalidUnaryOp",
+            LoweringError::ExternFnWithBody { .. } => "ExternFnWithBody",
+            LoweringError::CallParamCountMismatch { .. } => "CallParamCountMismatch",
+            LoweringError::GenericCountMismatch { .. } => "GenericCountMismatch",
+            LoweringError::UnknownLangItem { .. } => "UnknownLangItem",
+            LoweringError::MissingLangItem { .. } => "MissingLangItem",
+            LoweringError::InvalidMatch { .. } => "InvalidMatch",
+            LoweringError::Unimplemented { .. } => "Unimplemented",
+            LoweringError::MissingVariant(_) => "MissingVariant",
+            LoweringError::InvalidAttribute { .. } => "InvalidAttribute",
+            LoweringError::BorrowConflict { .. } => "BorrowConflict",
+            LoweringError::UseOfMovedValue { .. } => "UseOfMovedValue",
+            LoweringError::NotIndexable { .. } => "NotIndexable",
+            LoweringError::NotIterable { .. } => "NotIterable",
+            LoweringError::InvalidTry { .. } => "InvalidTry",
+            LoweringError::InvalidAwait { .. } => "InvalidAwait",
+            LoweringError::InvalidAsyncFn { .. } => "InvalidAsyncFn",
+            LoweringError::InvalidTailCall { .. } => "InvalidTailCall",
+            LoweringError::ReturnInDefer { .. } => "ReturnInDefer",
+            LoweringError::UnsafeOperation { .. } => "UnsafeOperation",
+            LoweringError::RefutablePattern { .. } => "RefutablePattern",
+            LoweringError::UnknownAbi { .. } => "UnknownAbi",
+            LoweringError::NotConst { .. } => "NotConst",
+            LoweringError::DuplicateDiscriminant { .. } => "DuplicateDiscriminant",
+            LoweringError::DiscriminantOverflow { .. } => "DiscriminantOverflow",
+            LoweringError::RecursiveTypeAlias { .. } => "RecursiveTypeAlias",
+            LoweringError::InvalidMacro { .. } => "InvalidMacro",
+            LoweringError::InMacroExpansion(expansion) => expansion.error.code(),
+            LoweringError::Multiple(errors) => errors[0].code(),
+            LoweringError::InvalidCast { .. } => "InvalidCast",
+            LoweringError::LiteralOutOfRange { .. } => "LiteralOutOfRange",
+            LoweringError::MissingFields { .. } => "MissingFields",
+            LoweringError::MethodNotFound { .. } => "MethodNotFound",
+            LoweringError::NotAMethod { .. } => "NotAMethod",
+        }
+    }
+
+    /// The suggestions to fix this error, they apply to the file of the primary span unless they
+    /// have a path of their own.
+    pub fn suggestions(&self) -> Vec<Suggestion> {
+        match self {
+            LoweringError::NotMutable { suggestion, .. }
+            | LoweringError::CantTakeMutableBorrow { suggestion, .. }
+            | LoweringError::VariantNotFound { suggestion, .. } => {
+                suggestion.iter().map(|x| x.as_ref().clone()).collect()
+            }
+            LoweringError::PrivateItem(error) => vec![Suggestion {
+                message: format!(
+                    "make `{}` public to use it outside of the module it's defined in",
+                    error.name
+                ),
+                path: (error.def_path != error.path).then(|| error.def_path.clone()),
+                span: error.vis_span,
+                replacement: "pub ".to_string(),
+                applicability: Applicability::MachineApplicable,
+            }],
+            // The code they'd change may come from the definition of the macro, not the call.
+            LoweringError::InMacroExpansion(_) => Vec::new(),
+            LoweringError::Multiple(errors) => {
+                let path = self.primary_span().1;
+                let mut suggestions = Vec::new();
+                // The errors may be in other files than the first one.
+                for error in errors {
+                    let error_path = error.primary_span().1;
+                    for suggestion in error.suggestions() {
+                        let file = suggestion.file(error_path).to_path_buf();
+                        suggestions.push(Suggestion {
+                            path: (file != *path).then_some(file),
+                            ..suggestion
+                        });
+                    }
+                }
+                suggestions
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The span and file the report of this error points at.
+    pub fn primary_span(&self) -> (Span, &PathBuf) {
+        match self {
+            LoweringError::ModuleNotFound { span, path, .. }
+            | LoweringError::TraitNotFound { span, path, .. }
+            | LoweringError::FunctionNotFound { span, path, .. }
+            | LoweringError::FieldNotFound { span, path, .. }
+            | LoweringError::VariantNotFound { span, path, .. }
+            | LoweringError::UseOfUndeclaredVariable { span, path, .. }
+            | LoweringError::BorrowNotMutable { span, path, .. }
+            | LoweringError::NotMutable { span, path, .. }
+            | LoweringError::CantTakeMutableBorrow { span, path, .. }
+            | LoweringError::UnrecognizedType { span, path, .. }
+            | LoweringError::NotYetImplemented { span, path, .. }
+            | LoweringError::ExternFnWithBody { span, path, .. }
+            | LoweringError::CallParamCountMismatch { span, path, .. }
+            | LoweringError::GenericCountMismatch { span, path, .. }
+            | LoweringError::UnknownLangItem { span, path, .. }
+            | LoweringError::MissingLangItem { span, path, .. }
+            | LoweringError::InvalidMatch { span, path, .. }
+            | LoweringError::Unimplemented { span, path, .. }
+            | LoweringError::InvalidAttribute { span, path, .. }
+            | LoweringError::BorrowConflict { span, path, .. }
+            | LoweringError::UseOfMovedValue { span, path, .. }
+            | LoweringError::NotIndexable { span, path, .. }
+            | LoweringError::NotIterable { span, path, .. }
+            | LoweringError::InvalidTry { span, path, .. }
+            | LoweringError::InvalidAwait { span, path, .. }
+            | LoweringError::InvalidAsyncFn { span, path, .. }
+            | LoweringError::InvalidTailCall { span, path, .. }
+            | LoweringError::ReturnInDefer { span, path, .. }
+            | LoweringError::UnsafeOperation { span, path, .. }
+            | LoweringError::RefutablePattern { span, path, .. }
+            | LoweringError::UnknownAbi { span, path, .. }
+            | LoweringError::NotConst { span, path, .. }
+            | LoweringError::DuplicateDiscriminant { span, path, .. }
+            | LoweringError::DiscriminantOverflow { span, path, .. }
+            | LoweringError::RecursiveTypeAlias { span, path, .. }
+            | LoweringError::InvalidMacro { span, path, .. }
+            | LoweringError::InvalidCast { span, path, .. }
+            | LoweringError::LiteralOutOfRange { span, path, .. }
+            | LoweringError::MissingFields { span, path, .. }
+            | LoweringError::MethodNotFound { span, path, .. }
+            | LoweringError::NotAMethod { span, path, .. } => (*span, path),
+            LoweringError::InvalidUnaryOp {
+                found_span, path, ..
+            } => (*found_span, path),
+            LoweringError::ImportNotFound { symbol, path, .. } => (symbol.span, path),
+            LoweringError::MissingTraitType(error) => (error.impl_trait_span, &error.path),
+            LoweringError::UnexpectedTraitType(error) => (error.impl_trait_span, &error.path),
+            LoweringError::MissingTraitFunction(error)
+            | LoweringError::UnexpectedTraitFunction(error) => (error.impl_trait_span, &error.path),
+            LoweringError::CantInferType(error) => (error.span, &error.path),
+            LoweringError::AmbiguousImport(error) => (error.span, &error.path),
+            LoweringError::PrivateItem(error) => (error.span, &error.path),
+            LoweringError::TraitBoundNotMet(error) => (error.func_name_span, &error.path),
+            LoweringError::MissingVariant(error) => (error.match_span, &error.path),
+            LoweringError::UnexpectedType(error) => (error.found_span, &error.path),
+            LoweringError::InMacroExpansion(expansion) => expansion.error.primary_span(),
+            LoweringError::Multiple(errors) => errors[0].primary_span(),
+        }
+    }
+}
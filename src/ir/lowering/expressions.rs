@@ -3,13 +3,18 @@ use std::sync::Arc;
 use tracing::{debug, instrument};
 
 use crate::{
-    ast::expressions::{
-        ArithOp, BinaryOp, BitwiseOp, CmpOp, Expression, LogicOp, PathOp, PathSegment, UnaryOp,
-        ValueExpr,
+    ast::{
+        common::Ident,
+        expressions::{
+            ArithOp, BinaryOp, BitwiseOp, CmpOp, Expression, FnCallOp, LogicOp, PathOp,
+            PathSegment, UnaryOp, ValueExpr,
+        },
     },
+    check::moves::is_linear,
     ir::{
-        ConstKind, ConstValue, FloatTy, IntTy, Local, Mutability, Operand, Place, PlaceElem, Span,
-        Statement, StatementKind, Type, UintTy, ValueTree,
+        AdtIndex, BasicBlock, BlockIndex, ConstKind, ConstValue, FloatTy, IntTy, LangItem, Local,
+        LocalIndex, Mutability, Operand, Place, PlaceElem, Span, Statement, StatementKind,
+        SwitchTargets, Terminator, TerminatorKind, Type, UintTy, UndefinedCheck, ValueTree,
         lowering::{adts::lower_struct, functions::lower_fn_call},
     },
 };
@@ -17,8 +22,11 @@ use crate::{
 use super::{
     FnIrBuilder,
     constants::lower_constant_ref,
-    errors::LoweringError,
+    errors::{LoweringError, UnexpectedTypeError},
+    fmt::lower_format,
     ir::{BinOp, ConstData, LogOp, Rvalue, TypeIndex},
+    macros::unexpanded,
+    statements::lower_return_block,
     types::lower_type,
 };
 
@@ -54,6 +62,14 @@ pub(crate) fn lower_expression(
 
             let ty = builder.builder.get_type(type_idx);
 
+            if matches!(ty, Type::Ptr(..)) && !builder.is_unsafe {
+                return Err(LoweringError::UnsafeOperation {
+                    span: *deref_span,
+                    operation: "dereferencing a raw pointer".to_string(),
+                    path: builder.get_file_path().clone(),
+                });
+            }
+
             let mut place = match value {
                 Rvalue::Ref(_, place) => place,
                 Rvalue::Use(op) => match op {
@@ -66,6 +82,13 @@ pub(crate) fn lower_expression(
             assert!(ty.is_ptr_like(), "not deferenceable");
             let type_idx = ty.get_inner_type().expect("should have inner");
 
+            // Raw pointers are checked for null and misaligned addresses with
+            // `--sanitize undefined`.
+            if matches!(ty, Type::Ptr(..)) {
+                let check = UndefinedCheck::Load(Operand::Place(place.clone()));
+                lower_undefined_check(builder, check, *deref_span);
+            }
+
             place.projection.push(PlaceElem::Deref);
 
             (Rvalue::Use(Operand::Place(place)), type_idx, *deref_span)
@@ -82,6 +105,7 @@ pub(crate) fn lower_expression(
                     return Err(LoweringError::CantTakeMutableBorrow {
                         span: *asref_span,
                         declare_span: builder.body.locals[local].span,
+                        suggestion: builder.suggest_mutable_local(local),
                         path: builder.get_file_path().clone(),
                     });
                 }
@@ -109,12 +133,12 @@ pub(crate) fn lower_expression(
                     };
 
                     builder.statements.push(Statement {
-                        span: None,
+                        span: Some(*asref_span),
                         kind: StatementKind::StorageLive(inner_local),
                     });
 
                     builder.statements.push(Statement {
-                        span: None,
+                        span: Some(*asref_span),
                         kind: StatementKind::Assign(inner_place.clone(), value),
                     });
                     Rvalue::Ref(mutability, inner_place)
@@ -199,15 +223,18 @@ pub(crate) fn lower_expression(
             };
 
             builder.statements.push(Statement {
-                span: None,
+                span: Some(info.span),
                 kind: StatementKind::StorageLive(struct_local),
             });
 
             for (field, value) in info.fields.iter() {
-                let idx = *struct_body
-                    .field_names
-                    .get(&field.name)
-                    .expect("failed to find field");
+                let idx = *struct_body.field_names.get(&field.name).ok_or_else(|| {
+                    LoweringError::FieldNotFound {
+                        span: field.span,
+                        name: field.name.clone(),
+                        path: builder.get_file_path().clone(),
+                    }
+                })?;
                 let mut field_place = place.clone();
                 field_place.projection.push(PlaceElem::Field(idx));
 
@@ -222,6 +249,53 @@ pub(crate) fn lower_expression(
                 });
             }
 
+            // The fields that aren't given are taken from the base, or take their default. The
+            // ones of linear types are moved out of it, leaving it partly consumed.
+            let base = match &info.base {
+                Some(base) => Some(lower_struct_base(builder, &base.value, struct_ty)?),
+                None => None,
+            };
+
+            let mut missing = Vec::new();
+            for (idx, field) in struct_body.fields.iter().enumerate() {
+                if info.fields.keys().any(|x| x.name == field.name) {
+                    continue;
+                }
+
+                let value = match (&base, &field.default) {
+                    (Some(base), _) => {
+                        let mut base_field = base.clone();
+                        base_field.projection.push(PlaceElem::Field(idx));
+                        if is_linear(&builder.builder.ir, field.ty) {
+                            Rvalue::Move(base_field)
+                        } else {
+                            Rvalue::Use(Operand::Place(base_field))
+                        }
+                    }
+                    (None, Some(default)) => Rvalue::Use(Operand::Const(default.clone())),
+                    (None, None) => {
+                        missing.push(field.name.clone());
+                        continue;
+                    }
+                };
+
+                let mut field_place = place.clone();
+                field_place.projection.push(PlaceElem::Field(idx));
+                builder.statements.push(Statement {
+                    span: Some(info.span),
+                    kind: StatementKind::Assign(field_place, value),
+                });
+            }
+
+            if !missing.is_empty() {
+                return Err(LoweringError::MissingFields {
+                    span: info.span,
+                    name: builder.builder.display_typename(struct_ty),
+                    fields: missing,
+                    path: builder.get_file_path().clone(),
+                });
+            }
+
             (Rvalue::Use(Operand::Place(place)), struct_ty, info.span)
         }
         Expression::Cast(value, cast_ty, span) => {
@@ -229,7 +303,26 @@ pub(crate) fn lower_expression(
 
             let new_ty = lower_type(builder.builder, cast_ty)?;
 
-            // todo: check if the cast is valid
+            let from = builder.builder.get_type(ty);
+            let to = builder.builder.get_type(new_ty);
+            if ty != new_ty && !is_valid_cast(from, to) {
+                let help = match (from, to) {
+                    (Type::Int(_) | Type::Uint(_), Type::Bool) => {
+                        Some("`value != 0` tells whether an integer isn't zero")
+                    }
+                    (Type::Float(_), Type::Char) => {
+                        Some("a float is cast to an integer, and the integer to a character")
+                    }
+                    _ => None,
+                };
+                return Err(LoweringError::InvalidCast {
+                    span: *span,
+                    from: builder.builder.display_typename(ty),
+                    to: builder.builder.display_typename(new_ty),
+                    help,
+                    path: builder.get_file_path().clone(),
+                });
+            }
 
             // check if its a use directly, to avoid a temporary.
             let rvalue = match value {
@@ -242,12 +335,12 @@ pub(crate) fn lower_expression(
                     };
 
                     builder.statements.push(Statement {
-                        span: None,
+                        span: Some(*span),
                         kind: StatementKind::StorageLive(inner_local),
                     });
 
                     builder.statements.push(Statement {
-                        span: None,
+                        span: Some(*span),
                         kind: StatementKind::Assign(inner_place.clone(), value),
                     });
                     Rvalue::Cast(Operand::Place(inner_place), new_ty, *span)
@@ -256,6 +349,7 @@ pub(crate) fn lower_expression(
 
             (rvalue, new_ty, *span)
         }
+        Expression::MacroCall(info) => lower_format(builder, info)?,
         Expression::ArrayInit(info) => {
             let element_type_hint =
                 type_hint.and_then(|x| builder.builder.get_type(x).get_inner_type());
@@ -286,7 +380,7 @@ pub(crate) fn lower_expression(
                 projection: Default::default(),
             };
             builder.statements.push(Statement {
-                span: None,
+                span: Some(info.span),
                 kind: StatementKind::StorageLive(array_local),
             });
 
@@ -318,6 +412,46 @@ pub(crate) fn lower_expression(
 
             (Rvalue::Use(Operand::Place(place)), ty, info.span)
         }
+        Expression::Try(inner, span) => lower_try(builder, inner, *span)?,
+        Expression::Await(inner, span) => lower_await(builder, inner, *span)?,
+        Expression::Tuple(values, span) => {
+            let field_hints = match type_hint.map(|x| builder.builder.get_type(x)) {
+                Some(Type::Tuple(fields)) if fields.len() == values.len() => {
+                    fields.iter().copied().map(Some).collect()
+                }
+                _ => vec![None; values.len()],
+            };
+
+            let mut fields = Vec::with_capacity(values.len());
+            let mut field_values = Vec::with_capacity(values.len());
+            for (value, hint) in values.iter().zip(field_hints) {
+                let (value, value_ty, _value_span) = lower_expression(builder, value, hint)?;
+                fields.push(value_ty);
+                field_values.push(value);
+            }
+
+            let ty = builder.builder.ir.types.insert(Some(Type::Tuple(fields)));
+            let tuple_local = builder.add_local(Local::temp(ty));
+            let place = Place {
+                local: tuple_local,
+                projection: Default::default(),
+            };
+            builder.statements.push(Statement {
+                span: Some(*span),
+                kind: StatementKind::StorageLive(tuple_local),
+            });
+
+            for (idx, value) in field_values.into_iter().enumerate() {
+                let mut field_place = place.clone();
+                field_place.projection.push(PlaceElem::Field(idx));
+                builder.statements.push(Statement {
+                    span: Some(*span),
+                    kind: StatementKind::Assign(field_place, value),
+                });
+            }
+
+            (Rvalue::Use(Operand::Place(place)), ty, *span)
+        }
         Expression::EnumInit(info) => {
             debug!("lowering enum init for struct {}", info.name);
 
@@ -333,14 +467,11 @@ pub(crate) fn lower_expression(
             };
 
             builder.statements.push(Statement {
-                span: None,
+                span: Some(info.span),
                 kind: StatementKind::StorageLive(enum_local),
             });
 
-            let variant_idx = *enum_body
-                .variant_names
-                .get(&info.variant.name)
-                .expect("variant not found");
+            let variant_idx = builder.builder.get_variant_idx(enum_idx, &info.variant)?;
 
             // add tag at start.
             {
@@ -355,7 +486,7 @@ pub(crate) fn lower_expression(
                             ty: builder.builder.ir.get_u64_ty(),
                             span: info.span,
                             data: ConstKind::Value(ValueTree::Leaf(ConstValue::U64(
-                                variant_idx as u64,
+                                enum_body.discriminant(variant_idx).into(),
                             ))),
                         })),
                     ),
@@ -407,9 +538,13 @@ pub(crate) fn find_expression_span(info: &Expression) -> Span {
         Expression::StructInit(struct_init_expr) => struct_init_expr.span,
         Expression::EnumInit(enum_init_expr) => enum_init_expr.span,
         Expression::ArrayInit(array_init_expr) => array_init_expr.span,
+        Expression::Tuple(_, span) => *span,
+        Expression::Try(_, span) => *span,
+        Expression::Await(_, span) => *span,
         Expression::Deref(_, span) => *span,
         Expression::AsRef(_, _, span) => *span,
         Expression::Cast(_, _, span) => *span,
+        Expression::MacroCall(macro_call) => macro_call.span,
     }
 }
 
@@ -424,7 +559,7 @@ pub(crate) fn find_expression_type(
             ValueExpr::ConstChar(_, _) => Some(fn_builder.builder.ir.get_char_ty()),
             ValueExpr::ConstInt(_, _) => None,
             ValueExpr::ConstFloat(_, _) => None,
-            ValueExpr::ConstStr(_, _) => Some(fn_builder.builder.ir.get_string_ty()),
+            ValueExpr::ConstStr(_, _) => fn_builder.builder.ir.get_string_ty(),
             ValueExpr::Path(info) => {
                 let local = *fn_builder.name_to_local.get(&info.first.name).ok_or(
                     LoweringError::UseOfUndeclaredVariable {
@@ -446,13 +581,17 @@ pub(crate) fn find_expression_type(
                 let mut ty = fn_builder.builder.get_type(type_idx).clone();
                 let old_generics = fn_builder.builder.context.generics_mapping.clone();
 
-                if let Type::Adt(adt_index) = ty {
+                let mut adt_type_idx = type_idx;
+                while let Type::Ref(inner, _) = fn_builder.builder.get_type(adt_type_idx) {
+                    adt_type_idx = *inner;
+                }
+                if let Type::Adt(adt_index) = *fn_builder.builder.get_type(adt_type_idx) {
                     let poly_idx = fn_builder
                         .builder
                         .mono_type_to_poly
-                        .get(&type_idx)
+                        .get(&adt_type_idx)
                         .copied()
-                        .unwrap_or(type_idx);
+                        .unwrap_or(adt_type_idx);
                     let poly_adt_idx = if let Type::Adt(id) = fn_builder.builder.get_type(poly_idx)
                     {
                         *id
@@ -522,6 +661,10 @@ pub(crate) fn find_expression_type(
                                     })?;
                                 type_idx = struct_body.fields[idx].ty;
                                 ty = fn_builder.builder.get_type(type_idx).clone();
+                            } else if let Type::Tuple(fields) = &ty {
+                                let idx = tuple_field_index(fn_builder, fields, name, *field_span)?;
+                                type_idx = fields[idx];
+                                ty = fn_builder.builder.get_type(type_idx).clone();
                             }
                         }
                         PathSegment::ArrayIndex(_expression, _) => {
@@ -530,12 +673,28 @@ pub(crate) fn find_expression_type(
                                 ty = fn_builder.builder.get_type(type_idx).clone();
                             }
 
-                            if let Type::Array(element_type, _) = ty {
+                            if let Type::Array(element_type, _) | Type::Slice(element_type) = ty {
                                 type_idx = element_type;
                                 ty = fn_builder.builder.get_type(type_idx).clone();
                             }
                         }
+                        PathSegment::Slice(..) => {
+                            while let Type::Ref(inner, _) = ty {
+                                type_idx = inner;
+                                ty = fn_builder.builder.get_type(type_idx).clone();
+                            }
+
+                            if let Type::Array(element_type, _) | Type::Slice(element_type) = ty {
+                                ty = Type::Slice(element_type);
+                                type_idx = fn_builder.builder.ir.types.insert(Some(ty.clone()));
+                            }
+                        }
                         PathSegment::MethodCall(fn_call_op, _span) => {
+                            while let Type::Ref(inner, _) = ty {
+                                type_idx = inner;
+                                ty = fn_builder.builder.get_type(type_idx).clone();
+                            }
+
                             let (poly_id, mono_id) =
                                 fn_builder.get_id_for_fn_call(fn_call_op, Some(type_idx))?;
                             let body = fn_builder.builder.get_function(mono_id.unwrap_or(poly_id));
@@ -553,17 +712,16 @@ pub(crate) fn find_expression_type(
         },
         Expression::FnCall(info) => {
             let (poly_fn_id, mono_fn_id) = fn_builder.get_id_for_fn_call(info, None)?;
-            let ret_ty = fn_builder.builder.ir.functions[mono_fn_id.unwrap_or(poly_fn_id)]
+            // A callee that isn't lowered yet, or failed to, has no known type yet.
+            fn_builder.builder.ir.functions[mono_fn_id.unwrap_or(poly_fn_id)]
                 .as_ref()
-                .unwrap()
-                .ret_ty;
-            Some(ret_ty)
+                .map(|x| x.ret_ty)
         }
         Expression::Match(_) => None,
         Expression::If(_) => None,
         Expression::UnaryOp(_, info) => find_expression_type(fn_builder, info)?,
         Expression::BinaryOp(lhs, op, rhs) => {
-            if matches!(op, BinaryOp::Logic(_)) {
+            if matches!(op, BinaryOp::Logic(_) | BinaryOp::Compare(_)) {
                 Some(fn_builder.builder.ir.get_bool_ty())
             } else {
                 find_expression_type(fn_builder, lhs)?.or(find_expression_type(fn_builder, rhs)?)
@@ -620,12 +778,9 @@ pub(crate) fn find_expression_type(
             let type_idx = lower_type(fn_builder.builder, &info.assoc_type.clone().into())?;
             let (poly_fn_id, mono_fn_id) =
                 fn_builder.get_id_for_fn_call(&info.fn_call, Some(type_idx))?;
-            let ret_ty = fn_builder.builder.ir.functions[mono_fn_id.unwrap_or(poly_fn_id)]
+            fn_builder.builder.ir.functions[mono_fn_id.unwrap_or(poly_fn_id)]
                 .as_ref()
-                .unwrap()
-                .ret_ty;
-
-            Some(ret_ty)
+                .map(|x| x.ret_ty)
         }
         Expression::Cast(_, cast_ty, _) => {
             let new_ty = lower_type(fn_builder.builder, cast_ty)?;
@@ -663,9 +818,41 @@ pub(crate) fn find_expression_type(
 
             Some(ty)
         }
+        Expression::Tuple(values, _) => {
+            let mut fields = Vec::with_capacity(values.len());
+            for value in values {
+                match find_expression_type(fn_builder, value)? {
+                    Some(ty) => fields.push(ty),
+                    None => return Ok(None),
+                }
+            }
+
+            Some(
+                fn_builder
+                    .builder
+                    .ir
+                    .types
+                    .insert(Some(Type::Tuple(fields))),
+            )
+        }
+        Expression::Try(inner, _) => find_expression_type(fn_builder, inner)?
+            .and_then(|ty| try_lang_item(fn_builder, ty))
+            .map(|(adt_idx, lang_item)| {
+                let adt = fn_builder.builder.get_adt(adt_idx);
+                let unwrapped = adt.variant_names[lang_item.variants()[0].0];
+                adt.variants[unwrapped].fields[0].ty
+            }),
+        Expression::Await(inner, _) => find_expression_type(fn_builder, inner)?
+            .and_then(|ty| fn_builder.builder.get_future_output_ty(ty)),
         Expression::EnumInit(_enum_init) => {
             todo!()
         }
+        Expression::MacroCall(info) if info.name.name == "format" => {
+            fn_builder.builder.ir.get_string_ty()
+        }
+        Expression::MacroCall(info) => {
+            return Err(unexpanded(info, fn_builder.get_file_path()));
+        }
     })
 }
 
@@ -692,69 +879,7 @@ pub(crate) fn lower_value_expr(
             fn_builder.builder.ir.get_char_ty(),
         ),
         ValueExpr::ConstInt(value, const_span) => {
-            let (data, ty) = match type_hint {
-                Some(type_idx) => (
-                    {
-                        let ty = fn_builder.builder.get_type(type_idx);
-                        ConstData {
-                            ty: type_idx,
-                            span: *const_span,
-                            data: ConstKind::Value(ValueTree::Leaf(match ty {
-                                Type::Int(ty) => match ty {
-                                    IntTy::I8 => ConstValue::I8(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    IntTy::I16 => ConstValue::I16(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    IntTy::I32 => ConstValue::I32(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    IntTy::I64 => ConstValue::I64(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    IntTy::I128 => ConstValue::I128(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                },
-                                Type::Uint(ty) => match ty {
-                                    UintTy::U8 => ConstValue::U8(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    UintTy::U16 => ConstValue::U16(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    UintTy::U32 => ConstValue::U32(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    UintTy::U64 => ConstValue::U64(
-                                        (*value).try_into().expect("value out of range"),
-                                    ),
-                                    UintTy::U128 => ConstValue::U128(*value),
-                                },
-                                Type::Bool => ConstValue::Bool(*value != 0),
-                                Type::Ptr(_inner, _mutable) => ConstValue::I64(
-                                    (*value).try_into().expect("value out of range"),
-                                ),
-                                x => unreachable!("{:?}", x),
-                            })),
-                        }
-                    },
-                    type_idx,
-                ),
-                None => (
-                    ConstData {
-                        ty: fn_builder.builder.ir.get_i64_ty(),
-                        span: *const_span,
-                        data: ConstKind::Value(ValueTree::Leaf(ConstValue::I64(
-                            (*value).try_into().expect("value out of range"),
-                        ))),
-                    },
-                    fn_builder.builder.ir.get_i64_ty(),
-                ),
-            };
-
-            (Rvalue::Use(Operand::Const(data)), ty)
+            lower_int_literal(fn_builder, *value, false, *const_span, type_hint)?
         }
         ValueExpr::ConstFloat(value, const_span) => {
             let (data, ty) = match type_hint {
@@ -794,7 +919,13 @@ pub(crate) fn lower_value_expr(
             (Rvalue::Use(Operand::Const(data)), ty)
         }
         ValueExpr::ConstStr(value, span) => {
-            let ty = fn_builder.builder.ir.get_string_ty();
+            let ty = fn_builder.builder.ir.get_string_ty().ok_or_else(|| {
+                LoweringError::MissingLangItem {
+                    span: *span,
+                    item: "String".to_string(),
+                    path: fn_builder.get_file_path().clone(),
+                }
+            })?;
             let data = ConstData {
                 ty,
                 span: *span,
@@ -814,6 +945,24 @@ pub(crate) fn lower_value_expr(
     })
 }
 
+/// The position of a tuple field accessed as `.0`, `.1`, ...
+fn tuple_field_index(
+    fn_builder: &FnIrBuilder,
+    fields: &[TypeIndex],
+    name: &Ident,
+    span: Span,
+) -> Result<usize, LoweringError> {
+    name.name
+        .parse::<usize>()
+        .ok()
+        .filter(|idx| *idx < fields.len())
+        .ok_or_else(|| LoweringError::FieldNotFound {
+            span,
+            name: name.name.clone(),
+            path: fn_builder.get_file_path().clone(),
+        })
+}
+
 #[instrument(level = "debug", skip_all, fields(first = ?info.first.name))]
 pub(crate) fn lower_path(
     fn_builder: &mut FnIrBuilder,
@@ -841,13 +990,18 @@ pub(crate) fn lower_path(
     let mut projection = Vec::new();
     let old_generics = fn_builder.builder.context.generics_mapping.clone();
 
-    if let Type::Adt(adt_index) = ty {
+    // The generics of an ADT behind references are the ones of its methods too.
+    let mut adt_type_idx = type_idx;
+    while let Type::Ref(inner, _) = fn_builder.builder.get_type(adt_type_idx) {
+        adt_type_idx = *inner;
+    }
+    if let Type::Adt(adt_index) = *fn_builder.builder.get_type(adt_type_idx) {
         let poly_idx = fn_builder
             .builder
             .mono_type_to_poly
-            .get(&type_idx)
+            .get(&adt_type_idx)
             .copied()
-            .unwrap_or(type_idx);
+            .unwrap_or(adt_type_idx);
         let poly_adt_idx = if let Type::Adt(id) = fn_builder.builder.get_type(poly_idx) {
             *id
         } else {
@@ -910,31 +1064,44 @@ pub(crate) fn lower_path(
                     projection.push(PlaceElem::Field(idx));
                     type_idx = struct_body.fields[idx].ty;
                     ty = fn_builder.builder.get_type(type_idx).clone();
+                } else if let Type::Tuple(fields) = &ty {
+                    let idx = tuple_field_index(fn_builder, fields, name, *field_span)?;
+                    projection.push(PlaceElem::Field(idx));
+                    type_idx = fields[idx];
+                    ty = fn_builder.builder.get_type(type_idx).clone();
                 }
             }
-            PathSegment::ArrayIndex(expression, _) => {
+            PathSegment::ArrayIndex(expression, index_span) => {
                 while let Type::Ref(inner, _) = ty {
                     projection.push(PlaceElem::Deref);
                     type_idx = inner;
                     ty = fn_builder.builder.get_type(type_idx).clone();
                 }
 
-                if let Type::Array(element_type, _) = ty {
+                if let Type::Array(element_type, _) | Type::Slice(element_type) = ty {
                     // Assign the index expression to a temporary local
-                    let (index, index_type_idx) = lower_value_expr(fn_builder, expression, None)?;
-                    let index_local = fn_builder.add_temp_local(index_type_idx);
-                    let index_place = Place {
-                        local: index_local,
-                        projection: vec![],
-                    };
-                    fn_builder.statements.push(Statement {
-                        span: None,
-                        kind: StatementKind::StorageLive(index_local),
-                    });
-                    fn_builder.statements.push(Statement {
-                        span: None,
-                        kind: StatementKind::Assign(index_place.clone(), index),
-                    });
+                    let index_local = lower_index(fn_builder, expression, *index_span)?;
+                    let len = lower_len(
+                        fn_builder,
+                        Place {
+                            local,
+                            projection: projection.clone(),
+                        },
+                        &ty,
+                        *index_span,
+                    );
+                    lower_bounds_check(
+                        fn_builder,
+                        BinOp::Lt,
+                        (
+                            Operand::Place(Place {
+                                local: index_local,
+                                projection: vec![],
+                            }),
+                            len,
+                        ),
+                        *index_span,
+                    );
 
                     // Use the local's value as index of the array
                     projection.push(PlaceElem::Index(index_local));
@@ -943,8 +1110,86 @@ pub(crate) fn lower_path(
                     ty = fn_builder.builder.get_type(type_idx).clone();
                 }
             }
+            PathSegment::Slice(start, end, slice_span) => {
+                while let Type::Ref(inner, _) = ty {
+                    projection.push(PlaceElem::Deref);
+                    type_idx = inner;
+                    ty = fn_builder.builder.get_type(type_idx).clone();
+                }
+
+                let (Type::Array(element_type, _) | Type::Slice(element_type)) = ty else {
+                    return Err(LoweringError::NotIndexable {
+                        span: *slice_span,
+                        found: fn_builder.builder.display_typename(type_idx),
+                        path: fn_builder.get_file_path().clone(),
+                    });
+                };
+
+                let place = Place {
+                    local,
+                    projection: std::mem::take(&mut projection),
+                };
+                let len = lower_len(fn_builder, place.clone(), &ty, *slice_span);
+
+                // The bounds must be ordered and within the length: start <= end <= len.
+                let end = match end {
+                    Some(end) => {
+                        let end = Operand::Place(Place {
+                            local: lower_index(fn_builder, end, *slice_span)?,
+                            projection: vec![],
+                        });
+                        lower_bounds_check(fn_builder, BinOp::Le, (end.clone(), len), *slice_span);
+                        end
+                    }
+                    None => len,
+                };
+                let start = match start {
+                    Some(start) => {
+                        let start = Operand::Place(Place {
+                            local: lower_index(fn_builder, start, *slice_span)?,
+                            projection: vec![],
+                        });
+                        lower_bounds_check(
+                            fn_builder,
+                            BinOp::Le,
+                            (start.clone(), end.clone()),
+                            *slice_span,
+                        );
+                        start
+                    }
+                    None => Operand::Const(ConstData {
+                        ty: fn_builder.builder.ir.get_u64_ty(),
+                        span: *slice_span,
+                        data: ConstKind::Value(ValueTree::Leaf(ConstValue::U64(0))),
+                    }),
+                };
+
+                ty = Type::Slice(element_type);
+                type_idx = fn_builder.builder.ir.types.insert(Some(ty.clone()));
+                local = fn_builder.add_temp_local(type_idx);
+                fn_builder.statements.push(Statement {
+                    span: Some(*slice_span),
+                    kind: StatementKind::StorageLive(local),
+                });
+                fn_builder.statements.push(Statement {
+                    span: Some(*slice_span),
+                    kind: StatementKind::Assign(
+                        Place {
+                            local,
+                            projection: vec![],
+                        },
+                        Rvalue::Slice(place, start, end, type_idx),
+                    ),
+                });
+            }
             PathSegment::MethodCall(fn_call_op, _span) => {
-                // TODO: auto deref?
+                // auto deref, the methods are looked up on the referenced type.
+                while let Type::Ref(inner, _) = ty {
+                    projection.push(PlaceElem::Deref);
+                    type_idx = inner;
+                    ty = fn_builder.builder.get_type(type_idx).clone();
+                }
+
                 let (value, new_type_idx, _span) = lower_fn_call(
                     fn_builder,
                     fn_call_op,
@@ -981,12 +1226,739 @@ pub(crate) fn lower_path(
     Ok((Place { local, projection }, type_idx, info.span))
 }
 
+/// Lowers the index of an array or slice into a temporary u64 local, negative indexes become big
+/// ones that are out of bounds.
+fn lower_index(
+    fn_builder: &mut FnIrBuilder,
+    expression: &ValueExpr,
+    span: Span,
+) -> Result<LocalIndex, LoweringError> {
+    let u64_ty = fn_builder.builder.ir.get_u64_ty();
+    let (index, index_ty) = lower_value_expr(fn_builder, expression, Some(u64_ty))?;
+
+    let index = match (fn_builder.builder.get_type(index_ty), index) {
+        (Type::Uint(UintTy::U64), index) => index,
+        (Type::Int(_) | Type::Uint(_), Rvalue::Use(op)) => Rvalue::Cast(op, u64_ty, span),
+        _ => {
+            return Err(LoweringError::UnexpectedType(Box::new(
+                UnexpectedTypeError {
+                    found_span: span,
+                    found: fn_builder.builder.display_typename(index_ty),
+                    expected: fn_builder.builder.display_typename(u64_ty),
+                    expected_span: None,
+                    expected_path: None,
+                    difference: None,
+                    path: fn_builder.get_file_path().clone(),
+                },
+            )));
+        }
+    };
+
+    let index_local = fn_builder.add_temp_local(u64_ty);
+    fn_builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::StorageLive(index_local),
+    });
+    fn_builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(
+            Place {
+                local: index_local,
+                projection: vec![],
+            },
+            index,
+        ),
+    });
+
+    Ok(index_local)
+}
+
+/// The number of elements of the array or slice of the given type at the place.
+pub(crate) fn lower_len(
+    fn_builder: &mut FnIrBuilder,
+    place: Place,
+    ty: &Type,
+    span: Span,
+) -> Operand {
+    if let Type::Array(_, size) = ty {
+        return Operand::Const(size.as_ref().clone());
+    }
+
+    let len_local = fn_builder.add_temp_local(fn_builder.builder.ir.get_u64_ty());
+    fn_builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::StorageLive(len_local),
+    });
+    fn_builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(
+            Place {
+                local: len_local,
+                projection: vec![],
+            },
+            Rvalue::Len(place),
+        ),
+    });
+
+    Operand::Place(Place {
+        local: len_local,
+        projection: vec![],
+    })
+}
+
+/// Lowers an integer literal, negated if it's `negative`, as a constant of the hinted type, `i64` by
+/// default.
+fn lower_int_literal(
+    fn_builder: &mut FnIrBuilder,
+    value: u128,
+    negative: bool,
+    span: Span,
+    type_hint: Option<TypeIndex>,
+) -> Result<(Rvalue, TypeIndex), LoweringError> {
+    // A hint of another type is a mismatch the caller reports.
+    let ty = type_hint
+        .filter(|x| {
+            matches!(
+                fn_builder.builder.get_type(*x),
+                Type::Int(_) | Type::Uint(_) | Type::Bool | Type::Ptr(..)
+            )
+        })
+        .unwrap_or_else(|| fn_builder.builder.ir.get_i64_ty());
+    let signed = if negative {
+        (value <= 1 << 127).then(|| (value as i128).wrapping_neg())
+    } else {
+        i128::try_from(value).ok()
+    };
+    let unsigned = (!negative || value == 0).then_some(value);
+
+    let constant = match fn_builder.builder.get_type(ty) {
+        Type::Int(IntTy::I8) => signed.and_then(|x| x.try_into().ok()).map(ConstValue::I8),
+        Type::Int(IntTy::I16) => signed.and_then(|x| x.try_into().ok()).map(ConstValue::I16),
+        Type::Int(IntTy::I32) => signed.and_then(|x| x.try_into().ok()).map(ConstValue::I32),
+        Type::Int(IntTy::I64) | Type::Ptr(..) => {
+            signed.and_then(|x| x.try_into().ok()).map(ConstValue::I64)
+        }
+        Type::Int(IntTy::I128) => signed.map(ConstValue::I128),
+        Type::Uint(UintTy::U8) => unsigned.and_then(|x| x.try_into().ok()).map(ConstValue::U8),
+        Type::Uint(UintTy::U16) => unsigned
+            .and_then(|x| x.try_into().ok())
+            .map(ConstValue::U16),
+        Type::Uint(UintTy::U32) => unsigned
+            .and_then(|x| x.try_into().ok())
+            .map(ConstValue::U32),
+        Type::Uint(UintTy::U64) => unsigned
+            .and_then(|x| x.try_into().ok())
+            .map(ConstValue::U64),
+        Type::Uint(UintTy::U128) => unsigned.map(ConstValue::U128),
+        Type::Bool => Some(ConstValue::Bool(value != 0)),
+        x => unreachable!("{:?}", x),
+    };
+
+    let Some(constant) = constant else {
+        let range = match fn_builder.builder.get_type(ty) {
+            Type::Int(IntTy::I8) => (i8::MIN.to_string(), i8::MAX.to_string()),
+            Type::Int(IntTy::I16) => (i16::MIN.to_string(), i16::MAX.to_string()),
+            Type::Int(IntTy::I32) => (i32::MIN.to_string(), i32::MAX.to_string()),
+            Type::Int(IntTy::I128) => (i128::MIN.to_string(), i128::MAX.to_string()),
+            Type::Uint(UintTy::U8) => ("0".to_string(), u8::MAX.to_string()),
+            Type::Uint(UintTy::U16) => ("0".to_string(), u16::MAX.to_string()),
+            Type::Uint(UintTy::U32) => ("0".to_string(), u32::MAX.to_string()),
+            Type::Uint(UintTy::U64) => ("0".to_string(), u64::MAX.to_string()),
+            Type::Uint(UintTy::U128) => ("0".to_string(), u128::MAX.to_string()),
+            _ => (i64::MIN.to_string(), i64::MAX.to_string()),
+        };
+        return Err(LoweringError::LiteralOutOfRange {
+            span,
+            ty: fn_builder.builder.display_typename(ty),
+            range,
+            path: fn_builder.get_file_path().clone(),
+        });
+    };
+
+    let data = ConstData {
+        ty,
+        span,
+        data: ConstKind::Value(ValueTree::Leaf(constant)),
+    };
+    Ok((Rvalue::Use(Operand::Const(data)), ty))
+}
+
+/// Whether `as` converts values of the type to the other. Numbers convert to each other, and
+/// integers to characters and pointers, while a bool only converts to integers.
+fn is_valid_cast(from: &Type, to: &Type) -> bool {
+    match to {
+        Type::Int(_) | Type::Uint(_) => matches!(
+            from,
+            Type::Int(_)
+                | Type::Uint(_)
+                | Type::Float(_)
+                | Type::Char
+                | Type::Bool
+                | Type::Ptr(..)
+                | Type::Ref(..)
+        ),
+        Type::Float(_) => matches!(from, Type::Int(_) | Type::Uint(_) | Type::Float(_)),
+        Type::Char => matches!(from, Type::Int(_) | Type::Uint(_) | Type::Char),
+        Type::Ptr(..) | Type::Ref(..) => matches!(
+            from,
+            Type::Int(_) | Type::Uint(_) | Type::Ptr(..) | Type::Ref(..) | Type::Array(..)
+        ),
+        _ => false,
+    }
+}
+
+/// Lowers the `..base` of a struct initialization into a place its fields are copied from.
+fn lower_struct_base(
+    builder: &mut FnIrBuilder,
+    base: &Expression,
+    struct_ty: TypeIndex,
+) -> Result<Place, LoweringError> {
+    let (value, base_ty, base_span) = lower_expression(builder, base, Some(struct_ty))?;
+
+    if !builder
+        .builder
+        .get_type(base_ty)
+        .is_equal(builder.builder.get_type(struct_ty), &builder.builder.ir)
+    {
+        return Err(LoweringError::UnexpectedType(Box::new(
+            UnexpectedTypeError {
+                found_span: base_span,
+                found: builder.builder.display_typename(base_ty),
+                expected: builder.builder.display_typename(struct_ty),
+                expected_span: None,
+                expected_path: None,
+                difference: builder.builder.type_difference(struct_ty, base_ty),
+                path: builder.get_file_path().clone(),
+            },
+        )));
+    }
+
+    if let Rvalue::Use(Operand::Place(place)) = value {
+        return Ok(place);
+    }
+
+    let local = builder.add_temp_local(base_ty);
+    let place = Place {
+        local,
+        projection: vec![],
+    };
+    builder.statements.push(Statement {
+        span: Some(base_span),
+        kind: StatementKind::StorageLive(local),
+    });
+    builder.statements.push(Statement {
+        span: Some(base_span),
+        kind: StatementKind::Assign(place.clone(), value),
+    });
+    Ok(place)
+}
+
+/// The enum and lang item of the type, if it's an `Option` or a `Result`.
+fn try_lang_item(builder: &FnIrBuilder, ty: TypeIndex) -> Option<(AdtIndex, LangItem)> {
+    let Type::Adt(adt_idx) = builder.builder.get_type(ty) else {
+        return None;
+    };
+    let lang_item = builder.builder.ir.aggregates[*adt_idx]
+        .as_ref()?
+        .lang_item?;
+    Some((*adt_idx, lang_item))
+}
+
+/// Lowers `value?`, which is the field of the `Some` or `Ok` of the value, or otherwise returns
+/// its `None` or `Err` from the function.
+fn lower_try(
+    builder: &mut FnIrBuilder,
+    inner: &Expression,
+    span: Span,
+) -> Result<(Rvalue, TypeIndex, Span), LoweringError> {
+    let (value, ty, value_span) = lower_expression(builder, inner, None)?;
+
+    let Some((adt_idx, lang_item)) = try_lang_item(builder, ty) else {
+        return Err(LoweringError::InvalidTry {
+            span: value_span,
+            reason: format!(
+                "`?` can't be applied to a value of type `{}`",
+                builder.builder.display_typename(ty)
+            ),
+            path: builder.get_file_path().clone(),
+        });
+    };
+    let [(unwrapped, _), (early, _)] = lang_item.variants();
+
+    let ret_ty = builder.body.locals[builder.ret_local].ty;
+    let ret_adt_idx = match try_lang_item(builder, ret_ty) {
+        Some((ret_adt_idx, ret_lang_item)) if ret_lang_item == lang_item => ret_adt_idx,
+        _ => {
+            return Err(LoweringError::InvalidTry {
+                span,
+                reason: format!(
+                    "`?` returns the `{early}` early, but the function returns `{}`",
+                    builder.builder.display_typename(ret_ty)
+                ),
+                path: builder.get_file_path().clone(),
+            });
+        }
+    };
+
+    let adt = builder.builder.get_adt(adt_idx);
+    let unwrapped_idx = adt.variant_names[unwrapped];
+    let unwrapped_discriminant = adt.discriminant(unwrapped_idx);
+    let value_ty = adt.variants[unwrapped_idx].fields[0].ty;
+    let early_idx = adt.variant_names[early];
+    let early_field = adt.variants[early_idx].fields.first().map(|x| x.ty);
+
+    let ret_adt = builder.builder.get_adt(ret_adt_idx);
+    let ret_early_idx = ret_adt.variant_names[early];
+    let ret_early_discriminant = ret_adt.discriminant(ret_early_idx);
+    let ret_early_field = ret_adt.variants[ret_early_idx].fields.first().map(|x| x.ty);
+
+    // The error is returned as it is, so it must have the type of the function's.
+    if let (Some(field_ty), Some(ret_field_ty)) = (early_field, ret_early_field) {
+        if !builder
+            .builder
+            .get_type(field_ty)
+            .is_equal(builder.builder.get_type(ret_field_ty), &builder.builder.ir)
+        {
+            return Err(LoweringError::UnexpectedType(Box::new(
+                UnexpectedTypeError {
+                    found_span: span,
+                    found: builder.builder.display_typename(field_ty),
+                    expected: builder.builder.display_typename(ret_field_ty),
+                    expected_span: Some(builder.ret_type_span.unwrap_or(span)),
+                    expected_path: None,
+                    difference: builder.builder.type_difference(ret_field_ty, field_ty),
+                    path: builder.get_file_path().clone(),
+                },
+            )));
+        }
+    }
+
+    let place = match value {
+        Rvalue::Use(Operand::Place(place)) => place,
+        value => {
+            let local = builder.add_temp_local(ty);
+            let place = Place {
+                local,
+                projection: vec![],
+            };
+            builder.statements.push(Statement {
+                span: Some(value_span),
+                kind: StatementKind::StorageLive(local),
+            });
+            builder.statements.push(Statement {
+                span: Some(value_span),
+                kind: StatementKind::Assign(place.clone(), value),
+            });
+            place
+        }
+    };
+
+    let tag_local = builder.add_temp_local(builder.builder.ir.get_u32_ty());
+    let tag_place = Place {
+        local: tag_local,
+        projection: vec![],
+    };
+    let mut variant_place = place.clone();
+    variant_place.projection.push(PlaceElem::GetVariant);
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::StorageLive(tag_local),
+    });
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(
+            tag_place.clone(),
+            Rvalue::Use(Operand::Place(variant_place)),
+        ),
+    });
+
+    let cond_block_idx = builder.body.basic_blocks.len();
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Unreachable,
+        }),
+    });
+
+    // The early return builds the `None` or `Err` of the return type, with the error of the value.
+    let ret_place = Place {
+        local: builder.ret_local,
+        projection: vec![PlaceElem::Variant(ret_early_idx)],
+    };
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(
+            ret_place.clone(),
+            Rvalue::Use(Operand::Const(ConstData {
+                ty: builder.builder.ir.get_u64_ty(),
+                span,
+                data: ConstKind::Value(ValueTree::Leaf(ConstValue::U64(
+                    ret_early_discriminant.into(),
+                ))),
+            })),
+        ),
+    });
+    if early_field.is_some() {
+        let mut error_place = ret_place;
+        // Stores into the fields of a variant skip its tag.
+        error_place.projection.push(PlaceElem::Field(1));
+        let mut error_value = place.clone();
+        error_value.projection.push(PlaceElem::Variant(early_idx));
+        error_value.projection.push(PlaceElem::Field(0));
+        builder.statements.push(Statement {
+            span: Some(span),
+            kind: StatementKind::Assign(error_place, Rvalue::Use(Operand::Place(error_value))),
+        });
+    }
+    lower_return_block(builder, span)?;
+
+    let unwrapped_block_idx = builder.body.basic_blocks.len();
+    builder.body.basic_blocks[cond_block_idx].terminator.kind = TerminatorKind::SwitchInt {
+        discriminator: Operand::Place(tag_place),
+        targets: SwitchTargets {
+            values: vec![ValueTree::Leaf(ConstValue::U32(unwrapped_discriminant))],
+            targets: vec![unwrapped_block_idx, cond_block_idx + 1],
+        },
+    };
+
+    let mut unwrapped_place = place;
+    unwrapped_place
+        .projection
+        .push(PlaceElem::Variant(unwrapped_idx));
+    unwrapped_place.projection.push(PlaceElem::Field(0));
+
+    Ok((Rvalue::Use(Operand::Place(unwrapped_place)), value_ty, span))
+}
+
+/// Lowers `await future`, which polls the future until it's ready and is its value, the async
+/// function suspending each time it isn't, to poll it again when it's resumed.
+fn lower_await(
+    builder: &mut FnIrBuilder,
+    inner: &Expression,
+    span: Span,
+) -> Result<(Rvalue, TypeIndex, Span), LoweringError> {
+    if builder.suspends.is_none() {
+        return Err(LoweringError::InvalidAwait {
+            span,
+            reason: "`await` can only be used in async functions".to_string(),
+            path: builder.get_file_path().clone(),
+        });
+    }
+
+    let (value, future_ty, value_span) = lower_expression(builder, inner, None)?;
+    let Some(output_ty) = builder.builder.get_future_output_ty(future_ty) else {
+        return Err(LoweringError::InvalidAwait {
+            span: value_span,
+            reason: format!(
+                "`await` can't be applied to a value of type `{}`",
+                builder.builder.display_typename(future_ty)
+            ),
+            path: builder.get_file_path().clone(),
+        });
+    };
+
+    // The future is moved to a local of its own, it's polled in place.
+    let future_local = builder.add_temp_local(future_ty);
+    let future_place = Place {
+        local: future_local,
+        projection: vec![],
+    };
+    let output_local = builder.add_temp_local(output_ty);
+    let output_place = Place {
+        local: output_local,
+        projection: vec![],
+    };
+    let output_ref_ty = builder
+        .builder
+        .ir
+        .types
+        .insert(Some(Type::Ref(output_ty, Mutability::Mut)));
+    let output_ref_local = builder.add_temp_local(output_ref_ty);
+    let output_ptr_ty = builder
+        .builder
+        .ir
+        .types
+        .insert(Some(Type::Ptr(output_ty, Mutability::Mut)));
+    let output_ptr_local = builder.add_temp_local(output_ptr_ty);
+    let output_ptr_place = Place {
+        local: output_ptr_local,
+        projection: vec![],
+    };
+    for local in [
+        future_local,
+        output_local,
+        output_ref_local,
+        output_ptr_local,
+    ] {
+        builder.statements.push(Statement {
+            span: Some(span),
+            kind: StatementKind::StorageLive(local),
+        });
+    }
+    builder.statements.push(Statement {
+        span: Some(value_span),
+        kind: StatementKind::Assign(future_place.clone(), value),
+    });
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(
+            Place {
+                local: output_ref_local,
+                projection: vec![],
+            },
+            Rvalue::Ref(Mutability::Mut, output_place.clone()),
+        ),
+    });
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(
+            output_ptr_place.clone(),
+            Rvalue::Cast(
+                Operand::Place(Place {
+                    local: output_ref_local,
+                    projection: vec![],
+                }),
+                output_ptr_ty,
+                span,
+            ),
+        ),
+    });
+
+    let poll_call = FnCallOp {
+        path: Vec::new(),
+        target: Ident {
+            name: "poll".to_string(),
+            span,
+        },
+        generics: Vec::new(),
+        args: Vec::new(),
+        span,
+    };
+    let (poll_poly, poll_mono) = builder.get_id_for_fn_call(&poll_call, Some(future_ty))?;
+
+    let poll_block_idx = builder.body.basic_blocks.len() + 1;
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Goto {
+                target: poll_block_idx,
+            },
+        }),
+    });
+
+    let ready_local = builder.add_temp_local(builder.builder.ir.get_bool_ty());
+    let ready_place = Place {
+        local: ready_local,
+        projection: vec![],
+    };
+    builder.body.basic_blocks.push(BasicBlock {
+        statements: Vec::new(),
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Call {
+                func: poll_mono.unwrap_or(poll_poly),
+                args: vec![
+                    Rvalue::Ref(Mutability::Mut, future_place),
+                    Rvalue::Use(Operand::Place(output_ptr_place)),
+                ],
+                destination: ready_place.clone(),
+                target: Some(poll_block_idx + 1),
+            },
+        }),
+    });
+
+    // Until it's ready, the function returns from the block suspending it, and is resumed by
+    // polling the future again.
+    let suspend_block_idx = poll_block_idx + 2;
+    builder.body.basic_blocks.push(BasicBlock {
+        statements: Vec::new(),
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::SwitchInt {
+                discriminator: Operand::Place(ready_place),
+                targets: SwitchTargets {
+                    values: vec![ValueTree::Leaf(ConstValue::Bool(false))],
+                    targets: vec![suspend_block_idx, suspend_block_idx + 1],
+                },
+            },
+        }),
+    });
+    builder.body.basic_blocks.push(BasicBlock {
+        statements: Vec::new(),
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Goto {
+                target: poll_block_idx,
+            },
+        }),
+    });
+    builder
+        .suspends
+        .as_mut()
+        .expect("checked it's an async function")
+        .push(suspend_block_idx);
+
+    Ok((Rvalue::Use(Operand::Place(output_place)), output_ty, span))
+}
+
+/// Ends the current block with a check of the comparison between the index and the length, the
+/// program panics if it doesn't hold.
+fn lower_bounds_check(
+    fn_builder: &mut FnIrBuilder,
+    op: BinOp,
+    (index, len): (Operand, Operand),
+    span: Span,
+) {
+    lower_check(
+        fn_builder,
+        Rvalue::BinaryOp(op, (index.clone(), len.clone())),
+        span,
+        |cond, target| TerminatorKind::BoundsCheck {
+            cond,
+            index,
+            len,
+            target,
+        },
+    );
+}
+
+/// Ends the current block with a check that the arithmetic on the operands doesn't overflow, the
+/// program panics at the operation if it does.
+fn lower_overflow_check(
+    fn_builder: &mut FnIrBuilder,
+    op: BinOp,
+    operands: (Operand, Operand),
+    span: Span,
+) {
+    lower_check(
+        fn_builder,
+        Rvalue::Overflows(op, operands),
+        span,
+        |cond, target| TerminatorKind::OverflowCheck { cond, op, target },
+    );
+}
+
+/// Ends the current block with a check that the operation isn't undefined behavior, continuing in
+/// the next block.
+pub(crate) fn lower_undefined_check(
+    fn_builder: &mut FnIrBuilder,
+    check: UndefinedCheck,
+    span: Span,
+) {
+    let target = fn_builder.body.basic_blocks.len() + 1;
+    let statements = std::mem::take(&mut fn_builder.statements);
+    fn_builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::UndefinedCheck { check, target },
+        }),
+    });
+}
+
+/// Ends the current block with the check of the condition, continuing in the next block.
+fn lower_check(
+    fn_builder: &mut FnIrBuilder,
+    cond: Rvalue,
+    span: Span,
+    check: impl FnOnce(Operand, BlockIndex) -> TerminatorKind,
+) {
+    let cond_local = fn_builder.add_temp_local(fn_builder.builder.ir.get_bool_ty());
+    fn_builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::StorageLive(cond_local),
+    });
+    fn_builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(
+            Place {
+                local: cond_local,
+                projection: vec![],
+            },
+            cond,
+        ),
+    });
+
+    let target = fn_builder.body.basic_blocks.len() + 1;
+    let statements = std::mem::take(&mut fn_builder.statements);
+    let cond = Operand::Place(Place {
+        local: cond_local,
+        projection: vec![],
+    });
+    fn_builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: check(cond, target),
+        }),
+    });
+}
+
+/// Lowers `lhs + rhs` on strings as `lhs.concat(rhs)`, which takes both and returns a new string.
+fn lower_string_concat(
+    builder: &mut FnIrBuilder,
+    lhs: &Expression,
+    rhs: &Expression,
+    string_ty: TypeIndex,
+) -> Result<(Rvalue, TypeIndex, Span), LoweringError> {
+    let (lhs, _, lhs_span) = lower_expression(builder, lhs, Some(string_ty))?;
+    let rhs_span = find_expression_span(rhs);
+
+    let lhs_local = builder.add_temp_local(string_ty);
+    let lhs_place = Place {
+        local: lhs_local,
+        projection: vec![],
+    };
+    builder.statements.push(Statement {
+        span: Some(lhs_span),
+        kind: StatementKind::StorageLive(lhs_local),
+    });
+    builder.statements.push(Statement {
+        span: Some(lhs_span),
+        kind: StatementKind::Assign(lhs_place.clone(), lhs),
+    });
+
+    let span = Span::new(lhs_span.from, rhs_span.to);
+    let concat = FnCallOp {
+        path: Vec::new(),
+        target: Ident {
+            name: "concat".to_string(),
+            span,
+        },
+        generics: Vec::new(),
+        args: vec![rhs.clone()],
+        span,
+    };
+
+    let (value, ty, _) = lower_fn_call(
+        builder,
+        &concat,
+        Some((lhs_place, string_ty)),
+        Some(string_ty),
+    )?;
+
+    Ok((value, ty, span))
+}
+
 pub(crate) fn lower_unary_op(
     builder: &mut FnIrBuilder,
     lhs: &Expression,
     op: UnaryOp,
     type_hint: Option<TypeIndex>,
 ) -> Result<(Rvalue, TypeIndex, Span), LoweringError> {
+    // A negated literal is a constant of its own, `-128` fits in an `i8` even if `128` doesn't.
+    if let (UnaryOp::ArithNeg, Expression::Value(ValueExpr::ConstInt(value, span), _)) = (op, lhs) {
+        let ty = type_hint.unwrap_or_else(|| builder.builder.ir.get_i32_ty());
+        if matches!(builder.builder.get_type(ty), Type::Int(_) | Type::Uint(_)) {
+            let (rvalue, ty) = lower_int_literal(builder, *value, true, *span, Some(ty))?;
+            return Ok((rvalue, ty, *span));
+        }
+    }
+
     let (lhs, lhs_type_idx, lhs_span) = if type_hint.is_none() {
         let ty = find_expression_type(builder, lhs)?;
 
@@ -1024,12 +1996,12 @@ pub(crate) fn lower_unary_op(
     };
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(lhs_span),
         kind: StatementKind::StorageLive(lhs_local),
     });
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(lhs_span),
         kind: StatementKind::Assign(lhs_place.clone(), lhs),
     });
 
@@ -1061,6 +2033,21 @@ pub(crate) fn lower_binary_op(
     rhs: &Expression,
     type_hint: Option<TypeIndex>,
 ) -> Result<(Rvalue, TypeIndex, Span), LoweringError> {
+    // The type of a comparison isn't the one of its operands.
+    let type_hint = match op {
+        BinaryOp::Compare(_) => None,
+        _ => type_hint,
+    };
+
+    // Strings are added with the `concat` method of the `String` lang item.
+    if let BinaryOp::Arith(ArithOp::Add) = op {
+        if let Some(string_ty) = builder.builder.ir.builtin_types.get(&Type::String).copied() {
+            if type_hint.or(find_expression_type(builder, lhs)?) == Some(string_ty) {
+                return lower_string_concat(builder, lhs, rhs, string_ty);
+            }
+        }
+    }
+
     let (lhs, lhs_type_idx, lhs_span) = if type_hint.is_none() {
         let ty = find_expression_type(builder, lhs)?.or(find_expression_type(builder, rhs)?);
 
@@ -1092,16 +2079,28 @@ pub(crate) fn lower_binary_op(
 
     let rhs_ty = builder.builder.get_type(rhs_type_idx);
 
-    if !is_lhs_ptr && !lhs_ty.is_equal(rhs_ty, &builder.builder.ir) {
-        return Err(LoweringError::UnexpectedType {
-            found_span: rhs_span,
-            found: builder.builder.display_typename(rhs_type_idx),
-            expected: builder.builder.display_typename(rhs_type_idx),
-            expected_span: Some(lhs_span),
+    if is_lhs_ptr && matches!(op, BinaryOp::Arith(_)) && !builder.is_unsafe {
+        return Err(LoweringError::UnsafeOperation {
+            span: Span::new(lhs_span.from, rhs_span.to),
+            operation: "offsetting a raw pointer".to_string(),
             path: builder.get_file_path().clone(),
         });
     }
 
+    if !is_lhs_ptr && !lhs_ty.is_equal(rhs_ty, &builder.builder.ir) {
+        return Err(LoweringError::UnexpectedType(Box::new(
+            UnexpectedTypeError {
+                found_span: rhs_span,
+                found: builder.builder.display_typename(rhs_type_idx),
+                expected: builder.builder.display_typename(lhs_type_idx),
+                expected_span: Some(lhs_span),
+                expected_path: None,
+                difference: builder.builder.type_difference(lhs_type_idx, rhs_type_idx),
+                path: builder.get_file_path().clone(),
+            },
+        )));
+    }
+
     let lhs_local = builder.add_local(Local::temp(lhs_type_idx));
     let rhs_local = builder.add_local(Local::temp(rhs_type_idx));
     let lhs_place = Place {
@@ -1114,22 +2113,22 @@ pub(crate) fn lower_binary_op(
     };
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(lhs_span),
         kind: StatementKind::StorageLive(lhs_local),
     });
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(lhs_span),
         kind: StatementKind::Assign(lhs_place.clone(), lhs),
     });
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(rhs_span),
         kind: StatementKind::StorageLive(rhs_local),
     });
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(rhs_span),
         kind: StatementKind::Assign(rhs_place.clone(), rhs),
     });
 
@@ -1138,6 +2137,25 @@ pub(crate) fn lower_binary_op(
 
     let full_span = Span::new(lhs_span.from, rhs_span.to);
 
+    // The integer arithmetic panics on overflow where overflow checks are enabled.
+    if let BinaryOp::Arith(op @ (ArithOp::Add | ArithOp::Sub | ArithOp::Mul)) = op {
+        if matches!(lhs_ty, Type::Int(_) | Type::Uint(_)) {
+            let op = match op {
+                ArithOp::Add => BinOp::Add,
+                ArithOp::Sub => BinOp::Sub,
+                _ => BinOp::Mul,
+            };
+            lower_overflow_check(builder, op, (lhs.clone(), rhs.clone()), full_span);
+        }
+    }
+    // And the divisor is checked for zero with `--sanitize undefined`.
+    if let BinaryOp::Arith(ArithOp::Div | ArithOp::Mod) = op {
+        if matches!(lhs_ty, Type::Int(_) | Type::Uint(_)) {
+            let check = UndefinedCheck::DivisionByZero(rhs.clone());
+            lower_undefined_check(builder, check, full_span);
+        }
+    }
+
     Ok(match op {
         BinaryOp::Arith(op) => (
             match op {
@@ -0,0 +1,437 @@
+//! `format!`, `print!` and `println!`, the macros built into the compiler, lowered once the types
+//! of their arguments are known.
+//!
+//! The first argument is a string literal whose placeholders are replaced by the other arguments,
+//! in order: `{}` writes a number, boolean, character or string as it is, `{:?}` writes it for
+//! debugging, like `#[derive(Debug)]` does, and writes any value implementing `std.fmt.Debug`.
+//! `{{` and `}}` write a brace. The placeholders are checked against the arguments, and each one
+//! becomes a call to the function of `std.fmt`, or the method of `String`, writing its type.
+//!
+//! A macro defined with the same name takes their place, as it's expanded before lowering.
+
+use crate::{
+    ast::{
+        common::{Ident, Span, TypeName},
+        expressions::{Expression, FnCallOp, PathOp, PathSegment, ValueExpr},
+        macros::MacroCall,
+        statements,
+    },
+    ir::{Operand, Place, Rvalue, Statement, StatementKind, Type},
+};
+
+use super::{
+    FnIrBuilder,
+    errors::LoweringError,
+    expressions::{lower_expression, lower_path},
+    functions::lower_fn_call,
+    ir::{LocalIndex, TypeIndex},
+    macros::{parse_arguments, unexpanded},
+};
+
+/// The macros lowered here, when no macro with their name is in scope.
+pub(crate) const BUILTIN_MACROS: &[&str] = &["format", "print", "println"];
+
+/// A part of the format string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    /// `{}`, or `{:?}` if it's written for debugging.
+    Value {
+        debug: bool,
+    },
+}
+
+/// `format!(...)`, the string with the arguments written in it.
+pub(crate) fn lower_format(
+    builder: &mut FnIrBuilder,
+    call: &MacroCall,
+) -> Result<(Rvalue, TypeIndex, Span), LoweringError> {
+    match call.name.name.as_str() {
+        "format" => {
+            let out = write_arguments(builder, call)?;
+            let ty = builder.body.locals[out].ty;
+            let place = Place {
+                local: out,
+                projection: Vec::new(),
+            };
+            Ok((Rvalue::Use(Operand::Place(place)), ty, call.span))
+        }
+        "print" | "println" => Err(invalid(
+            builder,
+            call.span,
+            call,
+            format!(
+                "`{}!` writes to the output, it's a statement",
+                call.name.name
+            ),
+        )),
+        _ => Err(unexpanded(call, builder.get_file_path())),
+    }
+}
+
+/// `print!(...);` or `println!(...);`, which write the string to the standard output.
+pub(crate) fn lower_print(
+    builder: &mut FnIrBuilder,
+    call: &MacroCall,
+) -> Result<(), LoweringError> {
+    let newline = match call.name.name.as_str() {
+        "print" => false,
+        "println" => true,
+        "format" => {
+            return Err(invalid(
+                builder,
+                call.span,
+                call,
+                "the string `format!` makes isn't used, `print!` writes it to the output"
+                    .to_string(),
+            ));
+        }
+        _ => return Err(unexpanded(call, builder.get_file_path())),
+    };
+
+    let out = write_arguments(builder, call)?;
+    let ast = Ast {
+        span: call.span,
+        out: local_name(out),
+    };
+    if newline {
+        lower_call(builder, ast.method(ast.out(), "push", vec![ast.char('\n')]))?;
+    }
+
+    let print = ast.call(&["std", "io"], "write_stdout", vec![ast.out_ref(false)]);
+    lower_call(builder, print)?;
+    lower_call(builder, ast.method(ast.out(), "drop", Vec::new()))
+}
+
+/// Writes the format string and the arguments into a new string, returning its local.
+fn write_arguments(
+    builder: &mut FnIrBuilder,
+    call: &MacroCall,
+) -> Result<LocalIndex, LoweringError> {
+    let path = builder.get_file_path().clone();
+    let mut args = parse_arguments(&call.args, &path)
+        .map_err(|(span, reason)| invalid(builder, span, call, reason))?
+        .into_iter();
+
+    // `println!()` writes an empty line.
+    let (text, text_span) = match args.next() {
+        None if call.name.name == "println" => (String::new(), call.span),
+        Some((Expression::Value(ValueExpr::ConstStr(text, _), _), span)) => (text, span),
+        Some((_, span)) => {
+            return Err(invalid(
+                builder,
+                span,
+                call,
+                format!(
+                    "the first argument of `{}!` must be a string literal, like \"{{}}\"",
+                    call.name.name
+                ),
+            ));
+        }
+        None => {
+            return Err(invalid(
+                builder,
+                call.span,
+                call,
+                "expected the string to write, like \"{}\"".to_string(),
+            ));
+        }
+    };
+
+    let pieces = pieces(&text).map_err(|reason| invalid(builder, text_span, call, reason))?;
+    let args: Vec<_> = args.collect();
+    let placeholders = pieces
+        .iter()
+        .filter(|x| matches!(x, Piece::Value { .. }))
+        .count();
+    if placeholders != args.len() {
+        return Err(invalid(
+            builder,
+            call.args_span,
+            call,
+            format!(
+                "the string has {placeholders} placeholder{}, but {} argument{} given",
+                if placeholders == 1 { "" } else { "s" },
+                args.len(),
+                if args.len() == 1 { " is" } else { "s are" },
+            ),
+        ));
+    }
+
+    let empty = Expression::Value(ValueExpr::ConstStr(String::new(), call.span), call.span);
+    let out = store(builder, &empty)?;
+    builder.body.locals[out].mutable = true;
+
+    let mut args = args.into_iter();
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => {
+                let ast = Ast {
+                    span: call.span,
+                    out: local_name(out),
+                };
+                let write = ast.call(
+                    &["std", "fmt"],
+                    "write_str",
+                    vec![ast.out_ref(true), ast.str(&text)],
+                );
+                lower_call(builder, write)?;
+            }
+            Piece::Value { debug } => {
+                let (arg, span) = args.next().expect("the arguments are counted");
+                write_argument(builder, call, out, &arg, span, debug)?;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Writes the argument with the function for its type.
+fn write_argument(
+    builder: &mut FnIrBuilder,
+    call: &MacroCall,
+    out: LocalIndex,
+    arg: &Expression,
+    span: Span,
+    debug: bool,
+) -> Result<(), LoweringError> {
+    let value = store(builder, arg)?;
+    let ast = Ast {
+        span,
+        out: local_name(out),
+    };
+
+    // A reference writes the value it points to, and a string is written from a reference.
+    let mut ty = builder.body.locals[value].ty;
+    let mut value_expr = ast.path(&local_name(value));
+    let mut value_ref = Expression::AsRef(Box::new(value_expr.clone()), false, span);
+    if let Type::Ref(inner, _) = builder.builder.get_type(ty) {
+        value_ref = value_expr.clone();
+        value_expr = Expression::Deref(Box::new(value_expr), span);
+        ty = *inner;
+    }
+
+    let primitive = |function: &str, cast: &str| {
+        ast.call(
+            &["std", "fmt"],
+            function,
+            vec![ast.out_ref(true), ast.cast(value_expr.clone(), cast)],
+        )
+    };
+
+    // `String` is the struct of its lang item.
+    let is_string = builder.builder.ir.builtin_types.get(&Type::String) == Some(&ty);
+    let write = match builder.builder.get_type(ty) {
+        Type::Int(_) => primitive("write_int", "i64"),
+        Type::Uint(_) | Type::Ptr(..) => primitive("write_uint", "u64"),
+        Type::Float(_) => primitive("write_float", "f64"),
+        Type::Bool => primitive("write_bool", "bool"),
+        Type::Char if debug => primitive("write_char", "char"),
+        Type::Char => ast.method(ast.out(), "push", vec![value_expr]),
+        Type::Adt(_) if is_string && !debug => ast.method(ast.out(), "push_str", vec![value_ref]),
+        Type::Adt(_) if debug => {
+            let write = ast.method(
+                ast.path_op(&local_name(value)),
+                "fmt",
+                vec![ast.out_ref(true)],
+            );
+            let type_name = builder.builder.display_typename(ty);
+            return lower_call(builder, write).map_err(|error| match error {
+                LoweringError::MethodNotFound { name, .. } if name == "fmt" => invalid(
+                    builder,
+                    span,
+                    call,
+                    format!(
+                        "`{{:?}}` writes values implementing `Debug`, which `{type_name}` doesn't"
+                    ),
+                ),
+                error => error,
+            });
+        }
+        _ => {
+            let type_name = builder.builder.display_typename(ty);
+            let reason = match builder.builder.get_type(ty) {
+                Type::Adt(_) => format!(
+                    "`{{}}` can't write a value of type `{type_name}`, `{{:?}}` writes it if it implements `Debug`"
+                ),
+                _ => format!("`{{}}` can't write a value of type `{type_name}`"),
+            };
+            return Err(invalid(builder, span, call, reason));
+        }
+    };
+
+    lower_call(builder, write)
+}
+
+/// Stores the value of the expression in a new local, which the AST built here refers to by
+/// [`local_name`].
+fn store(builder: &mut FnIrBuilder, value: &Expression) -> Result<LocalIndex, LoweringError> {
+    let (rvalue, ty, span) = lower_expression(builder, value, None)?;
+    let local = builder.add_temp_local(ty);
+    builder.name_to_local.insert(local_name(local), local);
+    builder.local_exists.insert(local);
+
+    let place = Place {
+        local,
+        projection: Vec::new(),
+    };
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::StorageLive(local),
+    });
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(place, rvalue),
+    });
+
+    Ok(local)
+}
+
+/// The name of a local made here, which no variable can have.
+fn local_name(local: LocalIndex) -> String {
+    format!("fmt${local}")
+}
+
+fn lower_call(builder: &mut FnIrBuilder, call: statements::Statement) -> Result<(), LoweringError> {
+    match call {
+        statements::Statement::FnCall(info) => {
+            lower_fn_call(builder, &info, None, None).map(|_| ())
+        }
+        statements::Statement::PathOp(info) => lower_path(builder, &info).map(|_| ()),
+        _ => unreachable!("only calls are built"),
+    }
+}
+
+fn invalid(builder: &FnIrBuilder, span: Span, call: &MacroCall, reason: String) -> LoweringError {
+    LoweringError::InvalidMacro {
+        span,
+        name: call.name.name.clone(),
+        reason,
+        path: builder.get_file_path().clone(),
+    }
+}
+
+/// The text and placeholders of a format string.
+fn pieces(text: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                current.push(c);
+            }
+            ('{', _) => {
+                let placeholder: String = chars.by_ref().take_while(|x| *x != '}').collect();
+                let debug = match placeholder.as_str() {
+                    "" => false,
+                    ":?" => true,
+                    _ => {
+                        return Err(format!(
+                            "unknown placeholder `{{{placeholder}}}`, the placeholders are `{{}}` \
+                             and `{{:?}}`, and `{{{{` writes a brace"
+                        ));
+                    }
+                };
+                if !current.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut current)));
+                }
+                pieces.push(Piece::Value { debug });
+            }
+            ('}', _) => return Err("unmatched `}`, `}}` writes a brace".to_string()),
+            _ => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        pieces.push(Piece::Text(current));
+    }
+
+    Ok(pieces)
+}
+
+/// Builds the AST of the calls writing into the string, at the span of what they write.
+struct Ast {
+    span: Span,
+    /// The name of the local of the string.
+    out: String,
+}
+
+impl Ast {
+    fn ident(&self, name: &str) -> Ident {
+        Ident {
+            name: name.to_string(),
+            span: self.span,
+        }
+    }
+
+    fn path_op(&self, name: &str) -> PathOp {
+        PathOp {
+            first: self.ident(name),
+            extra: Vec::new(),
+            span: self.span,
+        }
+    }
+
+    fn path(&self, name: &str) -> Expression {
+        Expression::Value(ValueExpr::Path(self.path_op(name)), self.span)
+    }
+
+    fn out(&self) -> PathOp {
+        self.path_op(&self.out)
+    }
+
+    /// `&mut out`, or `&out`.
+    fn out_ref(&self, is_mut: bool) -> Expression {
+        Expression::AsRef(Box::new(self.path(&self.out)), is_mut, self.span)
+    }
+
+    fn str(&self, text: &str) -> Expression {
+        Expression::Value(ValueExpr::ConstStr(text.to_string(), self.span), self.span)
+    }
+
+    fn char(&self, value: char) -> Expression {
+        Expression::Value(ValueExpr::ConstChar(value, self.span), self.span)
+    }
+
+    fn cast(&self, value: Expression, ty: &str) -> Expression {
+        let name = TypeName {
+            path: Vec::new(),
+            name: self.ident(ty),
+            generics: Vec::new(),
+            span: self.span,
+        };
+        Expression::Cast(Box::new(value), name.into(), self.span)
+    }
+
+    fn fn_call(&self, path: &[&str], target: &str, args: Vec<Expression>) -> FnCallOp {
+        FnCallOp {
+            path: path.iter().map(|x| self.ident(x)).collect(),
+            target: self.ident(target),
+            generics: Vec::new(),
+            args,
+            span: self.span,
+        }
+    }
+
+    fn call(&self, path: &[&str], target: &str, args: Vec<Expression>) -> statements::Statement {
+        statements::Statement::FnCall(self.fn_call(path, target, args))
+    }
+
+    /// `receiver.method(args)`, the methods look through references.
+    fn method(
+        &self,
+        mut receiver: PathOp,
+        method: &str,
+        args: Vec<Expression>,
+    ) -> statements::Statement {
+        receiver.extra.push(PathSegment::MethodCall(
+            self.fn_call(&[], method, args),
+            self.span,
+        ));
+        statements::Statement::PathOp(receiver)
+    }
+}
@@ -4,14 +4,20 @@ use tracing::{debug, instrument};
 
 use crate::{
     ast::{
-        common::GenericParam,
+        common::{Attribute, AttributeArg, GenericParam, Ident},
         expressions::FnCallOp,
         functions::{FunctionDecl, FunctionDef},
         statements::{self, LetStmtTarget},
+        types::TypeDescriptor,
+    },
+    check::{
+        lints::Lint,
+        suggestions::{Applicability, Suggestion},
+        unused::{unreachable_statements, unused_locals},
     },
     ir::{
-        BasicBlock, ConcreteIntrinsic, Function, Local, LocalKind, Operand, Place, Span,
-        Terminator, TerminatorKind, Type,
+        AtomicOp, BasicBlock, BinOp, ConcreteIntrinsic, Function, Inline, IntTy, Local, LocalKind,
+        Mutability, Operand, Place, Span, Terminator, TerminatorKind, Type, UintTy,
         lowering::{
             Symbol,
             expressions::{find_expression_type, lower_expression},
@@ -22,9 +28,9 @@ use crate::{
 
 use super::{
     FnIrBuilder, IRBuilder,
-    errors::LoweringError,
-    ir::{FnIndex, Rvalue, TypeIndex},
-    statements::lower_statement,
+    errors::{LoweringError, UnexpectedTypeError},
+    ir::{FnIndex, ModuleIndex, Rvalue, TypeIndex},
+    statements::{lower_pattern, lower_return_block, lower_statement},
 };
 
 /// Lowers a function or method if its not yet lowered.
@@ -37,6 +43,11 @@ pub(crate) fn lower_func(
     method_of: Option<TypeIndex>,
 ) -> Result<FnIndex, LoweringError> {
     debug!("lowering function {:?}", func.decl.name.name);
+    crate::activity::set_current_item(format_args!("lowering function `{}`", func.decl.name.name));
+    let _span = crate::driver::self_profile::span(
+        "lowering",
+        format_args!("lower `{}`", func.decl.name.name),
+    );
 
     let is_intrinsic: Option<ConcreteIntrinsic> = None;
 
@@ -48,9 +59,12 @@ pub(crate) fn lower_func(
     let old_self_ty = builder.context.self_ty;
     let module_idx = if let Some(id) = method_of {
         builder.context.self_ty = Some(id);
+        // The methods of a generic type are in the module of the polymorphic type, which isn't
+        // always the module where the monomorphic one was first used.
+        let poly_id = builder.mono_type_to_poly.get(&id).copied().unwrap_or(id);
         builder
             .type_to_module
-            .get(&id)
+            .get(&poly_id)
             .copied()
             .expect("should exist")
     } else {
@@ -128,34 +142,64 @@ pub(crate) fn lower_func(
         .map(|x| lower_type(builder, x))
         .unwrap_or(Ok(builder.ir.get_unit_ty()))?;
 
+    let codegen_attributes = codegen_attributes(builder, &func.decl)?;
+
     let mut fn_builder = FnIrBuilder {
         body: Function {
-            name: if !func.decl.is_extern && func.decl.name.name != "main" {
+            name: if let Some(symbol) = codegen_attributes.symbol {
+                symbol
+            } else if !func.decl.is_extern && func.decl.name.name != "main" {
                 builder
-                    .get_mangled_name(module_idx, &func.decl.name.name, fn_id)
+                    .get_mangled_name(module_idx, &symbol)
                     .expect("should get mangled name")
             } else {
                 func.decl.name.name.clone()
             },
             debug_name: if !func.decl.is_extern && func.decl.name.name != "main" {
-                builder.get_debug_name(module_idx, &func.decl.name.name)
+                // Each instance of a generic function is told apart by its generic types.
+                builder
+                    .get_debug_name(module_idx, &func.decl.name.name)
+                    .map(|name| match symbol.generics.as_slice() {
+                        [] => name,
+                        generics => format!(
+                            "{}<{}>",
+                            name,
+                            generics
+                                .iter()
+                                .map(|x| builder.display_typename(*x))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    })
             } else {
                 Some(func.decl.name.name.clone())
             },
             args: args_ty.clone(),
             ret_ty,
             is_extern: func.decl.is_extern,
+            is_pub: func.decl.is_pub,
             is_intrinsic,
+            inline: codegen_attributes.inline,
+            is_cold: codegen_attributes.is_cold,
             basic_blocks: Vec::new(),
             module_idx,
             locals: Vec::new(),
+            scopes: Vec::new(),
+            span: Some(func.span),
         },
         fn_id,
         name_to_local: HashMap::new(),
         statements: Vec::new(),
         ret_local: 0,
+        ret_type_span: func.decl.ret_type.as_ref().map(|x| x.get_span()),
         builder,
         local_exists: Default::default(),
+        pattern_locals: Default::default(),
+        scope: None,
+        defers: Vec::new(),
+        deferring: None,
+        is_unsafe: func.decl.is_unsafe,
+        suspends: func.decl.is_async.then(Vec::new),
     };
 
     // A extern fn cannot have a body.
@@ -167,6 +211,36 @@ pub(crate) fn lower_func(
         });
     }
 
+    if func.decl.is_async {
+        let reason = if func.decl.name.name == "main" {
+            Some("the program starts from it, it can run a future with `std::task::block_on`")
+        } else if func.decl.is_extern {
+            Some("functions called from native code can't be polled")
+        } else if func.decl.is_const {
+            Some("its value is only known once the future is polled, at runtime")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Err(LoweringError::InvalidAsyncFn {
+                span: func.decl.name.span,
+                name: func.decl.name.name.clone(),
+                reason: reason.to_string(),
+                path: fn_builder.get_file_path().clone(),
+            });
+        }
+    }
+
+    // The code of a function of a prebuilt dependency is in the object it was built to, it's
+    // only declared, under the symbol it was defined with.
+    if func.is_prebuilt {
+        fn_builder.body.is_extern = true;
+        fn_builder.builder.ir.functions[fn_id] = Some(fn_builder.body);
+        builder.ir.modules[module_idx].functions.insert(fn_id);
+        builder.context.self_ty = old_self_ty;
+        return Ok(fn_id);
+    }
+
     fn_builder.ret_local = fn_builder.body.locals.len();
     fn_builder.body.locals.push(Local::new(
         None,
@@ -178,10 +252,13 @@ pub(crate) fn lower_func(
 
     // Add argument locals.
     for (arg, ty) in func.decl.params.iter().zip(args_ty) {
-        fn_builder
-            .name_to_local
-            .insert(arg.name.name.clone(), fn_builder.body.locals.len());
-        fn_builder.local_exists.insert(fn_builder.body.locals.len());
+        // The arguments destructured by a pattern are only reachable through its bindings.
+        if arg.pattern.is_none() {
+            fn_builder
+                .name_to_local
+                .insert(arg.name.name.clone(), fn_builder.body.locals.len());
+            fn_builder.local_exists.insert(fn_builder.body.locals.len());
+        }
         fn_builder.body.locals.push(Local::new(
             Some(arg.name.span),
             LocalKind::Arg,
@@ -191,6 +268,24 @@ pub(crate) fn lower_func(
         ));
     }
 
+    for (local, arg) in func.decl.params.iter().enumerate() {
+        if let Some(pattern) = &arg.pattern {
+            // The argument locals follow the return one.
+            let local = fn_builder.ret_local + 1 + local;
+            let ty = fn_builder.body.locals[local].ty;
+            lower_pattern(
+                &mut fn_builder,
+                pattern,
+                Place {
+                    local,
+                    projection: vec![],
+                },
+                ty,
+                false,
+            )?;
+        }
+    }
+
     // Get all top level locals
     for stmt in &func.body {
         get_locals(&mut fn_builder, stmt)?;
@@ -200,33 +295,257 @@ pub(crate) fn lower_func(
         lower_statement(&mut fn_builder, stmt, ret_ty)?;
     }
 
-    let statements = std::mem::take(&mut fn_builder.statements);
-    fn_builder.body.basic_blocks.push(BasicBlock {
-        statements,
-        terminator: Box::new(Terminator {
-            span: None,
-            kind: TerminatorKind::Return,
-        }),
-    });
+    lower_return_block(&mut fn_builder, func.span)?;
+
+    // Calling an async function returns the future of its value, its body becomes the poll
+    // function of the future once its borrows are checked.
+    if let Some(suspends) = fn_builder.suspends.take() {
+        fn_builder.body.ret_ty = fn_builder
+            .builder
+            .get_future_ty(ret_ty, func.decl.name.span)?;
+        fn_builder.builder.async_fns.push((fn_id, suspends));
+    }
 
     fn_builder.builder.ir.functions[fn_id] = Some(fn_builder.body);
     builder.ir.modules[module_idx].functions.insert(fn_id);
 
     builder.context.self_ty = old_self_ty;
 
+    warn_unused_code(builder, func, fn_id);
+
+    for param in &func.decl.params {
+        builder.warn_unknown_attributes(&param.attributes, PARAM_ATTRIBUTES);
+    }
+
     for attr in &func.decl.attributes {
-        if attr.name.as_str() == "test" {
+        match attr.name.as_str() {
             // TODO: check its a valid test function, i.e: no arguments, returns a i32.
-            if builder.context.add_tests {
+            "test" if builder.context.add_tests => {
                 builder.ir.tests.push(fn_id);
             }
-            break;
+            // The parameter types are checked by the test runner, which generates the inputs.
+            "property_test" if builder.context.add_tests => {
+                builder.ir.property_tests.push(fn_id);
+            }
+            "retries" => {
+                let retries = attr
+                    .value
+                    .as_ref()
+                    .and_then(|x| x.parse::<u32>().ok())
+                    .ok_or_else(|| LoweringError::InvalidAttribute {
+                        span: attr.span,
+                        name: attr.name.clone(),
+                        reason: "expected the number of retries, e.g: #[retries = \"3\"]"
+                            .to_string(),
+                        path: builder.get_current_module().file_path.clone(),
+                    })?;
+
+                if builder.context.add_tests {
+                    builder.ir.test_retries.insert(fn_id, retries);
+                }
+            }
+            "fuzz_target" => {
+                check_fuzz_target(builder, fn_id, attr)?;
+
+                if builder.context.add_tests {
+                    builder.ir.fuzz_targets.push(fn_id);
+                }
+            }
+            "test" | "property_test" => {}
+            // Read before lowering the body, see `codegen_attributes`.
+            "inline" | "cold" | "no_mangle" | "export_name" => {}
+            // Checked before lowering, the function wouldn't be here if its predicate didn't hold.
+            "cfg" => {}
+            // Found before lowering, they set the levels of the lints inside the function.
+            "allow" | "warn" | "deny" => {}
+            _ => {
+                builder.warn_unknown_attribute(attr, FUNCTION_ATTRIBUTES);
+            }
         }
     }
 
     Ok(fn_id)
 }
 
+/// Warns about the statements of the function that can't run and its variables whose values are
+/// never read.
+fn warn_unused_code(builder: &mut IRBuilder, func: &FunctionDef, fn_id: FnIndex) {
+    for span in unreachable_statements(&func.body) {
+        builder.emit_warning(
+            Lint::UnreachableCode,
+            span,
+            "unreachable statement".to_string(),
+            None,
+        );
+    }
+
+    let function = builder.ir.functions[fn_id]
+        .as_ref()
+        .expect("the function should be lowered");
+    let unused: Vec<(Span, String)> = unused_locals(function)
+        .into_iter()
+        .filter_map(|x| {
+            let local = &function.locals[x];
+            Some((local.span?, local.debug_name.clone()?))
+        })
+        .collect();
+
+    for (span, name) in unused {
+        builder.emit_warning(
+            Lint::UnusedVariables,
+            span,
+            format!("unused variable '{name}'"),
+            Some(Suggestion {
+                message: "if this is intentional, prefix it with an underscore".to_string(),
+                path: None,
+                span,
+                replacement: format!("_{name}"),
+                applicability: Applicability::MaybeIncorrect,
+            }),
+        );
+    }
+}
+
+/// The attributes a function can have.
+pub(crate) const FUNCTION_ATTRIBUTES: &[&str] = &[
+    "test",
+    "property_test",
+    "fuzz_target",
+    "retries",
+    "intrinsic",
+    "inline",
+    "cold",
+    "no_mangle",
+    "export_name",
+    "cfg",
+    "allow",
+    "warn",
+    "deny",
+];
+
+/// What the attributes of a function change in the code generated for it.
+#[derive(Debug, Default)]
+struct CodegenAttributes {
+    inline: Option<Inline>,
+    is_cold: bool,
+    /// The symbol of the function instead of its mangled name, set by `#[no_mangle]` and
+    /// `#[export_name = "..."]`.
+    symbol: Option<String>,
+}
+
+/// Reads the `#[inline]`, `#[cold]`, `#[no_mangle]` and `#[export_name]` attributes of the
+/// function.
+fn codegen_attributes(
+    builder: &IRBuilder,
+    decl: &FunctionDecl,
+) -> Result<CodegenAttributes, LoweringError> {
+    let mut attributes = CodegenAttributes::default();
+    let invalid = |attr: &Attribute, reason: &str| LoweringError::InvalidAttribute {
+        span: attr.span,
+        name: attr.name.clone(),
+        reason: reason.to_string(),
+        path: builder.get_current_module().file_path.clone(),
+    };
+
+    for attr in &decl.attributes {
+        match attr.name.as_str() {
+            "inline" => {
+                let inline = match (&attr.value, attr.args.as_slice()) {
+                    (None, []) => Some(Inline::Hint),
+                    (None, [AttributeArg::Word(x)]) if x.name == "always" => Some(Inline::Always),
+                    (None, [AttributeArg::Word(x)]) if x.name == "never" => Some(Inline::Never),
+                    _ => None,
+                };
+                attributes.inline = Some(inline.ok_or_else(|| {
+                    invalid(
+                        attr,
+                        "expected `#[inline]`, `#[inline(always)]` or `#[inline(never)]`",
+                    )
+                })?);
+            }
+            "cold" if attr.value.is_some() || !attr.args.is_empty() => {
+                return Err(invalid(attr, "`#[cold]` takes no arguments"));
+            }
+            "cold" => attributes.is_cold = true,
+            "no_mangle" | "export_name" if !decl.generic_params.is_empty() => {
+                return Err(invalid(
+                    attr,
+                    "a generic function has a symbol for each instance, it can't be given one",
+                ));
+            }
+            "no_mangle" if attr.value.is_some() || !attr.args.is_empty() => {
+                return Err(invalid(attr, "`#[no_mangle]` takes no arguments"));
+            }
+            // The name given by `#[export_name]` takes precedence.
+            "no_mangle" => {
+                attributes
+                    .symbol
+                    .get_or_insert_with(|| decl.name.name.clone());
+            }
+            "export_name" => match &attr.value {
+                Some(name) if !name.is_empty() => attributes.symbol = Some(name.clone()),
+                _ => {
+                    return Err(invalid(
+                        attr,
+                        "expected the name of the symbol, e.g: #[export_name = \"my_function\"]",
+                    ));
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Ok(attributes)
+}
+
+/// The attributes a parameter can have.
+pub(crate) const PARAM_ATTRIBUTES: &[&str] = &["cfg"];
+
+/// Checks a fuzz target has the signature libFuzzer calls it with: `fn(*const u8, u64) -> i32`.
+fn check_fuzz_target(
+    builder: &IRBuilder,
+    fn_id: FnIndex,
+    attr: &Attribute,
+) -> Result<(), LoweringError> {
+    let function = builder.ir.functions[fn_id]
+        .as_ref()
+        .expect("the function should be lowered");
+    let ty = |x: TypeIndex| builder.ir.types[x].as_ref();
+
+    let is_data = |x: TypeIndex| match ty(x) {
+        Some(Type::Ptr(inner, _)) => ty(*inner) == Some(&Type::Uint(UintTy::U8)),
+        _ => false,
+    };
+
+    let valid = matches!(function.args.as_slice(), [data, len] if is_data(*data) && ty(*len) == Some(&Type::Uint(UintTy::U64)))
+        && ty(function.ret_ty) == Some(&Type::Int(IntTy::I32));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(LoweringError::InvalidAttribute {
+            span: attr.span,
+            name: attr.name.clone(),
+            reason: "a fuzz target must have the signature `fn(data: *const u8, len: u64) -> i32`"
+                .to_string(),
+            path: builder.get_current_module().file_path.clone(),
+        })
+    }
+}
+
+/// Lowers a type of the signature of a called function in the module it's defined in, where the
+/// names it uses are known.
+fn lower_signature_type(
+    fn_builder: &mut FnIrBuilder,
+    fn_module_idx: ModuleIndex,
+    ty: &TypeDescriptor,
+) -> Result<TypeIndex, LoweringError> {
+    fn_builder.enter_module_context(fn_module_idx);
+    let ty = lower_type(fn_builder.builder, ty);
+    fn_builder.leave_module_context();
+    ty
+}
+
 /// Lowers a function or method call.
 ///
 /// If the function is generic, and hasn't been monomorphized yet, it gets lowered with the given generic types.
@@ -239,6 +558,7 @@ pub(crate) fn lower_fn_call(
 ) -> Result<(Rvalue, TypeIndex, Span), LoweringError> {
     debug!("lowering fn call");
 
+    let use_module_idx = fn_builder.get_current_module_idx();
     let module_idx = fn_builder.builder.get_path_module_idx(&info.path)?;
 
     // Temporarly set the local module to the import module in case the function is not yet
@@ -249,6 +569,79 @@ pub(crate) fn lower_fn_call(
 
     fn_builder.leave_module_context();
 
+    // The entry of the threads running a task calls its `run` method, found at the first call.
+    let target_fn_id = mono_fn_id.unwrap_or(poly_fn_id);
+    if let Some(ConcreteIntrinsic::ThreadStart { task, run: None }) =
+        fn_builder.builder.ir.functions[target_fn_id]
+            .as_ref()
+            .and_then(|x| x.is_intrinsic)
+    {
+        let run_call = FnCallOp {
+            path: Vec::new(),
+            target: Ident {
+                name: "run".to_string(),
+                span: info.target.span,
+            },
+            generics: Vec::new(),
+            args: Vec::new(),
+            span: info.span,
+        };
+        let (run_poly_id, run_mono_id) = fn_builder.get_id_for_fn_call(&run_call, Some(task))?;
+        if let Some(function) = fn_builder.builder.ir.functions[target_fn_id].as_mut() {
+            function.is_intrinsic = Some(ConcreteIntrinsic::ThreadStart {
+                task,
+                run: Some(run_mono_id.unwrap_or(run_poly_id)),
+            });
+        }
+    }
+
+    // The wrapping and the overflow checked arithmetic are only defined on integers, and so are
+    // the atomics, on the integer behind their pointer.
+    let integer_ty = fn_builder.builder.ir.functions[target_fn_id]
+        .as_ref()
+        .and_then(|x| match x.is_intrinsic {
+            Some(ConcreteIntrinsic::Wrapping(_) | ConcreteIntrinsic::Overflows(_)) => {
+                x.args.first().copied()
+            }
+            // The fence has no pointer.
+            Some(ConcreteIntrinsic::Atomic(_)) => x
+                .args
+                .first()
+                .and_then(|ty| fn_builder.builder.get_type(*ty).get_inner_type()),
+            _ => None,
+        });
+    if let Some(ty) = integer_ty {
+        if !matches!(
+            fn_builder.builder.get_type(ty),
+            Type::Int(_) | Type::Uint(_)
+        ) {
+            return Err(LoweringError::UnexpectedType(Box::new(
+                UnexpectedTypeError {
+                    found_span: info.span,
+                    found: fn_builder.builder.display_typename(ty),
+                    expected: "an integer type".to_string(),
+                    expected_span: None,
+                    expected_path: None,
+                    difference: None,
+                    path: fn_builder.get_file_path().clone(),
+                },
+            )));
+        }
+    }
+
+    if let Some(def_module) = fn_builder.builder.private_functions.get(&poly_fn_id) {
+        let decl = fn_builder.builder.get_fn_decl(poly_fn_id);
+        fn_builder.builder.check_visibility(
+            *def_module,
+            use_module_idx,
+            "function",
+            &info.target.name,
+            info.target.span,
+            decl.name.span,
+            decl.vis_span,
+        )?;
+    }
+
     // Get the function declaration to inspect its types.
     let target_fn_decl = fn_builder
         .builder
@@ -267,8 +660,42 @@ pub(crate) fn lower_fn_call(
         .unwrap()
         .clone();
 
+    if target_fn_decl.is_unsafe && !fn_builder.is_unsafe {
+        return Err(LoweringError::UnsafeOperation {
+            span: info.span,
+            operation: format!("calling the unsafe function `{}`", info.target.name),
+            path: fn_builder.get_file_path().clone(),
+        });
+    }
+
+    let fn_module_idx = fn_builder
+        .builder
+        .fn_to_module
+        .get(&poly_fn_id)
+        .copied()
+        .unwrap_or(module_idx);
+
     // Enter a new scope for generics.
     let old_generic_map = fn_builder.builder.context.generics_mapping.clone();
+    if let Some(method_ty_idx) = method_idx {
+        fn_builder.builder.add_instance_generics(method_ty_idx);
+    }
+
+    // A method call needs a function that takes self.
+    if let Some((_, self_ty)) = &self_value {
+        if !target_fn_decl
+            .params
+            .first()
+            .is_some_and(|x| matches!(x.r#type, TypeDescriptor::SelfType { .. }))
+        {
+            return Err(LoweringError::NotAMethod {
+                span: info.target.span,
+                name: info.target.name.clone(),
+                ty: fn_builder.builder.display_typename(*self_ty),
+                path: fn_builder.get_file_path().clone(),
+            });
+        }
+    }
 
     // Check parameter count is correct first, makes things simpler.
     if target_fn_decl.params.len() != info.args.len() + { if self_value.is_some() { 1 } else { 0 } }
@@ -278,6 +705,12 @@ pub(crate) fn lower_fn_call(
             found: info.args.len(),
             needs: target_fn_decl.params.len() - if self_value.is_some() { 1 } else { 0 },
             path: fn_builder.get_file_path().clone(),
+            fn_span: target_fn_decl.name.span,
+            fn_path: fn_builder
+                .builder
+                .get_fn_file_path(poly_fn_id)
+                .unwrap_or_else(|| fn_builder.get_file_path())
+                .clone(),
         });
     }
 
@@ -293,6 +726,12 @@ pub(crate) fn lower_fn_call(
                 found: info.generics.len(),
                 needs: target_fn_decl.generic_params.len(),
                 path: fn_builder.get_file_path().clone(),
+                fn_span: target_fn_decl.name.span,
+                fn_path: fn_builder
+                    .builder
+                    .get_fn_file_path(poly_fn_id)
+                    .unwrap_or_else(|| fn_builder.get_file_path())
+                    .clone(),
             });
         }
     }
@@ -301,9 +740,9 @@ pub(crate) fn lower_fn_call(
 
     let mut args_ty = Vec::new();
 
-    // Set self_ty if there is one.
+    // Set self_ty if there is one, `Type#method(&value)` calls a method too.
     // Used in lower_type.
-    if let Some((_, self_ty)) = self_value {
+    if let Some(self_ty) = self_value.as_ref().map(|(_, ty)| *ty).or(method_idx) {
         fn_builder.builder.context.self_ty = Some(self_ty);
     }
 
@@ -332,11 +771,12 @@ pub(crate) fn lower_fn_call(
                         .generics_mapping
                         .insert(name.clone(), infer_ty);
 
+                    // Check trait bounds, named in the module of the function.
                     for bound in &generic.bounds {
                         if let Some(check_trait) = fn_builder
                             .builder
                             .trait_db
-                            .get_trait_by_name(&bound.name.name, module_idx)
+                            .get_trait_by_name(&bound.name.name, fn_module_idx)
                         {
                             let trait_generics = Vec::new(); // TODO: implement trait generics here
                             if !fn_builder.builder.trait_db.type_implements_trait(
@@ -352,8 +792,26 @@ pub(crate) fn lower_fn_call(
                     }
                 }
             }
-            let ty = lower_type(
-                fn_builder.builder,
+            // The generics within the parameter's type, like the `T` of `Future<T>`.
+            if let Some(param_generics) = target_fn_decl.params[target_fn_param_start_idx + i]
+                .r#type
+                .get_generics()
+                .filter(|x| !x.is_empty())
+            {
+                if let Some(arg_ty) = find_expression_type(fn_builder, param)? {
+                    for (name, ty) in fn_builder
+                        .builder
+                        .infer_adt_generics(&param_generics, arg_ty)
+                    {
+                        if generics.contains_key(&name) {
+                            fn_builder.builder.context.generics_mapping.insert(name, ty);
+                        }
+                    }
+                }
+            }
+            let ty = lower_signature_type(
+                fn_builder,
+                fn_module_idx,
                 &target_fn_decl.params[target_fn_param_start_idx + i].r#type,
             )?;
             args_ty.push(ty);
@@ -365,17 +823,27 @@ pub(crate) fn lower_fn_call(
             .iter()
             .skip(if self_value.is_some() { 1 } else { 0 })
         {
-            let ty = lower_type(fn_builder.builder, &param.r#type)?;
+            let ty = lower_signature_type(fn_builder, fn_module_idx, &param.r#type)?;
             args_ty.push(ty);
         }
     }
 
     // Lower the return type.
-    let return_ty = if let Some(ret_ty) = &target_fn_decl.ret_type {
-        lower_type(fn_builder.builder, ret_ty)?
+    let mut return_ty = if let Some(ret_ty) = &target_fn_decl.ret_type {
+        lower_signature_type(fn_builder, fn_module_idx, ret_ty)?
     } else {
         fn_builder.builder.ir.get_unit_ty()
     };
+    // Calling an async function returns the future of its value.
+    if target_fn_decl.is_async {
+        return_ty = fn_builder.builder.get_future_ty(return_ty, info.span)?;
+    }
+
+    // The declared params of the arguments, to point at them on type mismatches.
+    let params = target_fn_decl
+        .params
+        .iter()
+        .skip(if self_value.is_some() { 1 } else { 0 });
 
     let mut args = Vec::new();
 
@@ -384,10 +852,13 @@ pub(crate) fn lower_fn_call(
         // Here self_arg_ty is the type without references.
         // We should use the type from the fn sig to know if it needs a reference.
         let expected_self_arg_ty_idx =
-            lower_type(fn_builder.builder, &target_fn_decl.params[0].r#type)?;
+            lower_signature_type(fn_builder, fn_module_idx, &target_fn_decl.params[0].r#type)?;
         let expected_ty = fn_builder.builder.get_type(expected_self_arg_ty_idx);
         match expected_ty {
             Type::Ref(_, mutability) => {
+                if matches!(mutability, Mutability::Mut) {
+                    fn_builder.check_mutable_borrow(&arg, info.target.span)?;
+                }
                 args.push(Rvalue::Ref(*mutability, arg.clone()));
             }
             _ => {
@@ -397,20 +868,30 @@ pub(crate) fn lower_fn_call(
     }
 
     // Lower the argument expressions.
-    for (arg, arg_type_idx) in info.args.iter().zip(args_ty.into_iter()) {
+    for ((arg, arg_type_idx), param) in info.args.iter().zip(args_ty.into_iter()).zip(params) {
         let (rvalue, rvalue_type_idx, rvalue_span) =
             lower_expression(fn_builder, arg, Some(arg_type_idx))?;
         let arg_ty = fn_builder.builder.get_type(arg_type_idx);
         let rvalue_ty = fn_builder.builder.get_type(rvalue_type_idx);
 
         if !rvalue_ty.is_equal(arg_ty, &fn_builder.builder.ir) {
-            return Err(LoweringError::UnexpectedType {
-                found_span: rvalue_span,
-                found: fn_builder.builder.display_typename(rvalue_type_idx),
-                expected: fn_builder.builder.display_typename(arg_type_idx),
-                expected_span: Some(rvalue_span),
-                path: fn_builder.get_file_path().clone(),
-            });
+            let fn_path = fn_builder.builder.get_fn_file_path(poly_fn_id);
+
+            return Err(LoweringError::UnexpectedType(Box::new(
+                UnexpectedTypeError {
+                    found_span: rvalue_span,
+                    found: fn_builder.builder.display_typename(rvalue_type_idx),
+                    expected: fn_builder.builder.display_typename(arg_type_idx),
+                    expected_span: Some(param.r#type.get_span()),
+                    expected_path: fn_path
+                        .filter(|x| *x != fn_builder.get_file_path())
+                        .cloned(),
+                    difference: fn_builder
+                        .builder
+                        .type_difference(arg_type_idx, rvalue_type_idx),
+                    path: fn_builder.get_file_path().clone(),
+                },
+            )));
         }
 
         args.push(rvalue);
@@ -465,6 +946,10 @@ pub(crate) fn lower_func_decl(
 
     let generic_types = builder.lower_generic_params(&func.generic_params)?;
 
+    for param in &func.params {
+        builder.warn_unknown_attributes(&param.attributes, PARAM_ATTRIBUTES);
+    }
+
     for attr in &func.attributes {
         match attr.name.as_str() {
             "intrinsic" => {
@@ -478,17 +963,105 @@ pub(crate) fn lower_func_decl(
                         is_intrinsic =
                             Some(ConcreteIntrinsic::AlignOf(*generic_types.first().unwrap()));
                     }
+                    "bounds_checks" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::BoundsChecks);
+                    }
+                    "overflow_checks" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::OverflowChecks);
+                    }
+                    "wrapping_add" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Wrapping(BinOp::Add));
+                    }
+                    "wrapping_sub" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Wrapping(BinOp::Sub));
+                    }
+                    "wrapping_mul" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Wrapping(BinOp::Mul));
+                    }
+                    "add_overflows" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Overflows(BinOp::Add));
+                    }
+                    "sub_overflows" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Overflows(BinOp::Sub));
+                    }
+                    "mul_overflows" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Overflows(BinOp::Mul));
+                    }
+                    "atomic_load" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::Load));
+                    }
+                    "atomic_store" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::Store));
+                    }
+                    "atomic_swap" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::Swap));
+                    }
+                    "atomic_add" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::Rmw(BinOp::Add)));
+                    }
+                    "atomic_sub" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::Rmw(BinOp::Sub)));
+                    }
+                    "atomic_and" => {
+                        is_intrinsic =
+                            Some(ConcreteIntrinsic::Atomic(AtomicOp::Rmw(BinOp::BitAnd)));
+                    }
+                    "atomic_or" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::Rmw(BinOp::BitOr)));
+                    }
+                    "atomic_xor" => {
+                        is_intrinsic =
+                            Some(ConcreteIntrinsic::Atomic(AtomicOp::Rmw(BinOp::BitXor)));
+                    }
+                    "atomic_compare_exchange" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::CompareExchange));
+                    }
+                    "atomic_fence" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::Atomic(AtomicOp::Fence));
+                    }
+                    "thread_start" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::ThreadStart {
+                            task: *generic_types.first().unwrap(),
+                            run: None,
+                        });
+                    }
+                    "future_poll" => {
+                        is_intrinsic = Some(ConcreteIntrinsic::FuturePoll);
+                    }
                     _ => {
                         debug!("Unknown intrinsic attribute {:?}", attr);
                     }
                 }
             }
+            "cfg" => {}
+            // Found before lowering, they set the levels of the lints inside the function.
+            "allow" | "warn" | "deny" => {}
             _ => {
-                debug!("Unknown attribute {:?}", attr);
+                builder.warn_unknown_attribute(attr, FUNCTION_ATTRIBUTES);
             }
         }
     }
 
+    // The atomics access the memory behind the pointer they're given, which must be valid.
+    if let Some(ConcreteIntrinsic::Atomic(_)) = is_intrinsic {
+        if !func.is_unsafe {
+            let attr = func
+                .attributes
+                .iter()
+                .find(|x| x.name == "intrinsic")
+                .unwrap();
+            return Err(LoweringError::InvalidAttribute {
+                span: attr.span,
+                name: attr.name.clone(),
+                reason: format!(
+                    "the atomic intrinsic `{}` must be declared `unsafe fn`",
+                    func.name.name
+                ),
+                path: builder.get_current_module().file_path.clone(),
+            });
+        }
+    }
+
     // Initially, this is the polymorphic symbol, if its a generic function, the symbol changes to the monomorphic version after
     // id resolution
     let mut symbol = Symbol {
@@ -559,7 +1132,7 @@ pub(crate) fn lower_func_decl(
         body: Function {
             name: if !func.is_extern && func.name.name != "main" {
                 builder
-                    .get_mangled_name(module_idx, &func.name.name, fn_id)
+                    .get_mangled_name(module_idx, &symbol)
                     .expect("should get mangled name")
             } else {
                 func.name.name.clone()
@@ -572,19 +1145,41 @@ pub(crate) fn lower_func_decl(
             args: args_ty.clone(),
             ret_ty,
             is_extern: func.is_extern,
+            is_pub: func.is_pub,
             is_intrinsic,
+            inline: None,
+            is_cold: false,
             basic_blocks: Vec::new(),
             module_idx,
             locals: Vec::new(),
+            scopes: Vec::new(),
+            span: Some(func.span),
         },
         name_to_local: HashMap::new(),
         statements: Vec::new(),
         ret_local: 0,
+        ret_type_span: func.ret_type.as_ref().map(|x| x.get_span()),
         builder,
         fn_id,
         local_exists: Default::default(),
+        pattern_locals: Default::default(),
+        scope: None,
+        defers: Vec::new(),
+        deferring: None,
+        is_unsafe: func.is_unsafe,
+        suspends: None,
     };
 
+    // Without a body there's nothing to poll.
+    if func.is_async {
+        return Err(LoweringError::InvalidAsyncFn {
+            span: func.name.span,
+            name: func.name.name.clone(),
+            reason: "only functions with a body can be async".to_string(),
+            path: fn_builder.get_file_path().clone(),
+        });
+    }
+
     fn_builder.builder.ir.functions[fn_id] = Some(fn_builder.body);
     builder.ir.modules[module_idx].functions.insert(fn_id);
 
@@ -606,43 +1201,47 @@ pub(crate) fn get_locals(
                 match &info.target {
                     LetStmtTarget::Simple { id: name, r#type } => {
                         let ty = lower_type(builder.builder, r#type)?;
-                        builder
-                            .name_to_local
-                            .insert(name.name.clone(), builder.body.locals.len());
-                        builder.body.locals.push(Local::new(
+                        let local = builder.add_local(Local::new(
                             Some(name.span),
                             LocalKind::Temp,
                             ty,
                             Some(name.name.clone()),
                             info.is_mutable,
                         ));
+                        builder.name_to_local.insert(name.name.clone(), local);
                     }
-                    LetStmtTarget::Destructure(_) => todo!(),
+                    // The bindings are added when lowering the pattern.
+                    LetStmtTarget::Destructure(_) => {}
                 }
             }
         }
         // handled in the lower function
+        statements::Statement::ForIn(_info) => {}
         statements::Statement::If(_info) => {}
         statements::Statement::Let(info) => match &info.target {
             LetStmtTarget::Simple { id: name, r#type } => {
                 let ty = lower_type(builder.builder, r#type)?;
-                builder
-                    .name_to_local
-                    .insert(name.name.clone(), builder.body.locals.len());
-                builder.body.locals.push(Local::new(
+                let local = builder.add_local(Local::new(
                     Some(name.span),
                     LocalKind::Temp,
                     ty,
                     Some(name.name.clone()),
                     info.is_mutable,
                 ));
+                builder.name_to_local.insert(name.name.clone(), local);
             }
-            LetStmtTarget::Destructure(_) => todo!(),
+            // The bindings are added when lowering the pattern.
+            LetStmtTarget::Destructure(_) => {}
         },
         statements::Statement::Return(_return_stmt) => {}
+        statements::Statement::Become(_become_stmt) => {}
         statements::Statement::While(_while_stmt) => {}
         statements::Statement::FnCall(_fn_call_op) => {}
         statements::Statement::PathOp(_path_op) => {}
+        statements::Statement::MacroCall(_macro_call) => {}
+        // handled where the defer is lowered
+        statements::Statement::Defer(_defer_stmt) => {}
+        statements::Statement::Unsafe(_unsafe_block) => {}
     }
 
     Ok(())
@@ -1,14 +1,32 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, mpsc::Sender},
+};
 
 use tracing::debug;
 
 use crate::{
-    ast::{self, modules::ModuleDefItem},
+    ast::{
+        self,
+        common::{Attribute, AttributeArg, Ident, Span},
+        functions::FunctionDef,
+        modules::ModuleDefItem,
+        types::TypeDescriptor,
+    },
+    check::{
+        borrow_check::check_borrows,
+        lints::{Lint, LintLevel, LintScope, Warning},
+        moves::check_moves,
+        suggestions::{Applicability, Suggestion, find_similar_name},
+        tail_calls::check_tail_calls,
+        unused::{item_names, reachable_functions, referenced_names, used_adts},
+    },
     ir::{
         Adts, Constants, Functions, Module, Modules, Types,
         lowering::{
             Bodies, IRBuilder,
-            errors::{LoweringError, MissingTraitType, UnexpectedTraitType},
+            errors::{AmbiguousImport, LoweringError, MissingTraitType, UnexpectedTraitType},
         },
     },
 };
@@ -16,16 +34,102 @@ use crate::{
 use super::{
     IRBuilderContext, Symbol,
     adts::{lower_enum, lower_struct},
+    cfg::configure_unit,
     constants::lower_constant,
+    coroutines::lower_async_fns,
+    derive::expand_unit,
     errors::{MissingTraitFunction, UnexpectedTraitFunction},
     functions::{lower_func, lower_func_decl},
-    ir::{IR, ModuleIndex, Type},
+    ir::{IR, LangItem, ModuleIndex, Type},
+    macros::{Expansions, MACRO_ATTRIBUTES, expand_units},
     traits::{TraitDatabase, TraitGeneric, TraitImpl},
     types::{lower_type, lower_type_decl},
 };
 
+/// The attributes a struct can have.
+const STRUCT_ATTRIBUTES: &[&str] = &["langitem", "repr", "derive", "cfg", "allow", "warn", "deny"];
+
+/// The lints about unused code, which aren't reported in dependencies.
+const UNUSED_LINTS: &[Lint] = &[
+    Lint::UnusedVariables,
+    Lint::UnusedFunctions,
+    Lint::UnusedTypes,
+    Lint::UnusedImports,
+    Lint::UnreachableCode,
+];
+
+/// The attributes that make a function used even if it's never called, like the ones giving it a
+/// symbol other code calls it by.
+const USED_FUNCTION_ATTRIBUTES: &[&str] = &[
+    "test",
+    "property_test",
+    "fuzz_target",
+    "intrinsic",
+    "no_mangle",
+    "export_name",
+];
+
+/// The calling conventions the functions of an `extern` block can have.
+const EXTERN_ABIS: &[&str] = &["C"];
+
 /// Lowers the ast compile units, the last should be the "main" unit whose unit tests are saved.
+///
+/// The units are lowered one after the other into a single IR, not in parallel: a unit resolves the
+/// items it imports from the others, and the generic functions and types it uses are instantiated
+/// into the units declaring them. Only parsing and generating code are done per unit in parallel.
 pub fn lower_compile_units(compile_units: &[ast::CompilationUnit]) -> Result<IR, LoweringError> {
+    lower_compile_units_with_sink(compile_units, None)
+}
+
+/// Lowers the ast compile units like [`lower_compile_units`], sending each warning to the sink as soon
+/// as it's found, so they can be reported while lowering is still running.
+pub fn lower_compile_units_with_sink(
+    compile_units: &[ast::CompilationUnit],
+    warning_sink: Option<Sender<Warning>>,
+) -> Result<IR, LoweringError> {
+    let (compile_units, expansions) = expand(compile_units)?;
+
+    // The errors found in the code a macro expanded to point at its call and its definition.
+    lower_expanded_units(&compile_units, warning_sink).map_err(|error| expansions.wrap(error))
+}
+
+/// Configures the items of the compile units and expands their macros and derives, like lowering
+/// does first, for the metadata of the units of dependencies. The macros of a unit can be used in
+/// the units after it.
+pub fn expand_compile_units(
+    compile_units: &[ast::CompilationUnit],
+) -> Result<Vec<ast::CompilationUnit>, LoweringError> {
+    expand(compile_units).map(|(units, _)| units)
+}
+
+/// Configures and expands the compile units, except the prebuilt ones, which already are.
+fn expand(
+    compile_units: &[ast::CompilationUnit],
+) -> Result<(Vec<ast::CompilationUnit>, Expansions), LoweringError> {
+    let compile_units = compile_units
+        .iter()
+        .map(|unit| match unit.is_prebuilt {
+            true => Ok(unit.clone()),
+            false => configure_unit(unit),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (compile_units, expansions) = expand_units(compile_units)?;
+    let compile_units = compile_units
+        .into_iter()
+        .map(|unit| match unit.is_prebuilt {
+            true => Ok(unit),
+            false => expand_unit(unit),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((compile_units, expansions))
+}
+
+/// Lowers the compile units once their items are configured and their macros expanded.
+fn lower_expanded_units(
+    compile_units: &[ast::CompilationUnit],
+    warning_sink: Option<Sender<Warning>>,
+) -> Result<IR, LoweringError> {
     let mut builder = IRBuilder {
         ir: IR {
             types: Types::new(),
@@ -34,11 +138,18 @@ pub fn lower_compile_units(compile_units: &[ast::CompilationUnit]) -> Result<IR,
             constants: Constants::new(),
             modules: Modules::new(),
             top_level_modules: Vec::new(),
+            compile_units: Vec::new(),
             builtin_types: Default::default(),
             tests: Vec::new(),
+            property_tests: Vec::new(),
+            fuzz_targets: Vec::new(),
+            test_retries: HashMap::new(),
+            warnings: Vec::new(),
+            prebuilt_modules: HashSet::new(),
         },
         symbols: Default::default(),
         top_level_modules_names: Default::default(),
+        module_aliases: Default::default(),
         adt_to_type_idx: Default::default(),
         type_to_module: Default::default(),
         bodies: Bodies::default(),
@@ -49,17 +160,46 @@ pub fn lower_compile_units(compile_units: &[ast::CompilationUnit]) -> Result<IR,
             self_ty: None,
             generics_mapping: Default::default(),
             module_stack: Vec::with_capacity(8),
+            constants_stack: Vec::new(),
+            type_aliases_stack: Vec::new(),
         },
+        warning_sink,
+        lint_scopes: Vec::new(),
+        const_fn_calls: HashSet::new(),
+        fn_to_module: HashMap::new(),
+        private_functions: HashMap::new(),
+        private_adts: HashMap::new(),
+        lang_items: HashMap::new(),
+        future_adt: None,
+        async_fns: Vec::new(),
+        errors: Vec::new(),
     };
 
+    for compile_unit in compile_units {
+        for module in &compile_unit.modules {
+            collect_lint_scopes(&mut builder, module, compile_unit.is_dependency);
+        }
+    }
+
     // Prepass to fill some symbols.
     let last_i = compile_units.len() - 1;
     for (i, compile_unit) in compile_units.iter().enumerate() {
         builder.context.add_tests = i == last_i;
+        let mut unit_modules = Vec::new();
         for module in &compile_unit.modules {
             debug!("Lowering symbols for module {:?}", module.name.name);
-            lower_module_symbols(&mut builder, module, &[])?;
+            let module_idx = lower_module_symbols(&mut builder, module, &[])?;
+            if compile_unit.is_prebuilt {
+                builder.ir.prebuilt_modules.insert(module_idx);
+            }
+            if !compile_unit.module_aliases.is_empty() {
+                builder
+                    .module_aliases
+                    .insert(module_idx, compile_unit.module_aliases.clone());
+            }
+            unit_modules.push(module_idx);
         }
+        builder.ir.compile_units.push(unit_modules);
     }
 
     // Handle imports so they are transparent afterwards.
@@ -72,9 +212,12 @@ pub fn lower_compile_units(compile_units: &[ast::CompilationUnit]) -> Result<IR,
                 .get(&module.name.name)
                 .expect("should exist");
 
-            lower_imports(&mut builder, module, module_idx, &[])?;
+            builder.recover(|builder| lower_imports(builder, module, module_idx, &[]));
         }
     }
+    // The items can't be lowered with unresolved imports, their errors would only follow from
+    // these.
+    builder.check_errors()?;
 
     for (i, compile_unit) in compile_units.iter().enumerate() {
         builder.context.add_tests = i == last_i;
@@ -86,10 +229,307 @@ pub fn lower_compile_units(compile_units: &[ast::CompilationUnit]) -> Result<IR,
             lower_module(&mut builder, module, module_idx)?;
         }
     }
+    builder.check_errors()?;
+
+    warn_dead_code(&mut builder, compile_units);
+    check_borrows(&builder.ir)?;
+    check_moves(&builder.ir)?;
+    check_tail_calls(&builder.ir)?;
+    lower_async_fns(&mut builder);
 
     Ok(builder.ir)
 }
 
+/// Finds the items with lint attributes before lowering, so the warnings found in them get the
+/// levels they set.
+fn collect_lint_scopes(
+    builder: &mut IRBuilder,
+    module: &ast::modules::Module,
+    is_dependency: bool,
+) {
+    let path = module.file_path.as_path();
+
+    // Unused code in dependencies isn't something the package can fix.
+    if is_dependency {
+        for lint in UNUSED_LINTS {
+            builder.lint_scopes.push(LintScope {
+                lint: *lint,
+                level: LintLevel::Allow,
+                path: path.to_path_buf(),
+                span: module.span,
+            });
+        }
+    }
+
+    add_lint_scopes(builder, path, &module.attributes, module.span);
+
+    for item in &module.contents {
+        match item {
+            ModuleDefItem::Function(x) => {
+                add_lint_scopes(builder, path, &x.decl.attributes, x.span);
+            }
+            ModuleDefItem::FunctionDecl(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::ExternBlock(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::Impl(x) => {
+                add_lint_scopes(builder, path, &x.attributes, x.span);
+                for method in &x.methods {
+                    add_lint_scopes(builder, path, &method.decl.attributes, method.span);
+                }
+            }
+            ModuleDefItem::ImplTrait(x) => {
+                add_lint_scopes(builder, path, &x.attributes, x.span);
+                for method in &x.methods {
+                    add_lint_scopes(builder, path, &method.decl.attributes, method.span);
+                }
+            }
+            ModuleDefItem::Struct(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::Union(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::Enum(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::Type(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::Trait(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::Module(x) => collect_lint_scopes(builder, x, is_dependency),
+            ModuleDefItem::Import(x) => add_lint_scopes(builder, path, &x.attributes, x.span),
+            ModuleDefItem::Constant(_)
+            | ModuleDefItem::ExternalModule(_)
+            | ModuleDefItem::Macro(_) => {}
+        }
+    }
+}
+
+/// Adds the levels set by the lint attributes of an item, like `#[allow(unused_variables)]`.
+/// `warnings` names all the lints.
+fn add_lint_scopes(builder: &mut IRBuilder, path: &Path, attributes: &[Attribute], span: Span) {
+    for attr in attributes {
+        let Some(level) = LintLevel::from_attribute(&attr.name) else {
+            continue;
+        };
+
+        for arg in &attr.args {
+            let AttributeArg::Word(name) = arg else {
+                builder.emit_warning_in(
+                    path,
+                    Lint::UnknownLints,
+                    arg.span(),
+                    format!(
+                        "expected the name of a lint, e.g: #[{}(unused_variables)]",
+                        attr.name
+                    ),
+                    None,
+                );
+                continue;
+            };
+
+            let lints = match Lint::from_name(&name.name) {
+                Some(lint) => vec![lint],
+                None if name.name == "warnings" => Lint::ALL.to_vec(),
+                None => {
+                    let names = Lint::ALL.iter().map(|x| x.name()).chain(["warnings"]);
+                    let suggestion = find_similar_name(&name.name, names).map(|x| Suggestion {
+                        message: "a lint with a similar name exists".to_string(),
+                        path: None,
+                        span: name.span,
+                        replacement: x.to_string(),
+                        applicability: Applicability::MaybeIncorrect,
+                    });
+                    builder.emit_warning_in(
+                        path,
+                        Lint::UnknownLints,
+                        name.span,
+                        format!("unknown lint '{}'", name.name),
+                        suggestion,
+                    );
+                    continue;
+                }
+            };
+
+            for lint in lints {
+                builder.lint_scopes.push(LintScope {
+                    lint,
+                    level,
+                    path: path.to_path_buf(),
+                    span,
+                });
+            }
+        }
+    }
+}
+
+/// Warns about the private functions and types that are never used and the imports never referred
+/// to.
+///
+/// Functions are used if they can be called from a root: `main`, the public functions, the ones
+/// with attributes like `#[test]` used by the runner, trait methods and the functions called at
+/// compile time. Types are used if the used functions or the constants use them.
+fn warn_dead_code(builder: &mut IRBuilder, compile_units: &[ast::CompilationUnit]) {
+    // The bodies of generic functions are only lowered if they're called, the functions they
+    // call are used if they are.
+    let mut generic_calls = HashSet::new();
+    let mut trait_methods = HashSet::new();
+    for unit in compile_units {
+        for module in &unit.modules {
+            find_implicit_roots(module, &mut generic_calls, &mut trait_methods);
+        }
+    }
+
+    let is_root = |def: &Arc<FunctionDef>| {
+        let decl = &def.decl;
+        decl.is_pub
+            || decl.is_extern
+            || decl.name.name == "main"
+            // Generic functions are only lowered for the types they're called with.
+            || !decl.generic_params.is_empty()
+            || decl
+                .attributes
+                .iter()
+                .any(|x| USED_FUNCTION_ATTRIBUTES.contains(&x.name.as_str()))
+            || generic_calls.contains(&decl.name.name)
+            || trait_methods.contains(&Arc::as_ptr(def))
+    };
+    let roots = builder
+        .bodies
+        .functions
+        .iter()
+        .filter(|(_, def)| is_root(def))
+        .map(|(id, _)| *id)
+        .chain(builder.const_fn_calls.iter().copied());
+    let reached = reachable_functions(&builder.ir, roots);
+    let used_adts = used_adts(&builder.ir, &reached);
+
+    let mut unused = Unused::default();
+    for (id, def) in &builder.bodies.functions {
+        if !reached.contains(id) {
+            unused.functions.insert(Arc::as_ptr(def));
+        }
+    }
+    for (id, decl) in &builder.bodies.structs {
+        if !used_adts.contains(id) {
+            unused.structs.insert(Arc::as_ptr(decl));
+        }
+    }
+    for (id, decl) in &builder.bodies.enums {
+        if !used_adts.contains(id) {
+            unused.enums.insert(Arc::as_ptr(decl));
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for unit in compile_units {
+        for module in &unit.modules {
+            find_dead_code(module, &unused, &mut warnings);
+        }
+    }
+
+    for (path, lint, span, message) in warnings {
+        builder.emit_warning_in(&path, lint, span, message, None);
+    }
+}
+
+/// The items no root reaches, by the address of their definitions.
+#[derive(Default)]
+struct Unused {
+    functions: HashSet<*const FunctionDef>,
+    structs: HashSet<*const ast::structs::StructDecl>,
+    enums: HashSet<*const ast::enums::EnumDecl>,
+}
+
+/// The names called in the generic functions and methods of generic types of the module, and the
+/// methods of its trait implementations, which can be called through the trait.
+fn find_implicit_roots(
+    module: &ast::modules::Module,
+    names: &mut HashSet<String>,
+    trait_methods: &mut HashSet<*const FunctionDef>,
+) {
+    for item in &module.contents {
+        let (functions, is_generic) = match item {
+            ModuleDefItem::Function(x) => (std::slice::from_ref(x), false),
+            ModuleDefItem::Impl(x) => (x.methods.as_slice(), !x.generic_params.is_empty()),
+            ModuleDefItem::ImplTrait(x) => {
+                trait_methods.extend(x.methods.iter().map(Arc::as_ptr));
+                (x.methods.as_slice(), !x.generic_params.is_empty())
+            }
+            ModuleDefItem::Module(x) => {
+                find_implicit_roots(x, names, trait_methods);
+                continue;
+            }
+            _ => continue,
+        };
+
+        for function in functions {
+            if is_generic || !function.decl.generic_params.is_empty() {
+                referenced_names(&function.body, names);
+            }
+        }
+    }
+}
+
+fn find_dead_code(
+    module: &ast::modules::Module,
+    unused: &Unused,
+    warnings: &mut Vec<(PathBuf, Lint, Span, String)>,
+) {
+    let mut names = HashSet::new();
+    item_names(module, &mut names);
+
+    let mut found = Vec::new();
+    for item in &module.contents {
+        match item {
+            ModuleDefItem::Function(x) if unused.functions.contains(&Arc::as_ptr(x)) => {
+                found.push((Lint::UnusedFunctions, &x.decl.name, "function"));
+            }
+            // The methods of generic types are only lowered for the types they're used with.
+            ModuleDefItem::Impl(x) if x.generic_params.is_empty() => {
+                for method in &x.methods {
+                    if unused.functions.contains(&Arc::as_ptr(method)) {
+                        found.push((Lint::UnusedFunctions, &method.decl.name, "function"));
+                    }
+                }
+            }
+            // Like generic functions, generic types are only lowered for the types they're used
+            // with.
+            ModuleDefItem::Struct(x)
+                if !x.is_pub
+                    && x.generics.is_empty()
+                    && !has_layout_attribute(&x.attributes)
+                    && unused.structs.contains(&Arc::as_ptr(x)) =>
+            {
+                found.push((Lint::UnusedTypes, &x.name, "struct"));
+            }
+            ModuleDefItem::Enum(x)
+                if !x.is_pub
+                    && x.generics.is_empty()
+                    && !has_layout_attribute(&x.attributes)
+                    && unused.enums.contains(&Arc::as_ptr(x)) =>
+            {
+                found.push((Lint::UnusedTypes, &x.name, "enum"));
+            }
+            ModuleDefItem::Import(x) => {
+                for symbol in x.symbols.iter().map(|x| x.local_name()) {
+                    if !names.contains(&symbol.name) {
+                        found.push((Lint::UnusedImports, symbol, "import"));
+                    }
+                }
+            }
+            ModuleDefItem::Module(x) => find_dead_code(x, unused, warnings),
+            _ => {}
+        }
+    }
+
+    for (lint, name, kind) in found {
+        let message = match lint {
+            Lint::UnusedImports => format!("unused import '{}'", name.name),
+            _ => format!("{kind} '{}' is never used", name.name),
+        };
+        warnings.push((module.file_path.clone(), lint, name.span, message));
+    }
+}
+
+/// Types the compiler or C code rely on, which are used even if the program doesn't mention them.
+fn has_layout_attribute(attributes: &[Attribute]) -> bool {
+    attributes
+        .iter()
+        .any(|x| x.name == "langitem" || x.name == "repr")
+}
+
 fn lower_module_symbols(
     builder: &mut IRBuilder,
     module: &ast::modules::Module,
@@ -149,7 +589,10 @@ fn lower_module_symbols(
             ast::modules::ModuleDefItem::ImplTrait(_impl_trait) => { /* Done in second pass */ }
             ast::modules::ModuleDefItem::Constant(constant_def) => {
                 let idx = builder.ir.constants.insert(None);
-                builder.bodies.constants.insert(idx, constant_def.clone());
+                builder
+                    .bodies
+                    .constants
+                    .insert(idx, (constant_def.clone(), module_idx));
                 builder
                     .symbols
                     .get_mut(&module_idx)
@@ -174,6 +617,10 @@ fn lower_module_symbols(
                         (idx, module_idx),
                     );
                 builder.ir.modules[module_idx].functions.insert(idx);
+                builder.fn_to_module.insert(idx, module_idx);
+                if !function_def.decl.is_pub {
+                    builder.private_functions.insert(idx, module_idx);
+                }
                 debug!(
                     "Adding function symbol {:?} to module {:?} ({})",
                     function_def.decl.name.name,
@@ -182,25 +629,7 @@ fn lower_module_symbols(
                 );
             }
             ast::modules::ModuleDefItem::FunctionDecl(function_decl) => {
-                let idx = builder.ir.functions.insert(None);
-                builder
-                    .bodies
-                    .functions_decls
-                    .insert(idx, function_decl.clone());
-                builder
-                    .symbols
-                    .get_mut(&module_idx)
-                    .unwrap()
-                    .functions
-                    .insert(
-                        Symbol {
-                            name: function_decl.name.name.clone(),
-                            method_of: None,
-                            generics: Vec::new(),
-                        },
-                        (idx, module_idx),
-                    );
-                builder.ir.modules[module_idx].functions.insert(idx);
+                add_function_decl_symbol(builder, function_decl, module_idx);
                 debug!(
                     "Adding function decl symbol {:?} to module {:?} ({})",
                     function_decl.name.name,
@@ -208,6 +637,11 @@ fn lower_module_symbols(
                     module_idx.to_idx(),
                 );
             }
+            ast::modules::ModuleDefItem::ExternBlock(block) => {
+                for function_decl in &block.functions {
+                    add_function_decl_symbol(builder, function_decl, module_idx);
+                }
+            }
             ast::modules::ModuleDefItem::Impl(_) => {}
             ast::modules::ModuleDefItem::Struct(struct_decl) => {
                 let idx = builder.ir.aggregates.insert(None);
@@ -230,6 +664,9 @@ fn lower_module_symbols(
                 builder.ir.modules[module_idx].types.insert(type_idx);
                 builder.adt_to_type_idx.insert(idx, type_idx);
                 builder.type_to_module.insert(type_idx, module_idx);
+                if !struct_decl.is_pub {
+                    builder.private_adts.insert(idx, module_idx);
+                }
 
                 for attr in &struct_decl.attributes {
                     if attr.name == "langitem" {
@@ -239,14 +676,56 @@ fn lower_module_symbols(
                             "String" => {
                                 builder.ir.builtin_types.insert(Type::String, type_idx);
                             }
+                            // The calls of async functions build its values, of the pointers to
+                            // their frame and poll function.
+                            "Future" => {
+                                let is_ptr = |x: &ast::structs::Field| {
+                                    matches!(
+                                        x.r#type,
+                                        TypeDescriptor::MutPtr { .. }
+                                            | TypeDescriptor::ConstPtr { .. }
+                                    )
+                                };
+                                if struct_decl.generics.len() != 1
+                                    || struct_decl.fields.len() != 2
+                                    || !struct_decl.fields.iter().all(is_ptr)
+                                {
+                                    return Err(LoweringError::InvalidAttribute {
+                                        span: attr.span,
+                                        name: attr.name.clone(),
+                                        reason: "the `Future` lang item must have a generic \
+                                                 parameter and two pointer fields, to the frame \
+                                                 and the poll function"
+                                            .to_string(),
+                                        path: builder.get_current_module().file_path.clone(),
+                                    });
+                                }
+                                builder.future_adt = Some(idx);
+                            }
                             _ => {
                                 return Err(LoweringError::UnknownLangItem {
                                     span: attr.span,
-                                    item: attr.name.clone(),
+                                    item: langitem.clone(),
                                     path: builder.get_current_module().file_path.clone(),
                                 });
                             }
                         }
+                    } else if attr.name == "repr" {
+                        // Structs are already laid out like C ones, it's only checked.
+                        if attr.value.as_deref() != Some("C") {
+                            return Err(LoweringError::InvalidAttribute {
+                                span: attr.span,
+                                name: attr.name.clone(),
+                                reason: "the only supported representation is `#[repr = \"C\"]`"
+                                    .to_string(),
+                                path: builder.get_current_module().file_path.clone(),
+                            });
+                        }
+                    } else if attr.name != "cfg"
+                        && attr.name != "derive"
+                        && LintLevel::from_attribute(&attr.name).is_none()
+                    {
+                        builder.warn_unknown_attribute(attr, STRUCT_ATTRIBUTES);
                     }
                 }
 
@@ -277,6 +756,49 @@ fn lower_module_symbols(
                 builder.ir.modules[module_idx].types.insert(type_idx);
                 builder.adt_to_type_idx.insert(idx, type_idx);
                 builder.type_to_module.insert(type_idx, module_idx);
+                if !enum_decl.is_pub {
+                    builder.private_adts.insert(idx, module_idx);
+                }
+
+                for attr in &enum_decl.attributes {
+                    if attr.name == "langitem" {
+                        let langitem = attr.value.as_ref().unwrap();
+                        let Some(lang_item) = LangItem::from_name(langitem) else {
+                            return Err(LoweringError::UnknownLangItem {
+                                span: attr.span,
+                                item: langitem.clone(),
+                                path: builder.get_current_module().file_path.clone(),
+                            });
+                        };
+
+                        // The compiler builds and matches its values by the names of the variants.
+                        let has_variants = lang_item.variants().iter().all(|(name, fields)| {
+                            enum_decl
+                                .variants
+                                .iter()
+                                .any(|x| x.name.name == *name && x.fields.len() == *fields)
+                        });
+                        if !has_variants || enum_decl.variants.len() != 2 {
+                            let [(first, _), (other, other_fields)] = lang_item.variants();
+                            let other = if other_fields == 0 {
+                                format!("`{other}`")
+                            } else {
+                                format!("`{other}` with one field")
+                            };
+                            return Err(LoweringError::InvalidAttribute {
+                                span: attr.span,
+                                name: attr.name.clone(),
+                                reason: format!(
+                                    "the `{}` lang item must have the variants `{first}` with one field and {other}",
+                                    lang_item.name()
+                                ),
+                                path: builder.get_current_module().file_path.clone(),
+                            });
+                        }
+
+                        builder.lang_items.insert(idx, lang_item);
+                    }
+                }
 
                 debug!(
                     "Adding enum symbol {:?} to module {:?}",
@@ -302,6 +824,8 @@ fn lower_module_symbols(
             }
             ast::modules::ModuleDefItem::ExternalModule(_) => {}
             ast::modules::ModuleDefItem::Import(_) => {}
+            // Expanded before lowering.
+            ast::modules::ModuleDefItem::Macro(_) => {}
         }
     }
 
@@ -349,19 +873,15 @@ fn lower_module_symbols(
                         .functions
                         .insert(sym.clone(), (idx, module_idx));
                     builder.ir.modules[module_idx].functions.insert(idx);
+                    builder.fn_to_module.insert(idx, module_idx);
+                    if !function_def.decl.is_pub {
+                        builder.private_functions.insert(idx, module_idx);
+                    }
                 }
             }
             ast::modules::ModuleDefItem::ImplTrait(impl_trait) => {
-                // Get trait id and verify it exists.
-                let _trait_id = builder
-                    .trait_db
-                    .get_trait_by_name(&impl_trait.target_trait.name.name, module_idx)
-                    .ok_or_else(|| LoweringError::TraitNotFound {
-                        span: impl_trait.target_trait.span,
-                        name: impl_trait.target_trait.name.name.clone(),
-                        path: builder.get_current_module().file_path.clone(),
-                    })?;
-
+                // The trait may be imported, so it's only looked up in `lower_module`, once the
+                // imports are resolved.
                 let ty = if !impl_trait.generic_params.is_empty() {
                     let adt_symbol = Symbol {
                         name: impl_trait.target.get_name().unwrap(),
@@ -413,6 +933,7 @@ fn lower_module_symbols(
                         .functions
                         .insert(sym.clone(), (idx, module_idx));
                     builder.ir.modules[module_idx].functions.insert(idx);
+                    builder.fn_to_module.insert(idx, module_idx);
                 }
             }
             _ => {}
@@ -424,6 +945,37 @@ fn lower_module_symbols(
     Ok(module_idx)
 }
 
+/// Adds the symbol of a function without a body, defined in native code.
+fn add_function_decl_symbol(
+    builder: &mut IRBuilder,
+    function_decl: &Arc<ast::functions::FunctionDecl>,
+    module_idx: ModuleIndex,
+) {
+    let idx = builder.ir.functions.insert(None);
+    builder
+        .bodies
+        .functions_decls
+        .insert(idx, function_decl.clone());
+    builder
+        .symbols
+        .get_mut(&module_idx)
+        .unwrap()
+        .functions
+        .insert(
+            Symbol {
+                name: function_decl.name.name.clone(),
+                method_of: None,
+                generics: Vec::new(),
+            },
+            (idx, module_idx),
+        );
+    builder.ir.modules[module_idx].functions.insert(idx);
+    builder.fn_to_module.insert(idx, module_idx);
+    if !function_decl.is_pub {
+        builder.private_functions.insert(idx, module_idx);
+    }
+}
+
 fn lower_imports(
     builder: &mut IRBuilder,
     module: &ast::modules::Module,
@@ -433,6 +985,7 @@ fn lower_imports(
     builder.enter_module_context(module_idx);
     let import_from_path = builder.ir.modules[module_idx].file_path.clone();
 
+    let mut globs = Vec::new();
     for stmt in &module.contents {
         if let ModuleDefItem::Import(import) = stmt {
             let mut target_module = module_idx;
@@ -451,22 +1004,22 @@ fn lower_imports(
                         root_requested = true;
                     } else {
                         let id = if root_requested {
-                            *builder
-                                .top_level_modules_names
-                                .get(&m.name)
+                            builder
+                                .get_top_level_module(module_idx, &m.name)
                                 .ok_or_else(|| LoweringError::ModuleNotFound {
                                     span: m.span,
                                     module: m.name.clone(),
                                     path: import_from_path.to_path_buf(),
                                 })?
                         } else {
-                            *builder
+                            builder
                                 .symbols
                                 .get(&target_module)
                                 .unwrap()
                                 .modules
                                 .get(&m.name)
-                                .or_else(|| builder.top_level_modules_names.get(&m.name))
+                                .copied()
+                                .or_else(|| builder.get_top_level_module(module_idx, &m.name))
                                 .ok_or_else(|| LoweringError::ModuleNotFound {
                                     span: m.span,
                                     module: m.name.clone(),
@@ -494,100 +1047,68 @@ fn lower_imports(
 
             let target_module = target_module;
 
-            for sym in &import.symbols {
-                let target_symbols = builder.symbols.get(&target_module).unwrap();
-
-                let symbol = Symbol {
-                    name: sym.name.clone(),
-                    method_of: None,
-                    generics: Vec::new(),
-                };
-                if let Some((id, mod_id)) = target_symbols.functions.get(&symbol).cloned() {
-                    debug!(
-                        "Imported function symbol {:?} ({}) to module {} ({})",
-                        symbol.name,
-                        mod_id.to_idx(),
-                        builder.ir.modules[module_idx].name,
-                        module_idx.to_idx()
-                    );
-                    builder.ir.modules[module_idx].functions.insert(id);
-                    builder
-                        .symbols
-                        .get_mut(&module_idx)
-                        .unwrap()
-                        .functions
-                        .insert(symbol.clone(), (id, mod_id));
-                    continue;
-                }
-
-                let target_symbols = builder.symbols.get(&target_module).unwrap();
-                if let Some(adt_idx) = target_symbols.aggregates.get(&symbol).cloned() {
-                    debug!(
-                        "Imported adt symbol {:?} to module {}",
-                        symbol, builder.ir.modules[module_idx].name
-                    );
-                    builder.ir.modules[module_idx].aggregates.insert(adt_idx);
-                    builder
-                        .symbols
-                        .get_mut(&module_idx)
-                        .unwrap()
-                        .aggregates
-                        .insert(symbol.clone(), adt_idx);
-
-                    continue;
-                }
-
-                let target_symbols = builder.symbols.get(&target_module).unwrap();
-                if let Some(id) = target_symbols.types.get(&sym.name).cloned() {
-                    debug!(
-                        "Imported type symbol {:?} to module {}",
-                        symbol, builder.ir.modules[module_idx].name
-                    );
-                    builder.ir.modules[module_idx].types.insert(id);
-                    builder
-                        .symbols
-                        .get_mut(&module_idx)
-                        .unwrap()
-                        .types
-                        .insert(sym.name.clone(), id);
-                    continue;
-                }
+            // Glob imports are resolved last, so they can find the names they conflict with.
+            if import.glob.is_some() {
+                globs.push((import, target_module));
+                continue;
+            }
 
-                let target_symbols = builder.symbols.get(&target_module).unwrap();
-                if let Some(id) = target_symbols.constants.get(&sym.name).cloned() {
-                    debug!(
-                        "Imported constant symbol {:?} to module {}",
-                        symbol, builder.ir.modules[module_idx].name
-                    );
-                    builder.ir.modules[module_idx].constants.insert(id);
-                    builder
-                        .symbols
-                        .get_mut(&module_idx)
-                        .unwrap()
-                        .constants
-                        .insert(sym.name.clone(), id);
-                    continue;
+            for sym in &import.symbols {
+                if !import_symbol(
+                    builder,
+                    module_idx,
+                    target_module,
+                    &sym.name,
+                    sym.local_name(),
+                )? {
+                    Err(LoweringError::ImportNotFound {
+                        module_span: module.span,
+                        import_span: import.span,
+                        symbol: sym.name.clone(),
+                        path: import_from_path.to_path_buf(),
+                    })?;
                 }
 
-                Err(LoweringError::ImportNotFound {
-                    module_span: module.span,
-                    import_span: import.span,
-                    symbol: sym.clone(),
-                    path: import_from_path.to_path_buf(),
-                })?;
+                // Imported items are monomorphized where they're imported from, so the modules
+                // importing a generic struct or enum share its instances.
+                builder.get_current_symbols_mut().aliases.insert(
+                    sym.local_name().name.clone(),
+                    (target_module, sym.name.name.clone()),
+                );
             }
         }
+    }
 
-        for m in &module.contents {
-            if let ast::modules::ModuleDefItem::Module(submodule) = m {
-                let mut parents = parents.to_vec();
-                parents.push(module_idx);
-                let new_module_idx = *builder.ir.modules[module_idx]
-                    .modules
-                    .get(&submodule.name.name)
-                    .expect("failed to find submodule id");
-                lower_imports(builder, submodule, new_module_idx, &parents)?;
+    for (import, target_module) in globs {
+        let glob_span = import.glob.expect("it's a glob import");
+        for (name, existing) in glob_symbols(builder, module_idx, target_module) {
+            if let Some((def_span, def_path)) = existing {
+                return Err(LoweringError::AmbiguousImport(Box::new(AmbiguousImport {
+                    span: glob_span,
+                    name,
+                    path: import_from_path.clone(),
+                    def_span,
+                    def_path,
+                })));
             }
+
+            let sym = Ident {
+                name,
+                span: glob_span,
+            };
+            import_symbol(builder, module_idx, target_module, &sym, &sym)?;
+        }
+    }
+
+    for m in &module.contents {
+        if let ast::modules::ModuleDefItem::Module(submodule) = m {
+            let mut parents = parents.to_vec();
+            parents.push(module_idx);
+            let new_module_idx = *builder.ir.modules[module_idx]
+                .modules
+                .get(&submodule.name.name)
+                .expect("failed to find submodule id");
+            lower_imports(builder, submodule, new_module_idx, &parents)?;
         }
     }
 
@@ -596,6 +1117,291 @@ fn lower_imports(
     Ok(())
 }
 
+/// Imports the symbol of the target module with the given name into the module, known there by
+/// the local name. Returns whether the target module has it.
+fn import_symbol(
+    builder: &mut IRBuilder,
+    module_idx: ModuleIndex,
+    target_module: ModuleIndex,
+    sym: &Ident,
+    local: &Ident,
+) -> Result<bool, LoweringError> {
+    let symbol = Symbol {
+        name: sym.name.clone(),
+        method_of: None,
+        generics: Vec::new(),
+    };
+    let local_symbol = Symbol {
+        name: local.name.clone(),
+        ..symbol.clone()
+    };
+
+    let target_symbols = builder.symbols.get(&target_module).unwrap();
+    if let Some((id, mod_id)) = target_symbols.functions.get(&symbol).cloned() {
+        if let Some(def_module) = builder.private_functions.get(&id) {
+            let decl = builder.get_fn_decl(id);
+            builder.check_visibility(
+                *def_module,
+                module_idx,
+                "function",
+                &sym.name,
+                sym.span,
+                decl.name.span,
+                decl.vis_span,
+            )?;
+        }
+        debug!(
+            "Imported function symbol {:?} ({}) to module {} ({})",
+            symbol.name,
+            mod_id.to_idx(),
+            builder.ir.modules[module_idx].name,
+            module_idx.to_idx()
+        );
+        builder.ir.modules[module_idx].functions.insert(id);
+        builder
+            .symbols
+            .get_mut(&module_idx)
+            .unwrap()
+            .functions
+            .insert(local_symbol, (id, mod_id));
+        return Ok(true);
+    }
+
+    let target_symbols = builder.symbols.get(&target_module).unwrap();
+    if let Some(adt_idx) = target_symbols.aggregates.get(&symbol).cloned() {
+        if let Some(def_module) = builder.private_adts.get(&adt_idx) {
+            let (kind, def_span, vis_span) = builder.get_adt_decl_name(adt_idx);
+            builder.check_visibility(
+                *def_module,
+                module_idx,
+                kind,
+                &sym.name,
+                sym.span,
+                def_span,
+                vis_span,
+            )?;
+        }
+        debug!(
+            "Imported adt symbol {:?} to module {}",
+            symbol, builder.ir.modules[module_idx].name
+        );
+        builder.ir.modules[module_idx].aggregates.insert(adt_idx);
+        builder
+            .symbols
+            .get_mut(&module_idx)
+            .unwrap()
+            .aggregates
+            .insert(local_symbol, adt_idx);
+        return Ok(true);
+    }
+
+    let target_symbols = builder.symbols.get(&target_module).unwrap();
+    if let Some(id) = target_symbols.types.get(&sym.name).cloned() {
+        if let Some(decl) = builder.bodies.types.get(&id).filter(|x| !x.is_pub) {
+            builder.check_visibility(
+                builder.type_to_module[&id],
+                module_idx,
+                "type",
+                &sym.name,
+                sym.span,
+                decl.name.span,
+                decl.vis_span,
+            )?;
+        }
+        debug!(
+            "Imported type symbol {:?} to module {}",
+            symbol, builder.ir.modules[module_idx].name
+        );
+        builder.ir.modules[module_idx].types.insert(id);
+        builder
+            .symbols
+            .get_mut(&module_idx)
+            .unwrap()
+            .types
+            .insert(local.name.clone(), id);
+        return Ok(true);
+    }
+
+    let target_symbols = builder.symbols.get(&target_module).unwrap();
+    if let Some(id) = target_symbols.constants.get(&sym.name).cloned() {
+        let (def, def_module) = &builder.bodies.constants[&id];
+        if !def.decl.is_pub {
+            builder.check_visibility(
+                *def_module,
+                module_idx,
+                "constant",
+                &sym.name,
+                sym.span,
+                def.decl.name.span,
+                def.decl.vis_span,
+            )?;
+        }
+        debug!(
+            "Imported constant symbol {:?} to module {}",
+            symbol, builder.ir.modules[module_idx].name
+        );
+        builder.ir.modules[module_idx].constants.insert(id);
+        builder
+            .symbols
+            .get_mut(&module_idx)
+            .unwrap()
+            .constants
+            .insert(local.name.clone(), id);
+        return Ok(true);
+    }
+
+    if let Some(id) = builder.trait_db.get_trait_by_name(&sym.name, target_module) {
+        let decl = builder.trait_db.traits[id].clone();
+        if !decl.is_pub {
+            builder.check_visibility(
+                builder.trait_db.get_trait_module_idx(id),
+                module_idx,
+                "trait",
+                &sym.name,
+                sym.span,
+                decl.name.span,
+                decl.vis_span,
+            )?;
+        }
+        debug!(
+            "Imported trait symbol {:?} to module {}",
+            symbol, builder.ir.modules[module_idx].name
+        );
+        builder.trait_db.import_trait(id, module_idx, &local.name);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// The names of the items defined in the target module a glob import brings into the module, the
+/// ones the module can use. If the module already has another item with the same name, the span
+/// and file of its definition.
+fn glob_symbols(
+    builder: &IRBuilder,
+    module_idx: ModuleIndex,
+    target_module: ModuleIndex,
+) -> Vec<(String, Option<(Span, PathBuf)>)> {
+    let target_symbols = &builder.symbols[&target_module];
+    let symbols = &builder.symbols[&module_idx];
+    let def_path = |module: ModuleIndex| builder.ir.modules[module].file_path.clone();
+    let mut found = Vec::new();
+
+    for (symbol, (id, def_module)) in &target_symbols.functions {
+        if symbol.method_of.is_some() || !symbol.generics.is_empty() || *def_module != target_module
+        {
+            continue;
+        }
+        if builder.private_functions.contains_key(id)
+            && !builder.is_visible(target_module, module_idx)
+        {
+            continue;
+        }
+
+        let existing = symbols
+            .functions
+            .get(symbol)
+            .filter(|(other, _)| other != id)
+            .map(|(other, other_module)| {
+                let span = builder.get_fn_decl(*other).name.span;
+                (span, def_path(*other_module))
+            });
+        found.push((symbol.name.clone(), existing));
+    }
+
+    for (symbol, id) in &target_symbols.aggregates {
+        let def_module = builder.type_to_module[&builder.adt_to_type_idx[id]];
+        if !symbol.generics.is_empty() || def_module != target_module {
+            continue;
+        }
+        if builder.private_adts.contains_key(id) && !builder.is_visible(target_module, module_idx) {
+            continue;
+        }
+
+        let existing = symbols
+            .aggregates
+            .get(symbol)
+            .filter(|other| *other != id)
+            .map(|other| {
+                let other_module = builder.type_to_module[&builder.adt_to_type_idx[other]];
+                (builder.get_adt_decl_name(*other).1, def_path(other_module))
+            });
+        found.push((symbol.name.clone(), existing));
+    }
+
+    for (name, id) in &target_symbols.types {
+        let Some(decl) = builder.bodies.types.get(id) else {
+            continue;
+        };
+        let def_module = builder.type_to_module[id];
+        if def_module != target_module
+            || (!decl.is_pub && !builder.is_visible(target_module, module_idx))
+        {
+            continue;
+        }
+
+        let existing = symbols
+            .types
+            .get(name)
+            .filter(|other| *other != id)
+            .map(|other| {
+                let span = builder
+                    .bodies
+                    .types
+                    .get(other)
+                    .map_or(decl.name.span, |x| x.name.span);
+                (span, def_path(builder.type_to_module[other]))
+            });
+        found.push((name.clone(), existing));
+    }
+
+    for (name, id) in &target_symbols.constants {
+        let (def, def_module) = &builder.bodies.constants[id];
+        if *def_module != target_module
+            || (!def.decl.is_pub && !builder.is_visible(target_module, module_idx))
+        {
+            continue;
+        }
+
+        let existing = symbols
+            .constants
+            .get(name)
+            .filter(|other| *other != id)
+            .map(|other| {
+                let (other_def, other_module) = &builder.bodies.constants[other];
+                (other_def.decl.name.span, def_path(*other_module))
+            });
+        found.push((name.clone(), existing));
+    }
+
+    for ((trait_module, name), id) in &builder.trait_db.name_to_trait {
+        let decl = &builder.trait_db.traits[*id];
+        if *trait_module != target_module
+            || builder.trait_db.get_trait_module_idx(*id) != target_module
+            || (!decl.is_pub && !builder.is_visible(target_module, module_idx))
+        {
+            continue;
+        }
+
+        let existing = builder
+            .trait_db
+            .get_trait_by_name(name, module_idx)
+            .filter(|other| other != id)
+            .map(|other| {
+                let other_module = builder.trait_db.get_trait_module_idx(other);
+                (
+                    builder.trait_db.traits[other].name.span,
+                    def_path(other_module),
+                )
+            });
+        found.push((name.clone(), existing));
+    }
+
+    // The symbol tables are hash maps, sorted to report the same conflict first on every build.
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found
+}
+
 fn add_builtins(builder: &mut IRBuilder) {
     for kind in [
         (Type::Unit),
@@ -621,6 +1427,8 @@ fn add_builtins(builder: &mut IRBuilder) {
     }
 }
 
+/// Lowers the items of the module, the errors of each one being kept in the builder, see
+/// [`IRBuilder::recover`].
 pub fn lower_module(
     builder: &mut IRBuilder,
     module: &ast::modules::Module,
@@ -629,229 +1437,247 @@ pub fn lower_module(
     builder.enter_module_context(module_idx);
 
     for item in &module.contents {
-        match item {
-            ast::modules::ModuleDefItem::Trait(_) => {}
-            ast::modules::ModuleDefItem::ImplTrait(impl_trait) => {
-                let trait_id = builder
-                    .trait_db
-                    .get_trait_by_name(&impl_trait.target_trait.name.name, module_idx)
-                    .ok_or_else(|| LoweringError::TraitNotFound {
-                        span: impl_trait.target_trait.span,
-                        name: impl_trait.target_trait.name.name.clone(),
-                        path: builder.get_current_module().file_path.clone(),
-                    })?;
+        builder.recover(|builder| lower_module_item(builder, item, module_idx));
+    }
 
-                let mut trait_generics = Vec::new();
+    builder.leave_module_context();
 
-                for g in &impl_trait.generic_params {
-                    if let Some(ty) = builder.context.generics_mapping.get(&g.name.name) {
-                        trait_generics.push(TraitGeneric::Type(*ty));
-                    } else {
-                        trait_generics.push(TraitGeneric::Generic);
-                    }
-                }
+    Ok(())
+}
 
-                let target_ty = if !impl_trait.generic_params.is_empty() {
-                    let adt_symbol = Symbol {
-                        name: impl_trait.target.get_name().unwrap(),
-                        method_of: None,
-                        generics: Vec::new(),
-                    };
+fn lower_module_item(
+    builder: &mut IRBuilder,
+    item: &ast::modules::ModuleDefItem,
+    module_idx: ModuleIndex,
+) -> Result<(), LoweringError> {
+    match item {
+        ast::modules::ModuleDefItem::Trait(_) => {}
+        ast::modules::ModuleDefItem::ImplTrait(impl_trait) => {
+            let trait_id = builder
+                .trait_db
+                .get_trait_by_name(&impl_trait.target_trait.name.name, module_idx)
+                .ok_or_else(|| LoweringError::TraitNotFound {
+                    span: impl_trait.target_trait.span,
+                    name: impl_trait.target_trait.name.name.clone(),
+                    path: builder.get_current_module().file_path.clone(),
+                })?;
 
-                    let id = *builder
-                        .symbols
-                        .get(&builder.get_current_module_idx())
-                        .unwrap()
-                        .aggregates
-                        .get(&adt_symbol)
-                        .unwrap();
+            let mut trait_generics = Vec::new();
 
-                    let type_id = *builder.adt_to_type_idx.get(&id).unwrap();
-                    type_id
+            for g in &impl_trait.generic_params {
+                if let Some(ty) = builder.context.generics_mapping.get(&g.name.name) {
+                    trait_generics.push(TraitGeneric::Type(*ty));
                 } else {
-                    lower_type(builder, &impl_trait.target)?
+                    trait_generics.push(TraitGeneric::Generic);
+                }
+            }
+
+            let target_ty = if !impl_trait.generic_params.is_empty() {
+                let adt_symbol = Symbol {
+                    name: impl_trait.target.get_name().unwrap(),
+                    method_of: None,
+                    generics: Vec::new(),
                 };
 
-                {
-                    let tr = &builder.trait_db.traits[trait_id];
-
-                    let assoc_types: HashMap<_, _> = tr
-                        .associated_types
-                        .iter()
-                        .map(|x| (x.name.name.clone(), x.clone()))
-                        .collect();
-                    let assoc_types_found: HashMap<_, _> = impl_trait
-                        .associated_types
-                        .iter()
-                        .map(|x| (x.name.name.clone(), x.clone()))
-                        .collect();
-
-                    let trait_module_id = builder.trait_db.get_trait_module_idx(trait_id);
-                    for trait_assoc_ty in &tr.associated_types {
-                        if !assoc_types_found.contains_key(&trait_assoc_ty.name.name) {
-                            return Err(LoweringError::MissingTraitType(Box::new(
-                                MissingTraitType {
-                                    trait_name: impl_trait.target_trait.name.name.clone(),
-                                    trait_span: tr.span,
-                                    type_name: impl_trait.target.get_name().unwrap(),
-                                    type_name_span: impl_trait.target.get_span(),
-                                    assoc_type_name: trait_assoc_ty.name.name.clone(),
-                                    assoc_type_name_span_def: trait_assoc_ty.span,
-                                    impl_trait_span: impl_trait.span,
-                                    path: builder.get_current_module().file_path.clone(),
-                                    trait_path: builder.ir.modules[trait_module_id]
-                                        .file_path
-                                        .clone(),
-                                },
-                            )));
-                        }
-                    }
+                let id = *builder
+                    .symbols
+                    .get(&builder.get_current_module_idx())
+                    .unwrap()
+                    .aggregates
+                    .get(&adt_symbol)
+                    .unwrap();
 
-                    for assoc_ty in &impl_trait.associated_types {
-                        if !assoc_types.contains_key(&assoc_ty.name.name) {
-                            return Err(LoweringError::UnexpectedTraitType(Box::new(
-                                UnexpectedTraitType {
-                                    trait_name: impl_trait.target_trait.name.name.clone(),
-                                    trait_span: tr.span,
-                                    type_name: impl_trait.target.get_name().unwrap(),
-                                    type_name_span: impl_trait.target.get_span(),
-                                    assoc_type_name: assoc_ty.name.name.clone(),
-                                    assoc_type_name_span_def: assoc_ty.span,
-                                    impl_trait_span: impl_trait.span,
-                                    path: builder.get_current_module().file_path.clone(),
-                                    trait_path: builder.ir.modules[trait_module_id]
-                                        .file_path
-                                        .clone(),
-                                },
-                            )));
-                        }
+                let type_id = *builder.adt_to_type_idx.get(&id).unwrap();
+                type_id
+            } else {
+                lower_type(builder, &impl_trait.target)?
+            };
+
+            {
+                let tr = &builder.trait_db.traits[trait_id];
+
+                let assoc_types: HashMap<_, _> = tr
+                    .associated_types
+                    .iter()
+                    .map(|x| (x.name.name.clone(), x.clone()))
+                    .collect();
+                let assoc_types_found: HashMap<_, _> = impl_trait
+                    .associated_types
+                    .iter()
+                    .map(|x| (x.name.name.clone(), x.clone()))
+                    .collect();
+
+                let trait_module_id = builder.trait_db.get_trait_module_idx(trait_id);
+                for trait_assoc_ty in &tr.associated_types {
+                    if !assoc_types_found.contains_key(&trait_assoc_ty.name.name) {
+                        return Err(LoweringError::MissingTraitType(Box::new(
+                            MissingTraitType {
+                                trait_name: impl_trait.target_trait.name.name.clone(),
+                                trait_span: tr.span,
+                                type_name: impl_trait.target.get_name().unwrap(),
+                                type_name_span: impl_trait.target.get_span(),
+                                assoc_type_name: trait_assoc_ty.name.name.clone(),
+                                assoc_type_name_span_def: trait_assoc_ty.span,
+                                impl_trait_span: impl_trait.span,
+                                path: builder.get_current_module().file_path.clone(),
+                                trait_path: builder.ir.modules[trait_module_id].file_path.clone(),
+                            },
+                        )));
                     }
+                }
 
-                    let trait_functions: HashMap<_, _> = tr
-                        .methods
-                        .iter()
-                        .map(|x| (x.name.name.clone(), x.clone()))
-                        .collect();
-                    let functions_found: HashMap<_, _> = impl_trait
-                        .methods
-                        .iter()
-                        .map(|x| (x.decl.name.name.clone(), x.clone()))
-                        .collect();
-
-                    for trait_method in &tr.methods {
-                        if !functions_found.contains_key(&trait_method.name.name) {
-                            return Err(LoweringError::MissingTraitFunction(Box::new(
-                                MissingTraitFunction {
-                                    trait_name: impl_trait.target_trait.name.name.clone(),
-                                    trait_span: tr.span,
-                                    type_name: impl_trait.target.get_name().unwrap(),
-                                    type_name_span: impl_trait.target.get_span(),
-                                    func_name: trait_method.name.name.clone(),
-                                    func_name_span_def: trait_method.span,
-                                    impl_trait_span: impl_trait.span,
-                                    path: builder.get_current_module().file_path.clone(),
-                                    trait_path: builder.ir.modules[trait_module_id]
-                                        .file_path
-                                        .clone(),
-                                },
-                            )));
-                        }
+                for assoc_ty in &impl_trait.associated_types {
+                    if !assoc_types.contains_key(&assoc_ty.name.name) {
+                        return Err(LoweringError::UnexpectedTraitType(Box::new(
+                            UnexpectedTraitType {
+                                trait_name: impl_trait.target_trait.name.name.clone(),
+                                trait_span: tr.span,
+                                type_name: impl_trait.target.get_name().unwrap(),
+                                type_name_span: impl_trait.target.get_span(),
+                                assoc_type_name: assoc_ty.name.name.clone(),
+                                assoc_type_name_span_def: assoc_ty.span,
+                                impl_trait_span: impl_trait.span,
+                                path: builder.get_current_module().file_path.clone(),
+                                trait_path: builder.ir.modules[trait_module_id].file_path.clone(),
+                            },
+                        )));
                     }
+                }
 
-                    for method in &impl_trait.methods {
-                        if !trait_functions.contains_key(&method.decl.name.name) {
-                            return Err(LoweringError::UnexpectedTraitFunction(Box::new(
-                                UnexpectedTraitFunction {
-                                    trait_name: impl_trait.target_trait.name.name.clone(),
-                                    trait_span: tr.span,
-                                    type_name: impl_trait.target.get_name().unwrap(),
-                                    type_name_span: impl_trait.target.get_span(),
-                                    func_name: method.decl.name.name.clone(),
-                                    func_name_span_def: method.span,
-                                    impl_trait_span: impl_trait.span,
-                                    path: builder.get_current_module().file_path.clone(),
-                                    trait_path: builder.ir.modules[trait_module_id]
-                                        .file_path
-                                        .clone(),
-                                },
-                            )));
-                        }
+                let trait_functions: HashMap<_, _> = tr
+                    .methods
+                    .iter()
+                    .map(|x| (x.name.name.clone(), x.clone()))
+                    .collect();
+                let functions_found: HashMap<_, _> = impl_trait
+                    .methods
+                    .iter()
+                    .map(|x| (x.decl.name.name.clone(), x.clone()))
+                    .collect();
+
+                for trait_method in &tr.methods {
+                    if !functions_found.contains_key(&trait_method.name.name) {
+                        return Err(LoweringError::MissingTraitFunction(Box::new(
+                            MissingTraitFunction {
+                                trait_name: impl_trait.target_trait.name.name.clone(),
+                                trait_span: tr.span,
+                                type_name: impl_trait.target.get_name().unwrap(),
+                                type_name_span: impl_trait.target.get_span(),
+                                func_name: trait_method.name.name.clone(),
+                                func_name_span_def: trait_method.span,
+                                impl_trait_span: impl_trait.span,
+                                path: builder.get_current_module().file_path.clone(),
+                                trait_path: builder.ir.modules[trait_module_id].file_path.clone(),
+                            },
+                        )));
                     }
                 }
 
-                builder.trait_db.add_trait_impl(
-                    trait_id,
-                    TraitImpl {
-                        implementor: target_ty,
-                        generics: trait_generics,
-                    },
-                );
+                for method in &impl_trait.methods {
+                    if !trait_functions.contains_key(&method.decl.name.name) {
+                        return Err(LoweringError::UnexpectedTraitFunction(Box::new(
+                            UnexpectedTraitFunction {
+                                trait_name: impl_trait.target_trait.name.name.clone(),
+                                trait_span: tr.span,
+                                type_name: impl_trait.target.get_name().unwrap(),
+                                type_name_span: impl_trait.target.get_span(),
+                                func_name: method.decl.name.name.clone(),
+                                func_name_span_def: method.span,
+                                impl_trait_span: impl_trait.span,
+                                path: builder.get_current_module().file_path.clone(),
+                                trait_path: builder.ir.modules[trait_module_id].file_path.clone(),
+                            },
+                        )));
+                    }
+                }
+            }
 
-                if impl_trait.generic_params.is_empty() {
-                    builder.context.self_ty = Some(target_ty);
-                    for method in &impl_trait.methods {
-                        if method.decl.generic_params.is_empty() {
-                            lower_func(builder, method, Some(target_ty))?;
-                        }
+            builder.trait_db.add_trait_impl(
+                trait_id,
+                TraitImpl {
+                    implementor: target_ty,
+                    generics: trait_generics,
+                },
+            );
+
+            if impl_trait.generic_params.is_empty() {
+                builder.context.self_ty = Some(target_ty);
+                for method in &impl_trait.methods {
+                    if method.decl.generic_params.is_empty() {
+                        builder.recover(|builder| lower_func(builder, method, Some(target_ty)));
                     }
-                    builder.context.self_ty = None;
                 }
+                builder.context.self_ty = None;
             }
-            ast::modules::ModuleDefItem::Constant(constant_def) => {
-                lower_constant(builder, constant_def)?;
+        }
+        ast::modules::ModuleDefItem::Constant(constant_def) => {
+            lower_constant(builder, constant_def)?;
+        }
+        ast::modules::ModuleDefItem::Struct(info) => {
+            if info.generics.is_empty() {
+                lower_struct(builder, info)?;
             }
-            ast::modules::ModuleDefItem::Struct(info) => {
-                if info.generics.is_empty() {
-                    lower_struct(builder, info)?;
-                }
+        }
+        ast::modules::ModuleDefItem::Type(info) => {
+            lower_type_decl(builder, info)?;
+        }
+        ast::modules::ModuleDefItem::Function(function_def) => {
+            if function_def.decl.generic_params.is_empty() {
+                lower_func(builder, function_def, None)?;
             }
-            ast::modules::ModuleDefItem::Type(info) => {
-                lower_type_decl(builder, info)?;
+        }
+        ast::modules::ModuleDefItem::FunctionDecl(function_decl) => {
+            if function_decl.generic_params.is_empty() {
+                lower_func_decl(builder, function_decl)?;
             }
-            ast::modules::ModuleDefItem::Function(function_def) => {
-                if function_def.decl.generic_params.is_empty() {
-                    lower_func(builder, function_def, None)?;
-                }
+        }
+        ast::modules::ModuleDefItem::ExternBlock(block) => {
+            if !EXTERN_ABIS.contains(&block.abi.as_str()) {
+                return Err(LoweringError::UnknownAbi {
+                    span: block.abi_span,
+                    abi: block.abi.clone(),
+                    path: builder.get_current_module().file_path.clone(),
+                });
             }
-            ast::modules::ModuleDefItem::FunctionDecl(function_decl) => {
-                if function_decl.generic_params.is_empty() {
-                    lower_func_decl(builder, function_decl)?;
-                }
+
+            for function_decl in &block.functions {
+                lower_func_decl(builder, function_decl)?;
             }
-            ast::modules::ModuleDefItem::Impl(impl_block) => {
-                if impl_block.generic_params.is_empty() {
-                    let target_ty = lower_type(builder, &impl_block.target)?;
-                    builder.context.self_ty = Some(target_ty);
-                    for method in &impl_block.methods {
-                        if method.decl.generic_params.is_empty() {
-                            lower_func(builder, method, Some(target_ty))?;
-                        }
+        }
+        ast::modules::ModuleDefItem::Impl(impl_block) => {
+            if impl_block.generic_params.is_empty() {
+                let target_ty = lower_type(builder, &impl_block.target)?;
+                builder.context.self_ty = Some(target_ty);
+                for method in &impl_block.methods {
+                    if method.decl.generic_params.is_empty() {
+                        builder.recover(|builder| lower_func(builder, method, Some(target_ty)));
                     }
-                    builder.context.self_ty = None;
                 }
+                builder.context.self_ty = None;
             }
-            ast::modules::ModuleDefItem::Union(_union_decl) => todo!(),
-            ast::modules::ModuleDefItem::Enum(info) => {
-                if info.generics.is_empty() {
-                    lower_enum(builder, info)?;
-                }
-            }
-            ast::modules::ModuleDefItem::Module(module) => {
-                let new_module_idx = *builder
-                    .symbols
-                    .get(&module_idx)
-                    .unwrap()
-                    .modules
-                    .get(&module.name.name)
-                    .unwrap();
-                lower_module(builder, module, new_module_idx)?;
+        }
+        ast::modules::ModuleDefItem::Union(_union_decl) => todo!(),
+        ast::modules::ModuleDefItem::Enum(info) => {
+            if info.generics.is_empty() {
+                lower_enum(builder, info)?;
             }
-            ast::modules::ModuleDefItem::ExternalModule(_) => {}
-            ast::modules::ModuleDefItem::Import(_) => {}
+        }
+        ast::modules::ModuleDefItem::Module(module) => {
+            let new_module_idx = *builder
+                .symbols
+                .get(&module_idx)
+                .unwrap()
+                .modules
+                .get(&module.name.name)
+                .unwrap();
+            lower_module(builder, module, new_module_idx)?;
+        }
+        ast::modules::ModuleDefItem::ExternalModule(_) => {}
+        ast::modules::ModuleDefItem::Import(_) => {}
+        ast::modules::ModuleDefItem::Macro(def) => {
+            builder.warn_unknown_attributes(&def.attributes, MACRO_ATTRIBUTES);
         }
     }
 
-    builder.leave_module_context();
-
     Ok(())
 }
@@ -0,0 +1,1290 @@
+//! Declarative macros, `macro name { (pattern) => { template }, ... }`, expanded before lowering
+//! like `#[derive(...)]`. A call `name!(...)` is replaced by the template of the first rule whose
+//! pattern matches its arguments, parsed as statements or as an expression, as the call is one.
+//!
+//! The expansion is hygienic for the variables of the template: the names its `let` and `for`
+//! bind are renamed in each expansion, so they can't clash with the ones of the caller. The other
+//! names of the template are resolved where the macro is called.
+//!
+//! The tokens of the template get the span of the call, as the macro may be defined in another
+//! file, so the errors in the code it expands to point at the call. [`Expansions::wrap`] adds the
+//! rule of the macro the code comes from.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use lalrpop_util::ParseError;
+
+use crate::{
+    ast::{
+        CompilationUnit,
+        common::{Ident, Span},
+        expressions::{
+            Expression, FnCallOp, IfExpr, MatchExpr, PathOp, PathSegment, StructInitField,
+            ValueExpr,
+        },
+        imports::ImportStmt,
+        macros::{Delimiter, MacroCall, MacroDef, TokenTree},
+        modules::{Module, ModuleDefItem},
+        statements::{ForIterable, ReturnStmt, Statement},
+    },
+    check::suggestions::unknown_value_message,
+    parser::{error::SyntaxError, parse_token_statements, tokens::Token},
+};
+
+use super::{
+    errors::{LoweringError, MacroExpansion, PrivateItem},
+    fmt::BUILTIN_MACROS,
+};
+
+/// The attributes a macro can have.
+pub(crate) const MACRO_ATTRIBUTES: &[&str] = &["cfg"];
+
+/// How deep the expansion of a macro can call macros, to stop the ones calling themselves forever.
+const RECURSION_LIMIT: usize = 64;
+
+const FRAGMENT_KINDS: &[&str] = &["expr", "ident", "literal", "tt", "ty"];
+
+type Tokens = Vec<(usize, Token, usize)>;
+
+/// The macros a module can call, by name.
+type Scope = HashMap<String, Arc<Macro>>;
+
+/// What a `$name:kind` of a pattern matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fragment {
+    /// An expression, up to the token following it in the pattern.
+    Expr,
+    Ident,
+    /// A number, string, character or boolean.
+    Literal,
+    /// A token, or a group of them between delimiters.
+    Tt,
+    /// A type, up to the token following it in the pattern.
+    Ty,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Token(Token),
+    Group(Delimiter, Vec<Matcher>),
+    Fragment(String, Fragment, Span),
+}
+
+#[derive(Debug)]
+struct Rule {
+    matchers: Vec<Matcher>,
+    template: Vec<TokenTree>,
+    span: Span,
+}
+
+/// A macro with its rules checked, ready to expand.
+#[derive(Debug)]
+struct Macro {
+    /// The file it's defined in.
+    path: PathBuf,
+    rules: Vec<Rule>,
+}
+
+/// The tokens a fragment of a pattern matched.
+struct Binding {
+    fragment: Fragment,
+    trees: Vec<TokenTree>,
+}
+
+/// The calls of macros expanded, to report the errors found in the code they expanded to with the
+/// definition of the macro.
+#[derive(Debug, Default)]
+pub(crate) struct Expansions(Vec<Expansion>);
+
+#[derive(Debug, Clone)]
+struct Expansion {
+    name: String,
+    call_span: Span,
+    args_span: Span,
+    path: PathBuf,
+    /// The span of the rule used.
+    def_span: Span,
+    def_path: PathBuf,
+}
+
+impl Expansions {
+    /// Adds the macro to the error if it points at the code a macro expanded to, but not at its
+    /// arguments, which are code of the caller.
+    pub(crate) fn wrap(&self, error: LoweringError) -> LoweringError {
+        if let LoweringError::Multiple(errors) = error {
+            return LoweringError::Multiple(errors.into_iter().map(|x| self.wrap(x)).collect());
+        }
+
+        let (span, path) = error.primary_span();
+        let inside = |outer: Span| outer.from <= span.from && span.to <= outer.to;
+        let Some(expansion) = self
+            .0
+            .iter()
+            .rev()
+            .find(|x| x.path == *path && inside(x.call_span) && !inside(x.args_span))
+            .cloned()
+        else {
+            return error;
+        };
+
+        LoweringError::InMacroExpansion(Box::new(MacroExpansion {
+            error,
+            name: expansion.name,
+            call_span: expansion.call_span,
+            path: expansion.path,
+            def_span: expansion.def_span,
+            def_path: expansion.def_path,
+        }))
+    }
+}
+
+/// The compile units with the calls of macros expanded, and where they were.
+pub(crate) fn expand_units(
+    units: Vec<CompilationUnit>,
+) -> Result<(Vec<CompilationUnit>, Expansions), LoweringError> {
+    let mut expander = Expander {
+        modules: units
+            .iter()
+            .flat_map(|x| &x.modules)
+            .map(|x| (x.name.name.as_str(), x))
+            .collect(),
+        expansions: Expansions::default(),
+        count: 0,
+    };
+
+    let mut expanded = Vec::with_capacity(units.len());
+    for unit in &units {
+        // The units loaded from their metadata are expanded already, only their macros are used.
+        if unit.is_prebuilt {
+            expanded.push(unit.clone());
+            continue;
+        }
+
+        let modules = unit
+            .modules
+            .iter()
+            .map(|module| {
+                let module_path = [module.name.name.clone()];
+                expander.module(module, &module_path, &unit.module_aliases, &Scope::new())
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|error| expander.expansions.wrap(error))?;
+
+        expanded.push(CompilationUnit {
+            modules,
+            ..unit.clone()
+        });
+    }
+
+    Ok((expanded, expander.expansions))
+}
+
+struct Expander<'a> {
+    /// The top level modules of all the units, by name.
+    modules: HashMap<&'a str, &'a Module>,
+    expansions: Expansions,
+    /// How many macros were expanded, to name the variables of each expansion apart.
+    count: usize,
+}
+
+impl<'a> Expander<'a> {
+    fn module(
+        &mut self,
+        module: &Module,
+        module_path: &[String],
+        aliases: &HashMap<String, String>,
+        inherited: &Scope,
+    ) -> Result<Module, LoweringError> {
+        let path = module.file_path.as_path();
+
+        // The macros of the parent modules can be called in their submodules.
+        let mut scope = inherited.clone();
+        for item in &module.contents {
+            if let ModuleDefItem::Macro(def) = item {
+                scope.insert(def.name.name.clone(), Arc::new(compile(def, path)?));
+            }
+        }
+
+        let mut contents = Vec::with_capacity(module.contents.len());
+        for item in &module.contents {
+            match item {
+                ModuleDefItem::Import(import) => {
+                    if let Some(import) =
+                        self.import(import, module_path, aliases, path, &mut scope)?
+                    {
+                        contents.push(ModuleDefItem::Import(import));
+                    }
+                }
+                _ => contents.push(item.clone()),
+            }
+        }
+
+        for item in &mut contents {
+            match item {
+                ModuleDefItem::Module(submodule) => {
+                    let mut submodule_path = module_path.to_vec();
+                    submodule_path.push(submodule.name.name.clone());
+                    *submodule =
+                        Arc::new(self.module(submodule, &submodule_path, aliases, &scope)?);
+                }
+                // Without macros, the calls are left for lowering to report.
+                _ if scope.is_empty() => {}
+                ModuleDefItem::Function(function) => {
+                    let mut function = (**function).clone();
+                    self.block(&mut function.body, &scope, path, 0)?;
+                    *item = ModuleDefItem::Function(Arc::new(function));
+                }
+                ModuleDefItem::Impl(block) => {
+                    let mut block = (**block).clone();
+                    for method in &mut block.methods {
+                        let mut function = (**method).clone();
+                        self.block(&mut function.body, &scope, path, 0)?;
+                        *method = Arc::new(function);
+                    }
+                    *item = ModuleDefItem::Impl(Arc::new(block));
+                }
+                ModuleDefItem::ImplTrait(block) => {
+                    let mut block = (**block).clone();
+                    for method in &mut block.methods {
+                        let mut function = (**method).clone();
+                        self.block(&mut function.body, &scope, path, 0)?;
+                        *method = Arc::new(function);
+                    }
+                    *item = ModuleDefItem::ImplTrait(Arc::new(block));
+                }
+                ModuleDefItem::Constant(constant) => {
+                    let mut constant = (**constant).clone();
+                    self.expression(&mut constant.value, &scope, path, 0)?;
+                    *item = ModuleDefItem::Constant(Arc::new(constant));
+                }
+                ModuleDefItem::Enum(decl) => {
+                    let mut decl = (**decl).clone();
+                    for variant in &mut decl.variants {
+                        if let Some(discriminant) = &mut variant.discriminant {
+                            self.expression(discriminant, &scope, path, 0)?;
+                        }
+                    }
+                    *item = ModuleDefItem::Enum(Arc::new(decl));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Module {
+            contents,
+            ..module.clone()
+        })
+    }
+
+    /// Adds the macros the import names to the scope, returning the import without them, if it
+    /// still imports something.
+    fn import(
+        &self,
+        import: &Arc<ImportStmt>,
+        module_path: &[String],
+        aliases: &HashMap<String, String>,
+        path: &Path,
+        scope: &mut Scope,
+    ) -> Result<Option<Arc<ImportStmt>>, LoweringError> {
+        let Some((target, target_path)) = self.resolve(&import.module, module_path, aliases) else {
+            return Ok(Some(import.clone()));
+        };
+
+        let macros: HashMap<&str, &Arc<MacroDef>> = target
+            .contents
+            .iter()
+            .filter_map(|x| match x {
+                ModuleDefItem::Macro(def) => Some((def.name.name.as_str(), def)),
+                _ => None,
+            })
+            .collect();
+        if macros.is_empty() {
+            return Ok(Some(import.clone()));
+        }
+
+        // Like the other items, the private macros can be used in the submodules of their module.
+        let visible = |def: &MacroDef| def.is_pub || module_path.starts_with(&target_path);
+
+        if import.glob.is_some() {
+            for (name, def) in macros.iter().filter(|(_, def)| visible(def)) {
+                scope.insert(name.to_string(), Arc::new(compile(def, &target.file_path)?));
+            }
+            return Ok(Some(import.clone()));
+        }
+
+        let mut symbols = Vec::with_capacity(import.symbols.len());
+        for symbol in &import.symbols {
+            let Some(def) = macros.get(symbol.name.name.as_str()) else {
+                symbols.push(symbol.clone());
+                continue;
+            };
+
+            if !visible(def) {
+                return Err(LoweringError::PrivateItem(Box::new(PrivateItem {
+                    span: symbol.name.span,
+                    kind: "macro".to_string(),
+                    name: symbol.name.name.clone(),
+                    path: path.to_path_buf(),
+                    def_span: def.name.span,
+                    def_path: target.file_path.clone(),
+                    vis_span: def.vis_span,
+                })));
+            }
+
+            scope.insert(
+                symbol.local_name().name.clone(),
+                Arc::new(compile(def, &target.file_path)?),
+            );
+        }
+
+        if symbols.len() == import.symbols.len() {
+            Ok(Some(import.clone()))
+        } else if symbols.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Arc::new(ImportStmt {
+                symbols,
+                ..(**import).clone()
+            })))
+        }
+    }
+
+    /// The module an import refers to, found like lowering does, with its path.
+    fn resolve(
+        &self,
+        segments: &[Ident],
+        module_path: &[String],
+        aliases: &HashMap<String, String>,
+    ) -> Option<(&'a Module, Vec<String>)> {
+        let top_level = |name: &str| aliases.get(name).cloned().unwrap_or(name.to_string());
+        let mut segments = segments.iter().map(|x| x.name.as_str());
+        let mut target = module_path.to_vec();
+
+        match segments.next()? {
+            "super" => {
+                target.pop();
+            }
+            "root" => target = vec![top_level(segments.next()?)],
+            name => {
+                target.push(name.to_string());
+                if self.find(&target).is_none() {
+                    target = vec![top_level(name)];
+                }
+            }
+        }
+        target.extend(segments.map(String::from));
+
+        Some((self.find(&target)?, target))
+    }
+
+    fn find(&self, path: &[String]) -> Option<&'a Module> {
+        let (first, rest) = path.split_first()?;
+        let mut module = *self.modules.get(first.as_str())?;
+        for name in rest {
+            module = module.contents.iter().find_map(|x| match x {
+                ModuleDefItem::Module(submodule) if submodule.name.name == *name => {
+                    Some(&**submodule)
+                }
+                _ => None,
+            })?;
+        }
+
+        Some(module)
+    }
+
+    fn block(
+        &mut self,
+        block: &mut Vec<Statement>,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        let mut i = 0;
+        while i < block.len() {
+            let builtin =
+                matches!(&block[i], Statement::MacroCall(call) if is_builtin(call, scope));
+            if let (Statement::MacroCall(call), true) = (&mut block[i], builtin) {
+                self.builtin_arguments(&mut call.args, scope, path, depth)?;
+                i += 1;
+            } else if let Statement::MacroCall(call) = &block[i] {
+                let call = call.clone();
+                let statements = self.expand_statements(&call, scope, path, depth)?;
+                let len = statements.len();
+                block.splice(i..=i, statements);
+                i += len;
+            } else {
+                self.statement(&mut block[i], scope, path, depth)?;
+                i += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(
+        &mut self,
+        statement: &mut Statement,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        match statement {
+            Statement::Assign(info) => {
+                self.path_op(&mut info.lvalue, scope, path, depth)?;
+                self.expression(&mut info.rvalue, scope, path, depth)
+            }
+            Statement::Match(info) => self.match_expr(info, scope, path, depth),
+            Statement::For(info) => {
+                if let Some(init) = &mut info.init {
+                    self.expression(&mut init.value, scope, path, depth)?;
+                }
+                if let Some(condition) = &mut info.condition {
+                    self.expression(condition, scope, path, depth)?;
+                }
+                if let Some(post) = &mut info.post {
+                    self.path_op(&mut post.lvalue, scope, path, depth)?;
+                    self.expression(&mut post.rvalue, scope, path, depth)?;
+                }
+                self.block(&mut info.block_stmts, scope, path, depth)
+            }
+            Statement::ForIn(info) => {
+                match &mut info.iterable {
+                    ForIterable::Range { start, end } => {
+                        self.expression(start, scope, path, depth)?;
+                        self.expression(end, scope, path, depth)?;
+                    }
+                    ForIterable::Value(value) => self.expression(value, scope, path, depth)?,
+                }
+                self.block(&mut info.block_stmts, scope, path, depth)
+            }
+            Statement::If(info) => self.if_expr(info, scope, path, depth),
+            Statement::Let(info) => self.expression(&mut info.value, scope, path, depth),
+            Statement::Return(info) => match &mut info.value {
+                Some(value) => self.expression(value, scope, path, depth),
+                None => Ok(()),
+            },
+            Statement::Become(info) => self.expression(&mut info.value, scope, path, depth),
+            Statement::While(info) => {
+                self.expression(&mut info.condition, scope, path, depth)?;
+                self.block(&mut info.block_stmts, scope, path, depth)
+            }
+            Statement::Defer(info) => self.block(&mut info.block_stmts, scope, path, depth),
+            Statement::Unsafe(info) => self.block(&mut info.block_stmts, scope, path, depth),
+            Statement::FnCall(info) => self.fn_call(info, scope, path, depth),
+            Statement::PathOp(info) => self.path_op(info, scope, path, depth),
+            Statement::MacroCall(_) => {
+                unreachable!("the blocks expand the calls of their statements")
+            }
+        }
+    }
+
+    fn expression(
+        &mut self,
+        expression: &mut Expression,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        match expression {
+            Expression::Value(value, _) => self.value(value, scope, path, depth),
+            Expression::AssocMethodCall(info) => {
+                self.fn_call(&mut info.fn_call, scope, path, depth)
+            }
+            Expression::FnCall(info) => self.fn_call(info, scope, path, depth),
+            Expression::Match(info) => self.match_expr(info, scope, path, depth),
+            Expression::If(info) => self.if_expr(info, scope, path, depth),
+            Expression::UnaryOp(_, value)
+            | Expression::Deref(value, _)
+            | Expression::Await(value, _)
+            | Expression::AsRef(value, _, _)
+            | Expression::Cast(value, _, _) => self.expression(value, scope, path, depth),
+            Expression::BinaryOp(lhs, _, rhs) => {
+                self.expression(lhs, scope, path, depth)?;
+                self.expression(rhs, scope, path, depth)
+            }
+            Expression::StructInit(info) => {
+                if let Some(base) = &mut info.base {
+                    self.expression(&mut base.value, scope, path, depth)?;
+                }
+                self.fields(info.fields.values_mut(), scope, path, depth)
+            }
+            Expression::EnumInit(info) => self.fields(info.fields.values_mut(), scope, path, depth),
+            Expression::ArrayInit(info) => {
+                for value in &mut info.values {
+                    self.expression(value, scope, path, depth)?;
+                }
+                Ok(())
+            }
+            Expression::Tuple(values, _) => {
+                for value in values {
+                    self.expression(value, scope, path, depth)?;
+                }
+                Ok(())
+            }
+            Expression::Try(value, _) => self.expression(value, scope, path, depth),
+            Expression::MacroCall(call) if is_builtin(call, scope) => {
+                self.builtin_arguments(&mut call.args, scope, path, depth)
+            }
+            Expression::MacroCall(call) => {
+                let call = call.clone();
+                *expression = self.expand_expression(&call, scope, path, depth)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn fields<'b>(
+        &mut self,
+        fields: impl Iterator<Item = &'b mut StructInitField>,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        for field in fields {
+            self.expression(&mut field.value, scope, path, depth)?;
+        }
+        Ok(())
+    }
+
+    fn value(
+        &mut self,
+        value: &mut ValueExpr,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        match value {
+            ValueExpr::Path(info) => self.path_op(info, scope, path, depth),
+            _ => Ok(()),
+        }
+    }
+
+    fn path_op(
+        &mut self,
+        info: &mut PathOp,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        for segment in &mut info.extra {
+            if let PathSegment::MethodCall(call, _) = segment {
+                self.fn_call(call, scope, path, depth)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn fn_call(
+        &mut self,
+        info: &mut FnCallOp,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        for arg in &mut info.args {
+            self.expression(arg, scope, path, depth)?;
+        }
+        Ok(())
+    }
+
+    fn if_expr(
+        &mut self,
+        info: &mut IfExpr,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        self.expression(&mut info.cond, scope, path, depth)?;
+        self.block(&mut info.block_stmts, scope, path, depth)?;
+        if let Some(else_stmts) = &mut info.else_stmts {
+            self.block(else_stmts, scope, path, depth)?;
+        }
+        Ok(())
+    }
+
+    fn match_expr(
+        &mut self,
+        info: &mut MatchExpr,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        self.expression(&mut info.expr, scope, path, depth)?;
+        for variant in &mut info.variants {
+            self.block(&mut variant.block, scope, path, depth)?;
+        }
+        Ok(())
+    }
+
+    /// Expands the calls in the arguments of a macro lowered by the compiler, which are parsed
+    /// once the macros are gone. Each expansion is put between parentheses.
+    fn builtin_arguments(
+        &mut self,
+        trees: &mut Vec<TokenTree>,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        let mut i = 0;
+        while i < trees.len() {
+            match &trees[i..] {
+                [
+                    TokenTree::Token(Token::Identifier(name), name_span),
+                    TokenTree::Token(Token::OperatorNot, _),
+                    TokenTree::Group(Delimiter::Paren, args, args_group),
+                    ..,
+                ] if scope.contains_key(name) => {
+                    let span = Span::new(name_span.from, args_group.to);
+                    let call = MacroCall {
+                        name: Ident {
+                            name: name.clone(),
+                            span: *name_span,
+                        },
+                        args: args.clone(),
+                        args_span: Span::new(args_group.from + 1, args_group.to - 1),
+                        span,
+                    };
+
+                    let tokens = self.expand(&call, scope, path, depth)?;
+                    let mut expanded = token_trees(&tokens);
+                    self.builtin_arguments(&mut expanded, scope, path, depth + 1)?;
+                    trees.splice(
+                        i..i + 3,
+                        [TokenTree::Group(Delimiter::Paren, expanded, span)],
+                    );
+                }
+                [TokenTree::Group(_, inner, _), ..] => {
+                    let mut inner = inner.clone();
+                    self.builtin_arguments(&mut inner, scope, path, depth)?;
+                    if let TokenTree::Group(_, trees, _) = &mut trees[i] {
+                        *trees = inner;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        Ok(())
+    }
+
+    fn expand_statements(
+        &mut self,
+        call: &MacroCall,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<Vec<Statement>, LoweringError> {
+        let tokens = self.expand(call, scope, path, depth)?;
+        if tokens.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut statements = parse_token_statements(path, tokens)
+            .map_err(|error| expansion_error(call, &error, "statements", path))?;
+        self.block(&mut statements, scope, path, depth + 1)?;
+
+        Ok(statements)
+    }
+
+    fn expand_expression(
+        &mut self,
+        call: &MacroCall,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<Expression, LoweringError> {
+        let tokens = self.expand(call, scope, path, depth)?;
+        let mut expression = parse_expression(path, tokens, call.span)
+            .map_err(|error| expansion_error(call, &error, "an expression", path))?;
+        self.expression(&mut expression, scope, path, depth + 1)?;
+
+        Ok(expression)
+    }
+
+    /// The tokens the call expands to.
+    fn expand(
+        &mut self,
+        call: &MacroCall,
+        scope: &Scope,
+        path: &Path,
+        depth: usize,
+    ) -> Result<Tokens, LoweringError> {
+        let name = &call.name.name;
+        let invalid = |span: Span, reason: String| LoweringError::InvalidMacro {
+            span,
+            name: name.clone(),
+            reason,
+            path: path.to_path_buf(),
+        };
+
+        let Some(mac) = scope.get(name) else {
+            return Err(invalid(
+                call.name.span,
+                unknown_value_message("macro", name, scope.keys().map(String::as_str)),
+            ));
+        };
+
+        if depth >= RECURSION_LIMIT {
+            return Err(invalid(
+                call.span,
+                format!(
+                    "`{name}!` expands to calls of macros more than {RECURSION_LIMIT} levels deep"
+                ),
+            ));
+        }
+
+        let mut bindings = HashMap::new();
+        let Some(rule) = mac.rules.iter().find(|rule| {
+            bindings.clear();
+            match_trees(&rule.matchers, &call.args, &mut bindings, path)
+        }) else {
+            return Err(invalid(
+                call.args_span,
+                format!("no rule of `{name}!` matches these arguments"),
+            ));
+        };
+
+        self.count += 1;
+        let mut renames = HashMap::new();
+        declared_names(&rule.template, self.count, &mut renames);
+
+        let mut tokens = Vec::new();
+        substitute(&rule.template, &bindings, &renames, call.span, &mut tokens);
+
+        self.expansions.0.push(Expansion {
+            name: name.clone(),
+            call_span: call.span,
+            args_span: call.args_span,
+            path: path.to_path_buf(),
+            def_span: rule.span,
+            def_path: mac.path.clone(),
+        });
+
+        Ok(tokens)
+    }
+}
+
+/// Whether the call is of a macro the compiler lowers, as no macro with its name is in scope.
+fn is_builtin(call: &MacroCall, scope: &Scope) -> bool {
+    BUILTIN_MACROS.contains(&call.name.name.as_str()) && !scope.contains_key(&call.name.name)
+}
+
+/// The error of a call left for lowering, which only the calls of macros not in scope are.
+pub(crate) fn unexpanded(call: &MacroCall, path: &Path) -> LoweringError {
+    LoweringError::InvalidMacro {
+        span: call.name.span,
+        name: call.name.name.clone(),
+        reason: unknown_value_message("macro", &call.name.name, BUILTIN_MACROS.iter().copied()),
+        path: path.to_path_buf(),
+    }
+}
+
+/// Checks the rules of the macro, reading the fragments of their patterns.
+fn compile(def: &Arc<MacroDef>, path: &Path) -> Result<Macro, LoweringError> {
+    let invalid = |span: Span, reason: String| LoweringError::InvalidMacro {
+        span,
+        name: def.name.name.clone(),
+        reason,
+        path: path.to_path_buf(),
+    };
+
+    if def.rules.is_empty() {
+        return Err(invalid(
+            def.name.span,
+            "a macro needs a rule, like `($x:expr) => { $x }`".to_string(),
+        ));
+    }
+
+    let rules = def
+        .rules
+        .iter()
+        .map(|rule| {
+            let mut names = Vec::new();
+            let matchers = matchers(&rule.pattern, &mut names, &invalid)?;
+            check_template(&rule.template, &names, &invalid)?;
+            Ok(Rule {
+                matchers,
+                template: rule.template.clone(),
+                span: rule.span,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Macro {
+        path: path.to_path_buf(),
+        rules,
+    })
+}
+
+fn matchers(
+    pattern: &[TokenTree],
+    names: &mut Vec<String>,
+    invalid: &impl Fn(Span, String) -> LoweringError,
+) -> Result<Vec<Matcher>, LoweringError> {
+    let mut matchers = Vec::new();
+    let mut i = 0;
+
+    while i < pattern.len() {
+        match &pattern[i] {
+            TokenTree::Token(Token::Dollar, span) => {
+                let Some(
+                    [
+                        TokenTree::Token(Token::Identifier(name), _),
+                        TokenTree::Token(Token::Colon, _),
+                        TokenTree::Token(Token::Identifier(kind), kind_span),
+                    ],
+                ) = pattern.get(i + 1..i + 4)
+                else {
+                    return Err(invalid(
+                        *span,
+                        "expected a fragment like `$name:expr` after `$`".to_string(),
+                    ));
+                };
+
+                let fragment = match kind.as_str() {
+                    "expr" => Fragment::Expr,
+                    "ident" => Fragment::Ident,
+                    "literal" => Fragment::Literal,
+                    "tt" => Fragment::Tt,
+                    "ty" => Fragment::Ty,
+                    _ => {
+                        return Err(invalid(
+                            *kind_span,
+                            unknown_value_message(
+                                "fragment kind",
+                                kind,
+                                FRAGMENT_KINDS.iter().copied(),
+                            ),
+                        ));
+                    }
+                };
+                if names.contains(name) {
+                    return Err(invalid(
+                        *span,
+                        format!("`${name}` is bound twice in the pattern"),
+                    ));
+                }
+
+                names.push(name.clone());
+                let span = Span::new(span.from, kind_span.to);
+                matchers.push(Matcher::Fragment(name.clone(), fragment, span));
+                i += 4;
+            }
+            TokenTree::Token(token, _) => {
+                matchers.push(Matcher::Token(token.clone()));
+                i += 1;
+            }
+            TokenTree::Group(delimiter, trees, _) => {
+                matchers.push(Matcher::Group(
+                    *delimiter,
+                    self::matchers(trees, names, invalid)?,
+                ));
+                i += 1;
+            }
+        }
+    }
+
+    // An expression or a type ends where the token following it is found.
+    for (i, matcher) in matchers.iter().enumerate() {
+        if let Matcher::Fragment(name, Fragment::Expr | Fragment::Ty, span) = matcher {
+            if !matches!(matchers.get(i + 1), None | Some(Matcher::Token(_))) {
+                return Err(invalid(
+                    *span,
+                    format!("`${name}` must be followed by a token, like `,`, or end the pattern"),
+                ));
+            }
+        }
+    }
+
+    Ok(matchers)
+}
+
+/// Checks that the template only uses the fragments the pattern binds.
+fn check_template(
+    template: &[TokenTree],
+    names: &[String],
+    invalid: &impl Fn(Span, String) -> LoweringError,
+) -> Result<(), LoweringError> {
+    let mut trees = template.iter();
+    while let Some(tree) = trees.next() {
+        match tree {
+            TokenTree::Token(Token::Dollar, span) => match trees.next() {
+                Some(TokenTree::Token(Token::Identifier(name), _)) if names.contains(name) => {}
+                Some(TokenTree::Token(Token::Identifier(name), name_span)) => {
+                    return Err(invalid(
+                        Span::new(span.from, name_span.to),
+                        format!("`${name}` isn't bound by the pattern of the rule"),
+                    ));
+                }
+                _ => {
+                    return Err(invalid(
+                        *span,
+                        "expected the name of a fragment after `$`".to_string(),
+                    ));
+                }
+            },
+            TokenTree::Group(_, trees, _) => check_template(trees, names, invalid)?,
+            TokenTree::Token(..) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Matches the arguments of a call against a pattern, binding its fragments.
+fn match_trees(
+    matchers: &[Matcher],
+    trees: &[TokenTree],
+    bindings: &mut HashMap<String, Binding>,
+    path: &Path,
+) -> bool {
+    let mut pos = 0;
+
+    for (i, matcher) in matchers.iter().enumerate() {
+        pos = match matcher {
+            Matcher::Token(token) => match trees.get(pos) {
+                Some(TokenTree::Token(x, _)) if x == token => pos + 1,
+                _ => return false,
+            },
+            Matcher::Group(delimiter, inner) => match trees.get(pos) {
+                Some(TokenTree::Group(x, group, _))
+                    if x == delimiter && match_trees(inner, group, bindings, path) =>
+                {
+                    pos + 1
+                }
+                _ => return false,
+            },
+            Matcher::Fragment(name, fragment, _) => {
+                let end = match (fragment, matchers.get(i + 1)) {
+                    (Fragment::Expr | Fragment::Ty, Some(Matcher::Token(next))) => {
+                        let Some(len) = trees[pos..]
+                            .iter()
+                            .position(|x| matches!(x, TokenTree::Token(token, _) if token == next))
+                        else {
+                            return false;
+                        };
+                        pos + len
+                    }
+                    (Fragment::Expr | Fragment::Ty, _) => trees.len(),
+                    _ => pos + 1,
+                };
+
+                let Some(matched) = trees.get(pos..end).filter(|x| !x.is_empty()) else {
+                    return false;
+                };
+                if !fragment.matches(matched, path) {
+                    return false;
+                }
+
+                bindings.insert(
+                    name.clone(),
+                    Binding {
+                        fragment: *fragment,
+                        trees: matched.to_vec(),
+                    },
+                );
+                end
+            }
+        };
+    }
+
+    pos == trees.len()
+}
+
+impl Fragment {
+    fn matches(&self, trees: &[TokenTree], path: &Path) -> bool {
+        let mut tokens = Vec::new();
+        flatten(trees, &mut tokens);
+        let span = Span::new(
+            trees.first().map_or(0, |x| x.span().from),
+            trees.last().map_or(0, |x| x.span().to),
+        );
+
+        match (self, trees) {
+            (Fragment::Ident, [TokenTree::Token(Token::Identifier(_) | Token::KeywordSelf, _)]) => {
+                true
+            }
+            (
+                Fragment::Literal,
+                [
+                    TokenTree::Token(
+                        Token::Integer(_)
+                        | Token::Float(_)
+                        | Token::String(_)
+                        | Token::Char(_)
+                        | Token::Boolean(_),
+                        _,
+                    ),
+                ],
+            ) => true,
+            (Fragment::Tt, [_]) => true,
+            (Fragment::Expr, _) => parse_expression(path, tokens, span).is_ok(),
+            // Parsed as the type of a variable, `let x: <type> = 0;`.
+            (Fragment::Ty, _) => {
+                let mut declaration = vec![
+                    (span.from, Token::KeywordLet, span.from),
+                    (span.from, Token::Identifier("x".to_string()), span.from),
+                    (span.from, Token::Colon, span.from),
+                ];
+                declaration.extend(tokens);
+                declaration.extend([
+                    (span.to, Token::Assign, span.to),
+                    (span.to, Token::Integer(0), span.to),
+                    (span.to, Token::Semicolon, span.to),
+                ]);
+                parse_token_statements(path, declaration).is_ok_and(|x| x.len() == 1)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Binding {
+    /// Whether the expression has operators outside of its delimiters, so it's put between
+    /// parentheses to keep its precedence where it's used.
+    fn needs_parentheses(&self) -> bool {
+        self.fragment == Fragment::Expr
+            && self.trees.len() > 1
+            && self.trees.iter().any(|x| {
+                matches!(
+                    x,
+                    TokenTree::Token(token, _) if !matches!(
+                        token,
+                        Token::Identifier(_)
+                            | Token::KeywordSelf
+                            | Token::Integer(_)
+                            | Token::Float(_)
+                            | Token::String(_)
+                            | Token::Char(_)
+                            | Token::Boolean(_)
+                            | Token::Dot
+                            | Token::DoubleColon
+                            | Token::Hashtag
+                    )
+                )
+            })
+    }
+}
+
+/// Gives the variables the template declares with `let` or `for` the names they have in this
+/// expansion, which the code of the caller can't refer to.
+fn declared_names(template: &[TokenTree], expansion: usize, renames: &mut HashMap<String, String>) {
+    for (i, tree) in template.iter().enumerate() {
+        match tree {
+            TokenTree::Token(Token::KeywordLet | Token::KeywordFor, _) => {
+                let name = match template.get(i + 1..) {
+                    Some(
+                        [
+                            TokenTree::Token(Token::KeywordMut, _),
+                            TokenTree::Token(Token::Identifier(name), _),
+                            ..,
+                        ],
+                    )
+                    | Some([TokenTree::Token(Token::Identifier(name), _), ..]) => name,
+                    _ => continue,
+                };
+                renames.insert(name.clone(), format!("{name}${expansion}"));
+            }
+            TokenTree::Group(_, trees, _) => declared_names(trees, expansion, renames),
+            TokenTree::Token(..) => {}
+        }
+    }
+}
+
+/// Writes the tokens of the template, with the fragments in place of their `$name`, and the
+/// renamed variables.
+fn substitute(
+    template: &[TokenTree],
+    bindings: &HashMap<String, Binding>,
+    renames: &HashMap<String, String>,
+    span: Span,
+    out: &mut Tokens,
+) {
+    let mut trees = template.iter();
+    while let Some(tree) = trees.next() {
+        match tree {
+            TokenTree::Token(Token::Dollar, _) => {
+                let Some(TokenTree::Token(Token::Identifier(name), _)) = trees.next() else {
+                    unreachable!("the templates are checked with their macro");
+                };
+                let binding = &bindings[name];
+
+                if binding.needs_parentheses() {
+                    let from = binding.trees[0].span().from;
+                    let to = binding.trees[binding.trees.len() - 1].span().to;
+                    out.push((from, Token::LeftParen, from));
+                    flatten(&binding.trees, out);
+                    out.push((to, Token::RightParen, to));
+                } else {
+                    flatten(&binding.trees, out);
+                }
+            }
+            TokenTree::Token(Token::Identifier(name), _) if renames.contains_key(name) => {
+                out.push((span.from, Token::Identifier(renames[name].clone()), span.to));
+            }
+            TokenTree::Token(token, _) => out.push((span.from, token.clone(), span.to)),
+            TokenTree::Group(delimiter, trees, _) => {
+                let (open, close) = delimiter.tokens();
+                out.push((span.from, open, span.to));
+                substitute(trees, bindings, renames, span, out);
+                out.push((span.from, close, span.to));
+            }
+        }
+    }
+}
+
+/// The tokens of the trees, with the delimiters of their groups.
+fn flatten(trees: &[TokenTree], out: &mut Tokens) {
+    for tree in trees {
+        match tree {
+            TokenTree::Token(token, span) => out.push((span.from, token.clone(), span.to)),
+            TokenTree::Group(delimiter, trees, span) => {
+                let (open, close) = delimiter.tokens();
+                out.push((span.from, open, span.from + 1));
+                flatten(trees, out);
+                out.push((span.to.saturating_sub(1), close, span.to));
+            }
+        }
+    }
+}
+
+/// The arguments of a call, separated by commas, parsed as expressions with their spans. The
+/// error has the span and the reason.
+pub(crate) fn parse_arguments(
+    trees: &[TokenTree],
+    path: &Path,
+) -> Result<Vec<(Expression, Span)>, (Span, String)> {
+    let mut parts: Vec<_> = trees
+        .split(|x| matches!(x, TokenTree::Token(Token::Coma, _)))
+        .collect();
+    // No arguments, or a trailing comma.
+    if parts.last().is_some_and(|x| x.is_empty()) && (parts.len() > 1 || trees.is_empty()) {
+        parts.pop();
+    }
+
+    let mut args = Vec::with_capacity(parts.len());
+    for arg in parts {
+        let (Some(first), Some(last)) = (arg.first(), arg.last()) else {
+            let span = trees.first().map_or(Span::new(0, 0), TokenTree::span);
+            return Err((span, "expected an argument before the `,`".to_string()));
+        };
+
+        let span = Span::new(first.span().from, last.span().to);
+        let mut tokens = Vec::new();
+        flatten(arg, &mut tokens);
+        let expression = parse_expression(path, tokens, span).map_err(|error| {
+            let found = match &error.0 {
+                ParseError::UnrecognizedToken {
+                    token: (_, token, _),
+                    ..
+                }
+                | ParseError::ExtraToken {
+                    token: (_, token, _),
+                } => format!(", found `{}`", token_text(token)),
+                _ => String::new(),
+            };
+            (span, format!("expected an expression{found}"))
+        })?;
+        args.push((expression, span));
+    }
+
+    Ok(args)
+}
+
+/// The trees of the tokens, grouping the ones between delimiters.
+fn token_trees(tokens: &[(usize, Token, usize)]) -> Vec<TokenTree> {
+    let mut open: Vec<(Delimiter, usize, Vec<TokenTree>)> = Vec::new();
+    let mut trees = Vec::new();
+
+    for (from, token, to) in tokens {
+        let delimiter = match token {
+            Token::LeftParen => Some(Delimiter::Paren),
+            Token::LeftBracket => Some(Delimiter::Brace),
+            Token::LeftSquareBracket => Some(Delimiter::Bracket),
+            _ => None,
+        };
+        let closes = matches!(
+            token,
+            Token::RightParen | Token::RightBracket | Token::RightSquareBracket
+        );
+
+        if let Some(delimiter) = delimiter {
+            open.push((delimiter, *from, std::mem::take(&mut trees)));
+        } else if let Some((delimiter, from, outer)) = closes.then(|| open.pop()).flatten() {
+            let inner = std::mem::replace(&mut trees, outer);
+            trees.push(TokenTree::Group(delimiter, inner, Span::new(from, *to)));
+        } else {
+            trees.push(TokenTree::Token(token.clone(), Span::new(*from, *to)));
+        }
+    }
+
+    trees
+}
+
+/// Parses the tokens as the value of a `return`, as the parser only reads statements from tokens.
+fn parse_expression(path: &Path, tokens: Tokens, span: Span) -> Result<Expression, SyntaxError> {
+    let mut statement = vec![(span.from, Token::KeywordReturn, span.from)];
+    statement.extend(tokens);
+    statement.push((span.to, Token::Semicolon, span.to));
+
+    let mut statements = parse_token_statements(path, statement)?;
+    match statements.pop() {
+        Some(Statement::Return(ReturnStmt {
+            value: Some(value), ..
+        })) if statements.is_empty() => Ok(value),
+        // The statements before the last one end with a `;` of the tokens.
+        _ => Err(SyntaxError(ParseError::ExtraToken {
+            token: (span.from, Token::Semicolon, span.to),
+        })),
+    }
+}
+
+/// The error of an expansion that doesn't parse as what the call is.
+fn expansion_error(
+    call: &MacroCall,
+    error: &SyntaxError,
+    expected: &str,
+    path: &Path,
+) -> LoweringError {
+    let (span, found) = match &error.0 {
+        ParseError::UnrecognizedToken {
+            token: (from, token, to),
+            ..
+        }
+        | ParseError::ExtraToken {
+            token: (from, token, to),
+        } => (
+            Span::new(*from, *to),
+            format!("found `{}`", token_text(token)),
+        ),
+        _ => (call.span, "it ends too early".to_string()),
+    };
+
+    LoweringError::InvalidMacro {
+        span,
+        name: call.name.name.clone(),
+        reason: format!(
+            "`{}!` expands to code that isn't valid as {expected}, {found}",
+            call.name.name
+        ),
+        path: path.to_path_buf(),
+    }
+}
+
+/// How the token is written.
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::Identifier(x) | Token::Float(x) => x.clone(),
+        Token::Integer(x) => x.to_string(),
+        Token::String(x) => format!("{x:?}"),
+        Token::Char(x) => format!("{x:?}"),
+        Token::Boolean(x) => x.to_string(),
+        Token::DocString(x) => format!("///{x}"),
+        _ => token.to_string(),
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{Arc, mpsc::Sender},
 };
 
 use adts::{lower_enum, lower_struct};
@@ -13,10 +13,19 @@ use tracing::debug;
 use traits::TraitDatabase;
 
 use crate::{
-    ast::{common::Ident, expressions::EnumInitExpr},
+    ast::{
+        common::{Attribute, Ident, Span},
+        expressions::EnumInitExpr,
+    },
+    check::{
+        lints::{Lint, LintScope, Warning},
+        suggestions::{Applicability, Suggestion, find_similar_name},
+    },
     ir::{
-        AdtBody, AdtIndex, ConstBody, ConstIndex, FnIndex, Function, IR, Local, LocalIndex, Module,
-        ModuleIndex, Statement, Type, TypeIndex,
+        AdtBody, AdtIndex, BlockIndex, ConstBody, ConstIndex, FnIndex, Function, IR, LangItem,
+        LexicalScope, Local, LocalIndex, LocalKind, Module, ModuleIndex, Place, PlaceElem,
+        ScopeIndex, Statement, Type, TypeIndex,
+        mangling::{self, MangledName},
     },
 };
 use crate::{
@@ -33,22 +42,32 @@ use crate::ast::{
     constants::ConstantDef,
     expressions::{FnCallOp, StructInitExpr},
     functions::{FunctionDecl, FunctionDef},
+    statements::Statement as AstStatement,
     structs::StructDecl,
     types::{TypeDecl, TypeDescriptor},
 };
 
 mod adts;
+mod cfg;
 mod constants;
+mod coroutines;
+mod derive;
 mod errors;
 mod expressions;
+mod fmt;
 mod functions;
 mod lower;
+mod macros;
 mod statements;
 mod traits;
 mod types;
 
-pub use errors::LoweringError;
-pub use lower::lower_compile_units;
+pub use cfg::is_builtin_cfg;
+pub use errors::{
+    AmbiguousImport, LoweringError, MacroExpansion, PrivateItem, TypeDifference,
+    UnexpectedTypeError,
+};
+pub use lower::{expand_compile_units, lower_compile_units, lower_compile_units_with_sink};
 
 /// A symbol (currently either a struct/adt or function).
 ///
@@ -74,6 +93,9 @@ pub struct SymbolTable {
     pub constants: HashMap<String, ConstIndex>,
     pub aggregates: HashMap<Symbol, AdtIndex>,
     pub types: HashMap<String, TypeIndex>,
+    /// The symbols imported with another name, `import a.{b as c};`, with the module they're
+    /// imported from and their name there.
+    pub aliases: HashMap<String, (ModuleIndex, String)>,
 }
 
 /// A Struct holding the AST bodies of the given structures.
@@ -85,7 +107,8 @@ pub struct Bodies {
     pub functions: HashMap<FnIndex, Arc<FunctionDef>>,
     pub functions_decls: HashMap<FnIndex, Arc<FunctionDecl>>,
     pub types: HashMap<TypeIndex, Arc<TypeDecl>>,
-    pub constants: HashMap<ConstIndex, Arc<ConstantDef>>,
+    /// The constants with the modules they're defined in, their values are found in them.
+    pub constants: HashMap<ConstIndex, (Arc<ConstantDef>, ModuleIndex)>,
 }
 
 /// Context to help build the IR.
@@ -97,6 +120,8 @@ pub struct IRBuilder {
     pub symbols: HashMap<ModuleIndex, SymbolTable>,
     /// The top level module names, used in imports.
     pub top_level_modules_names: HashMap<String, ModuleIndex>,
+    /// The aliases of the top level module names in each top level module, from its compile unit.
+    pub module_aliases: HashMap<ModuleIndex, HashMap<String, String>>,
     pub bodies: Bodies,
     /// Needed to not duplicate TypeIndexes for structs.
     pub adt_to_type_idx: HashMap<AdtIndex, TypeIndex>,
@@ -108,6 +133,29 @@ pub struct IRBuilder {
     /// A helper context to resolve context dependent information.
     /// Like the current generic mappings or the current module id.
     pub context: IRBuilderContext,
+    /// Where the warnings are sent as soon as they are found, besides being saved in the IR.
+    pub warning_sink: Option<Sender<Warning>>,
+    /// The items with lint attributes, found before lowering.
+    pub lint_scopes: Vec<LintScope>,
+    /// The functions called while evaluating constants, which aren't called in the IR.
+    pub const_fn_calls: HashSet<FnIndex>,
+    /// The modules the polymorphic functions are defined in, the types of their signatures are
+    /// named as they're known there.
+    pub fn_to_module: HashMap<FnIndex, ModuleIndex>,
+    /// The modules the functions without `pub` are defined in, only them and their submodules can
+    /// use them. Trait methods are as visible as their trait.
+    pub private_functions: HashMap<FnIndex, ModuleIndex>,
+    /// The modules the structs and enums without `pub` are defined in.
+    pub private_adts: HashMap<AdtIndex, ModuleIndex>,
+    /// The enums marked as lang items, by their polymorphic index.
+    pub lang_items: HashMap<AdtIndex, LangItem>,
+    /// The struct marked as the `Future` lang item, by its polymorphic index.
+    pub future_adt: Option<AdtIndex>,
+    /// The async functions with the blocks where they suspend, lowered like the other functions
+    /// and turned into state machines once their borrows are checked.
+    pub async_fns: Vec<(FnIndex, Vec<BlockIndex>)>,
+    /// The errors of the items lowered so far, see [`IRBuilder::recover`].
+    pub errors: Vec<LoweringError>,
 }
 
 #[derive(Debug, Clone)]
@@ -124,6 +172,11 @@ pub struct IRBuilderContext {
     /// a generic function from a import that needs to be lowered at the time of a function call
     /// from another module.
     pub module_stack: Vec<ModuleIndex>,
+    /// The constants whose values are being evaluated, to find the ones that depend on themselves.
+    pub constants_stack: Vec<ConstIndex>,
+    /// The type aliases being lowered, by module and name, to find the ones that refer to
+    /// themselves.
+    pub type_aliases_stack: Vec<(ModuleIndex, String)>,
 }
 
 #[derive(Debug)]
@@ -132,10 +185,36 @@ pub struct FnIrBuilder<'b> {
     pub name_to_local: HashMap<String, LocalIndex>,
     pub statements: Vec<Statement>,
     pub ret_local: LocalIndex,
+    /// The span of the declared return type, if any.
+    pub ret_type_span: Option<Span>,
     pub builder: &'b mut IRBuilder,
     pub fn_id: FnIndex,
     // To check when a variable is used before its declared/init
     pub local_exists: HashSet<LocalIndex>,
+    /// The variables bound by patterns and loops, which can't be made mutable on their own.
+    pub pattern_locals: HashSet<LocalIndex>,
+    /// The lexical scope being lowered, `None` in the function body.
+    pub scope: Option<ScopeIndex>,
+    /// The defers of the blocks being lowered, innermost last.
+    pub defers: Vec<Defer>,
+    /// The span of the defer whose statements are being lowered, they can't return.
+    pub deferring: Option<Span>,
+    /// Whether raw pointers can be dereferenced and offset, in unsafe functions and blocks.
+    pub is_unsafe: bool,
+    /// The blocks where the async function being lowered suspends until it's polled again,
+    /// `None` if it isn't async.
+    pub suspends: Option<Vec<BlockIndex>>,
+}
+
+/// The statements of a `defer`, lowered again at each place its block is left.
+#[derive(Debug, Clone)]
+pub struct Defer {
+    pub stmts: Vec<AstStatement>,
+    /// The variables visible where it's declared, the ones its statements use.
+    pub name_to_local: HashMap<String, LocalIndex>,
+    /// Whether it's declared in unsafe code, its statements being unsafe too.
+    pub is_unsafe: bool,
+    pub span: Span,
 }
 
 impl IRBuilder {
@@ -169,10 +248,238 @@ impl IRBuilder {
         self.context.module_stack.push(module_id);
     }
 
+    /// Lowers an item, keeping its error to report it with the ones of the next items instead of
+    /// stopping. The context it was lowered in is restored, the error may have left it halfway.
+    pub fn recover<T>(
+        &mut self,
+        lower: impl FnOnce(&mut Self) -> Result<T, LoweringError>,
+    ) -> Option<T> {
+        let context = self.context.clone();
+        match lower(self) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                self.context = context;
+                self.errors.push(error);
+                None
+            }
+        }
+    }
+
+    /// Fails with the errors of the items lowered so far, if there are any, before the passes
+    /// that need all of them lowered.
+    pub fn check_errors(&mut self) -> Result<(), LoweringError> {
+        match LoweringError::from_errors(std::mem::take(&mut self.errors)) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
     pub fn leave_module_context(&mut self) {
         self.context.module_stack.pop();
     }
 
+    /// The module and name a symbol of the module imported with another name is imported from,
+    /// following the imports of that module if it's imported there with another name too.
+    pub fn resolve_alias(&self, module: ModuleIndex, name: &str) -> Option<(ModuleIndex, String)> {
+        let mut resolved = self.symbols[&module].aliases.get(name).cloned()?;
+        while let Some(next) = self.symbols[&resolved.0].aliases.get(&resolved.1) {
+            resolved = next.clone();
+        }
+        Some(resolved)
+    }
+
+    /// The module of the path and the symbol of the ADT there, the one it's imported from if it's
+    /// imported with another name.
+    fn resolve_path_symbol(
+        &self,
+        path: &[Ident],
+        sym: Symbol,
+    ) -> Result<(ModuleIndex, Symbol), LoweringError> {
+        let module_idx = self.get_path_module_idx(path)?;
+
+        Ok(match self.resolve_alias(module_idx, &sym.name) {
+            Some((module_idx, name)) => (module_idx, Symbol { name, ..sym }),
+            None => (module_idx, sym),
+        })
+    }
+
+    /// Whether the private items defined in `module` can be used from `use_module`: the module
+    /// itself or one of its submodules.
+    pub fn is_visible(&self, module: ModuleIndex, use_module: ModuleIndex) -> bool {
+        use_module == module || self.ir.modules[use_module].parents.contains(&module)
+    }
+
+    /// Fails if a private item, defined in `module`, is used from `use_module` but that's not the
+    /// module or one of its submodules. `vis_span` is where `pub` goes in its definition.
+    #[allow(clippy::too_many_arguments)]
+    pub fn check_visibility(
+        &self,
+        module: ModuleIndex,
+        use_module: ModuleIndex,
+        kind: &str,
+        name: &str,
+        span: Span,
+        def_span: Span,
+        vis_span: Span,
+    ) -> Result<(), LoweringError> {
+        if self.is_visible(module, use_module) {
+            return Ok(());
+        }
+
+        Err(LoweringError::PrivateItem(Box::new(PrivateItem {
+            span,
+            kind: kind.to_string(),
+            name: name.to_string(),
+            path: self.ir.modules[use_module].file_path.clone(),
+            def_span,
+            def_path: self.ir.modules[module].file_path.clone(),
+            vis_span,
+        })))
+    }
+
+    /// Emits a warning at the given span of the current module.
+    ///
+    /// The same item may be lowered more than once (e.g generics), so duplicates are ignored.
+    pub fn emit_warning(
+        &mut self,
+        lint: Lint,
+        span: Span,
+        message: String,
+        suggestion: Option<Suggestion>,
+    ) {
+        let path = self.get_current_module().file_path.clone();
+        self.emit_warning_in(&path, lint, span, message, suggestion);
+    }
+
+    /// Emits a warning at the given span of a file, at the level set by the lint attributes of
+    /// the items containing it.
+    pub fn emit_warning_in(
+        &mut self,
+        path: &Path,
+        lint: Lint,
+        span: Span,
+        message: String,
+        suggestion: Option<Suggestion>,
+    ) {
+        let warning = Warning {
+            lint,
+            span,
+            message,
+            suggestion,
+            path: path.to_path_buf(),
+            level: LintScope::level_at(&self.lint_scopes, lint, path, span),
+        };
+
+        if !self.ir.warnings.contains(&warning) {
+            if let Some(sink) = &self.warning_sink {
+                // The receiver may have stopped listening, the warning is still saved in the IR.
+                let _ = sink.send(warning.clone());
+            }
+
+            self.ir.warnings.push(warning);
+        }
+    }
+
+    /// Warns about the attributes that aren't one of the known ones.
+    pub fn warn_unknown_attributes(&mut self, attributes: &[Attribute], known: &[&str]) {
+        for attr in attributes {
+            if !known.contains(&attr.name.as_str()) {
+                self.warn_unknown_attribute(attr, known);
+            }
+        }
+    }
+
+    /// Warns about an attribute that isn't one of the known ones, suggesting the closest one.
+    pub fn warn_unknown_attribute(&mut self, attr: &Attribute, known: &[&str]) {
+        let suggestion =
+            find_similar_name(&attr.name, known.iter().copied()).map(|name| Suggestion {
+                message: "an attribute with a similar name exists".to_string(),
+                path: None,
+                span: attr.span,
+                replacement: match &attr.value {
+                    Some(value) => format!("#[{} = {:?}]", name, value),
+                    None => format!("#[{}]", name),
+                },
+                applicability: Applicability::MaybeIncorrect,
+            });
+
+        self.emit_warning(
+            Lint::UnknownAttributes,
+            attr.span,
+            format!("unknown attribute '{}'", attr.name),
+            suggestion,
+        );
+    }
+
+    /// Gets the index of the variant of the enum with the given name, suggesting the closest one if
+    /// there's none.
+    pub fn get_variant_idx(
+        &self,
+        adt_idx: AdtIndex,
+        variant: &Ident,
+    ) -> Result<usize, LoweringError> {
+        let adt_body = self.get_adt(adt_idx);
+
+        if let Some(idx) = adt_body.variant_names.get(&variant.name) {
+            return Ok(*idx);
+        }
+
+        let known: Vec<&str> = adt_body
+            .variant_names
+            .iter()
+            .sorted_by_key(|(_, idx)| **idx)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let suggestion = find_similar_name(&variant.name, known).map(|name| {
+            Box::new(Suggestion {
+                message: "a variant with a similar name exists".to_string(),
+                path: None,
+                span: variant.span,
+                replacement: name.to_string(),
+                applicability: Applicability::MaybeIncorrect,
+            })
+        });
+
+        Err(LoweringError::VariantNotFound {
+            span: variant.span,
+            name: variant.name.clone(),
+            type_name: adt_body.name.clone(),
+            suggestion,
+            path: self.get_current_module().file_path.clone(),
+        })
+    }
+
+    /// Gets the file the given function is declared in.
+    pub fn get_fn_file_path(&self, idx: FnIndex) -> Option<&PathBuf> {
+        self.ir
+            .modules
+            .iter()
+            .find(|(_, module)| module.functions.contains(&idx))
+            .map(|(_, module)| &module.file_path)
+    }
+
+    /// The declaration of the function, with a body or not.
+    pub fn get_fn_decl(&self, idx: FnIndex) -> &FunctionDecl {
+        self.bodies
+            .functions
+            .get(&idx)
+            .map(|x| &*x.decl)
+            .or_else(|| self.bodies.functions_decls.get(&idx).map(|x| &**x))
+            .expect("the function has a declaration")
+    }
+
+    /// Whether the polymorphic ADT is a struct or an enum, the span of its name and the one of
+    /// its visibility.
+    pub fn get_adt_decl_name(&self, idx: AdtIndex) -> (&'static str, Span, Span) {
+        match self.bodies.structs.get(&idx) {
+            Some(decl) => ("struct", decl.name.span, decl.vis_span),
+            None => {
+                let decl = &self.bodies.enums[&idx];
+                ("enum", decl.name.span, decl.vis_span)
+            }
+        }
+    }
+
     pub fn get_type(&self, idx: TypeIndex) -> &Type {
         self.ir.types[idx].as_ref().unwrap()
     }
@@ -189,45 +496,58 @@ impl IRBuilder {
         self.ir.constants[idx].as_ref().unwrap()
     }
 
-    /// Gets the mangled name for the given function name.
-    pub fn get_mangled_name(
-        &self,
-        module_idx: ModuleIndex,
-        fn_name: &str,
-        fn_idx: FnIndex,
-    ) -> Option<String> {
-        let mut name_path: Vec<&str> = Vec::new();
-
-        let cur_module = &self.ir.modules[module_idx];
-
-        for parent_id in &cur_module.parents {
-            let module = &self.ir.modules[*parent_id];
-            name_path.push(module.name.as_ref());
-        }
-        name_path.push(cur_module.name.as_ref());
-
-        name_path.push(fn_name);
+    /// Gets the mangled name for the function with the given symbol, see [`mangling`].
+    pub fn get_mangled_name(&self, module_idx: ModuleIndex, symbol: &Symbol) -> Option<String> {
+        let name = MangledName {
+            modules: self.module_path(module_idx),
+            self_ty: symbol.method_of.map(|x| self.display_typename(x)),
+            name: &symbol.name,
+            generics: symbol
+                .generics
+                .iter()
+                .map(|x| self.display_typename(*x))
+                .collect(),
+        };
 
-        Some(format!("{}_{}", name_path.join("_"), fn_idx.to_idx()))
+        // The types are hashed with the paths of their modules.
+        let self_ty = symbol.method_of.map(|x| self.qualified_typename(x));
+        let generics: Vec<String> = symbol
+            .generics
+            .iter()
+            .map(|x| self.qualified_typename(*x))
+            .collect();
+        let hash = mangling::stable_hash(
+            name.modules
+                .iter()
+                .copied()
+                .chain(self_ty.as_deref())
+                .chain([name.name])
+                .chain(generics.iter().map(String::as_str)),
+        );
+
+        Some(name.mangle(hash))
     }
 
     /// Gets the mangled name for the given function name.
     pub fn get_debug_name(&self, module_idx: ModuleIndex, fn_name: &str) -> Option<String> {
-        let mut name_path: Vec<&str> = Vec::new();
-
-        let cur_module = &self.ir.modules[module_idx];
-
-        for parent_id in &cur_module.parents {
-            let module = &self.ir.modules[*parent_id];
-            name_path.push(module.name.as_ref());
-        }
-        name_path.push(cur_module.name.as_ref());
-
+        let mut name_path = self.module_path(module_idx);
         name_path.push(fn_name);
 
         Some(name_path.join("::"))
     }
 
+    /// The names of the module and its parents, from the top level one.
+    fn module_path(&self, module_idx: ModuleIndex) -> Vec<&str> {
+        let cur_module = &self.ir.modules[module_idx];
+
+        cur_module
+            .parents
+            .iter()
+            .map(|x| self.ir.modules[*x].name.as_ref())
+            .chain([cur_module.name.as_ref()])
+            .collect()
+    }
+
     /// Lowers the given generics to their final type.
     pub fn lower_generic_params(
         &self,
@@ -323,6 +643,17 @@ impl IRBuilder {
         Ok(())
     }
 
+    /// Gets the top level module with the given name, as it's referred to from the given module.
+    pub fn get_top_level_module(&self, from: ModuleIndex, name: &str) -> Option<ModuleIndex> {
+        let name = self
+            .module_aliases
+            .get(&self.ir.top_level_module(from))
+            .and_then(|x| x.get(name))
+            .map_or(name, String::as_str);
+
+        self.top_level_modules_names.get(name).copied()
+    }
+
     /// Get the module idx of the given iden path, using the current module as reference.
     pub fn get_path_module_idx(&self, path: &[Ident]) -> Result<ModuleIndex, LoweringError> {
         let mut type_module_idx = self.get_current_module_idx();
@@ -334,15 +665,13 @@ impl IRBuilder {
             if let Some(first) = self.ir.modules[type_module_idx].modules.get(&first.name) {
                 type_module_idx = *first;
             } else {
-                type_module_idx =
-                    *self
-                        .top_level_modules_names
-                        .get(&first.name)
-                        .ok_or_else(|| LoweringError::ModuleNotFound {
-                            span: first.span,
-                            module: first.name.clone(),
-                            path: self.get_current_module().file_path.clone(),
-                        })?;
+                type_module_idx = self
+                    .get_top_level_module(type_module_idx, &first.name)
+                    .ok_or_else(|| LoweringError::ModuleNotFound {
+                        span: first.span,
+                        module: first.name.clone(),
+                        path: self.get_current_module().file_path.clone(),
+                    })?;
             }
 
             for next in it {
@@ -372,7 +701,8 @@ impl IRBuilder {
             generics: Vec::new(),
         };
 
-        let type_module_idx = self.get_path_module_idx(&info.name.path)?;
+        let use_module_idx = self.get_current_module_idx();
+        let (type_module_idx, sym) = self.resolve_path_symbol(&info.name.path, sym)?;
         self.enter_module_context(type_module_idx);
 
         let poly_idx = *self.symbols[&self.get_current_module_idx()]
@@ -381,6 +711,18 @@ impl IRBuilder {
             .unwrap();
         let struct_decl = self.bodies.structs.get(&poly_idx).unwrap().clone();
 
+        if let Some(def_module) = self.private_adts.get(&poly_idx) {
+            self.check_visibility(
+                *def_module,
+                use_module_idx,
+                "struct",
+                &sym.name,
+                info.name.name.span,
+                struct_decl.name.span,
+                struct_decl.vis_span,
+            )?;
+        }
+
         let old_generic_params = self.context.generics_mapping.clone();
 
         self.add_generic_params(&info.name.generics, &struct_decl.generics)?;
@@ -406,12 +748,25 @@ impl IRBuilder {
             generics: Vec::new(),
         };
 
-        let type_module_idx = self.get_path_module_idx(&info.name.path)?;
+        let use_module_idx = self.get_current_module_idx();
+        let (type_module_idx, sym) = self.resolve_path_symbol(&info.name.path, sym)?;
         self.enter_module_context(type_module_idx);
 
         let poly_idx = *self.get_current_symbols().aggregates.get(&sym).unwrap();
         let enum_decl = self.bodies.enums.get(&poly_idx).unwrap().clone();
 
+        if let Some(def_module) = self.private_adts.get(&poly_idx) {
+            self.check_visibility(
+                *def_module,
+                use_module_idx,
+                "enum",
+                &sym.name,
+                info.name.name.span,
+                enum_decl.name.span,
+                enum_decl.vis_span,
+            )?;
+        }
+
         let old_generic_params = self.context.generics_mapping.clone();
 
         self.add_generic_params(&info.name.generics, &enum_decl.generics)?;
@@ -425,88 +780,326 @@ impl IRBuilder {
         Ok(id)
     }
 
+    /// The type of the `Future` of a value of the given type, which calling an async function
+    /// returns.
+    pub fn get_future_ty(
+        &mut self,
+        output: TypeIndex,
+        span: Span,
+    ) -> Result<TypeIndex, LoweringError> {
+        let Some(adt_idx) = self.future_adt else {
+            return Err(LoweringError::MissingLangItem {
+                span,
+                item: "Future".to_string(),
+                path: self.get_current_module().file_path.clone(),
+            });
+        };
+        let module_idx = self.type_to_module[&self.adt_to_type_idx[&adt_idx]];
+
+        types::lower_adt_instance(self, module_idx, adt_idx, vec![output])
+    }
+
+    /// The type of the value of the `Future`, `None` if the type isn't one.
+    pub fn get_future_output_ty(&self, ty: TypeIndex) -> Option<TypeIndex> {
+        let Type::Adt(adt_idx) = self.get_type(ty) else {
+            return None;
+        };
+        let poly_ty = self
+            .mono_type_to_poly
+            .get(self.adt_to_type_idx.get(adt_idx)?)?;
+        if self.adt_to_type_idx.get(&self.future_adt?) != Some(poly_ty) {
+            return None;
+        }
+
+        let (_, generics) = self.adt_name_and_generics(*adt_idx)?;
+        generics.first().copied()
+    }
+
     /// Format a type for displaying as a type name.
     pub fn display_typename(&self, id: TypeIndex) -> String {
+        self.typename(id, false)
+    }
+
+    /// The name of the type with the paths of the modules of its ADTs, telling apart the types
+    /// named the same in different modules.
+    pub fn qualified_typename(&self, id: TypeIndex) -> String {
+        self.typename(id, true)
+    }
+
+    fn typename(&self, id: TypeIndex, qualified: bool) -> String {
         let ty = self.get_type(id);
 
         match ty {
             Type::Array(index, const_data) => {
-                let value = if let ConstKind::Value(ValueTree::Leaf(ConstValue::U64(x))) =
-                    &const_data.data
-                {
-                    *x
-                } else {
-                    unreachable!("const data for array sizes should always be u64")
-                };
-                format!("[{}; {}]", self.display_typename(*index), value)
+                format!(
+                    "[{}; {}]",
+                    self.typename(*index, qualified),
+                    array_len(const_data)
+                )
             }
+            Type::Slice(index) => format!("[{}]", self.typename(*index, qualified)),
             Type::Ref(index, mutability) => {
-                let word = if let Mutability::Mut = mutability {
-                    "mut"
-                } else {
-                    "const"
-                };
-
-                format!("&{word} {}", self.display_typename(*index))
+                format!(
+                    "&{} {}",
+                    mutability_word(*mutability),
+                    self.typename(*index, qualified)
+                )
             }
             Type::Ptr(index, mutability) => {
-                let word = if let Mutability::Mut = mutability {
-                    "mut"
-                } else {
-                    "const"
-                };
-
-                format!("*{word} {}", self.display_typename(*index))
+                format!(
+                    "*{} {}",
+                    mutability_word(*mutability),
+                    self.typename(*index, qualified)
+                )
             }
             Type::Adt(index) => {
-                if let Some(Some(adt_body)) = self.ir.aggregates.get(*index) {
-                    let generics = match adt_body.kind {
-                        ir::AdtKind::Struct => self
-                            .bodies
-                            .structs
-                            .get(index)
-                            .unwrap()
-                            .generics
-                            .iter()
-                            .map(|x| x.name.name.clone())
-                            .collect_vec(),
-                        ir::AdtKind::Enum => self
-                            .bodies
-                            .enums
-                            .get(index)
-                            .unwrap()
-                            .generics
-                            .iter()
-                            .map(|x| x.name.name.clone())
-                            .collect_vec(),
-                        ir::AdtKind::Union => todo!(),
+                if let Some((name, generics)) = self.adt_name_and_generics(*index) {
+                    let name = match self.adt_module(*index).filter(|_| qualified) {
+                        Some(module_idx) => {
+                            let mut path = self.module_path(module_idx);
+                            path.push(name);
+                            path.join("::")
+                        }
+                        None => name.to_string(),
                     };
+                    display_adt(
+                        &name,
+                        generics
+                            .iter()
+                            .map(|x| self.typename(*x, qualified))
+                            .collect(),
+                    )
+                } else {
+                    "Unknown yet".to_string()
+                }
+            }
+            Type::Tuple(fields) => display_tuple(
+                fields
+                    .iter()
+                    .map(|x| self.typename(*x, qualified))
+                    .collect(),
+            ),
+            _ => ty.display(&self.ir).unwrap(),
+        }
+    }
 
-                    let mut result = String::new();
-                    result.push_str(&adt_body.name);
+    /// The module the ADT is declared in.
+    fn adt_module(&self, index: AdtIndex) -> Option<ModuleIndex> {
+        let ty = self.adt_to_type_idx.get(&index)?;
+        let poly_ty = self.mono_type_to_poly.get(ty).unwrap_or(ty);
+        self.type_to_module.get(poly_ty).copied()
+    }
 
-                    if !generics.is_empty() {
-                        result.push('<');
+    /// The name of the ADT and the types of its generic parameters, if it's already lowered.
+    fn adt_name_and_generics(&self, index: AdtIndex) -> Option<(&str, Vec<TypeIndex>)> {
+        let adt_body = self.ir.aggregates.get(index)?.as_ref()?;
 
-                        for (i, g) in generics.iter().enumerate() {
-                            let ty = adt_body.generics_used.get(g).unwrap();
-                            result.push_str(&self.display_typename(*ty));
+        let generics = match adt_body.kind {
+            ir::AdtKind::Struct => &self.bodies.structs.get(&index).unwrap().generics,
+            ir::AdtKind::Enum => &self.bodies.enums.get(&index).unwrap().generics,
+            ir::AdtKind::Union => todo!(),
+        };
 
-                            if i != generics.len() - 1 {
-                                result.push_str(", ");
-                            }
-                        }
-                        result.push('>');
-                    }
+        let generics = generics
+            .iter()
+            .map(|x| *adt_body.generics_used.get(&x.name.name).unwrap())
+            .collect();
 
-                    result
-                } else {
-                    "Unknown yet".to_string()
-                }
+        Some((&adt_body.name, generics))
+    }
+
+    /// Maps the generics of the type to those of its instance, for its methods. These aren't
+    /// always those in scope, like for a `Vec<Future<T>>` field of an `Executor<T>`, nor found
+    /// from the fields, like the `T` of a `Future<T>`.
+    pub(crate) fn add_instance_generics(&mut self, ty: TypeIndex) {
+        if let Type::Adt(adt_idx) = *self.get_type(ty) {
+            if let Some(adt) = self.ir.aggregates[adt_idx].as_ref() {
+                let generics_used = adt.generics_used.clone();
+                self.context.generics_mapping.extend(generics_used);
             }
-            _ => ty.display(&self.ir).unwrap(),
         }
     }
+
+    /// The types the generics of a parameter's type are given by the type of its argument, like
+    /// `T` for a `Future<i32>` passed as a `Future<T>`.
+    pub(crate) fn infer_adt_generics(
+        &self,
+        param_generics: &[TypeName],
+        arg_ty: TypeIndex,
+    ) -> Vec<(String, TypeIndex)> {
+        let Type::Adt(adt_idx) = self.get_type(arg_ty) else {
+            return Vec::new();
+        };
+        let Some((_, arg_generics)) = self.adt_name_and_generics(*adt_idx) else {
+            return Vec::new();
+        };
+
+        param_generics
+            .iter()
+            .zip(arg_generics)
+            .filter(|(x, _)| x.generics.is_empty() && x.path.is_empty())
+            .map(|(x, ty)| (x.name.name.clone(), ty))
+            .collect()
+    }
+
+    /// Finds where the two types differ when they only differ inside a compound type, like `u8`
+    /// and `i8` in `&mut [u8; 4]` and `&mut [i8; 4]`.
+    pub fn type_difference(&self, expected: TypeIndex, found: TypeIndex) -> Option<TypeDifference> {
+        self.find_type_difference(expected, found)
+            .filter(|x| x.context != "_")
+    }
+
+    /// Finds the innermost part where the types differ, `None` if they are equal.
+    fn find_type_difference(
+        &self,
+        expected: TypeIndex,
+        found: TypeIndex,
+    ) -> Option<TypeDifference> {
+        let expected_ty = self.get_type(expected);
+        let found_ty = self.get_type(found);
+
+        if expected_ty.is_equal(found_ty, &self.ir) {
+            return None;
+        }
+
+        let inner = match (expected_ty, found_ty) {
+            (Type::Ref(expected, mutability), Type::Ref(found, found_mutability))
+                if mutability == found_mutability =>
+            {
+                self.find_type_difference(*expected, *found).map(|x| {
+                    x.within(|context| format!("&{} {context}", mutability_word(*mutability)))
+                })
+            }
+            (Type::Ptr(expected, mutability), Type::Ptr(found, found_mutability))
+                if mutability == found_mutability =>
+            {
+                self.find_type_difference(*expected, *found).map(|x| {
+                    x.within(|context| format!("*{} {context}", mutability_word(*mutability)))
+                })
+            }
+            (Type::Array(expected, len), Type::Array(found, found_len))
+                if len.data == found_len.data =>
+            {
+                self.find_type_difference(*expected, *found)
+                    .map(|x| x.within(|context| format!("[{context}; {}]", array_len(len))))
+            }
+            (Type::Slice(expected), Type::Slice(found)) => self
+                .find_type_difference(*expected, *found)
+                .map(|x| x.within(|context| format!("[{context}]"))),
+            (Type::Adt(expected), Type::Adt(found)) => {
+                self.find_generics_difference(*expected, *found)
+            }
+            (Type::Tuple(expected), Type::Tuple(found)) if expected.len() == found.len() => {
+                expected
+                    .iter()
+                    .zip(found)
+                    .enumerate()
+                    .filter_map(|(i, (expected, found))| {
+                        self.find_type_difference(*expected, *found).map(|x| (i, x))
+                    })
+                    .exactly_one()
+                    .ok()
+                    .map(|(i, difference)| {
+                        difference.within(|context| {
+                            display_tuple(
+                                expected
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(j, x)| {
+                                        if i == j {
+                                            context.to_string()
+                                        } else {
+                                            self.display_typename(*x)
+                                        }
+                                    })
+                                    .collect(),
+                            )
+                        })
+                    })
+            }
+            _ => None,
+        };
+
+        Some(inner.unwrap_or_else(|| TypeDifference {
+            context: "_".to_string(),
+            expected: self.display_typename(expected),
+            found: self.display_typename(found),
+        }))
+    }
+
+    /// Finds where two instances of the same generic ADT differ, if only one of their generic
+    /// parameters does.
+    fn find_generics_difference(
+        &self,
+        expected: AdtIndex,
+        found: AdtIndex,
+    ) -> Option<TypeDifference> {
+        let (name, expected_generics) = self.adt_name_and_generics(expected)?;
+        let (found_name, found_generics) = self.adt_name_and_generics(found)?;
+
+        if name != found_name || expected_generics.len() != found_generics.len() {
+            return None;
+        }
+
+        let (i, difference) = expected_generics
+            .iter()
+            .zip(&found_generics)
+            .enumerate()
+            .filter_map(|(i, (expected, found))| {
+                self.find_type_difference(*expected, *found).map(|x| (i, x))
+            })
+            .exactly_one()
+            .ok()?;
+
+        Some(difference.within(|context| {
+            display_adt(
+                name,
+                expected_generics
+                    .iter()
+                    .enumerate()
+                    .map(|(j, x)| {
+                        if i == j {
+                            context.to_string()
+                        } else {
+                            self.display_typename(*x)
+                        }
+                    })
+                    .collect(),
+            )
+        }))
+    }
+}
+
+fn mutability_word(mutability: Mutability) -> &'static str {
+    if let Mutability::Mut = mutability {
+        "mut"
+    } else {
+        "const"
+    }
+}
+
+fn array_len(const_data: &ir::ConstData) -> u64 {
+    if let ConstKind::Value(ValueTree::Leaf(ConstValue::U64(x))) = &const_data.data {
+        *x
+    } else {
+        unreachable!("const data for array sizes should always be u64")
+    }
+}
+
+fn display_adt(name: &str, generics: Vec<String>) -> String {
+    if generics.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}<{}>", name, generics.join(", "))
+    }
+}
+
+fn display_tuple(fields: Vec<String>) -> String {
+    if fields.len() == 1 {
+        format!("({},)", fields[0])
+    } else {
+        format!("({})", fields.join(", "))
+    }
 }
 
 impl FnIrBuilder<'_> {
@@ -534,10 +1127,32 @@ impl FnIrBuilder<'_> {
 
     pub fn add_local(&mut self, local: Local) -> LocalIndex {
         let id = self.body.locals.len();
-        self.body.locals.push(local);
+        self.body.locals.push(Local {
+            scope: self.scope,
+            ..local
+        });
         id
     }
 
+    /// Opens the lexical scope of a block with the given span, returning the enclosing scope to
+    /// restore once the block is lowered.
+    pub fn enter_scope(&mut self, span: Span) -> Option<ScopeIndex> {
+        let parent = self.scope;
+        self.scope = Some(self.body.scopes.len());
+        self.body.scopes.push(LexicalScope { span, parent });
+        parent
+    }
+
+    /// Opens the lexical scope of the given block statements, if there are any.
+    pub fn enter_block_scope(&mut self, stmts: &[AstStatement]) -> Option<ScopeIndex> {
+        match (stmts.first(), stmts.last()) {
+            (Some(first), Some(last)) => {
+                self.enter_scope(Span::new(first.span().from, last.span().to))
+            }
+            _ => self.scope,
+        }
+    }
+
     pub fn add_temp_local(&mut self, ty: TypeIndex) -> LocalIndex {
         let id = self.body.locals.len();
         self.body.locals.push(Local::temp(ty));
@@ -552,6 +1167,79 @@ impl FnIrBuilder<'_> {
         &self.get_current_module().file_path
     }
 
+    /// Suggests declaring the given local mutable, only possible for let bindings.
+    pub fn suggest_mutable_local(&self, local: LocalIndex) -> Option<Box<Suggestion>> {
+        if self.pattern_locals.contains(&local) {
+            return None;
+        }
+
+        let local = &self.body.locals[local];
+
+        if !matches!(local.kind, LocalKind::Temp) {
+            return None;
+        }
+
+        Some(Box::new(Suggestion {
+            message: "make the variable mutable".to_string(),
+            path: None,
+            span: local.span?,
+            replacement: format!("mut {}", local.debug_name.as_ref()?),
+            applicability: Applicability::MachineApplicable,
+        }))
+    }
+
+    /// Checks that the place can be borrowed mutably, like the receiver of a `&mut self` method:
+    /// a variable must be declared mutable, and what's behind a reference needs a `&mut` one.
+    pub fn check_mutable_borrow(&self, place: &Place, span: Span) -> Result<(), LoweringError> {
+        let local = &self.body.locals[place.local];
+        let mut ty = self.builder.get_type(local.ty);
+        let mut variant = 0;
+
+        for elem in &place.projection {
+            match (elem, ty) {
+                (PlaceElem::Deref, Type::Ref(inner, mutability) | Type::Ptr(inner, mutability)) => {
+                    if matches!(mutability, Mutability::Not) {
+                        return Err(LoweringError::BorrowNotMutable {
+                            span,
+                            type_span: None,
+                            name: local.debug_name.clone().unwrap_or_default(),
+                            path: self.get_file_path().clone(),
+                        });
+                    }
+                    ty = self.builder.get_type(*inner);
+                }
+                (PlaceElem::Variant(index), _) => variant = *index,
+                (PlaceElem::Field(index), Type::Adt(id)) => {
+                    ty = self
+                        .builder
+                        .get_type(self.builder.get_adt(*id).variants[variant].fields[*index].ty);
+                    variant = 0;
+                }
+                (PlaceElem::Field(index), Type::Tuple(fields)) => {
+                    ty = self.builder.get_type(fields[*index]);
+                }
+                (
+                    PlaceElem::Index(_) | PlaceElem::ConstantIndex(_),
+                    Type::Array(inner, _) | Type::Slice(inner),
+                ) => ty = self.builder.get_type(*inner),
+                _ => break,
+            }
+        }
+
+        // Temporaries, like the values returned by calls, are always mutable.
+        let declared = local.debug_name.is_some();
+        if declared && !local.mutable && !place.projection.contains(&PlaceElem::Deref) {
+            return Err(LoweringError::CantTakeMutableBorrow {
+                span,
+                declare_span: local.span,
+                suggestion: self.suggest_mutable_local(place.local),
+                path: self.get_file_path().clone(),
+            });
+        }
+
+        Ok(())
+    }
+
     /// Returns the polymorphic id and optionally the monomorphized id.
     ///
     /// If the function/method is generic and hasn't been lowered, it gets lowered.
@@ -573,14 +1261,33 @@ impl FnIrBuilder<'_> {
             }
         }
 
+        let mut name = info.target.name.clone();
+        let method_not_found = |this: &Self| LoweringError::MethodNotFound {
+            span: info.target.span,
+            name: info.target.name.clone(),
+            ty: this.builder.display_typename(method_of_type_idx.unwrap()),
+            path: this.get_file_path().clone(),
+        };
+
         let module_id = if let Some(id) = polymorphic_method_of_type_idx {
-            self.builder.type_to_module.get(&id).copied().unwrap()
+            // Only the types declared in a module have impl blocks.
+            match self.builder.type_to_module.get(&id) {
+                Some(module_id) => *module_id,
+                None => return Err(method_not_found(self)),
+            }
+        } else if let Some((module_id, alias_of)) = self
+            .builder
+            .resolve_alias(self.get_current_module_idx(), &name)
+        {
+            // Functions imported with another name are monomorphized by their own name.
+            name = alias_of;
+            module_id
         } else {
             self.builder.get_current_module_idx()
         };
 
         let poly_symbol = Symbol {
-            name: info.target.name.clone(),
+            name: name.clone(),
             method_of: polymorphic_method_of_type_idx,
             generics: Vec::new(),
         };
@@ -605,10 +1312,15 @@ impl FnIrBuilder<'_> {
                 {
                     let old_generics = self.builder.context.generics_mapping.clone();
 
+                    if let Some(method_ty_idx) = method_of_type_idx {
+                        self.builder.add_instance_generics(method_ty_idx);
+                    }
+
                     let mut generic_types = Vec::new();
 
                     if info.generics.is_empty() {
                         // Generic parameter type inference
+                        let mut inferred: HashMap<String, TypeIndex> = HashMap::new();
                         let generics: HashMap<String, GenericParam> = fn_decl
                             .generic_params
                             .iter()
@@ -634,15 +1346,14 @@ impl FnIrBuilder<'_> {
                                         .context
                                         .generics_mapping
                                         .insert(name.clone(), infer_ty);
-                                    generic_types.push(infer_ty);
+                                    inferred.insert(name.clone(), infer_ty);
 
-                                    // Check trait bounds
+                                    // Check trait bounds, named in the module of the function.
                                     for bound in &generic.bounds {
-                                        if let Some(check_trait) =
-                                            self.builder.trait_db.get_trait_by_name(
-                                                &bound.name.name,
-                                                self.get_current_module_idx(),
-                                            )
+                                        if let Some(check_trait) = self
+                                            .builder
+                                            .trait_db
+                                            .get_trait_by_name(&bound.name.name, fn_module_id)
                                         {
                                             let trait_generics = Vec::new(); // TODO: implement trait generics here
                                             if !self.builder.trait_db.type_implements_trait(
@@ -675,25 +1386,56 @@ impl FnIrBuilder<'_> {
                                     }
                                 }
                             }
-                        }
 
-                        if let Some(ret_type) = &fn_decl.ret_type {
-                            if let Some(name) = ret_type.get_name() {
-                                if generics.contains_key(&name) {
-                                    // we can't infer return types yet.
-                                    Err(LoweringError::CantInferType(
-                                        CantInferType {
-                                            message:
-                                                "Can't infer return type for this generic function call."
-                                                    .to_string(),
-                                            span: info.target.span,
-                                            path: self.get_file_path().clone(),
+                            // The generics within the parameter's type, like the `T` of `Future<T>`.
+                            if let Some(param_generics) = fn_decl.params[i]
+                                .r#type
+                                .get_generics()
+                                .filter(|x| !x.is_empty())
+                            {
+                                if let Some(arg_ty) = find_expression_type(self, param)? {
+                                    for (name, ty) in
+                                        self.builder.infer_adt_generics(&param_generics, arg_ty)
+                                    {
+                                        if generics.contains_key(&name)
+                                            && !inferred.contains_key(&name)
+                                        {
+                                            self.builder
+                                                .context
+                                                .generics_mapping
+                                                .insert(name.clone(), ty);
+                                            inferred.insert(name, ty);
                                         }
-                                        .into(),
-                                    ))?;
+                                    }
                                 }
                             }
                         }
+
+                        // The generic types in the order they're declared, a generic type used by
+                        // several parameters is only inferred once.
+                        for generic in &fn_decl.generic_params {
+                            let Some(ty) = inferred.get(&generic.name.name) else {
+                                let only_returned =
+                                    fn_decl.ret_type.as_ref().and_then(|x| x.get_name())
+                                        == Some(generic.name.name.clone());
+
+                                Err(LoweringError::CantInferType(
+                                    CantInferType {
+                                        message: if only_returned {
+                                            "Can't infer return type for this generic function call."
+                                        } else {
+                                            "Can't infer generic argument type for this function call."
+                                        }
+                                        .to_string(),
+                                        span: info.target.span,
+                                        path: self.get_file_path().clone(),
+                                    }
+                                    .into(),
+                                ))?
+                            };
+
+                            generic_types.push(*ty);
+                        }
                     } else {
                         // Generic parameter info was given explicitly.
                         generic_types = self
@@ -707,11 +1449,13 @@ impl FnIrBuilder<'_> {
                             found: info.generics.len(),
                             needs: fn_decl.generic_params.len(),
                             path: self.get_file_path().clone(),
+                            fn_span: fn_decl.name.span,
+                            fn_path: self.builder.ir.modules[fn_module_id].file_path.clone(),
                         });
                     }
 
                     let mono_symbol = Symbol {
-                        name: info.target.name.clone(),
+                        name: name.clone(),
                         method_of: method_of_type_idx,
                         generics: generic_types,
                     };
@@ -770,6 +1514,8 @@ impl FnIrBuilder<'_> {
                 } else {
                     (poly_id, None)
                 }
+            } else if method_of_type_idx.is_some() {
+                return Err(method_not_found(self));
             } else {
                 return Err(LoweringError::FunctionNotFound {
                     span: info.span,
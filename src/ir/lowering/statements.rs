@@ -4,24 +4,38 @@ use tracing::{debug, instrument};
 
 use crate::{
     ast::{
-        common::TypeName,
-        expressions::{IfExpr, MatchCaseExpr, MatchExpr, ValueExpr},
-        statements::{self, AssignStmt, ForStmt, LetStmt, LetStmtTarget, ReturnStmt, WhileStmt},
+        common::{Ident, Span, TypeName},
+        expressions::{FnCallOp, IfExpr, MatchCaseExpr, MatchExpr, ValueExpr},
+        patterns::Pattern,
+        statements::{
+            self, AssignStmt, BecomeStmt, ForInStmt, ForIterable, ForStmt, LetStmt, LetStmtTarget,
+            ReturnStmt, WhileStmt,
+        },
         types::TypeDescriptor,
     },
     ir::{
-        AdtKind, BasicBlock, ConstData, ConstKind, ConstValue, Local, LocalKind, Mutability,
+        AdtKind, BasicBlock, BinOp, ConstData, ConstKind, ConstValue, Local, LocalKind, Mutability,
         Operand, Place, PlaceElem, Rvalue, Statement, StatementKind, SwitchTargets, Terminator,
-        TerminatorKind, Type, ValueTree,
+        TerminatorKind, Type, UndefinedCheck, ValueTree,
         lowering::{
-            Symbol, errors::MissingVariantError, expressions::lower_expression,
-            functions::get_locals, types::lower_type,
+            Symbol,
+            errors::MissingVariantError,
+            expressions::{
+                find_expression_type, lower_expression, lower_len, lower_undefined_check,
+                lower_value_expr,
+            },
+            functions::get_locals,
+            types::lower_type,
         },
     },
 };
 
 use super::{
-    FnIrBuilder, errors::LoweringError, expressions::lower_path, functions::lower_fn_call,
+    Defer, FnIrBuilder,
+    errors::{LoweringError, UnexpectedTypeError},
+    expressions::lower_path,
+    fmt::lower_print,
+    functions::lower_fn_call,
     ir::TypeIndex,
 };
 
@@ -37,6 +51,10 @@ pub(crate) fn lower_statement(
             lower_for(builder, info)?;
             assert!(builder.statements.is_empty());
         }
+        statements::Statement::ForIn(info) => {
+            lower_for_in(builder, info)?;
+            assert!(builder.statements.is_empty());
+        }
         statements::Statement::If(info) => {
             lower_if_statement(builder, info)?;
             assert!(builder.statements.is_empty());
@@ -45,6 +63,7 @@ pub(crate) fn lower_statement(
         statements::Statement::Return(info) => {
             lower_return(builder, info, ret_type)?;
         }
+        statements::Statement::Become(info) => lower_become(builder, info, ret_type)?,
         statements::Statement::While(info) => {
             lower_while(builder, info)?;
             assert!(builder.statements.is_empty());
@@ -55,7 +74,88 @@ pub(crate) fn lower_statement(
         statements::Statement::PathOp(info) => {
             lower_path(builder, info)?;
         }
+        statements::Statement::MacroCall(info) => lower_print(builder, info)?,
+        statements::Statement::Defer(info) => builder.defers.push(Defer {
+            stmts: info.block_stmts.clone(),
+            name_to_local: builder.name_to_local.clone(),
+            is_unsafe: builder.is_unsafe,
+            span: info.span,
+        }),
+        statements::Statement::Unsafe(info) => {
+            let outer_scope_locals = builder.name_to_local.clone();
+            let outer_scope = builder.enter_block_scope(&info.block_stmts);
+            let is_unsafe = std::mem::replace(&mut builder.is_unsafe, true);
+            lower_block(builder, &info.block_stmts)?;
+            builder.is_unsafe = is_unsafe;
+            builder.scope = outer_scope;
+            builder.name_to_local = outer_scope_locals;
+        }
+    }
+    Ok(())
+}
+
+/// Lowers the statements of a block, then the ones deferred in it.
+fn lower_block(
+    builder: &mut FnIrBuilder,
+    stmts: &[crate::ast::statements::Statement],
+) -> Result<(), LoweringError> {
+    let defers = builder.defers.len();
+    for stmt in stmts {
+        get_locals(builder, stmt)?;
+        lower_statement(builder, stmt, builder.body.locals[builder.ret_local].ty)?;
+    }
+    lower_defers(builder, defers)
+}
+
+/// Lowers the defers after the given number of them in reverse order, and forgets them, as their
+/// block is left.
+fn lower_defers(builder: &mut FnIrBuilder, len: usize) -> Result<(), LoweringError> {
+    for defer in builder.defers.split_off(len).iter().rev() {
+        lower_defer(builder, defer)?;
+    }
+    Ok(())
+}
+
+fn lower_defer(builder: &mut FnIrBuilder, defer: &Defer) -> Result<(), LoweringError> {
+    let name_to_local = std::mem::replace(&mut builder.name_to_local, defer.name_to_local.clone());
+    let deferring = builder.deferring.replace(defer.span);
+    let is_unsafe = std::mem::replace(&mut builder.is_unsafe, defer.is_unsafe);
+    let outer_scope = builder.enter_block_scope(&defer.stmts);
+    lower_block(builder, &defer.stmts)?;
+    builder.scope = outer_scope;
+    builder.is_unsafe = is_unsafe;
+    builder.deferring = deferring;
+    builder.name_to_local = name_to_local;
+    Ok(())
+}
+
+/// Ends the current block returning from the function, once the defers of all the blocks being
+/// left ran, innermost first.
+pub(crate) fn lower_return_block(
+    builder: &mut FnIrBuilder,
+    span: Span,
+) -> Result<(), LoweringError> {
+    if let Some(defer_span) = builder.deferring {
+        return Err(LoweringError::ReturnInDefer {
+            span,
+            defer_span,
+            path: builder.get_file_path().clone(),
+        });
+    }
+
+    for defer in builder.defers.clone().iter().rev() {
+        lower_defer(builder, defer)?;
     }
+
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Return,
+        }),
+    });
+
     Ok(())
 }
 
@@ -77,13 +177,17 @@ fn lower_let(builder: &mut FnIrBuilder, info: &LetStmt) -> Result<(), LoweringEr
             let rvalue_ty = builder.builder.ir.types[rvalue_type_idx].clone().unwrap();
 
             if !ty.is_equal(&rvalue_ty, &builder.builder.ir) {
-                return Err(LoweringError::UnexpectedType {
-                    found_span: rvalue_span,
-                    found: builder.builder.display_typename(rvalue_type_idx),
-                    expected: builder.builder.display_typename(type_idx),
-                    expected_span: Some(r#type.get_span()),
-                    path: builder.get_file_path().clone(),
-                });
+                return Err(LoweringError::UnexpectedType(Box::new(
+                    UnexpectedTypeError {
+                        found_span: rvalue_span,
+                        found: builder.builder.display_typename(rvalue_type_idx),
+                        expected: builder.builder.display_typename(type_idx),
+                        expected_span: Some(r#type.get_span()),
+                        expected_path: None,
+                        difference: builder.builder.type_difference(type_idx, rvalue_type_idx),
+                        path: builder.get_file_path().clone(),
+                    },
+                )));
             }
 
             let local_idx = builder.name_to_local.get(&name.name).copied().unwrap();
@@ -104,9 +208,39 @@ fn lower_let(builder: &mut FnIrBuilder, info: &LetStmt) -> Result<(), LoweringEr
                 ),
             });
         }
-        LetStmtTarget::Destructure(_) => {
-            debug!("let target is a destructure");
-            todo!()
+        LetStmtTarget::Destructure(pattern) => {
+            tracing::Span::current().record("variant", "destructure");
+            debug!("lowering");
+
+            // The type comes from the value, the pattern is checked against it.
+            let (rvalue, type_idx, rvalue_span) = lower_expression(builder, &info.value, None)?;
+
+            let local = builder.add_temp_local(type_idx);
+            builder.statements.push(Statement {
+                span: Some(rvalue_span),
+                kind: StatementKind::StorageLive(local),
+            });
+            builder.statements.push(Statement {
+                span: Some(rvalue_span),
+                kind: StatementKind::Assign(
+                    Place {
+                        local,
+                        projection: vec![],
+                    },
+                    rvalue,
+                ),
+            });
+
+            lower_pattern(
+                builder,
+                pattern,
+                Place {
+                    local,
+                    projection: vec![],
+                },
+                type_idx,
+                info.is_mutable,
+            )?;
         }
     };
     Ok(())
@@ -121,13 +255,21 @@ fn lower_assign(builder: &mut FnIrBuilder, info: &AssignStmt) -> Result<(), Lowe
         return Err(LoweringError::NotMutable {
             span: info.span,
             declare_span: builder.body.locals[place.local].span,
+            suggestion: builder.suggest_mutable_local(place.local),
             path: builder.get_file_path().clone(),
         });
     }
 
     let mut ty = builder.builder.get_type(type_idx).clone();
 
-    for _ in 0..info.derefs {
+    for deref in 0..info.derefs {
+        if matches!(ty, Type::Ptr(..)) && !builder.is_unsafe {
+            return Err(LoweringError::UnsafeOperation {
+                span: Span::new(info.span.from, path_span.to),
+                operation: "dereferencing a raw pointer".to_string(),
+                path: builder.get_file_path().clone(),
+            });
+        }
         match &ty {
             Type::Ref(inner, is_mut) | Type::Ptr(inner, is_mut) => {
                 if matches!(is_mut, Mutability::Not) {
@@ -139,10 +281,21 @@ fn lower_assign(builder: &mut FnIrBuilder, info: &AssignStmt) -> Result<(), Lowe
                     })?;
                 }
                 type_idx = *inner;
-                ty = builder.builder.get_type(type_idx).clone();
             }
             _ => unreachable!(),
         }
+        // Raw pointers are checked for null and misaligned addresses with `--sanitize undefined`,
+        // the last one is written through.
+        if matches!(ty, Type::Ptr(..)) {
+            let ptr = Operand::Place(place.clone());
+            let check = if deref + 1 == info.derefs {
+                UndefinedCheck::Store(ptr)
+            } else {
+                UndefinedCheck::Load(ptr)
+            };
+            lower_undefined_check(builder, check, Span::new(info.span.from, path_span.to));
+        }
+        ty = builder.builder.get_type(type_idx).clone();
         place.projection.push(PlaceElem::Deref);
     }
 
@@ -152,13 +305,17 @@ fn lower_assign(builder: &mut FnIrBuilder, info: &AssignStmt) -> Result<(), Lowe
     let rvalue_ty = builder.builder.get_type(rvalue_type_idx).clone();
 
     if !ty.is_equal(&rvalue_ty, &builder.builder.ir) {
-        return Err(LoweringError::UnexpectedType {
-            found_span: rvalue_span,
-            found: builder.builder.display_typename(rvalue_type_idx),
-            expected: builder.builder.display_typename(type_idx),
-            expected_span: Some(path_span),
-            path: builder.get_file_path().clone(),
-        });
+        return Err(LoweringError::UnexpectedType(Box::new(
+            UnexpectedTypeError {
+                found_span: rvalue_span,
+                found: builder.builder.display_typename(rvalue_type_idx),
+                expected: builder.builder.display_typename(type_idx),
+                expected_span: Some(path_span),
+                expected_path: None,
+                difference: builder.builder.type_difference(type_idx, rvalue_type_idx),
+                path: builder.get_file_path().clone(),
+            },
+        )));
     }
 
     builder.statements.push(Statement {
@@ -185,17 +342,21 @@ fn lower_return(
         let value_ty = builder.builder.get_type(value_type_idx);
 
         if !ret_ty.is_equal(value_ty, &builder.builder.ir) {
-            return Err(LoweringError::UnexpectedType {
-                found_span: exp_span,
-                found: builder.builder.display_typename(value_type_idx),
-                expected: builder.builder.display_typename(ret_type),
-                expected_span: Some(info.span), // todo: change this span to point to the "-> x" return type in fn
-                path: builder.get_file_path().clone(),
-            });
+            return Err(LoweringError::UnexpectedType(Box::new(
+                UnexpectedTypeError {
+                    found_span: exp_span,
+                    found: builder.builder.display_typename(value_type_idx),
+                    expected: builder.builder.display_typename(ret_type),
+                    expected_span: Some(builder.ret_type_span.unwrap_or(info.span)),
+                    expected_path: None,
+                    difference: builder.builder.type_difference(ret_type, value_type_idx),
+                    path: builder.get_file_path().clone(),
+                },
+            )));
         }
 
         builder.statements.push(Statement {
-            span: None,
+            span: Some(exp_span),
             kind: StatementKind::Assign(
                 Place {
                     local: builder.ret_local,
@@ -206,14 +367,76 @@ fn lower_return(
         });
     }
 
-    let statements = std::mem::take(&mut builder.statements);
-    builder.body.basic_blocks.push(BasicBlock {
-        statements,
-        terminator: Box::new(Terminator {
-            span: None,
-            kind: TerminatorKind::Return,
-        }),
-    });
+    lower_return_block(builder, info.span)
+}
+
+/// Lowers `become call(args)`, the call ending the function in place of a return. Whether the
+/// callee can take the frame of the function is checked once all the functions are lowered.
+#[instrument(level = "debug", skip_all)]
+fn lower_become(
+    builder: &mut FnIrBuilder,
+    info: &BecomeStmt,
+    ret_type: TypeIndex,
+) -> Result<(), LoweringError> {
+    debug!("begin lowering become");
+    if let Some(defer_span) = builder.deferring {
+        return Err(LoweringError::ReturnInDefer {
+            span: info.span,
+            defer_span,
+            path: builder.get_file_path().clone(),
+        });
+    }
+
+    let invalid = |builder: &FnIrBuilder, reason: &str| LoweringError::InvalidTailCall {
+        span: info.span,
+        reason: reason.to_string(),
+        path: builder.get_file_path().clone(),
+    };
+
+    if builder.suspends.is_some() {
+        return Err(invalid(
+            builder,
+            "async functions return a `Future`, they can't make tail calls",
+        ));
+    }
+    if !builder.defers.is_empty() {
+        return Err(invalid(builder, "a defer would run after the call"));
+    }
+
+    let blocks = builder.body.basic_blocks.len();
+    let (value, _, _) = lower_expression(builder, &info.value, Some(ret_type))?;
+
+    // The call has to be the last thing the expression does, its value being the one returned.
+    let call_block = builder
+        .body
+        .basic_blocks
+        .last()
+        .filter(|_| builder.body.basic_blocks.len() > blocks && builder.statements.is_empty());
+    let tail_call = match (call_block, &value) {
+        (Some(block), Rvalue::Use(Operand::Place(value))) => match &block.terminator.kind {
+            TerminatorKind::Call {
+                func,
+                args,
+                destination,
+                ..
+            } if destination.local == value.local && value.projection.is_empty() => {
+                Some(TerminatorKind::TailCall {
+                    func: *func,
+                    args: args.clone(),
+                })
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some(tail_call) = tail_call else {
+        return Err(invalid(builder, "`become` takes a function call"));
+    };
+
+    *builder.body.basic_blocks.last_mut().unwrap().terminator = Terminator {
+        span: Some(info.span),
+        kind: tail_call,
+    };
 
     Ok(())
 }
@@ -221,7 +444,7 @@ fn lower_return(
 #[instrument(level = "debug", skip_all)]
 fn lower_if_statement(builder: &mut FnIrBuilder, info: &IfExpr) -> Result<(), LoweringError> {
     debug!("begin lowering if");
-    let (discriminator, discriminator_type_idx, _disc_span) =
+    let (discriminator, discriminator_type_idx, disc_span) =
         lower_expression(builder, &info.cond, None)?;
 
     let local = builder.add_temp_local(builder.builder.ir.get_bool_ty());
@@ -231,7 +454,7 @@ fn lower_if_statement(builder: &mut FnIrBuilder, info: &IfExpr) -> Result<(), Lo
     };
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(disc_span),
         kind: StatementKind::Assign(place.clone(), discriminator),
     });
 
@@ -244,7 +467,7 @@ fn lower_if_statement(builder: &mut FnIrBuilder, info: &IfExpr) -> Result<(), Lo
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: Some(disc_span),
             kind: TerminatorKind::Unreachable,
         }),
     });
@@ -252,10 +475,9 @@ fn lower_if_statement(builder: &mut FnIrBuilder, info: &IfExpr) -> Result<(), Lo
     // keep idx for switch targets
     let first_then_block_idx = builder.body.basic_blocks.len();
 
-    for stmt in &info.block_stmts {
-        get_locals(builder, stmt)?;
-        lower_statement(builder, stmt, builder.body.locals[builder.ret_local].ty)?;
-    }
+    let outer_scope = builder.enter_block_scope(&info.block_stmts);
+    lower_block(builder, &info.block_stmts)?;
+    builder.scope = outer_scope;
 
     // keet idx to change terminator
     let last_then_block_idx = {
@@ -264,7 +486,7 @@ fn lower_if_statement(builder: &mut FnIrBuilder, info: &IfExpr) -> Result<(), Lo
         builder.body.basic_blocks.push(BasicBlock {
             statements,
             terminator: Box::new(Terminator {
-                span: None,
+                span: Some(info.span),
                 kind: TerminatorKind::Unreachable,
             }),
         });
@@ -276,16 +498,15 @@ fn lower_if_statement(builder: &mut FnIrBuilder, info: &IfExpr) -> Result<(), Lo
     builder.name_to_local = outer_scope_locals.clone();
 
     if let Some(contents) = &info.else_stmts {
-        for stmt in contents {
-            get_locals(builder, stmt)?;
-            lower_statement(builder, stmt, builder.body.locals[builder.ret_local].ty)?;
-        }
+        let outer_scope = builder.enter_block_scope(contents);
+        lower_block(builder, contents)?;
+        builder.scope = outer_scope;
 
         let statements = std::mem::take(&mut builder.statements);
         builder.body.basic_blocks.push(BasicBlock {
             statements,
             terminator: Box::new(Terminator {
-                span: None,
+                span: Some(info.span),
                 kind: TerminatorKind::Goto {
                     target: builder.body.basic_blocks.len() + 1,
                 },
@@ -356,13 +577,17 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
             match adt.kind {
                 AdtKind::Struct => {
                     // a struct in a match makes no sense
-                    return Err(LoweringError::UnexpectedType {
-                        found_span: disc_span,
-                        found: builder.builder.display_typename(discriminator_type_idx),
-                        expected: "Any integral or enum type.".to_string(),
-                        expected_span: Some(info.span),
-                        path: builder.get_file_path().clone(),
-                    });
+                    return Err(LoweringError::UnexpectedType(Box::new(
+                        UnexpectedTypeError {
+                            found_span: disc_span,
+                            found: builder.builder.display_typename(discriminator_type_idx),
+                            expected: "Any integral or enum type.".to_string(),
+                            expected_span: Some(info.span),
+                            expected_path: None,
+                            difference: None,
+                            path: builder.get_file_path().clone(),
+                        },
+                    )));
                 }
                 AdtKind::Enum => {
                     let enum_place = discriminator.get_place().unwrap();
@@ -387,7 +612,7 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
     };
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(disc_span),
         kind: StatementKind::Assign(place.clone(), discriminator.clone()),
     });
 
@@ -397,7 +622,7 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: Some(disc_span),
             kind: TerminatorKind::Unreachable,
         }),
     });
@@ -420,6 +645,7 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
         // keep idx for switch targets
         let current_block_idx = builder.body.basic_blocks.len();
         targets.push(current_block_idx);
+        let outer_scope = builder.enter_scope(variant.span);
 
         match &variant.case {
             MatchCaseExpr::Value(value_expr) => {
@@ -512,24 +738,32 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
                 if !sym.generics.is_empty() {
                     if let Some(expected_adt_id_if_generics) = expected_adt_id_if_generics {
                         if expected_adt_id_if_generics != adt_id {
-                            return Err(LoweringError::UnexpectedType {
-                                found_span: disc_span,
-                                found: builder.builder.display_typename(*enum_ty),
-                                expected: enum_match_expr.name.to_string(),
-                                expected_span: Some(enum_match_expr.span),
-                                path: builder.get_file_path().clone(),
-                            });
+                            return Err(LoweringError::UnexpectedType(Box::new(
+                                UnexpectedTypeError {
+                                    found_span: disc_span,
+                                    found: builder.builder.display_typename(*enum_ty),
+                                    expected: enum_match_expr.name.to_string(),
+                                    expected_span: Some(enum_match_expr.span),
+                                    expected_path: None,
+                                    difference: None,
+                                    path: builder.get_file_path().clone(),
+                                },
+                            )));
                         }
                     } else {
                         // Type not found, meaning it may not be lowered, but nonetheless doesn't match the expected type.
                         // Because generics where specified explicitly.
-                        return Err(LoweringError::UnexpectedType {
-                            found_span: enum_match_expr.name.span,
-                            found: enum_match_expr.name.to_string(),
-                            expected: builder.builder.display_typename(*enum_ty),
-                            expected_span: Some(disc_span),
-                            path: builder.get_file_path().clone(),
-                        });
+                        return Err(LoweringError::UnexpectedType(Box::new(
+                            UnexpectedTypeError {
+                                found_span: enum_match_expr.name.span,
+                                found: enum_match_expr.name.to_string(),
+                                expected: builder.builder.display_typename(*enum_ty),
+                                expected_span: Some(disc_span),
+                                expected_path: None,
+                                difference: None,
+                                path: builder.get_file_path().clone(),
+                            },
+                        )));
                     }
                 }
 
@@ -545,46 +779,32 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
                     all_type_variants = Some(map);
                 }
 
-                let variant_idx = *adt_body
-                    .variant_names
-                    .get(&enum_match_expr.variant.name)
-                    .expect("variant not found");
+                let variant_idx = builder
+                    .builder
+                    .get_variant_idx(adt_id, &enum_match_expr.variant)?;
+                let variant_body = builder.builder.get_adt(adt_id).variants[variant_idx].clone();
+                let discriminant = builder.builder.get_adt(adt_id).discriminant(variant_idx);
 
                 covered_variants.insert(variant_idx);
 
-                let variant_value = ValueTree::Leaf(ConstValue::U32(variant_idx as u32));
+                let variant_value = ValueTree::Leaf(ConstValue::U32(discriminant));
 
                 target_conds.push(variant_value);
 
                 for field_value in &enum_match_expr.field_values {
-                    debug!("lowering variant field {}", field_value.name);
-                    let field_idx = *adt_body.variants[variant_idx]
+                    debug!("lowering variant field {}", field_value.name.name);
+                    let Some(field_idx) = variant_body
                         .field_names
-                        .get(&field_value.name)
-                        .expect("field not found");
-                    let ty_idx = adt_body.variants[variant_idx].fields[field_idx].ty;
-
-                    let field_local_idx = builder.body.locals.len();
-                    builder
-                        .name_to_local
-                        .insert(field_value.name.clone(), field_local_idx);
-                    builder.local_exists.insert(field_local_idx);
-
-                    let field_local = Local::new(
-                        Some(field_value.span),
-                        LocalKind::Temp,
-                        ty_idx,
-                        Some(field_value.name.clone()),
-                        false,
-                    );
-
-                    builder.body.locals.push(field_local);
-                    builder.local_exists.insert(field_local_idx);
-
-                    let field_place = Place {
-                        local: field_local_idx,
-                        projection: Vec::new(),
+                        .get(&field_value.name.name)
+                        .copied()
+                    else {
+                        return Err(LoweringError::FieldNotFound {
+                            span: field_value.span,
+                            name: field_value.name.name.clone(),
+                            path: builder.get_file_path().clone(),
+                        });
                     };
+                    let ty_idx = variant_body.fields[field_idx].ty;
 
                     // todo: maybe add a "mut ref" keyword to be able to modify the field in the match
 
@@ -595,26 +815,18 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
                         place
                     };
 
-                    builder.statements.push(Statement {
-                        span: Some(enum_match_expr.span),
-                        kind: StatementKind::Assign(
-                            field_place,
-                            Rvalue::Use(Operand::Place(enum_field_place)),
-                        ),
-                    });
-
-                    builder.statements.push(Statement {
-                        span: Some(enum_match_expr.span),
-                        kind: StatementKind::StorageLive(field_local_idx),
-                    });
+                    lower_pattern(
+                        builder,
+                        &field_value.pattern,
+                        enum_field_place,
+                        ty_idx,
+                        false,
+                    )?;
                 }
             }
         }
 
-        for stmt in &variant.block {
-            get_locals(builder, stmt)?;
-            lower_statement(builder, stmt, builder.body.locals[builder.ret_local].ty)?;
-        }
+        lower_block(builder, &variant.block)?;
 
         // keep idx to change terminator
         let last_then_block_idx = {
@@ -623,7 +835,7 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
             builder.body.basic_blocks.push(BasicBlock {
                 statements,
                 terminator: Box::new(Terminator {
-                    span: None,
+                    span: Some(info.span),
                     kind: TerminatorKind::Unreachable,
                 }),
             });
@@ -633,27 +845,32 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
 
         builder.name_to_local = outer_scope_locals.clone();
         builder.local_exists = outer_scope_local_exists.clone();
+        builder.scope = outer_scope;
     }
 
     if let Some(type_variants) = &all_type_variants {
-        for (variant, (variant_idx, variant_span)) in type_variants.iter() {
-            if !covered_variants.contains(variant_idx) {
-                let (ty, type_span) = adt_ty_for_debug.unwrap();
-                let module_id = *builder.builder.type_to_module.get(&ty).unwrap();
-
-                let type_definition_file = builder.builder.ir.modules[module_id].file_path.clone();
-                return Err(LoweringError::MissingVariant(Box::new(
-                    MissingVariantError {
-                        match_span: info.span,
-                        variant_span: *variant_span,
-                        variant_name: (*variant).clone(),
-                        type_span,
-                        type_name: builder.builder.display_typename(ty),
-                        type_path: type_definition_file,
-                        path: builder.get_file_path().clone(),
-                    },
-                )));
-            }
+        // The first missing variant in the order they're declared is reported.
+        let missing = type_variants
+            .iter()
+            .filter(|(_, (variant_idx, _))| !covered_variants.contains(variant_idx))
+            .min_by_key(|(_, (variant_idx, _))| *variant_idx);
+
+        if let Some((variant, (_, variant_span))) = missing {
+            let (ty, type_span) = adt_ty_for_debug.unwrap();
+            let module_id = *builder.builder.type_to_module.get(&ty).unwrap();
+
+            let type_definition_file = builder.builder.ir.modules[module_id].file_path.clone();
+            return Err(LoweringError::MissingVariant(Box::new(
+                MissingVariantError {
+                    match_span: info.span,
+                    variant_span: *variant_span,
+                    variant_name: (*variant).clone(),
+                    type_span,
+                    type_name: builder.builder.display_typename(ty),
+                    type_path: type_definition_file,
+                    path: builder.get_file_path().clone(),
+                },
+            )));
         }
     }
 
@@ -667,7 +884,7 @@ fn lower_match(builder: &mut FnIrBuilder, info: &MatchExpr) -> Result<(), Loweri
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: Some(info.span),
             kind: TerminatorKind::Unreachable,
         }),
     });
@@ -715,7 +932,7 @@ fn lower_while(builder: &mut FnIrBuilder, info: &WhileStmt) -> Result<(), Loweri
         }),
     });
 
-    let (discriminator, discriminator_type_idx, _disc_span) =
+    let (discriminator, discriminator_type_idx, disc_span) =
         lower_expression(builder, &info.condition, None)?;
 
     let local = builder.add_temp_local(builder.builder.ir.get_bool_ty());
@@ -725,7 +942,7 @@ fn lower_while(builder: &mut FnIrBuilder, info: &WhileStmt) -> Result<(), Loweri
     };
 
     builder.statements.push(Statement {
-        span: None,
+        span: Some(disc_span),
         kind: StatementKind::Assign(place.clone(), discriminator),
     });
 
@@ -736,7 +953,7 @@ fn lower_while(builder: &mut FnIrBuilder, info: &WhileStmt) -> Result<(), Loweri
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: Some(disc_span),
             kind: TerminatorKind::Unreachable,
         }),
     });
@@ -745,18 +962,17 @@ fn lower_while(builder: &mut FnIrBuilder, info: &WhileStmt) -> Result<(), Loweri
     let first_then_block_idx = builder.body.basic_blocks.len();
 
     let outer_scope_locals = builder.name_to_local.clone();
+    let outer_scope = builder.enter_block_scope(&info.block_stmts);
 
-    for stmt in &info.block_stmts {
-        get_locals(builder, stmt)?;
-        lower_statement(builder, stmt, builder.body.locals[builder.ret_local].ty)?;
-    }
+    lower_block(builder, &info.block_stmts)?;
 
+    builder.scope = outer_scope;
     builder.body.basic_blocks.len();
     let statements = std::mem::take(&mut builder.statements);
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: Some(disc_span),
             kind: TerminatorKind::Goto {
                 target: check_block_idx,
             },
@@ -786,6 +1002,7 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     debug!("lowering for");
 
     let outer_scope_locals = builder.name_to_local.clone();
+    let outer_scope = builder.enter_scope(info.span);
 
     if let Some(init) = &info.init {
         lower_let(builder, init)?;
@@ -795,14 +1012,14 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: Some(info.span),
             kind: TerminatorKind::Goto {
                 target: builder.body.basic_blocks.len() + 1,
             },
         }),
     });
 
-    let (discriminator, discriminator_type_idx, _disc_span) = if let Some(condition) =
+    let (discriminator, discriminator_type_idx, disc_span) = if let Some(condition) =
         &info.condition
     {
         let (discriminator, discriminator_type, span) = lower_expression(builder, condition, None)?;
@@ -828,7 +1045,7 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     };
 
     builder.statements.push(Statement {
-        span: None,
+        span: disc_span,
         kind: StatementKind::Assign(place.clone(), discriminator),
     });
 
@@ -839,7 +1056,7 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: disc_span,
             kind: TerminatorKind::Unreachable,
         }),
     });
@@ -847,6 +1064,7 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     // keep idx for switch targets
     let first_then_block_idx = builder.body.basic_blocks.len();
 
+    let defers = builder.defers.len();
     for stmt in &info.block_stmts {
         get_locals(builder, stmt)?;
     }
@@ -854,6 +1072,7 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     for stmt in &info.block_stmts {
         lower_statement(builder, stmt, builder.body.locals[builder.ret_local].ty)?;
     }
+    lower_defers(builder, defers)?;
 
     if let Some(post) = &info.post {
         lower_assign(builder, post)?;
@@ -864,7 +1083,7 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     builder.body.basic_blocks.push(BasicBlock {
         statements,
         terminator: Box::new(Terminator {
-            span: None,
+            span: Some(info.span),
             kind: TerminatorKind::Goto {
                 target: check_block_idx,
             },
@@ -886,6 +1105,532 @@ fn lower_for(builder: &mut FnIrBuilder, info: &ForStmt) -> Result<(), LoweringEr
     };
     builder.body.basic_blocks[check_block_idx].terminator.kind = kind;
     builder.name_to_local = outer_scope_locals;
+    builder.scope = outer_scope;
+
+    Ok(())
+}
+
+/// Lowers `for x in iterable`: ranges, arrays and slices are iterated with a counter, any other
+/// value by calling its `next` method until it returns something other than `Some`.
+#[instrument(level = "debug", skip_all)]
+fn lower_for_in(builder: &mut FnIrBuilder, info: &ForInStmt) -> Result<(), LoweringError> {
+    debug!("lowering for in");
+
+    let outer_scope_locals = builder.name_to_local.clone();
+    let outer_scope_local_exists = builder.local_exists.clone();
+    let outer_scope = builder.enter_scope(info.span);
+
+    match &info.iterable {
+        ForIterable::Range { start, end } => {
+            // Like binary operations, the type comes from the side that has one.
+            let ty = match find_expression_type(builder, start)? {
+                Some(ty) => Some(ty),
+                None => find_expression_type(builder, end)?,
+            };
+            let ty = ty.unwrap_or_else(|| builder.builder.ir.get_i32_ty());
+
+            let (start, start_ty, start_span) = lower_expression(builder, start, Some(ty))?;
+            let (end, end_ty, end_span) = lower_expression(builder, end, Some(ty))?;
+
+            for (found_ty, found_span) in [(start_ty, start_span), (end_ty, end_span)] {
+                if !matches!(
+                    builder.builder.get_type(found_ty),
+                    Type::Int(_) | Type::Uint(_)
+                ) || !builder
+                    .builder
+                    .get_type(found_ty)
+                    .is_equal(builder.builder.get_type(ty), &builder.builder.ir)
+                {
+                    return Err(LoweringError::UnexpectedType(Box::new(
+                        UnexpectedTypeError {
+                            found_span,
+                            found: builder.builder.display_typename(found_ty),
+                            expected: "The same integer type on both ends of the range."
+                                .to_string(),
+                            expected_span: None,
+                            expected_path: None,
+                            difference: None,
+                            path: builder.get_file_path().clone(),
+                        },
+                    )));
+                }
+            }
+
+            lower_counted_for(builder, info, (start, end), ty, |counter| {
+                (Rvalue::Use(Operand::Place(counter)), ty)
+            })?;
+        }
+        ForIterable::Value(value) => {
+            let (value, mut type_idx, span) = lower_expression(builder, value, None)?;
+
+            let mut place = match value {
+                Rvalue::Use(Operand::Place(place)) => place,
+                value => {
+                    let local = builder.add_temp_local(type_idx);
+                    let place = Place {
+                        local,
+                        projection: vec![],
+                    };
+                    builder.statements.push(Statement {
+                        span: Some(span),
+                        kind: StatementKind::StorageLive(local),
+                    });
+                    builder.statements.push(Statement {
+                        span: Some(span),
+                        kind: StatementKind::Assign(place.clone(), value),
+                    });
+                    place
+                }
+            };
+
+            // auto deref
+            while let Type::Ref(inner, _) = builder.builder.get_type(type_idx) {
+                place.projection.push(PlaceElem::Deref);
+                type_idx = *inner;
+            }
+
+            match builder.builder.get_type(type_idx).clone() {
+                ty @ (Type::Array(element_ty, _) | Type::Slice(element_ty)) => {
+                    if let Type::Slice(_) = ty {
+                        // The slice is copied, so assigning another one to the iterated variable
+                        // doesn't change the bounds of the loop.
+                        let local = builder.add_temp_local(type_idx);
+                        builder.statements.push(Statement {
+                            span: Some(span),
+                            kind: StatementKind::StorageLive(local),
+                        });
+                        builder.statements.push(Statement {
+                            span: Some(span),
+                            kind: StatementKind::Assign(
+                                Place {
+                                    local,
+                                    projection: vec![],
+                                },
+                                Rvalue::Use(Operand::Place(place)),
+                            ),
+                        });
+                        place = Place {
+                            local,
+                            projection: vec![],
+                        };
+                    }
+
+                    let u64_ty = builder.builder.ir.get_u64_ty();
+                    let (start, _) =
+                        lower_value_expr(builder, &ValueExpr::ConstInt(0, span), Some(u64_ty))?;
+                    let len = lower_len(builder, place.clone(), &ty, span);
+
+                    lower_counted_for(
+                        builder,
+                        info,
+                        (start, Rvalue::Use(len)),
+                        u64_ty,
+                        |counter| {
+                            let mut element = place.clone();
+                            element.projection.push(PlaceElem::Index(counter.local));
+                            (Rvalue::Use(Operand::Place(element)), element_ty)
+                        },
+                    )?;
+                }
+                Type::Adt(_) => lower_iterator_for(builder, info, (place, type_idx), span)?,
+                _ => {
+                    return Err(LoweringError::NotIterable {
+                        span,
+                        found: builder.builder.display_typename(type_idx),
+                        path: builder.get_file_path().clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    builder.name_to_local = outer_scope_locals;
+    builder.local_exists = outer_scope_local_exists;
+    builder.scope = outer_scope;
+
+    Ok(())
+}
+
+/// A loop over a counter going from start up to end, the item bound on each iteration is made
+/// from the place of the counter.
+fn lower_counted_for(
+    builder: &mut FnIrBuilder,
+    info: &ForInStmt,
+    (start, end): (Rvalue, Rvalue),
+    counter_ty: TypeIndex,
+    item: impl FnOnce(Place) -> (Rvalue, TypeIndex),
+) -> Result<(), LoweringError> {
+    let counter = Place {
+        local: builder.add_temp_local(counter_ty),
+        projection: vec![],
+    };
+    let end_place = Place {
+        local: builder.add_temp_local(counter_ty),
+        projection: vec![],
+    };
+
+    for (place, value) in [(&counter, start), (&end_place, end)] {
+        builder.statements.push(Statement {
+            span: Some(info.span),
+            kind: StatementKind::StorageLive(place.local),
+        });
+        builder.statements.push(Statement {
+            span: Some(info.span),
+            kind: StatementKind::Assign(place.clone(), value),
+        });
+    }
+
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(info.span),
+            kind: TerminatorKind::Goto {
+                target: builder.body.basic_blocks.len() + 1,
+            },
+        }),
+    });
+
+    let bool_ty = builder.builder.ir.get_bool_ty();
+    let cond = Place {
+        local: builder.add_temp_local(bool_ty),
+        projection: vec![],
+    };
+    builder.statements.push(Statement {
+        span: Some(info.span),
+        kind: StatementKind::Assign(
+            cond.clone(),
+            Rvalue::BinaryOp(
+                BinOp::Lt,
+                (Operand::Place(counter.clone()), Operand::Place(end_place)),
+            ),
+        ),
+    });
+
+    // keep idx to change terminator
+    let check_block_idx = builder.body.basic_blocks.len();
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(info.span),
+            kind: TerminatorKind::Unreachable,
+        }),
+    });
+
+    // keep idx for switch targets
+    let first_then_block_idx = builder.body.basic_blocks.len();
+
+    let (item, item_ty) = item(counter.clone());
+    lower_for_body(builder, info, item, item_ty)?;
+
+    let (one, _) = lower_value_expr(
+        builder,
+        &ValueExpr::ConstInt(1, info.span),
+        Some(counter_ty),
+    )?;
+    let Rvalue::Use(one) = one else {
+        unreachable!("constants are used as-is")
+    };
+    builder.statements.push(Statement {
+        span: Some(info.span),
+        kind: StatementKind::Assign(
+            counter.clone(),
+            Rvalue::BinaryOp(BinOp::Add, (Operand::Place(counter), one)),
+        ),
+    });
+
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(info.span),
+            kind: TerminatorKind::Goto {
+                target: check_block_idx,
+            },
+        }),
+    });
+
+    let otherwise_block_idx = builder.body.basic_blocks.len();
+
+    let targets = SwitchTargets {
+        values: vec![builder.builder.get_type(bool_ty).get_falsy_value()],
+        targets: vec![otherwise_block_idx, first_then_block_idx],
+    };
+    builder.body.basic_blocks[check_block_idx].terminator.kind = TerminatorKind::SwitchInt {
+        discriminator: Operand::Place(cond),
+        targets,
+    };
+
+    Ok(())
+}
+
+/// A loop calling `next` on the iterator until the returned enum isn't its `Some` variant, whose
+/// single field is the item.
+fn lower_iterator_for(
+    builder: &mut FnIrBuilder,
+    info: &ForInStmt,
+    (iterator, iterator_ty): (Place, TypeIndex),
+    span: Span,
+) -> Result<(), LoweringError> {
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(info.span),
+            kind: TerminatorKind::Goto {
+                target: builder.body.basic_blocks.len() + 1,
+            },
+        }),
+    });
+
+    // The block calling next, where each iteration goes back to.
+    let next_block_idx = builder.body.basic_blocks.len();
+
+    let next = FnCallOp {
+        path: Vec::new(),
+        target: Ident {
+            name: "next".to_string(),
+            span,
+        },
+        generics: Vec::new(),
+        args: Vec::new(),
+        span,
+    };
+    let (result, result_ty, _) = lower_fn_call(
+        builder,
+        &next,
+        Some((iterator, iterator_ty)),
+        Some(iterator_ty),
+    )?;
+    let result = result.get_place().unwrap();
+
+    let some = match builder.builder.get_type(result_ty) {
+        Type::Adt(index) => {
+            let adt = builder.builder.get_adt(*index);
+            adt.variant_names
+                .get("Some")
+                .filter(|_| matches!(adt.kind, AdtKind::Enum))
+                .filter(|x| adt.variants[**x].fields.len() == 1)
+                .map(|x| (*x, adt.discriminant(*x), adt.variants[*x].fields[0].ty))
+        }
+        _ => None,
+    };
+    let Some((some_idx, some_discriminant, item_ty)) = some else {
+        return Err(LoweringError::NotIterable {
+            span,
+            found: builder.builder.display_typename(iterator_ty),
+            path: builder.get_file_path().clone(),
+        });
+    };
+
+    let tag = Place {
+        local: builder.add_temp_local(builder.builder.ir.get_u32_ty()),
+        projection: vec![],
+    };
+    let mut tag_place = result.clone();
+    tag_place.projection.push(PlaceElem::GetVariant);
+    builder.statements.push(Statement {
+        span: Some(span),
+        kind: StatementKind::Assign(tag.clone(), Rvalue::Use(Operand::Place(tag_place))),
+    });
+
+    // keep idx to change terminator
+    let check_block_idx = builder.body.basic_blocks.len();
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(span),
+            kind: TerminatorKind::Unreachable,
+        }),
+    });
+
+    // keep idx for switch targets
+    let first_then_block_idx = builder.body.basic_blocks.len();
+
+    let mut item = result;
+    item.projection.push(PlaceElem::Variant(some_idx));
+    item.projection.push(PlaceElem::Field(0));
+    lower_for_body(builder, info, Rvalue::Use(Operand::Place(item)), item_ty)?;
+
+    let statements = std::mem::take(&mut builder.statements);
+    builder.body.basic_blocks.push(BasicBlock {
+        statements,
+        terminator: Box::new(Terminator {
+            span: Some(info.span),
+            kind: TerminatorKind::Goto {
+                target: next_block_idx,
+            },
+        }),
+    });
+
+    let otherwise_block_idx = builder.body.basic_blocks.len();
+
+    let targets = SwitchTargets {
+        values: vec![ValueTree::Leaf(ConstValue::U32(some_discriminant))],
+        targets: vec![first_then_block_idx, otherwise_block_idx],
+    };
+    builder.body.basic_blocks[check_block_idx].terminator.kind = TerminatorKind::SwitchInt {
+        discriminator: Operand::Place(tag),
+        targets,
+    };
+
+    Ok(())
+}
+
+/// Binds the item to the name of the loop and lowers the statements of its block.
+fn lower_for_body(
+    builder: &mut FnIrBuilder,
+    info: &ForInStmt,
+    item: Rvalue,
+    item_ty: TypeIndex,
+) -> Result<(), LoweringError> {
+    let local = builder.add_local(Local::new(
+        Some(info.name.span),
+        LocalKind::Temp,
+        item_ty,
+        Some(info.name.name.clone()),
+        false,
+    ));
+    builder.name_to_local.insert(info.name.name.clone(), local);
+    builder.local_exists.insert(local);
+    builder.pattern_locals.insert(local);
+
+    builder.statements.push(Statement {
+        span: Some(info.name.span),
+        kind: StatementKind::StorageLive(local),
+    });
+    builder.statements.push(Statement {
+        span: Some(info.name.span),
+        kind: StatementKind::Assign(
+            Place {
+                local,
+                projection: vec![],
+            },
+            item,
+        ),
+    });
+
+    lower_block(builder, &info.block_stmts)
+}
+
+/// Binds the names of the pattern to the parts of the value at the place, the pattern has to
+/// match any value of the type.
+#[instrument(level = "debug", skip_all)]
+pub(crate) fn lower_pattern(
+    builder: &mut FnIrBuilder,
+    pattern: &Pattern,
+    mut place: Place,
+    mut type_idx: TypeIndex,
+    is_mutable: bool,
+) -> Result<(), LoweringError> {
+    let (name, variant, fields) = match pattern {
+        Pattern::Binding(name) => {
+            let local = builder.add_local(Local::new(
+                Some(name.span),
+                LocalKind::Temp,
+                type_idx,
+                Some(name.name.clone()),
+                is_mutable,
+            ));
+            builder.name_to_local.insert(name.name.clone(), local);
+            builder.local_exists.insert(local);
+            builder.pattern_locals.insert(local);
+
+            builder.statements.push(Statement {
+                span: Some(name.span),
+                kind: StatementKind::StorageLive(local),
+            });
+            builder.statements.push(Statement {
+                span: Some(name.span),
+                kind: StatementKind::Assign(
+                    Place {
+                        local,
+                        projection: vec![],
+                    },
+                    Rvalue::Use(Operand::Place(place)),
+                ),
+            });
+            return Ok(());
+        }
+        Pattern::Struct { name, fields, .. } => (name, None, fields),
+        Pattern::Variant {
+            name,
+            variant,
+            fields,
+            ..
+        } => (name, Some(variant), fields),
+    };
+
+    // auto deref, the parts of a referenced value are copied out of it.
+    while let Type::Ref(inner, _) = builder.builder.get_type(type_idx) {
+        place.projection.push(PlaceElem::Deref);
+        type_idx = *inner;
+    }
+
+    let adt_id = match builder.builder.get_type(type_idx) {
+        Type::Adt(id)
+            if builder.builder.get_adt(*id).name == name.name.name
+                && matches!(
+                    (builder.builder.get_adt(*id).kind, variant),
+                    (AdtKind::Struct, None) | (AdtKind::Enum, Some(_))
+                ) =>
+        {
+            *id
+        }
+        _ => {
+            return Err(LoweringError::UnexpectedType(Box::new(
+                UnexpectedTypeError {
+                    found_span: pattern.get_span(),
+                    found: builder.builder.display_typename(type_idx),
+                    expected: name.to_string(),
+                    expected_span: None,
+                    expected_path: None,
+                    difference: None,
+                    path: builder.get_file_path().clone(),
+                },
+            )));
+        }
+    };
+
+    let variant_idx = match variant {
+        Some(variant) => {
+            let variant_idx = builder.builder.get_variant_idx(adt_id, variant)?;
+            if builder.builder.get_adt(adt_id).variants.len() > 1 {
+                return Err(LoweringError::RefutablePattern {
+                    span: pattern.get_span(),
+                    variant: format!("{}#{}", name.name.name, variant.name),
+                    type_name: builder.builder.display_typename(type_idx),
+                    path: builder.get_file_path().clone(),
+                });
+            }
+            place.projection.push(PlaceElem::Variant(variant_idx));
+            variant_idx
+        }
+        None => 0,
+    };
+
+    let variant_def = builder.builder.get_adt(adt_id).variants[variant_idx].clone();
+    for field in fields {
+        let Some(field_idx) = variant_def.field_names.get(&field.name.name).copied() else {
+            return Err(LoweringError::FieldNotFound {
+                span: field.span,
+                name: field.name.name.clone(),
+                path: builder.get_file_path().clone(),
+            });
+        };
+
+        let mut field_place = place.clone();
+        field_place.projection.push(PlaceElem::Field(field_idx));
+        lower_pattern(
+            builder,
+            &field.pattern,
+            field_place,
+            variant_def.fields[field_idx].ty,
+            is_mutable,
+        )?;
+    }
 
     Ok(())
 }
@@ -68,6 +68,12 @@ impl TraitDatabase {
         *self.trait_to_module.get(&id).unwrap()
     }
 
+    /// Makes the trait known in the module by the given name, like when it's imported.
+    pub fn import_trait(&mut self, idx: TraitIdx, module_idx: ModuleIndex, name: &str) {
+        self.name_to_trait
+            .insert((module_idx, name.to_string()), idx);
+    }
+
     pub fn get_trait_by_name(&self, name: &str, module_id: ModuleIndex) -> Option<TraitIdx> {
         self.name_to_trait
             .get(&(module_id, name.to_string()))
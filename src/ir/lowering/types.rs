@@ -3,15 +3,19 @@ use std::sync::Arc;
 use tracing::{debug, instrument};
 
 use crate::{
-    ast::types::{TypeDecl, TypeDescriptor},
+    ast::types::{ArraySize, TypeDecl, TypeDescriptor},
     ir::lowering::{Symbol, adts::lower_enum},
 };
 
 use super::{
     IRBuilder,
     adts::lower_struct,
+    constants::evaluate_array_size,
     errors::LoweringError,
-    ir::{self, ConstData, ConstKind, ConstValue, FloatTy, Mutability, Type, TypeIndex, ValueTree},
+    ir::{
+        self, AdtIndex, ConstData, ConstKind, ConstValue, FloatTy, ModuleIndex, Mutability, Type,
+        TypeIndex, ValueTree,
+    },
 };
 
 /// Lowers a type.
@@ -69,12 +73,21 @@ pub(crate) fn lower_type(
                 .get(&Type::Float(FloatTy::F64))
                 .unwrap(),
             "bool" => *builder.ir.builtin_types.get(&Type::Bool).unwrap(),
-            "String" => *builder.ir.builtin_types.get(&Type::String).unwrap(),
+            "String" => {
+                builder
+                    .ir
+                    .get_string_ty()
+                    .ok_or_else(|| LoweringError::MissingLangItem {
+                        span: *span,
+                        item: "String".to_string(),
+                        path: builder.get_current_module().file_path.clone(),
+                    })?
+            }
             "char" => *builder.ir.builtin_types.get(&Type::Char).unwrap(),
             other => {
                 // Check if it exists in the type aliases
-                if let Some(ty) = builder.get_current_symbols().types.get(other) {
-                    return Ok(*ty);
+                if let Some(ty) = builder.get_current_symbols().types.get(other).copied() {
+                    return resolve_type_alias(builder, ty);
                 }
 
                 // Check if the type name exists in the generic map.
@@ -87,10 +100,17 @@ pub(crate) fn lower_type(
                     return Ok(ty);
                 }
 
+                let use_module_idx = builder.get_current_module_idx();
+                // ADTs imported with another name are looked up by their own name where they're
+                // imported from, which is where they're monomorphized.
+                let mut type_module_idx = builder.get_path_module_idx(&name.path)?;
+                let mut type_name = other.to_string();
+                if let Some((module_idx, alias_of)) = builder.resolve_alias(type_module_idx, other)
                 {
-                    let type_module_idx = builder.get_path_module_idx(&name.path)?;
-                    builder.enter_module_context(type_module_idx);
+                    type_module_idx = module_idx;
+                    type_name = alias_of;
                 }
+                builder.enter_module_context(type_module_idx);
 
                 let symbols = builder
                     .symbols
@@ -98,8 +118,8 @@ pub(crate) fn lower_type(
                     .expect("failed to get symbols for module");
 
                 // Find using the polymorphic symbol.
-                let mut sym = Symbol {
-                    name: other.to_string(),
+                let sym = Symbol {
+                    name: type_name,
                     method_of: None,
                     generics: Vec::new(),
                 };
@@ -108,19 +128,27 @@ pub(crate) fn lower_type(
                 if let Some(adt_idx) = symbols.aggregates.get(&sym).copied() {
                     debug!("Found adt with symbol {:?}", sym);
 
+                    if let Some(def_module) = builder.private_adts.get(&adt_idx) {
+                        let (kind, def_span, vis_span) = builder.get_adt_decl_name(adt_idx);
+                        builder.check_visibility(
+                            *def_module,
+                            use_module_idx,
+                            kind,
+                            other,
+                            name.name.span,
+                            def_span,
+                            vis_span,
+                        )?;
+                    }
+
                     // Get the type id of this adt id.
                     let adt_type_idx = *builder.adt_to_type_idx.get(&adt_idx).unwrap();
 
                     // Find the generic types needed.
-
-                    // If it's not a struct, it will be changed once a body is found.
-                    let mut kind = ir::AdtKind::Struct;
-
                     let body_generics =
                         if let Some(body) = builder.bodies.structs.get(&adt_idx).as_ref() {
                             body.generics.clone()
                         } else if let Some(body) = builder.bodies.enums.get(&adt_idx).as_ref() {
-                            kind = ir::AdtKind::Enum;
                             body.generics.clone()
                         } else {
                             panic!("adt should be found")
@@ -136,7 +164,11 @@ pub(crate) fn lower_type(
                         for generic_param in body_generics.clone().iter() {
                             if type_name_generics.peek().is_some() {
                                 let tyname = type_name_generics.next().unwrap();
-                                let ty = lower_type(builder, &tyname.clone().into())?;
+                                // The arguments are named where the type is used.
+                                builder.leave_module_context();
+                                let ty = lower_type(builder, &tyname.clone().into());
+                                builder.enter_module_context(type_module_idx);
+                                let ty = ty?;
                                 generics.push(ty);
                             } else if let Some(ty) = builder
                                 .context
@@ -148,92 +180,8 @@ pub(crate) fn lower_type(
                             }
                         }
 
-                        sym.generics = generics.clone();
-
-                        // for borrowck
-                        let symbols = builder.get_current_symbols();
-
-                        // Get the monomorphized adt id or lower it.
-                        let mono_adt_idx = if let Some(mono_struct_idx) =
-                            symbols.aggregates.get(&sym).copied()
-                        {
-                            mono_struct_idx
-                        } else {
-                            // First get the new id.
-                            let mono_adt_idx = builder.ir.aggregates.insert(None);
-                            // And the type id for this new adt id.
-                            let type_id = builder.ir.types.insert(Some(Type::Adt(mono_adt_idx)));
-
-                            // Store them.
-                            builder.adt_to_type_idx.insert(mono_adt_idx, type_id);
-                            builder
-                                .type_to_module
-                                .insert(type_id, builder.get_current_module_idx());
-                            builder
-                                .get_current_symbols_mut()
-                                .aggregates
-                                .insert(sym.clone(), mono_adt_idx);
-
-                            // Save the id to ast body mapping.
-                            match kind {
-                                ir::AdtKind::Struct => {
-                                    builder.bodies.structs.insert(
-                                        mono_adt_idx,
-                                        builder.bodies.structs.get(&adt_idx).unwrap().clone(),
-                                    );
-                                }
-                                ir::AdtKind::Enum => {
-                                    builder.bodies.enums.insert(
-                                        mono_adt_idx,
-                                        builder.bodies.enums.get(&adt_idx).unwrap().clone(),
-                                    );
-                                }
-                                ir::AdtKind::Union => todo!(),
-                            }
-
-                            mono_adt_idx
-                        };
-
-                        // In case we have a id but not yet lowered
-                        if builder.ir.aggregates[mono_adt_idx].is_none() {
-                            let generics_mapping = builder.context.generics_mapping.clone();
-                            for (gen_ty, gen_param) in generics.iter().zip(body_generics.iter()) {
-                                builder
-                                    .context
-                                    .generics_mapping
-                                    .insert(gen_param.name.name.clone(), *gen_ty);
-                            }
-
-                            let id = match kind {
-                                ir::AdtKind::Struct => lower_struct(
-                                    builder,
-                                    &builder.bodies.structs.get(&adt_idx).unwrap().clone(),
-                                )?,
-                                ir::AdtKind::Enum => lower_enum(
-                                    builder,
-                                    &builder.bodies.enums.get(&adt_idx).unwrap().clone(),
-                                )?,
-                                ir::AdtKind::Union => todo!(),
-                            };
-                            assert_eq!(
-                                id, mono_adt_idx,
-                                "adt was already inserted so id should match"
-                            );
-
-                            builder.mono_type_to_poly.insert(
-                                *builder.adt_to_type_idx.get(&mono_adt_idx).unwrap(),
-                                adt_type_idx,
-                            );
-
-                            builder.context.generics_mapping = generics_mapping;
-                        }
-
                         builder.leave_module_context();
-
-                        *builder
-                            .adt_to_type_idx
-                            .get(&mono_adt_idx)
-                            .expect("should have a type idx")
+                        lower_adt_instance(builder, type_module_idx, adt_idx, generics)?
                     } else {
                         builder.leave_module_context();
                         adt_type_idx
@@ -267,16 +215,31 @@ pub(crate) fn lower_type(
             builder.ir.types.insert(Some(tykind))
         }
         TypeDescriptor::Array { of, size, span } => {
+            let size = match size {
+                ArraySize::Literal(size) => *size,
+                ArraySize::Constant(expr) => evaluate_array_size(builder, expr)?,
+            };
             let tykind = Type::Array(
                 lower_type(builder, of)?,
                 Arc::new(ConstData {
                     ty: builder.ir.get_u64_ty(),
                     span: *span,
-                    data: ConstKind::Value(ValueTree::Leaf(ConstValue::U64(*size))),
+                    data: ConstKind::Value(ValueTree::Leaf(ConstValue::U64(size))),
                 }),
             );
             builder.ir.types.insert(Some(tykind))
         }
+        TypeDescriptor::Slice { of, span: _ } => {
+            let tykind = Type::Slice(lower_type(builder, of)?);
+            builder.ir.types.insert(Some(tykind))
+        }
+        TypeDescriptor::Tuple { of, span: _ } => {
+            let fields = of
+                .iter()
+                .map(|ty| lower_type(builder, ty))
+                .collect::<Result<Vec<_>, _>>()?;
+            builder.ir.types.insert(Some(Type::Tuple(fields)))
+        }
         TypeDescriptor::SelfType { is_ref, is_mut, .. } => {
             let ty = builder.context.self_ty.expect("should have self type");
 
@@ -297,13 +260,166 @@ pub(crate) fn lower_type(
     })
 }
 
+/// The type of an alias, which is lowered here if it isn't yet. The modules importing it have the
+/// placeholder type of the declaration, and can be lowered before the module declaring it.
+fn resolve_type_alias(builder: &mut IRBuilder, ty: TypeIndex) -> Result<TypeIndex, LoweringError> {
+    let Some(decl) = builder.bodies.types.get(&ty).cloned() else {
+        return Ok(ty);
+    };
+    let module_idx = builder.type_to_module[&ty];
+    if let Some(lowered) = builder.symbols[&module_idx]
+        .types
+        .get(&decl.name.name)
+        .copied()
+        .filter(|x| *x != ty)
+    {
+        return Ok(lowered);
+    }
+
+    builder.enter_module_context(module_idx);
+    let lowered = lower_type_decl(builder, &decl);
+    builder.leave_module_context();
+    lowered
+}
+
+/// The type of the generic struct or enum with the given generic types, monomorphizing it the
+/// first time in the given module.
+pub(crate) fn lower_adt_instance(
+    builder: &mut IRBuilder,
+    module_idx: ModuleIndex,
+    adt_idx: AdtIndex,
+    generics: Vec<TypeIndex>,
+) -> Result<TypeIndex, LoweringError> {
+    let adt_type_idx = *builder.adt_to_type_idx.get(&adt_idx).unwrap();
+    let (name, kind, body_generics) = if let Some(body) = builder.bodies.structs.get(&adt_idx) {
+        (
+            body.name.name.clone(),
+            ir::AdtKind::Struct,
+            body.generics.clone(),
+        )
+    } else if let Some(body) = builder.bodies.enums.get(&adt_idx) {
+        (
+            body.name.name.clone(),
+            ir::AdtKind::Enum,
+            body.generics.clone(),
+        )
+    } else {
+        panic!("adt should be found")
+    };
+    let sym = Symbol {
+        name,
+        method_of: None,
+        generics: generics.clone(),
+    };
+
+    builder.enter_module_context(module_idx);
+
+    // for borrowck
+    let symbols = builder.get_current_symbols();
+
+    // Get the monomorphized adt id or lower it.
+    let mono_adt_idx = if let Some(mono_struct_idx) = symbols.aggregates.get(&sym).copied() {
+        mono_struct_idx
+    } else {
+        // First get the new id.
+        let mono_adt_idx = builder.ir.aggregates.insert(None);
+        // And the type id for this new adt id.
+        let type_id = builder.ir.types.insert(Some(Type::Adt(mono_adt_idx)));
+
+        // Store them.
+        builder.adt_to_type_idx.insert(mono_adt_idx, type_id);
+        builder
+            .type_to_module
+            .insert(type_id, builder.get_current_module_idx());
+        builder
+            .get_current_symbols_mut()
+            .aggregates
+            .insert(sym.clone(), mono_adt_idx);
+
+        // Save the id to ast body mapping.
+        match kind {
+            ir::AdtKind::Struct => {
+                builder.bodies.structs.insert(
+                    mono_adt_idx,
+                    builder.bodies.structs.get(&adt_idx).unwrap().clone(),
+                );
+            }
+            ir::AdtKind::Enum => {
+                builder.bodies.enums.insert(
+                    mono_adt_idx,
+                    builder.bodies.enums.get(&adt_idx).unwrap().clone(),
+                );
+            }
+            ir::AdtKind::Union => todo!(),
+        }
+
+        mono_adt_idx
+    };
+
+    // In case we have a id but not yet lowered
+    if builder.ir.aggregates[mono_adt_idx].is_none() {
+        let generics_mapping = builder.context.generics_mapping.clone();
+        for (gen_ty, gen_param) in generics.iter().zip(body_generics.iter()) {
+            builder
+                .context
+                .generics_mapping
+                .insert(gen_param.name.name.clone(), *gen_ty);
+        }
+
+        let id = match kind {
+            ir::AdtKind::Struct => lower_struct(
+                builder,
+                &builder.bodies.structs.get(&adt_idx).unwrap().clone(),
+            )?,
+            ir::AdtKind::Enum => lower_enum(
+                builder,
+                &builder.bodies.enums.get(&adt_idx).unwrap().clone(),
+            )?,
+            ir::AdtKind::Union => todo!(),
+        };
+        assert_eq!(
+            id, mono_adt_idx,
+            "adt was already inserted so id should match"
+        );
+
+        builder.mono_type_to_poly.insert(
+            *builder.adt_to_type_idx.get(&mono_adt_idx).unwrap(),
+            adt_type_idx,
+        );
+
+        builder.context.generics_mapping = generics_mapping;
+    }
+
+    builder.leave_module_context();
+
+    Ok(*builder
+        .adt_to_type_idx
+        .get(&mono_adt_idx)
+        .expect("should have a type idx"))
+}
+
 /// Lowers a type alias.
 #[instrument(skip_all)]
 pub(crate) fn lower_type_decl(
     builder: &mut IRBuilder,
     type_decl: &TypeDecl,
 ) -> Result<TypeIndex, LoweringError> {
-    let result_ty = lower_type(builder, &type_decl.value)?;
+    let alias = (
+        builder.get_current_module_idx(),
+        type_decl.name.name.clone(),
+    );
+    if builder.context.type_aliases_stack.contains(&alias) {
+        return Err(LoweringError::RecursiveTypeAlias {
+            span: type_decl.name.span,
+            name: type_decl.name.name.clone(),
+            path: builder.get_current_module().file_path.clone(),
+        });
+    }
+
+    builder.context.type_aliases_stack.push(alias);
+    let result_ty = lower_type(builder, &type_decl.value);
+    builder.context.type_aliases_stack.pop();
+    let result_ty = result_ty?;
     debug!(
         "Added type alias {} -> {}",
         type_decl.name.name,
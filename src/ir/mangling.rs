@@ -0,0 +1,246 @@
+//! The symbols of the functions, mangled with a versioned scheme so they're the same across builds
+//! and can be read back by tools like profilers and debuggers.
+//!
+//! Version 1 mangles a function as:
+//!
+//! ```text
+//! _C1_ <module>... [M <self type>] <name> [G <generic>... E] h <hash>
+//! ```
+//!
+//! Each of the modules of its path, the type it's a method of, its name and the types of its
+//! generic parameters is written as its length followed by its text, where the bytes other than
+//! letters, digits and `_`, and a leading digit, are escaped as `$` and their two hex digits. The
+//! hash is 16 hex digits of the 64 bit FNV-1a of the qualified names of all of these, telling apart
+//! the functions whose types are named the same in different modules. A suffix starting with `.`, like the one of the
+//! poll function of an `async` function, is kept as is.
+//!
+//! For example, `std::vec::Vec<i32>::push` is mangled as `_C1_3std3vecM12Vec$3ci32$3e4pushh...`.
+
+use std::fmt::Write as _;
+
+/// The version of the mangling scheme.
+pub const VERSION: u32 = 1;
+
+const PREFIX: &str = "_C1_";
+
+/// The parts of the name of a function that are mangled in its symbol.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MangledName<'a> {
+    /// The modules of its path, from the top level one.
+    pub modules: Vec<&'a str>,
+    /// The type it's a method of.
+    pub self_ty: Option<String>,
+    pub name: &'a str,
+    /// The types of its generic parameters, for an instance of a generic function.
+    pub generics: Vec<String>,
+}
+
+impl MangledName<'_> {
+    /// The symbol of the function, with the hash of the qualified names of its types.
+    pub fn mangle(&self, hash: u64) -> String {
+        let mut symbol = PREFIX.to_string();
+        for module in &self.modules {
+            push_component(&mut symbol, module);
+        }
+        if let Some(self_ty) = &self.self_ty {
+            symbol.push('M');
+            push_component(&mut symbol, self_ty);
+        }
+        push_component(&mut symbol, self.name);
+        if !self.generics.is_empty() {
+            symbol.push('G');
+            for generic in &self.generics {
+                push_component(&mut symbol, generic);
+            }
+            symbol.push('E');
+        }
+        write!(symbol, "h{hash:016x}").unwrap();
+        symbol
+    }
+}
+
+/// The stable hash of the parts, 64 bit FNV-1a, the same across builds of the compiler unlike
+/// the std hasher.
+pub fn stable_hash<'a>(parts: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        // Each part ends with a byte the names don't have, so they can't run into each other.
+        for byte in part.bytes().chain([0xff]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// The name of the function with the mangled symbol, like `std::vec::Vec<i32>::push`, `None` if
+/// it isn't mangled by Concrete.
+pub fn demangle(symbol: &str) -> Option<String> {
+    let (name, len) = parse(symbol)?;
+    (len == symbol.len()).then_some(name)
+}
+
+/// Demangles all the symbols found in the text, like the ones in a backtrace or a profile.
+pub fn demangle_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PREFIX) {
+        // A symbol starts at the start of a word.
+        let starts_word = !rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|x| x.is_ascii_alphanumeric() || x == '_' || x == '$');
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match parse(rest).filter(|_| starts_word) {
+            Some((name, len)) => {
+                result.push_str(&name);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push_str(&rest[..PREFIX.len()]);
+                rest = &rest[PREFIX.len()..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn push_component(symbol: &mut String, text: &str) {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, byte) in text.bytes().enumerate() {
+        // A leading digit would be read as part of the length.
+        if byte.is_ascii_alphabetic() || byte == b'_' || (i > 0 && byte.is_ascii_digit()) {
+            escaped.push(byte as char);
+        } else {
+            write!(escaped, "${byte:02x}").unwrap();
+        }
+    }
+    write!(symbol, "{}{escaped}", escaped.len()).unwrap();
+}
+
+/// The demangled name at the start of the text and the length of its symbol.
+fn parse(text: &str) -> Option<(String, usize)> {
+    let mut rest = text.strip_prefix(PREFIX)?;
+
+    let mut path = Vec::new();
+    while let Some(component) = parse_component(&mut rest) {
+        path.push(component?);
+    }
+    if let Some(after) = rest.strip_prefix('M') {
+        rest = after;
+        path.push(parse_component(&mut rest)??);
+        path.push(parse_component(&mut rest)??);
+    }
+    if path.len() < 2 {
+        return None;
+    }
+
+    let mut name = path.join("::");
+    if let Some(after) = rest.strip_prefix('G') {
+        rest = after;
+        let mut generics = Vec::new();
+        while let Some(component) = parse_component(&mut rest) {
+            generics.push(component?);
+        }
+        rest = rest.strip_prefix('E')?;
+        write!(name, "<{}>", generics.join(", ")).unwrap();
+    }
+
+    let hash = rest.strip_prefix('h')?.get(..16)?;
+    if !hash.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    rest = &rest[17..];
+
+    // The suffixes added to the symbol, like `.poll`.
+    let suffix_len = rest
+        .find(|x: char| !(x.is_ascii_alphanumeric() || x == '_' || x == '.'))
+        .unwrap_or(rest.len());
+    if rest.starts_with('.') {
+        name.push_str(&rest[..suffix_len]);
+        rest = &rest[suffix_len..];
+    }
+
+    Some((name, text.len() - rest.len()))
+}
+
+/// The unescaped text of the component at the start, `None` if there's none and `Some(None)` if
+/// it's malformed.
+fn parse_component(rest: &mut &str) -> Option<Option<String>> {
+    let digits = rest
+        .find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(rest.len());
+    if digits == 0 {
+        return None;
+    }
+
+    let Some(escaped) = rest[..digits]
+        .parse::<usize>()
+        .ok()
+        .and_then(|len| rest.get(digits..digits + len))
+    else {
+        return Some(None);
+    };
+    *rest = &rest[digits + escaped.len()..];
+    Some(unescape(escaped))
+}
+
+fn unescape(escaped: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'$' {
+            let hex = std::str::from_utf8(after.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mangled_names_demangle() {
+        let name = MangledName {
+            modules: vec!["std", "vec"],
+            self_ty: Some("Vec<i32>".to_string()),
+            name: "push",
+            generics: Vec::new(),
+        };
+        let symbol = name.mangle(0xabc);
+        assert_eq!(symbol, "_C1_3std3vecM12Vec$3ci32$3e4pushh0000000000000abc");
+        assert_eq!(demangle(&symbol).unwrap(), "std::vec::Vec<i32>::push");
+
+        let name = MangledName {
+            modules: vec!["app"],
+            self_ty: None,
+            name: "swap",
+            generics: vec!["&mut u8".to_string(), "[i32; 4]".to_string()],
+        };
+        let symbol = name.mangle(stable_hash(["app", "swap"]));
+        assert!(
+            symbol
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || "_$".contains(x))
+        );
+        assert_eq!(
+            demangle(&format!("{symbol}.poll")).unwrap(),
+            "app::swap<&mut u8, [i32; 4]>.poll"
+        );
+
+        assert_eq!(demangle("main"), None);
+        assert_eq!(demangle("_C1_3appx"), None);
+        assert_eq!(
+            demangle_text(&format!("at {symbol} (main.con:3)")),
+            "at app::swap<&mut u8, [i32; 4]> (main.con:3)"
+        );
+    }
+}
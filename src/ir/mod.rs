@@ -6,11 +6,14 @@ use std::{
 };
 
 pub mod lowering;
+pub mod mangling;
+pub mod source_map;
 
 pub type LocalIndex = usize;
 pub type BlockIndex = usize;
 pub type VariantIndex = usize;
 pub type FieldIndex = usize;
+pub type ScopeIndex = usize;
 
 pub type ModuleIndex = SmallSlabIndex<Module>;
 pub type AdtIndex = SmallSlabIndex<Option<AdtBody>>;
@@ -25,6 +28,7 @@ pub type Constants = SmallSlab<Option<ConstBody>>;
 pub type Modules = SmallSlab<Module>;
 
 pub use crate::ast::common::Span;
+use crate::check::lints::Warning;
 use typed_generational_arena::{SmallSlab, SmallSlabIndex};
 
 /// Holds all the IR structures.
@@ -43,12 +47,36 @@ pub struct IR {
     /// The top level modules, to start traversing them from this.
     /// Since the `modules` field is a flat structure holding all modules regardles of depth.
     pub top_level_modules: Vec<ModuleIndex>,
+    /// The top level modules of each compile unit, in the order they were given, the code of each
+    /// one is generated in parallel into its own object.
+    pub compile_units: Vec<Vec<ModuleIndex>>,
     pub builtin_types: HashMap<Type, TypeIndex>,
     // Test functions.
     pub tests: Vec<FnIndex>,
+    // Property test functions, called with generated arguments.
+    pub property_tests: Vec<FnIndex>,
+    // Functions marked with `#[fuzz_target]`, called by libFuzzer with each input.
+    pub fuzz_targets: Vec<FnIndex>,
+    // The times a test marked with `#[retries = "n"]` is retried before it's considered failed.
+    pub test_retries: HashMap<FnIndex, u32>,
+    /// The warnings found while lowering, the driver reports them according to the lint levels.
+    pub warnings: Vec<Warning>,
+    /// The top level modules of the units loaded from the metadata of dependencies, whose
+    /// functions are in the object the dependency was built to, so the ones with a body here are
+    /// copies of its inlinable functions.
+    pub prebuilt_modules: HashSet<ModuleIndex>,
 }
 
 impl IR {
+    /// Gets the top level module the given module is in, or itself if it's one.
+    pub fn top_level_module(&self, module: ModuleIndex) -> ModuleIndex {
+        self.modules[module]
+            .parents
+            .first()
+            .copied()
+            .unwrap_or(module)
+    }
+
     /// Get the builtin `bool` type.
     pub fn get_bool_ty(&self) -> TypeIndex {
         *self.builtin_types.get(&Type::Bool).unwrap()
@@ -85,8 +113,9 @@ impl IR {
     }
 
     /// Get the builtin `string` type.
-    pub fn get_string_ty(&self) -> TypeIndex {
-        *self.builtin_types.get(&Type::String).unwrap()
+    /// Get the `String` lang item, defined by std, `None` in programs without it.
+    pub fn get_string_ty(&self) -> Option<TypeIndex> {
+        self.builtin_types.get(&Type::String).copied()
     }
 
     /// Get the builtin `unit` type.
@@ -125,10 +154,20 @@ pub struct Function {
     pub args: Vec<TypeIndex>,
     pub ret_ty: TypeIndex,
     pub is_extern: bool,
+    /// Whether the function is declared `pub`, only these get JavaScript bindings.
+    pub is_pub: bool,
     pub is_intrinsic: Option<ConcreteIntrinsic>,
+    /// How the function is inlined, set by its `#[inline]` attribute.
+    pub inline: Option<Inline>,
+    /// Whether it's marked `#[cold]`, rarely called.
+    pub is_cold: bool,
     pub basic_blocks: Vec<BasicBlock>,
     pub module_idx: ModuleIndex,
     pub locals: Vec<Local>,
+    /// The lexical scopes of the blocks within the body, for debug info.
+    pub scopes: Vec<LexicalScope>,
+    /// The span of the declaration, for debug info.
+    pub span: Option<Span>,
 }
 
 impl Function {
@@ -144,6 +183,16 @@ impl Function {
     }
 }
 
+/// A block of statements of a function body whose locals are only visible within it, e.g the body
+/// of a loop.
+#[derive(Debug, Clone)]
+pub struct LexicalScope {
+    /// The span of the block.
+    pub span: Span,
+    /// The enclosing scope, `None` if it's the function body.
+    pub parent: Option<ScopeIndex>,
+}
+
 #[derive(Debug, Clone)]
 pub struct BasicBlock {
     pub statements: Vec<Statement>,
@@ -189,6 +238,40 @@ pub enum TerminatorKind {
         /// What basic block to jump to after the function call, if the function is non-diverging (i.e it returns control back).
         target: Option<BlockIndex>,
     },
+    /// Function call returning its value from the current function, made in place of the
+    /// current function's frame, from `become`.
+    TailCall {
+        /// The function to call.
+        func: FnIndex,
+        /// The arguments.
+        args: Vec<Rvalue>,
+    },
+    /// Jumps to the target if the index is in bounds, the program panics at the indexing otherwise.
+    /// The check is left out when bounds checks are disabled.
+    BoundsCheck {
+        /// Whether the index is in bounds.
+        cond: Operand,
+        /// The index and the length it's checked against, for the panic message.
+        index: Operand,
+        len: Operand,
+        target: BlockIndex,
+    },
+    /// Jumps to the target if the arithmetic doesn't overflow, the program panics at the operation
+    /// otherwise. The check is left out when overflow checks are disabled, and the operation wraps.
+    OverflowCheck {
+        /// Whether the operation overflows.
+        cond: Operand,
+        /// The operation, for the panic message.
+        op: BinOp,
+        target: BlockIndex,
+    },
+    /// Jumps to the target if the operation isn't undefined behavior, the program reports it with
+    /// the runtime of UndefinedBehaviorSanitizer otherwise. The check is only made with
+    /// `--sanitize undefined`.
+    UndefinedCheck {
+        check: UndefinedCheck,
+        target: BlockIndex,
+    },
     /// Conditional branching, used in ifs, while
     SwitchInt {
         /// The value to check.
@@ -198,6 +281,28 @@ pub enum TerminatorKind {
     },
 }
 
+/// The undefined behavior an `UndefinedCheck` checks for.
+#[derive(Debug, Clone)]
+pub enum UndefinedCheck {
+    /// The divisor of an integer division or remainder is zero.
+    DivisionByZero(Operand),
+    /// The raw pointer read through is null or misaligned for its pointee.
+    Load(Operand),
+    /// The raw pointer written through is null or misaligned for its pointee.
+    Store(Operand),
+}
+
+impl UndefinedCheck {
+    /// The checked divisor or pointer.
+    pub fn operand(&self) -> &Operand {
+        match self {
+            UndefinedCheck::DivisionByZero(operand)
+            | UndefinedCheck::Load(operand)
+            | UndefinedCheck::Store(operand) => operand,
+        }
+    }
+}
+
 /// Used for ifs, match
 #[derive(Debug, Clone)]
 pub struct SwitchTargets {
@@ -217,23 +322,33 @@ pub struct SwitchTargets {
 pub enum Rvalue {
     /// Use the operand as-is.
     Use(Operand),
+    /// The value at the place, moved out of it: the place can't be used again until it's
+    /// assigned. Struct updates move the fields of linear types they take from their `..base`.
+    Move(Place),
     /// The result of the logical operation.
     LogicOp(LogOp, (Operand, Operand)), // separate due to short-circuit
     /// The result of a binary operation.
     BinaryOp(BinOp, (Operand, Operand)),
+    /// Whether the `Add`, `Sub` or `Mul` of the integer operands overflows their type.
+    Overflows(BinOp, (Operand, Operand)),
     /// The result of a unary operation.
     UnaryOp(UnOp, Operand),
     /// A reference to a place.
     Ref(Mutability, Place),
     /// A cast.
     Cast(Operand, TypeIndex, Span),
+    /// The number of elements of the array or slice at the place, as a u64.
+    Len(Place),
+    /// The slice of the elements from start up to end of the array or slice at the place, with
+    /// the type of the slice.
+    Slice(Place, Operand, Operand, TypeIndex),
 }
 
 impl Rvalue {
     pub fn get_local(&self) -> Option<usize> {
         match self {
             Rvalue::Use(op) => op.get_local(),
-            Rvalue::Ref(_, op) => Some(op.local),
+            Rvalue::Ref(_, op) | Rvalue::Move(op) => Some(op.local),
             Rvalue::Cast(op, _, _) => op.get_local(),
             _ => None,
         }
@@ -242,7 +357,7 @@ impl Rvalue {
     pub fn get_place(&self) -> Option<Place> {
         match self {
             Rvalue::Use(op) => op.get_place(),
-            Rvalue::Ref(_, op) => Some(op.clone()),
+            Rvalue::Ref(_, op) | Rvalue::Move(op) => Some(op.clone()),
             Rvalue::Cast(op, _, _) => op.get_place(),
             _ => None,
         }
@@ -309,6 +424,8 @@ pub struct Local {
     pub kind: LocalKind,
     /// Whether this local is declared mutable.
     pub mutable: bool,
+    /// The lexical scope where the local is declared, `None` if it's the function body.
+    pub scope: Option<ScopeIndex>,
 }
 
 impl Local {
@@ -325,6 +442,7 @@ impl Local {
             ty,
             debug_name,
             mutable,
+            scope: None,
         }
     }
 
@@ -335,6 +453,7 @@ impl Local {
             kind: LocalKind::Temp,
             debug_name: None,
             mutable: false,
+            scope: None,
         }
     }
 
@@ -378,9 +497,74 @@ pub struct AdtBody {
     pub variant_names: HashMap<String, usize>,
     /// This is used for displaying errors and info to the user.
     pub generics_used: HashMap<String, TypeIndex>,
+    /// The lang item the enum is, for the compiler to know its variants.
+    pub lang_item: Option<LangItem>,
     pub span: Span,
 }
 
+/// The enums of the standard library the compiler knows about, marked with
+/// `#[langitem = "..."]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LangItem {
+    /// `Option<T>`, with the variants `Some { value: T }` and `None`.
+    Option,
+    /// `Result<T, E>`, with the variants `Ok { value: T }` and `Err { error: E }`.
+    Result,
+}
+
+impl LangItem {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Option" => Some(LangItem::Option),
+            "Result" => Some(LangItem::Result),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LangItem::Option => "Option",
+            LangItem::Result => "Result",
+        }
+    }
+
+    /// The variants and their field counts: the one holding the value `?` unwraps first, then the
+    /// one it returns early.
+    pub fn variants(self) -> [(&'static str, usize); 2] {
+        match self {
+            LangItem::Option => [("Some", 1), ("None", 0)],
+            LangItem::Result => [("Ok", 1), ("Err", 1)],
+        }
+    }
+}
+
+impl AdtBody {
+    /// The value of the tag of the given enum variant.
+    pub fn discriminant(&self, variant_idx: usize) -> u32 {
+        self.checked_discriminant(variant_idx)
+            .expect("the discriminants are checked when the enum is lowered")
+    }
+
+    /// The value of the tag of the given enum variant, `None` if it's past the largest `u32`.
+    pub fn checked_discriminant(&self, variant_idx: usize) -> Option<u32> {
+        match &self.variants[variant_idx].discriminant {
+            VariantDiscr::Explicit(value) => match &value.data {
+                ConstKind::Value(ValueTree::Leaf(ConstValue::U32(value))) => Some(*value),
+                _ => unreachable!("discriminants are u32"),
+            },
+            VariantDiscr::Relative(distance) => {
+                let base = variant_idx - *distance as usize;
+                match &self.variants[base].discriminant {
+                    VariantDiscr::Explicit(_) => {
+                        self.checked_discriminant(base)?.checked_add(*distance)
+                    }
+                    VariantDiscr::Relative(_) => Some(*distance),
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum AdtKind {
     Struct,
@@ -408,6 +592,8 @@ pub struct FieldDef {
     pub name: String,
     pub is_pub: bool,
     pub ty: TypeIndex,
+    /// The value of the field when a struct initialization doesn't give it.
+    pub default: Option<ConstData>,
 }
 
 // A variant discriminant
@@ -440,9 +626,13 @@ pub enum Type {
     String,
     /// A fixed size array.
     Array(TypeIndex, Arc<ConstData>),
+    /// A view into the elements of an array, a pointer to them and their count.
+    Slice(TypeIndex),
     Ref(TypeIndex, Mutability),
     Ptr(TypeIndex, Mutability),
     Adt(AdtIndex),
+    /// An anonymous product type, laid out like a struct with fields `0`, `1`, ...
+    Tuple(Vec<TypeIndex>),
 }
 
 impl Type {
@@ -465,6 +655,15 @@ impl Type {
                     false
                 }
             }
+            Type::Slice(index) => {
+                if let Type::Slice(other_index) = other {
+                    let self_ty = ir.types[*index].as_ref().unwrap();
+                    let other_ty = ir.types[*other_index].as_ref().unwrap();
+                    self_ty.is_equal(other_ty, ir)
+                } else {
+                    false
+                }
+            }
             Type::Ref(index, mutability) => {
                 if let Type::Ref(other_index, other_mutability) = other {
                     let self_ty = ir.types[*index].as_ref().unwrap();
@@ -490,6 +689,18 @@ impl Type {
                     false
                 }
             }
+            Type::Tuple(fields) => {
+                if let Type::Tuple(other_fields) = other {
+                    fields.len() == other_fields.len()
+                        && fields.iter().zip(other_fields).all(|(a, b)| {
+                            let self_ty = ir.types[*a].as_ref().unwrap();
+                            let other_ty = ir.types[*b].as_ref().unwrap();
+                            self_ty.is_equal(other_ty, ir)
+                        })
+                } else {
+                    false
+                }
+            }
         }
     }
 
@@ -507,9 +718,11 @@ impl Type {
             Type::Float(_) => None,
             Type::String => None,
             Type::Array(index, _) => Some(*index),
+            Type::Slice(index) => Some(*index),
             Type::Ref(index, _) => Some(*index),
             Type::Ptr(index, _) => Some(*index),
             Type::Adt { .. } => None,
+            Type::Tuple(_) => None,
         }
     }
 
@@ -583,6 +796,8 @@ impl Type {
                 }
                 todo!()
             }
+            // The pointer and the length.
+            Type::Slice(_) => 128,
             Type::Ref(_, _) => 64,
             Type::Ptr(_, _) => 64,
             Type::Adt(idx) => {
@@ -628,6 +843,27 @@ impl Type {
 
                 max_size
             }
+            Type::Tuple(fields) => {
+                let tuple_align = self.get_align(ir);
+                let mut size = 0;
+
+                for field in fields {
+                    let field_ty = ir.types[*field].as_ref().unwrap();
+                    let field_align = field_ty.get_align(ir);
+
+                    if field_align != 0 && size % field_align != 0 {
+                        size += (field_align - (size % field_align)) % field_align;
+                    }
+
+                    size += field_ty.get_bit_width(ir);
+                }
+
+                if size % tuple_align != 0 {
+                    size += (tuple_align - (size % tuple_align)) % tuple_align;
+                }
+
+                size
+            }
         }
     }
 
@@ -655,12 +891,14 @@ impl Type {
                 FloatTy::F32 => 32,
                 FloatTy::F64 => 64,
             },
-            Type::String => todo!(),
+            // The `String` lang item: the pointer, the length and the capacity.
+            Type::String => 64,
             Type::Array(inner_idx, _size_data) => {
                 let inner_ty = ir.types[*inner_idx].as_ref().unwrap();
 
                 inner_ty.get_align(ir)
             }
+            Type::Slice(_) => 64,
             Type::Ref(_, _) => 64,
             Type::Ptr(_, _) => 64,
             Type::Adt(idx) => {
@@ -684,6 +922,10 @@ impl Type {
 
                 max_align
             }
+            Type::Tuple(fields) => fields
+                .iter()
+                .map(|field| ir.types[*field].as_ref().unwrap().get_align(ir))
+                .fold(8, usize::max),
         }
     }
 }
@@ -710,8 +952,8 @@ impl Type {
                 UintTy::U8 => write!(f, "u8"),
             },
             Type::Float(ty) => match ty {
-                FloatTy::F32 => write!(f, "f64"),
-                FloatTy::F64 => write!(f, "f32"),
+                FloatTy::F32 => write!(f, "f32"),
+                FloatTy::F64 => write!(f, "f64"),
             },
             Type::String => write!(f, "string"),
             Type::Array(inner, size) => {
@@ -728,6 +970,9 @@ impl Type {
                     value
                 )
             }
+            Type::Slice(inner) => {
+                write!(f, "[{}]", ir.types[*inner].as_ref().unwrap().display(ir)?)
+            }
             Type::Ref(inner, is_mut) => {
                 let word = if let Mutability::Mut = is_mut {
                     "mut"
@@ -788,6 +1033,19 @@ impl Type {
 
                 Ok(())
             }
+            Type::Tuple(fields) => {
+                write!(f, "(")?;
+                for (i, field) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", ir.types[*field].as_ref().unwrap().display(ir)?)?;
+                }
+                if fields.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
         }?;
 
         Ok(f)
@@ -817,8 +1075,10 @@ impl Type {
             Type::Float(_) => todo!(),
             Type::String => todo!(),
             Type::Array(_, _) => todo!(),
+            Type::Slice(_) => todo!(),
             Type::Ref(_, _) => todo!(),
             Type::Adt { .. } => todo!(),
+            Type::Tuple(_) => todo!(),
             Type::Ptr(_, _) => todo!(),
         }
     }
@@ -862,6 +1122,45 @@ pub struct ConstData {
     pub data: ConstKind,
 }
 
+impl ConstData {
+    /// The value of an unsigned constant, like the size of an array, evaluating its expression if
+    /// it's given by one. Calls of const functions are only evaluated when lowering.
+    pub fn eval_u64(&self) -> Option<u64> {
+        match &self.data {
+            ConstKind::Value(ValueTree::Leaf(value)) => match value {
+                ConstValue::U8(value) => Some((*value).into()),
+                ConstValue::U16(value) => Some((*value).into()),
+                ConstValue::U32(value) => Some((*value).into()),
+                ConstValue::U64(value) => Some(*value),
+                _ => None,
+            },
+            ConstKind::Value(ValueTree::Branch(_)) => None,
+            ConstKind::Expr(expr) => match expr.as_ref() {
+                ConstExpr::Binop(op, lhs, rhs) => {
+                    let (lhs, rhs) = (lhs.eval_u64()?, rhs.eval_u64()?);
+                    match op {
+                        BinOp::Add => lhs.checked_add(rhs),
+                        BinOp::Sub => lhs.checked_sub(rhs),
+                        BinOp::Mul => lhs.checked_mul(rhs),
+                        BinOp::Div => lhs.checked_div(rhs),
+                        BinOp::Mod => lhs.checked_rem(rhs),
+                        BinOp::BitXor => Some(lhs ^ rhs),
+                        BinOp::BitAnd => Some(lhs & rhs),
+                        BinOp::BitOr => Some(lhs | rhs),
+                        BinOp::Shl => lhs.checked_shl(rhs.try_into().ok()?),
+                        BinOp::Shr => lhs.checked_shr(rhs.try_into().ok()?),
+                        BinOp::Eq | BinOp::Lt | BinOp::Le | BinOp::Ne | BinOp::Ge | BinOp::Gt => {
+                            None
+                        }
+                    }
+                }
+                ConstExpr::UnOp(UnOp::Not, value) => Some(!value.eval_u64()?),
+                ConstExpr::UnOp(UnOp::Neg, _) | ConstExpr::FunctionCall(..) => None,
+            },
+        }
+    }
+}
+
 /// The kind of a const data.
 #[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub enum ConstKind {
@@ -938,6 +1237,17 @@ pub enum ConstValue {
     String(String),
 }
 
+/// The inlining hint of a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inline {
+    /// `#[inline]`, inlining is preferred.
+    Hint,
+    /// `#[inline(always)]`
+    Always,
+    /// `#[inline(never)]`
+    Never,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum ConcreteIntrinsic {
     /// ```no_run
@@ -950,4 +1260,109 @@ pub enum ConcreteIntrinsic {
     ///    fn alignof<T>() -> u64;
     /// ```
     AlignOf(TypeIndex),
+    /// Whether the indexes of the standard library collections are checked, they aren't at
+    /// `OptLevel::Aggressive`.
+    /// ```no_run
+    ///    #[intrinsic = "bounds_checks"]
+    ///    fn bounds_checks() -> bool;
+    /// ```
+    BoundsChecks,
+    /// Whether the integer arithmetic is checked for overflows, it is at `OptLevel::None`.
+    /// ```no_run
+    ///    #[intrinsic = "overflow_checks"]
+    ///    fn overflow_checks() -> bool;
+    /// ```
+    OverflowChecks,
+    /// The `Add`, `Sub` or `Mul` of the integers, wrapping around on overflow.
+    /// ```no_run
+    ///    #[intrinsic = "wrapping_add"]
+    ///    fn wrapping_add<T>(a: T, b: T) -> T;
+    /// ```
+    Wrapping(BinOp),
+    /// Whether the `Add`, `Sub` or `Mul` of the integers overflows.
+    /// ```no_run
+    ///    #[intrinsic = "add_overflows"]
+    ///    fn add_overflows<T>(a: T, b: T) -> bool;
+    /// ```
+    Overflows(BinOp),
+    /// An atomic operation on the integer behind the pointer, the last argument is its ordering:
+    /// 0 is relaxed, 1 release, 2 acquire, 3 acquire-release and anything else sequentially
+    /// consistent.
+    /// ```no_run
+    ///    #[intrinsic = "atomic_add"]
+    ///    fn atomic_add<T>(ptr: *mut T, value: T, ordering: u32) -> T;
+    /// ```
+    Atomic(AtomicOp),
+    /// The address of a C function taking a `*mut T`, which calls the `run` method of `T` on it,
+    /// for threads to start from. The method is found at the first call of the intrinsic.
+    /// ```no_run
+    ///    #[intrinsic = "thread_start"]
+    ///    fn thread_start<T>() -> *mut u8;
+    /// ```
+    ThreadStart {
+        task: TypeIndex,
+        run: Option<FnIndex>,
+    },
+    /// Calls the poll function of a `Future` on its frame, returning whether it's ready, with the
+    /// value written to the output.
+    /// ```no_run
+    ///    #[intrinsic = "future_poll"]
+    ///    fn future_poll<T>(poll: *mut u8, frame: *mut u8, output: *mut T) -> bool;
+    /// ```
+    FuturePoll,
+    /// What calling an async function does: allocates the frame of its state machine, with the
+    /// arguments in the fields after the state, and returns the `Future` of the frame and the
+    /// poll function running the body.
+    AsyncStart { frame: TypeIndex, poll: FnIndex },
+}
+
+/// The operation of an atomic intrinsic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AtomicOp {
+    /// `atomic_load<T>(ptr: *mut T, ordering: u32) -> T`
+    Load,
+    /// `atomic_store<T>(ptr: *mut T, value: T, ordering: u32)`
+    Store,
+    /// `atomic_swap<T>(ptr: *mut T, value: T, ordering: u32) -> T`, returns the old value.
+    Swap,
+    /// `atomic_add`, `atomic_sub`, `atomic_and`, `atomic_or` and `atomic_xor`, with the same
+    /// signature as `atomic_swap`.
+    Rmw(BinOp),
+    /// `atomic_compare_exchange<T>(ptr: *mut T, current: T, new: T, ordering: u32) -> bool`,
+    /// whether the value was `current` and got replaced.
+    CompareExchange,
+    /// `atomic_fence(ordering: u32)`
+    Fence,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn array_sizes_given_by_expressions_evaluate() {
+        let mut types = Types::new();
+        let ty = types.insert(Some(Type::Uint(UintTy::U64)));
+        let constant = |data: ConstKind| ConstData {
+            ty,
+            span: Span::new(0, 0),
+            data,
+        };
+        let leaf = |value: u64| constant(ConstKind::Value(ValueTree::Leaf(ConstValue::U64(value))));
+        let binop = |op: BinOp, lhs: ConstData, rhs: ConstData| {
+            constant(ConstKind::Expr(Box::new(ConstExpr::Binop(op, lhs, rhs))))
+        };
+
+        let size = binop(BinOp::Mul, binop(BinOp::Add, leaf(3), leaf(1)), leaf(2));
+        assert_eq!(size.eval_u64(), Some(8));
+        assert_eq!(binop(BinOp::Shl, leaf(1), leaf(4)).eval_u64(), Some(16));
+
+        assert_eq!(binop(BinOp::Sub, leaf(1), leaf(2)).eval_u64(), None);
+        assert_eq!(binop(BinOp::Lt, leaf(1), leaf(2)).eval_u64(), None);
+        let call = constant(ConstKind::Expr(Box::new(ConstExpr::FunctionCall(
+            leaf(0),
+            vec![leaf(2)],
+        ))));
+        assert_eq!(call.eval_u64(), None);
+    }
 }
@@ -0,0 +1,198 @@
+//! Maps the spans of the IR back to lines and columns of the Concrete sources, for the locations of
+//! the generated code and for tools like profilers, coverage or the debugger.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use super::{BlockIndex, FnIndex, IR, Span};
+
+/// A position in a source file, with the line and column starting at 1.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub path: PathBuf,
+    pub line: usize,
+    /// The column in characters, not bytes.
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+/// Where an instruction of a function is in the IR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstructionIndex {
+    pub block: BlockIndex,
+    /// The statement within the block, `None` for its terminator.
+    pub statement: Option<usize>,
+}
+
+/// The offsets where each line of a source starts.
+#[derive(Debug, Clone)]
+struct LineIndex {
+    source: String,
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(source: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            source,
+            line_starts,
+        }
+    }
+
+    /// The line and column, starting at 1, of the given byte offset.
+    fn line_column(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset > self.source.len() {
+            return None;
+        }
+
+        let line = self.line_starts.partition_point(|x| *x <= offset) - 1;
+        let line_start = self.line_starts[line];
+        let column = self.source.get(line_start..offset)?.chars().count();
+
+        Some((line + 1, column + 1))
+    }
+}
+
+/// The sources of a program, to find the line and column of its spans.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: HashMap<PathBuf, LineIndex>,
+}
+
+impl SourceMap {
+    /// Reads the source files of the modules of the program, the ones that can't be read, like
+    /// sources only in memory, must be added with [`SourceMap::insert`].
+    pub fn from_ir(ir: &IR) -> Self {
+        let mut source_map = Self::default();
+
+        for (_, module) in ir.modules.iter() {
+            if source_map.files.contains_key(&module.file_path) {
+                continue;
+            }
+
+            if let Ok(source) = crate::parser::read_source(&module.file_path) {
+                source_map.insert(module.file_path.clone(), source);
+            }
+        }
+
+        source_map
+    }
+
+    pub fn insert(&mut self, path: PathBuf, source: String) {
+        self.files.insert(path, LineIndex::new(source));
+    }
+
+    /// The location of the start of the span in the given file.
+    pub fn location(&self, path: &Path, span: Span) -> Option<SourceLocation> {
+        let (line, column) = self.files.get(path)?.line_column(span.from)?;
+
+        Some(SourceLocation {
+            path: path.to_path_buf(),
+            line,
+            column,
+        })
+    }
+
+    /// The locations of the instructions of the function that have one.
+    pub fn function_locations(
+        &self,
+        ir: &IR,
+        function: FnIndex,
+    ) -> Vec<(InstructionIndex, SourceLocation)> {
+        let Some(body) = ir.functions[function].as_ref() else {
+            return Vec::new();
+        };
+        let path = &ir.modules[body.module_idx].file_path;
+
+        let mut locations = Vec::new();
+
+        for (block, basic_block) in body.basic_blocks.iter().enumerate() {
+            let spans = basic_block
+                .statements
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (Some(i), x.span))
+                .chain(std::iter::once((None, basic_block.terminator.span)));
+
+            for (statement, span) in spans {
+                if let Some(location) = span.and_then(|x| self.location(path, x)) {
+                    locations.push((InstructionIndex { block, statement }, location));
+                }
+            }
+        }
+
+        locations
+    }
+
+    /// The lines of the sources the function was lowered from, sorted and without duplicates.
+    pub fn function_lines(&self, ir: &IR, function: FnIndex) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .function_locations(ir, function)
+            .into_iter()
+            .map(|(_, x)| x.line)
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+}
+
+/// Prints the IR of the functions with the source location of each instruction, as written by
+/// `--emit=ir-with-locs`.
+pub fn display_ir_with_locations(ir: &IR, source_map: &SourceMap) -> String {
+    let mut out = String::new();
+
+    for (fn_idx, function) in ir.functions.iter() {
+        let Some(function) = function else {
+            continue;
+        };
+
+        let _ = write!(out, "fn {}", function.name);
+        if let Some(debug_name) = &function.debug_name {
+            let _ = write!(out, " ({debug_name})");
+        }
+        let _ = writeln!(out, ":");
+
+        let locations: HashMap<InstructionIndex, SourceLocation> = source_map
+            .function_locations(ir, fn_idx)
+            .into_iter()
+            .collect();
+        let location = |block, statement| {
+            locations
+                .get(&InstructionIndex { block, statement })
+                .map(|x| format!("  // {x}"))
+                .unwrap_or_default()
+        };
+
+        for (block, basic_block) in function.basic_blocks.iter().enumerate() {
+            let _ = writeln!(out, "  bb{block}:");
+
+            for (i, statement) in basic_block.statements.iter().enumerate() {
+                let _ = writeln!(out, "    {:?}{}", statement.kind, location(block, Some(i)));
+            }
+
+            let _ = writeln!(
+                out,
+                "    {:?}{}",
+                basic_block.terminator.kind,
+                location(block, None)
+            );
+        }
+
+        let _ = writeln!(out);
+    }
+
+    out
+}
@@ -1,3 +1,4 @@
+pub mod activity;
 pub mod ast;
 pub mod check;
 pub mod codegen;
@@ -5,3 +6,8 @@ pub mod compile_unit_info;
 pub mod driver;
 pub mod ir;
 pub mod parser;
+pub mod plugin;
+pub mod session;
+
+pub use ir::mangling::demangle;
+pub use session::{CompileSession, check_str, compile_str};
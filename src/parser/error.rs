@@ -1,137 +1,222 @@
-use std::path::Path;
+use std::{ops::Range, path::PathBuf};
 
-use crate::check::FileSpan;
+use crate::{
+    ast::common::Span,
+    check::{
+        diagnostics::{Diagnostic, SourceCache},
+        suggestions::{Applicability, Suggestion},
+    },
+};
 
 use super::{
     ProgramSource,
-    lexer::LexicalError,
+    lexer::{Lexer, LexicalError},
     tokens::{self, Token},
 };
-use ariadne::{ColorGenerator, Label, Report, ReportKind};
 use itertools::Itertools;
 use lalrpop_util::ParseError;
 
 pub type Error = ParseError<usize, Token, LexicalError>;
 
-pub struct Diagnostic(pub Error);
+/// Why a source file couldn't be read as text.
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[error("failed to read {}: {error}", path.display())]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("{} is not a text file, found a null byte at offset {offset}", path.display())]
+    Binary { path: PathBuf, offset: usize },
+    #[error("{} is not valid UTF-8, found byte {byte:#04x} at offset {offset}", path.display())]
+    InvalidUtf8 {
+        path: PathBuf,
+        offset: usize,
+        byte: u8,
+        /// The file with the invalid bytes replaced, to show where they are.
+        lossy: String,
+        /// The offset of the first invalid byte in the lossy source.
+        lossy_offset: usize,
+    },
+}
+
+impl SourceError {
+    /// Creates a diagnostic pointing at the invalid bytes, which have to be shown from the lossy
+    /// source, as the file itself can't be read as text.
+    pub fn to_diagnostic(&self) -> Option<(Diagnostic, &str)> {
+        match self {
+            SourceError::InvalidUtf8 {
+                path,
+                offset,
+                byte,
+                lossy,
+                lossy_offset,
+            } => {
+                let span = Span::new(
+                    *lossy_offset,
+                    *lossy_offset + char::REPLACEMENT_CHARACTER.len_utf8(),
+                );
+                let diagnostic = Diagnostic::error("P6", path.clone(), span)
+                    .with_message("Source file is not valid UTF-8.")
+                    .with_label(span, format!("invalid byte {byte:#04x} at offset {offset}"))
+                    .with_note("source files must be encoded as UTF-8");
+
+                Some((diagnostic, lossy.as_str()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An error found while parsing a file.
+pub struct SyntaxError(pub Error);
+
+impl SyntaxError {
+    /// The code of this error, as shown in the rendered report.
+    pub fn code(&self) -> &'static str {
+        match &self.0 {
+            ParseError::InvalidToken { .. } => "P1",
+            ParseError::UnrecognizedEof { .. } => "P2",
+            ParseError::UnrecognizedToken { .. } => "P3",
+            ParseError::ExtraToken { .. } => "P4",
+            ParseError::User { .. } => "P5",
+        }
+    }
+
+    /// The byte range the report of this error points at.
+    pub fn span(&self) -> Range<usize> {
+        match &self.0 {
+            ParseError::InvalidToken { location }
+            | ParseError::UnrecognizedEof { location, .. } => *location..*location,
+            ParseError::UnrecognizedToken { token, .. } | ParseError::ExtraToken { token } => {
+                token.0..token.2
+            }
+            ParseError::User { error } => match error {
+                LexicalError::InvalidToken(_, range) => range.clone(),
+            },
+        }
+    }
 
-impl Diagnostic {
     pub fn render(&self, source: &ProgramSource) {
-        let path = source.path.display().to_string();
-        let error = &self.0;
+        let mut cache = SourceCache::default();
+        cache.insert(source.path.display().to_string(), source.input.clone());
+        self.to_diagnostic(source)
+            .eprint(&mut cache)
+            .expect("failed to print to stderr");
+    }
+
+    /// Creates a diagnostic from the parse error.
+    pub fn to_diagnostic(&self, source: &ProgramSource) -> Diagnostic {
+        let path = source.path.clone();
+        let span = self.span();
+        let diagnostic = Diagnostic::error(self.code(), path, Span::new(span.start, span.end));
 
-        let mut colors = ColorGenerator::new();
-        let report = match error {
+        match &self.0 {
             ParseError::InvalidToken { location } => {
                 let loc = *location;
-                Report::build(ReportKind::Error, FileSpan::new(path.clone(), loc..loc))
-                    .with_code("P1")
+                diagnostic
                     .with_message("Parse error.")
+                    .with_label(Span::new(loc, loc + 1), "invalid token")
                     .with_label(
-                        Label::new(FileSpan::new(path.clone(), loc..(loc + 1)))
-                            .with_color(colors.next())
-                            .with_message("invalid token"),
+                        Span::new(loc.saturating_sub(10), loc + 10),
+                        "There was a problem parsing part of this code.",
                     )
-                    .with_label(
-                        Label::new(FileSpan::new(
-                            path.clone(),
-                            (loc.saturating_sub(10))..(loc + 10),
-                        ))
-                        .with_message("There was a problem parsing part of this code."),
-                    )
-                    .finish()
             }
             ParseError::UnrecognizedEof { location, expected } => {
                 let loc = *location;
-                Report::build(ReportKind::Error, FileSpan::new(path.clone(), loc..loc))
-                    .with_code("P2")
+                diagnostic
                     .with_message("Parse error.")
-                    .with_label(
-                        Label::new(FileSpan::new(path.clone(), loc..(loc + 1)))
-                            .with_message("unrecognized eof")
-                            .with_color(colors.next()),
-                    )
+                    .with_label(Span::new(loc, loc + 1), "unrecognized eof")
                     .with_note(format!(
                         "expected one of the following: {}",
                         expected.iter().join(", ")
                     ))
                     .with_label(
-                        Label::new(FileSpan::new(
-                            path.clone(),
-                            (loc.saturating_sub(10))..(loc + 10),
-                        ))
-                        .with_message("There was a problem parsing part of this code."),
+                        Span::new(loc.saturating_sub(10), loc + 10),
+                        "There was a problem parsing part of this code.",
                     )
-                    .finish()
             }
-            ParseError::UnrecognizedToken { token, expected } => Report::build(
-                ReportKind::Error,
-                FileSpan::new(path.clone(), token.0..token.2),
-            )
-            .with_code(3)
-            .with_message("Parse error.")
-            .with_label(
-                Label::new(FileSpan::new(path.clone(), token.0..token.2))
-                    .with_message(format!("unrecognized token '{:?}'", token.1))
-                    .with_color(colors.next()),
-            )
-            .with_note(format!(
-                "expected one of the following: {}",
-                expected.iter().join(", ")
-            ))
-            .with_label(
-                Label::new(FileSpan::new(
-                    path.clone(),
-                    (token.0.saturating_sub(10))..(token.2 + 10),
+            ParseError::UnrecognizedToken { token, expected } => diagnostic
+                .with_message("Parse error.")
+                .with_label(
+                    Span::new(token.0, token.2),
+                    format!("unrecognized token '{:?}'", token.1),
+                )
+                .with_note(format!(
+                    "expected one of the following: {}",
+                    expected.iter().join(", ")
                 ))
-                .with_message("There was a problem parsing part of this code."),
-            )
-            .finish(),
-            ParseError::ExtraToken { token } => Report::build(
-                ReportKind::Error,
-                FileSpan::new(path.clone(), token.0..token.2),
-            )
-            .with_code("P3")
-            .with_message("Parse error.")
-            .with_label(
-                Label::new(FileSpan::new(path.clone(), token.0..token.2))
-                    .with_message(format!("unexpected extra token {:?}", token.1)),
-            )
-            .finish(),
+                .with_label(
+                    Span::new(token.0.saturating_sub(10), token.2 + 10),
+                    "There was a problem parsing part of this code.",
+                )
+                .with_suggestions(token_suggestions(source, token, expected)),
+            ParseError::ExtraToken { token } => diagnostic.with_message("Parse error.").with_label(
+                Span::new(token.0, token.2),
+                format!("unexpected extra token {:?}", token.1),
+            ),
             ParseError::User { error } => match error {
-                LexicalError::InvalidToken(err, range) => match err {
-                    tokens::LexingError::NumberParseError => Report::build(
-                        ReportKind::Error,
-                        FileSpan::new(path.clone(), range.clone()),
-                    )
-                    .with_code(4)
-                    .with_message("Error parsing literal number")
-                    .with_label(
-                        Label::new(FileSpan::new(path.clone(), range.clone()))
-                            .with_message("error parsing literal number")
-                            .with_color(colors.next()),
-                    )
-                    .finish(),
-                    tokens::LexingError::Other => Report::build(
-                        ReportKind::Error,
-                        FileSpan::new(path.clone(), range.clone()),
-                    )
-                    .with_code(4)
-                    .with_message("Other error")
-                    .with_label(
-                        Label::new(FileSpan::new(path.clone(), range.clone()))
-                            .with_message("other error")
-                            .with_color(colors.next()),
-                    )
-                    .finish(),
-                },
+                LexicalError::InvalidToken(err, range) => {
+                    let span = Span::new(range.start, range.end);
+                    match err {
+                        tokens::LexingError::NumberParseError => diagnostic
+                            .with_message("Error parsing literal number")
+                            .with_label(span, "error parsing literal number"),
+                        tokens::LexingError::Other => diagnostic
+                            .with_message("Other error")
+                            .with_label(span, "other error"),
+                    }
+                }
             },
-        };
+        }
+    }
+}
 
-        report
-            .eprint(ariadne::FnCache::new(|x: &String| {
-                std::fs::read_to_string(Path::new(x.as_str()))
-            }))
-            .expect("failed to print to stderr");
+/// The fixes of the common mistakes behind an unrecognized token: `=` written for `==` in a
+/// comparison, and a statement missing its `;`.
+fn token_suggestions(
+    source: &ProgramSource,
+    token: &(usize, Token, usize),
+    expected: &[String],
+) -> Vec<Suggestion> {
+    // The expected tokens are the terminals of the grammar, quoted.
+    let expects = |x: &str| expected.iter().any(|e| e.trim_matches('"') == x);
+    let mut suggestions = Vec::new();
+
+    if token.1 == Token::Assign && expects("==") {
+        suggestions.push(Suggestion {
+            message: "to compare the values".to_string(),
+            path: None,
+            span: Span::new(token.0, token.2),
+            replacement: "==".to_string(),
+            applicability: Applicability::MaybeIncorrect,
+        });
     }
+
+    if expects(";") {
+        // Right after the token before, not after the comments and lines that follow it.
+        let end = Lexer::new(&source.input)
+            .map_while(Result::ok)
+            .take_while(|x| x.2 <= token.0)
+            .last()
+            .map_or(0, |x| x.2);
+
+        if let Some(between) = source.input.get(end..token.0) {
+            // A token on another line most likely starts the next statement.
+            let applicability = if between.contains('\n') {
+                Applicability::MachineApplicable
+            } else {
+                Applicability::MaybeIncorrect
+            };
+            suggestions.push(Suggestion {
+                message: "to end the statement".to_string(),
+                path: None,
+                span: Span::new(end, end),
+                replacement: ";".to_string(),
+                applicability,
+            });
+        }
+    }
+
+    suggestions
 }
@@ -0,0 +1,1346 @@
+//! The source formatter behind `concrete fmt`: prints the AST of a file back with 4 spaces of
+//! indentation and one item, field, match arm or statement per line.
+//!
+//! The AST has no comments, they are taken from the gaps between the tokens of the lexer and written
+//! back before the item or statement that follows them, or at the end of the line they trailed.
+//! Blank lines between items and statements are kept, at most one.
+
+use itertools::Itertools;
+use logos::Logos;
+
+use super::{ProgramSource, error::SyntaxError, parse_ast, tokens::Token};
+use crate::ast::{
+    common::{Attribute, AttributeArg, DocString, GenericParam, Ident, Span, TypeName},
+    constants::ConstantDef,
+    enums::{EnumDecl, UnionDecl},
+    expressions::{
+        ArithOp, BinaryOp, BitwiseOp, CmpOp, Expression, FnCallOp, IfExpr, LogicOp, MatchCaseExpr,
+        MatchExpr, PathOp, PathSegment, StructInitField, UnaryOp, ValueExpr,
+    },
+    functions::{ExternBlock, FunctionDecl, FunctionDef, ImplBlock, ImplTraitBlock, Param},
+    modules::{Module, ModuleDefItem},
+    patterns::{FieldPattern, Pattern},
+    statements::{
+        AssignStmt, DeferStmt, ForInStmt, ForIterable, ForStmt, LetStmt, LetStmtTarget, Statement,
+        WhileStmt,
+    },
+    structs::{Field, StructDecl},
+    traits::TraitDecl,
+    types::{ArraySize, AssociatedType, TypeDecl, TypeDescriptor},
+};
+
+const INDENT: &str = "    ";
+
+/// The precedence level of casts, the loosest one, any expression can be written there.
+const ANY_LEVEL: u8 = 5;
+
+pub enum FormatError {
+    Syntax(SyntaxError),
+    /// The formatted source doesn't have the tokens of the original, a bug of the formatter, the
+    /// file is left as it is.
+    Changed,
+}
+
+/// Formats the source, failing if it doesn't parse.
+pub fn format_source(source: &ProgramSource) -> Result<String, FormatError> {
+    let ast = parse_ast(source).map_err(FormatError::Syntax)?;
+
+    let mut printer = Printer::new(&source.input);
+    for (i, module) in ast.modules.iter().enumerate() {
+        printer.start_line(module.span.from, i == 0);
+        printer.module(module);
+        printer.newline();
+    }
+    let formatted = printer.finish();
+
+    // Parentheses, separators and the braces of match arms are normalized, everything else must
+    // be kept.
+    let formatted_source = ProgramSource::new(formatted, &source.path);
+    let same_tokens = significant_tokens(&source.input)
+        .is_some_and(|x| significant_tokens(&formatted_source.input).is_some_and(|y| x == y));
+    let same_comments =
+        comments(&source.input)
+            .iter()
+            .map(|x| x.text)
+            .eq(comments(&formatted_source.input).iter().map(|x| x.text));
+
+    if !same_tokens || !same_comments || parse_ast(&formatted_source).is_err() {
+        return Err(FormatError::Changed);
+    }
+
+    Ok(formatted_source.input)
+}
+
+fn significant_tokens(source: &str) -> Option<Vec<Token>> {
+    Token::lexer(source)
+        .filter_map(|token| match token {
+            Ok(
+                Token::LeftParen
+                | Token::RightParen
+                | Token::LeftBracket
+                | Token::RightBracket
+                | Token::Coma
+                | Token::Semicolon,
+            ) => None,
+            Ok(Token::DocString(text)) => Some(Ok(Token::DocString(text.trim_end().to_string()))),
+            token => Some(token),
+        })
+        .collect::<Result<_, _>>()
+        .ok()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Comment<'a> {
+    offset: usize,
+    text: &'a str,
+    /// Whether there's code before it in its line.
+    trailing: bool,
+}
+
+/// The comments of the source, which the lexer skips between its tokens.
+fn comments(source: &str) -> Vec<Comment<'_>> {
+    let mut comments = Vec::new();
+    let mut gap_start = 0;
+
+    let token_starts = Token::lexer(source)
+        .spanned()
+        .map(|(_, span)| span)
+        .chain(std::iter::once(source.len()..source.len()));
+
+    for span in token_starts {
+        let gap = &source[gap_start..span.start];
+        let mut offset = 0;
+
+        while offset < gap.len() {
+            let rest = &gap[offset..];
+            let len = if rest.starts_with("/*") {
+                rest.find("*/").map_or(rest.len(), |x| x + 2)
+            } else if let Some(comment) = rest.strip_prefix("//") {
+                // Like the lexer, the character after the slashes is part of the comment even if
+                // it's a newline.
+                let after = comment.chars().next().map_or(0, char::len_utf8);
+                2 + after + comment[after..].find('\n').unwrap_or(comment.len() - after)
+            } else {
+                offset += rest.chars().next().map_or(1, char::len_utf8);
+                continue;
+            };
+
+            let absolute = gap_start + offset;
+            let line_start = source[..absolute].rfind('\n').map_or(0, |x| x + 1);
+            comments.push(Comment {
+                offset: absolute,
+                text: rest[..len].trim_end(),
+                trailing: !source[line_start..absolute].trim().is_empty(),
+            });
+            offset += len;
+        }
+
+        gap_start = span.end.max(gap_start);
+    }
+
+    comments
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    comments: Vec<Comment<'a>>,
+    next_comment: usize,
+    /// The starts of the `}` tokens, to find where blocks end.
+    close_braces: Vec<usize>,
+    out: String,
+    indent: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Self {
+        let close_braces = Token::lexer(source)
+            .spanned()
+            .filter(|(token, _)| matches!(token, Ok(Token::RightBracket)))
+            .map(|(_, span)| span.start)
+            .collect();
+
+        Self {
+            source,
+            comments: comments(source),
+            next_comment: 0,
+            close_braces,
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        let first = self.out.is_empty();
+        self.flush_comments(self.source.len(), first);
+
+        let len = self.out.trim_end().len();
+        self.out.truncate(len);
+        self.out.push('\n');
+        self.out
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.out.is_empty() || self.out.ends_with('\n') {
+            for _ in 0..self.indent {
+                self.out.push_str(INDENT);
+            }
+        }
+
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+    }
+
+    /// Starts the line of the node at the given offset, writing the comments before it and the
+    /// blank line separating it from the previous one. The first line of a block has none.
+    fn start_line(&mut self, offset: usize, first: bool) {
+        let first = self.flush_comments(offset, first);
+
+        if !first && self.blank_line_before(offset) {
+            self.newline();
+        }
+    }
+
+    /// Writes the comments before the given offset, returning whether the next line is still the
+    /// first of its block.
+    fn flush_comments(&mut self, offset: usize, mut first: bool) -> bool {
+        while let Some(comment) = self.comments.get(self.next_comment).copied() {
+            if comment.offset >= offset {
+                break;
+            }
+            self.next_comment += 1;
+
+            if comment.trailing && self.out.ends_with('\n') {
+                self.out.pop();
+                self.out.push(' ');
+                self.out.push_str(comment.text);
+                self.newline();
+                continue;
+            }
+
+            if !first && self.blank_line_before(comment.offset) {
+                self.newline();
+            }
+            self.write(comment.text);
+            self.newline();
+            first = false;
+        }
+
+        first
+    }
+
+    /// Whether the source line before the one starting at the offset is blank.
+    fn blank_line_before(&self, offset: usize) -> bool {
+        let Some(line_start) = self.source[..offset].rfind('\n') else {
+            return false;
+        };
+        if !self.source[line_start + 1..offset].trim().is_empty() {
+            return false;
+        }
+
+        let previous = &self.source[..line_start];
+        let previous_start = previous.rfind('\n').map_or(0, |x| x + 1);
+        previous[previous_start..].trim().is_empty()
+    }
+
+    /// The start of the first `}` at or after the offset.
+    fn close_brace(&self, offset: usize) -> usize {
+        let idx = self.close_braces.partition_point(|x| *x < offset);
+        self.close_braces
+            .get(idx)
+            .copied()
+            .unwrap_or(self.source.len())
+    }
+
+    /// Writes the lines of a block, between its braces, the comments before `end` going inside it.
+    fn block<T>(
+        &mut self,
+        items: &[T],
+        end: usize,
+        start: impl Fn(&T) -> usize,
+        mut print: impl FnMut(&mut Self, &T),
+    ) {
+        let has_comments = self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|x| x.offset < end);
+        if items.is_empty() && !has_comments {
+            self.write("{}");
+            return;
+        }
+
+        self.write("{");
+        self.newline();
+        self.indent += 1;
+
+        for (i, item) in items.iter().enumerate() {
+            self.start_line(start(item), i == 0);
+            print(self, item);
+            self.newline();
+        }
+
+        self.flush_comments(end, items.is_empty());
+        self.indent -= 1;
+        self.write("}");
+    }
+
+    fn statements(&mut self, statements: &[Statement], end: usize) {
+        self.block(statements, end, statement_start, |printer, statement| {
+            printer.statement(statement)
+        });
+    }
+
+    /// Writes the source of the span as it is, with the comments in it, for the token trees of
+    /// macros, which have no layout to normalize.
+    fn verbatim(&mut self, span: Span) {
+        self.write(&self.source[span.from..span.to]);
+        while self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|x| x.offset < span.to)
+        {
+            self.next_comment += 1;
+        }
+    }
+
+    fn doc_string(&mut self, doc_string: &Option<DocString>) {
+        for line in doc_string.iter().flat_map(|x| &x.contents) {
+            self.write(&format!("///{}", line.trim_end()));
+            self.newline();
+        }
+    }
+
+    fn attributes(&mut self, attributes: &[Attribute]) {
+        for x in attributes {
+            self.write(&attribute(x));
+            self.newline();
+        }
+    }
+
+    fn module(&mut self, module: &Module) {
+        self.doc_string(&module.doc_string);
+        self.attributes(&module.attributes);
+        self.write(&format!("mod {} ", module.name.name));
+        self.block(
+            &module.contents,
+            module.span.to,
+            item_start,
+            Self::module_item,
+        );
+    }
+
+    fn module_item(&mut self, item: &ModuleDefItem) {
+        match item {
+            ModuleDefItem::Constant(constant) => self.constant(constant),
+            ModuleDefItem::Function(function) => self.function(function),
+            ModuleDefItem::FunctionDecl(decl) => {
+                self.function_decl(decl);
+                self.write(";");
+            }
+            ModuleDefItem::Impl(block) => self.impl_block(block),
+            ModuleDefItem::ImplTrait(block) => self.impl_trait_block(block),
+            ModuleDefItem::ExternBlock(block) => self.extern_block(block),
+            ModuleDefItem::Struct(decl) => self.struct_decl(decl),
+            ModuleDefItem::Union(decl) => self.union_decl(decl),
+            ModuleDefItem::Enum(decl) => self.enum_decl(decl),
+            ModuleDefItem::Type(decl) => self.type_decl(decl),
+            ModuleDefItem::Trait(decl) => self.trait_decl(decl),
+            ModuleDefItem::Module(module) => self.module(module),
+            ModuleDefItem::ExternalModule(name) => self.write(&format!("mod {};", name.name)),
+            ModuleDefItem::Macro(def) => self.verbatim(def.span),
+            ModuleDefItem::Import(import) => {
+                self.attributes(&import.attributes);
+                let symbols = match import.glob {
+                    Some(_) => "*".to_string(),
+                    None => import
+                        .symbols
+                        .iter()
+                        .map(|x| match &x.alias {
+                            Some(alias) => format!("{} as {}", x.name.name, alias.name),
+                            None => x.name.name.clone(),
+                        })
+                        .join(", "),
+                };
+                self.write(&format!(
+                    "import {}.{{{}}};",
+                    import.module.iter().map(|x| &x.name).join("."),
+                    symbols
+                ))
+            }
+        }
+    }
+
+    fn constant(&mut self, constant: &ConstantDef) {
+        let decl = &constant.decl;
+        self.doc_string(&decl.doc_string);
+        self.attributes(&decl.attributes);
+        if decl.is_pub {
+            self.write("pub ");
+        }
+        self.write(&format!(
+            "const {}: {} = ",
+            decl.name.name,
+            type_descriptor(&decl.r#type)
+        ));
+        self.expression(&constant.value, ANY_LEVEL);
+        self.write(";");
+    }
+
+    fn function_decl(&mut self, decl: &FunctionDecl) {
+        self.signature(decl, decl.is_extern);
+    }
+
+    /// The functions of an extern block are extern already, without the keyword.
+    fn signature(&mut self, decl: &FunctionDecl, is_extern: bool) {
+        self.doc_string(&decl.doc_string);
+        self.attributes(&decl.attributes);
+        if decl.is_pub {
+            self.write("pub ");
+        }
+        if decl.is_const {
+            self.write("const ");
+        }
+        if decl.is_unsafe {
+            self.write("unsafe ");
+        }
+        if decl.is_async {
+            self.write("async ");
+        }
+        if is_extern {
+            self.write("extern ");
+        }
+        self.write(&format!(
+            "fn {}{}({})",
+            decl.name.name,
+            generic_params(&decl.generic_params),
+            decl.params.iter().map(param).join(", ")
+        ));
+        if let Some(ret_type) = &decl.ret_type {
+            self.write(&format!(" -> {}", type_descriptor(ret_type)));
+        }
+    }
+
+    fn function(&mut self, function: &FunctionDef) {
+        self.function_decl(&function.decl);
+        self.write(" ");
+        self.statements(&function.body, function.span.to);
+    }
+
+    fn impl_block(&mut self, block: &ImplBlock) {
+        self.attributes(&block.attributes);
+        self.write(&format!(
+            "impl{} {} ",
+            generic_params(&block.generic_params),
+            type_descriptor(&block.target)
+        ));
+        self.block(
+            &block.methods,
+            block.span.to,
+            |x| x.span.from,
+            |printer, x| printer.function(x),
+        );
+    }
+
+    fn extern_block(&mut self, block: &ExternBlock) {
+        self.attributes(&block.attributes);
+        self.write(&format!("extern {:?} ", block.abi));
+        self.block(
+            &block.functions,
+            block.span.to,
+            |x| x.span.from,
+            |printer, x| {
+                printer.signature(x, false);
+                printer.write(";");
+            },
+        );
+    }
+
+    fn impl_trait_block(&mut self, block: &ImplTraitBlock) {
+        self.attributes(&block.attributes);
+        self.write(&format!(
+            "impl{} {} for {} ",
+            generic_params(&block.generic_params),
+            type_name(&block.target_trait, false),
+            type_descriptor(&block.target)
+        ));
+
+        let items: Vec<_> = block
+            .associated_types
+            .iter()
+            .map(|x| ImplItem::Type(x))
+            .chain(block.methods.iter().map(|x| ImplItem::Method(x)))
+            .collect();
+        self.block(
+            &items,
+            block.span.to,
+            |x| match x {
+                ImplItem::Type(x) => x.span.from,
+                ImplItem::Method(x) => x.span.from,
+            },
+            |printer, x| match x {
+                ImplItem::Type(x) => printer.type_decl(x),
+                ImplItem::Method(x) => printer.function(x),
+            },
+        );
+    }
+
+    fn fields(&mut self, fields: &[Field], end: usize) {
+        self.block(
+            fields,
+            end,
+            |x| x.span.from,
+            |printer, x| {
+                printer.attributes(&x.attributes);
+                if x.is_pub {
+                    printer.write("pub ");
+                }
+                printer.write(&format!("{}: {}", x.name.name, type_descriptor(&x.r#type)));
+                if let Some(default) = &x.default {
+                    printer.write(" = ");
+                    printer.expression(default, ANY_LEVEL);
+                }
+                printer.write(",");
+            },
+        );
+    }
+
+    fn struct_decl(&mut self, decl: &StructDecl) {
+        self.doc_string(&decl.doc_string);
+        self.attributes(&decl.attributes);
+        if decl.is_pub {
+            self.write("pub ");
+        }
+        self.write(&format!(
+            "struct {}{} ",
+            decl.name.name,
+            generic_params(&decl.generics)
+        ));
+        self.fields(&decl.fields, decl.span.to);
+    }
+
+    fn union_decl(&mut self, decl: &UnionDecl) {
+        self.attributes(&decl.attributes);
+        self.write(&format!(
+            "union {}{} ",
+            decl.name.name,
+            generic_params(&decl.generics)
+        ));
+        self.fields(&decl.variants, decl.span.to);
+    }
+
+    fn enum_decl(&mut self, decl: &EnumDecl) {
+        self.attributes(&decl.attributes);
+        if decl.is_pub {
+            self.write("pub ");
+        }
+        self.write(&format!(
+            "enum {}{} ",
+            decl.name.name,
+            generic_params(&decl.generics)
+        ));
+        self.block(
+            &decl.variants,
+            decl.span.to,
+            |x| x.span.from,
+            |printer, x| {
+                printer.write(&x.name.name);
+                if !x.fields.is_empty() {
+                    printer.write(" ");
+                    printer.fields(&x.fields, x.span.to);
+                }
+                if let Some(discriminant) = &x.discriminant {
+                    printer.write(" = ");
+                    printer.expression(discriminant, ANY_LEVEL);
+                }
+                printer.write(",");
+            },
+        );
+    }
+
+    fn type_decl(&mut self, decl: &TypeDecl) {
+        self.doc_string(&decl.doc_string);
+        self.attributes(&decl.attributes);
+        if decl.is_pub {
+            self.write("pub ");
+        }
+        self.write(&format!(
+            "type {} = {};",
+            decl.name.name,
+            type_descriptor(&decl.value)
+        ));
+    }
+
+    fn trait_decl(&mut self, decl: &TraitDecl) {
+        self.doc_string(&decl.doc_string);
+        self.attributes(&decl.attributes);
+        if decl.is_pub {
+            self.write("pub ");
+        }
+        self.write(&format!(
+            "trait {}{} ",
+            decl.name.name,
+            generic_params(&decl.generic_params)
+        ));
+
+        let items: Vec<_> = decl
+            .associated_types
+            .iter()
+            .map(|x| TraitItem::Type(x))
+            .chain(decl.methods.iter().map(|x| TraitItem::Method(x)))
+            .collect();
+        self.block(
+            &items,
+            decl.span.to,
+            |x| match x {
+                TraitItem::Type(x) => x.span.from,
+                TraitItem::Method(x) => x.span.from,
+            },
+            |printer, x| match x {
+                TraitItem::Type(x) => printer.associated_type(x),
+                TraitItem::Method(x) => {
+                    printer.function_decl(x);
+                    printer.write(";");
+                }
+            },
+        );
+    }
+
+    fn associated_type(&mut self, decl: &AssociatedType) {
+        self.doc_string(&decl.doc_string);
+        match &decl.value {
+            Some(value) => self.write(&format!(
+                "type {} = {};",
+                decl.name.name,
+                type_descriptor(value)
+            )),
+            None => self.write(&format!("type {};", decl.name.name)),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assign(assign) => {
+                self.assign(assign);
+                self.write(";");
+            }
+            Statement::Match(expr) => self.match_expr(expr),
+            Statement::For(stmt) => self.for_stmt(stmt),
+            Statement::ForIn(stmt) => self.for_in_stmt(stmt),
+            Statement::If(expr) => self.if_expr(expr),
+            Statement::Let(stmt) => {
+                self.let_stmt(stmt);
+                self.write(";");
+            }
+            Statement::Return(stmt) => match &stmt.value {
+                Some(value) => {
+                    self.write("return ");
+                    self.expression(value, ANY_LEVEL);
+                    self.write(";");
+                }
+                None => self.write("return;"),
+            },
+            Statement::Become(stmt) => {
+                self.write("become ");
+                self.expression(&stmt.value, ANY_LEVEL);
+                self.write(";");
+            }
+            Statement::While(stmt) => self.while_stmt(stmt),
+            Statement::Defer(stmt) => self.defer_stmt(stmt),
+            Statement::Unsafe(stmt) => {
+                self.write("unsafe ");
+                self.statements(&stmt.block_stmts, stmt.span.to);
+            }
+            Statement::FnCall(call) => {
+                self.fn_call(call);
+                self.write(";");
+            }
+            Statement::MacroCall(call) => {
+                self.verbatim(call.span);
+                self.write(";");
+            }
+            Statement::PathOp(path) => {
+                self.path(path);
+                self.write(";");
+            }
+        }
+    }
+
+    fn let_stmt(&mut self, stmt: &LetStmt) {
+        self.write("let ");
+        if stmt.is_mutable {
+            self.write("mut ");
+        }
+        match &stmt.target {
+            LetStmtTarget::Simple { id, r#type } => {
+                self.write(&format!("{}: {} = ", id.name, type_descriptor(r#type)));
+            }
+            LetStmtTarget::Destructure(target) => {
+                self.write(&format!("{} = ", pattern(target)));
+            }
+        }
+        self.rvalue(&stmt.value);
+    }
+
+    fn assign(&mut self, assign: &AssignStmt) {
+        self.write(&"*".repeat(assign.derefs));
+        self.path(&assign.lvalue);
+        self.write(" = ");
+        self.rvalue(&assign.rvalue);
+    }
+
+    fn while_stmt(&mut self, stmt: &WhileStmt) {
+        self.write("while ");
+        self.expression(&stmt.condition, ANY_LEVEL);
+        self.write(" ");
+        self.statements(&stmt.block_stmts, stmt.span.to);
+    }
+
+    fn defer_stmt(&mut self, stmt: &DeferStmt) {
+        self.write("defer ");
+        match stmt.block_stmts.as_slice() {
+            // Without braces the defer ends with its call.
+            [Statement::FnCall(call)] if call.span.to == stmt.span.to => {
+                self.fn_call(call);
+                self.write(";");
+            }
+            [Statement::PathOp(path)] if path.span.to == stmt.span.to => {
+                self.path(path);
+                self.write(";");
+            }
+            stmts => self.statements(stmts, stmt.span.to),
+        }
+    }
+
+    fn for_stmt(&mut self, stmt: &ForStmt) {
+        self.write("for ");
+
+        match (&stmt.init, &stmt.condition, &stmt.post) {
+            (None, None, None) => {}
+            (None, Some(condition), None) => {
+                self.write("(");
+                self.expression(condition, ANY_LEVEL);
+                self.write(") ");
+            }
+            (init, condition, post) => {
+                self.write("(");
+                if let Some(init) = init {
+                    self.let_stmt(init);
+                }
+                self.write(";");
+                if let Some(condition) = condition {
+                    self.write(" ");
+                    self.expression(condition, ANY_LEVEL);
+                }
+                self.write(";");
+                if let Some(post) = post {
+                    self.write(" ");
+                    self.assign(post);
+                }
+                self.write(") ");
+            }
+        }
+
+        self.statements(&stmt.block_stmts, stmt.span.to);
+    }
+
+    fn for_in_stmt(&mut self, stmt: &ForInStmt) {
+        self.write(&format!("for {} in ", stmt.name.name));
+        match &stmt.iterable {
+            ForIterable::Range { start, end } => {
+                self.expression(start, ANY_LEVEL);
+                self.write("..");
+                self.expression(end, ANY_LEVEL);
+            }
+            ForIterable::Value(value) => self.expression(value, ANY_LEVEL),
+        }
+        self.write(" ");
+        self.statements(&stmt.block_stmts, stmt.span.to);
+    }
+
+    fn if_expr(&mut self, expr: &IfExpr) {
+        self.write("if ");
+        self.expression(&expr.cond, ANY_LEVEL);
+        self.write(" ");
+
+        match &expr.else_stmts {
+            Some(else_stmts) => {
+                let last = expr
+                    .block_stmts
+                    .last()
+                    .map_or(expr.span.from, statement_end);
+                self.statements(&expr.block_stmts, self.close_brace(last));
+                self.write(" else ");
+                self.statements(else_stmts, expr.span.to);
+            }
+            None => self.statements(&expr.block_stmts, expr.span.to),
+        }
+    }
+
+    fn match_expr(&mut self, expr: &MatchExpr) {
+        self.write("match ");
+        self.expression(&expr.expr, ANY_LEVEL);
+        self.write(" ");
+        self.block(
+            &expr.variants,
+            expr.span.to,
+            |x| x.span.from,
+            |printer, variant| {
+                match &variant.case {
+                    MatchCaseExpr::Value(value) => printer.value(value),
+                    MatchCaseExpr::Enum(case) => {
+                        printer.write(&format!(
+                            "{}#{}",
+                            type_name(&case.name, true),
+                            case.variant.name
+                        ));
+                        if !case.field_values.is_empty() {
+                            printer.write(&format!(" {}", field_patterns(&case.field_values)));
+                        }
+                    }
+                }
+                printer.write(" => ");
+                printer.statements(&variant.block, variant.span.to);
+                printer.write(",");
+            },
+        );
+    }
+
+    /// Writes the value of a let or an assignment, which can be a struct or enum initialization
+    /// or an associated method call without parentheses.
+    fn rvalue(&mut self, value: &Expression) {
+        match value {
+            Expression::StructInit(init) => {
+                self.write(&format!("{} ", type_name(&init.name, true)));
+                let mut fields: Vec<_> = init.fields.iter().map(InitField::Field).collect();
+                fields.extend(init.base.as_deref().map(InitField::Base));
+                self.struct_init_fields(fields, init.span.to);
+            }
+            Expression::EnumInit(init) => {
+                self.write(&format!(
+                    "{}#{}",
+                    type_name(&init.name, true),
+                    init.variant.name
+                ));
+                if !init.fields.is_empty() {
+                    self.write(" ");
+                    let fields = init.fields.iter().map(InitField::Field).collect();
+                    self.struct_init_fields(fields, init.span.to);
+                }
+            }
+            Expression::AssocMethodCall(call) => {
+                self.write(&format!("{}#", type_name(&call.assoc_type, true)));
+                self.fn_call(&call.fn_call);
+            }
+            _ => self.expression(value, ANY_LEVEL),
+        }
+    }
+
+    fn struct_init_fields(&mut self, mut fields: Vec<InitField>, end: usize) {
+        // The fields are kept in a map, they're written in the order of the source.
+        fields.sort_by_key(InitField::start);
+
+        self.block(
+            &fields,
+            end,
+            InitField::start,
+            |printer, field| match field {
+                InitField::Field((name, field)) => {
+                    printer.write(&format!("{}: ", name.name));
+                    match &field.value {
+                        Expression::StructInit(_) => printer.rvalue(&field.value),
+                        value => printer.expression(value, ANY_LEVEL),
+                    }
+                    printer.write(",");
+                }
+                InitField::Base(base) => {
+                    printer.write("..");
+                    printer.expression(&base.value, ANY_LEVEL);
+                }
+            },
+        );
+    }
+
+    /// Writes the expression, in parentheses if it binds looser than the given precedence level.
+    fn expression(&mut self, expr: &Expression, level: u8) {
+        if precedence(expr) > level {
+            self.write("(");
+            self.expression(expr, ANY_LEVEL);
+            self.write(")");
+            return;
+        }
+
+        match expr {
+            Expression::Value(value, _) => self.value(value),
+            Expression::FnCall(call) => self.fn_call(call),
+            Expression::MacroCall(call) => self.verbatim(call.span),
+            Expression::Match(expr) => self.match_expr(expr),
+            Expression::If(expr) => self.if_expr(expr),
+            Expression::UnaryOp(op, operand) => {
+                self.write(match op {
+                    UnaryOp::ArithNeg => "-",
+                    UnaryOp::LogicalNot => "!",
+                    UnaryOp::BitwiseNot => "~",
+                });
+                self.expression(operand, 1);
+            }
+            Expression::BinaryOp(lhs, op, rhs) => {
+                let level = precedence(expr);
+                self.expression(lhs, level);
+                self.write(&format!(" {} ", binary_op(*op)));
+                self.expression(rhs, level - 1);
+            }
+            Expression::Deref(operand, _) => {
+                self.write("*");
+                self.expression(operand, 1);
+            }
+            Expression::Await(operand, _) => {
+                self.write("await ");
+                self.expression(operand, 1);
+            }
+            Expression::AsRef(operand, is_mut, _) => {
+                self.write(if *is_mut { "&mut " } else { "&" });
+                // `&&` is the logical and.
+                if !is_mut && matches!(**operand, Expression::AsRef(..)) {
+                    self.write(" ");
+                }
+                self.expression(operand, 1);
+            }
+            Expression::Cast(value, ty, _) => {
+                // Only unary operations are written without parentheses, for readability.
+                self.expression(value, 1);
+                self.write(&format!(" as {}", type_descriptor(ty)));
+            }
+            Expression::StructInit(_) | Expression::AssocMethodCall(_) => {
+                self.write("(");
+                self.rvalue(expr);
+                self.write(")");
+            }
+            Expression::EnumInit(_) => self.rvalue(expr),
+            Expression::ArrayInit(init) => {
+                self.write("[");
+                for (i, value) in init.values.iter().enumerate() {
+                    if i > 0 {
+                        self.write(", ");
+                    }
+                    self.expression(value, ANY_LEVEL);
+                }
+                self.write("]");
+            }
+            Expression::Tuple(values, _) => {
+                self.write("(");
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        self.write(", ");
+                    }
+                    self.expression(value, ANY_LEVEL);
+                }
+                if values.len() == 1 {
+                    self.write(",");
+                }
+                self.write(")");
+            }
+            Expression::Try(value, _) => {
+                self.expression(value, 0);
+                self.write("?");
+            }
+        }
+    }
+
+    fn value(&mut self, value: &ValueExpr) {
+        match value {
+            ValueExpr::ConstBool(value, _) => self.write(&value.to_string()),
+            ValueExpr::ConstChar(value, _) => self.write(&quote(&value.to_string(), '\'')),
+            ValueExpr::ConstInt(value, _) => self.write(&value.to_string()),
+            ValueExpr::ConstFloat(value, _) => self.write(value),
+            ValueExpr::ConstStr(value, _) => self.write(&quote(value, '"')),
+            ValueExpr::Path(path) => self.path(path),
+        }
+    }
+
+    fn path(&mut self, path: &PathOp) {
+        self.write(&path.first.name);
+
+        for segment in &path.extra {
+            match segment {
+                PathSegment::FieldAccess(name, _) => self.write(&format!(".{}", name.name)),
+                PathSegment::ArrayIndex(value, _) => {
+                    self.write("[");
+                    self.value(value);
+                    self.write("]");
+                }
+                PathSegment::Slice(start, end, _) => {
+                    self.write("[");
+                    if let Some(start) = start {
+                        self.value(start);
+                    }
+                    self.write("..");
+                    if let Some(end) = end {
+                        self.value(end);
+                    }
+                    self.write("]");
+                }
+                PathSegment::MethodCall(call, _) => {
+                    self.write(".");
+                    self.fn_call(call);
+                }
+            }
+        }
+    }
+
+    fn fn_call(&mut self, call: &FnCallOp) {
+        for segment in &call.path {
+            self.write(&format!("{}::", segment.name));
+        }
+        self.write(&call.target.name);
+        if !call.generics.is_empty() {
+            self.write(&format!(
+                "::<{}>",
+                call.generics.iter().map(|x| type_name(x, false)).join(", ")
+            ));
+        }
+
+        self.write("(");
+        for (i, arg) in call.args.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.expression(arg, ANY_LEVEL);
+        }
+        self.write(")");
+    }
+}
+
+enum ImplItem<'a> {
+    Type(&'a TypeDecl),
+    Method(&'a FunctionDef),
+}
+
+/// A field of a struct initialization, or the `..base` after them.
+enum InitField<'a> {
+    Field((&'a Ident, &'a StructInitField)),
+    Base(&'a StructInitField),
+}
+
+impl InitField<'_> {
+    fn start(&self) -> usize {
+        match self {
+            InitField::Field((_, field)) | InitField::Base(field) => field.span.from,
+        }
+    }
+}
+
+enum TraitItem<'a> {
+    Type(&'a AssociatedType),
+    Method(&'a FunctionDecl),
+}
+
+fn item_start(item: &ModuleDefItem) -> usize {
+    match item {
+        ModuleDefItem::Constant(constant) => {
+            let decl = &constant.decl;
+            match (&decl.doc_string, decl.attributes.first()) {
+                (Some(doc_string), _) => doc_string.span.from,
+                (None, Some(attribute)) => attribute.span.from,
+                (None, None) => decl.name.span.from,
+            }
+        }
+        ModuleDefItem::Function(function) => function.span.from,
+        ModuleDefItem::FunctionDecl(decl) => decl.span.from,
+        ModuleDefItem::Impl(block) => block.span.from,
+        ModuleDefItem::ImplTrait(block) => block.span.from,
+        ModuleDefItem::ExternBlock(block) => block.span.from,
+        ModuleDefItem::Struct(decl) => decl.span.from,
+        ModuleDefItem::Union(decl) => decl.span.from,
+        ModuleDefItem::Enum(decl) => decl.span.from,
+        ModuleDefItem::Type(decl) => decl.span.from,
+        ModuleDefItem::Trait(decl) => decl.span.from,
+        ModuleDefItem::Module(module) => module.span.from,
+        ModuleDefItem::ExternalModule(name) => name.span.from,
+        ModuleDefItem::Import(import) => import.span.from,
+        ModuleDefItem::Macro(def) => def.span.from,
+    }
+}
+
+fn statement_start(statement: &Statement) -> usize {
+    match statement {
+        Statement::Assign(stmt) => stmt.span.from,
+        Statement::Match(expr) => expr.span.from,
+        Statement::For(stmt) => stmt.span.from,
+        Statement::ForIn(stmt) => stmt.span.from,
+        Statement::If(expr) => expr.span.from,
+        Statement::Let(stmt) => stmt.span.from,
+        Statement::Return(stmt) => stmt.span.from,
+        Statement::Become(stmt) => stmt.span.from,
+        Statement::While(stmt) => stmt.span.from,
+        Statement::Defer(stmt) => stmt.span.from,
+        Statement::Unsafe(stmt) => stmt.span.from,
+        Statement::FnCall(call) => call.span.from,
+        Statement::PathOp(path) => path.span.from,
+        Statement::MacroCall(call) => call.span.from,
+    }
+}
+
+fn statement_end(statement: &Statement) -> usize {
+    match statement {
+        Statement::Assign(stmt) => stmt.span.to,
+        Statement::Match(expr) => expr.span.to,
+        Statement::For(stmt) => stmt.span.to,
+        Statement::ForIn(stmt) => stmt.span.to,
+        Statement::If(expr) => expr.span.to,
+        Statement::Let(stmt) => stmt.span.to,
+        Statement::Return(stmt) => stmt.span.to,
+        Statement::Become(stmt) => stmt.span.to,
+        Statement::While(stmt) => stmt.span.to,
+        Statement::Defer(stmt) => stmt.span.to,
+        Statement::Unsafe(stmt) => stmt.span.to,
+        Statement::FnCall(call) => call.span.to,
+        Statement::PathOp(path) => path.span.to,
+        Statement::MacroCall(call) => call.span.to,
+    }
+}
+
+/// The precedence level of the expression in the grammar, lower levels bind tighter.
+fn precedence(expr: &Expression) -> u8 {
+    match expr {
+        Expression::Value(..)
+        | Expression::FnCall(_)
+        | Expression::Match(_)
+        | Expression::If(_)
+        | Expression::EnumInit(_)
+        | Expression::Tuple(..)
+        | Expression::Try(..)
+        | Expression::MacroCall(_) => 0,
+        Expression::UnaryOp(..)
+        | Expression::Deref(..)
+        | Expression::AsRef(..)
+        | Expression::Await(..) => 1,
+        Expression::BinaryOp(_, op, _) => match op {
+            BinaryOp::Compare(_) | BinaryOp::Logic(_) => 2,
+            BinaryOp::Arith(ArithOp::Mul | ArithOp::Div | ArithOp::Mod) => 3,
+            BinaryOp::Arith(ArithOp::Add | ArithOp::Sub) | BinaryOp::Bitwise(_) => 4,
+        },
+        Expression::Cast(..)
+        | Expression::StructInit(_)
+        | Expression::AssocMethodCall(_)
+        | Expression::ArrayInit(_) => ANY_LEVEL,
+    }
+}
+
+fn binary_op(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Arith(op) => match op {
+            ArithOp::Add => "+",
+            ArithOp::Sub => "-",
+            ArithOp::Mul => "*",
+            ArithOp::Div => "/",
+            ArithOp::Mod => "%",
+        },
+        BinaryOp::Logic(op) => match op {
+            LogicOp::And => "&&",
+            LogicOp::Or => "||",
+        },
+        BinaryOp::Compare(op) => match op {
+            CmpOp::Eq => "==",
+            CmpOp::NotEq => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::LtEq => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::GtEq => ">=",
+        },
+        BinaryOp::Bitwise(op) => match op {
+            BitwiseOp::And => "&",
+            BitwiseOp::Or => "|",
+            BitwiseOp::Xor => "^",
+        },
+    }
+}
+
+/// A type name, with `::` before its generics in expressions, where `<` would be a comparison.
+fn type_name(name: &TypeName, in_expression: bool) -> String {
+    let mut text = String::new();
+    for segment in &name.path {
+        text.push_str(&segment.name);
+        text.push_str("::");
+    }
+    text.push_str(&name.name.name);
+
+    if !name.generics.is_empty() {
+        if in_expression {
+            text.push_str("::");
+        }
+        text.push_str(&format!(
+            "<{}>",
+            name.generics.iter().map(|x| type_name(x, false)).join(", ")
+        ));
+    }
+
+    text
+}
+
+fn type_descriptor(ty: &TypeDescriptor) -> String {
+    match ty {
+        TypeDescriptor::Type { name, .. } => type_name(name, false),
+        // `&&` is the logical and.
+        TypeDescriptor::Ref { of, .. } => match **of {
+            TypeDescriptor::Ref { .. } | TypeDescriptor::MutRef { .. } => {
+                format!("& {}", type_descriptor(of))
+            }
+            _ => format!("&{}", type_descriptor(of)),
+        },
+        TypeDescriptor::MutRef { of, .. } => format!("&mut {}", type_descriptor(of)),
+        TypeDescriptor::ConstPtr { of, .. } => format!("*const {}", type_descriptor(of)),
+        TypeDescriptor::MutPtr { of, .. } => format!("*mut {}", type_descriptor(of)),
+        TypeDescriptor::Array { of, size, .. } => {
+            format!("[{}; {}]", type_descriptor(of), array_size(size))
+        }
+        TypeDescriptor::Slice { of, .. } => format!("[{}]", type_descriptor(of)),
+        TypeDescriptor::Tuple { of, .. } => match of.as_slice() {
+            [single] => format!("({},)", type_descriptor(single)),
+            of => format!("({})", of.iter().map(type_descriptor).join(", ")),
+        },
+        TypeDescriptor::SelfType { .. } => ty.to_string(),
+    }
+}
+
+fn array_size(size: &ArraySize) -> String {
+    match size {
+        ArraySize::Literal(size) => size.to_string(),
+        ArraySize::Constant(expr) => {
+            let mut printer = Printer::new("");
+            printer.expression(expr, ANY_LEVEL);
+            printer.out
+        }
+    }
+}
+
+fn generic_params(params: &[GenericParam]) -> String {
+    if params.is_empty() {
+        return String::new();
+    }
+
+    let mut params = params.iter().map(|param| {
+        if param.bounds.is_empty() {
+            param.name.name.clone()
+        } else {
+            format!(
+                "{}: {}",
+                param.name.name,
+                param.bounds.iter().map(|x| type_name(x, false)).join(" + ")
+            )
+        }
+    });
+
+    format!("<{}>", params.join(", "))
+}
+
+fn param(param: &Param) -> String {
+    // The attributes of a parameter are written on its line.
+    let attributes: String = param
+        .attributes
+        .iter()
+        .map(|x| format!("{} ", attribute(x)))
+        .collect();
+
+    let param = match (&param.r#type, &param.pattern) {
+        (TypeDescriptor::SelfType { .. }, _) => param.r#type.to_string(),
+        (ty, Some(target)) => format!("{}: {}", pattern(target), type_descriptor(ty)),
+        (ty, None) => format!("{}: {}", param.name.name, type_descriptor(ty)),
+    };
+
+    attributes + &param
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Binding(name) => name.name.clone(),
+        Pattern::Struct { name, fields, .. } => {
+            format!("{} {}", type_name(name, true), field_patterns(fields))
+        }
+        Pattern::Variant {
+            name,
+            variant,
+            fields,
+            ..
+        } if fields.is_empty() => format!("{}#{}", type_name(name, true), variant.name),
+        Pattern::Variant {
+            name,
+            variant,
+            fields,
+            ..
+        } => format!(
+            "{}#{} {}",
+            type_name(name, true),
+            variant.name,
+            field_patterns(fields)
+        ),
+    }
+}
+
+/// The fields in braces, the ones bound to their own name are written in short.
+fn field_patterns(fields: &[FieldPattern]) -> String {
+    if fields.is_empty() {
+        return "{}".to_string();
+    }
+
+    let fields = fields
+        .iter()
+        .map(|x| match &x.pattern {
+            Pattern::Binding(name) if name.name == x.name.name => name.name.clone(),
+            target => format!("{}: {}", x.name.name, pattern(target)),
+        })
+        .join(", ");
+    format!("{{ {fields} }}")
+}
+
+fn attribute(attribute: &Attribute) -> String {
+    match &attribute.value {
+        Some(value) => format!("#[{} = {}]", attribute.name, quote(value, '"')),
+        None if !attribute.args.is_empty() => format!(
+            "#[{}({})]",
+            attribute.name,
+            attribute.args.iter().map(attribute_arg).join(", ")
+        ),
+        None => format!("#[{}]", attribute.name),
+    }
+}
+
+fn attribute_arg(arg: &AttributeArg) -> String {
+    match arg {
+        AttributeArg::Word(name) => name.name.clone(),
+        AttributeArg::NameValue { name, value, .. } => {
+            format!("{} = {}", name.name, quote(value, '"'))
+        }
+        AttributeArg::List { name, args, .. } => {
+            format!(
+                "{}({})",
+                name.name,
+                args.iter().map(attribute_arg).join(", ")
+            )
+        }
+    }
+}
+
+/// Quotes a string or char literal, escaping what the lexer unescaped.
+fn quote(value: &str, quote: char) -> String {
+    let mut text = String::from(quote);
+
+    for c in value.chars() {
+        match c {
+            '\\' => text.push_str("\\\\"),
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            '\t' => text.push_str("\\t"),
+            '\0' => text.push_str("\\0"),
+            c if c == quote => {
+                text.push('\\');
+                text.push(c);
+            }
+            c => text.push(c),
+        }
+    }
+
+    text.push(quote);
+    text
+}
@@ -1,10 +1,12 @@
 use std::path::{Path, PathBuf};
 
-use crate::ast::CompilationUnit;
-use error::Diagnostic;
+use crate::ast::{CompilationUnit, statements::Statement};
+use error::{SourceError, SyntaxError};
 use lexer::Lexer;
+use tokens::Token;
 
 pub mod error;
+pub mod format;
 mod lexer;
 pub mod tokens;
 
@@ -31,14 +33,78 @@ impl ProgramSource {
     }
 }
 
+/// The UTF-8 byte order mark some editors add at the start of files.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// How many bytes are checked for null bytes to tell binary files apart, the same heuristic git uses.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Reads a source file, which must be UTF-8 text.
+///
+/// A byte order mark is replaced with spaces instead of being removed, so the spans still point to
+/// the same bytes of the file.
+pub fn read_source(path: &Path) -> Result<String, SourceError> {
+    let mut bytes = std::fs::read(path).map_err(|error| SourceError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    if bytes.starts_with(UTF8_BOM) {
+        bytes[..UTF8_BOM.len()].fill(b' ');
+    }
+
+    if let Some(offset) = bytes.iter().take(BINARY_CHECK_LEN).position(|x| *x == 0) {
+        return Err(SourceError::Binary {
+            path: path.to_path_buf(),
+            offset,
+        });
+    }
+
+    String::from_utf8(bytes).map_err(|error| {
+        let offset = error.utf8_error().valid_up_to();
+        let bytes = error.into_bytes();
+        SourceError::InvalidUtf8 {
+            path: path.to_path_buf(),
+            offset,
+            byte: bytes[offset],
+            lossy: String::from_utf8_lossy(&bytes).into_owned(),
+            lossy_offset: String::from_utf8_lossy(&bytes[..offset]).len(),
+        }
+    })
+}
+
 // Todo: better error handling
-pub fn parse_ast(source: &ProgramSource) -> Result<CompilationUnit, Diagnostic> {
+pub fn parse_ast(source: &ProgramSource) -> Result<CompilationUnit, SyntaxError> {
     let lexer = Lexer::new(&source.input);
     let parser = grammar::CompilationUnitParser::new();
 
     match parser.parse(&source.path, lexer) {
         Ok(ast) => Ok(ast),
-        Err(e) => Err(Diagnostic(e)),
+        Err(e) => Err(SyntaxError(e)),
+    }
+}
+
+/// Parses a list of statements, like the code blocks of doc comments.
+pub fn parse_statements(source: &ProgramSource) -> Result<Vec<Statement>, SyntaxError> {
+    let lexer = Lexer::new(&source.input);
+    let parser = grammar::StatementsParser::new();
+
+    match parser.parse(&source.path, lexer) {
+        Ok(statements) => Ok(statements),
+        Err(e) => Err(SyntaxError(e)),
+    }
+}
+
+/// Parses a list of statements from tokens instead of source, like the expansions of macros.
+pub fn parse_token_statements(
+    path: &Path,
+    tokens: Vec<(usize, Token, usize)>,
+) -> Result<Vec<Statement>, SyntaxError> {
+    let parser = grammar::StatementsParser::new();
+
+    match parser.parse(path, tokens.into_iter().map(Ok)) {
+        Ok(statements) => Ok(statements),
+        Err(e) => Err(SyntaxError(e)),
     }
 }
 
@@ -379,6 +445,11 @@ mod MyMod {
     pub fn my_func(x: u64) -> u64 {
         return x + 1;
     }
+
+    /// A point.
+    pub struct Point {
+        x: u64,
+    }
 }
 "##;
         let lexer = Lexer::new(source);
@@ -393,6 +464,10 @@ mod MyMod {
             ast::modules::ModuleDefItem::Function(x) => x,
             _ => unreachable!(),
         };
+        let struct_item = match &module.modules[0].contents[2] {
+            ast::modules::ModuleDefItem::Struct(x) => x,
+            _ => unreachable!(),
+        };
 
         assert_eq!(
             module.modules[0].doc_string,
@@ -419,5 +494,12 @@ mod MyMod {
                 span: ast::common::Span::new(147, 170),
             }),
         );
+        assert_eq!(
+            struct_item.doc_string,
+            Some(ast::common::DocString {
+                contents: vec![" A point.".to_string()],
+                span: ast::common::Span::new(240, 252),
+            }),
+        );
     }
 }
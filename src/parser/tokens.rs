@@ -1,4 +1,5 @@
 use logos::Logos;
+use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 
 #[derive(Debug, PartialEq, Clone, Default)]
@@ -20,7 +21,16 @@ impl From<Infallible> for LexingError {
     }
 }
 
-#[derive(Logos, logos_display::Debug, logos_display::Display, PartialEq, Clone)]
+#[derive(
+    Logos,
+    logos_display::Debug,
+    logos_display::Display,
+    PartialEq,
+    Eq,
+    Clone,
+    Serialize,
+    Deserialize,
+)]
 #[logos(error = LexingError, skip r"[ \t\n\f]+", skip r"//[^/][^\n]*", skip r"/\*(?:[^*]|\*[^/])*\*/")]
 pub enum Token {
     #[token("let")]
@@ -31,6 +41,8 @@ pub enum Token {
     KeywordFn,
     #[token("return")]
     KeywordReturn,
+    #[token("become")]
+    KeywordBecome,
     #[token("struct")]
     KeywordStruct,
     #[token("union")]
@@ -49,8 +61,18 @@ pub enum Token {
     KeywordElse,
     #[token("while")]
     KeywordWhile,
+    #[token("defer")]
+    KeywordDefer,
+    #[token("unsafe")]
+    KeywordUnsafe,
+    #[token("async")]
+    KeywordAsync,
+    #[token("await")]
+    KeywordAwait,
     #[token("for")]
     KeywordFor,
+    #[token("in")]
+    KeywordIn,
     #[token("match")]
     KeywordMatch,
     #[token("mod")]
@@ -67,6 +89,8 @@ pub enum Token {
     KeywordAs,
     #[token("self")]
     KeywordSelf,
+    #[token("macro")]
+    KeywordMacro,
 
     // Modern way of allowing identifiers, read: https://unicode.org/reports/tr31/
     #[regex(r"[\p{XID_Start}_]\p{XID_Continue}*", |lex| lex.slice().to_string())]
@@ -121,8 +145,14 @@ pub enum Token {
     Coma,
     #[token(".")]
     Dot,
+    #[token("..")]
+    DoubleDot,
     #[token("#")]
     Hashtag,
+    #[token("$")]
+    Dollar,
+    #[token("?")]
+    Question,
     #[token("<")]
     LessThanSign,
     #[token(">")]
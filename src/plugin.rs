@@ -0,0 +1,294 @@
+//! Compiler plugins, dynamic libraries listed in the `[plugins]` table of `Concrete.toml` that add
+//! lints over the AST and the IR, and passes transforming the IR before code generation, so
+//! projects can enforce their own rules without forking the compiler.
+//!
+//! A plugin is a `cdylib` crate depending on this one, which registers its lints and passes with
+//! [`declare_plugin!`]:
+//!
+//! ```ignore
+//! struct NoExit;
+//!
+//! impl concrete::plugin::LintPass for NoExit {
+//!     fn name(&self) -> &str {
+//!         "no_exit"
+//!     }
+//!
+//!     fn check_ir(&self, ir: &IR, cx: &mut LintContext) {
+//!         // ...
+//!     }
+//! }
+//!
+//! concrete::declare_plugin!(|registry| registry.register_lint(NoExit));
+//! ```
+//!
+//! Plugins share the types of the compiler, so they must be built with the same version of it and
+//! of rustc.
+
+use std::{fmt, path::Path};
+
+use anyhow::{Context, Result, bail};
+use libloading::Library;
+
+use crate::{
+    ast::{CompilationUnit, common::Span},
+    check::{
+        diagnostics::Diagnostic,
+        lints::{LintLevel, LintLevels, lint_diagnostic},
+        suggestions::Suggestion,
+    },
+    ir::IR,
+};
+
+/// Bumped whenever the types plugins see change, plugins built for another version are rejected.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+/// The static holding the [`PLUGIN_API_VERSION`] a plugin was built for.
+pub const API_VERSION_SYMBOL: &[u8] = b"CONCRETE_PLUGIN_API_VERSION";
+
+/// The `fn(&mut PluginRegistry)` registering the lints and passes of a plugin.
+pub const REGISTER_SYMBOL: &[u8] = b"concrete_plugin_register";
+
+/// Exports the symbols the compiler looks for in a plugin, given the function registering its lints
+/// and passes.
+#[macro_export]
+macro_rules! declare_plugin {
+    ($register:expr) => {
+        #[unsafe(no_mangle)]
+        pub static CONCRETE_PLUGIN_API_VERSION: u32 = $crate::plugin::PLUGIN_API_VERSION;
+
+        #[unsafe(no_mangle)]
+        pub fn concrete_plugin_register(registry: &mut $crate::plugin::PluginRegistry) {
+            let register: fn(&mut $crate::plugin::PluginRegistry) = $register;
+            register(registry)
+        }
+    };
+}
+
+/// A lint added by a plugin, reported as `<plugin>::<name>`, which is also the name used to change
+/// its level with `-A`, `-W` and `-D`.
+pub trait LintPass: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn default_level(&self) -> LintLevel {
+        LintLevel::Warn
+    }
+
+    /// Checks the compile units once they are parsed, before lowering.
+    fn check_ast(&self, _units: &[CompilationUnit], _cx: &mut LintContext) {}
+
+    /// Checks the lowered program, before the passes of the plugins run.
+    fn check_ir(&self, _ir: &IR, _cx: &mut LintContext) {}
+}
+
+/// A pass transforming the IR before code generation, passes run in the order they are registered,
+/// and plugins in the order of their names.
+pub trait IrPass: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Transforms the program, an error stops the compilation.
+    fn run(&self, ir: &mut IR) -> Result<(), String>;
+}
+
+/// Where a plugin registers its lints and passes.
+#[derive(Default)]
+pub struct PluginRegistry {
+    lints: Vec<Box<dyn LintPass>>,
+    passes: Vec<Box<dyn IrPass>>,
+}
+
+impl PluginRegistry {
+    pub fn register_lint(&mut self, lint: impl LintPass + 'static) {
+        self.lints.push(Box::new(lint));
+    }
+
+    pub fn register_ir_pass(&mut self, pass: impl IrPass + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+}
+
+/// Something a lint found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Finding {
+    path: std::path::PathBuf,
+    span: Span,
+    message: String,
+    suggestion: Option<Suggestion>,
+}
+
+/// Collects what a lint finds while checking the program.
+#[derive(Debug, Default)]
+pub struct LintContext {
+    findings: Vec<Finding>,
+}
+
+impl LintContext {
+    pub fn report(&mut self, path: &Path, span: Span, message: impl fmt::Display) {
+        self.findings.push(Finding {
+            path: path.to_path_buf(),
+            span,
+            message: message.to_string(),
+            suggestion: None,
+        });
+    }
+
+    pub fn report_with_suggestion(
+        &mut self,
+        path: &Path,
+        span: Span,
+        message: impl fmt::Display,
+        suggestion: Suggestion,
+    ) {
+        self.findings.push(Finding {
+            path: path.to_path_buf(),
+            span,
+            message: message.to_string(),
+            suggestion: Some(suggestion),
+        });
+    }
+}
+
+struct Plugin {
+    name: String,
+    registry: PluginRegistry,
+}
+
+/// The plugins of a build.
+#[derive(Default)]
+pub struct Plugins {
+    // Declared before the libraries, so the lints and passes are dropped while their code is loaded.
+    plugins: Vec<Plugin>,
+    libraries: Vec<Library>,
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.plugins.iter().map(|x| &x.name))
+            .finish()
+    }
+}
+
+impl Plugins {
+    /// Adds a plugin whose lints and passes are already registered, like the ones built into a
+    /// tool using the compiler as a library.
+    pub fn add(&mut self, name: &str, registry: PluginRegistry) {
+        self.plugins.push(Plugin {
+            name: name.to_string(),
+            registry,
+        });
+    }
+
+    /// Loads the plugin in the given dynamic library.
+    pub fn load(&mut self, name: &str, path: &Path) -> Result<()> {
+        // SAFETY: loading a library runs its initializers, plugins are trusted like build scripts.
+        let library = unsafe { Library::new(path) }
+            .with_context(|| format!("failed to load plugin `{name}` from {}", path.display()))?;
+
+        // SAFETY: the symbol is the static exported by `declare_plugin!`.
+        let version = unsafe {
+            let version = library
+                .get::<*const u32>(API_VERSION_SYMBOL)
+                .with_context(|| {
+                    format!(
+                        "{} is not a concrete plugin, it must use `declare_plugin!`",
+                        path.display()
+                    )
+                })?;
+            **version
+        };
+
+        if version != PLUGIN_API_VERSION {
+            bail!(
+                "plugin `{name}` was built for version {version} of the plugin interface, but this compiler uses version {PLUGIN_API_VERSION}, rebuild it against this compiler"
+            );
+        }
+
+        let mut registry = PluginRegistry::default();
+
+        // SAFETY: the version matches, so the function has the signature `declare_plugin!` gives it.
+        unsafe {
+            let register = library
+                .get::<fn(&mut PluginRegistry)>(REGISTER_SYMBOL)
+                .with_context(|| format!("plugin `{name}` has no register function"))?;
+            register(&mut registry);
+        }
+
+        self.add(name, registry);
+        self.libraries.push(library);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Runs the lints of the plugins over the parsed compile units.
+    pub fn check_ast(&self, units: &[CompilationUnit], levels: &LintLevels) -> Vec<Diagnostic> {
+        self.check(levels, |lint, cx| lint.check_ast(units, cx))
+    }
+
+    /// Runs the lints of the plugins over the lowered program.
+    pub fn check_ir(&self, ir: &IR, levels: &LintLevels) -> Vec<Diagnostic> {
+        self.check(levels, |lint, cx| lint.check_ir(ir, cx))
+    }
+
+    fn check(
+        &self,
+        levels: &LintLevels,
+        run: impl Fn(&dyn LintPass, &mut LintContext),
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for plugin in &self.plugins {
+            for lint in &plugin.registry.lints {
+                let name = format!("{}::{}", plugin.name, lint.name());
+                let level = levels.plugin_level(&name, lint.default_level());
+
+                if level == LintLevel::Allow {
+                    continue;
+                }
+
+                let mut cx = LintContext::default();
+                run(lint.as_ref(), &mut cx);
+
+                diagnostics.extend(cx.findings.into_iter().map(|finding| {
+                    lint_diagnostic(
+                        &name,
+                        level,
+                        levels.is_plugin_default(&name),
+                        levels.deny_warnings,
+                        &finding.path,
+                        finding.span,
+                        &finding.message,
+                    )
+                    .with_suggestions(finding.suggestion)
+                }));
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Runs the passes of the plugins over the program.
+    pub fn run_passes(&self, ir: &mut IR) -> Result<()> {
+        for plugin in &self.plugins {
+            for pass in &plugin.registry.passes {
+                let _span = crate::driver::self_profile::span(
+                    "plugin",
+                    format_args!("{}::{}", plugin.name, pass.name()),
+                );
+
+                if let Err(error) = pass.run(ir) {
+                    bail!(
+                        "the pass `{}` of plugin `{}` failed: {}",
+                        pass.name(),
+                        plugin.name,
+                        error
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
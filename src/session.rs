@@ -0,0 +1,315 @@
+//! The compiler as a library: compiles sources to object files and returns the diagnostics instead
+//! of printing them, so tools, tests and services can embed it.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{CompilationUnit, common::Span},
+    check::{
+        diagnostics::{Diagnostic, Severity, SourceCache},
+        lints::{LintLevel, LintLevels, warning_to_diagnostic},
+        lowering_error_to_diagnostics,
+    },
+    codegen::errors::CodegenError,
+    compile_unit_info::{CompileUnitInfo, DebugInfo, Lto, OptLevel, TargetInfo},
+    driver::{
+        LOWERING_STACK_SIZE, add_external_modules,
+        sources::{ModuleFiles, SourceExtensions, module_declarations},
+    },
+    ir::{IR, lowering::lower_compile_units_with_sink},
+    parser::{ProgramSource, read_source},
+};
+
+/// The path given to the source compiled by [`compile_str`].
+pub const DEFAULT_SOURCE_PATH: &str = "main.con";
+
+/// A compilation of a set of sources, which can be given from memory or read from disk.
+///
+/// Nothing is printed and the process never exits, all the problems found are returned as
+/// diagnostics.
+#[derive(Debug, Clone)]
+pub struct CompileSession {
+    info: CompileUnitInfo,
+    lint_levels: LintLevels,
+    extensions: SourceExtensions,
+    /// The files compiled, each with its modules declared in other files.
+    roots: Vec<PathBuf>,
+    /// Sources given in memory, they take precedence over the files on disk.
+    sources: HashMap<PathBuf, String>,
+}
+
+/// The result of checking the sources, without generating code.
+#[derive(Debug)]
+pub struct CheckOutput {
+    /// The lowered program, if there were no errors.
+    pub ir: Option<IR>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The result of compiling the sources.
+#[derive(Debug)]
+pub struct CompileOutput {
+    /// The object file, if there were no errors.
+    pub object: Option<PathBuf>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl CheckOutput {
+    pub fn has_errors(&self) -> bool {
+        self.ir.is_none()
+    }
+}
+
+impl CompileOutput {
+    pub fn has_errors(&self) -> bool {
+        self.object.is_none()
+    }
+}
+
+impl CompileSession {
+    pub fn new(info: CompileUnitInfo) -> Self {
+        Self {
+            info,
+            lint_levels: LintLevels::default(),
+            extensions: SourceExtensions::default(),
+            roots: Vec::new(),
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn with_lint_levels(mut self, lint_levels: LintLevels) -> Self {
+        self.lint_levels = lint_levels;
+        self
+    }
+
+    pub fn with_extensions(mut self, extensions: SourceExtensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Adds a source to compile from memory, the path is used in the diagnostics and to find the
+    /// modules it declares in other files.
+    pub fn add_source(&mut self, path: impl Into<PathBuf>, source: impl Into<String>) {
+        let path = path.into();
+        self.sources.insert(path.clone(), source.into());
+        self.roots.push(path);
+    }
+
+    /// Adds a source only used by the modules declaring it in another file, from memory.
+    pub fn add_module_source(&mut self, path: impl Into<PathBuf>, source: impl Into<String>) {
+        self.sources.insert(path.into(), source.into());
+    }
+
+    /// Adds a file to compile, read from disk.
+    pub fn add_file(&mut self, path: impl Into<PathBuf>) {
+        self.roots.push(path.into());
+    }
+
+    /// The sources the diagnostics point to, to render them with [`Diagnostic::to_report`].
+    pub fn source_cache(&self) -> SourceCache {
+        let mut cache = SourceCache::default();
+
+        for (path, source) in &self.sources {
+            cache.insert(path.display().to_string(), source.clone());
+        }
+
+        cache
+    }
+
+    /// Parses the sources, returning the compile units of the ones without syntax errors, with
+    /// the modules they declare in other files.
+    pub fn parse_roots(&self) -> (Vec<CompilationUnit>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+
+        let units = self
+            .roots
+            .iter()
+            .filter_map(|path| self.parse(path, &mut ModuleFiles::new(path), &mut diagnostics))
+            .collect();
+
+        (units, diagnostics)
+    }
+
+    /// Parses and lowers the sources, without generating code.
+    pub fn check(&self) -> CheckOutput {
+        let (units, mut diagnostics) = self.parse_roots();
+
+        if units.len() != self.roots.len() {
+            return CheckOutput {
+                ir: None,
+                diagnostics,
+            };
+        }
+
+        let ir = self.lower(&units, &mut diagnostics);
+
+        CheckOutput { ir, diagnostics }
+    }
+
+    /// Compiles the sources to the object file set in the options.
+    ///
+    /// Errors in the sources are returned as diagnostics, only failures generating the code
+    /// return an error.
+    pub fn compile(&self) -> Result<CompileOutput, CodegenError> {
+        let CheckOutput { ir, diagnostics } = self.check();
+
+        let object = match ir {
+            Some(ir) => Some(crate::codegen::compile(&self.info, &ir)?),
+            None => None,
+        };
+
+        Ok(CompileOutput {
+            object,
+            diagnostics,
+        })
+    }
+
+    fn read(&self, path: &Path, diagnostics: &mut Vec<Diagnostic>) -> Option<String> {
+        if let Some(source) = self.sources.get(path) {
+            return Some(source.clone());
+        }
+
+        match read_source(path) {
+            Ok(x) => Some(x),
+            Err(error) => {
+                diagnostics.push(match error.to_diagnostic() {
+                    Some((diagnostic, _)) => diagnostic,
+                    None => {
+                        Diagnostic::error("SourceNotFound", path.to_path_buf(), Span::new(0, 0))
+                            .with_message(error)
+                    }
+                });
+                None
+            }
+        }
+    }
+
+    /// Parses the file and the modules it declares in other files.
+    fn parse(
+        &self,
+        path: &Path,
+        files: &mut ModuleFiles,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<CompilationUnit> {
+        let input = self.read(path, diagnostics)?;
+        let source = ProgramSource::new(input, path);
+
+        let mut compile_unit = match crate::parser::parse_ast(&source) {
+            Ok(x) => x,
+            Err(error) => {
+                diagnostics.push(error.to_diagnostic(&source));
+                return None;
+            }
+        };
+
+        let exists = |x: &Path| self.sources.contains_key(x) || x.is_file();
+        let mut modules_to_add = Vec::new();
+        let mut failed = false;
+
+        for declaration in module_declarations(&compile_unit, path) {
+            let span = declaration.name.span;
+            let module_path = match self
+                .extensions
+                .find_module_in(&declaration.dir, &declaration.name.name, &exists)
+                .and_then(|x| files.enter(&x).map(|_| x))
+            {
+                Ok(x) => x,
+                Err(error) => {
+                    diagnostics.push(
+                        Diagnostic::error(error.code(), path.to_path_buf(), span)
+                            .with_label(span, error),
+                    );
+                    failed = true;
+                    continue;
+                }
+            };
+
+            let unit = self.parse(&module_path, files, diagnostics);
+            files.leave();
+            match unit {
+                Some(unit) => modules_to_add.push((declaration, unit)),
+                None => failed = true,
+            }
+        }
+
+        if failed {
+            return None;
+        }
+
+        add_external_modules(&mut compile_unit, modules_to_add);
+
+        Some(compile_unit)
+    }
+
+    fn lower(&self, units: &[CompilationUnit], diagnostics: &mut Vec<Diagnostic>) -> Option<IR> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        // Lowering recurses over the AST, so it runs in a thread with a big enough stack.
+        let lowered = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .name("lowering".to_string())
+                .stack_size(LOWERING_STACK_SIZE)
+                .spawn_scoped(scope, move || {
+                    lower_compile_units_with_sink(units, Some(sender))
+                })
+                .expect("failed to spawn the lowering thread")
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        });
+
+        diagnostics.extend(
+            receiver
+                .try_iter()
+                .filter(|x| self.lint_levels.warning_level(x) != LintLevel::Allow)
+                .map(|x| warning_to_diagnostic(&x, &self.lint_levels)),
+        );
+
+        let denied = diagnostics.iter().any(|x| x.severity == Severity::Error);
+
+        match lowered {
+            Ok(ir) if !denied => Some(ir),
+            Ok(_) => None,
+            Err(error) => {
+                diagnostics.extend(lowering_error_to_diagnostics(error));
+                None
+            }
+        }
+    }
+}
+
+/// Checks and compiles a single source from memory to the given object file, as a debug build.
+pub fn compile_str(source: &str, output_file: &Path) -> Result<CompileOutput, CodegenError> {
+    let mut session = CompileSession::new(debug_build(output_file.to_path_buf()));
+    session.add_source(DEFAULT_SOURCE_PATH, source);
+    session.compile()
+}
+
+/// Checks a single source from memory, without generating code.
+pub fn check_str(source: &str) -> CheckOutput {
+    let output_file = Path::new(DEFAULT_SOURCE_PATH).with_extension("o");
+    let mut session = CompileSession::new(debug_build(output_file));
+    session.add_source(DEFAULT_SOURCE_PATH, source);
+    session.check()
+}
+
+pub(crate) fn debug_build(output_file: PathBuf) -> CompileUnitInfo {
+    CompileUnitInfo {
+        debug_info: DebugInfo::Full,
+        optlevel: OptLevel::None,
+        library: false,
+        output_file,
+        output_mlir: false,
+        output_ll: false,
+        output_asm: false,
+        fuzzing: false,
+        unchecked_indexing: false,
+        target: TargetInfo::default(),
+        lto: Lto::Off,
+        sanitizers: Vec::new(),
+        coverage: false,
+        freestanding: false,
+    }
+}
@@ -0,0 +1,85 @@
+use concrete::ir::lowering::LoweringError;
+
+use crate::check_invalid_program;
+
+#[test]
+fn invalid_borrow_mut() {
+    let (source, name) = (
+        include_str!("../invalid_programs/invalid_borrow_mut.con"),
+        "invalid_programs/invalid_borrow_mut.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::NotMutable { .. }),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn immutable_mutation() {
+    let (source, name) = (
+        include_str!("../invalid_programs/immutable_mutation.con"),
+        "invalid_programs/immutable_mutation.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::NotMutable { .. }),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn immutable_pattern() {
+    let (source, name) = (
+        include_str!("../invalid_programs/immutable_pattern.con"),
+        "invalid_programs/immutable_pattern.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    // The bindings of a pattern are only mutable in a `let mut`.
+    assert!(
+        matches!(&error, LoweringError::NotMutable { .. }),
+        "{:#?}",
+        error
+    );
+    // `mut` can't be written on a single binding of the pattern.
+    assert!(error.suggestions().is_empty());
+}
+
+#[test]
+fn mutable_nonmut_borrow() {
+    let (source, name) = (
+        include_str!("../invalid_programs/mutable_nonmut_borrow.con"),
+        "invalid_programs/mutable_nonmut_borrow.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::CantTakeMutableBorrow { .. }),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn borrow_conflict() {
+    let (source, name) = (
+        include_str!("../invalid_programs/borrow_conflict.con"),
+        "invalid_programs/borrow_conflict.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(
+            &error,
+            LoweringError::BorrowConflict { name, access: "mutably borrow", mutable_borrow: false, .. }
+                if name == "x"
+        ),
+        "{:#?}",
+        error
+    );
+}
@@ -0,0 +1,544 @@
+use std::path::Path;
+
+use concrete::ir::lowering::{LoweringError, lower_compile_units};
+
+use crate::{check_invalid_program, lower_program, parse_program};
+
+#[test]
+fn compile_units_are_split_for_parallel_codegen() {
+    let dependency = parse_program(
+        "mod Dep {
+            pub fn value() -> i32 {
+                return 1;
+            }
+
+            mod Inner {
+                pub fn other() -> i32 {
+                    return 2;
+                }
+            }
+        }",
+        "dep/lib.con",
+    );
+    let program = parse_program(
+        "mod Main {
+            import Dep.{value};
+
+            fn main() -> i32 {
+                return value();
+            }
+        }",
+        "main.con",
+    );
+
+    let ir = lower_compile_units(&[dependency, program]).expect("the program is valid");
+    assert_eq!(ir.compile_units.len(), 2);
+
+    let unit_of = |name: &str| {
+        let (_, function) = ir
+            .functions
+            .iter()
+            .find(|(_, x)| {
+                x.as_ref()
+                    .and_then(|x| x.debug_name.as_deref())
+                    .is_some_and(|x| x.ends_with(name))
+            })
+            .unwrap_or_else(|| panic!("no function {name}"));
+        let module = ir.top_level_module(function.as_ref().unwrap().module_idx);
+        ir.compile_units
+            .iter()
+            .position(|x| x.contains(&module))
+            .unwrap()
+    };
+    assert_eq!(unit_of("value"), 0);
+    assert_eq!(unit_of("other"), 0);
+    assert_eq!(unit_of("main"), 1);
+
+    let items: Vec<usize> = (0..100).collect();
+    concrete::driver::parallel::set_jobs(Some(4));
+    let squares = concrete::driver::parallel::map(&items, |x| x * x);
+    assert_eq!(squares, items.iter().map(|x| x * x).collect::<Vec<_>>());
+}
+
+#[test]
+fn incremental_unit_hashes_only_change_with_their_unit() {
+    use concrete::driver::incremental::unit_hashes;
+
+    let lower = |main_body: &str| {
+        let dependency = parse_program(
+            "mod Dep {
+                pub fn value() -> i32 {
+                    return 1;
+                }
+            }",
+            "dep/lib.con",
+        );
+        let program = parse_program(
+            &format!(
+                "mod Main {{
+                    import Dep.{{value}};
+
+                    fn main() -> i32 {{
+                        {main_body}
+                    }}
+                }}"
+            ),
+            "main.con",
+        );
+        lower_compile_units(&[dependency, program]).expect("the program is valid")
+    };
+
+    let options = "dev";
+    let first = unit_hashes(&lower("return value();"), options);
+    assert_eq!(first.len(), 2);
+    assert_eq!(first, unit_hashes(&lower("return value();"), options));
+
+    let changed = unit_hashes(&lower("let x: i32 = value();\n return x + 1;"), options);
+    assert_eq!(changed[0], first[0]);
+    assert_ne!(changed[1], first[1]);
+
+    assert_ne!(unit_hashes(&lower("return value();"), "release"), first);
+}
+
+#[test]
+fn shared_unit_hashes_are_the_same_in_every_program() {
+    use concrete::driver::{
+        cache::{Cache, reuse_unit, store_unit},
+        config::{DependencyOrigin, DependencySource},
+        incremental::{shared_unit_hashes, unit_hashes},
+    };
+
+    let lower = |main_source: &str| {
+        let dependency = parse_program(
+            "mod Dep {
+                pub struct Point {
+                    pub x: i32,
+                    pub y: i32,
+                }
+
+                pub fn sum(point: Point) -> i32 {
+                    return point.x + point.y;
+                }
+            }",
+            "dep/lib.con",
+        );
+        let program = parse_program(main_source, "main.con");
+        lower_compile_units(&[dependency, program]).expect("the program is valid")
+    };
+
+    let first = lower(
+        "mod Main {
+            import Dep.{Point, sum};
+
+            fn main() -> i32 {
+                let point: Point = Point { x: 1, y: 2 };
+                return sum(point);
+            }
+        }",
+    );
+    let second = lower(
+        "mod Main {
+            import Dep.{Point, sum};
+
+            struct Pair {
+                a: u64,
+                b: bool,
+            }
+
+            fn twice(point: Point) -> i32 {
+                return sum(point) * 2;
+            }
+
+            fn main() -> i32 {
+                let pair: Pair = Pair { a: 1, b: true };
+                let point: Point = Point { x: 3, y: 4 };
+                return twice(point);
+            }
+        }",
+    );
+
+    // The whole program hash of the dependency changes with the program using it, its shared one
+    // doesn't, so both programs reuse the same cached object.
+    let options = "dev";
+    assert_ne!(
+        unit_hashes(&first, options)[0],
+        unit_hashes(&second, options)[0]
+    );
+    let shared = shared_unit_hashes(&first, options);
+    assert_eq!(shared[0], shared_unit_hashes(&second, options)[0]);
+    assert_ne!(shared[1], shared_unit_hashes(&second, options)[1]);
+
+    assert_ne!(shared_unit_hashes(&first, "release")[0], shared[0]);
+
+    // Both programs get the same object of the dependency from the global cache.
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache {
+        dir: dir.path().join("cache"),
+    };
+    let checkout = dir.path().join("dep");
+    std::fs::create_dir_all(&checkout).unwrap();
+    std::fs::write(checkout.join("lib.con"), "mod Dep {}").unwrap();
+    let repo = git2::Repository::init(&checkout).unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "dep", &tree, &[])
+        .unwrap();
+
+    let origin = DependencyOrigin {
+        name: "dep".to_string(),
+        version: None,
+        source: "git https://example.com/dep.git".to_string(),
+        resolved_source: DependencySource::Git {
+            url: "https://example.com/dep.git".to_string(),
+            r#ref: None,
+        },
+        key: "dep".to_string(),
+        dir: checkout,
+        required_by: vec!["app".to_string()],
+    };
+    let unit = |ir: &concrete::ir::IR| {
+        cache
+            .dependency_unit(&origin, "x86_64", shared_unit_hashes(ir, options)[0], "o")
+            .expect("git dependencies are cached")
+    };
+    assert_eq!(unit(&first), unit(&second));
+
+    let object = dir.path().join("first.o");
+    std::fs::write(&object, "object").unwrap();
+    store_unit(&object, &unit(&first));
+    let other = dir.path().join("second.o");
+    assert!(reuse_unit(&unit(&second), &other));
+    assert_eq!(std::fs::read_to_string(other).unwrap(), "object");
+}
+
+#[test]
+fn target_library_extension() {
+    use concrete::compile_unit_info::{CompileUnitInfo, TargetInfo};
+
+    assert_eq!(
+        TargetInfo::default().library_ext(),
+        CompileUnitInfo::get_platform_library_ext()
+    );
+    assert_eq!(
+        TargetInfo::new(Some("aarch64-apple-darwin".to_string())).library_ext(),
+        "dylib"
+    );
+    assert_eq!(
+        TargetInfo::new(Some("x86_64-pc-windows-msvc".to_string())).library_ext(),
+        "dll"
+    );
+    assert_eq!(
+        TargetInfo::new(Some("riscv64gc-unknown-linux-gnu".to_string())).library_ext(),
+        "so"
+    );
+}
+
+#[test]
+fn wasm_targets() {
+    use concrete::compile_unit_info::{TargetInfo, TargetKind};
+
+    let wasm = TargetInfo::new(Some("wasm32".to_string()));
+    assert_eq!(wasm.triple.as_deref(), Some("wasm32-unknown-unknown"));
+    assert_eq!(wasm.kind, TargetKind::Wasm32);
+    assert_eq!(wasm.library_ext(), "wasm");
+    assert_eq!(wasm.binary_ext(), Some("wasm"));
+
+    let wasi = TargetInfo::new(Some("wasi".to_string()));
+    assert_eq!(wasi.triple.as_deref(), Some("wasm32-wasi"));
+    assert_eq!(wasi.kind, TargetKind::Wasi);
+
+    let native = TargetInfo::new(Some("aarch64-unknown-linux-gnu".to_string()));
+    assert_eq!(native.kind, TargetKind::Native);
+    assert_eq!(native.binary_ext(), None);
+}
+
+#[test]
+fn misspelled_targets_suggest_the_closest_architecture() {
+    use concrete::{codegen::check_target, compile_unit_info::TargetInfo};
+
+    let target = TargetInfo::new(Some("x86_46-unknown-linux-gnu".to_string()));
+    let error = check_target(&target).unwrap_err().to_string();
+    assert!(
+        error.starts_with("unknown target `x86_46`, did you mean `x86_64`?"),
+        "{error}"
+    );
+    assert!(
+        error.contains("note: the available targets are: "),
+        "{error}"
+    );
+
+    let target = TargetInfo::new(Some("x86_64-unknown-linux-gnu".to_string()));
+    check_target(&target).expect("LLVM is built with x86");
+    check_target(&TargetInfo::default()).expect("the host is a target");
+}
+
+#[test]
+fn generic_instances_are_separate_functions() {
+    let ir = lower_program(
+        "mod Main {
+            fn id<T>(x: T) -> T {
+                return x;
+            }
+
+            fn first<T>(a: T, b: T) -> T {
+                return a;
+            }
+
+            fn main() -> i32 {
+                let a: i64 = id::<i64>(1);
+                let b: bool = id(true);
+                let c: bool = first(b, false);
+                return id::<i32>(2);
+            }
+        }",
+        "main.con",
+    )
+    .expect("the program is valid");
+
+    let mut instances: Vec<(&str, &str)> = ir
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .filter_map(|x| Some((x.debug_name.as_deref()?, x.name.as_str())))
+        .filter(|(debug_name, _)| debug_name.starts_with("Main::id"))
+        .collect();
+    instances.sort();
+
+    let debug_names: Vec<&str> = instances.iter().map(|(x, _)| *x).collect();
+    assert_eq!(
+        debug_names,
+        ["Main::id<bool>", "Main::id<i32>", "Main::id<i64>"]
+    );
+
+    let mut names: Vec<&str> = instances.iter().map(|(_, x)| *x).collect();
+    names.dedup();
+    assert_eq!(names.len(), 3, "each instance has its own symbol");
+
+    for (debug_name, symbol) in &instances {
+        assert_eq!(concrete::demangle(symbol).as_deref(), Some(*debug_name));
+    }
+}
+
+#[test]
+fn symbols_are_stable() {
+    let symbol = |items: &str| {
+        let ir = lower_program(
+            &format!(
+                "mod Math {{
+{items}
+    pub fn add(a: i32, b: i32) -> i32 {{
+        return a + b;
+    }}
+}}"
+            ),
+            "lib.con",
+        )
+        .expect("the program is valid");
+        ir.functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref())
+            .find(|x| x.debug_name.as_deref() == Some("Math::add"))
+            .map(|x| x.name.clone())
+            .unwrap()
+    };
+
+    // The symbol doesn't depend on the other functions of the program.
+    assert_eq!(symbol(""), "_C1_4Math3addh5ce46587cadea2e4");
+    assert_eq!(
+        symbol("    fn sub(a: i32, b: i32) -> i32 {\n        return a - b;\n    }"),
+        "_C1_4Math3addh5ce46587cadea2e4"
+    );
+}
+
+#[test]
+fn codegen_function_attributes() {
+    use concrete::ir::Inline;
+
+    let ir = lower_program(
+        r#"mod Main {
+            #[inline(always)]
+            fn double(x: i32) -> i32 {
+                return x * 2;
+            }
+
+            #[cold]
+            #[inline(never)]
+            fn fail() -> i32 {
+                return 1;
+            }
+
+            #[no_mangle]
+            pub fn concrete_add(a: i32, b: i32) -> i32 {
+                return a + b;
+            }
+
+            #[export_name = "lib_version"]
+            pub fn version() -> i32 {
+                return 3;
+            }
+
+            fn main() -> i32 {
+                return double(2) + fail();
+            }
+        }"#,
+        "codegen_attributes.con",
+    )
+    .expect("the program is valid");
+
+    let function = |name: &str| {
+        ir.functions
+            .iter()
+            .find_map(|(_, x)| x.as_ref().filter(|x| x.debug_name.as_deref() == Some(name)))
+            .unwrap_or_else(|| panic!("{name} should be lowered"))
+    };
+
+    assert_eq!(function("Main::double").inline, Some(Inline::Always));
+    assert_eq!(function("Main::fail").inline, Some(Inline::Never));
+    assert!(function("Main::fail").is_cold);
+    assert!(!function("Main::double").is_cold);
+
+    // The exported functions keep their symbols, and are used though nothing calls them.
+    assert_eq!(function("Main::concrete_add").name, "concrete_add");
+    assert_eq!(function("Main::version").name, "lib_version");
+    assert!(ir.warnings.is_empty(), "{:#?}", ir.warnings);
+
+    let error = check_invalid_program(
+        "mod Main {\n    #[inline(sometimes)]\n    fn main() -> i32 {\n        return 0;\n    }\n}\n",
+        "inline_invalid.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { name, .. } if name == "inline"),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        "mod Main {\n    #[no_mangle]\n    fn id<T>(x: T) -> T {\n        return x;\n    }\n\n    fn main() -> i32 {\n        return id::<i32>(0);\n    }\n}\n",
+        "no_mangle_generic.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { name, .. } if name == "no_mangle"),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn lto_profile_settings() {
+    use concrete::{compile_unit_info::Lto, driver::config::Profile};
+
+    let profile = |lto: &str| {
+        toml::from_str::<Profile>(&format!(
+            "release = true\nopt_level = 3\ndebug_info = false\n{lto}"
+        ))
+    };
+
+    assert_eq!(profile("").unwrap().lto, Lto::Off);
+    assert_eq!(profile("lto = true").unwrap().lto, Lto::Full);
+    assert_eq!(profile("lto = false").unwrap().lto, Lto::Off);
+    assert_eq!(profile("lto = \"thin\"").unwrap().lto, Lto::Thin);
+    assert_eq!(profile("lto = \"full\"").unwrap().lto, Lto::Full);
+    assert!(profile("lto = \"fat\"").is_err());
+}
+
+#[test]
+fn sanitizer_profile_settings() {
+    use concrete::{
+        compile_unit_info::Sanitizer,
+        driver::config::{Profile, unknown_config_keys},
+    };
+
+    let source = r#"
+        release = false
+        opt_level = 0
+        debug_info = true
+        sanitizers = ["address", "undefined"]
+    "#;
+    let profile: Profile = toml::from_str(source).unwrap();
+    assert_eq!(
+        profile.sanitizers,
+        [Sanitizer::Address, Sanitizer::Undefined]
+    );
+    assert!(toml::from_str::<Profile>(&source.replace("undefined", "memory")).is_err());
+
+    let warnings = unknown_config_keys(
+        Path::new("Concrete.toml"),
+        &format!("[profile.dev]\n{source}\n"),
+    );
+    assert!(warnings.is_empty(), "{:#?}", warnings);
+}
+
+#[test]
+fn freestanding_builds_need_an_entry() {
+    use clap::Parser;
+    use concrete::driver::{
+        CompilerArgs, check_entry,
+        config::{Config, unknown_config_keys},
+    };
+
+    let source = r#"
+[package]
+name = "kernel"
+version = "0.1.0"
+license = "MIT"
+no_std = true
+entry = "kmain"
+"#;
+    let config: Config = toml::from_str(source).unwrap();
+    let package = config.package().unwrap();
+    assert!(package.no_std);
+    assert_eq!(package.entry.as_deref(), Some("kmain"));
+    let warnings = unknown_config_keys(Path::new("Concrete.toml"), source);
+    assert!(warnings.is_empty(), "{:#?}", warnings);
+
+    CompilerArgs::try_parse_from([
+        "concrete",
+        "main.con",
+        "build/main",
+        "--freestanding",
+        "--entry",
+        "kmain",
+    ])
+    .expect("valid arguments");
+
+    let lower = |source: &str| lower_program(source, "kernel.con");
+    let program = lower(
+        "mod Kernel {
+            #[no_mangle]
+            fn kmain() {
+                let x: i32 = 1;
+            }
+        }",
+    )
+    .expect("the program doesn't need std");
+    check_entry(&program, "kmain").expect("the entry is defined");
+    let error = check_entry(&program, "_start").expect_err("there's no `_start`");
+    assert!(error.to_string().contains("`_start`"), "{error}");
+
+    // Without std, the string literals have no type.
+    let error = lower(
+        "mod Kernel {
+            fn kmain() {
+                let x: String = \"hello\";
+            }
+        }",
+    )
+    .expect_err("there's no String lang item");
+    assert!(
+        matches!(&error, LoweringError::MissingLangItem { item, .. } if item == "String"),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn codegen_units_are_grouped_evenly() {
+    use concrete::codegen::codegen_unit_groups;
+
+    assert_eq!(codegen_unit_groups(5, 2), [0..3, 3..5]);
+    let groups = codegen_unit_groups(3, 1);
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0], 0..3);
+    assert_eq!(codegen_unit_groups(2, 4), [0..1, 1..2]);
+}
@@ -0,0 +1,300 @@
+use std::path::Path;
+
+use concrete::ir::lowering::{LoweringError, lower_compile_units};
+
+use crate::{assert_formats, check_invalid_program, lower_program, parse_program};
+
+#[test]
+fn threads_and_atomics_lower() {
+    use concrete::driver::{parse_file, sources::SourceExtensions};
+    use concrete::ir::{AtomicOp, BinOp, ConcreteIntrinsic};
+
+    let text = include_str!("../../examples/threads.con");
+    assert_formats(text, text);
+
+    let ir = lower_program(text, "threads.con").unwrap();
+    let intrinsics: Vec<_> = ir
+        .functions
+        .iter()
+        .flat_map(|(_, x)| x.as_ref().and_then(|x| x.is_intrinsic))
+        .collect();
+    assert!(intrinsics.contains(&ConcreteIntrinsic::Atomic(AtomicOp::Load)));
+    assert!(intrinsics.contains(&ConcreteIntrinsic::Atomic(AtomicOp::CompareExchange)));
+    assert!(intrinsics.contains(&ConcreteIntrinsic::Atomic(AtomicOp::Rmw(BinOp::Add))));
+
+    // The thread entry calls the `run` of the task it's instantiated with.
+    let run = intrinsics
+        .iter()
+        .find_map(|x| match x {
+            ConcreteIntrinsic::ThreadStart { run, .. } => *run,
+            _ => None,
+        })
+        .expect("the thread entry should be lowered");
+    let run = ir.functions[run].as_ref().unwrap();
+    assert_eq!(run.debug_name.as_deref(), Some("Main::run"));
+    assert_eq!(concrete::driver::program_libs(&ir), ["pthread"]);
+
+    // The std tasks, atomics and mutexes, used from another package.
+    let std = parse_file(
+        Path::new("std/src/lib.con").to_path_buf(),
+        &SourceExtensions::default(),
+    )
+    .unwrap();
+    let program = parse_program(
+        "mod Main {
+            import std.thread.{spawn, Task, JoinHandle};
+            import std.sync.{Atomic, AtomicU64, Mutex, MutexGuard, SEQ_CST};
+
+            struct Worker {
+                count: *mut AtomicU64,
+                total: *mut Mutex<u64>,
+            }
+
+            impl Task for Worker {
+                fn run(&mut self) {
+                    let count: &mut AtomicU64 = self.count as &mut AtomicU64;
+                    let total: &mut Mutex<u64> = self.total as &mut Mutex<u64>;
+                    count.fetch_add(1, SEQ_CST);
+                    let mut guard: MutexGuard<u64> = total.lock();
+                    let value: &mut u64 = guard.get();
+                    *value = *value + 2;
+                    guard.unlock();
+                }
+            }
+
+            fn main() -> i32 {
+                let mut count: AtomicU64 = Atomic::<u64>#new(0);
+                let mut total: Mutex<u64> = Mutex::<u64>#new(0);
+                let worker: Worker = Worker {
+                    count: &mut count as *mut AtomicU64,
+                    total: &mut total as *mut Mutex<u64>,
+                };
+                let handle: JoinHandle<Worker> = spawn(worker);
+                let worker: Worker = handle.join();
+                return (count.load(SEQ_CST) + total.into_inner()) as i32;
+            }
+        }",
+        "workers.con",
+    );
+    let ir = lower_compile_units(&[std.clone(), program]).unwrap();
+    assert_eq!(concrete::driver::program_libs(&ir), ["pthread"]);
+
+    // Only the programs spawning threads are linked with pthread, not all the ones using std.
+    let program = parse_program(
+        "mod Main {
+            import std.sync.{Atomic, AtomicU64, SEQ_CST};
+
+            fn main() -> i32 {
+                let count: AtomicU64 = Atomic::<u64>#new(0);
+                count.fetch_add(1, SEQ_CST);
+                return count.load(SEQ_CST) as i32;
+            }
+        }",
+        "no_threads.con",
+    );
+    let ir = lower_compile_units(&[std, program]).unwrap();
+    assert!(concrete::driver::program_libs(&ir).is_empty());
+}
+
+#[test]
+fn atomics_are_only_on_integers() {
+    use concrete::driver::{parse_file, sources::SourceExtensions};
+
+    let error = check_invalid_program(
+        "mod Main {
+            #[intrinsic = \"atomic_load\"]
+            unsafe fn atomic_load<T>(ptr: *mut T, ordering: u32) -> T;
+
+            fn main() -> i32 {
+                let mut value: f64 = 1.0;
+                unsafe {
+                    value = atomic_load::<f64>(&mut value as *mut f64, 4);
+                }
+                return 0;
+            }
+        }",
+        "atomic_float.con",
+    );
+    assert!(
+        matches!(
+            &error,
+            LoweringError::UnexpectedType(x) if x.found == "f64" && x.expected == "an integer type"
+        ),
+        "{error:#?}"
+    );
+
+    // Nor on the types std's `Atomic` is instantiated with.
+    let std = parse_file(
+        Path::new("std/src/lib.con").to_path_buf(),
+        &SourceExtensions::default(),
+    )
+    .unwrap();
+    let program = parse_program(
+        "mod Main {
+            import std.sync.{Atomic, SEQ_CST};
+
+            fn main() -> i32 {
+                let flag: Atomic<bool> = Atomic::<bool>#new(false);
+                flag.store(true, SEQ_CST);
+                return 0;
+            }
+        }",
+        "atomic_bool.con",
+    );
+    let error = lower_compile_units(&[std, program]).expect_err("expected error");
+    assert!(
+        matches!(
+            &error,
+            LoweringError::UnexpectedType(x) if x.found == "bool" && x.expected == "an integer type"
+        ),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn atomic_intrinsics_are_unsafe() {
+    let program = |declaration: &str, call: &str| {
+        format!(
+            "mod Main {{
+                #[intrinsic = \"atomic_load\"]
+                {declaration} atomic_load<T>(ptr: *mut T, ordering: u32) -> T;
+
+                fn main() -> i32 {{
+                    let mut x: i32 = 1;
+                    {call}
+                    return 0;
+                }}
+            }}"
+        )
+    };
+
+    let error = check_invalid_program(
+        &program("fn", "atomic_load::<i32>(&mut x as *mut i32, 4);"),
+        "atomic_safe.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { reason, .. } if reason.contains("`unsafe fn`")),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        &program("unsafe fn", "atomic_load::<i32>(&mut x as *mut i32, 4);"),
+        "atomic_call.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::UnsafeOperation { .. }),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn async_functions_lower() {
+    use concrete::driver::{parse_file, sources::SourceExtensions};
+    use concrete::ir::{ConcreteIntrinsic, TerminatorKind};
+
+    let text = include_str!("../../examples/async.con");
+    assert_formats(text, text);
+
+    let ir = lower_program(text, "async.con").unwrap();
+
+    // Calling an async function allocates its frame, the body is in the poll function.
+    let mut polls: Vec<_> = ir
+        .functions
+        .iter()
+        .flat_map(|(_, x)| match x.as_ref()?.is_intrinsic? {
+            ConcreteIntrinsic::AsyncStart { poll, .. } => ir.functions[poll].as_ref(),
+            _ => None,
+        })
+        .collect();
+    polls.sort_by_key(|x| x.debug_name.clone());
+    let names: Vec<_> = polls.iter().map(|x| x.debug_name.as_deref()).collect();
+    assert_eq!(
+        names,
+        [Some("Main::double::poll"), Some("Main::sum_doubles::poll")]
+    );
+
+    // The poll function starts from the state of the frame, `sum_doubles` can also resume from
+    // its `await`.
+    let TerminatorKind::SwitchInt { targets, .. } = &polls[1].basic_blocks[0].terminator.kind
+    else {
+        panic!("the poll function should start by switching on the state");
+    };
+    assert_eq!(targets.values.len(), 2);
+
+    // The std futures and executors, used from another package.
+    let std = parse_file(
+        Path::new("std/src/lib.con").to_path_buf(),
+        &SourceExtensions::default(),
+    )
+    .unwrap();
+    let program = parse_program(
+        "mod Main {
+            import std.task.{Future, Executor, block_on};
+            import std.vec.{Vec};
+
+            async fn add(a: i32, b: i32) -> i32 {
+                return a + b;
+            }
+
+            async fn twice(x: i32) -> i32 {
+                let first: i32 = await add(x, 0);
+                return await add(first, x);
+            }
+
+            fn main() -> i32 {
+                let value: i32 = block_on(twice(2));
+                let mut executor: Executor<i32> = Executor::<i32>#new();
+                executor.spawn(twice(1));
+                executor.spawn(add(1, 2));
+                let values: Vec<i32> = executor.run();
+                return value + *values.get(0) + *values.get(1);
+            }
+        }",
+        "tasks.con",
+    );
+    lower_compile_units(&[std, program]).unwrap();
+
+    let error = check_invalid_program(
+        "mod Main {
+            fn main() -> i32 {
+                return await 1;
+            }
+        }",
+        "await.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAwait { reason, .. } if reason.contains("async functions")),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        "mod Main {
+            async fn value() -> i32 {
+                let x: i32 = 1;
+                return await x;
+            }
+
+            fn main() -> i32 {
+                return 0;
+            }
+        }",
+        "await_value.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAwait { reason, .. } if reason.contains("`i32`")),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        "mod Main {
+            async fn main() -> i32 {
+                return 0;
+            }
+        }",
+        "async_main.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAsyncFn { name, .. } if name == "main"),
+        "{error:#?}"
+    );
+}
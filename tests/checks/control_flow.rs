@@ -0,0 +1,274 @@
+use concrete::ir::lowering::LoweringError;
+
+use crate::{assert_formats, check_invalid_program, lower_program};
+
+#[test]
+fn try_operator_is_checked() {
+    let text = include_str!("../../examples/try_operator.con");
+    assert_formats(text, text);
+    lower_program(text, "try_operator.con").expect("the `?` operators are well typed");
+
+    let program = |ret: &str, body: &str| {
+        format!(
+            "mod Main {{
+                #[langitem = \"Option\"]
+                enum Option<T> {{
+                    Some {{
+                        value: T,
+                    }},
+                    None,
+                }}
+
+                #[langitem = \"Result\"]
+                enum Result<T, E> {{
+                    Ok {{
+                        value: T,
+                    }},
+                    Err {{
+                        error: E,
+                    }},
+                }}
+
+                fn get() -> Result<i32, bool> {{
+                    let ok: Result<i32, bool> = Result::<i32, bool>#Ok {{ value: 1 }};
+                    return ok;
+                }}
+
+                fn main() -> {ret} {{
+                    {body}
+                }}
+            }}"
+        )
+    };
+
+    let error = check_invalid_program(
+        &program(
+            "Result<i32, bool>",
+            "let x: i32 = 1; let y: i32 = x?; return get();",
+        ),
+        "try_value.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidTry { reason, .. } if reason.contains("`i32`")),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(&program("i32", "return get()?;"), "try_return.con");
+    assert!(
+        matches!(&error, LoweringError::InvalidTry { reason, .. } if reason.contains("`Err`")),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        &program(
+            "Result<i32, u8>",
+            "let x: i32 = get()?; let ok: Result<i32, u8> = Result::<i32, u8>#Ok { value: x }; return ok;",
+        ),
+        "try_error.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::UnexpectedType(_)),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        "mod Main {
+            #[langitem = \"Option\"]
+            enum Maybe {
+                Just {
+                    value: i32,
+                },
+                Nothing,
+            }
+
+            fn main() -> i32 {
+                return 0;
+            }
+        }",
+        "try_lang_item.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { reason, .. } if reason.contains("`Some`")),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn defers_run_when_leaving_their_block() {
+    use concrete::ir::TerminatorKind;
+
+    let text = include_str!("../../examples/defer.con");
+    assert_formats(text, text);
+    let ir = lower_program(
+        "mod Main {
+            fn first() -> i32 {
+                return 1;
+            }
+
+            fn second() -> i32 {
+                return 2;
+            }
+
+            fn work() -> i32 {
+                return 3;
+            }
+
+            fn run(early: bool) -> i32 {
+                defer first();
+                if early {
+                    defer {
+                        second();
+                    }
+                    return 1;
+                }
+                work();
+                return 2;
+            }
+        }",
+        "defer.con",
+    )
+    .expect("the defers are valid");
+
+    let name = |idx| {
+        let debug_name = ir.functions[idx].as_ref().unwrap().debug_name.as_deref();
+        debug_name.unwrap().trim_start_matches("Main::")
+    };
+    let (_, run) = ir
+        .functions
+        .iter()
+        .find(|(idx, _)| name(*idx) == "run")
+        .unwrap();
+    let run = run.as_ref().unwrap();
+
+    // The functions called on each path to a return.
+    let mut paths = Vec::new();
+    let mut pending = vec![(0, Vec::new())];
+    while let Some((block, mut calls)) = pending.pop() {
+        match &run.basic_blocks[block].terminator.kind {
+            TerminatorKind::Return => paths.push(calls),
+            TerminatorKind::Goto { target } => pending.push((*target, calls)),
+            TerminatorKind::Call { func, target, .. } => {
+                calls.push(name(*func));
+                pending.extend(target.map(|target| (target, calls)));
+            }
+            TerminatorKind::SwitchInt { targets, .. } => {
+                for target in &targets.targets {
+                    pending.push((*target, calls.clone()));
+                }
+            }
+            kind => panic!("unexpected terminator {kind:?}"),
+        }
+    }
+    paths.sort();
+    assert_eq!(paths, [vec!["second", "first"], vec!["work", "first"]]);
+
+    let error = check_invalid_program(
+        "mod Main {
+            fn main() -> i32 {
+                defer {
+                    return 1;
+                }
+                return 0;
+            }
+        }",
+        "defer_return.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::ReturnInDefer { .. }),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn tail_calls_are_checked() {
+    use concrete::ir::TerminatorKind;
+
+    let text = include_str!("../../examples/tail_calls.con");
+    assert_formats(text, text);
+
+    let ir = lower_program(text, "tail_calls.con").unwrap();
+
+    // `even` ends in a tail call to `odd` instead of returning.
+    let (_, even) = ir
+        .functions
+        .iter()
+        .find(|(_, x)| {
+            x.as_ref()
+                .is_some_and(|x| x.debug_name.as_deref() == Some("Main::even"))
+        })
+        .unwrap();
+    let tail_calls: Vec<_> = even
+        .as_ref()
+        .unwrap()
+        .basic_blocks
+        .iter()
+        .filter_map(|x| match &x.terminator.kind {
+            TerminatorKind::TailCall { func, .. } => ir.functions[*func].as_ref(),
+            _ => None,
+        })
+        .map(|x| x.debug_name.as_deref())
+        .collect();
+    assert_eq!(tail_calls, [Some("Main::odd")]);
+
+    // Borrowing through a reference the caller took is fine, the value outlives it.
+    lower_program(
+        "mod Main {
+            fn first(values: &[i32; 2], n: i32) -> i32 {
+                if n == 0 {
+                    return values[0];
+                }
+                become first(&*values, n - 1);
+            }
+
+            fn main() -> i32 {
+                let values: [i32; 2] = [1, 2];
+                return first(&values, 3);
+            }
+        }",
+        "reborrow.con",
+    )
+    .unwrap();
+
+    let invalid = [
+        ("become 1;", "takes a function call"),
+        ("become other(1, 2);", "takes other parameters"),
+        ("become wide(x);", "returns another type"),
+        (
+            "defer { let y: i32 = 0; }\n become caller(x);",
+            "a defer would run",
+        ),
+        ("let y: i32 = x;\n become by_ref(&y);", "borrows `y`"),
+    ];
+    for (statements, reason) in invalid {
+        let error = check_invalid_program(
+            &format!(
+                "mod Main {{
+                    fn other(a: i32, b: i32) -> i32 {{
+                        return a + b;
+                    }}
+
+                    fn wide(a: i32) -> i64 {{
+                        return a as i64;
+                    }}
+
+                    fn by_ref(a: &i32) -> i32 {{
+                        return *a;
+                    }}
+
+                    fn caller(x: i32) -> i32 {{
+                        {statements}
+                    }}
+
+                    fn main() -> i32 {{
+                        return caller(1);
+                    }}
+                }}"
+            ),
+            "tail_call.con",
+        );
+        assert!(
+            matches!(&error, LoweringError::InvalidTailCall { reason: found, .. } if found.contains(reason)),
+            "{statements}: {error:#?}"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use std::path::Path;
+
+use concrete::check::diagnostics::SourceCache;
+use concrete::check::lowering_error_to_diagnostic;
+use concrete::check::sarif::to_sarif;
+use concrete::check::suggestions::apply_suggestions;
+use concrete::ir::lowering::{LoweringError, lower_compile_units, lower_compile_units_with_sink};
+use concrete::parser::ProgramSource;
+
+use crate::{check_invalid_program, lower_program, parse_program};
+
+#[test]
+fn ui_tests() {
+    let results = concrete::driver::ui::run_ui_tests(Path::new("tests/ui")).unwrap();
+
+    assert!(!results.is_empty());
+
+    for result in results {
+        assert!(result.passed(), "ui test failed: {:#?}", result);
+    }
+}
+
+#[test]
+fn invalid_retries() {
+    let (source, name) = (
+        include_str!("../invalid_programs/invalid_retries.con"),
+        "invalid_programs/invalid_retries.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { name, .. } if name == "retries"),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn immutable_mutation_fix() {
+    let (source, name) = (
+        include_str!("../invalid_programs/immutable_mutation.con"),
+        "invalid_programs/immutable_mutation.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let mut fixed = source.to_string();
+    assert_eq!(apply_suggestions(&mut fixed, &error.suggestions()), 1);
+    assert!(fixed.contains("let mut x: i32 = 2;"), "{}", fixed);
+
+    lower_program(&fixed, name).expect("fixed program should lower");
+}
+
+#[test]
+fn immutable_mutation_sarif() {
+    let (source, name) = (
+        include_str!("../invalid_programs/immutable_mutation.con"),
+        "invalid_programs/immutable_mutation.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let mut sources = SourceCache::default();
+    sources.insert(name, source);
+    let log = to_sarif(&[lowering_error_to_diagnostic(error)], &mut sources);
+    let result = &log["runs"][0]["results"][0];
+
+    assert_eq!(result["ruleId"], "NotMutable");
+    assert_eq!(result["level"], "error");
+    assert_eq!(
+        result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+        name
+    );
+    assert_eq!(
+        result["locations"][0]["physicalLocation"]["region"]["startLine"],
+        4
+    );
+    assert_eq!(
+        result["fixes"][0]["artifactChanges"][0]["replacements"][0]["insertedContent"]["text"],
+        "mut x"
+    );
+}
+
+#[test]
+fn immutable_mutation_json() {
+    let (source, name) = (
+        include_str!("../invalid_programs/immutable_mutation.con"),
+        "invalid_programs/immutable_mutation.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let mut sources = SourceCache::default();
+    sources.insert(name, source);
+    let diagnostic = lowering_error_to_diagnostic(error);
+    let json = diagnostic.to_json(&mut sources);
+
+    assert_eq!(json["code"], "NotMutable");
+    assert_eq!(json["severity"], "error");
+    assert_eq!(json["file"], name);
+    assert_eq!(json["span"]["from"], diagnostic.span.from);
+    assert_eq!(json["span"]["to"], diagnostic.span.to);
+    assert_eq!(json["span"]["line"], 4);
+    assert_eq!(json["suggestions"][0]["replacement"], "mut x");
+    assert!(!json.to_string().contains('\n'));
+}
+
+#[test]
+fn syntax_error_suggestions() {
+    let suggest = |source: &str| {
+        let program = ProgramSource::new(source.to_string(), Path::new("main.con"));
+        let Err(error) = concrete::parser::parse_ast(&program) else {
+            panic!("expected a syntax error");
+        };
+        let diagnostic = error.to_diagnostic(&program);
+
+        let mut sources = SourceCache::default();
+        sources.insert("main.con", source);
+        let json = diagnostic.to_json(&mut sources);
+
+        let mut fixed = source.to_string();
+        apply_suggestions(&mut fixed, &diagnostic.suggestions);
+        (json["suggestions"][0].clone(), fixed)
+    };
+
+    let (suggestion, fixed) = suggest(
+        "mod Simple {\n    fn main() -> i32 {\n        let x: i32 = 2 // two\n        return x;\n    }\n}\n",
+    );
+    assert_eq!(suggestion["replacement"], ";");
+    assert_eq!(suggestion["file"], "main.con");
+    assert_eq!(suggestion["span"]["line"], 3);
+    assert_eq!(suggestion["machine_applicable"], true);
+    assert!(fixed.contains("let x: i32 = 2; // two"), "{}", fixed);
+
+    let (suggestion, fixed) = suggest(
+        "mod Simple {\n    fn main() -> i32 {\n        let x: i32 = 2;\n        if x = 2 {\n            return 1;\n        }\n        return 0;\n    }\n}\n",
+    );
+    assert_eq!(suggestion["replacement"], "==");
+    assert_eq!(suggestion["machine_applicable"], false);
+    assert!(fixed.contains("if x = 2"), "{}", fixed);
+}
+
+#[test]
+fn call_param_count_mismatch_other_file() {
+    let library =
+        "mod Library {\n    pub fn add(a: i32, b: i32) -> i32 {\n        return a + b;\n    }\n}\n";
+    let main = "mod Main {\n    import Library.{add};\n\n    fn main() -> i32 {\n        return add(1);\n    }\n}\n";
+    let units = [
+        parse_program(library, "library.con"),
+        parse_program(main, "main.con"),
+    ];
+
+    let error = lower_compile_units(&units).expect_err("expected error");
+    let diagnostic = lowering_error_to_diagnostic(error);
+
+    assert_eq!(diagnostic.path, Path::new("main.con"));
+    let declared = diagnostic
+        .labels
+        .iter()
+        .find(|x| x.path == Path::new("library.con"))
+        .expect("expected a label in the library file");
+    assert_eq!(&library[declared.span.from..declared.span.to], "add");
+
+    let mut sources = SourceCache::default();
+    sources.insert("library.con", library);
+    sources.insert("main.con", main);
+    let mut rendered = Vec::new();
+    diagnostic
+        .to_report()
+        .write(&mut sources, &mut rendered)
+        .unwrap();
+    let rendered = String::from_utf8_lossy(&rendered);
+
+    assert!(rendered.contains("library.con"), "{}", rendered);
+    assert!(rendered.contains("main.con"), "{}", rendered);
+    assert!(rendered.contains("function declared here"), "{}", rendered);
+}
+
+#[test]
+fn immutable_mutation_short() {
+    let (source, name) = (
+        include_str!("../invalid_programs/immutable_mutation.con"),
+        "invalid_programs/immutable_mutation.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let mut sources = SourceCache::default();
+    sources.insert(name, source);
+
+    assert_eq!(
+        lowering_error_to_diagnostic(error).to_short(&mut sources),
+        "invalid_programs/immutable_mutation.con:4:9: error[NotMutable]: can't mutate this variable because it's not mutable"
+    );
+}
+
+#[test]
+fn warnings_sent_before_error() {
+    let program = parse_program(
+        "mod Simple {\n    #[tset]\n    fn helper() -> i32 {\n        return 0;\n    }\n\n    fn main() -> i32 {\n        let x: i32 = 2;\n        x = 4;\n        return x;\n    }\n}\n",
+        "warnings_before_error.con",
+    );
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let error =
+        lower_compile_units_with_sink(&[program], Some(sender)).expect_err("expected error");
+    let warnings: Vec<_> = receiver.try_iter().collect();
+
+    assert!(
+        matches!(error, LoweringError::NotMutable { .. }),
+        "{:#?}",
+        error
+    );
+    assert_eq!(warnings.len(), 1, "{:#?}", warnings);
+    assert_eq!(warnings[0].message, "unknown attribute 'tset'");
+}
+
+#[test]
+fn source_encoding() {
+    use concrete::parser::{error::SourceError, read_source};
+
+    let dir = tempfile::tempdir().unwrap();
+
+    let bom = dir.path().join("bom.con");
+    std::fs::write(&bom, b"\xEF\xBB\xBFmod Simple {}\n").unwrap();
+    let source = read_source(&bom).unwrap();
+    assert_eq!(source, "   mod Simple {}\n");
+    let program = ProgramSource::new(source, &bom);
+    assert!(concrete::parser::parse_ast(&program).is_ok());
+
+    let invalid = dir.path().join("invalid.con");
+    std::fs::write(&invalid, b"mod Simple {\n    // caf\xE9\n}\n").unwrap();
+    let error = read_source(&invalid).unwrap_err();
+    assert!(
+        matches!(
+            error,
+            SourceError::InvalidUtf8 {
+                offset: 23,
+                byte: 0xE9,
+                ..
+            }
+        ),
+        "{:#?}",
+        error
+    );
+    let (diagnostic, lossy) = error.to_diagnostic().expect("expected a diagnostic");
+    assert_eq!(diagnostic.code, "P6");
+    assert_eq!(
+        &lossy[diagnostic.span.from..diagnostic.span.to],
+        char::REPLACEMENT_CHARACTER.to_string()
+    );
+
+    let binary = dir.path().join("binary.con");
+    std::fs::write(&binary, b"\x7FELF\x02\x01\x01\x00\x00").unwrap();
+    let error = read_source(&binary).unwrap_err();
+    assert!(
+        matches!(error, SourceError::Binary { offset: 7, .. }),
+        "{:#?}",
+        error
+    );
+    assert!(error.to_diagnostic().is_none());
+}
+
+#[test]
+fn check_str_diagnostics() {
+    let output = concrete::check_str(include_str!("../invalid_programs/immutable_mutation.con"));
+
+    assert!(output.has_errors());
+    assert_eq!(output.diagnostics.len(), 1, "{:#?}", output.diagnostics);
+    assert_eq!(output.diagnostics[0].code, "NotMutable");
+    assert_eq!(
+        output.diagnostics[0].path,
+        Path::new(concrete::session::DEFAULT_SOURCE_PATH)
+    );
+}
+
+#[test]
+fn lint_attributes_set_levels() {
+    use concrete::check::lints::{Lint, LintLevel, LintLevels};
+
+    let ir = lower_program(
+        "mod Simple {
+    #[deny(unused_variables)]
+    fn strict(x: i32) -> i32 {
+        return 0;
+    }
+
+    fn main() -> i32 {
+        let y: i32 = 1;
+        return strict(2);
+    }
+}
+",
+        "lint_attributes.con",
+    )
+    .expect("failed to lower");
+
+    let levels = LintLevels::default();
+    let level_of = |name: &str| {
+        let warning = ir
+            .warnings
+            .iter()
+            .find(|x| x.lint == Lint::UnusedVariables && x.message.contains(name))
+            .unwrap_or_else(|| panic!("no warning about {name}: {:#?}", ir.warnings));
+        levels.warning_level(warning)
+    };
+    assert_eq!(level_of("'x'"), LintLevel::Deny);
+    assert_eq!(level_of("'y'"), LintLevel::Warn);
+
+    // The attributes take precedence over the levels of the command line.
+    let mut levels = LintLevels::default();
+    levels.set(Lint::UnusedVariables, LintLevel::Allow);
+    let warning = ir
+        .warnings
+        .iter()
+        .find(|x| x.message.contains("'x'"))
+        .unwrap();
+    assert_eq!(levels.warning_level(warning), LintLevel::Deny);
+}
@@ -0,0 +1,86 @@
+use concrete::parser::ProgramSource;
+use concrete::parser::format::format_source;
+
+use crate::assert_formats;
+
+#[test]
+fn fmt_formats_source() {
+    let source = r#"// Header comment.
+
+mod Example {
+    /// Adds.
+    pub fn add(a:i32,b:i32)->i32{
+        let x:i32=(a+b)*2; // Trailing.
+
+        /* Block
+           comment. */
+        if x>0 {
+            return  x;
+        }
+        else {
+            // Otherwise.
+            return -(x+1) as i32;
+        }
+    }
+    fn deref(a: & &i32) -> i32 { return **a; }
+}"#;
+    let expected = r#"// Header comment.
+
+mod Example {
+    /// Adds.
+    pub fn add(a: i32, b: i32) -> i32 {
+        let x: i32 = (a + b) * 2; // Trailing.
+
+        /* Block
+           comment. */
+        if x > 0 {
+            return x;
+        } else {
+            // Otherwise.
+            return -(x + 1) as i32;
+        }
+    }
+    fn deref(a: & &i32) -> i32 {
+        return **a;
+    }
+}
+"#;
+
+    assert_formats(source, expected);
+}
+
+#[test]
+fn fmt_is_idempotent_on_examples() {
+    use concrete::parser::format::FormatError;
+
+    // The examples kept with outdated syntax, which don't parse.
+    let outdated = ["enum.con", "factorial.con"];
+    let mut formatted_files = 0;
+
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|x| x != "con") {
+            continue;
+        }
+        let is_outdated = outdated.contains(&&*path.file_name().unwrap().to_string_lossy());
+
+        let source = ProgramSource::new(std::fs::read_to_string(&path).unwrap(), &path);
+        let formatted = match format_source(&source) {
+            Ok(_) if is_outdated => panic!(
+                "{} parses, remove it from the outdated examples",
+                path.display()
+            ),
+            Ok(formatted) => formatted,
+            Err(FormatError::Syntax(_)) if is_outdated => continue,
+            Err(FormatError::Syntax(_)) => panic!("failed to parse {}", path.display()),
+            Err(FormatError::Changed) => panic!("formatting {} changes it", path.display()),
+        };
+
+        let again = format_source(&ProgramSource::new(formatted.clone(), &path))
+            .unwrap_or_else(|_| panic!("failed to format {} again", path.display()));
+        assert_eq!(formatted, again, "{}", path.display());
+        formatted_files += 1;
+    }
+
+    assert!(formatted_files > 0);
+}
@@ -0,0 +1,441 @@
+use std::path::Path;
+
+use concrete::check::suggestions::{Applicability, apply_suggestions};
+use concrete::ir::lowering::LoweringError;
+
+use crate::{assert_formats, check_invalid_program, lower_program, parse_program};
+
+#[test]
+fn module_not_found() {
+    let (source, name) = (
+        include_str!("../invalid_programs/import1.con"),
+        "invalid_programs/import1.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(
+            &error,
+            LoweringError::ModuleNotFound { span: _, module, .. } if module == "Other"
+        ),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn import_not_found() {
+    let (source, name) = (
+        include_str!("../invalid_programs/import2.con"),
+        "invalid_programs/import2.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(
+            &error,
+            LoweringError::ImportNotFound { symbol, .. } if symbol.name == "world"
+        ),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn private_item() {
+    let (source, name) = (
+        include_str!("../invalid_programs/private_item.con"),
+        "invalid_programs/private_item.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let LoweringError::PrivateItem(error) = error else {
+        panic!("{:#?}", error);
+    };
+    assert_eq!(error.kind, "function");
+    assert_eq!(error.name, "secret");
+    assert_eq!(&source[error.span.from..error.span.to], "secret");
+    assert_eq!(&source[error.def_span.from..error.def_span.to], "secret");
+    assert!(error.def_span.from < error.span.from);
+}
+
+#[test]
+fn private_item_fix() {
+    let (source, name) = (
+        include_str!("../invalid_programs/private_item.con"),
+        "invalid_programs/private_item.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let suggestions = error.suggestions();
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!(
+        suggestions[0].applicability,
+        Applicability::MachineApplicable
+    );
+
+    let mut fixed = source.to_string();
+    assert_eq!(apply_suggestions(&mut fixed, &suggestions), 1);
+    assert!(fixed.contains("pub fn secret() -> i64"), "{}", fixed);
+
+    lower_program(&fixed, name).expect("fixed program should lower");
+}
+
+#[test]
+fn ambiguous_glob_import() {
+    let (source, name) = (
+        include_str!("../invalid_programs/ambiguous_glob.con"),
+        "invalid_programs/ambiguous_glob.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let LoweringError::AmbiguousImport(error) = error else {
+        panic!("{:#?}", error);
+    };
+    assert_eq!(error.name, "hello");
+    assert_eq!(&source[error.span.from..error.span.to], "*");
+    assert_eq!(&source[error.def_span.from..error.def_span.to], "hello");
+    assert!(error.def_span.from < source.find("mod Other").unwrap());
+}
+
+#[test]
+fn import_aliases_and_globs() {
+    let text = "mod Main {
+    import Shapes.{Point as P, make as new_point, Pair as Couple, identity as id};
+    import Shapes.{*};
+
+    pub fn main() -> i32 {
+        let p: P = new_point(1, 2);
+        let c: Couple<i32> = Couple::<i32> {
+            a: 1,
+            b: 2,
+        };
+        return id::<i32>(p.x) + c.b + LIMIT + twice(2);
+    }
+}
+
+mod Shapes {
+    pub const LIMIT: i32 = 10;
+
+    pub struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    pub struct Pair<T> {
+        a: T,
+        b: T,
+    }
+
+    pub fn make(x: i32, y: i32) -> Point {
+        let p: Point = Point {
+            x: x,
+            y: y,
+        };
+        return p;
+    }
+
+    pub fn identity<T>(value: T) -> T {
+        return value;
+    }
+
+    pub fn twice(x: i32) -> i32 {
+        return x * 2;
+    }
+}
+";
+    lower_program(text, "main.con").expect("the program is valid");
+    assert_formats(text, text);
+}
+
+#[test]
+fn compile_session_in_memory_modules() {
+    let mut session = concrete::CompileSession::new(concrete::compile_unit_info::CompileUnitInfo {
+        debug_info: concrete::compile_unit_info::DebugInfo::None,
+        optlevel: concrete::compile_unit_info::OptLevel::None,
+        library: false,
+        output_file: "app/main.o".into(),
+        output_mlir: false,
+        output_ll: false,
+        output_asm: false,
+        fuzzing: false,
+        unchecked_indexing: false,
+        target: concrete::compile_unit_info::TargetInfo::default(),
+        lto: concrete::compile_unit_info::Lto::Off,
+        sanitizers: Vec::new(),
+        coverage: false,
+        freestanding: false,
+    });
+    session.add_source(
+        "app/main.con",
+        "mod Main {\n    mod Other;\n\n    #[tset]\n    fn main() -> i32 {\n        return 0;\n    }\n}\n",
+    );
+    session.add_module_source(
+        "app/Other.con",
+        "mod Other {\n    pub fn value() -> i32 {\n        return 1;\n    }\n}\n",
+    );
+
+    let output = session.check();
+    assert!(!output.has_errors(), "{:#?}", output.diagnostics);
+    assert_eq!(output.diagnostics.len(), 1, "{:#?}", output.diagnostics);
+    assert_eq!(output.diagnostics[0].code, "unknown_attributes");
+
+    session.add_source("app/broken.con", "mod Broken {\n    mod Missing;\n}\n");
+    let output = session.check();
+    assert!(output.has_errors());
+    assert!(
+        output
+            .diagnostics
+            .iter()
+            .any(|x| x.code == "ModuleFileNotFound" && x.path == Path::new("app/broken.con")),
+        "{:#?}",
+        output.diagnostics
+    );
+}
+
+#[test]
+fn module_graph_resolves_imports() {
+    use concrete::driver::graph::{EdgeKind, Graph, GraphFormat};
+
+    let program = parse_program(
+        "mod App {
+            import Std.io.{print};
+            import Util.{helper};
+
+            mod Util {
+                import super.{run};
+
+                pub fn helper() -> i32 {
+                    return 1;
+                }
+            }
+
+            pub fn run() -> i32 {
+                return helper();
+            }
+        }
+
+        mod Other {
+            import App.Util.{helper};
+        }",
+        "main.con",
+    );
+    let graph = Graph::modules(&[program]);
+
+    let edges: Vec<_> = graph.edges().collect();
+    assert_eq!(
+        edges,
+        [
+            ("App", "App::Util", EdgeKind::Contains),
+            ("App", "App::Util", EdgeKind::Imports),
+            ("App", "Std::io", EdgeKind::Imports),
+            ("App::Util", "App", EdgeKind::Imports),
+            ("Other", "App::Util", EdgeKind::Imports),
+        ]
+    );
+
+    let dot = graph.render(GraphFormat::Dot);
+    assert!(dot.contains("    \"Std::io\" [style=dashed];"), "{}", dot);
+    assert!(dot.contains("    \"Other\" -> \"App::Util\";"), "{}", dot);
+
+    let mermaid = graph.render(GraphFormat::Mermaid);
+    assert!(
+        mermaid.starts_with("graph LR\n    n0[\"App\"]\n"),
+        "{}",
+        mermaid
+    );
+    assert!(mermaid.contains("    n3([\"Std::io\"])"), "{}", mermaid);
+    assert!(mermaid.contains("    n0 -.- n1"), "{}", mermaid);
+}
+
+#[test]
+fn generic_methods_from_other_modules() {
+    use concrete::ir::ConcreteIntrinsic;
+
+    let ir = lower_program(
+        "mod Collections {
+            #[intrinsic = \"bounds_checks\"]
+            pub fn bounds_checks() -> bool;
+
+            pub struct Stack<T> {
+                top: T,
+                len: u64,
+            }
+
+            impl<T> Stack<T> {
+                pub fn peek(&self) -> &T {
+                    if bounds_checks() {
+                        self.check();
+                    }
+                    return &self.top;
+                }
+
+                fn check(&self) -> u64 {
+                    return self.len;
+                }
+            }
+        }
+
+        mod Main {
+            import Collections.{Stack};
+
+            fn main() -> i32 {
+                let stack: Stack<i32> = Stack::<i32> {
+                    top: 1,
+                    len: 1,
+                };
+                let top: &i32 = stack.peek();
+                return *top;
+            }
+        }",
+        "stack.con",
+    )
+    .expect("the program is valid");
+
+    let functions: Vec<_> = ir
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .collect();
+    assert!(
+        functions
+            .iter()
+            .any(|x| x.is_intrinsic == Some(ConcreteIntrinsic::BoundsChecks))
+    );
+    assert!(
+        functions
+            .iter()
+            .any(|x| x.debug_name.as_deref() == Some("Collections::check"))
+    );
+}
+
+#[test]
+fn imported_generic_enums_share_instances() {
+    lower_program(
+        "mod Options {
+            pub enum Option<T> {
+                Some {
+                    value: T,
+                },
+                None,
+            }
+        }
+
+        mod Wrap {
+            import Options.{Option};
+
+            pub fn some<T>(value: T) -> Option<T> {
+                let some: Option<T> = Option::<T>#Some {
+                    value: value,
+                };
+                return some;
+            }
+        }
+
+        mod Main {
+            import Options.{Option};
+            import Wrap.{some};
+
+            fn main() -> i32 {
+                let x: Option<u8> = some::<u8>(1);
+                return 0;
+            }
+        }",
+        "generic_import.con",
+    )
+    .expect("`Option<u8>` is the same type in both modules");
+}
+
+#[test]
+fn type_aliases_imported_before_their_module_is_lowered() {
+    lower_program(
+        "mod Main {
+            import Other.{Count};
+
+            fn main() -> i32 {
+                let x: Count = 1;
+                return x as i32;
+            }
+        }
+
+        mod Other {
+            pub type Count = u64;
+        }",
+        "alias_import.con",
+    )
+    .expect("`Count` is lowered when `Main` uses it");
+}
+
+#[test]
+fn generics_are_resolved_in_the_modules_naming_them() {
+    let lower = |text: &str| lower_program(text, "generic_modules.con");
+
+    // `Point` is named in `Main`, not in the module of `Wrapper`.
+    lower(
+        "mod Main {
+            import Other.{Wrapper};
+
+            struct Point {
+                x: i32,
+            }
+
+            fn get(w: &Wrapper<Point>) -> i32 {
+                return w.value.x;
+            }
+
+            fn main() -> i32 {
+                return 0;
+            }
+        }
+
+        mod Other {
+            pub struct Wrapper<T> {
+                pub value: T,
+            }
+        }",
+    )
+    .expect("the generic argument is a type of `Main`");
+
+    // The bound `Task` is named in `Other`, `Main` doesn't import it.
+    lower(
+        "mod Jobs {
+            import Other.{Task};
+
+            pub struct Job {
+                x: i32,
+            }
+
+            impl Task for Job {
+                fn run(&self) -> i32 {
+                    return self.x;
+                }
+            }
+
+            pub fn job() -> Job {
+                let j: Job = Job { x: 1 };
+                return j;
+            }
+        }
+
+        mod Other {
+            pub trait Task {
+                fn run(&self) -> i32;
+            }
+
+            pub fn run_it<T: Task>(task: T) -> i32 {
+                return task.run();
+            }
+        }
+
+        mod Main {
+            import Other.{run_it};
+            import Jobs.{Job, job};
+
+            fn main() -> i32 {
+                let j: Job = job();
+                return run_it(j);
+            }
+        }",
+    )
+    .expect("the bound is a trait of `Other`");
+}
@@ -0,0 +1,490 @@
+use concrete::ir::lowering::{LoweringError, lower_compile_units};
+
+use crate::{assert_formats, check_invalid_program, lower_program, parse_program};
+
+#[test]
+fn cfg_removes_disabled_items() {
+    use concrete::ast::cfg::Cfg;
+
+    let program = parse_program(
+        "mod Main {
+            #[cfg(target_os = \"linux\")]
+            fn name() -> i32 {
+                return 1;
+            }
+
+            #[cfg(not(target_os = \"linux\"))]
+            fn name() -> i32 {
+                return 2;
+            }
+
+            #[cfg(all(test, feature = \"extra\"))]
+            fn extra_test() -> i32 {
+                return missing();
+            }
+
+            #[cfg(any(test, profile = \"release\"))]
+            mod Inner {
+                fn inner() -> i32 {
+                    return 3;
+                }
+            }
+
+            fn main() -> i32 {
+                return name();
+            }
+        }",
+        "cfg.con",
+    );
+
+    let function_names = |cfg: Cfg| -> Vec<String> {
+        let mut unit = program.clone();
+        unit.cfg = cfg;
+        let ir = lower_compile_units(&[unit]).expect("the program is valid");
+        ir.functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref().and_then(|x| x.debug_name.clone()))
+            .collect()
+    };
+
+    let linux = function_names(Cfg::for_target(Some("x86_64-unknown-linux-gnu")));
+    assert!(linux.contains(&"Main::name".to_string()));
+    assert!(!linux.contains(&"Main::Inner::inner".to_string()));
+
+    let release = function_names(Cfg {
+        profile: "release".to_string(),
+        ..Cfg::for_target(Some("aarch64-apple-darwin"))
+    });
+    assert!(release.contains(&"Main::Inner::inner".to_string()));
+    assert!(!release.contains(&"Main::extra_test".to_string()));
+
+    let mut unit = program.clone();
+    unit.cfg.test = true;
+    unit.cfg.features.insert("extra".to_string());
+    assert!(matches!(
+        lower_compile_units(&[unit]),
+        Err(LoweringError::FunctionNotFound { function, .. }) if function == "missing"
+    ));
+
+    let error = check_invalid_program(
+        "mod Main {\n    #[cfg(target_sys = \"linux\")]\n    fn main() -> i32 {\n        return 0;\n    }\n}\n",
+        "cfg_unknown.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { name, reason, .. } if name == "cfg" && reason.contains("did you mean `target_os`?")),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn attributes_on_params_and_fields() {
+    use concrete::ast::modules::ModuleDefItem;
+
+    let text = r#"mod Main {
+    struct Point {
+        x: i32,
+        #[cfg(test)]
+        debug: i32,
+    }
+
+    fn add(a: i32, #[cfg(feature = "extra")] b: i32, #[tset] c: i32) -> i32 {
+        return a + c;
+    }
+
+    fn main() -> i32 {
+        let point: Point = Point {
+            x: 1,
+        };
+        return add(point.x, 2);
+    }
+}
+"#;
+    let program = parse_program(text, "attributes.con");
+
+    let ModuleDefItem::Function(add) = &program.modules[0].contents[1] else {
+        panic!("expected a function");
+    };
+    let params: Vec<_> = add.decl.params.iter().map(|x| &x.attributes).collect();
+    assert!(params[0].is_empty());
+    assert_eq!(params[1][0].name, "cfg");
+    assert_eq!(params[2][0].name, "tset");
+
+    // The disabled parameter and field are gone, the unknown attribute is kept with a warning.
+    let ir = lower_compile_units(&[program]).expect("the program is valid");
+    let (_, add) = ir
+        .functions
+        .iter()
+        .find(|(_, x)| {
+            x.as_ref()
+                .is_some_and(|x| x.debug_name.as_deref() == Some("Main::add"))
+        })
+        .unwrap();
+    assert_eq!(add.as_ref().unwrap().args.len(), 2);
+    let point = ir
+        .aggregates
+        .iter()
+        .find_map(|(_, x)| x.as_ref().filter(|x| x.name == "Point"))
+        .unwrap();
+    assert_eq!(point.variants[0].fields.len(), 1);
+    assert!(
+        ir.warnings
+            .iter()
+            .any(|x| x.message == "unknown attribute 'tset'"),
+        "{:#?}",
+        ir.warnings
+    );
+
+    assert_formats(text, text);
+}
+
+#[test]
+fn derive_implements_traits() {
+    let ir = lower_program(
+        r#"mod Traits {
+            pub trait Eq {
+                fn eq(&self, other: &Self) -> bool;
+            }
+
+            pub trait Clone {
+                fn clone(&self) -> Self;
+            }
+        }
+
+        mod Main {
+            import Traits.{Eq, Clone};
+
+            #[derive(Eq, Clone)]
+            struct Point {
+                x: i32,
+                y: i32,
+            }
+
+            #[derive(Eq, Clone)]
+            enum Shape {
+                Dot,
+                Circle { center: Point, radius: u64 },
+            }
+
+            fn main() -> i32 {
+                let a: Shape = Shape#Circle {
+                    center: Point {
+                        x: 1,
+                        y: 2,
+                    },
+                    radius: 3,
+                };
+                let b: Shape = a.clone();
+                if a.eq(&b) {
+                    return 0;
+                }
+                return 1;
+            }
+        }"#,
+        "derive.con",
+    )
+    .expect("the program is valid");
+
+    let count = |name: &str| {
+        ir.functions
+            .iter()
+            .filter(|(_, x)| {
+                x.as_ref()
+                    .is_some_and(|x| x.debug_name.as_deref() == Some(name))
+            })
+            .count()
+    };
+
+    // One implementation for the struct and one for the enum.
+    assert_eq!(count("Main::eq"), 2);
+    assert_eq!(count("Main::clone"), 2);
+    assert!(ir.warnings.is_empty(), "{:#?}", ir.warnings);
+
+    let error = check_invalid_program(
+        "mod Main {\n    #[derive(Eqq)]\n    struct A {\n        x: i32,\n    }\n\n    fn main() -> i32 {\n        return 0;\n    }\n}\n",
+        "derive_unknown.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { name, reason, .. }
+            if name == "derive" && reason.contains("did you mean `Eq`?")),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        "mod Main {\n    trait Eq {\n        fn eq(&self, other: &Self) -> bool;\n    }\n\n    #[derive(Eq)]\n    struct Wrapper<T> {\n        value: T,\n    }\n\n    fn main() -> i32 {\n        return 0;\n    }\n}\n",
+        "derive_generic.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::InvalidAttribute { name, .. } if name == "derive"),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn macros_expand_hygienically() {
+    let ir = lower_program(
+        r#"mod Utils {
+            pub macro square {
+                ($x:expr) => { $x * $x }
+            }
+
+            macro hidden {
+                () => { 0 }
+            }
+        }
+
+        mod Main {
+            import Utils.{square};
+
+            macro swap {
+                ($a:ident, $b:ident) => {
+                    let tmp: i32 = $a;
+                    $a = $b;
+                    $b = tmp;
+                }
+            }
+
+            fn main() -> i32 {
+                let mut tmp: i32 = 1;
+                let mut y: i32 = 2;
+                swap!(tmp, y);
+                return square!(tmp + 1) - y;
+            }
+        }"#,
+        "macros.con",
+    )
+    .expect("the program is valid");
+    assert!(ir.warnings.is_empty(), "{:#?}", ir.warnings);
+
+    // The `tmp` of the macro is another variable than the one of the caller.
+    let main = ir
+        .functions
+        .iter()
+        .find_map(|(_, x)| {
+            x.as_ref()
+                .filter(|x| x.debug_name.as_deref() == Some("main"))
+        })
+        .expect("main is lowered");
+    let names: Vec<_> = main
+        .locals
+        .iter()
+        .filter_map(|x| x.debug_name.as_deref())
+        .collect();
+    assert!(
+        names.contains(&"tmp") && names.contains(&"tmp$1"),
+        "{names:?}"
+    );
+
+    let program = |body: &str| {
+        format!(
+            "mod Main {{\n    macro square {{\n        ($x:expr) => {{ $x * $x }}\n    }}\n\n    macro bad {{\n        ($x:expr) => {{ $x + true }}\n    }}\n\n    fn main() -> i32 {{\n        {body}\n    }}\n}}\n"
+        )
+    };
+
+    let error = check_invalid_program(&program("return sqare!(2);"), "macro_unknown.con");
+    assert!(
+        matches!(&error, LoweringError::InvalidMacro { name, reason, .. }
+            if name == "sqare" && reason.contains("did you mean `square`?")),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(&program("return square!(1, 2);"), "macro_no_rule.con");
+    assert!(
+        matches!(&error, LoweringError::InvalidMacro { reason, .. }
+            if reason.contains("no rule of `square!` matches")),
+        "{error:#?}"
+    );
+
+    // The errors in the code of the macro point at the call, with the rule it comes from.
+    let error = check_invalid_program(&program("return bad!(1);"), "macro_expansion.con");
+    assert!(
+        matches!(&error, LoweringError::InMacroExpansion(expansion) if expansion.name == "bad"),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        "mod Utils {\n    macro hidden {\n        () => { 0 }\n    }\n}\n\nmod Main {\n    import Utils.{hidden};\n\n    fn main() -> i32 {\n        return hidden!();\n    }\n}\n",
+        "macro_private.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::PrivateItem(item) if item.kind == "macro"),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn constants_and_const_fn_are_evaluated() {
+    use concrete::ir::{ConstKind, ConstValue, Type, ValueTree};
+
+    let ir = lower_program(
+        "mod Main {
+            const SIDE: u64 = 3;
+            const CELLS: u64 = square(SIDE) + 1;
+
+            enum Level {
+                Low = 2,
+                Medium,
+                High = (CELLS as u32) * 2,
+            }
+
+            const fn square(value: u64) -> u64 {
+                let mut total: u64 = 0;
+                let mut i: u64 = 0;
+                while i < value {
+                    total = total + value;
+                    i = i + 1;
+                }
+                return total;
+            }
+
+            fn main() -> i32 {
+                let cells: [u8; CELLS - 1] = [0, 0, 0, 0, 0, 0, 0, 0, 0];
+                let level: Level = Level#Medium;
+                return 0;
+            }
+        }",
+        "const_fn.con",
+    )
+    .expect("the program is valid");
+
+    let cells = ir
+        .constants
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .find(|x| x.name == "CELLS")
+        .expect("CELLS should be lowered");
+    assert_eq!(
+        cells.value.data,
+        ConstKind::Value(ValueTree::Leaf(ConstValue::U64(10)))
+    );
+
+    let level = ir
+        .aggregates
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .find(|x| x.name == "Level")
+        .expect("Level should be lowered");
+    let discriminants: Vec<u32> = (0..3).map(|x| level.discriminant(x)).collect();
+    assert_eq!(discriminants, [2, 3, 20]);
+
+    let array_len = ir.types.iter().find_map(|(_, x)| match x {
+        Some(Type::Array(_, len)) => Some(len.data.clone()),
+        _ => None,
+    });
+    assert_eq!(
+        array_len,
+        Some(ConstKind::Value(ValueTree::Leaf(ConstValue::U64(9))))
+    );
+}
+
+#[test]
+fn format_checks_placeholders() {
+    use concrete::ir::TerminatorKind;
+
+    let prelude = r#"mod std {
+            mod fmt {
+                pub fn write_str(out: &mut String, value: String) {}
+                pub fn write_int(out: &mut String, value: i64) {}
+                pub fn write_uint(out: &mut String, value: u64) {}
+                pub fn write_float(out: &mut String, value: f64) {}
+                pub fn write_bool(out: &mut String, value: bool) {}
+                pub fn write_char(out: &mut String, value: char) {}
+            }
+
+            mod io {
+                pub fn write_stdout(value: &String) {}
+            }
+
+            mod string {
+                #[langitem = "String"]
+                pub struct String {
+                    ptr: *mut u8,
+                    len: u64,
+                    cap: u64,
+                }
+
+                impl String {
+                    pub fn push(&mut self, value: char) {}
+                    pub fn push_str(&mut self, other: &String) {}
+                    pub fn drop(self) {}
+                    pub fn fmt(&self, out: &mut String) {}
+                }
+            }
+        }"#;
+    let program = |body: &str| {
+        format!(
+            "{prelude}\n\nmod Main {{\n    struct Point {{\n        x: i32,\n    }}\n\n    fn main() -> i32 {{\n        let name: String = \"world\";\n        let point: Point = Point {{ x: 1 }};\n        {body}\n        return 0;\n    }}\n}}\n"
+        )
+    };
+
+    let ir = lower_program(
+        &program(
+            r#"let count: u8 = 3;
+        let greeting: String = format!("{{hello}} {}, {} times: {:?}", name, count, &name);
+        println!("{}{}", greeting, 'x');"#,
+        ),
+        "format.con",
+    )
+    .expect("the program is valid");
+
+    let main = ir
+        .functions
+        .iter()
+        .find_map(|(_, x)| {
+            x.as_ref()
+                .filter(|x| x.debug_name.as_deref() == Some("main"))
+        })
+        .expect("main is lowered");
+    let calls: Vec<_> = main
+        .basic_blocks
+        .iter()
+        .filter_map(|x| match &x.terminator.kind {
+            TerminatorKind::Call { func, .. } => ir.functions[*func]
+                .as_ref()
+                .and_then(|x| x.debug_name.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        calls,
+        [
+            "std::fmt::write_str",
+            "std::string::push_str",
+            "std::fmt::write_str",
+            "std::fmt::write_uint",
+            "std::fmt::write_str",
+            "std::string::fmt",
+            "std::string::push_str",
+            "std::string::push",
+            "std::string::push",
+            "std::io::write_stdout",
+            "std::string::drop",
+        ]
+    );
+
+    let invalid = |body: &str, path: &str| {
+        let error = check_invalid_program(&program(body), path);
+        match error {
+            LoweringError::InvalidMacro { reason, .. } => reason,
+            error => panic!("{error:#?}"),
+        }
+    };
+
+    let reason = invalid(r#"println!("{} {}", 1);"#, "format_count.con");
+    assert!(
+        reason.contains("the string has 2 placeholders, but 1 argument is given"),
+        "{reason}"
+    );
+    let reason = invalid(r#"println!("{x}", 1);"#, "format_placeholder.con");
+    assert!(reason.contains("unknown placeholder `{x}`"), "{reason}");
+    let reason = invalid(r#"print!(name);"#, "format_literal.con");
+    assert!(reason.contains("must be a string literal"), "{reason}");
+    let reason = invalid(r#"println!("{}", point);"#, "format_display.con");
+    assert!(
+        reason.contains("`{}` can't write a value of type `Point`"),
+        "{reason}"
+    );
+    let reason = invalid(r#"println!("{:?}", point);"#, "format_debug.con");
+    assert!(reason.contains("which `Point` doesn't"), "{reason}");
+    let reason = invalid(r#"format!("{}", 1);"#, "format_unused.con");
+    assert!(reason.contains("isn't used"), "{reason}");
+}
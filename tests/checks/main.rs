@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use concrete::ast::CompilationUnit;
+use concrete::ir::IR;
+use concrete::ir::lowering::{LoweringError, lower_compile_units};
+use concrete::parser::ProgramSource;
+use concrete::parser::format::format_source;
+
+mod borrows;
+mod codegen;
+mod concurrency;
+mod control_flow;
+mod diagnostics;
+mod format;
+mod imports;
+mod items;
+mod runtime_checks;
+mod tooling;
+mod types;
+
+/// Parses the program, which must parse.
+pub fn parse_program(source: &str, path: &str) -> CompilationUnit {
+    let source = ProgramSource::new(source.to_string(), Path::new(path));
+    concrete::parser::parse_ast(&source).unwrap_or_else(|_| panic!("failed to parse {path}"))
+}
+
+/// Parses the program and lowers it, the program must parse.
+pub fn lower_program(source: &str, path: &str) -> Result<IR, LoweringError> {
+    lower_compile_units(&[parse_program(source, path)])
+}
+
+pub fn check_invalid_program(source: &str, path: &str) -> LoweringError {
+    lower_program(source, path).expect_err("expected error")
+}
+
+/// Formats the program, checking it gives the expected source.
+pub fn assert_formats(source: &str, expected: &str) {
+    let source = ProgramSource::new(source.to_string(), Path::new("main.con"));
+    let formatted = format_source(&source).unwrap_or_else(|_| panic!("failed to format"));
+    assert_eq!(formatted, expected);
+}
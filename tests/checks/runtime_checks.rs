@@ -0,0 +1,301 @@
+use concrete::ir::lowering::LoweringError;
+
+use crate::{assert_formats, check_invalid_program, lower_program};
+
+#[test]
+fn indexing_and_slicing_are_bounds_checked() {
+    use concrete::ir::TerminatorKind;
+
+    let ir = lower_program(
+        "mod Main {
+            fn first(values: [i32]) -> i32 {
+                return values[0];
+            }
+
+            fn main() -> i32 {
+                let values: [i32; 4] = [1, 2, 3, 4];
+                let rest: [i32] = values[1..3];
+                let all: [i32] = values[..];
+                return first(rest) + first(all);
+            }
+        }",
+        "slices.con",
+    )
+    .expect("the program is valid");
+
+    let bounds_checks = |name: &str| {
+        ir.functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref())
+            .find(|x| x.debug_name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{name} should be lowered"))
+            .basic_blocks
+            .iter()
+            .filter(|x| matches!(x.terminator.kind, TerminatorKind::BoundsCheck { .. }))
+            .count()
+    };
+
+    assert_eq!(bounds_checks("Main::first"), 1);
+    // `start <= end` and `end <= len` for the first slice, nothing to check for the second.
+    assert_eq!(bounds_checks("main"), 2);
+}
+
+#[test]
+fn divisions_and_raw_pointers_have_undefined_checks() {
+    use concrete::ir::{TerminatorKind, UndefinedCheck};
+
+    let ir = lower_program(
+        "mod Main {
+            fn divide(x: i32, y: i32) -> i32 {
+                return x / y + x % y;
+            }
+
+            fn halve(x: f64) -> f64 {
+                return x / 2.0;
+            }
+
+            unsafe fn replace(ptr: *mut i32, value: i32) -> i32 {
+                let old: i32 = *ptr;
+                *ptr = value;
+                return old;
+            }
+
+            fn main() -> i32 {
+                return divide(7, 2);
+            }
+        }",
+        "undefined_checks.con",
+    )
+    .expect("the program is valid");
+
+    let undefined_checks = |name: &str| -> Vec<UndefinedCheck> {
+        ir.functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref())
+            .find(|x| x.debug_name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{name} should be lowered"))
+            .basic_blocks
+            .iter()
+            .filter_map(|x| match &x.terminator.kind {
+                TerminatorKind::UndefinedCheck { check, .. } => Some(check.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let divide = undefined_checks("Main::divide");
+    assert_eq!(divide.len(), 2);
+    assert!(
+        divide
+            .iter()
+            .all(|x| matches!(x, UndefinedCheck::DivisionByZero(_)))
+    );
+    // Floats divide by zero to infinity or NaN.
+    assert!(undefined_checks("Main::halve").is_empty());
+    assert!(matches!(
+        undefined_checks("Main::replace")[..],
+        [UndefinedCheck::Load(_), UndefinedCheck::Store(_)]
+    ));
+}
+
+#[test]
+fn for_in_loops_call_next_and_skip_bounds_checks() {
+    use concrete::ir::TerminatorKind;
+
+    let ir = lower_program(
+        "mod Main {
+            enum Option<T> {
+                Some {
+                    value: T,
+                },
+                None,
+            }
+
+            struct Once {
+                done: bool,
+            }
+
+            impl Once {
+                fn next(&mut self) -> Option<i32> {
+                    if self.done {
+                        let none: Option<i32> = Option::<i32>#None;
+                        return none;
+                    }
+                    self.done = true;
+                    let some: Option<i32> = Option::<i32>#Some { value: 1 };
+                    return some;
+                }
+            }
+
+            fn sum(values: [i32]) -> i32 {
+                let mut total: i32 = 0;
+                for x in values {
+                    total = total + x;
+                }
+                for i in 0..3 {
+                    total = total + i;
+                }
+                return total;
+            }
+
+            fn main() -> i32 {
+                let mut once: Once = Once { done: false };
+                let mut total: i32 = 0;
+                for x in once {
+                    total = total + x;
+                }
+                return total;
+            }
+        }",
+        "for_in.con",
+    )
+    .expect("the program is valid");
+
+    let function = |name: &str| {
+        ir.functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref())
+            .find(|x| x.debug_name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{name} should be lowered"))
+    };
+
+    // The index of an array or slice loop is always in bounds.
+    assert!(
+        !function("Main::sum")
+            .basic_blocks
+            .iter()
+            .any(|x| matches!(x.terminator.kind, TerminatorKind::BoundsCheck { .. }))
+    );
+
+    let calls_to_next = function("main")
+        .basic_blocks
+        .iter()
+        .filter(|x| match &x.terminator.kind {
+            TerminatorKind::Call { func, .. } => ir.functions[*func]
+                .as_ref()
+                .and_then(|x| x.debug_name.as_deref())
+                .is_some_and(|x| x.ends_with("next")),
+            _ => false,
+        })
+        .count();
+    assert_eq!(calls_to_next, 1);
+}
+
+#[test]
+fn integer_arithmetic_checks_overflows() {
+    use concrete::ir::TerminatorKind;
+
+    let ir = lower_program(
+        "mod Main {
+            fn area(width: u32, height: u32) -> u32 {
+                return width * height;
+            }
+
+            fn scale(value: f64) -> f64 {
+                return value * 2.0;
+            }
+
+            fn main() -> i32 {
+                let x: i32 = 1 + 2;
+                return x - 1;
+            }
+        }",
+        "overflow.con",
+    )
+    .expect("the program is valid");
+
+    let checks = |name: &str| {
+        ir.functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref())
+            .find(|x| x.debug_name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{name} should be lowered"))
+            .basic_blocks
+            .iter()
+            .filter(|x| matches!(x.terminator.kind, TerminatorKind::OverflowCheck { .. }))
+            .count()
+    };
+    assert_eq!(checks("Main::area"), 1);
+    assert_eq!(checks("main"), 2);
+    // Floats don't overflow.
+    assert_eq!(checks("Main::scale"), 0);
+}
+
+#[test]
+fn wrapping_arithmetic_is_only_on_integers() {
+    let error = check_invalid_program(
+        "mod Main {
+            #[intrinsic = \"wrapping_add\"]
+            fn wrapping_add<T>(a: T, b: T) -> T;
+
+            fn main() -> i32 {
+                let x: f64 = wrapping_add::<f64>(1.0, 2.0);
+                return 0;
+            }
+        }",
+        "wrapping_float.con",
+    );
+    assert!(
+        matches!(
+            &error,
+            LoweringError::UnexpectedType(x) if x.found == "f64" && x.expected == "an integer type"
+        ),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn raw_pointer_operations_need_unsafe() {
+    let text = include_str!("../../examples/raw_pointers.con");
+    assert_formats(text, text);
+
+    lower_program(text, "raw_pointers.con").expect("the operations are in unsafe code");
+
+    let cases = [
+        ("let copy: i32 = *ptr;", "dereferencing a raw pointer"),
+        ("*ptr = 1;", "dereferencing a raw pointer"),
+        ("let next: *mut i32 = ptr + 1;", "offsetting a raw pointer"),
+        ("read(ptr);", "calling the unsafe function `read`"),
+    ];
+    for (statement, expected) in cases {
+        let error = check_invalid_program(
+            &format!(
+                "mod Main {{
+                    unsafe fn read(ptr: *mut i32) -> i32 {{
+                        return *ptr;
+                    }}
+
+                    fn main() -> i32 {{
+                        let mut value: i32 = 0;
+                        let ptr: *mut i32 = &mut value as *mut i32;
+                        {statement}
+                        return 0;
+                    }}
+                }}"
+            ),
+            "unsafe.con",
+        );
+        assert!(
+            matches!(&error, LoweringError::UnsafeOperation { operation, .. } if operation == expected),
+            "{error:#?}"
+        );
+    }
+
+    // The statements of a defer are as unsafe as the code declaring it.
+    lower_program(
+        "mod Main {
+            fn main() -> i32 {
+                let mut value: i32 = 0;
+                let ptr: *mut i32 = &mut value as *mut i32;
+                unsafe {
+                    defer {
+                        *ptr = 1;
+                    }
+                }
+                return value;
+            }
+        }",
+        "unsafe_defer.con",
+    )
+    .expect("the defer is declared in unsafe code");
+}
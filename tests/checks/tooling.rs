@@ -0,0 +1,673 @@
+use std::path::Path;
+
+use concrete::ir::IR;
+use concrete::ir::lowering::{LoweringError, lower_compile_units};
+
+use crate::{lower_program, parse_program};
+
+#[test]
+fn bindgen_generates_valid_module() {
+    use concrete::driver::bindgen::{CDecl, CType, generate_module};
+
+    let int = CType::Int {
+        signed: true,
+        bits: 32,
+    };
+    let decls = [
+        CDecl::Struct {
+            name: "point".to_string(),
+            fields: vec![
+                ("x".to_string(), int.clone()),
+                ("y".to_string(), int.clone()),
+            ],
+        },
+        CDecl::Typedef {
+            name: "point_t".to_string(),
+            ty: CType::Named("point".to_string()),
+        },
+        CDecl::Enum {
+            name: "color".to_string(),
+            ty: CType::Int {
+                signed: false,
+                bits: 32,
+            },
+            variants: vec![("RED".to_string(), 0), ("GREEN".to_string(), 1)],
+        },
+        CDecl::Function {
+            name: "move_point".to_string(),
+            params: vec![
+                (
+                    "p".to_string(),
+                    CType::Pointer {
+                        pointee: Box::new(CType::Named("point".to_string())),
+                        is_const: false,
+                    },
+                ),
+                ("type".to_string(), int.clone()),
+                (
+                    String::new(),
+                    CType::Array {
+                        of: Box::new(CType::Float { bits: 64 }),
+                        len: 2,
+                    },
+                ),
+            ],
+            ret: CType::Void,
+            is_variadic: false,
+        },
+        CDecl::Function {
+            name: "printf".to_string(),
+            params: vec![(
+                "format".to_string(),
+                CType::Pointer {
+                    pointee: Box::new(CType::Int {
+                        signed: false,
+                        bits: 8,
+                    }),
+                    is_const: true,
+                },
+            )],
+            ret: int,
+            is_variadic: true,
+        },
+    ];
+
+    let generated = generate_module("geometry", &decls);
+    assert!(
+        generated.contains("pub extern fn move_point(p: *mut point, type_: i32, arg2: [f64; 2]);"),
+        "{}",
+        generated
+    );
+    assert!(
+        generated.contains("// skipped `printf`: variadic functions are not supported"),
+        "{}",
+        generated
+    );
+
+    lower_program(&generated, "geometry.con").expect("expected the generated module to lower");
+}
+
+#[test]
+fn debug_adapter_rewrites_function_names() {
+    use concrete::driver::debug::{SymbolMap, read_message, write_message};
+    use serde_json::json;
+
+    let ir = lower_program(
+        "mod Main {
+            fn add(a: i32, b: i32) -> i32 {
+                return a + b;
+            }
+
+            fn main() -> i32 {
+                return add(1, 2);
+            }
+        }",
+        "main.con",
+    )
+    .expect("failed to lower");
+    let symbols = SymbolMap::from_ir(&ir);
+
+    let symbol = symbols
+        .mangle("Main::add")
+        .expect("expected a symbol for Main::add")
+        .to_string();
+    assert_eq!(symbols.demangle(&symbol), Some("Main::add"));
+    assert_eq!(symbols.mangle("main"), None);
+
+    let mut request = json!({
+        "type": "request",
+        "command": "setFunctionBreakpoints",
+        "arguments": { "breakpoints": [{ "name": "Main::add" }, { "name": "main" }] },
+    });
+    symbols.rewrite_request(&mut request, Path::new("main"));
+    assert_eq!(
+        request["arguments"]["breakpoints"][0]["name"],
+        symbol.as_str()
+    );
+    assert_eq!(request["arguments"]["breakpoints"][1]["name"], "main");
+
+    let mut response = json!({
+        "type": "response",
+        "command": "stackTrace",
+        "body": { "stackFrames": [{ "id": 1, "name": symbol }, { "id": 2, "name": "main" }] },
+    });
+    symbols.rewrite_response(&mut response);
+    assert_eq!(response["body"]["stackFrames"][0]["name"], "Main::add");
+    assert_eq!(response["body"]["stackFrames"][1]["name"], "main");
+
+    let mut framed = Vec::new();
+    write_message(&mut framed, &request).unwrap();
+    write_message(&mut framed, &response).unwrap();
+    let mut reader = std::io::Cursor::new(framed);
+    assert_eq!(read_message(&mut reader).unwrap(), Some(request));
+    assert_eq!(read_message(&mut reader).unwrap(), Some(response));
+    assert_eq!(read_message(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn js_bindings_export_public_functions() {
+    use concrete::driver::js_bindings::generate_bindings;
+
+    let ir = lower_program(
+        "mod Math {
+            struct Point {
+                x: i32,
+                y: i32,
+            }
+
+            pub fn add(a: i32, b: u32) -> u32 {
+                return (a as u32) + b;
+            }
+
+            pub fn keep(value: i64, flag: bool) -> bool {
+                return flag;
+            }
+
+            pub fn norm(point: Point) -> i32 {
+                return point.x + point.y;
+            }
+
+            fn private(new: i32) -> i32 {
+                return new;
+            }
+
+            mod Nested {
+                pub fn double(new: u64) -> u64 {
+                    return new * 2;
+                }
+            }
+        }",
+        "lib.con",
+    )
+    .expect("failed to lower");
+    let bindings = generate_bindings(&ir);
+
+    let js = &bindings.javascript;
+    assert!(
+        js.contains("add: (a, b) => wasm[\"_C1_4Math3addh"),
+        "{}",
+        js
+    );
+    assert!(js.contains("](a, b) >>> 0,"), "{}", js);
+    assert!(js.contains("](value, flag) !== 0,"), "{}", js);
+    assert!(js.contains("    Nested: {"), "{}", js);
+    assert!(
+        js.contains("double: (new_) => BigInt.asUintN(64, wasm[\"_C1_4Math6Nested6doubleh"),
+        "{}",
+        js
+    );
+    assert!(!js.contains("private"), "{}", js);
+    assert!(
+        js.contains(
+            "// skipped `Math::norm`: the parameter `point` can't be passed from JavaScript"
+        ),
+        "{}",
+        js
+    );
+
+    let ts = &bindings.typescript;
+    assert!(
+        ts.contains("    add(a: number, b: number): number;"),
+        "{}",
+        ts
+    );
+    assert!(
+        ts.contains("    keep(value: bigint, flag: boolean): boolean;"),
+        "{}",
+        ts
+    );
+    assert!(ts.contains("      double(new_: bigint): bigint;"), "{}", ts);
+    assert!(
+        ts.contains(
+            "export function instantiate(source: BufferSource, imports?: WebAssembly.Imports): Promise<Exports>;"
+        ),
+        "{}",
+        ts
+    );
+}
+
+#[test]
+fn source_map_locates_instructions() {
+    use concrete::ir::source_map::{SourceMap, display_ir_with_locations};
+
+    let input = "mod Main {
+    fn add(a: i32, b: i32) -> i32 {
+        let sum: i32 = a + b;
+        return sum;
+    }
+
+    fn main() -> i32 {
+        return add(1, 2);
+    }
+}";
+    let ir = lower_program(input, "main.con").expect("failed to lower");
+
+    let mut source_map = SourceMap::default();
+    source_map.insert("main.con".into(), input.to_string());
+
+    let (add, _) = ir
+        .functions
+        .iter()
+        .find(|(_, x)| x.as_ref().and_then(|x| x.debug_name.as_deref()) == Some("Main::add"))
+        .expect("add not found");
+    assert_eq!(source_map.function_lines(&ir, add), [2, 3, 4]);
+
+    let locations = source_map.function_locations(&ir, add);
+    let (_, first) = &locations[0];
+    assert_eq!(first.to_string(), "main.con:3:24");
+
+    let listing = display_ir_with_locations(&ir, &source_map);
+    assert!(listing.contains("(Main::add):\n  bb0:\n"), "{}", listing);
+    assert!(listing.contains("Return  // main.con:4:9"), "{}", listing);
+    assert!(listing.contains("  // main.con:8:16"), "{}", listing);
+}
+
+#[test]
+fn plugins_lint_and_transform_the_program() {
+    use concrete::ast::{CompilationUnit, modules::ModuleDefItem};
+    use concrete::check::diagnostics::Severity;
+    use concrete::check::lints::{LintLevel, LintLevels};
+    use concrete::ir::IR;
+    use concrete::plugin::{IrPass, LintContext, LintPass, PluginRegistry, Plugins};
+
+    struct NoTemporaryFunctions;
+
+    impl LintPass for NoTemporaryFunctions {
+        fn name(&self) -> &str {
+            "no_tmp"
+        }
+
+        fn check_ast(&self, units: &[CompilationUnit], cx: &mut LintContext) {
+            for module in units.iter().flat_map(|x| &x.modules) {
+                for item in &module.contents {
+                    let ModuleDefItem::Function(function) = item else {
+                        continue;
+                    };
+                    if function.decl.name.name.starts_with("tmp_") {
+                        cx.report(
+                            &module.file_path,
+                            function.decl.name.span,
+                            "temporary functions can't be committed",
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    struct HideFunctions;
+
+    impl IrPass for HideFunctions {
+        fn name(&self) -> &str {
+            "hide_functions"
+        }
+
+        fn run(&self, ir: &mut IR) -> Result<(), String> {
+            for function in ir.functions.iter_mut().filter_map(|(_, x)| x.as_mut()) {
+                function.is_pub = false;
+            }
+            Ok(())
+        }
+    }
+
+    let program = parse_program(
+        "mod Main {
+            pub fn tmp_debug() -> i32 {
+                return 1;
+            }
+
+            fn main() -> i32 {
+                return tmp_debug();
+            }
+        }",
+        "main.con",
+    );
+
+    let mut registry = PluginRegistry::default();
+    registry.register_lint(NoTemporaryFunctions);
+    registry.register_ir_pass(HideFunctions);
+    let mut plugins = Plugins::default();
+    plugins.add("house_rules", registry);
+
+    let units = [program];
+    let diagnostics = plugins.check_ast(&units, &LintLevels::default());
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, "house_rules::no_tmp");
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(
+        diagnostics[0].notes,
+        ["`house_rules::no_tmp` is on by default, use `-A house_rules::no_tmp` to allow it"]
+    );
+
+    let mut levels = LintLevels::default();
+    levels.set_plugin("house_rules::no_tmp", LintLevel::Deny);
+    assert_eq!(
+        plugins.check_ast(&units, &levels)[0].severity,
+        Severity::Error
+    );
+
+    levels.set_plugin("house_rules::no_tmp", LintLevel::Allow);
+    assert!(plugins.check_ast(&units, &levels).is_empty());
+
+    let mut ir = lower_compile_units(&units).expect("failed to lower");
+    assert!(
+        ir.functions
+            .iter()
+            .any(|(_, x)| x.as_ref().is_some_and(|x| x.is_pub))
+    );
+    plugins.run_passes(&mut ir).expect("the pass failed");
+    assert!(
+        ir.functions
+            .iter()
+            .all(|(_, x)| x.as_ref().is_none_or(|x| !x.is_pub))
+    );
+}
+
+#[test]
+fn fuzz_target_becomes_the_libfuzzer_entry() {
+    use concrete::driver::fuzz::{ENTRY_SYMBOL, RENAMED_MAIN_SYMBOL, use_fuzz_target};
+
+    let mut ir = lower_program(
+        "mod Main {
+            #[fuzz_target]
+            fn parse_header(data: *const u8, len: u64) -> i32 {
+                return 0;
+            }
+
+            fn main() -> i32 {
+                return 0;
+            }
+        }",
+        "main.con",
+    )
+    .expect("failed to lower");
+    assert_eq!(ir.fuzz_targets.len(), 1);
+
+    let error = use_fuzz_target(&mut ir, "parse_headr").expect_err("the target doesn't exist");
+    assert!(
+        error.to_string().contains("Main::parse_header"),
+        "{}",
+        error
+    );
+
+    use_fuzz_target(&mut ir, "parse_header").expect("the target exists");
+    let names: Vec<&str> = ir
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .map(|x| x.name.as_str())
+        .collect();
+    assert!(names.contains(&ENTRY_SYMBOL), "{:?}", names);
+    assert!(names.contains(&RENAMED_MAIN_SYMBOL), "{:?}", names);
+    assert!(!names.contains(&"main"), "{:?}", names);
+
+    let error = lower_program(
+        "mod Main {
+            #[fuzz_target]
+            fn parse_header(data: *const u8) -> i32 {
+                return 0;
+            }
+        }",
+        "main.con",
+    )
+    .expect_err("the signature is wrong");
+    assert!(
+        matches!(error, LoweringError::InvalidAttribute { ref name, .. } if name == "fuzz_target"),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn c_header_declares_public_functions() {
+    use concrete::driver::c_header::generate_header;
+
+    let ir = lower_program(
+        "mod Math {
+            struct Point {
+                x: i32,
+                y: i32,
+            }
+
+            pub fn add(a: i32, b: u32) -> u32 {
+                return (a as u32) + b;
+            }
+
+            pub fn norm(point: &Point) -> f64 {
+                return 1.0;
+            }
+
+            pub fn by_value(point: Point) -> i32 {
+                return point.x;
+            }
+
+            pub fn fill(values: *mut u8, count: u64) {
+                return;
+            }
+
+            fn private(value: i32) -> i32 {
+                return value;
+            }
+        }",
+        "lib.con",
+    )
+    .expect("failed to lower");
+    let header = generate_header(&ir, "my-math");
+
+    assert!(header.contains("#ifndef MY_MATH_H"), "{}", header);
+    assert!(header.contains("typedef struct Point Point;"), "{}", header);
+    assert!(
+        header.contains("// Math::add\nuint32_t _C1_4Math3addh"),
+        "{}",
+        header
+    );
+    assert!(header.contains("(int32_t a, uint32_t b);"), "{}", header);
+    assert!(header.contains("(const Point *point);"), "{}", header);
+    assert!(
+        header.contains("(uint8_t *values, uint64_t count);"),
+        "{}",
+        header
+    );
+    assert!(header.contains("void _C1_4Math4fillh"), "{}", header);
+    assert!(!header.contains("private"), "{}", header);
+    assert!(
+        header
+            .contains("// skipped `Math::by_value`: the parameter `point` can't be passed from C"),
+        "{}",
+        header
+    );
+}
+
+#[test]
+fn build_plan_orders_compile_and_link() {
+    use clap::Parser;
+    use concrete::driver::{
+        CompilerArgs,
+        build_plan::{BuildPlan, InvocationKind},
+    };
+
+    let args = CompilerArgs::try_parse_from(["concrete", "main.con", "build/main"])
+        .expect("valid arguments");
+
+    let program = parse_program(
+        "mod Main {
+            fn main() -> i32 {
+                return 0;
+            }
+        }",
+        "main.con",
+    );
+
+    let mut plan = BuildPlan::default();
+    plan.add_program(&args, &[program], "main", "dev", &[]);
+
+    let kinds: Vec<_> = plan.invocations.iter().map(|x| x.kind).collect();
+    assert_eq!(kinds, [InvocationKind::Compile, InvocationKind::Link]);
+
+    let (compile, link) = (&plan.invocations[0], &plan.invocations[1]);
+    assert_eq!(compile.inputs, [Path::new("main.con")]);
+    assert_eq!(compile.outputs, [Path::new("build/main.o")]);
+    assert_eq!(compile.units[0].package, "main");
+    assert_eq!(link.deps, [0]);
+    assert_eq!(link.inputs, compile.outputs);
+    assert_eq!(link.outputs, [Path::new("build/main")]);
+
+    let json = serde_json::to_value(&plan).unwrap();
+    assert_eq!(json["invocations"][1]["kind"], "link");
+}
+
+#[test]
+fn lexical_scopes_for_debug_info() {
+    let text = "mod Main {
+    pub fn main() -> i32 {
+        let mut total: i32 = 0;
+        for (let mut i: i32 = 0; i < 3; i = i + 1) {
+            let step: i32 = i * 2;
+            if step > 2 {
+                let big: i32 = step;
+                total = total + big;
+            }
+        }
+        return total;
+    }
+}";
+    let ir = lower_program(text, "main.con").expect("failed to lower");
+
+    let main = ir
+        .functions
+        .iter()
+        .filter_map(|(_, body)| body.as_ref())
+        .find(|body| body.name == "main")
+        .expect("main is lowered");
+    let span = main.span.expect("functions have a span");
+    assert!(text[span.from..span.to].starts_with("pub fn main"));
+
+    // The for loop and the if inside it.
+    assert_eq!(main.scopes.len(), 2);
+    assert_eq!(main.scopes[0].parent, None);
+    assert_eq!(main.scopes[1].parent, Some(0));
+    let scope = &main.scopes[1];
+    assert!(text[scope.span.from..scope.span.to].starts_with("let big"));
+
+    let local_scope = |name: &str| {
+        main.locals
+            .iter()
+            .find(|x| x.debug_name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{name} is a local"))
+            .scope
+    };
+    assert_eq!(local_scope("total"), None);
+    assert_eq!(local_scope("step"), Some(0));
+    assert_eq!(local_scope("big"), Some(1));
+}
+
+#[test]
+fn coverage_report_lines() {
+    use concrete::driver::coverage::{CoverageMap, CoverageProfile, CoverageReport};
+    use concrete::ir::source_map::SourceMap;
+
+    let text = "mod Main {
+    pub fn main() -> i32 {
+        let x: i32 = 2;
+        if x > 5 {
+            return 1;
+        }
+        return 0;
+    }
+}";
+    let ir = lower_program(text, "main.con").expect("failed to lower");
+    let mut source_map = SourceMap::default();
+    source_map.insert("main.con".into(), text.to_string());
+    let map = CoverageMap::from_ir(&ir, &source_map);
+
+    let blocks = ir
+        .functions
+        .iter()
+        .filter_map(|(_, body)| body.as_ref())
+        .find(|body| body.name == "main")
+        .expect("main is lowered")
+        .basic_blocks
+        .len();
+    let profile = |counts: Vec<u64>| -> CoverageProfile {
+        serde_json::from_value(serde_json::json!({ "counts": { "main": counts } })).unwrap()
+    };
+
+    // Only the entry block ran.
+    let mut counts = vec![0; blocks];
+    counts[0] = 1;
+    let mut profile_run = profile(counts);
+    let report = CoverageReport::new(&map, &profile_run);
+    let lines = &report.files[Path::new("main.con")];
+    assert_eq!(lines[&3], 1);
+    assert_eq!(lines[&5], 0);
+    let (covered, total) = report.file_coverage(Path::new("main.con"));
+    assert!(covered > 0 && covered < total);
+
+    // Merged with a run where every block ran, all the lines are covered.
+    profile_run.merge(&profile(vec![1; blocks]));
+    let report = CoverageReport::new(&map, &profile_run);
+    assert_eq!(report.files[Path::new("main.con")][&3], 2);
+    let (covered, total) = report.file_coverage(Path::new("main.con"));
+    assert_eq!(covered, total);
+    assert!(report.to_string().contains("100.00%"));
+}
+
+#[test]
+fn build_script_directives_set_cfg_options() {
+    use concrete::ast::cfg::Cfg;
+    use concrete::driver::build_script::parse_directives;
+
+    let output = "Checking for zlib\n\
+        concrete:link-lib=z\n\
+        concrete:link-search=vendor/lib\n\
+        concrete:cfg=has_zlib\n\
+        concrete:cfg=zlib_version=\"1.3\"\n\
+        concrete:warning=zlib is old\n";
+    let directives = parse_directives(output, Path::new("/pkg")).unwrap();
+    assert_eq!(directives.link_libs, ["z"]);
+    assert_eq!(directives.lib_dirs, [Path::new("/pkg/vendor/lib")]);
+    assert_eq!(directives.warnings, ["zlib is old"]);
+    assert!(directives.cfgs["has_zlib"].is_empty());
+    assert!(directives.cfgs["zlib_version"].contains("1.3"));
+
+    for (output, message) in [
+        ("concrete:link-libs=z", "did you mean `link-lib`?"),
+        ("concrete:cfg=target_os=\"linux\"", "set by the compiler"),
+        ("concrete:cfg=zlib_version=1.3", "isn't quoted"),
+        ("concrete:cfg", "has no value"),
+    ] {
+        let error = parse_directives(output, Path::new("/pkg")).unwrap_err();
+        assert!(error.to_string().contains(message), "{error}");
+    }
+
+    let mut program = parse_program(
+        "mod Main {
+            #[cfg(all(has_zlib, zlib_version = \"1.3\"))]
+            fn compress() -> i32 {
+                return 1;
+            }
+
+            #[cfg(has_bzip2)]
+            fn compress() -> i32 {
+                return 2;
+            }
+
+            fn main() -> i32 {
+                return compress();
+            }
+        }",
+        "build_script.con",
+    );
+    program.cfg = Cfg {
+        custom: Some(directives.cfgs),
+        ..Cfg::default()
+    };
+    lower_compile_units(&[program.clone()]).expect("the program is valid");
+
+    // Without a build script, the options it would set are unknown.
+    program.cfg = Cfg::default();
+    assert!(matches!(
+        lower_compile_units(&[program]),
+        Err(LoweringError::InvalidAttribute { name, .. }) if name == "cfg"
+    ));
+}
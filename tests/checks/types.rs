@@ -0,0 +1,651 @@
+use concrete::check::lowering_error_to_diagnostic;
+use concrete::ir::lowering::{LoweringError, TypeDifference};
+
+use crate::{assert_formats, check_invalid_program, lower_program};
+
+#[test]
+fn unrecorgnized_type() {
+    let (source, name) = (
+        include_str!("../invalid_programs/type.con"),
+        "invalid_programs/type.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(
+            &error,
+            LoweringError::UnrecognizedType { name, .. } if name == "invalid_type"
+        ),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn field_not_found() {
+    let (source, name) = (
+        include_str!("../invalid_programs/field_not_found.con"),
+        "invalid_programs/field_not_found.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(
+            &error,
+            LoweringError::FieldNotFound { name, .. } if name == "x"
+        ),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn undeclared_var() {
+    let (source, name) = (
+        include_str!("../invalid_programs/undeclared_var.con"),
+        "invalid_programs/undeclared_var.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(
+            &error,
+            LoweringError::UseOfUndeclaredVariable { name, .. } if name == "b"
+        ),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn call_param_count_mismatch() {
+    let (source, name) = (
+        include_str!("../invalid_programs/call_param_count_mismatch.con"),
+        "invalid_programs/call_param_count_mismatch.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(
+            &error,
+            LoweringError::CallParamCountMismatch { found, needs, .. } if *found == 2 && *needs == 1
+        ),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn call_param_type_mismatch() {
+    let (source, name) = (
+        include_str!("../invalid_programs/call_param_type_mismatch.con"),
+        "invalid_programs/call_param_type_mismatch.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let LoweringError::UnexpectedType(error) = error else {
+        panic!("{:#?}", error);
+    };
+    assert_eq!(error.expected, "i32");
+    assert_eq!(error.found, "u32");
+    let expected_span = error.expected_span.expect("expected the param type span");
+    assert_eq!(&source[expected_span.from..expected_span.to], "i32");
+    assert!(error.difference.is_none());
+}
+
+#[test]
+fn nested_type_mismatch() {
+    let (source, name) = (
+        include_str!("../invalid_programs/nested_type_mismatch.con"),
+        "invalid_programs/nested_type_mismatch.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    let LoweringError::UnexpectedType(error) = error else {
+        panic!("{:#?}", error);
+    };
+    assert_eq!(
+        error.difference,
+        Some(TypeDifference {
+            context: "&mut [_; 2]".to_string(),
+            expected: "i32".to_string(),
+            found: "u32".to_string(),
+        })
+    );
+
+    let diagnostic = lowering_error_to_diagnostic(LoweringError::UnexpectedType(error));
+    assert_eq!(diagnostic.primary_message(), "Mismatched types.");
+    assert_eq!(
+        diagnostic.labels[0].message.as_deref(),
+        Some("expected `&mut [i32; 2]`, found `&mut [u32; 2]`")
+    );
+}
+
+#[test]
+fn invalid_assign() {
+    let (source, name) = (
+        include_str!("../invalid_programs/invalid_assign.con"),
+        "invalid_programs/invalid_assign.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::UnexpectedType(_)),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn invalid_match_generic() {
+    let (source, name) = (
+        include_str!("../invalid_programs/invalid_match_generic.con"),
+        "invalid_programs/invalid_match_generic.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::UnexpectedType(_)),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn unknown_variant() {
+    let (source, name) = (
+        include_str!("../invalid_programs/unknown_variant.con"),
+        "invalid_programs/unknown_variant.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::VariantNotFound { name, .. } if name == "Sume"),
+        "{:#?}",
+        error
+    );
+    assert_eq!(error.suggestions()[0].replacement, "Some");
+}
+
+#[test]
+fn use_undeclared_var() {
+    let (source, name) = (
+        include_str!("../invalid_programs/use_undeclared_var.con"),
+        "invalid_programs/use_undeclared_var.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::UseOfUndeclaredVariable { .. }),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn undef_var_in_for() {
+    let (source, name) = (
+        include_str!("../invalid_programs/undef_var_in_for.con"),
+        "invalid_programs/undef_var_in_for.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::UseOfUndeclaredVariable { .. }),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn missing_variant() {
+    let (source, name) = (
+        include_str!("../invalid_programs/missing_variant.con"),
+        "invalid_programs/missing_variant.con",
+    );
+    let error = check_invalid_program(source, name);
+
+    assert!(
+        matches!(&error, LoweringError::MissingVariant { .. }),
+        "{:#?}",
+        error
+    );
+}
+
+#[test]
+fn string_addition_calls_concat() {
+    use concrete::ir::TerminatorKind;
+
+    let ir = lower_program(
+        "mod Main {
+            #[langitem = \"String\"]
+            struct String {
+                ptr: *mut u8,
+                len: u64,
+                cap: u64,
+            }
+
+            impl String {
+                fn concat(self, other: String) -> String {
+                    return self;
+                }
+
+                fn len(&self) -> u64 {
+                    return self.len;
+                }
+            }
+
+            fn length(value: &String) -> u64 {
+                return value.len();
+            }
+
+            fn main() -> u64 {
+                let a: String = \"a\";
+                let b: String = a + \"b\";
+                return length(&b);
+            }
+        }",
+        "string.con",
+    )
+    .expect("the program is valid");
+
+    let function = |name: &str| {
+        ir.functions
+            .iter()
+            .filter_map(|(_, x)| x.as_ref())
+            .find(|x| x.debug_name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{name} should be lowered"))
+    };
+    let calls = |name: &str| -> Vec<String> {
+        function(name)
+            .basic_blocks
+            .iter()
+            .filter_map(|x| match &x.terminator.kind {
+                TerminatorKind::Call { func, .. } => {
+                    ir.functions[*func].as_ref()?.debug_name.clone()
+                }
+                _ => None,
+            })
+            .collect()
+    };
+
+    assert!(calls("main").iter().any(|x| x == "Main::concat"));
+    // The method is found through the reference.
+    assert!(calls("Main::length").iter().any(|x| x == "Main::len"));
+}
+
+#[test]
+fn casts_are_checked() {
+    let error = check_invalid_program(
+        "mod Main {
+            fn main() -> i32 {
+                let x: bool = 1 as bool;
+                return 0;
+            }
+        }",
+        "cast.con",
+    );
+    assert!(
+        matches!(
+            &error,
+            LoweringError::InvalidCast { from, to, help: Some(_), .. } if from == "i64" && to == "bool"
+        ),
+        "{:#?}",
+        error
+    );
+
+    let error = check_invalid_program(
+        "mod Main {
+            fn main() -> i32 {
+                let x: u8 = 300;
+                return 0;
+            }
+        }",
+        "literal.con",
+    );
+    assert!(
+        matches!(
+            &error,
+            LoweringError::LiteralOutOfRange { ty, range, .. }
+                if ty == "u8" && range == &("0".to_string(), "255".to_string())
+        ),
+        "{:#?}",
+        error
+    );
+
+    let error = check_invalid_program(
+        "mod Main {
+            fn main() -> i32 {
+                let x: i8 = -129;
+                return 0;
+            }
+        }",
+        "literal.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::LiteralOutOfRange { ty, .. } if ty == "i8"),
+        "{:#?}",
+        error
+    );
+
+    lower_program(
+        "mod Main {
+            fn main() -> i32 {
+                let x: i8 = -128;
+                let y: f64 = x as f64;
+                let z: u8 = y as u8;
+                let c: char = z as char;
+                return c as i32;
+            }
+        }",
+        "cast.con",
+    )
+    .expect("numeric casts are valid");
+}
+
+#[test]
+fn method_calls_check_receivers() {
+    let program = |body: &str| {
+        format!(
+            "mod Main {{
+                struct Rect {{
+                    w: i64,
+                    h: i64,
+                }}
+
+                impl Rect {{
+                    pub fn new(w: i64, h: i64) -> Rect {{
+                        let rect: Rect = Rect {{
+                            w: w,
+                            h: h,
+                        }};
+                        return rect;
+                    }}
+
+                    pub fn area(&self) -> i64 {{
+                        return self.w * self.h;
+                    }}
+
+                    pub fn grow(&mut self) {{
+                        self.w = self.w + 1;
+                    }}
+                }}
+
+                fn main() -> i32 {{
+                    {body}
+                    return 0;
+                }}
+            }}"
+        )
+    };
+
+    lower_program(
+        &program(
+            "let mut rect: Rect = Rect#new(1, 2);
+            rect.grow();
+            let borrow: &mut Rect = &mut rect;
+            borrow.grow();
+            let area: i64 = rect.area();
+            let same: i64 = Rect#area(&rect);",
+        ),
+        "methods.con",
+    )
+    .expect("the receivers are valid");
+
+    let error = check_invalid_program(
+        &program("let rect: Rect = Rect#new(1, 2); rect.grow();"),
+        "method_mut.con",
+    );
+    assert!(
+        matches!(
+            &error,
+            LoweringError::CantTakeMutableBorrow {
+                suggestion: Some(_),
+                ..
+            }
+        ),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        &program("let rect: Rect = Rect#new(1, 2); let borrow: &Rect = &rect; borrow.grow();"),
+        "method_borrow.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::BorrowNotMutable { name, .. } if name == "borrow"),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        &program("let rect: Rect = Rect#new(1, 2); let p: i64 = rect.perimeter();"),
+        "method_missing.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::MethodNotFound { name, ty, .. } if name == "perimeter" && ty == "Rect"),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        &program("let rect: Rect = Rect#new(1, 2); let other: Rect = rect.new(1, 2);"),
+        "method_assoc.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::NotAMethod { name, .. } if name == "new"),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn struct_defaults_and_update_syntax() {
+    let text = include_str!("../../examples/struct_defaults.con");
+    assert_formats(text, text);
+    lower_program(text, "struct_defaults.con").expect("the fields are given, defaulted or copied");
+
+    let program = |init: &str| {
+        format!(
+            "mod Main {{
+                struct Point {{
+                    x: i32,
+                    y: i32 = 2,
+                    z: i32,
+                }}
+
+                fn main() -> i32 {{
+                    let other: Point = Point {{
+                        x: 1,
+                        z: 3,
+                    }};
+                    let point: Point = {init};
+                    return 0;
+                }}
+            }}"
+        )
+    };
+
+    let error = check_invalid_program(&program("Point { x: 1 }"), "struct_missing.con");
+    assert!(
+        matches!(&error, LoweringError::MissingFields { fields, .. } if fields == &["z"]),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(&program("Point { w: 1, ..other }"), "struct_unknown.con");
+    assert!(
+        matches!(&error, LoweringError::FieldNotFound { name, .. } if name == "w"),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(&program("Point { x: 1, ..2 }"), "struct_base.con");
+    assert!(
+        matches!(&error, LoweringError::UnexpectedType(_)),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        "mod Main {
+            struct Point {
+                x: i32 = answer(),
+            }
+
+            fn answer() -> i32 {
+                return 42;
+            }
+
+            fn main() -> i32 {
+                let point: Point = Point {};
+                return point.x;
+            }
+        }",
+        "struct_default.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::NotConst { .. }),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn tuples_are_checked() {
+    let text = include_str!("../../examples/tuples.con");
+    assert_formats(text, text);
+    lower_program(text, "tuples.con").expect("the tuples are well typed");
+
+    let program = |body: &str| {
+        format!(
+            "mod Main {{
+                fn main() -> i32 {{
+                    let pair: (i32, bool) = (1, true);
+                    {body}
+                    return 0;
+                }}
+            }}"
+        )
+    };
+
+    let error = check_invalid_program(&program("let x: i32 = pair.2;"), "tuple_field.con");
+    assert!(
+        matches!(&error, LoweringError::FieldNotFound { name, .. } if name == "2"),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(
+        &program("let other: (i32, bool) = (true, 1);"),
+        "tuple_type.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::UnexpectedType(_)),
+        "{error:#?}"
+    );
+
+    let error = check_invalid_program(&program("pair.0 = 2;"), "tuple_mut.con");
+    assert!(
+        matches!(&error, LoweringError::NotMutable { .. }),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn lowering_reports_the_errors_of_all_items() {
+    use concrete::check::lowering_error_to_diagnostics;
+
+    let error = check_invalid_program(
+        "mod Main {
+    struct Point {
+        x: i32,
+    }
+
+    impl Point {
+        fn sum(&self) -> i32 {
+            return self.x + missing_field;
+        }
+    }
+
+    fn first() -> i32 {
+        return undeclared;
+    }
+
+    fn second() -> i32 {
+        return first() + unknown_fn();
+    }
+
+    fn main() -> i32 {
+        return second();
+    }
+}
+",
+        "multiple_errors.con",
+    );
+    assert!(
+        matches!(&error, LoweringError::Multiple(errors) if errors.len() == 3),
+        "{error:#?}"
+    );
+
+    // The error of `first` is found again lowering `second`, which calls it, but kept once.
+    let errors = error.into_errors();
+    assert!(matches!(
+        &errors[0],
+        LoweringError::UseOfUndeclaredVariable { name, .. } if name == "missing_field"
+    ));
+    assert!(matches!(
+        &errors[1],
+        LoweringError::UseOfUndeclaredVariable { name, .. } if name == "undeclared"
+    ));
+    assert!(matches!(
+        &errors[2],
+        LoweringError::FunctionNotFound { function, .. } if function == "unknown_fn"
+    ));
+
+    let diagnostics = lowering_error_to_diagnostics(LoweringError::Multiple(errors));
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|x| x.code.as_str())
+            .collect::<Vec<_>>(),
+        [
+            "UseOfUndeclaredVariable",
+            "UseOfUndeclaredVariable",
+            "FunctionNotFound"
+        ]
+    );
+
+    // An unresolved import stops before the items, their errors would follow from it.
+    let error = check_invalid_program(
+        "mod Main {
+    import Other.{ helper };
+
+    fn main() -> i32 {
+        return undeclared;
+    }
+}
+",
+        "import_error.con",
+    );
+    assert!(
+        matches!(error, LoweringError::ModuleNotFound { .. }),
+        "{error:#?}"
+    );
+}
+
+#[test]
+fn implicit_discriminant_past_u32_is_an_error() {
+    let error = check_invalid_program(
+        "mod Overflow {
+            enum Tag {
+                A = 4294967294,
+                B,
+                C,
+            }
+
+            fn main() -> i32 {
+                return 0;
+            }
+        }",
+        "discriminant_overflow.con",
+    );
+    assert!(
+        matches!(
+            &error,
+            LoweringError::DiscriminantOverflow { name, previous, .. } if name == "C" && previous == "A"
+        ),
+        "{error:#?}"
+    );
+}
@@ -5,9 +5,9 @@ use std::{
     process::{Output, Stdio},
 };
 
-use concrete::compile_unit_info::{CompileUnitInfo, DebugInfo, OptLevel};
-use concrete::driver::linker::{link_binary, link_shared_lib};
-use concrete::ir::lowering::lower_compile_units;
+use concrete::compile_unit_info::{CompileUnitInfo, DebugInfo, Lto, OptLevel, TargetInfo};
+use concrete::driver::linker::{LinkOptions, link_binary, link_sanitized, link_shared_lib};
+use concrete::ir::{IR, lowering::lower_compile_units};
 use concrete::parser::ProgramSource;
 use tempfile::TempDir;
 
@@ -29,6 +29,9 @@ pub struct CompileResult {
     #[allow(unused)]
     pub object_file: PathBuf,
     pub binary_file: PathBuf,
+    /// The lowered program the binary was compiled from.
+    #[allow(unused)]
+    pub program_ir: IR,
 }
 
 pub fn compile_program(
@@ -36,6 +39,17 @@ pub fn compile_program(
     name: &str,
     library: bool,
     optlevel: OptLevel,
+) -> Result<CompileResult, Box<dyn std::error::Error>> {
+    compile_program_with(source, name, library, optlevel, |_| {})
+}
+
+/// Compiles the program like [`compile_program`], with the session changed by `configure`.
+pub fn compile_program_with(
+    source: &str,
+    name: &str,
+    library: bool,
+    optlevel: OptLevel,
+    configure: impl FnOnce(&mut CompileUnitInfo),
 ) -> Result<CompileResult, Box<dyn std::error::Error>> {
     let source = ProgramSource::new(source.to_string(), Path::new(""));
     tracing::debug!("source code:\n{}", &source.input);
@@ -61,7 +75,7 @@ pub fn compile_program(
         output_file.with_extension("")
     };
 
-    let session = CompileUnitInfo {
+    let mut session = CompileUnitInfo {
         debug_info: DebugInfo::Full,
         optlevel,
         library,
@@ -69,22 +83,46 @@ pub fn compile_program(
         output_mlir: false,
         output_ll: false,
         output_asm: false,
+        fuzzing: false,
+        unchecked_indexing: false,
+        target: TargetInfo::default(),
+        lto: Lto::Off,
+        sanitizers: Vec::new(),
+        coverage: false,
+        freestanding: false,
     };
+    configure(&mut session);
 
     let program_ir = lower_compile_units(&[program])?;
 
     let object_path = concrete::codegen::compile(&session, &program_ir)?;
+    let options = LinkOptions {
+        args: concrete::driver::program_libs(&program_ir)
+            .iter()
+            .map(|x| format!("-l{x}"))
+            .collect(),
+        sanitizers: session.sanitizers.clone(),
+        ..Default::default()
+    };
 
     if library {
         link_shared_lib(
             &[object_path.clone()],
+            &options,
             &session
                 .output_file
                 .with_extension(CompileUnitInfo::get_platform_library_ext()),
         )?;
+    } else if !session.sanitizers.is_empty() {
+        link_sanitized(
+            &[object_path.clone()],
+            &options,
+            &session.output_file.with_extension(""),
+        )?;
     } else {
         link_binary(
             &[object_path.clone()],
+            &options,
             &session.output_file.with_extension(""),
         )?;
     }
@@ -93,9 +131,51 @@ pub fn compile_program(
         folder: test_dir,
         object_file: object_path,
         binary_file: session.output_file,
+        program_ir,
     })
 }
 
+/// Compiles the program to an object without linking it, with the session changed by `configure`,
+/// and returns the LLVM IR of its module as written by `--llvm`, once optimized and instrumented.
+#[allow(unused)] // false positive
+pub fn compile_to_llvm_ir(
+    source: &str,
+    name: &str,
+    configure: impl FnOnce(&mut CompileUnitInfo),
+) -> Result<String, Box<dyn std::error::Error>> {
+    let source = ProgramSource::new(source.to_string(), Path::new(""));
+    let program = match concrete::parser::parse_ast(&source) {
+        Ok(x) => x,
+        Err(_) => {
+            return Err(Box::new(TestError("error compiling".into())));
+        }
+    };
+
+    let test_dir = tempfile::tempdir()?;
+    let mut session = CompileUnitInfo {
+        debug_info: DebugInfo::None,
+        optlevel: OptLevel::None,
+        library: false,
+        output_file: test_dir.path().join(name),
+        output_mlir: false,
+        output_ll: true,
+        output_asm: false,
+        fuzzing: false,
+        unchecked_indexing: false,
+        target: TargetInfo::default(),
+        lto: Lto::Off,
+        sanitizers: Vec::new(),
+        coverage: false,
+        freestanding: false,
+    };
+    configure(&mut session);
+
+    let program_ir = lower_compile_units(&[program])?;
+    let object_path = concrete::codegen::compile(&session, &program_ir)?;
+
+    Ok(std::fs::read_to_string(object_path.with_extension("ll"))?)
+}
+
 pub fn run_program(program: &Path) -> Result<Output, std::io::Error> {
     std::process::Command::new(program)
         .stdout(Stdio::piped())
@@ -40,7 +40,20 @@ mod common;
 #[test_case(include_str!("../examples/for.con"), "for", false, 10 ; "for.con")]
 #[test_case(include_str!("../examples/for_while.con"), "for_while", false, 10 ; "for_while.con")]
 #[test_case(include_str!("../examples/arrays.con"), "arrays", false, 5 ; "arrays.con")]
+#[test_case(include_str!("../examples/slices.con"), "slices", false, 19 ; "slices.con")]
+#[test_case(include_str!("../examples/for_in.con"), "for_in", false, 23 ; "for_in.con")]
+#[test_case(include_str!("../examples/destructuring.con"), "destructuring", false, 34 ; "destructuring.con")]
+#[test_case(include_str!("../examples/const_fn.con"), "const_fn", false, 23 ; "const_fn.con")]
+#[test_case(include_str!("../examples/struct_defaults.con"), "struct_defaults", false, 147 ; "struct_defaults.con")]
+#[test_case(include_str!("../examples/tuples.con"), "tuples", false, 59 ; "tuples.con")]
+#[test_case(include_str!("../examples/try_operator.con"), "try_operator", false, 69 ; "try_operator.con")]
+#[test_case(include_str!("../examples/defer.con"), "defer", false, 86 ; "defer.con")]
+#[test_case(include_str!("../examples/raw_pointers.con"), "raw_pointers", false, 18 ; "raw_pointers.con")]
+#[test_case(include_str!("../examples/extern_block.con"), "extern_block", false, 42 ; "extern_block.con")]
 #[test_case(include_str!("../examples/constants.con"), "constants", false, 20 ; "constants.con")]
+#[test_case(include_str!("../examples/threads.con"), "threads", false, 20 ; "threads.con")]
+#[test_case(include_str!("../examples/async.con"), "async", false, 20 ; "async.con")]
+#[test_case(include_str!("../examples/tail_calls.con"), "tail_calls", false, 15 ; "tail_calls.con")]
 #[test_case(include_str!("../examples/linearExample01.con"), "linearity", false, 2 ; "linearExample01.con")]
 #[test_case(include_str!("../examples/linearExample02.con"), "linearity", false, 2 ; "linearExample02.con")]
 #[test_case(include_str!("../examples/linearExample03if.con"), "linearity", false, 0 ; "linearExample03if.con")]
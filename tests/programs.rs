@@ -1,6 +1,8 @@
+use std::path::Path;
+
 use concrete::compile_unit_info::OptLevel;
 
-use crate::common::compile_and_run;
+use crate::common::{compile_and_run, compile_program, compile_program_with, compile_to_llvm_ir};
 
 mod common;
 
@@ -243,3 +245,332 @@ fn test_reference() {
         compile_and_run(source, "references", false, OptLevel::Aggressive)
     );
 }
+
+#[test]
+fn test_array_sized_by_constant_expression() {
+    let source = r#"
+            mod Simple {
+                const N: u64 = 3;
+
+                const fn double(x: u64) -> u64 {
+                    return x * 2;
+                }
+
+                fn total(values: [i32]) -> i32 {
+                    let mut sum: i32 = 0;
+                    for value in values {
+                        sum = sum + value;
+                    }
+                    return sum;
+                }
+
+                fn main() -> i32 {
+                    let values: [i32; double(N) - 1] = [1, 2, 3, 4, 5];
+                    let mut count: i32 = 0;
+                    for value in values {
+                        count = count + 1;
+                    }
+                    let tail: [i32] = values[1..];
+                    return total(tail) * 10 + count;
+                }
+            }
+        "#;
+
+    assert_eq!(
+        145,
+        compile_and_run(source, "array_size", false, OptLevel::None)
+    );
+    assert_eq!(
+        145,
+        compile_and_run(source, "array_size", false, OptLevel::Less)
+    );
+    assert_eq!(
+        145,
+        compile_and_run(source, "array_size", false, OptLevel::Default)
+    );
+    assert_eq!(
+        145,
+        compile_and_run(source, "array_size", false, OptLevel::Aggressive)
+    );
+}
+
+#[test]
+fn test_become_is_musttail() {
+    let source = r#"
+            mod Simple {
+                fn count_down(n: u64, steps: u64) -> u64 {
+                    if n == 0 {
+                        return steps;
+                    }
+                    become count_down(n - 1, steps + 1);
+                }
+
+                fn main() -> i32 {
+                    // Deep enough to overflow the stack if each call took a frame.
+                    if count_down(100000000, 0) == 100000000 {
+                        return 7;
+                    }
+                    return 1;
+                }
+            }
+        "#;
+
+    let ir = compile_to_llvm_ir(source, "musttail", |_| {}).expect("failed to compile");
+    assert!(ir.contains("musttail call"), "{ir}");
+    let ir = compile_to_llvm_ir(source, "musttail", |session| {
+        session.optlevel = OptLevel::Aggressive;
+    })
+    .expect("failed to compile");
+    assert!(ir.contains("musttail call"), "{ir}");
+
+    assert_eq!(
+        7,
+        compile_and_run(source, "musttail", false, OptLevel::None)
+    );
+    assert_eq!(
+        7,
+        compile_and_run(source, "musttail", false, OptLevel::Less)
+    );
+    assert_eq!(
+        7,
+        compile_and_run(source, "musttail", false, OptLevel::Default)
+    );
+    assert_eq!(
+        7,
+        compile_and_run(source, "musttail", false, OptLevel::Aggressive)
+    );
+}
+
+#[test]
+fn test_overflow_panics() {
+    let source = r#"
+            mod Simple {
+                fn add(x: u8, y: u8) -> u8 {
+                    return x + y;
+                }
+
+                fn main() -> i32 {
+                    let sum: u8 = add(255, 1);
+                    return 0;
+                }
+            }
+        "#;
+
+    let result = compile_program(source, "overflow_panics", false, OptLevel::None)
+        .expect("failed to compile");
+    let output = std::process::Command::new(&result.binary_file)
+        .output()
+        .expect("failed to run");
+
+    // It aborts rather than exiting with a code.
+    assert_eq!(output.status.code(), None);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(": attempt to add with overflow"),
+        "{stderr}"
+    );
+
+    // At the other levels it wraps around.
+    assert_eq!(
+        0,
+        compile_and_run(source, "overflow_panics", false, OptLevel::Less)
+    );
+}
+
+#[test]
+fn test_out_of_bounds_index_panics() {
+    let source = r#"
+            mod Simple {
+                fn get(values: [i32], index: u64) -> i32 {
+                    return values[index];
+                }
+
+                fn main() -> i32 {
+                    let values: [i32; 3] = [1, 2, 3];
+                    let all: [i32] = values[..];
+                    return get(all, 5);
+                }
+            }
+        "#;
+
+    let result =
+        compile_program(source, "bounds_panics", false, OptLevel::None).expect("failed to compile");
+    let output = std::process::Command::new(&result.binary_file)
+        .output()
+        .expect("failed to run");
+
+    assert_eq!(output.status.code(), None);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(": index out of bounds: the index is 5 but the length is 3"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_sanitizers_instrument_the_code() {
+    use concrete::compile_unit_info::Sanitizer;
+
+    let source = r#"
+            mod Simple {
+                fn get(values: [i32], index: u64) -> i32 {
+                    return values[index];
+                }
+
+                fn main() -> i32 {
+                    let values: [i32; 3] = [1, 2, 3];
+                    let all: [i32] = values[..];
+                    return get(all, 1);
+                }
+            }
+        "#;
+
+    let ir = compile_to_llvm_ir(source, "sanitizers", |session| {
+        session.unchecked_indexing = true;
+    })
+    .expect("failed to compile");
+    assert!(!ir.contains("call void @__concrete_panic_bounds"), "{ir}");
+    assert!(!ir.contains("__asan_"), "{ir}");
+
+    // The index is checked despite `--unchecked-indexing`, panicking when out of bounds.
+    let ir = compile_to_llvm_ir(source, "sanitizers", |session| {
+        session.unchecked_indexing = true;
+        session.sanitizers = vec![Sanitizer::Undefined];
+    })
+    .expect("failed to compile");
+    assert!(ir.contains("call void @__concrete_panic_bounds"), "{ir}");
+
+    let ir = compile_to_llvm_ir(source, "sanitizers", |session| {
+        session.sanitizers = vec![Sanitizer::Address];
+    })
+    .expect("failed to compile");
+    assert!(ir.contains("__asan_"), "{ir}");
+}
+
+#[test]
+fn test_undefined_sanitizer_reports_undefined_behavior() {
+    use concrete::compile_unit_info::Sanitizer;
+
+    let source = r#"
+            mod Simple {
+                fn divide(x: i32, y: i32) -> i32 {
+                    return x / y;
+                }
+
+                fn main() -> i32 {
+                    return divide(1, 0);
+                }
+            }
+        "#;
+
+    let result = compile_program_with(
+        source,
+        "division_by_zero",
+        false,
+        OptLevel::None,
+        |session| {
+            session.sanitizers = vec![Sanitizer::Undefined];
+        },
+    )
+    .expect("failed to compile");
+    let output = std::process::Command::new(&result.binary_file)
+        .output()
+        .expect("failed to run");
+
+    // The runtime of UndefinedBehaviorSanitizer reports it and exits.
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("runtime error: division by zero"),
+        "{stderr}"
+    );
+
+    let source = r#"
+            mod Simple {
+                unsafe fn replace(ptr: *mut i32, value: i32) -> i32 {
+                    let old: i32 = *ptr;
+                    *ptr = value;
+                    return old;
+                }
+
+                fn main() -> i32 {
+                    return 0;
+                }
+            }
+        "#;
+
+    let ir = compile_to_llvm_ir(source, "raw_pointers", |_| {}).expect("failed to compile");
+    assert!(!ir.contains("__ubsan_handle"), "{ir}");
+
+    let ir = compile_to_llvm_ir(source, "raw_pointers", |session| {
+        session.sanitizers = vec![Sanitizer::Undefined];
+    })
+    .expect("failed to compile");
+    assert!(
+        ir.contains("call void @__ubsan_handle_type_mismatch_v1_abort"),
+        "{ir}"
+    );
+}
+
+#[test]
+fn test_coverage_of_a_library() {
+    use concrete::driver::coverage::{CoverageMap, CoverageProfile, CoverageReport};
+    use concrete::ir::source_map::SourceMap;
+
+    let source = "mod Main {
+    pub fn classify(x: i32) -> i32 {
+        if x > 5 {
+            return 1;
+        }
+        return 0;
+    }
+}
+";
+    let result = compile_program_with(source, "coverage", true, OptLevel::None, |session| {
+        session.coverage = true;
+    })
+    .expect("failed to compile");
+
+    // The map written by the build, read back by `concrete cov report`.
+    let mut source_map = SourceMap::default();
+    source_map.insert("".into(), source.to_string());
+    let map_path = CoverageMap::path_for(&result.binary_file);
+    CoverageMap::from_ir(&result.program_ir, &source_map)
+        .save(&map_path)
+        .unwrap();
+    let map = CoverageMap::load(&map_path).unwrap();
+
+    let symbol = result
+        .program_ir
+        .functions
+        .iter()
+        .filter_map(|(_, x)| x.as_ref())
+        .find(|x| x.debug_name.as_deref() == Some("Main::classify"))
+        .expect("classify is lowered")
+        .get_mangled_name();
+    let library = unsafe { libloading::Library::new(&result.binary_file) }.unwrap();
+    let classify =
+        unsafe { library.get::<unsafe extern "C" fn(i32) -> i32>(symbol.as_bytes()) }.unwrap();
+
+    assert_eq!(unsafe { classify(2) }, 0);
+    let profile = unsafe { CoverageProfile::read(&library, &map) };
+    let report = CoverageReport::new(&map, &profile);
+    let lines = &report.files[Path::new("")];
+    assert_eq!((lines[&3], lines[&4], lines[&6]), (1, 0, 1));
+
+    // The counters keep counting in the loaded library.
+    assert_eq!(unsafe { classify(9) }, 1);
+    let profile = unsafe { CoverageProfile::read(&library, &map) };
+    let report = CoverageReport::new(&map, &profile);
+    let lines = &report.files[Path::new("")];
+    assert_eq!((lines[&3], lines[&4], lines[&6]), (2, 1, 1));
+    assert_eq!(report.file_coverage(Path::new("")), (4, 4));
+
+    // A saved profile is added to the counts of a later run.
+    let profile_path = CoverageProfile::path_for(&result.binary_file);
+    profile.save(&profile_path).unwrap();
+    let mut merged = profile.clone();
+    merged.merge(&CoverageProfile::load(&profile_path).unwrap());
+    let report = CoverageReport::new(&map, &merged);
+    assert_eq!(report.files[Path::new("")][&3], 4);
+}